@@ -0,0 +1,83 @@
+extern crate futures;
+extern crate nice_game;
+
+use futures::executor::block_on;
+use nice_game::{
+	Context,
+	Format,
+	GpuFuture,
+	Version,
+	batch::sprite::{ SpriteBatch, SpriteBatchShaders, SpriteBatchShared },
+	golden::{ render_blocking, GoldenImages },
+	software,
+	texture::{ ImageFormat, ImmutableTexture, TargetTexture },
+};
+use std::{ env, process };
+
+// run with `--software` to render on lavapipe or SwiftShader, and with NICE_GAME_BLESS=1 to update the images. CI
+// runs it on lavapipe in .github/workflows/golden.yml, which blesses them when started by hand with `bless`
+fn main() {
+	if env::args().any(|arg| arg == "--software") {
+		software::use_software_icd().expect("no software Vulkan ICD found, set NICE_GAME_SOFTWARE_ICD");
+	}
+
+	let mut ctx =
+		Context::new(
+			Some("Golden Images"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	// no window, so this runs in CI without a display
+	let device = ctx.headless_device().unwrap();
+	let golden = GoldenImages::new("examples/golden/images");
+	// sRGB like most windows, but with the channels in the order images are saved in
+	let target = TargetTexture::from_device(&device, [256, 256], Format::R8G8B8A8Srgb).unwrap();
+
+	let (shaders, shaders_future) = SpriteBatchShaders::from_device(&device).unwrap();
+	let sprite_batch_shared = SpriteBatchShared::new(shaders, Format::R8G8B8A8Srgb);
+
+	let (texture, texture_future) =
+		block_on(ImmutableTexture::from_file_with_format_on_device(
+			&device,
+			"examples/assets/colors.png",
+			ImageFormat::PNG,
+			true,
+		))
+		.unwrap();
+	let (sprite, sprite_future) = sprite_batch_shared.create_sprite(&texture, [10.0, 10.0]).unwrap();
+	let (mut sprite_batch, sprite_batch_future) =
+		SpriteBatch::from_device(&device, &target, sprite_batch_shared).unwrap();
+	sprite_batch.add_sprite(Box::new(sprite));
+
+	shaders_future.join(texture_future).join(sprite_future).join(sprite_batch_future)
+		.then_signal_fence_and_flush()
+		.unwrap()
+		.wait(None)
+		.unwrap();
+
+	let sprite_image =
+		render_blocking(&device, &target, |target, compositor| {
+			sprite_batch.composite_on_device(&device, target, 0, compositor)
+		})
+		.unwrap();
+
+	let mut failed = false;
+	for (name, image) in vec![("sprite", sprite_image)] {
+		match golden.check(name, &image) {
+			Ok(()) => println!("{}: ok", name),
+			Err(err) => {
+				println!("{}: {:?}", name, err);
+				failed = true;
+			},
+		}
+	}
+
+	if failed {
+		process::exit(1);
+	}
+}
@@ -0,0 +1,134 @@
+extern crate cgmath;
+extern crate nice_game;
+
+use cgmath::{ prelude::*, Quaternion, Rad, vec3 };
+use nice_game::{
+	Context,
+	GpuFuture,
+	RenderTarget,
+	Version,
+	batch::{
+		mesh::{ DirectionalLight, GpuCulledMesh, Light, Mesh, MeshBatch, MeshShaders, MeshRenderPass },
+		sprite::{ LayerBlend, SpriteBatch, SpriteBatchShaders, SpriteBatchShared, StatsOverlay },
+	},
+	camera::Camera,
+	color::Color,
+	compositor::Layer,
+	geometry::MeshGeometry,
+	math::Transform,
+	window::{ ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent },
+};
+use std::time::Instant;
+
+/// Instances per side of the grid.
+const GRID: usize = 100;
+
+fn main() {
+	let mut ctx =
+		Context::new(
+			Some("Instancing Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
+
+	let (mesh_shaders, mesh_shaders_future) = MeshShaders::new(&window).unwrap();
+	let render_pass = MeshRenderPass::new(mesh_shaders, window.format());
+	window.join_future(mesh_shaders_future);
+
+	let (mut mesh_batch, mesh_batch_future) = MeshBatch::new(&window, render_pass.clone()).unwrap();
+
+	// one upload of the geometry, drawn 10000 times with a transform each and culled on the GPU
+	let (cube, cube_future) =
+		Mesh::from_geometry(
+			&window,
+			render_pass.clone(),
+			MeshGeometry::cube(),
+			Color::from_linear(0.8, 0.3, 0.5, 1.0),
+			Transform::identity(),
+		)
+		.unwrap();
+	let transforms =
+		(0..GRID * GRID).map(|i| {
+			let (x, z) = ((i % GRID) as f32, (i / GRID) as f32);
+			let position = vec3((x - GRID as f32 / 2.0) * 2.0, ((x * 0.3).sin() + (z * 0.2).cos()) * 2.0, -z * 2.0);
+			Transform::new(position, Quaternion::from_angle_y(Rad(i as f32)))
+		});
+	let (instances, instances_future) =
+		GpuCulledMesh::new(&render_pass, &cube, transforms).expect("instancing needs compute shader support");
+	mesh_batch.add_drawable(Box::new(instances));
+	window.join_future(mesh_batch_future.join(cube_future).join(instances_future));
+
+	mesh_batch.add_light(Light::Directional(DirectionalLight {
+		direction: vec3(1.0, 4.0, -2.0).normalize(),
+		color: Color::from_temperature(5500.0),
+		illuminance: 1000.0,
+	}));
+
+	let [win_width, win_height] = window.images()[0].dimensions().width_height();
+	let mut camera =
+		Camera::new(
+			&window,
+			Transform::from_position(vec3(0.0, -20.0, 20.0)),
+			win_width as f32 / win_height as f32,
+			90.0,
+			0.1,
+			1000.0,
+		)
+		.unwrap();
+
+	let (sprite_shaders, sprite_shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+	let sprite_batch_shared = SpriteBatchShared::new(sprite_shaders, window.format());
+	let font = window.device().get_font("examples/assets/consola.ttf", 16.0).unwrap();
+	let stats_overlay = StatsOverlay::new(font, [10.0, 20.0]);
+	let stats_toggle = stats_overlay.toggle();
+
+	let (mut hud, hud_future) = SpriteBatch::new(&window, &window, sprite_batch_shared).unwrap();
+	hud.set_blend(LayerBlend::Over);
+	hud.add_sprite(Box::new(stats_overlay));
+	window.join_future(sprite_shaders_future.join(hud_future));
+
+	let start = Instant::now();
+	loop {
+		let mut done = false;
+		ctx.poll_events(|event| match event {
+			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+			Event::WindowEvent {
+				event: WindowEvent::KeyboardInput {
+					input: KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F3),
+						..
+					},
+					..
+				},
+				..
+			} => stats_toggle.toggle(),
+			Event::WindowEvent { event: WindowEvent::Resized(size), .. } => if size.width > 0.0 && size.height > 0.0 {
+				camera.set_projection((size.width / size.height) as f32, 90.0, 0.1, 1000.0).unwrap();
+			},
+			_ => (),
+		});
+
+		if done {
+			break;
+		}
+
+		let elapsed = start.elapsed();
+		let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0;
+		camera.set_rotation(Quaternion::from_angle_y(Rad(seconds * 0.1)) * Quaternion::from_angle_x(Rad(0.4))).unwrap();
+
+		window
+			.present_composited(|window, image_num, compositor| {
+				let (commands, commands_future) = mesh_batch.commands(window, window, image_num, &camera).unwrap();
+				compositor.add(Layer::SCENE, commands, commands_future);
+				hud.composite(window, window, image_num, compositor)
+			})
+			.unwrap();
+	}
+}
@@ -4,7 +4,7 @@ extern crate multiinput;
 extern crate nice_game;
 extern crate simplelog;
 
-use cgmath::{ prelude::*, Quaternion, Rad, vec2, vec3, Vector2, Vector3 };
+use cgmath::{ prelude::*, Quaternion, Rad, vec2, vec3, Vector3 };
 use futures::executor::block_on;
 use multiinput::{ DeviceType, KeyId, RawEvent, RawInputManager, State };
 use nice_game::{
@@ -13,10 +13,13 @@ use nice_game::{
 	RenderTarget,
 	Version,
 	batch::{
-		mesh::{ Mesh, MeshBatch, MeshShaders, MeshRenderPass },
+		mesh::{ DirectionalLight, Light, Mesh, MeshBatch, MeshShaders, MeshRenderPass, PointLight },
 	},
 	camera::Camera,
-	window::{ Event, EventsLoop, MouseButton, MouseCursor, Window, WindowEvent },
+	color::Color,
+	collision::{ CharacterController, CharacterControllerConfig, CollisionWorld, MeshCollider },
+	math::Transform,
+	window::{ Event, MouseButton, MouseCursor, WindowEvent },
 };
 use simplelog::{ LevelFilter, SimpleLogger };
 use std::f32::consts::PI;
@@ -24,46 +27,57 @@ use std::f32::consts::PI;
 fn main() {
 	SimpleLogger::init(LevelFilter::Debug, simplelog::Config::default()).unwrap();
 
-	let mut events = EventsLoop::new();
-
-	let mut window =
-		Window::new(
-			&Context::new(
-				Some("Triangle Example"),
-				Some(Version {
-					major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-					minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-					patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-				}),
-			).unwrap(),
-			&mut events,
-			"nIce Game"
-		);
-
-	let (mesh_batch_shaders, mesh_batch_shaders_future) = MeshShaders::new(&mut window).unwrap();
+	let mut ctx =
+		Context::new(
+			Some("Triangle Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
+
+	let (mesh_batch_shaders, mesh_batch_shaders_future) = MeshShaders::new(&window).unwrap();
 	let mesh_batch_shared = MeshRenderPass::new(mesh_batch_shaders, window.format());
 
+	let mesh_transform = Transform::new(vec3(0.0, 0.0, -0.5), Quaternion::from_angle_y(Rad(PI / 2.0)));
 	let (mesh, mesh_future) =
 		block_on(
 			Mesh::from_file(
 				&window,
 				mesh_batch_shared.clone(),
 				"examples/assets/p250/p250.nmd",
-				vec3(0.0, 0.0, -0.5),
-				Quaternion::one() * Quaternion::from_angle_y(Rad(PI / 2.0)),
+				mesh_transform,
 			)
 		).unwrap();
+	let mut world = CollisionWorld::new();
+	world.add_collider(MeshCollider::from_mesh(&mesh));
 
 	let (mut mesh_batch, mesh_batch_future) = MeshBatch::new(&window, mesh_batch_shared).unwrap();
-	mesh_batch.add_mesh(mesh);
-
-	let mut character = Character::new();
+	let mesh_handle = mesh_batch.add_mesh(mesh);
+	mesh_batch.add_light(Light::Directional(DirectionalLight {
+		direction: vec3(1.0, 4.0, -2.0).normalize(),
+		color: Color::from_temperature(4000.0),
+		illuminance: 1000.0,
+	}));
+	mesh_batch.add_light(Light::Point(PointLight {
+		position: vec3(14.5, -11.0, -28.5),
+		color: Color::from_temperature(9000.0),
+		lumens: 50000.0,
+		radius: 5.0,
+	}));
+	let mut highlighted = false;
+
+	let mut character = CharacterController::new(CharacterControllerConfig::default(), Vector3::zero());
+	let mut look = vec2(0.0, 0.0);
 	let [win_width, win_height] = window.images()[0].dimensions().width_height();
 	let mut camera =
 		Camera::new(
 			&window,
-			Vector3::zero(),
-			Quaternion::one(),
+			Transform::identity(),
 			win_width as f32 / win_height as f32,
 			100.0,
 			0.05,
@@ -78,7 +92,6 @@ fn main() {
 	let mut s_down = false;
 	let mut d_down = false;
 	let mut space_down = false;
-	let mut shift_down = false;
 
 	let mut raw_input = RawInputManager::new().unwrap();
 	raw_input.register_devices(DeviceType::Keyboards);
@@ -87,7 +100,7 @@ fn main() {
 	loop {
 		let mut done = false;
 
-		events.poll_events(|event| match event {
+		ctx.poll_events(|event| match event {
 			Event::WindowEvent { event: WindowEvent::AxisMotion { axis, value, .. } , .. } => {
 				println!("axis {}, value {}", axis, value);
 			},
@@ -100,8 +113,8 @@ fn main() {
 				window.set_cursor(MouseCursor::Grab);
 				controls_active = true;
 			},
-			Event::WindowEvent { event: WindowEvent::Resized(_), .. } => {
-				camera.set_projection(win_width as f32 / win_height as f32, 100.0, 0.05, 1500.0).unwrap();
+			Event::WindowEvent { event: WindowEvent::Resized(size), .. } => if size.width > 0.0 && size.height > 0.0 {
+				camera.set_projection((size.width / size.height) as f32, 100.0, 0.05, 1500.0).unwrap();
 			},
 			_ => (),
 		});
@@ -119,21 +132,23 @@ fn main() {
 				RawEvent::KeyboardEvent(_,  KeyId::D, State::Released) => d_down = false,
 				RawEvent::KeyboardEvent(_,  KeyId::Space, State::Pressed) => space_down = true,
 				RawEvent::KeyboardEvent(_,  KeyId::Space, State::Released) => space_down = false,
-				RawEvent::KeyboardEvent(_,  KeyId::Shift, State::Pressed) => shift_down = true,
-				RawEvent::KeyboardEvent(_,  KeyId::Shift, State::Released) => shift_down = false,
+				RawEvent::KeyboardEvent(_,  KeyId::H, State::Pressed) => {
+					highlighted = !highlighted;
+					mesh_batch.set_highlighted(mesh_handle, if highlighted { Some([1.0, 0.6, 0.1, 1.0]) } else { None });
+				},
 				RawEvent::MouseMoveEvent(_, x, y) => if controls_active {
-					character.rotation += vec2(x as f32 / 300.0, y as f32 / 300.0);
+					look += vec2(x as f32 / 300.0, y as f32 / 300.0);
 
-					if character.rotation.x > 2.0 {
-						character.rotation.x -= 4.0;
-					} else if character.rotation.x < -2.0 {
-						character.rotation.x += 4.0;
+					if look.x > 2.0 {
+						look.x -= 4.0;
+					} else if look.x < -2.0 {
+						look.x += 4.0;
 					}
 
-					if character.rotation.y > 1.0 {
-						character.rotation.y = 1.0;
-					} else if character.rotation.y < -1.0 {
-						character.rotation.y = -1.0;
+					if look.y > 1.0 {
+						look.y = 1.0;
+					} else if look.y < -1.0 {
+						look.y = -1.0;
 					}
 				},
 				_ => (),
@@ -144,17 +159,20 @@ fn main() {
 			break;
 		}
 
-		let yaw = Quaternion::from_angle_y(Rad(-character.rotation.x * PI / 2.0));
+		character.set_yaw(Rad(look.x * PI / 2.0));
+		let yaw = character.rotation();
 
-		if controls_active && w_down { character.position += yaw.rotate_vector(vec3(0.0, 0.0, -0.1)); }
-		if controls_active && a_down { character.position += yaw.rotate_vector(vec3(-0.1, 0.0, 0.0)); }
-		if controls_active && s_down { character.position += yaw.rotate_vector(vec3(0.0, 0.0, 0.1)); }
-		if controls_active && d_down { character.position += yaw.rotate_vector(vec3(0.1, 0.0, 0.0)); }
-		if controls_active && space_down { character.position.y -= 0.1; }
-		if controls_active && shift_down { character.position.y += 0.1; }
+		let mut velocity = Vector3::zero();
+		if controls_active && w_down { velocity += yaw.rotate_vector(vec3(0.0, 0.0, -WALK_SPEED)); }
+		if controls_active && a_down { velocity += yaw.rotate_vector(vec3(-WALK_SPEED, 0.0, 0.0)); }
+		if controls_active && s_down { velocity += yaw.rotate_vector(vec3(0.0, 0.0, WALK_SPEED)); }
+		if controls_active && d_down { velocity += yaw.rotate_vector(vec3(WALK_SPEED, 0.0, 0.0)); }
+		if controls_active && space_down { character.jump(); }
 
-		camera.set_position(character.position).unwrap();
-		camera.set_rotation(yaw * Quaternion::from_angle_x(Rad(character.rotation.y * PI / 2.0))).unwrap();
+		character.update(&world, velocity, FRAME_TIME);
+
+		camera.set_position(character.eye_position(EYE_HEIGHT)).unwrap();
+		camera.set_rotation(yaw * Quaternion::from_angle_x(Rad(look.y * PI / 2.0))).unwrap();
 
 		window
 			.present(|window, image_num, mut future| {
@@ -162,7 +180,7 @@ fn main() {
 				if let Some(cmds_future) = cmds_future {
 					future = Box::new(future.join(cmds_future));
 				}
-				future.then_execute(window.queue().clone(), cmds).unwrap()
+				future.then_execute(window.device().queue().clone(), cmds).unwrap()
 			})
 			.unwrap();
 	}
@@ -170,12 +188,6 @@ fn main() {
 	window.set_cursor(MouseCursor::Default);
 }
 
-struct Character {
-	position: Vector3<f32>,
-	rotation: Vector2<f32>,
-}
-impl Character {
-	fn new() -> Self {
-		Self { position: vec3(0.0, 0.0, 0.0), rotation: vec2(0.0, 0.0) }
-	}
-}
+const EYE_HEIGHT: f32 = 1.6;
+const FRAME_TIME: f32 = 1.0 / 60.0;
+const WALK_SPEED: f32 = 4.0;
@@ -0,0 +1,130 @@
+extern crate futures;
+extern crate nice_game;
+
+use futures::executor::block_on;
+use nice_game::{
+	Context,
+	GpuFuture,
+	RenderTarget,
+	Version,
+	batch::sprite::{ LayerBlend, SpriteBatch, SpriteBatchShaders, SpriteBatchShared, StatsOverlay },
+	color::Color,
+	compositor::Layer,
+	texture::{ ImageFormat, ImmutableTexture, TargetTexture },
+	window::{ ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent },
+};
+
+/// Size of the first target. Each pass after it tiles the previous target 2x2, so doubles the size.
+const BASE_SIZE: u32 = 128;
+const PASSES: usize = 3;
+/// A different background for each pass, so the nesting shows.
+const CLEAR_COLORS: [Color; PASSES] =
+	[
+		Color { r: 0.2, g: 0.02, b: 0.02, a: 1.0 },
+		Color { r: 0.02, g: 0.2, b: 0.02, a: 1.0 },
+		Color { r: 0.02, g: 0.02, b: 0.2, a: 1.0 },
+	];
+
+fn main() {
+	let mut ctx =
+		Context::new(
+			Some("Post Chain Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
+
+	let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+	let sprite_batch_shared = SpriteBatchShared::new(shaders, window.format());
+	window.join_future(shaders_future);
+
+	let (texture, texture_future) =
+		block_on(ImmutableTexture::from_file_with_format(&window, "examples/assets/colors.png", ImageFormat::PNG, true))
+			.unwrap();
+	window.join_future(texture_future);
+
+	// every pass reads the target the one before it wrote, so each runs on a lower layer than the next
+	let mut targets: Vec<TargetTexture> = vec![];
+	let mut passes = vec![];
+	for pass in 0..PASSES {
+		let size = BASE_SIZE << pass;
+		let target = TargetTexture::new(&window, [size, size]).unwrap();
+		let (mut batch, batch_future) = SpriteBatch::new(&window, &target, sprite_batch_shared.clone()).unwrap();
+		batch.set_layer(Layer(Layer::OFFSCREEN.0 - (PASSES - pass) as i32));
+		batch.set_clear_color(CLEAR_COLORS[pass]);
+		window.join_future(batch_future);
+
+		match targets.last() {
+			Some(previous) => {
+				let half = (size / 2) as f32;
+				for &position in &[[0.0, 0.0], [half, 0.0], [0.0, half], [half, half]] {
+					let (sprite, sprite_future) = sprite_batch_shared.create_sprite(previous, position).unwrap();
+					batch.add_sprite(Box::new(sprite));
+					window.join_future(sprite_future);
+				}
+			},
+			None => {
+				let (sprite, sprite_future) = sprite_batch_shared.create_sprite(&texture, [0.0, 0.0]).unwrap();
+				batch.add_sprite(Box::new(sprite));
+				window.join_future(sprite_future);
+			},
+		}
+
+		targets.push(target);
+		passes.push(batch);
+	}
+
+	let (output, output_future) =
+		sprite_batch_shared.create_sprite(targets.last().unwrap(), [10.0, 40.0]).unwrap();
+	let (mut window_batch, window_batch_future) =
+		SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+	window_batch.set_layer(Layer::POST);
+	window_batch.add_sprite(Box::new(output));
+
+	let font = window.device().get_font("examples/assets/consola.ttf", 16.0).unwrap();
+	let stats_overlay = StatsOverlay::new(font, [10.0, 20.0]);
+	let stats_toggle = stats_overlay.toggle();
+	let (mut hud, hud_future) = SpriteBatch::new(&window, &window, sprite_batch_shared).unwrap();
+	hud.set_blend(LayerBlend::Over);
+	hud.add_sprite(Box::new(stats_overlay));
+
+	window.join_future(output_future.join(window_batch_future).join(hud_future));
+
+	loop {
+		let mut done = false;
+		ctx.poll_events(|event| match event {
+			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+			Event::WindowEvent {
+				event: WindowEvent::KeyboardInput {
+					input: KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F3),
+						..
+					},
+					..
+				},
+				..
+			} => stats_toggle.toggle(),
+			_ => (),
+		});
+
+		if done {
+			break;
+		}
+
+		window
+			.present_composited(|window, image_num, compositor| {
+				for (batch, target) in passes.iter_mut().zip(&targets) {
+					batch.composite(window, target, 0, compositor)?;
+				}
+				window_batch.composite(window, window, image_num, compositor)?;
+				hud.composite(window, window, image_num, compositor)
+			})
+			.unwrap();
+	}
+}
@@ -0,0 +1,126 @@
+extern crate futures;
+extern crate nice_game;
+
+use futures::{ executor::block_on, future, prelude::* };
+use nice_game::{
+	Context,
+	GpuFuture,
+	RenderTarget,
+	Version,
+	batch::sprite::{ LayerBlend, SpriteBatch, SpriteBatchShaders, SpriteBatchShared, StatsOverlay },
+	streaming,
+	texture::{ ImageFormat, ImmutableTexture },
+	window::{ ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent },
+};
+use std::{ sync::mpsc, thread };
+
+/// Times the test texture is loaded. There's only one in the assets, but every load goes through the whole pipeline.
+const COUNT: usize = 256;
+const COLUMNS: usize = 16;
+const SPACING: f32 = 48.0;
+
+fn main() {
+	let mut ctx =
+		Context::new(
+			Some("Streaming Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
+
+	let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+	let sprite_batch_shared = SpriteBatchShared::new(shaders, window.format());
+
+	let (mut sprite_batch, sprite_batch_future) =
+		SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+
+	let font = window.device().get_font("examples/assets/consola.ttf", 16.0).unwrap();
+	let stats_overlay = StatsOverlay::new(font.clone(), [10.0, 20.0]);
+	let stats_toggle = stats_overlay.toggle();
+	let (mut hud, hud_future) = SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+	hud.set_blend(LayerBlend::Over);
+	hud.add_sprite(Box::new(stats_overlay));
+
+	// progress is in a batch of its own, so it can be replaced without touching the overlay
+	let (mut progress, progress_future) = SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+	progress.set_blend(LayerBlend::Over);
+
+	window.join_future(shaders_future.join(sprite_batch_future).join(hud_future).join(progress_future));
+
+	// the stream is driven on a thread of its own, so the window keeps presenting while files load
+	let paths = (0..COUNT).map(|_| "examples/assets/colors.png");
+	let loads = streaming::load_all::<ImmutableTexture>(&window, paths, (ImageFormat::PNG, true), 8);
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		block_on(loads.for_each(move |loaded| {
+			// the receiver is gone once the window closes, and the remaining loads can be dropped
+			let _ = sender.send(loaded);
+			future::ready(())
+		}))
+	});
+
+	let mut loaded = 0;
+	let mut failed = 0;
+	loop {
+		let mut done = false;
+		ctx.poll_events(|event| match event {
+			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+			Event::WindowEvent {
+				event: WindowEvent::KeyboardInput {
+					input: KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F3),
+						..
+					},
+					..
+				},
+				..
+			} => stats_toggle.toggle(),
+			_ => (),
+		});
+
+		if done {
+			break;
+		}
+
+		let mut changed = false;
+		for item in receiver.try_iter() {
+			changed = true;
+			let (texture, texture_future) =
+				match item.result {
+					Ok(result) => result,
+					Err(err) => {
+						println!("failed to load {}: {:?}", item.path.display(), err);
+						failed += 1;
+						continue;
+					},
+				};
+
+			let position =
+				[10.0 + (item.index % COLUMNS) as f32 * SPACING, 60.0 + (item.index / COLUMNS) as f32 * SPACING];
+			let (sprite, sprite_future) = sprite_batch_shared.create_sprite(&texture, position).unwrap();
+			sprite_batch.add_sprite(Box::new(sprite));
+			window.join_future(texture_future.join(sprite_future));
+			loaded += 1;
+		}
+
+		if changed {
+			let text = format!("loaded {} of {}, {} failed", loaded, COUNT, failed);
+			progress.clear_sprites();
+			progress.add_sprite(Box::new(font.make_sprite(&text, &sprite_batch_shared, [10.0, 40.0]).unwrap()));
+		}
+
+		window
+			.present_composited(|window, image_num, compositor| {
+				sprite_batch.composite(window, window, image_num, compositor)?;
+				progress.composite(window, window, image_num, compositor)?;
+				hud.composite(window, window, image_num, compositor)
+			})
+			.unwrap();
+	}
+}
@@ -7,27 +7,25 @@ use nice_game::{
 	GpuFuture,
 	RenderTarget,
 	Version,
-	batch::sprite::{ Sprite, SpriteBatch, SpriteBatchShaders, SpriteBatchShared },
+	batch::sprite::{ SpriteBatch, SpriteBatchShaders, SpriteBatchShared },
+	compositor::Layer,
 	texture::{ ImageFormat, ImmutableTexture, TargetTexture },
-	window::{ Event, EventsLoop, Window, WindowEvent },
+	window::{ Event, WindowEvent },
 };
 
 fn main() {
-	let mut events = EventsLoop::new();
-
-	let mut window =
-		Window::new(
-			&Context::new(
-				Some("Triangle Example"),
-				Some(Version {
-					major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-					minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-					patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-				}),
-			).unwrap(),
-			&mut events,
-			"nIce Game"
-		);
+	let mut ctx =
+		Context::new(
+			Some("Triangle Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
 
 	let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
 	let sprite_batch_shared = SpriteBatchShared::new(shaders, window.format());
@@ -39,14 +37,16 @@ fn main() {
 			.unwrap();
 
 	let (texture_sprite, texture_sprite_future) =
-		Sprite::new(&window, &sprite_batch_shared, &texture, [0.0, 0.0]).unwrap();
+		sprite_batch_shared.create_sprite(&texture, [0.0, 0.0]).unwrap();
 
 	let (mut target_sprite_batch, target_sprite_batch_future) =
 		SpriteBatch::new(&window, &target, sprite_batch_shared.clone()).unwrap();
 	target_sprite_batch.add_sprite(Box::new(texture_sprite));
+	// window_sprite_batch samples target, so this has to run first
+	target_sprite_batch.set_layer(Layer::OFFSCREEN);
 
 	let (target_sprite, target_sprite_future) =
-		Sprite::new(&window, &sprite_batch_shared, &target, [10.0, 10.0]).unwrap();
+		sprite_batch_shared.create_sprite(&target, [10.0, 10.0]).unwrap();
 
 	let (mut window_sprite_batch, window_sprite_batch_future) =
 		SpriteBatch::new(&window, &window, sprite_batch_shared).unwrap();
@@ -62,7 +62,7 @@ fn main() {
 
 	loop {
 		let mut done = false;
-		events.poll_events(|event| match event {
+		ctx.poll_events(|event| match event {
 			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
 			_ => (),
 		});
@@ -72,23 +72,10 @@ fn main() {
 		}
 
 		window
-			.present(|window, image_num, mut future| {
-				let (target_commands, target_future) = target_sprite_batch.commands(window, &target, 0).unwrap();
-				if let Some(target_future) = target_future {
-					future = Box::new(future.join(target_future));
-				}
-
-				let (window_commands, window_future) = window_sprite_batch.commands(window, window, image_num).unwrap();
-				if let Some(window_future) = window_future {
-					future = Box::new(future.join(window_future));
-				}
-
-				future
-					.then_execute(window.queue().clone(), target_commands)
-					.unwrap()
-					.then_signal_semaphore()
-					.then_execute(window.queue().clone(), window_commands)
-					.unwrap()
+			.present_composited(|window, image_num, compositor| {
+				// added second, but runs first because of its layer
+				window_sprite_batch.composite(window, window, image_num, compositor)?;
+				target_sprite_batch.composite(window, &target, 0, compositor)
 			})
 			.unwrap();
 	}
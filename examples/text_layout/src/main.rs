@@ -0,0 +1,134 @@
+extern crate nice_game;
+
+use nice_game::{
+	Context,
+	GpuFuture,
+	RenderTarget,
+	Version,
+	batch::sprite::{ Font, LayerBlend, SpriteBatch, SpriteBatchShaders, SpriteBatchShared, StatsOverlay, TextSprite },
+	window::{ ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent },
+};
+use std::sync::Arc;
+
+const TEXT: &str =
+	"The quick brown fox jumped over the lazy dog. Pack my box with five dozen liquor jugs. How vexingly quick \
+	daft zebras jump! Sphinx of black quartz, judge my vow. The five boxing wizards jump quickly. Jackdaws love my \
+	big sphinx of quartz.";
+const MARGIN: f32 = 20.0;
+const COLUMN_GAP: f32 = 30.0;
+
+fn main() {
+	let mut ctx =
+		Context::new(
+			Some("Text Layout Example"),
+			Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		)
+		.unwrap();
+
+	let mut window = ctx.create_window("nIce Game");
+
+	let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+	let sprite_batch_shared = SpriteBatchShared::new(shaders, window.format());
+
+	let fonts =
+		[
+			window.device().get_font("examples/assets/consola.ttf", 14.0).unwrap(),
+			window.device().get_font("examples/assets/consola.ttf", 20.0).unwrap(),
+			window.device().get_font("examples/assets/consola.ttf", 28.0).unwrap(),
+		];
+
+	let (mut text_batch, text_batch_future) = SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+	let [width, _] = window.images()[0].dimensions().width_height();
+	layout(&mut text_batch, &sprite_batch_shared, &fonts, width as f32);
+
+	let stats_overlay = StatsOverlay::new(fonts[0].clone(), [MARGIN, 14.0]);
+	let stats_toggle = stats_overlay.toggle();
+	let (mut hud, hud_future) = SpriteBatch::new(&window, &window, sprite_batch_shared.clone()).unwrap();
+	hud.set_blend(LayerBlend::Over);
+	hud.add_sprite(Box::new(stats_overlay));
+
+	window.join_future(shaders_future.join(text_batch_future).join(hud_future));
+
+	loop {
+		let mut done = false;
+		let mut resized = None;
+		ctx.poll_events(|event| match event {
+			Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => done = true,
+			Event::WindowEvent {
+				event: WindowEvent::KeyboardInput {
+					input: KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::F3),
+						..
+					},
+					..
+				},
+				..
+			} => stats_toggle.toggle(),
+			Event::WindowEvent { event: WindowEvent::Resized(size), .. } => resized = Some(size),
+			_ => (),
+		});
+
+		if done {
+			break;
+		}
+
+		if let Some(size) = resized {
+			// wrap again for the new width
+			text_batch.clear_sprites();
+			layout(&mut text_batch, &sprite_batch_shared, &fonts, size.width as f32);
+		}
+
+		window
+			.present_composited(|window, image_num, compositor| {
+				text_batch.composite(window, window, image_num, compositor)?;
+				hud.composite(window, window, image_num, compositor)
+			})
+			.unwrap();
+	}
+}
+
+/// Sets `TEXT` once per font, in columns side by side that split the window's width.
+fn layout(batch: &mut SpriteBatch, shared: &SpriteBatchShared, fonts: &[Arc<Font>], width: f32) {
+	let column_width = ((width - MARGIN * 2.0 - COLUMN_GAP * (fonts.len() - 1) as f32) / fonts.len() as f32).max(1.0);
+	for (i, font) in fonts.iter().enumerate() {
+		let x = MARGIN + (column_width + COLUMN_GAP) * i as f32;
+		for line in wrap(font, shared, TEXT, [x, 60.0], column_width) {
+			batch.add_sprite(Box::new(line));
+		}
+	}
+}
+
+/// Breaks `text` into lines no wider than `width` at spaces, using each candidate line's bounds. A single word wider
+/// than `width` gets a line to itself.
+fn wrap(font: &Font, shared: &SpriteBatchShared, text: &str, [x, mut y]: [f32; 2], width: f32) -> Vec<TextSprite> {
+	let mut lines = vec![];
+	let mut line = String::new();
+	let mut sprite = None;
+
+	for word in text.split_whitespace() {
+		let candidate = if line.is_empty() { word.to_owned() } else { format!("{} {}", line, word) };
+		let candidate_sprite = font.make_sprite(&candidate, shared, [x, y]).unwrap();
+
+		if candidate_sprite.bounds().size()[0] <= width || line.is_empty() {
+			line = candidate;
+			sprite = Some(candidate_sprite);
+			continue;
+		}
+
+		// the word doesn't fit, so finish the line and start the next with it
+		let finished: TextSprite = sprite.take().unwrap();
+		let line_height = finished.bounds().size()[1];
+		lines.push(finished);
+		y += line_height * 1.4;
+		line = word.to_owned();
+		sprite = Some(font.make_sprite(&line, shared, [x, y]).unwrap());
+	}
+
+	lines.extend(sprite);
+	lines
+}
@@ -0,0 +1,15 @@
+//! `cargo fuzz run nmdl_read`. Anything `NiceModel::read` accepts has passed validation, so it's written back out and
+//! read again to check the two agree.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use nice_game::batch::mesh::NiceModel;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(model) = NiceModel::read(data) {
+		let mut written = vec![];
+		model.write(&mut written).unwrap();
+		let read = NiceModel::read(&written).expect("written model doesn't read back");
+		assert_eq!(read.indices, model.indices);
+		assert_eq!(read.materials.len(), model.materials.len());
+	}
+});
@@ -0,0 +1,10 @@
+#version 450
+layout(location = 0) in vec2 backdrop_coords;
+layout(location = 1) flat in vec4 tint;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 1, binding = 0) uniform sampler2D backdrop;
+
+void main() {
+	f_color = vec4(mix(texture(backdrop, backdrop_coords).rgb, tint.rgb, tint.a), 1);
+}
@@ -0,0 +1,22 @@
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 backdrop_coords;
+layout(location = 1) flat out vec4 out_tint;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; } target;
+
+layout(push_constant) uniform Backdrop {
+	// the panel, in target pixels
+	vec2 min;
+	vec2 size;
+	// linear color mixed over the blurred backdrop by its alpha
+	vec4 tint;
+};
+
+void main() {
+	vec2 pixel = min + size * position;
+	// the blurred backdrop covers the whole target, so it's sampled where the panel is on it
+	backdrop_coords = pixel / target.size;
+	out_tint = tint;
+	gl_Position = vec4(2 * pixel / target.size - 1, 0.0, 1.0);
+}
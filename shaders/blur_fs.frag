@@ -0,0 +1,31 @@
+#version 450
+// taps on either side of the center, enough for a sigma of about 10 pixels
+#define MAX_RADIUS 32
+
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+
+layout(push_constant) uniform Blur {
+	// one output pixel along the axis this pass blurs, in texture coordinates
+	vec2 step;
+	float sigma;
+};
+
+void main() {
+	vec4 sum = texture(tex, tex_coords);
+	if (sigma <= 0) {
+		f_color = sum;
+		return;
+	}
+
+	int radius = min(int(ceil(sigma * 3)), MAX_RADIUS);
+	float total = 1;
+	for (int i = 1; i <= radius; i++) {
+		float weight = exp(-float(i * i) / (2 * sigma * sigma));
+		sum += (texture(tex, tex_coords + step * i) + texture(tex, tex_coords - step * i)) * weight;
+		total += 2 * weight;
+	}
+	f_color = sum / total;
+}
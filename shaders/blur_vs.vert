@@ -0,0 +1,8 @@
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 tex_coords;
+
+void main() {
+	tex_coords = position;
+	gl_Position = vec4(position * 2 - 1, 0.0, 1.0);
+}
@@ -0,0 +1,82 @@
+#version 450
+layout(location = 0) in vec3 position_cs;
+layout(location = 1) in vec3 normal_cs;
+layout(location = 2) in vec2 texcoord;
+layout(location = 3) in vec3 base_albedo;
+layout(location = 4) in float ambient_occlusion;
+layout(location = 5) flat in float height_scale;
+// min for views straight at the surface, max for grazing views
+layout(location = 6) flat in uvec2 parallax_samples;
+layout(location = 7) flat in float light_probes;
+
+layout(location = 0) out vec4 out_albedo;
+layout(location = 1) out vec4 out_normal_cs;
+layout(location = 2) out vec4 out_mask;
+
+layout(set = 1, binding = 2) uniform Highlight { vec4 highlight; };
+// unused here, but declared so the set has room for Mesh::set_user_params, for pipelines set with Mesh::set_pipeline
+layout(set = 1, binding = 3) uniform UserParams { vec4 user_params[4]; };
+
+layout(set = 2, binding = 1) uniform sampler2D tex_albedo;
+layout(set = 2, binding = 2) uniform sampler2D tex_normal;
+layout(set = 2, binding = 3) uniform sampler2D tex_height;
+
+// unit vector folded onto an octahedron and flattened into [0, 1], so two channels hold it
+vec2 oct_encode(vec3 n) {
+	n /= abs(n.x) + abs(n.y) + abs(n.z);
+	vec2 folded = n.z >= 0 ? n.xy : (1.0 - abs(n.yx)) * vec2(n.x >= 0 ? 1 : -1, n.y >= 0 ? 1 : -1);
+	return folded * 0.5 + 0.5;
+}
+
+mat3 tangent_frame(vec3 fWorldNormal, vec3 vPosition, vec2 vTexCoord) {
+	vec3 dxPosition = dFdx(vPosition);
+	vec3 dyPosition = dFdy(vPosition);
+	vec2 dxTexCoord = dFdx(vTexCoord);
+	vec2 dyTexCoord = dFdy(vTexCoord);
+	if (dot(dxTexCoord, dxTexCoord) == 0) dxTexCoord = vec2(1, 0);
+	if (dot(dyTexCoord, dyTexCoord) == 0) dyTexCoord = vec2(0, -1);
+	vec3 dxPosPerp = cross(fWorldNormal, dxPosition);
+	vec3 dyPosPerp = cross(dyPosition, fWorldNormal);
+	vec3 fTangent = dxPosPerp * dyTexCoord.x + dyPosPerp * dxTexCoord.x;
+	vec3 fBitangent = dxPosPerp * dyTexCoord.y + dyPosPerp * dxTexCoord.y;
+	float tangentScale = inversesqrt(max(dot(fTangent, fTangent), dot(fBitangent, fBitangent)));
+	return mat3(fTangent * tangentScale, fBitangent * tangentScale, fWorldNormal);
+}
+
+// marches the view ray down through the height map until it passes under it, then interpolates between the last two
+// steps. derivatives are taken from the unshifted texcoords, since the loop isn't uniform across the quad
+vec2 parallax_occlusion(mat3 tbn) {
+	vec3 view_ts = normalize(transpose(tbn) * -position_cs);
+	vec2 dx = dFdx(texcoord);
+	vec2 dy = dFdy(texcoord);
+
+	float steps = max(mix(float(parallax_samples.y), float(parallax_samples.x), abs(view_ts.z)), 1.0);
+	float step_depth = 1.0 / steps;
+	vec2 step_uv = view_ts.xy / max(view_ts.z, 0.05) * height_scale * step_depth;
+
+	vec2 uv = texcoord;
+	float depth = 0.0;
+	float surface_depth = 1.0 - textureGrad(tex_height, uv, dx, dy).r;
+	for (int i = 0; i < int(steps) && depth < surface_depth; i++) {
+		uv -= step_uv;
+		depth += step_depth;
+		surface_depth = 1.0 - textureGrad(tex_height, uv, dx, dy).r;
+	}
+
+	float after = surface_depth - depth;
+	float before = 1.0 - textureGrad(tex_height, uv + step_uv, dx, dy).r - depth + step_depth;
+	float weight = after / min(after - before, -1e-5);
+	return mix(uv, uv + step_uv, weight);
+}
+
+void main() {
+	mat3 tbn = tangent_frame(normalize(normal_cs), position_cs, texcoord);
+	vec2 uv = height_scale > 0.0 ? parallax_occlusion(tbn) : texcoord;
+	vec4 albedo = texture(tex_albedo, uv);
+	vec3 normal_ts = texture(tex_normal, uv).xyz * 2.0 - 1.0;
+	vec3 normal_cs = normalize(tbn * normal_ts);
+	albedo.rgb = mix(base_albedo, albedo.rgb, albedo.a);
+	out_albedo = vec4(sqrt(albedo.rgb), 0);
+	out_normal_cs = vec4(oct_encode(normal_cs), ambient_occlusion, light_probes);
+	out_mask = highlight;
+}
@@ -0,0 +1,27 @@
+#version 450
+layout(set = 0, binding = 0) uniform Resolution { vec4 resolution; };
+layout(set = 0, binding = 1, input_attachment_index = 0) uniform subpassInput albedo;
+layout(set = 0, binding = 2, input_attachment_index = 1) uniform subpassInput normal;
+layout(set = 0, binding = 3, input_attachment_index = 2) uniform subpassInput depth;
+layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 1, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	vec4 camera_offset;
+};
+
+struct Light {
+	vec4 position_type;
+	vec4 direction_radius;
+	vec4 color_cookie;
+	vec4 cone;
+	mat4 cookie_matrix;
+};
+layout(set = 2, binding = 0) uniform LocalLights { Light lights[64]; };
+layout(set = 2, binding = 1) uniform sampler2D cookie0;
+layout(set = 2, binding = 2) uniform sampler2D cookie1;
+layout(set = 2, binding = 3) uniform sampler2D cookie2;
+layout(set = 2, binding = 4) uniform sampler2D cookie3;
+
+void main() {
+}
@@ -0,0 +1,102 @@
+#version 450
+layout(location = 0) flat in uint light_index;
+
+layout(location = 0) out vec4 out_lit;
+
+layout(set = 0, binding = 0) uniform Resolution { vec4 resolution; };
+layout(set = 0, binding = 1, input_attachment_index = 0) uniform subpassInput albedo;
+layout(set = 0, binding = 2, input_attachment_index = 1) uniform subpassInput normal;
+layout(set = 0, binding = 3, input_attachment_index = 2) uniform subpassInput depth;
+layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 1, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+struct Light {
+	// w is the type: 0 for directional, 1 for point, 2 for spot
+	vec4 position_type;
+	vec4 direction_radius;
+	// w is the cookie index, or -1 for none
+	vec4 color_cookie;
+	// cosines of the inner and outer cone angles, and 1 in z if the light is drawn with a cone
+	vec4 cone;
+	mat4 cookie_matrix;
+};
+layout(set = 2, binding = 0) uniform LocalLights { Light lights[64]; };
+layout(set = 2, binding = 1) uniform sampler2D cookie0;
+layout(set = 2, binding = 2) uniform sampler2D cookie1;
+layout(set = 2, binding = 3) uniform sampler2D cookie2;
+layout(set = 2, binding = 4) uniform sampler2D cookie3;
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec3 quat_mul(vec4 q, vec3 v) {
+	return cross(q.xyz, cross(q.xyz, v) + v * q.w) * 2.0 + v;
+}
+
+// inverse of oct_encode in the gbuffer shader
+vec3 oct_decode(vec2 e) {
+	e = e * 2.0 - 1.0;
+	vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+	float t = max(-n.z, 0);
+	n.xy += vec2(n.x >= 0 ? -t : t, n.y >= 0 ? -t : t);
+	return normalize(n);
+}
+
+vec3 sample_cookie(int index, vec2 uv) {
+	if (index == 0) return texture(cookie0, uv).rgb;
+	if (index == 1) return texture(cookie1, uv).rgb;
+	if (index == 2) return texture(cookie2, uv).rgb;
+	return texture(cookie3, uv).rgb;
+}
+
+vec3 shade(Light light, vec3 position_ws, vec3 normal_ws) {
+	uint light_type = uint(light.position_type.w);
+	vec3 color = light.color_cookie.rgb;
+
+	float radius = light.direction_radius.w;
+	float dist = distance(light.position_type.xyz, position_ws);
+	vec3 dir = normalize(light.position_type.xyz - position_ws);
+	float intensity = max(0, dot(normal_ws, dir));
+	// inverse square, windowed to reach 0 at the radius
+	float window = clamp(1 - pow(dist / radius, 4), 0, 1);
+	intensity *= window * window / max(dist * dist, 0.0001);
+
+	if (light_type == 2) {
+		intensity *= smoothstep(light.cone.y, light.cone.x, dot(-dir, light.direction_radius.xyz));
+
+		int cookie = int(light.color_cookie.w);
+		if (cookie >= 0 && intensity > 0) {
+			vec4 cookie_cs = light.cookie_matrix * vec4(position_ws, 1);
+			if (cookie_cs.w <= 0) {
+				return vec3(0);
+			}
+			color *= sample_cookie(cookie, cookie_cs.xy / cookie_cs.w * 0.5 + 0.5);
+		}
+	}
+
+	return color * intensity;
+}
+
+void main() {
+	// only scene pixels are marked, since overlay meshes are in front of every volume
+	float g_depth = subpassLoad(depth).x;
+	vec3 g_position_ds = vec3(gl_FragCoord.xy * resolution.zw, 2.0 * g_depth) - 1.0;
+	vec2 g_centered_ds = g_position_ds.xy - camera_offset.xy;
+	vec3 g_position_cs = vec3(g_centered_ds / camera_proj.xy, -1.0) * camera_proj.w / (g_position_ds.z + camera_proj.z);
+	vec3 g_position_ws = quat_mul(camera_rot, g_position_cs) + camera_pos;
+
+	vec4 g_normal_ao = subpassLoad(normal);
+	vec3 g_normal_cs = oct_decode(g_normal_ao.xy);
+	float g_ambient_occlusion = g_normal_ao.z;
+	vec3 g_normal_ws = quat_mul(camera_rot, g_normal_cs);
+
+	vec3 g_albedo = subpassLoad(albedo).rgb;
+	g_albedo *= g_albedo;
+
+	// added to the lit scene, with the baked contact shadows like the rest of its light
+	vec3 light = shade(lights[light_index], g_position_ws, g_normal_ws) * g_ambient_occlusion;
+	out_lit = vec4(g_albedo * light, 0);
+}
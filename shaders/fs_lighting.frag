@@ -0,0 +1,134 @@
+#version 450
+layout(location = 0) out vec4 out_lit;
+
+layout(set = 0, binding = 0) uniform Resolution { vec4 resolution; };
+layout(set = 0, binding = 1, input_attachment_index = 0) uniform subpassInput albedo;
+layout(set = 0, binding = 2, input_attachment_index = 1) uniform subpassInput normal;
+// for the positions light probes are sampled at. light volumes share the set
+layout(set = 0, binding = 3, input_attachment_index = 2) uniform subpassInput depth;
+layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 1, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+struct Light {
+	// w is the type: 0 for directional, 1 for point, 2 for spot
+	vec4 position_type;
+	vec4 direction_radius;
+	// w is the cookie index, or -1 for none
+	vec4 color_cookie;
+	// cosines of the inner and outer cone angles
+	vec4 cone;
+	mat4 cookie_matrix;
+};
+// directional lights. point and spot lights are drawn as volumes, by fs_light_volume
+layout(set = 2, binding = 0) uniform Lights {
+	uvec4 light_count;
+	// irradiance as spherical harmonics, with the constants folded in
+	vec4 ambient_sh[9];
+	// w is the exposure scale of the probes
+	vec4 probe_origin;
+	vec4 probe_spacing;
+	// w is 1 while there's a grid
+	uvec4 probe_counts;
+	Light lights[16];
+};
+// 9 coefficients a probe like ambient_sh, x fastest, then y, then z
+layout(set = 2, binding = 1) readonly buffer LightProbes { vec4 probe_sh[]; };
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec3 quat_mul(vec4 q, vec3 v) {
+	return cross(q.xyz, cross(q.xyz, v) + v * q.w) * 2.0 + v;
+}
+
+// inverse of oct_encode in the gbuffer shader
+vec3 oct_decode(vec2 e) {
+	e = e * 2.0 - 1.0;
+	vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+	float t = max(-n.z, 0);
+	n.xy += vec2(n.x >= 0 ? -t : t, n.y >= 0 ? -t : t);
+	return normalize(n);
+}
+
+vec3 eval_sh(vec4 coefficients[9], vec3 n) {
+	vec3 sh =
+		coefficients[0].rgb
+		+ coefficients[1].rgb * n.y
+		+ coefficients[2].rgb * n.z
+		+ coefficients[3].rgb * n.x
+		+ coefficients[4].rgb * (n.x * n.y)
+		+ coefficients[5].rgb * (n.y * n.z)
+		+ coefficients[6].rgb * (3 * n.z * n.z - 1)
+		+ coefficients[7].rgb * (n.x * n.z)
+		+ coefficients[8].rgb * (n.x * n.x - n.y * n.y);
+	// 2nd order ringing can dip below zero opposite bright lobes
+	return max(sh, 0);
+}
+
+// trilinear between the 8 probes around the position, clamped to the grid like `LightProbeGrid::sample`
+vec3 probe_ambient(vec3 position_ws, vec3 n) {
+	ivec3 counts = ivec3(probe_counts.xyz);
+	vec3 cell = clamp((position_ws - probe_origin.xyz) / probe_spacing.xyz, vec3(0), vec3(counts - 1));
+	ivec3 base = ivec3(cell);
+	ivec3 next = min(base + 1, counts - 1);
+	vec3 t = cell - vec3(base);
+
+	vec4 coefficients[9];
+	for (int i = 0; i < 9; i++) {
+		coefficients[i] = vec4(0);
+	}
+	for (int corner = 0; corner < 8; corner++) {
+		bvec3 upper = bvec3((corner & 1) != 0, (corner & 2) != 0, (corner & 4) != 0);
+		ivec3 probe = ivec3(mix(base, next, upper));
+		vec3 weights = mix(1 - t, t, upper);
+		float weight = weights.x * weights.y * weights.z;
+		int index = (probe.x + counts.x * (probe.y + counts.y * probe.z)) * 9;
+		for (int i = 0; i < 9; i++) {
+			coefficients[i] += probe_sh[index + i] * weight;
+		}
+	}
+	for (int i = 0; i < 9; i++) {
+		coefficients[i] *= probe_origin.w;
+	}
+	return eval_sh(coefficients, n);
+}
+
+vec3 shade(Light light, vec3 normal_ws) {
+	return light.color_cookie.rgb * max(0, dot(normal_ws, -light.direction_radius.xyz));
+}
+
+void main() {
+	vec4 g_normal_ao = subpassLoad(normal);
+	vec3 g_normal_cs = oct_decode(g_normal_ao.xy);
+	float g_ambient_occlusion = g_normal_ao.z;
+	vec3 g_normal_ws = quat_mul(camera_rot, g_normal_cs);
+
+	vec3 g_albedo = subpassLoad(albedo).rgb;
+	g_albedo *= g_albedo;
+
+	vec3 light = vec3(0);
+	for (uint i = 0; i < light_count.x; i++) {
+		light += shade(lights[i], g_normal_ws);
+	}
+
+	// meshes using the probes mark the normal's alpha
+	if (probe_counts.w != 0 && g_normal_ao.w > 0.5) {
+		float g_depth = subpassLoad(depth).x;
+		vec3 g_position_ds = vec3(gl_FragCoord.xy * resolution.zw, 2.0 * g_depth) - 1.0;
+		vec2 g_centered_ds = g_position_ds.xy - camera_offset.xy;
+		vec3 g_position_cs =
+			vec3(g_centered_ds / camera_proj.xy, -1.0) * camera_proj.w / (g_position_ds.z + camera_proj.z);
+		vec3 g_position_ws = quat_mul(camera_rot, g_position_cs) + camera_pos;
+		light += probe_ambient(g_position_ws, g_normal_ws);
+	} else {
+		light += eval_sh(ambient_sh, g_normal_ws);
+	}
+
+	// baked contact shadows
+	light *= g_ambient_occlusion;
+
+	out_lit = vec4(g_albedo * light, 1);
+}
@@ -0,0 +1,63 @@
+#version 450
+layout(location = 0) out vec4 out_color;
+// where each pixel's surface was last frame, in uv relative to where it is now without the jitter
+layout(location = 1) out vec2 out_motion;
+
+// last frame's output, black after the history is reset
+layout(set = 0, binding = 0) uniform sampler2D prev_out;
+layout(set = 0, binding = 1, input_attachment_index = 0) uniform subpassInput lit;
+layout(set = 0, binding = 2, input_attachment_index = 1) uniform subpassInput depth;
+layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 1, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+// last frame's camera, without its jitter
+layout(push_constant) uniform Reprojection {
+	vec4 prev_camera_pos;
+	vec4 prev_camera_rot;
+	vec4 prev_camera_proj;
+	vec4 prev_camera_offset;
+	vec2 resolution;
+	// how far this frame's view is moved, in normalized device coordinates
+	vec2 jitter;
+	// depth below overlay_depth was written by overlay meshes, which move with the camera
+	float overlay_depth;
+};
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+// only the camera's motion, since meshes don't keep their last transform
+vec2 motion() {
+	float g_depth = subpassLoad(depth).x;
+	if (g_depth < overlay_depth) {
+		return vec2(0);
+	}
+
+	vec3 g_position_ds = vec3(gl_FragCoord.xy / resolution * 2 - 1, 2.0 * g_depth - 1.0);
+	vec2 g_centered_ds = g_position_ds.xy - camera_offset.xy;
+	vec3 g_position_cs = vec3(g_centered_ds / camera_proj.xy, -1.0) * camera_proj.w / (g_position_ds.z + camera_proj.z);
+	vec3 g_position_ws = quat_mul(camera_rot, g_position_cs) + camera_pos;
+
+	vec3 prev_position_cs = quat_mul(quat_inv(prev_camera_rot), g_position_ws - prev_camera_pos.xyz);
+	vec2 prev_position_ds = prev_position_cs.xy * prev_camera_proj.xy / -prev_position_cs.z + prev_camera_offset.xy;
+	return (prev_position_ds - (g_position_ds.xy - jitter)) * 0.5;
+}
+
+void main() {
+	float exposure = 1.618;
+	vec3 out_hdr = subpassLoad(lit).rgb * exposure;
+	vec3 out_tonemapped = out_hdr / (1 + out_hdr);
+	out_color = vec4(out_tonemapped, 1);
+	out_motion = motion();
+}
@@ -0,0 +1,13 @@
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 out_lit;
+
+layout(set = 1, binding = 0) uniform sampler2D tex;
+
+void main() {
+	vec4 texel = texture(tex, tex_coords);
+	// the texture is coverage and tint, the color is linear light that may exceed 1
+	out_lit = vec4(color.rgb * texel.rgb * texel.a, color.a * texel.a);
+}
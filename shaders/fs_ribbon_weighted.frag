@@ -0,0 +1,22 @@
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+
+// summed
+layout(location = 0) out vec4 out_accum;
+// multiplied into the revealage by one minus this
+layout(location = 1) out float out_coverage;
+
+layout(set = 1, binding = 0) uniform sampler2D tex;
+
+void main() {
+	vec4 texel = texture(tex, tex_coords);
+	vec4 premultiplied = vec4(color.rgb * texel.rgb * texel.a, color.a * texel.a);
+
+	// coverage times a falloff with the distance, which 1 / w is, one of the weights from McGuire and Bavoil's
+	// Weighted Blended Order-Independent Transparency, so nearer and more opaque surfaces win where they overlap
+	float depth = 1.0 / gl_FragCoord.w;
+	float weight = clamp(10.0 / (1e-5 + pow(depth / 5.0, 2.0) + pow(depth / 200.0, 6.0)), 1e-2, 3e3);
+	out_accum = premultiplied * premultiplied.a * weight;
+	out_coverage = premultiplied.a;
+}
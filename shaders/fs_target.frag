@@ -0,0 +1,100 @@
+#version 450
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D mask;
+
+layout(push_constant) uniform Post {
+	// of the viewport, which covers only part of the target in side by side stereo
+	vec2 target_offset;
+	vec2 target_size;
+	int outline_width;
+	// 0 for nearest, 1 for linear, 2 for linear with sharpening
+	int upscale_filter;
+	float sharpness;
+	// 0 for none, 1 for ordered, 2 for interleaved gradient noise
+	int dither;
+};
+
+// from 0 to 1, for the pixel at `pixel`
+float dither_threshold(ivec2 pixel) {
+	if (dither == 1) {
+		// the bits of x ^ y and y, interleaved and reversed, give the 8x8 bayer matrix
+		uint x = uint(pixel.x) & 7, y = uint(pixel.y) & 7, xy = x ^ y;
+		uint bayer =
+			((xy & 1) << 5) | ((y & 1) << 4) | ((xy & 2) << 2) | ((y & 2) << 1) | ((xy & 4) >> 1) | ((y & 4) >> 2);
+		return (float(bayer) + 0.5) / 64;
+	}
+	return fract(52.9829189 * fract(dot(vec2(pixel), vec2(0.06711056, 0.00583715))));
+}
+
+// dithers in sRGB, where the target's 8-bit steps are even
+vec3 apply_dither(vec3 linear, ivec2 pixel) {
+	vec3 srgb = pow(max(linear, 0), vec3(1 / 2.2));
+	srgb += (dither_threshold(pixel) - 0.5) / 255;
+	return pow(max(srgb, 0), vec3(2.2));
+}
+
+vec3 sharpen(vec2 uv, vec2 texel) {
+	// contrast adaptive sharpening, like FSR 1's RCAS
+	vec3 c = texture(color, uv).rgb;
+	vec3 n = texture(color, uv - vec2(0, texel.y)).rgb;
+	vec3 s = texture(color, uv + vec2(0, texel.y)).rgb;
+	vec3 e = texture(color, uv + vec2(texel.x, 0)).rgb;
+	vec3 w = texture(color, uv - vec2(texel.x, 0)).rgb;
+
+	vec3 min_rgb = min(c, min(min(n, s), min(e, w)));
+	vec3 max_rgb = max(c, max(max(n, s), max(e, w)));
+	vec3 amp = sqrt(clamp(min(min_rgb, 1 - max_rgb) / max(max_rgb, 0.0001), 0, 1));
+	vec3 weight = amp * -1 / mix(8, 5, sharpness);
+
+	return clamp((c + (n + s + e + w) * weight) / (1 + 4 * weight), 0, 1);
+}
+
+// the strongest highlight within the outline width of a pixel outside of highlighted meshes
+vec4 outline_edge(ivec2 mask_coord) {
+	ivec2 size = textureSize(mask, 0);
+	vec4 edge = vec4(0);
+	for (int y = -outline_width; y <= outline_width; y++) {
+		for (int x = -outline_width; x <= outline_width; x++) {
+			ivec2 sample_coord = mask_coord + ivec2(x, y);
+			if (x * x + y * y > outline_width * outline_width || any(lessThan(sample_coord, ivec2(0))) ||
+				any(greaterThanEqual(sample_coord, size))) {
+				continue;
+			}
+
+			vec4 sample_mask = texelFetch(mask, sample_coord, 0);
+			if (sample_mask.a > edge.a) {
+				edge = sample_mask;
+			}
+		}
+	}
+
+	return edge;
+}
+
+void main() {
+	vec2 source_size = vec2(textureSize(color, 0));
+	vec2 uv = (gl_FragCoord.xy - target_offset) / target_size;
+	ivec2 coord = ivec2(uv * source_size);
+	// color is at the target's resolution when it's temporally upsampled, but the mask never is
+	ivec2 mask_coord = ivec2(uv * vec2(textureSize(mask, 0)));
+
+	if (upscale_filter == 0) {
+		out_color = texelFetch(color, coord, 0);
+	} else if (upscale_filter == 1) {
+		out_color = texture(color, uv);
+	} else {
+		out_color = vec4(sharpen(uv, 1 / source_size), 1);
+	}
+
+	// draw highlight outlines just outside of highlighted meshes, measured in rendered pixels
+	if (outline_width > 0 && texelFetch(mask, mask_coord, 0).a == 0) {
+		vec4 edge = outline_edge(mask_coord);
+		out_color.rgb = mix(out_color.rgb, edge.rgb, edge.a);
+	}
+
+	if (dither != 0) {
+		out_color.rgb = apply_dither(out_color.rgb, ivec2(gl_FragCoord.xy));
+	}
+}
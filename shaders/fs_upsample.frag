@@ -0,0 +1,48 @@
+#version 450
+layout(location = 0) out vec4 out_color;
+
+// this frame at the render resolution and its motion, and the history at the target's
+layout(set = 0, binding = 0) uniform sampler2D current;
+layout(set = 0, binding = 1) uniform sampler2D motion;
+layout(set = 0, binding = 2) uniform sampler2D prev_out;
+
+layout(push_constant) uniform Upsample {
+	vec2 target_size;
+	// how far this frame's view is moved, in uv
+	vec2 jitter;
+	// share of the history kept, or 0 right after it's reset
+	float history_weight;
+};
+
+void main() {
+	vec2 uv = gl_FragCoord.xy / target_size;
+	vec2 source_size = vec2(textureSize(current, 0));
+	// the jittered frame shows what's at uv at uv + jitter
+	vec2 source_uv = uv + jitter;
+	ivec2 coord = clamp(ivec2(source_uv * source_size), ivec2(0), ivec2(source_size) - 1);
+
+	vec2 prev_uv = uv + texelFetch(motion, coord, 0).xy;
+	bool prev_on_screen = all(greaterThanEqual(prev_uv, vec2(0))) && all(lessThanEqual(prev_uv, vec2(1)));
+	if (history_weight <= 0 || !prev_on_screen) {
+		out_color = vec4(texture(current, source_uv).rgb, 1);
+		return;
+	}
+
+	// history is clamped to the colors around it this frame, so it can't drag stale colors along
+	vec3 nearest = texelFetch(current, coord, 0).rgb;
+	vec3 lo = nearest;
+	vec3 hi = nearest;
+	for (int y = -1; y <= 1; y++) {
+		for (int x = -1; x <= 1; x++) {
+			vec3 neighbor = texelFetch(current, clamp(coord + ivec2(x, y), ivec2(0), ivec2(source_size) - 1), 0).rgb;
+			lo = min(lo, neighbor);
+			hi = max(hi, neighbor);
+		}
+	}
+	vec3 prev = clamp(texture(prev_out, prev_uv).rgb, lo, hi);
+
+	// samples landing near this pixel count for more than ones a pixel away
+	vec2 offset = fract(source_uv * source_size) - 0.5;
+	float current_weight = (1 - history_weight) * exp(-2.9 * dot(offset, offset));
+	out_color = vec4(mix(prev, nearest, current_weight), 1);
+}
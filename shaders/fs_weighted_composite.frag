@@ -0,0 +1,17 @@
+#version 450
+layout(location = 0) out vec4 out_lit;
+
+layout(set = 0, binding = 0, input_attachment_index = 0) uniform subpassInput accum;
+layout(set = 0, binding = 1, input_attachment_index = 1) uniform subpassInput revealage;
+
+void main() {
+	float revealed = subpassLoad(revealage).r;
+	if (revealed >= 1.0) {
+		discard;
+	}
+
+	// the weighted average color covers what isn't revealed, and the scene shows through the rest
+	vec4 accum = subpassLoad(accum);
+	vec3 average = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+	out_lit = vec4(average * (1.0 - revealed), revealed);
+}
@@ -0,0 +1,21 @@
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 0) out vec2 tex_coords;
+
+layout(set = 0, binding = 0) uniform Target {
+	uvec2 size;
+	// nonzero to round positions to whole pixels
+	uint snap;
+} target;
+
+layout(set = 1, binding = 0) uniform SpriteDynamic {
+	vec2 pos;
+} sprite_dynamic;
+
+layout(set = 2, binding = 0) uniform sampler2D tex;
+
+void main() {
+	vec2 pos = target.snap != 0 ? round(sprite_dynamic.pos) : sprite_dynamic.pos;
+	tex_coords = position;
+	gl_Position = vec4(2 * (pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
+}
@@ -0,0 +1,19 @@
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 glyph_pos;
+layout(location = 0) out vec2 tex_coords;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; uint snap; } target;
+layout(set = 1, binding = 0) uniform sampler2D tex;
+
+// glyph positions are relative to the text's origin, so one layout can be drawn anywhere
+layout(push_constant) uniform Text { vec2 origin; };
+
+void main() {
+	vec2 pos = origin + glyph_pos;
+	if (target.snap != 0) {
+		pos = round(pos);
+	}
+	tex_coords = position;
+	gl_Position = vec4(2 * (pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coords;
+layout(location = 2) in vec4 color;
+layout(location = 0) out vec2 out_tex_coords;
+layout(location = 1) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform Target { uvec2 size; } target;
+
+void main() {
+	out_tex_coords = tex_coords;
+	out_color = color;
+	gl_Position = vec4(2 * position / target.size - 1, 0.0, 1.0);
+}
@@ -0,0 +1,121 @@
+#version 450
+layout(location = 0) in vec3 position_os;
+layout(location = 1) in vec3 normal_os;
+layout(location = 2) in vec2 texcoord;
+layout(location = 3) in float ambient_occlusion;
+
+layout(location = 0) out vec3 out_position_cs;
+layout(location = 1) out vec3 out_normal_cs;
+layout(location = 2) out vec2 out_texcoord;
+layout(location = 3) out vec3 out_base_albedo;
+layout(location = 4) out float out_ambient_occlusion;
+layout(location = 5) flat out float out_height_scale;
+layout(location = 6) flat out uvec2 out_parallax_samples;
+layout(location = 7) flat out float out_light_probes;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+struct Instance {
+	vec3 mesh_pos;
+	float light_probes;
+	vec3 mesh_scale;
+	float vertex_animation_time;
+	vec4 mesh_rot;
+	// the world space bounding sphere the cull shader tests
+	vec4 bounds;
+};
+layout(set = 1, binding = 0) readonly buffer Instances { Instance instances[]; };
+// what the cull shader found visible, in the order the draws take them
+layout(set = 1, binding = 1) readonly buffer Visible { uint visible[]; };
+
+vec3 mesh_pos;
+float light_probes;
+vec3 mesh_scale;
+float vertex_animation_time;
+vec4 mesh_rot;
+
+layout(set = 2, binding = 0) uniform Material {
+	uint light_penetration;
+	uint subsurface_scattering;
+	uint emissive_brightness;
+	vec3 base_albedo;
+	float height_scale;
+	uvec2 parallax_samples;
+	vec2 uv_offset;
+	// columns of a mat2, which std140 would pad to two vec4s
+	vec4 uv_matrix;
+	// bit 0 plays the vertex animation, bit 1 loops it
+	uint material_flags;
+	uint vertex_animation_frame_count;
+	vec3 vertex_animation_min;
+	float vertex_animation_fps;
+	vec3 vertex_animation_max;
+};
+layout(set = 2, binding = 1) uniform sampler2D tex1;
+layout(set = 2, binding = 2) uniform sampler2D tex2;
+layout(set = 2, binding = 3) uniform sampler2D tex3;
+layout(set = 2, binding = 4) uniform sampler2D tex_vertex_animation;
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy - offset * pos.z, pos.z * proj.z + proj.w, -pos.z);
+}
+
+// every frame takes the same number of rows, and wraps the vertices across them
+vec3 animated_position(int frame) {
+	ivec2 size = textureSize(tex_vertex_animation, 0);
+	int rows = size.y / int(vertex_animation_frame_count);
+	ivec2 texel = ivec2(gl_VertexIndex % size.x, frame * rows + gl_VertexIndex / size.x);
+	vec3 norm = texelFetch(tex_vertex_animation, texel, 0).rgb;
+	return mix(vertex_animation_min, vertex_animation_max, norm);
+}
+
+vec3 object_position() {
+	if ((material_flags & 1) == 0) {
+		return position_os;
+	}
+
+	int frame_count = int(vertex_animation_frame_count);
+	float frame = vertex_animation_time * vertex_animation_fps;
+	bool looping = (material_flags & 2) != 0;
+	frame = looping ? mod(frame, float(frame_count)) : clamp(frame, 0.0, float(frame_count - 1));
+	int frame0 = int(frame);
+	int frame1 = looping ? (frame0 + 1) % frame_count : min(frame0 + 1, frame_count - 1);
+	return mix(animated_position(frame0), animated_position(frame1), fract(frame));
+}
+
+void main() {
+	Instance instance = instances[visible[gl_InstanceIndex]];
+	mesh_pos = instance.mesh_pos;
+	light_probes = instance.light_probes;
+	mesh_scale = instance.mesh_scale;
+	vertex_animation_time = instance.vertex_animation_time;
+	mesh_rot = instance.mesh_rot;
+
+	// normals scale inversely, so they stay perpendicular to non-uniformly scaled surfaces
+	vec3 normal_ws = quat_mul(mesh_rot, normalize(normal_os / mesh_scale));
+	out_normal_cs = quat_mul(quat_inv(camera_rot), normal_ws);
+	vec3 position_ws = quat_mul(mesh_rot, object_position() * mesh_scale) + mesh_pos;
+	out_position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
+	out_base_albedo = base_albedo;
+	out_texcoord = mat2(uv_matrix.xy, uv_matrix.zw) * texcoord + uv_offset;
+	out_ambient_occlusion = ambient_occlusion;
+	out_height_scale = height_scale;
+	out_parallax_samples = parallax_samples;
+	out_light_probes = light_probes;
+	gl_Position = perspective(camera_proj, camera_offset.xy, out_position_cs);
+}
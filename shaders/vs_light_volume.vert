@@ -0,0 +1,56 @@
+#version 450
+layout(location = 0) in vec3 position;
+
+layout(location = 0) flat out uint out_light_index;
+
+layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 1, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+struct Light {
+	// w is the type: 0 for directional, 1 for point, 2 for spot
+	vec4 position_type;
+	vec4 direction_radius;
+	// w is the cookie index, or -1 for none
+	vec4 color_cookie;
+	// cosines of the inner and outer cone angles, and 1 in z if the light is drawn with a cone
+	vec4 cone;
+	mat4 cookie_matrix;
+};
+layout(set = 2, binding = 0) uniform LocalLights { Light lights[64]; };
+
+layout(push_constant) uniform LightVolume { uint light_index; };
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy - offset * pos.z, pos.z * proj.z + proj.w, -pos.z);
+}
+
+void main() {
+	Light light = lights[light_index];
+	vec3 offset = position * light.direction_radius.w;
+
+	// the cone opens along z, so it's turned to the light's direction and widened to its outer angle
+	if (light.cone.z > 0) {
+		vec3 dir = light.direction_radius.xyz;
+		vec3 side = normalize(cross(dir, abs(dir.y) < 0.9 ? vec3(0, 1, 0) : vec3(1, 0, 0)));
+		float spread = sqrt(1 - light.cone.y * light.cone.y) / light.cone.y;
+		offset = mat3(side * spread, cross(dir, side) * spread, dir) * offset;
+	}
+
+	vec3 position_cs = quat_mul(quat_inv(camera_rot), light.position_type.xyz + offset - camera_pos);
+	out_light_index = light_index;
+	gl_Position = perspective(camera_proj, camera_offset.xy, position_cs);
+}
@@ -0,0 +1,42 @@
+#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 tangent;
+layout(location = 2) in float offset;
+layout(location = 3) in vec2 tex_coords;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec2 out_tex_coords;
+layout(location = 1) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
+layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
+layout(set = 0, binding = 2) uniform CameraProj {
+	vec4 camera_proj;
+	// xy is where the view's center lands in normalized device coordinates, off center for tiled captures
+	vec4 camera_offset;
+};
+
+// quaternions are x y z w, as uploaded by `math::quat_to_xyzw`
+vec4 quat_inv(vec4 quat) {
+	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
+}
+
+vec3 quat_mul(vec4 quat, vec3 vec) {
+	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+}
+
+vec4 perspective(vec4 proj, vec2 offset, vec3 pos) {
+	return vec4(pos.xy * proj.xy - offset * pos.z, pos.z * proj.z + proj.w, -pos.z);
+}
+
+void main() {
+	// widen across the trail and the view direction, so the ribbon always faces the camera
+	vec3 side = cross(tangent, camera_pos - position);
+	float side_len = length(side);
+	side = side_len > 0 ? side / side_len : vec3(0);
+	vec3 position_cs = quat_mul(quat_inv(camera_rot), position + side * offset - camera_pos);
+
+	out_tex_coords = tex_coords;
+	out_color = color;
+	gl_Position = perspective(camera_proj, camera_offset.xy, position_cs);
+}
@@ -0,0 +1,6 @@
+#version 450
+layout(location = 0) in vec2 position;
+
+void main() {
+	gl_Position = vec4(position * 2 - 1, 0.0, 1.0);
+}
@@ -0,0 +1,7 @@
+mod easing;
+mod timeline;
+mod tween;
+
+pub use self::easing::Easing;
+pub use self::timeline::Timeline;
+pub use self::tween::{ Lerp, Tween };
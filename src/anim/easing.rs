@@ -0,0 +1,66 @@
+use std::f32::consts::PI;
+
+/// Shapes progress through a tween. Each maps 0 to 0 and 1 to 1, though `BackOut` and `ElasticOut` overshoot between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+	Linear,
+	QuadIn,
+	QuadOut,
+	QuadInOut,
+	CubicIn,
+	CubicOut,
+	CubicInOut,
+	SineInOut,
+	/// Overshoots slightly, then settles.
+	BackOut,
+	/// Springs past the end a few times.
+	ElasticOut,
+	/// Bounces against the end like a dropped ball.
+	BounceOut,
+}
+impl Easing {
+	/// `t` is clamped to 0..1.
+	pub fn apply(self, t: f32) -> f32 {
+		let t = t.max(0.0).min(1.0);
+		match self {
+			Easing::Linear => t,
+			Easing::QuadIn => t * t,
+			Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+			Easing::QuadInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - (2.0 - 2.0 * t).powi(2) * 0.5 },
+			Easing::CubicIn => t * t * t,
+			Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+			Easing::CubicInOut => if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (2.0 - 2.0 * t).powi(3) * 0.5 },
+			Easing::SineInOut => (1.0 - (t * PI).cos()) * 0.5,
+			Easing::BackOut => {
+				let c = 1.70158;
+				1.0 + (c + 1.0) * (t - 1.0).powi(3) + c * (t - 1.0).powi(2)
+			},
+			Easing::ElasticOut =>
+				if t <= 0.0 || t >= 1.0 {
+					t
+				} else {
+					2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+				},
+			Easing::BounceOut => {
+				let (n, d) = (7.5625, 2.75);
+				if t < 1.0 / d {
+					n * t * t
+				} else if t < 2.0 / d {
+					let t = t - 1.5 / d;
+					n * t * t + 0.75
+				} else if t < 2.5 / d {
+					let t = t - 2.25 / d;
+					n * t * t + 0.9375
+				} else {
+					let t = t - 2.625 / d;
+					n * t * t + 0.984375
+				}
+			},
+		}
+	}
+}
+impl Default for Easing {
+	fn default() -> Self {
+		Easing::Linear
+	}
+}
@@ -0,0 +1,168 @@
+use crate::anim::{ Easing, Lerp, Tween };
+use crate::clock::duration_secs;
+use std::time::Duration;
+
+/// Sequence of tweens and callbacks, driven by `update`. Tweens set properties through closures that get `&mut C`,
+/// so one timeline can animate anything reachable from the context it's updated with, e.g. a struct holding the
+/// camera, the sprite batch and the mesh batch.
+///
+/// Steps are added one after another with `tween`, `wait` and `call`, and `with` starts a tween alongside the
+/// previous step instead of after it.
+pub struct Timeline<C> {
+	steps: Vec<Step<C>>,
+	prev_start: f32,
+	end: f32,
+	time: f32,
+	looping: bool,
+}
+impl<C> Timeline<C> {
+	pub fn new() -> Self {
+		Self { steps: vec![], prev_start: 0.0, end: 0.0, time: 0.0, looping: false }
+	}
+
+	/// Tweens from `from` to `to` after everything added so far, passing each value to `set`.
+	pub fn tween<T: Lerp + 'static>(
+		&mut self,
+		from: T,
+		to: T,
+		duration: Duration,
+		easing: Easing,
+		set: impl FnMut(&mut C, T) + 'static,
+	) -> &mut Self {
+		let start = self.end;
+		self.add_tween(start, Tween::new(from, to, duration, easing), set)
+	}
+
+	/// Like `tween`, but starts at the same time as the previous step.
+	pub fn with<T: Lerp + 'static>(
+		&mut self,
+		from: T,
+		to: T,
+		duration: Duration,
+		easing: Easing,
+		set: impl FnMut(&mut C, T) + 'static,
+	) -> &mut Self {
+		let start = self.prev_start;
+		self.add_tween(start, Tween::new(from, to, duration, easing), set)
+	}
+
+	/// Delays the next step.
+	pub fn wait(&mut self, duration: Duration) -> &mut Self {
+		self.prev_start = self.end;
+		self.end += duration_secs(duration);
+		self
+	}
+
+	/// Calls `callback` once everything added so far has finished.
+	pub fn call(&mut self, callback: impl FnMut(&mut C) + 'static) -> &mut Self {
+		self.prev_start = self.end;
+		self.steps.push(Step { start: self.end, duration: 0.0, action: Action::Call(Box::new(callback)), done: false });
+		self
+	}
+
+	/// Starts over from the beginning once the end is reached. Defaults to false.
+	pub fn set_looping(&mut self, looping: bool) {
+		self.looping = looping;
+	}
+
+	/// Total length, in seconds.
+	pub fn duration(&self) -> f32 {
+		self.end
+	}
+
+	/// Seconds since the start, or the current loop.
+	pub fn time(&self) -> f32 {
+		self.time
+	}
+
+	pub fn is_finished(&self) -> bool {
+		!self.looping && self.time >= self.end
+	}
+
+	/// Rewinds to the start without applying anything, so callbacks fire again.
+	pub fn reset(&mut self) {
+		self.time = 0.0;
+		for step in &mut self.steps {
+			step.done = false;
+		}
+	}
+
+	/// Advances by `dt`, applying every tween that's running and firing callbacks that were reached. Tweens that
+	/// finished during this update are set to their final value, so nothing stops short on a long frame.
+	pub fn update(&mut self, dt: Duration, context: &mut C) {
+		let mut time = self.time + duration_secs(dt);
+
+		while self.looping && self.end > 0.0 && time >= self.end {
+			let end = self.end;
+			self.advance(end, context);
+			self.reset();
+			time -= end;
+		}
+
+		self.advance(time, context);
+	}
+
+	fn advance(&mut self, time: f32, context: &mut C) {
+		self.time = time;
+		for step in &mut self.steps {
+			if step.done || time < step.start {
+				continue;
+			}
+
+			match step.action {
+				Action::Tween(ref mut apply) => {
+					let progress = if step.duration <= 0.0 { 1.0 } else { ((time - step.start) / step.duration).min(1.0) };
+					apply.apply(context, progress);
+				},
+				Action::Call(ref mut callback) => callback(context),
+			}
+			step.done = time >= step.start + step.duration;
+		}
+	}
+
+	fn add_tween<T: Lerp + 'static>(
+		&mut self,
+		start: f32,
+		tween: Tween<T>,
+		set: impl FnMut(&mut C, T) + 'static,
+	) -> &mut Self {
+		let duration = duration_secs(tween.duration);
+		self.steps.push(Step {
+			start: start,
+			duration: duration,
+			action: Action::Tween(Box::new(TweenStep { tween: tween, set: set })),
+			done: false,
+		});
+		self.prev_start = start;
+		self.end = self.end.max(start + duration);
+		self
+	}
+}
+
+struct Step<C> {
+	start: f32,
+	duration: f32,
+	action: Action<C>,
+	done: bool,
+}
+
+enum Action<C> {
+	Tween(Box<ApplyTween<C>>),
+	Call(Box<FnMut(&mut C)>),
+}
+
+/// Erases the tweened type, so steps of different types can share a timeline.
+trait ApplyTween<C> {
+	/// Applies the value at a progress from 0 to 1.
+	fn apply(&mut self, context: &mut C, progress: f32);
+}
+
+struct TweenStep<T, F> {
+	tween: Tween<T>,
+	set: F,
+}
+impl<C, T: Lerp, F: FnMut(&mut C, T)> ApplyTween<C> for TweenStep<T, F> {
+	fn apply(&mut self, context: &mut C, progress: f32) {
+		(self.set)(context, self.tween.value_at(progress));
+	}
+}
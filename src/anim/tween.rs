@@ -0,0 +1,88 @@
+use crate::anim::Easing;
+use crate::clock::duration_secs;
+use crate::color::Color;
+use cgmath::{ prelude::*, Quaternion, Vector2, Vector3, Vector4 };
+use std::time::Duration;
+
+/// Values a tween can interpolate.
+pub trait Lerp: Copy {
+	fn lerp(self, other: Self, t: f32) -> Self;
+}
+impl Lerp for f32 {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		self + (other - self) * t
+	}
+}
+impl Lerp for [f32; 2] {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		[self[0].lerp(other[0], t), self[1].lerp(other[1], t)]
+	}
+}
+impl Lerp for [f32; 3] {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		[self[0].lerp(other[0], t), self[1].lerp(other[1], t), self[2].lerp(other[2], t)]
+	}
+}
+impl Lerp for [f32; 4] {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		[self[0].lerp(other[0], t), self[1].lerp(other[1], t), self[2].lerp(other[2], t), self[3].lerp(other[3], t)]
+	}
+}
+impl Lerp for Vector2<f32> {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		<Self as InnerSpace>::lerp(self, other, t)
+	}
+}
+impl Lerp for Vector3<f32> {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		<Self as InnerSpace>::lerp(self, other, t)
+	}
+}
+impl Lerp for Vector4<f32> {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		<Self as InnerSpace>::lerp(self, other, t)
+	}
+}
+impl Lerp for Color {
+	/// Blends in linear light, which keeps mixes of saturated colors from going dark in the middle.
+	fn lerp(self, other: Self, t: f32) -> Self {
+		let [r, g, b, a] = self.to_linear().lerp(other.to_linear(), t);
+		Color::from_linear(r, g, b, a)
+	}
+}
+impl Lerp for Quaternion<f32> {
+	/// Takes the short way around. Overshooting easings extrapolate, which normalized lerp handles gracefully.
+	fn lerp(self, other: Self, t: f32) -> Self {
+		let other = if self.dot(other) < 0.0 { -other } else { other };
+		(self * (1.0 - t) + other * t).normalize()
+	}
+}
+
+/// Interpolation from one value to another over a duration.
+#[derive(Clone, Copy, Debug)]
+pub struct Tween<T> {
+	pub from: T,
+	pub to: T,
+	pub duration: Duration,
+	pub easing: Easing,
+}
+impl<T: Lerp> Tween<T> {
+	pub fn new(from: T, to: T, duration: Duration, easing: Easing) -> Self {
+		Self { from: from, to: to, duration: duration, easing: easing }
+	}
+
+	/// The value `elapsed` after the start. It holds at `to` once the tween is over.
+	pub fn value(&self, elapsed: Duration) -> T {
+		self.value_at(self.progress(elapsed))
+	}
+
+	/// `progress` is 0 at the start and 1 at the end, before easing.
+	pub fn value_at(&self, progress: f32) -> T {
+		self.from.lerp(self.to, self.easing.apply(progress))
+	}
+
+	pub fn progress(&self, elapsed: Duration) -> f32 {
+		let duration = duration_secs(self.duration);
+		if duration <= 0.0 { 1.0 } else { (duration_secs(elapsed) / duration).min(1.0) }
+	}
+}
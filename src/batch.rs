@@ -1,2 +1,9 @@
+#[cfg(not(feature = "builtin-shaders"))]
+#[macro_use]
+mod spirv_shader;
+mod hook;
+#[cfg(feature = "mesh")]
 pub mod mesh;
 pub mod sprite;
+
+pub use self::hook::{ HookCommands, HookError, RenderHook };
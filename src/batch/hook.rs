@@ -0,0 +1,163 @@
+use log::{ warn, log };
+use std::sync::Arc;
+use vulkano::{
+	OomError,
+	buffer::{ BufferAccess, TypedBufferAccess },
+	command_buffer::{
+		AutoCommandBuffer,
+		AutoCommandBufferBuilder,
+		BuildError,
+		DrawError,
+		DrawIndexedError,
+		DynamicState,
+	},
+	descriptor::{ DescriptorSet, descriptor_set::DescriptorSetsCollection },
+	device::Device,
+	framebuffer::{ RenderPassAbstract, Subpass },
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::{ GraphicsPipelineAbstract, input_assembly::Index, vertex::VertexSource, viewport::Viewport },
+};
+
+/// Called while a batch records its render pass, to draw into it without a pass of its own. A batch skips a hook that
+/// fails to draw for that frame, and returns the error if it failed to allocate.
+pub type RenderHook = Box<FnMut(&mut HookCommands) -> Result<(), HookError>>;
+
+/// Records draws for one subpass of a batch's render pass. Only draws can be recorded, so a hook can't end the pass,
+/// move to another subpass or copy between buffers in the middle of it.
+pub struct HookCommands {
+	cmd: Option<AutoCommandBufferBuilder>,
+	subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	dynamic_state: DynamicState,
+}
+impl HookCommands {
+	pub(crate) fn new(
+		device: Arc<Device>,
+		queue_family: QueueFamily,
+		subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+		desc: Arc<DescriptorSet + Send + Sync + 'static>,
+		dimensions: [f32; 2],
+	) -> Result<Self, OomError> {
+		let cmd = AutoCommandBufferBuilder::secondary_graphics_one_time_submit(device, queue_family, subpass.clone())?;
+
+		Ok(Self {
+			cmd: Some(cmd),
+			subpass: subpass,
+			desc: desc,
+			dynamic_state:
+				DynamicState {
+					line_width: None,
+					viewports:
+						Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+					scissors: None,
+				},
+		})
+	}
+
+	/// The subpass being recorded. Pipelines passed to `draw` have to be built for it.
+	pub fn subpass(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
+		&self.subpass
+	}
+
+	/// The set the batch binds as set 0 of its own pipelines in this subpass: the camera in a `MeshBatch` and the
+	/// target size in a `SpriteBatch`. Pipelines with the same set 0 layout can bind it too.
+	pub fn desc(&self) -> &Arc<DescriptorSet + Send + Sync + 'static> {
+		&self.desc
+	}
+
+	/// Covers the whole render area.
+	pub fn dynamic_state(&self) -> &DynamicState {
+		&self.dynamic_state
+	}
+
+	/// Fails if `pipeline` wasn't built for `subpass`, or `sets` and `constants` don't match its layout. A failed draw
+	/// discards everything recorded so far, and the draws after it fail with `HookError::Discarded`.
+	pub fn draw<V, Gp, S, Pc>(&mut self, pipeline: Gp, vertices: V, sets: S, constants: Pc) -> Result<(), HookError>
+	where
+		Gp: GraphicsPipelineAbstract + VertexSource<V> + Send + Sync + 'static + Clone,
+		S: DescriptorSetsCollection,
+	{
+		let cmd = self.cmd.take().ok_or(HookError::Discarded)?;
+		self.cmd = Some(cmd.draw(pipeline, &self.dynamic_state, vertices, sets, constants)?);
+		Ok(())
+	}
+
+	/// Fails like `draw`.
+	pub fn draw_indexed<V, Gp, S, Pc, Ib, I>(
+		&mut self,
+		pipeline: Gp,
+		vertices: V,
+		indices: Ib,
+		sets: S,
+		constants: Pc,
+	) -> Result<(), HookError>
+	where
+		Gp: GraphicsPipelineAbstract + VertexSource<V> + Send + Sync + 'static + Clone,
+		S: DescriptorSetsCollection,
+		Ib: BufferAccess + TypedBufferAccess<Content = [I]> + Send + Sync + 'static,
+		I: Index + 'static,
+	{
+		let cmd = self.cmd.take().ok_or(HookError::Discarded)?;
+		self.cmd = Some(cmd.draw_indexed(pipeline, &self.dynamic_state, vertices, indices, sets, constants)?);
+		Ok(())
+	}
+
+	pub(crate) fn build(mut self) -> Result<AutoCommandBuffer, HookError> {
+		Ok(
+			self.cmd.take().ok_or(HookError::Discarded)?
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+}
+
+#[derive(Debug)]
+pub enum HookError {
+	Draw(DrawError),
+	DrawIndexed(DrawIndexedError),
+	/// An earlier draw failed, discarding the hook's commands.
+	Discarded,
+	DeviceMemoryAlloc(DeviceMemoryAllocError),
+}
+impl From<DrawError> for HookError {
+	fn from(val: DrawError) -> Self {
+		HookError::Draw(val)
+	}
+}
+impl From<DrawIndexedError> for HookError {
+	fn from(val: DrawIndexedError) -> Self {
+		HookError::DrawIndexed(val)
+	}
+}
+impl From<DeviceMemoryAllocError> for HookError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		HookError::DeviceMemoryAlloc(val)
+	}
+}
+impl From<OomError> for HookError {
+	fn from(val: OomError) -> Self {
+		HookError::DeviceMemoryAlloc(val.into())
+	}
+}
+
+/// Runs `hook` and builds what it recorded. `None` if it failed to draw, which is logged rather than returned, so one
+/// broken hook doesn't stop the batch from drawing.
+pub(crate) fn record(
+	hook: &mut RenderHook,
+	device: Arc<Device>,
+	queue_family: QueueFamily,
+	subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	dimensions: [f32; 2],
+) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+	let mut commands = HookCommands::new(device, queue_family, subpass, desc, dimensions)?;
+	match hook(&mut commands).and_then(|()| commands.build()) {
+		Ok(cmd) => Ok(Some(cmd)),
+		Err(HookError::DeviceMemoryAlloc(err)) => Err(err),
+		Err(err) => {
+			warn!("render hook failed to draw, skipping it this frame: {:?}", err);
+			Ok(None)
+		},
+	}
+}
@@ -1,40 +1,145 @@
+mod adaptive;
+mod ambient;
+mod frame_snapshot;
+mod light;
+mod material;
 mod mesh;
+mod path;
+mod probes;
 mod shaders;
 mod render_pass;
+mod ribbon;
+mod settings;
+mod snapshots;
+mod stereo;
+mod trail;
 
-pub use self::mesh::Mesh;
+pub use self::adaptive::{ AdaptiveQuality, QualityLevel, QualityPolicy, ShadowQuality };
+pub use self::ambient::AmbientLight;
+pub use self::frame_snapshot::{ snapshot_channel, FrameSnapshot, SnapshotReceiver, SnapshotSender };
+pub use self::light::{
+	DirectionalLight,
+	Light,
+	PointLight,
+	SpotLight,
+	MAX_LIGHTS,
+	MAX_LIGHT_COOKIES,
+	MAX_LOCAL_LIGHTS,
+};
+pub use self::material::{ MaterialDesc, MaterialHandle, TextureLoadError, UvTransform, VertexAnimation };
+pub(crate) use self::material::MaterialRegistry;
+pub use self::mesh::{
+	AmbientOcclusion,
+	GpuCulledMesh,
+	GpuCulledMeshError,
+	Mesh,
+	MeshFromFileError,
+	MeshIndexFormat,
+	MeshVertexFormat,
+	MESH_USER_PARAMS_SIZE,
+	NiceModel,
+	NiceModelBone,
+	NiceModelCollision,
+	NiceModelError,
+	NiceModelLod,
+	NiceModelMaterial,
+	NICE_MODEL_VERSION,
+};
+pub use self::path::DebugPath;
+pub use self::probes::LightProbeGrid;
 pub use self::shaders::{ MeshShaders, MeshShadersError };
-pub use self::render_pass::MeshRenderPass;
-use crate::{ ObjectId, RenderTarget, window::Window };
-use crate::camera::Camera;
-use cgmath::{ vec4, Vector4 };
-use std::sync::Arc;
+pub use self::render_pass::{ MeshCull, MeshRasterizer, MeshRenderPass, weighted_blend };
+pub use self::ribbon::Ribbon3D;
+pub use self::settings::{
+	Dither,
+	LOW_MEMORY_HEAP_SIZE,
+	MemoryProfile,
+	RenderSettings,
+	TransparencyMode,
+	TransparencySettings,
+	TransparentBucket,
+	UpscaleFilter,
+};
+pub use self::snapshots::TransformSnapshots;
+pub use self::stereo::{ Stereo, StereoMode };
+pub use self::trail::MotionTrail;
+use crate::{ DuplicateId, ObjectId, RenderTarget, StableId, StableIds, window::Window };
+use crate::batch::{ HookCommands, HookError, RenderHook, hook };
+use crate::camera::{ Camera, ProjectionUniform };
+use crate::device::{ AttachmentScope, AttachmentUsage };
+use crate::math::{ Transform, quat_to_xyzw };
+use crate::stats::{ self, BatchResources, Resource, ResourceTally };
+use crate::texture::{ self, TextureDeviceError };
+use self::light::{ LightProxy, LightsUniform, LocalLightsUniform };
+use self::ribbon::RibbonVertex;
+use self::stereo::EyeCameras;
+use self::trail::MotionTrailState;
+use cgmath::{ prelude::*, vec2, vec4, Rad, Vector2, Vector4 };
+use std::{ mem, sync::Arc };
 use vulkano::{
 	impl_vertex,
-	buffer::{ BufferUsage, ImmutableBuffer },
+	buffer::{ BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, ImmutableBuffer },
 	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
 	descriptor::{ DescriptorSet, descriptor_set::{ FixedSizeDescriptorSetsPool, PersistentDescriptorSet } },
 	device::Device,
 	format::{ ClearValue, Format },
 	framebuffer::{ Framebuffer, FramebufferCreationError },
 	image::{ AttachmentImage, ImageCreationError, ImageViewAccess },
+	instance::QueueFamily,
 	memory::{ DeviceMemoryAllocError },
 	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
 	sync::GpuFuture,
 };
 
-const ALBEDO_FORMAT: Format = Format::A2B10G10R10UnormPack32;
-const NORMAL_FORMAT: Format = Format::R32G32B32A32Sfloat;
-const DEPTH_FORMAT: Format = Format::D16Unorm;
+/// Share of the depth range in front of the scene that overlay meshes are drawn into.
+const OVERLAY_DEPTH: f32 = 0.1;
+/// Share of the upsampled history kept each frame with temporal upsampling.
+const UPSAMPLE_HISTORY_WEIGHT: f32 = 0.9;
+/// Distinct jitter offsets temporal upsampling cycles through.
+const JITTER_SAMPLES: u32 = 16;
 
 pub struct MeshBatch {
 	render_pass: Arc<MeshRenderPass>,
-	meshes: Vec<Mesh>,
+	meshes: Vec<MeshEntry>,
+	mesh_ids: StableIds,
+	drawables: Vec<DrawableEntry>,
+	drawable_ids: StableIds,
+	outline_width: u32,
+	max_extrapolation: f64,
+	lights: Vec<Light>,
+	light_stable_ids: Vec<StableId>,
+	light_ids: StableIds,
+	lights_pool: CpuBufferPool<LightsUniform>,
+	local_lights_pool: CpuBufferPool<LocalLightsUniform>,
+	last_camera: Option<Transform>,
+	/// Without the jitter.
+	last_projection: Option<ProjectionUniform>,
+	jitter_index: u32,
+	history_reset_distance: f32,
+	history_reset_angle: Rad<f32>,
+	/// `Window::history_generation` of the last frame drawn.
+	history_generation: u64,
+	settings: RenderSettings,
 	target_id: ObjectId,
 	gbuffers: GBuffers,
+	/// The right eye's view while `settings.stereo` is set. The left eye's is kept in the batch's own fields.
+	eye: Option<Eye>,
 	camera_desc_pool_gbuffers: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
-	camera_desc_pool_history: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
-	mesh_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	camera_desc_pool_lighting: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	lights_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	camera_desc_pool_light_volumes: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	local_lights_desc_pool: FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	hooks: [Option<RenderHook>; 2],
+	overlay: Option<OverlayProjection>,
+	exposure: f32,
+	ambient: AmbientLight,
+	projection_pool: CpuBufferPool<ProjectionUniform>,
+	snapshot_revision: u64,
+	trail_pool: CpuBufferPool<RibbonVertex>,
+	trail_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	light_probes: Option<LightProbeGrid>,
+	/// The probes' coefficients, or a single unused probe while there's no grid, since the binding needs a buffer.
+	light_probe_buffer: Arc<CpuAccessibleBuffer<[[f32; 4]]>>,
 }
 impl MeshBatch {
 	pub fn new(
@@ -42,26 +147,365 @@ impl MeshBatch {
 		render_pass: Arc<MeshRenderPass>
 	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
 		let camera_desc_pool_gbuffers = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_gbuffers.clone(), 0);
-		let camera_desc_pool_history = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_history.clone(), 1);
-		let mesh_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_gbuffers.clone(), 1);
-		let (gbuffers, future) = Self::make_gbuffers(target, &render_pass)?;
+		let camera_desc_pool_lighting = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_lighting.clone(), 1);
+		let lights_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_lighting.clone(), 2);
+		// the stencil pipeline has the same layout
+		let camera_desc_pool_light_volumes =
+			FixedSizeDescriptorSetsPool::new(render_pass.pipeline_light_volume.clone(), 1);
+		let local_lights_desc_pool = FixedSizeDescriptorSetsPool::new(render_pass.pipeline_light_volume.clone(), 2);
+		let settings = RenderSettings::default();
+		let render_dimensions = Self::render_dimensions(Self::view_dimensions(target, &settings), &settings);
+		let (gbuffers, future) = Self::make_gbuffers(target, &render_pass, 0, render_dimensions, None)?;
+
+		let lights_pool = CpuBufferPool::uniform_buffer(render_pass.shaders.target_vertices.device().clone());
+		let local_lights_pool = CpuBufferPool::uniform_buffer(render_pass.shaders.target_vertices.device().clone());
+		let projection_pool = CpuBufferPool::uniform_buffer(render_pass.shaders.target_vertices.device().clone());
+		let trail_pool = CpuBufferPool::vertex_buffer(render_pass.shaders.target_vertices.device().clone());
+		let light_probe_buffer =
+			Self::make_light_probe_buffer(render_pass.shaders.target_vertices.device(), (0..9).map(|_| [0.0; 4]))?;
+		let trail_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				PersistentDescriptorSet::start(render_pass.pipeline_ribbon.clone(), 1)
+					.add_sampled_image(
+						render_pass.shaders.white_pixel.clone(),
+						render_pass.shaders.clamp_sampler.clone()
+					)
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
 
 		Ok((
 			Self {
 				render_pass: render_pass,
 				meshes: vec![],
+				mesh_ids: StableIds::default(),
+				drawables: vec![],
+				drawable_ids: StableIds::default(),
+				outline_width: 2,
+				max_extrapolation: 0.1,
+				lights: vec![],
+				light_stable_ids: vec![],
+				light_ids: StableIds::default(),
+				lights_pool: lights_pool,
+				local_lights_pool: local_lights_pool,
+				last_camera: None,
+				last_projection: None,
+				jitter_index: 0,
+				history_reset_distance: 5.0,
+				history_reset_angle: Rad(0.5),
+				history_generation: 0,
+				settings: settings,
 				target_id: target.id_root().make_id(),
 				gbuffers: gbuffers,
+				eye: None,
 				camera_desc_pool_gbuffers: camera_desc_pool_gbuffers,
-				camera_desc_pool_history: camera_desc_pool_history,
-				mesh_desc_pool: mesh_desc_pool,
+				camera_desc_pool_lighting: camera_desc_pool_lighting,
+				lights_desc_pool: lights_desc_pool,
+				camera_desc_pool_light_volumes: camera_desc_pool_light_volumes,
+				local_lights_desc_pool: local_lights_desc_pool,
+				hooks: [None, None],
+				overlay: None,
+				exposure: 9.0,
+				ambient: AmbientLight::default(),
+				projection_pool: projection_pool,
+				snapshot_revision: 0,
+				trail_pool: trail_pool,
+				trail_desc: trail_desc,
+				light_probes: None,
+				light_probe_buffer: light_probe_buffer,
 			},
 			future
 		))
 	}
 
-	pub fn add_mesh(&mut self, mesh: Mesh) {
-		self.meshes.push(mesh);
+	pub fn add_mesh(&mut self, mesh: Mesh) -> MeshHandle {
+		let id = self.mesh_ids.insert(self.meshes.len());
+		self.insert_mesh(mesh, id)
+	}
+
+	/// Like `add_mesh`, with an id saved from an earlier run. Fails if the id is already used for a mesh.
+	pub fn add_mesh_with_id(&mut self, mesh: Mesh, id: StableId) -> Result<MeshHandle, DuplicateId> {
+		self.mesh_ids.insert_with_id(id, self.meshes.len())?;
+		Ok(self.insert_mesh(mesh, id))
+	}
+
+	pub fn mesh_id(&self, handle: MeshHandle) -> StableId {
+		self.meshes[handle.0].id
+	}
+
+	pub fn find_mesh(&self, id: StableId) -> Option<MeshHandle> {
+		self.mesh_ids.index(id).map(MeshHandle)
+	}
+
+	pub fn mesh(&self, handle: MeshHandle) -> &Mesh {
+		&self.meshes[handle.0].mesh
+	}
+
+	pub fn mesh_mut(&mut self, handle: MeshHandle) -> &mut Mesh {
+		&mut self.meshes[handle.0].mesh
+	}
+
+	/// Records where the mesh was at simulation time `time`, in seconds. Meshes with snapshots are moved by
+	/// `apply_transform_snapshots`, so entities simulated at a different tick rate, like remote players, move smoothly.
+	pub fn set_transform_snapshot(
+		&mut self,
+		handle: MeshHandle,
+		time: f64,
+		transform: Transform,
+	) {
+		self.meshes[handle.0].snapshots.get_or_insert_with(TransformSnapshots::new).push(time, transform);
+	}
+
+	/// Stops interpolating the mesh, leaving it where it was last placed.
+	pub fn clear_transform_snapshots(&mut self, handle: MeshHandle) {
+		self.meshes[handle.0].snapshots = None;
+	}
+
+	/// How far past the newest snapshot meshes keep moving before they hold still, in seconds. Defaults to 0.1.
+	pub fn set_max_extrapolation(&mut self, seconds: f64) {
+		self.max_extrapolation = seconds;
+	}
+
+	/// Moves every mesh with snapshots to its interpolated transform at `render_time`. This is usually the latest
+	/// simulation time minus a tick or two of delay, so there's a snapshot on either side to interpolate between.
+	pub fn apply_transform_snapshots(&mut self, render_time: f64) -> Result<(), DeviceMemoryAllocError> {
+		let max_extrapolation = self.max_extrapolation;
+		for entry in &mut self.meshes {
+			if let Some(transform) =
+				entry.snapshots.as_mut().and_then(|snapshots| snapshots.sample(render_time, max_extrapolation)) {
+				entry.mesh.set_transform(transform)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Adds custom geometry to the gbuffer and transparent passes, alongside the meshes.
+	pub fn add_drawable(&mut self, drawable: Box<Drawable3D>) -> DrawableHandle {
+		let id = self.drawable_ids.insert(self.drawables.len());
+		self.insert_drawable(drawable, id)
+	}
+
+	/// Fails if the id is already used for a drawable.
+	pub fn add_drawable_with_id(
+		&mut self,
+		drawable: Box<Drawable3D>,
+		id: StableId,
+	) -> Result<DrawableHandle, DuplicateId> {
+		self.drawable_ids.insert_with_id(id, self.drawables.len())?;
+		Ok(self.insert_drawable(drawable, id))
+	}
+
+	pub fn drawable_id(&self, handle: DrawableHandle) -> StableId {
+		self.drawables[handle.0].id
+	}
+
+	pub fn find_drawable(&self, id: StableId) -> Option<DrawableHandle> {
+		self.drawable_ids.index(id).map(DrawableHandle)
+	}
+
+	pub fn drawable_mut(&mut self, handle: DrawableHandle) -> &mut Drawable3D {
+		&mut *self.drawables[handle.0].drawable
+	}
+
+	/// Like `set_highlighted`, for drawables that bind the highlight color they're given.
+	pub fn set_drawable_highlighted(&mut self, handle: DrawableHandle, color: Option<[f32; 4]>) {
+		self.drawables[handle.0].highlight = color;
+	}
+
+	/// Draws an outline around the visible parts of the mesh, or removes it if `color` is `None`. `color` is RGBA,
+	/// and alpha blends the outline over the scene.
+	pub fn set_highlighted(&mut self, handle: MeshHandle, color: Option<[f32; 4]>) {
+		self.meshes[handle.0].highlight = color;
+	}
+
+	/// Draws the mesh with the overlay projection, in front of everything else, e.g. for first person arms that
+	/// shouldn't clip into walls. Has no effect while `set_overlay_projection` is `None`.
+	pub fn set_overlay(&mut self, handle: MeshHandle, overlay: bool) {
+		self.meshes[handle.0].overlay = overlay;
+	}
+
+	/// Draws a line through the positions the mesh was drawn at in recent frames, or removes it and forgets them if
+	/// `trail` is `None`. For debugging how meshes are moved, e.g. when they're synced to physics.
+	pub fn set_motion_trail(&mut self, handle: MeshHandle, trail: Option<MotionTrail>) {
+		let entry = &mut self.meshes[handle.0];
+		match (trail, &mut entry.motion_trail) {
+			(Some(trail), Some(state)) => state.set_trail(trail),
+			(trail, state) => *state = trail.map(MotionTrailState::new),
+		}
+	}
+
+	/// Projection overlay meshes are drawn with, from the camera's position and rotation. Overlay meshes get a depth
+	/// range of their own in front of the scene, as if depth were cleared after drawing it, and are lit by the scene's
+	/// directional and ambient light. Point and spot lights don't reach them, since their volumes are drawn in the
+	/// scene's depth range. Defaults to `None`, which draws overlay meshes like any other.
+	pub fn set_overlay_projection(&mut self, projection: Option<OverlayProjection>) {
+		self.overlay = projection;
+	}
+
+	/// Width of highlight outlines, in pixels. Defaults to 2.
+	pub fn set_outline_width(&mut self, width: u32) {
+		self.outline_width = width;
+	}
+
+	/// Discards the previous frame, so the next frame doesn't blend with it. Call after cutting to a different shot.
+	pub fn reset_history(&mut self) {
+		self.gbuffers.history_initialized = false;
+		if let Some(eye) = &mut self.eye {
+			eye.view.gbuffers.history_initialized = false;
+		}
+	}
+
+	/// History is also reset automatically when the target is resized, or when the camera moves further than `distance`
+	/// or turns more than `angle` in one frame. Defaults to 5 units and half a radian.
+	pub fn set_history_reset_threshold(&mut self, distance: f32, angle: Rad<f32>) {
+		self.history_reset_distance = distance;
+		self.history_reset_angle = angle;
+	}
+
+	pub fn render_settings(&self) -> &RenderSettings {
+		&self.settings
+	}
+
+	/// Takes effect on the next frame. Changing the render scale, temporal upsampling or the stereo mode reallocates
+	/// the batch's attachments and resets history.
+	pub fn set_render_settings(&mut self, settings: RenderSettings) {
+		self.settings = settings;
+	}
+
+	/// Exposure value at ISO 100, which light units are converted at. Bright days are around 15 and bright interiors
+	/// around 9, the default.
+	pub fn exposure(&self) -> f32 {
+		self.exposure
+	}
+
+	pub fn set_exposure(&mut self, ev100: f32) {
+		self.exposure = ev100;
+	}
+
+	pub fn light_probes(&self) -> Option<&LightProbeGrid> {
+		self.light_probes.as_ref()
+	}
+
+	/// Ambient light for meshes set to `Mesh::set_uses_light_probes`, replacing `ambient` for them. Uploads the probes,
+	/// so set the grid again after changing them, e.g. after rebaking part of it.
+	pub fn set_light_probes(&mut self, probes: Option<LightProbeGrid>) -> Result<(), DeviceMemoryAllocError> {
+		if let Some(probes) = &probes {
+			let device = self.render_pass.shaders.target_vertices.device();
+			self.light_probe_buffer = Self::make_light_probe_buffer(device, probes.coefficients().into_iter())?;
+		}
+		self.light_probes = probes;
+		Ok(())
+	}
+
+	pub fn ambient(&self) -> &AmbientLight {
+		&self.ambient
+	}
+
+	/// Light added to every surface from all around, converted with the exposure like lights are.
+	pub fn set_ambient(&mut self, ambient: AmbientLight) {
+		self.ambient = ambient;
+	}
+
+	/// Buffers, images and descriptor sets held by the batch, its attachments, meshes and drawables. Materials shared
+	/// with meshes in other batches count towards each of them. Targets aren't included, since they belong to whoever
+	/// created them.
+	pub fn resource_stats(&self) -> BatchResources {
+		let mut tally = ResourceTally::new();
+		self.gbuffers.resources(&mut tally);
+		if let Some(eye) = &self.eye {
+			eye.view.gbuffers.resources(&mut tally);
+		}
+		for entry in &self.meshes {
+			entry.mesh.resources(&mut tally);
+		}
+		for entry in &self.drawables {
+			entry.drawable.resources(&mut tally);
+		}
+		tally.finish()
+	}
+
+	/// Only the first `MAX_LIGHTS` directional lights and `MAX_LOCAL_LIGHTS` point and spot lights added are drawn.
+	pub fn add_light(&mut self, light: Light) -> LightHandle {
+		let id = self.light_ids.insert(self.lights.len());
+		self.insert_light(light, id)
+	}
+
+	/// Fails if the id is already used for a light.
+	pub fn add_light_with_id(&mut self, light: Light, id: StableId) -> Result<LightHandle, DuplicateId> {
+		self.light_ids.insert_with_id(id, self.lights.len())?;
+		Ok(self.insert_light(light, id))
+	}
+
+	pub fn light_id(&self, handle: LightHandle) -> StableId {
+		self.light_stable_ids[handle.0]
+	}
+
+	pub fn find_light(&self, id: StableId) -> Option<LightHandle> {
+		self.light_ids.index(id).map(LightHandle)
+	}
+
+	pub fn light(&self, handle: LightHandle) -> &Light {
+		&self.lights[handle.0]
+	}
+
+	pub fn light_mut(&mut self, handle: LightHandle) -> &mut Light {
+		&mut self.lights[handle.0]
+	}
+
+	/// Applies what changed in `snapshot` since the last one applied, moving `camera` if the snapshot places it. Call
+	/// on the render thread before `commands`.
+	pub fn apply_snapshot(
+		&mut self,
+		snapshot: &FrameSnapshot,
+		camera: &mut Camera,
+	) -> Result<(), DeviceMemoryAllocError> {
+		let revision = self.snapshot_revision;
+
+		for (index, state) in snapshot.meshes_since(revision) {
+			let entry = &mut self.meshes[index];
+			if let Some(transform) = state.transform {
+				entry.mesh.set_transform(transform)?;
+			}
+			entry.highlight = state.highlight;
+			entry.overlay = state.overlay;
+		}
+
+		for (index, light) in snapshot.lights_since(revision) {
+			self.lights[index] = light.clone();
+		}
+
+		if let Some(state) = snapshot.camera_since(revision) {
+			camera.set_transform(state.transform)?;
+			if let Some(fovx) = state.fovx.filter(|&fovx| fovx != camera.fovx()) {
+				camera.set_fovx(fovx)?;
+			}
+		}
+
+		if let Some(ev100) = snapshot.exposure_since(revision) {
+			self.exposure = ev100;
+		}
+		if let Some(ambient) = snapshot.ambient_since(revision) {
+			self.ambient = ambient;
+		}
+
+		self.snapshot_revision = snapshot.revision();
+		Ok(())
+	}
+
+	/// Calls `hook` every frame at `point`, to draw into the batch's render pass without a pass of its own. Replaces
+	/// any hook already set there.
+	pub fn set_hook(
+		&mut self,
+		point: MeshHook,
+		hook: impl FnMut(&mut HookCommands) -> Result<(), HookError> + 'static,
+	) {
+		self.hooks[point as usize] = Some(Box::new(hook));
+	}
+
+	pub fn clear_hook(&mut self, point: MeshHook) {
+		self.hooks[point as usize] = None;
 	}
 
 	pub fn commands(
@@ -70,71 +514,304 @@ impl MeshBatch {
 		target: &RenderTarget,
 		image_num: usize,
 		camera: &Camera,
-	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), DeviceMemoryAllocError> {
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), TextureDeviceError> {
 		assert!(self.target_id.is_child_of(target.id_root()));
 
+		let device = self.render_pass.shaders.target_vertices.device();
+		let cookies =
+			self.lights.iter().filter_map(|light| match light { Light::Spot(spot) => spot.cookie.as_ref(), _ => None });
+		for cookie in cookies {
+			texture::check_same_device(cookie, device, "light cookie")?;
+		}
+
+		for entry in &mut self.meshes {
+			if let Some(trail) = &mut entry.motion_trail {
+				trail.record(entry.mesh.transform().position);
+			}
+		}
+
 		let image = &target.images()[image_num];
-		let gbuffers_future =
-			if image.dimensions() != self.gbuffers.color.dimensions() {
-				let (gbuffers, gbuffers_future) = Self::make_gbuffers(target, &self.render_pass)?;
-				self.gbuffers = gbuffers;
-				Some(gbuffers_future)
+		let view_dimensions = Self::view_dimensions(target, &self.settings);
+		let mut gbuffers_future = self.update_gbuffers(target, 0, view_dimensions)?;
+		self.eye =
+			match (self.settings.stereo, self.eye.take()) {
+				(Some(_), Some(mut eye)) => {
+					self.swap_view(&mut eye.view);
+					let eye_future = self.update_gbuffers(target, 1, view_dimensions);
+					self.swap_view(&mut eye.view);
+					gbuffers_future = join_futures(gbuffers_future, eye_future?);
+					Some(eye)
+				},
+				(Some(stereo), None) => {
+					let render_dimensions = Self::render_dimensions(view_dimensions, &self.settings);
+					let upsampled_dimensions =
+						if self.settings.temporal_upsampling { Some(view_dimensions) } else { None };
+					let (gbuffers, eye_future) =
+						Self::make_gbuffers(target, &self.render_pass, 1, render_dimensions, upsampled_dimensions)?;
+					gbuffers_future = join_futures(gbuffers_future, Some(Box::new(eye_future)));
+					Some(Eye {
+						view: View { gbuffers: gbuffers, last_camera: None, last_projection: None, jitter_index: 0 },
+						cameras: EyeCameras::new(window, camera, stereo)?,
+					})
+				},
+				(None, _) => None,
+			};
+
+		let command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(
+				self.render_pass.shaders.target_vertices.device().clone(),
+				window.device().queue().family()
+			)?;
+
+		if window.history_generation() != self.history_generation {
+			self.history_generation = window.history_generation();
+			self.reset_history();
+		}
+
+		let eye_initialized = self.eye.as_ref().map_or(true, |eye| eye.view.gbuffers.history_initialized);
+		if window.is_throttled() && self.gbuffers.history_initialized && eye_initialized {
+			// show the last frame again instead of rendering the scene
+			let last_view = |gbuffers: &GBuffers| {
+				PostView { desc: gbuffers.target_desc(!gbuffers.history_index as usize), outline_width: 0 }
+			};
+			let views: Vec<_> =
+				Some(last_view(&self.gbuffers))
+					.into_iter()
+					.chain(self.eye.as_ref().map(|eye| last_view(&eye.view.gbuffers)))
+					.collect();
+			return Ok((self.post_commands(command_buffer, image, &views)?, gbuffers_future));
+		}
+
+		let (command_buffer, views) =
+			match self.eye.take() {
+				Some(mut eye) => {
+					let result = self.stereo_commands(command_buffer, window, camera, &mut eye, view_dimensions);
+					self.eye = Some(eye);
+					result?
+				},
+				None => {
+					let (command_buffer, view) =
+						self.scene_commands(command_buffer, window, camera, view_dimensions)?;
+					(command_buffer, vec![view])
+				},
+			};
+		let command_buffer = self.post_commands(command_buffer, image, &views)?;
+
+		Ok((command_buffer, gbuffers_future))
+	}
+
+	/// Reallocates the attachments of the view being rendered, number `view`, when they don't match the settings and
+	/// the target.
+	fn update_gbuffers(
+		&mut self,
+		target: &RenderTarget,
+		view: usize,
+		view_dimensions: [u32; 2],
+	) -> Result<Option<Box<GpuFuture>>, DeviceMemoryAllocError> {
+		let render_dimensions = Self::render_dimensions(view_dimensions, &self.settings);
+		let upsampled_dimensions = if self.settings.temporal_upsampling { Some(view_dimensions) } else { None };
+		if render_dimensions == self.gbuffers.color.dimensions().width_height()
+			&& upsampled_dimensions == self.gbuffers.upsampled_dimensions() {
+			return Ok(None);
+		}
+
+		let (gbuffers, future) =
+			Self::make_gbuffers(target, &self.render_pass, view, render_dimensions, upsampled_dimensions)?;
+		self.gbuffers = gbuffers;
+		Ok(Some(Box::new(future)))
+	}
+
+	/// Renders the left eye from the batch's own view and the right eye from `eye`'s, one after the other.
+	fn stereo_commands(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		window: &Window,
+		camera: &Camera,
+		eye: &mut Eye,
+		view_dimensions: [u32; 2],
+	) -> Result<(AutoCommandBufferBuilder, Vec<PostView>), DeviceMemoryAllocError> {
+		if let Some(stereo) = self.settings.stereo {
+			eye.cameras.update(camera, stereo)?;
+		}
+
+		let (command_buffer, left) = self.scene_commands(command_buffer, window, eye.cameras.left(), view_dimensions)?;
+		self.swap_view(&mut eye.view);
+		let right = self.scene_commands(command_buffer, window, eye.cameras.right(), view_dimensions);
+		self.swap_view(&mut eye.view);
+		let (command_buffer, right) = right?;
+
+		Ok((command_buffer, vec![left, right]))
+	}
+
+	/// Exchanges the state the batch keeps for the view being rendered with `view`'s.
+	fn swap_view(&mut self, view: &mut View) {
+		mem::swap(&mut self.gbuffers, &mut view.gbuffers);
+		mem::swap(&mut self.last_camera, &mut view.last_camera);
+		mem::swap(&mut self.last_projection, &mut view.last_projection);
+		mem::swap(&mut self.jitter_index, &mut view.jitter_index);
+	}
+
+	/// Renders the scene from `camera` into the batch's attachments, leaving the result in history for
+	/// `post_commands`.
+	fn scene_commands(
+		&mut self,
+		mut command_buffer: AutoCommandBufferBuilder,
+		window: &Window,
+		camera: &Camera,
+		view_dimensions: [u32; 2],
+	) -> Result<(AutoCommandBufferBuilder, PostView), DeviceMemoryAllocError> {
+		let render_dimensions = Self::render_dimensions(view_dimensions, &self.settings);
+		let camera_transform = camera.transform();
+		let projection = camera.tiled(camera.projection_vector());
+		let prev_camera = self.last_camera.unwrap_or(camera_transform);
+		let prev_projection = self.last_projection.unwrap_or(projection);
+		if let Some(last) = self.last_camera {
+			// a teleport or a cut, rather than motion history could follow
+			if camera_transform.position.distance(last.position) > self.history_reset_distance
+				|| Rad::acos(last.rotation.dot(camera_transform.rotation).abs().min(1.0)) * 2.0
+					> self.history_reset_angle {
+				self.reset_history();
+			}
+		}
+		self.last_camera = Some(camera_transform);
+		self.last_projection = Some(projection);
+
+		// sub-pixel offsets temporal upsampling accumulates, in normalized device coordinates
+		let jitter =
+			if self.settings.temporal_upsampling {
+				self.jitter_index = (self.jitter_index + 1) % JITTER_SAMPLES;
+				let [x, y] = halton_jitter(self.jitter_index + 1);
+				vec2(x * 2.0 / render_dimensions[0] as f32, y * 2.0 / render_dimensions[1] as f32)
 			} else {
-				None
+				Vector2::zero()
 			};
+		let jittered = |mut projection: ProjectionUniform| {
+			projection.offset.x += jitter.x;
+			projection.offset.y += jitter.y;
+			projection
+		};
 
-		let camera_desc_gbuffers =
-			Arc::new(
+		// with overlay meshes in the frame, the scene moves to the back of the depth range to make room for them
+		let overlay = self.overlay.filter(|_| self.meshes.iter().any(|entry| entry.overlay));
+		let (projection_buffer, overlay) =
+			match overlay {
+				Some(overlay) => {
+					let scene = camera.tiled(remap_depth(camera.projection_vector(), OVERLAY_DEPTH, 1.0));
+					let overlay =
+						camera.tiled(
+							remap_depth(
+								Camera::projection(camera.aspect(), overlay.fovx, overlay.znear, overlay.zfar),
+								0.0,
+								OVERLAY_DEPTH
+							)
+						);
+					let scene = stats::track(Resource::Subbuffer, self.projection_pool.next(jittered(scene))?);
+					let overlay = stats::track(Resource::Subbuffer, self.projection_pool.next(jittered(overlay))?);
+					(scene, Some(overlay))
+				},
+				None if self.settings.temporal_upsampling =>
+					(stats::track(Resource::Subbuffer, self.projection_pool.next(jittered(projection))?), None),
+				None => (camera.projection_buffer.clone(), None),
+			};
+
+		let camera_desc_gbuffers: Arc<DescriptorSet + Send + Sync + 'static> =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
 				self.camera_desc_pool_gbuffers.next()
 					.add_buffer(camera.position_buffer.clone())
 					.unwrap()
 					.add_buffer(camera.rotation_buffer.clone())
 					.unwrap()
-					.add_buffer(camera.projection_buffer.clone())
+					.add_buffer(projection_buffer.clone())
 					.unwrap()
 					.build()
-					.unwrap()
-			);
+					.unwrap(),
+			));
+		let camera_desc_overlay: Option<Arc<DescriptorSet + Send + Sync + 'static>> =
+			match &overlay {
+				Some(overlay_buffer) => {
+					Some(Arc::new(stats::track(
+						Resource::DescriptorSet,
+						self.camera_desc_pool_gbuffers.next()
+							.add_buffer(camera.position_buffer.clone())
+							.unwrap()
+							.add_buffer(camera.rotation_buffer.clone())
+							.unwrap()
+							.add_buffer(overlay_buffer.clone())
+							.unwrap()
+							.build()
+							.unwrap(),
+					)))
+				},
+				None => None,
+			};
 
-		let dimensions = [image.dimensions().width() as f32, image.dimensions().height() as f32];
+		let dimensions = [render_dimensions[0] as f32, render_dimensions[1] as f32];
 
 		let history_index = self.gbuffers.history_index as usize;
 		self.gbuffers.history_index = !self.gbuffers.history_index;
 
+		for entry in &mut self.drawables {
+			command_buffer = entry.drawable.record_before_render_pass(command_buffer, &self.render_pass, camera)?;
+		}
+
 		let mut command_buffer =
-			AutoCommandBufferBuilder
-				::primary_one_time_submit(
-					self.render_pass.shaders.target_vertices.device().clone(),
-					window.device().queue().family()
-				)?
+			command_buffer
 				.begin_render_pass(
-					Arc::new(
+					Arc::new(stats::track(
+						Resource::Framebuffer,
 						Framebuffer::start(self.render_pass.render_pass().clone())
 							.add(self.gbuffers.color.clone())
 							.and_then(|fb| fb.add(self.gbuffers.normal.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.mask.clone()))
 							.and_then(|fb| fb.add(self.gbuffers.depth.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.lit.clone()))
 							.and_then(|fb| fb.add(self.gbuffers.history[history_index].clone()))
-							.and_then(|fb| fb.add(image.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.motion.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.accum.clone()))
+							.and_then(|fb| fb.add(self.gbuffers.revealage.clone()))
 							.and_then(|fb| fb.build())
 							.map_err(|err| match err {
 								FramebufferCreationError::OomError(err) => err,
 								err => unreachable!("{:?}", err),
-							})?
-					),
+							})?,
+					)),
 					true,
-					vec![[0.0, 0.0, 0.0, 1.0].into(), [0.0; 4].into(), 1.0.into(), ClearValue::None, ClearValue::None]
+					vec![
+						[0.0, 0.0, 0.0, 1.0].into(),
+						[0.0; 4].into(),
+						[0.0; 4].into(),
+						ClearValue::DepthStencil((1.0, 0)),
+						ClearValue::None,
+						ClearValue::None,
+						ClearValue::None,
+						[0.0; 4].into(),
+						[1.0, 0.0, 0.0, 0.0].into()
+					]
 				)
 				.unwrap();
 
-		for mesh in &mut self.meshes {
+		let mut any_highlighted = false;
+		let drawables =
+			self.meshes.iter_mut()
+				.map(|entry| (&mut entry.mesh as &mut (Drawable3D + 'static), entry.highlight, entry.overlay))
+				.chain(self.drawables.iter_mut().map(|entry| (&mut *entry.drawable, entry.highlight, false)));
+		for (drawable, highlight, overlay) in drawables {
+			any_highlighted |= highlight.is_some();
+			let camera_desc =
+				match &camera_desc_overlay {
+					Some(camera_desc_overlay) if overlay => camera_desc_overlay,
+					_ => &camera_desc_gbuffers,
+				};
+
 			command_buffer =
 				unsafe {
 					command_buffer
 						.execute_commands(
-							mesh.make_commands(
+							drawable.make_commands(
 								&self.render_pass,
-								camera_desc_gbuffers.clone(),
-								&mut self.mesh_desc_pool,
+								camera_desc,
+								highlight,
 								window.device().queue().family(),
 								dimensions
 							)?
@@ -143,6 +820,33 @@ impl MeshBatch {
 				};
 		}
 
+		if let Some(commands) =
+			self.hook_commands(MeshHook::AfterGbuffers, window, &camera_desc_gbuffers, dimensions)? {
+			command_buffer = unsafe { command_buffer.execute_commands(commands).unwrap() };
+		}
+
+		let mut weighted = vec![];
+		let mut transparent = vec![];
+		for entry in &mut self.drawables {
+			let (drawable, render_pass, camera_desc) = (&mut entry.drawable, &self.render_pass, &camera_desc_gbuffers);
+			let queue_family = window.device().queue().family();
+			match self.settings.transparency.mode(drawable.transparent_bucket()) {
+				TransparencyMode::InOrder => {
+					let commands =
+						drawable.make_transparent_commands(render_pass, camera_desc, queue_family, dimensions)?;
+					transparent.extend(commands);
+				},
+				TransparencyMode::WeightedBlended => {
+					let commands =
+						drawable.make_weighted_blended_commands(render_pass, camera_desc, queue_family, dimensions)?;
+					weighted.extend(commands);
+				},
+			}
+		}
+		transparent.extend(
+			self.motion_trail_commands(window.device().queue().family(), &camera_desc_gbuffers, dimensions)?
+		);
+
 		let dynamic_state =
 			DynamicState {
 				line_width: None,
@@ -150,63 +854,426 @@ impl MeshBatch {
 				scissors: None,
 			};
 
-		let history_desc =
+		let lights =
+			light::pack_lights(
+				self.lights.iter(),
+				&self.ambient,
+				self.light_probes.as_ref(),
+				light::exposure_scale(self.exposure)
+			);
+		let lights_desc =
+			self.lights_desc_pool.next()
+				.add_buffer(stats::track(Resource::Subbuffer, self.lights_pool.next(lights.lights)?))
+				.unwrap()
+				.add_buffer(self.light_probe_buffer.clone())
+				.unwrap()
+				.build()
+				.unwrap();
+		let lights_desc = stats::track(Resource::DescriptorSet, lights_desc);
+
+		let light_volume_descs =
+			if lights.proxies.is_empty() {
+				None
+			} else {
+				let local_lights = stats::track(Resource::Subbuffer, self.local_lights_pool.next(lights.local_lights)?);
+				// every binding needs an image, even if no light samples it
+				let unused_cookie = self.render_pass.shaders.texture1_default.clone();
+				let cookie = |i: usize| lights.cookies.get(i).cloned().unwrap_or_else(|| unused_cookie.clone());
+				Some((
+					self.gbuffers.lighting_desc.clone(),
+					Arc::new(stats::track(
+						Resource::DescriptorSet,
+						self.camera_desc_pool_light_volumes.next()
+							.add_buffer(camera.position_buffer.clone())
+							.unwrap()
+							.add_buffer(camera.rotation_buffer.clone())
+							.unwrap()
+							.add_buffer(projection_buffer.clone())
+							.unwrap()
+							.build()
+							.unwrap(),
+					)),
+					Arc::new(stats::track(
+						Resource::DescriptorSet,
+						self.local_lights_desc_pool.next()
+							.add_buffer(local_lights)
+							.unwrap()
+							.add_sampled_image(cookie(0), self.render_pass.shaders.cookie_sampler.clone())
+							.unwrap()
+							.add_sampled_image(cookie(1), self.render_pass.shaders.cookie_sampler.clone())
+							.unwrap()
+							.add_sampled_image(cookie(2), self.render_pass.shaders.cookie_sampler.clone())
+							.unwrap()
+							.add_sampled_image(cookie(3), self.render_pass.shaders.cookie_sampler.clone())
+							.unwrap()
+							.build()
+							.unwrap(),
+					)),
+				))
+			};
+
+		let resolve_desc =
 			if self.gbuffers.history_initialized {
-				self.gbuffers.history_descs[history_index].clone()
+				self.gbuffers.resolve_descs[history_index].clone()
 			} else {
-				Arc::new(
-					PersistentDescriptorSet::start(self.render_pass.pipeline_history.clone(), 0)
-						.add_buffer(self.gbuffers.size.clone())
-						.unwrap()
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(self.render_pass.pipeline_resolve.clone(), 0)
 						.add_sampled_image(self.render_pass.shaders.black_pixel.clone(), self.render_pass.shaders.sampler.clone())
 						.unwrap()
-						.add_image(self.gbuffers.color.clone())
-						.unwrap()
-						.add_image(self.gbuffers.normal.clone())
+						.add_image(self.gbuffers.lit.clone())
 						.unwrap()
 						.add_image(self.gbuffers.depth.clone())
 						.unwrap()
 						.build()
-						.unwrap()
-				)
+						.unwrap(),
+				))
 			};
-		let command_buffer = command_buffer.next_subpass(false)
+		let history_weight = if self.gbuffers.history_initialized { UPSAMPLE_HISTORY_WEIGHT } else { 0.0 };
+		self.gbuffers.history_initialized = true;
+
+		// the resolve reads the same camera, for its motion
+		let camera_desc_lighting =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				self.camera_desc_pool_lighting.next()
+					.add_buffer(camera.position_buffer.clone())
+					.unwrap()
+					.add_buffer(camera.rotation_buffer.clone())
+					.unwrap()
+					.add_buffer(projection_buffer)
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		let mut command_buffer = command_buffer.next_subpass(false)
 			.unwrap()
 			.draw(
-				self.render_pass.pipeline_history.clone(),
+				self.render_pass.pipeline_lighting.clone(),
 				&dynamic_state,
 				vec![self.render_pass.shaders.target_vertices.clone()],
-				(
-					history_desc,
-					self.camera_desc_pool_history.next()
-						.add_buffer(camera.position_buffer.clone())
-						.unwrap()
-						.add_buffer(camera.rotation_buffer.clone())
-						.unwrap()
-						.add_buffer(camera.projection_buffer.clone())
-						.unwrap()
-						.build()
-						.unwrap(),
-				),
+				(self.gbuffers.lighting_desc.clone(), camera_desc_lighting.clone(), lights_desc),
 				()
 			)
-			.unwrap()
-			.next_subpass(false)
+			.unwrap();
+
+		if let Some(descs) = light_volume_descs {
+			for (i, proxy) in lights.proxies.iter().enumerate() {
+				let vertices =
+					match proxy {
+						LightProxy::Sphere => self.render_pass.shaders.light_sphere_vertices.clone(),
+						LightProxy::Cone => self.render_pass.shaders.light_cone_vertices.clone(),
+					};
+				let light_volume = shaders::vs_light_volume::ty::LightVolume { light_index: i as u32 };
+				command_buffer = command_buffer
+					.draw(
+						self.render_pass.pipeline_light_stencil.clone(),
+						&dynamic_state,
+						vec![vertices.clone()],
+						descs.clone(),
+						light_volume
+					)
+					.unwrap()
+					.draw(
+						self.render_pass.pipeline_light_volume.clone(),
+						&dynamic_state,
+						vec![vertices],
+						descs.clone(),
+						light_volume
+					)
+					.unwrap();
+			}
+		}
+
+		let mut command_buffer = command_buffer.next_subpass(true).unwrap();
+		let any_weighted = !weighted.is_empty();
+		for commands in weighted {
+			command_buffer = unsafe { command_buffer.execute_commands(commands).unwrap() };
+		}
+
+		let mut command_buffer = command_buffer.next_subpass(false).unwrap();
+		if any_weighted {
+			command_buffer = command_buffer
+				.draw(
+					self.render_pass.pipeline_weighted_composite.clone(),
+					&dynamic_state,
+					vec![self.render_pass.shaders.target_vertices.clone()],
+					self.gbuffers.weighted_composite_desc.clone(),
+					()
+				)
+				.unwrap();
+		}
+
+		let mut command_buffer = command_buffer.next_subpass(true).unwrap();
+
+		let after_lighting =
+			self.hook_commands(MeshHook::AfterLighting, window, &camera_desc_gbuffers, dimensions)?;
+		for commands in transparent.into_iter().chain(after_lighting) {
+			command_buffer = unsafe { command_buffer.execute_commands(commands).unwrap() };
+		}
+
+		let command_buffer = command_buffer.next_subpass(false)
 			.unwrap()
 			.draw(
-				self.render_pass.pipeline_target.clone(),
+				self.render_pass.pipeline_resolve.clone(),
 				&dynamic_state,
 				vec![self.render_pass.shaders.target_vertices.clone()],
-				self.gbuffers.target_descs[history_index].clone(),
-				()
+				(resolve_desc, camera_desc_lighting),
+				shaders::fs_resolve::ty::Reprojection {
+					prev_camera_pos: prev_camera.position.extend(0.0).into(),
+					prev_camera_rot: quat_to_xyzw(prev_camera.rotation),
+					prev_camera_proj: prev_projection.projection.into(),
+					prev_camera_offset: prev_projection.offset.into(),
+					resolution: dimensions,
+					jitter: jitter.into(),
+					overlay_depth: if overlay.is_some() { OVERLAY_DEPTH } else { 0.0 },
+				}
 			)
 			.unwrap()
 			.end_render_pass()
-			.unwrap()
-			.build()
-			.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+			.unwrap();
+		let command_buffer = self.upsample_commands(command_buffer, history_index, jitter, history_weight)?;
+		let outline_width = if any_highlighted { self.outline_width as i32 } else { 0 };
 
-		Ok((command_buffer, gbuffers_future))
+		Ok((command_buffer, PostView { desc: self.gbuffers.target_desc(history_index), outline_width: outline_width }))
+	}
+
+	fn hook_commands(
+		&mut self,
+		point: MeshHook,
+		window: &Window,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let subpass =
+			match point {
+				MeshHook::AfterGbuffers => self.render_pass.subpass_gbuffers.clone(),
+				MeshHook::AfterLighting => self.render_pass.subpass_transparent.clone(),
+			};
+
+		match &mut self.hooks[point as usize] {
+			Some(f) =>
+				hook::record(
+					f,
+					self.render_pass.shaders.target_vertices.device().clone(),
+					window.device().queue().family(),
+					subpass,
+					camera_desc.clone(),
+					dimensions
+				),
+			None => Ok(None),
+		}
+	}
+
+	fn make_light_probe_buffer(
+		device: &Arc<Device>,
+		coefficients: impl ExactSizeIterator<Item = [f32; 4]>,
+	) -> Result<Arc<CpuAccessibleBuffer<[[f32; 4]]>>, DeviceMemoryAllocError> {
+		let usage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+		CpuAccessibleBuffer::from_iter(device.clone(), usage, coefficients)
+	}
+
+	/// Draws the meshes' motion trails in the transparent pass, or `None` when no mesh has one long enough to draw.
+	fn motion_trail_commands(
+		&self,
+		queue_family: QueueFamily,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let mut trails = self.meshes.iter().filter_map(|entry| entry.motion_trail.as_ref()?.vertices()).peekable();
+		if trails.peek().is_none() {
+			return Ok(None);
+		}
+
+		let dynamic_state =
+			DynamicState {
+				line_width: None,
+				viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+				scissors: None,
+			};
+
+		let mut cmd =
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+				self.render_pass.shaders.target_vertices.device().clone(),
+				queue_family,
+				self.render_pass.subpass_transparent.clone()
+			)?;
+		for vertices in trails {
+			let vertices = stats::track(Resource::Subbuffer, self.trail_pool.chunk(vertices)?);
+			cmd = cmd
+				.draw(
+					self.render_pass.pipeline_ribbon.clone(),
+					&dynamic_state,
+					vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+					(camera_desc.clone(), self.trail_desc.clone()),
+					()
+				)
+				.unwrap();
+		}
+
+		Ok(Some(
+			cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		))
+	}
+
+	/// Accumulates history image `history_index` into the upsampled history, when the batch has one.
+	fn upsample_commands(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		history_index: usize,
+		jitter: Vector2<f32>,
+		history_weight: f32,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		let upsampled =
+			match &self.gbuffers.upsampled {
+				Some(upsampled) => upsampled,
+				None => return Ok(command_buffer),
+			};
+		let [width, height] = upsampled.images[history_index].dimensions().width_height();
+		let target_dimensions = [width as f32, height as f32];
+		let dynamic_state =
+			DynamicState {
+				line_width: None,
+				viewports:
+					Some(vec![Viewport { origin: [0.0, 0.0], dimensions: target_dimensions, depth_range: 0.0..1.0 }]),
+				scissors: None,
+			};
+
+		Ok(
+			command_buffer
+				.begin_render_pass(
+					Arc::new(stats::track(
+						Resource::Framebuffer,
+						Framebuffer::start(self.render_pass.upsample_render_pass.clone())
+							.add(upsampled.images[history_index].clone())
+							.and_then(|fb| fb.build())
+							.map_err(|err| match err {
+								FramebufferCreationError::OomError(err) => err,
+								err => unreachable!("{:?}", err),
+							})?,
+					)),
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap()
+				.draw(
+					self.render_pass.pipeline_upsample.clone(),
+					&dynamic_state,
+					vec![self.render_pass.shaders.target_vertices.clone()],
+					upsampled.descs[history_index].clone(),
+					shaders::fs_upsample::ty::Upsample {
+						target_size: target_dimensions,
+						jitter: (jitter * 0.5).into(),
+						history_weight: history_weight,
+					}
+				)
+				.unwrap()
+				.end_render_pass()
+				.unwrap()
+		)
+	}
+
+	/// Upscales and outlines each of `views` into `image`, side by side or over each other for stereo.
+	fn post_commands(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		views: &[PostView],
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		let [width, height] = image.dimensions().width_height();
+		let (upscale_filter, sharpness) = self.settings.upscale_filter.push_constants();
+
+		let mut command_buffer =
+			command_buffer
+				.begin_render_pass(
+					Arc::new(stats::track(
+						Resource::Framebuffer,
+						Framebuffer::start(self.render_pass.post_render_pass.clone())
+							.add(image.clone())
+							.and_then(|fb| fb.build())
+							.map_err(|err| match err {
+								FramebufferCreationError::OomError(err) => err,
+								err => unreachable!("{:?}", err),
+							})?,
+					)),
+					false,
+					vec![ClearValue::None]
+				)
+				.unwrap();
+
+		for (i, view) in views.iter().enumerate() {
+			let whole = [width as f32, height as f32];
+			let (offset, size, pipeline) =
+				match self.settings.stereo.map(|stereo| stereo.mode) {
+					// the same split as `view_dimensions`
+					Some(StereoMode::SideBySide) => {
+						let eye_width = (width / 2).max(1);
+						(
+							[(eye_width * i as u32) as f32, 0.0],
+							[eye_width as f32, height as f32],
+							self.render_pass.pipeline_target.clone(),
+						)
+					},
+					Some(StereoMode::Anaglyph) =>
+						([0.0, 0.0], whole, self.render_pass.pipelines_target_anaglyph[i].clone()),
+					None => ([0.0, 0.0], whole, self.render_pass.pipeline_target.clone()),
+				};
+			let dynamic_state =
+				DynamicState {
+					line_width: None,
+					viewports: Some(vec![Viewport { origin: offset, dimensions: size, depth_range: 0.0..1.0 }]),
+					scissors: None,
+				};
+
+			command_buffer = command_buffer
+				.draw(
+					pipeline,
+					&dynamic_state,
+					vec![self.render_pass.shaders.target_vertices.clone()],
+					view.desc.clone(),
+					shaders::fs_target::ty::Post {
+						target_offset: offset,
+						target_size: size,
+						outline_width: view.outline_width,
+						upscale_filter: upscale_filter,
+						sharpness: sharpness,
+						dither: self.settings.dither.push_constant(),
+					}
+				)
+				.unwrap();
+		}
+
+		Ok(
+			command_buffer
+				.end_render_pass()
+				.unwrap()
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+
+	fn insert_mesh(&mut self, mesh: Mesh, id: StableId) -> MeshHandle {
+		self.meshes.push(MeshEntry {
+			mesh: mesh,
+			id: id,
+			highlight: None,
+			overlay: false,
+			snapshots: None,
+			motion_trail: None,
+		});
+		MeshHandle(self.meshes.len() - 1)
+	}
+
+	fn insert_drawable(&mut self, drawable: Box<Drawable3D>, id: StableId) -> DrawableHandle {
+		self.drawables.push(DrawableEntry { drawable: drawable, id: id, highlight: None });
+		DrawableHandle(self.drawables.len() - 1)
+	}
+
+	fn insert_light(&mut self, light: Light, id: StableId) -> LightHandle {
+		self.lights.push(light);
+		self.light_stable_ids.push(id);
+		LightHandle(self.lights.len() - 1)
 	}
 
 	fn make_sampled_input_attachment(
@@ -218,51 +1285,64 @@ impl MeshBatch {
 			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!(err) })
 	}
 
-	fn make_transient_input_attachment(
-		device: Arc<Device>,
-		dimensions: [u32; 2],
-		format: Format,
-	) -> Result<Arc<AttachmentImage>, DeviceMemoryAllocError> {
-		AttachmentImage::transient_input_attachment(device, dimensions, format)
-			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!(err) })
+	/// The part of the target each view is shown in, which is all of it unless side by side stereo splits it.
+	fn view_dimensions(target: &RenderTarget, settings: &RenderSettings) -> [u32; 2] {
+		let [width, height] = target.images()[0].dimensions().width_height();
+		match settings.stereo {
+			Some(Stereo { mode: StereoMode::SideBySide, .. }) => [(width / 2).max(1), height],
+			_ => [width, height],
+		}
+	}
+
+	fn render_dimensions(view_dimensions: [u32; 2], settings: &RenderSettings) -> [u32; 2] {
+		// attachments can't be empty. render targets shouldn't be either, but don't panic deep in vulkano if they are.
+		let [width, height] = view_dimensions;
+		let scale = settings.render_scale.max(0.0);
+		[((width as f32 * scale).round() as u32).max(1), ((height as f32 * scale).round() as u32).max(1)]
 	}
 
+	/// Makes the attachments of view number `view`, which is 0 for the batch's own and 1 for the other stereo eye. With
+	/// `upsampled_dimensions`, also makes the upsampled history for temporal upsampling, at those dimensions.
 	fn make_gbuffers(
 		target: &RenderTarget,
 		shared: &MeshRenderPass,
+		view: usize,
+		dimensions: [u32; 2],
+		upsampled_dimensions: Option<[u32; 2]>,
 	) -> Result<(GBuffers, impl GpuFuture), DeviceMemoryAllocError> {
-		let dimensions = target.images()[0].dimensions().width_height();
-		let color =
-			Self::make_transient_input_attachment(
-				shared.shaders.target_vertices.device().clone(),
-				dimensions,
-				ALBEDO_FORMAT
-			)?;
-		let normal =
-			Self::make_transient_input_attachment(
-				shared.shaders.target_vertices.device().clone(),
-				dimensions,
-				NORMAL_FORMAT
-			)?;
-		let depth =
-			Self::make_transient_input_attachment(
+		// everything but history is only used within the frame, so it can be shared with other batches of the same
+		// size. the mask is only read once every view is rendered, so the eyes need one each.
+		let pool = shared.shaders.device.attachments();
+		let device = shared.shaders.device.device();
+		let formats = shared.gbuffer_formats;
+		let get = |name, scope, format, usage| pool.get(device, name, scope, dimensions, format, usage);
+		let color = get("albedo", AttachmentScope::View, formats.albedo, AttachmentUsage::TransientInput)?;
+		let normal = get("normal", AttachmentScope::View, formats.normal, AttachmentUsage::TransientInput)?;
+		let mask = get("mask", AttachmentScope::Batch { view: view }, formats.mask, AttachmentUsage::Sampled)?;
+		let depth = get("depth", AttachmentScope::View, formats.depth, AttachmentUsage::TransientInput)?;
+		let lit = get("lit", AttachmentScope::View, formats.lit, AttachmentUsage::TransientInput)?;
+		let motion = get("motion", AttachmentScope::View, formats.motion, AttachmentUsage::Sampled)?;
+		let accum = get("accum", AttachmentScope::View, formats.accum, AttachmentUsage::TransientInput)?;
+		let revealage = get("revealage", AttachmentScope::View, formats.revealage, AttachmentUsage::TransientInput)?;
+		let make_history = || {
+			Self::make_sampled_input_attachment(
 				shared.shaders.target_vertices.device().clone(),
 				dimensions,
-				DEPTH_FORMAT
-			)?;
+				target.format()
+			)
+		};
+		// the resolve doesn't read the previous frame, so with little memory both frames write the same image
+		let single_history = shared.memory_profile() == MemoryProfile::Low;
 		let history =
-			[
-				Self::make_sampled_input_attachment(
-					shared.shaders.target_vertices.device().clone(),
-					dimensions,
-					target.format()
-				)?,
-				Self::make_sampled_input_attachment(
-					shared.shaders.target_vertices.device().clone(),
-					dimensions,
-					target.format()
-				)?
-			];
+			if single_history {
+				let history = make_history()?;
+				[history.clone(), history]
+			} else {
+				[make_history()?, make_history()?]
+			};
+		let prev_history = |index: usize| -> Arc<ImageViewAccess + Send + Sync> {
+			if single_history { shared.shaders.black_pixel.clone() } else { history[index].clone() }
+		};
 
 		let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
 		let (size, size_future) =
@@ -277,69 +1357,153 @@ impl MeshBatch {
 				shared.shaders.queue.clone()
 			)?;
 
-		let history_descs =
+		let lighting_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				PersistentDescriptorSet::start(shared.pipeline_lighting.clone(), 0)
+					.add_buffer(size.clone())
+					.unwrap()
+					.add_image(color.clone())
+					.unwrap()
+					.add_image(normal.clone())
+					.unwrap()
+					.add_image(depth.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		let weighted_composite_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				PersistentDescriptorSet::start(shared.pipeline_weighted_composite.clone(), 0)
+					.add_image(accum.clone())
+					.unwrap()
+					.add_image(revealage.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		// each frame writes one history image and reads the other
+		let resolve_descs =
 			[
-				Arc::new(
-					PersistentDescriptorSet::start(shared.pipeline_history.clone(), 0)
-						.add_buffer(size.clone())
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(shared.pipeline_resolve.clone(), 0)
+						.add_sampled_image(prev_history(1), shared.shaders.sampler.clone())
 						.unwrap()
-						.add_sampled_image(history[1].clone(), shared.shaders.sampler.clone())
-						.unwrap()
-						.add_image(color.clone())
-						.unwrap()
-						.add_image(normal.clone())
+						.add_image(lit.clone())
 						.unwrap()
 						.add_image(depth.clone())
 						.unwrap()
 						.build()
+						.unwrap(),
+				)) as _,
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(shared.pipeline_resolve.clone(), 0)
+						.add_sampled_image(prev_history(0), shared.shaders.sampler.clone())
 						.unwrap()
-				) as _,
-				Arc::new(
-					PersistentDescriptorSet::start(shared.pipeline_history.clone(), 0)
-						.add_buffer(size.clone())
-						.unwrap()
-						.add_sampled_image(history[0].clone(), shared.shaders.sampler.clone())
-						.unwrap()
-						.add_image(color.clone())
-						.unwrap()
-						.add_image(normal.clone())
+						.add_image(lit.clone())
 						.unwrap()
 						.add_image(depth.clone())
 						.unwrap()
 						.build()
-						.unwrap()
-				) as _
+						.unwrap(),
+				)) as _
 			];
 
 		let target_descs =
 			[
-				Arc::new(
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
 					PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
-						.add_image(history[0].clone())
+						.add_sampled_image(history[0].clone(), shared.shaders.clamp_sampler.clone())
 						.unwrap()
-						.build()
+						.add_sampled_image(mask.clone(), shared.shaders.clamp_sampler.clone())
 						.unwrap()
-				) as _,
-				Arc::new(
+						.build()
+						.unwrap(),
+				)) as _,
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
 					PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
-						.add_image(history[1].clone())
+						.add_sampled_image(history[1].clone(), shared.shaders.clamp_sampler.clone())
 						.unwrap()
-						.build()
+						.add_sampled_image(mask.clone(), shared.shaders.clamp_sampler.clone())
 						.unwrap()
-				) as _
+						.build()
+						.unwrap(),
+				)) as _
 			];
 
+		// each frame accumulates its history image into one of these, from the other
+		let upsampled =
+			match upsampled_dimensions {
+				Some(upsampled_dimensions) => {
+					let make_upsampled = || {
+						Self::make_sampled_input_attachment(
+							shared.shaders.target_vertices.device().clone(),
+							upsampled_dimensions,
+							target.format()
+						)
+					};
+					let images = [make_upsampled()?, make_upsampled()?];
+					let upsample_desc = |index: usize| -> Arc<DescriptorSet + Send + Sync + 'static> {
+						Arc::new(stats::track(
+							Resource::DescriptorSet,
+							PersistentDescriptorSet::start(shared.pipeline_upsample.clone(), 0)
+								.add_sampled_image(history[index].clone(), shared.shaders.clamp_sampler.clone())
+								.unwrap()
+								.add_sampled_image(motion.clone(), shared.shaders.clamp_sampler.clone())
+								.unwrap()
+								.add_sampled_image(images[1 - index].clone(), shared.shaders.clamp_sampler.clone())
+								.unwrap()
+								.build()
+								.unwrap(),
+						))
+					};
+					let target_desc = |index: usize| -> Arc<DescriptorSet + Send + Sync + 'static> {
+						Arc::new(stats::track(
+							Resource::DescriptorSet,
+							PersistentDescriptorSet::start(shared.pipeline_target.clone(), 0)
+								.add_sampled_image(images[index].clone(), shared.shaders.clamp_sampler.clone())
+								.unwrap()
+								.add_sampled_image(mask.clone(), shared.shaders.clamp_sampler.clone())
+								.unwrap()
+								.build()
+								.unwrap(),
+						))
+					};
+
+					Some(Upsampled {
+						descs: [upsample_desc(0), upsample_desc(1)],
+						target_descs: [target_desc(0), target_desc(1)],
+						images: images,
+					})
+				},
+				None => None,
+			};
+
 		Ok((
 			GBuffers {
-				size: size,
 				color: color,
 				normal: normal,
+				mask: mask,
 				depth: depth,
-				history_descs: history_descs,
+				lit: lit,
+				motion: motion,
+				accum: accum,
+				revealage: revealage,
+				lighting_desc: lighting_desc,
+				weighted_composite_desc: weighted_composite_desc,
+				resolve_descs: resolve_descs,
 				target_descs: target_descs,
 				history: history,
 				history_index: false,
 				history_initialized: false,
+				upsampled: upsampled,
 			},
 			size_future
 		))
@@ -348,15 +1512,217 @@ impl MeshBatch {
 
 #[derive(Clone)]
 struct GBuffers {
-	size: Arc<ImmutableBuffer<Vector4<f32>>>,
 	color: Arc<AttachmentImage>,
 	normal: Arc<AttachmentImage>,
+	mask: Arc<AttachmentImage>,
 	depth: Arc<AttachmentImage>,
-	history_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
+	lit: Arc<AttachmentImage>,
+	motion: Arc<AttachmentImage>,
+	accum: Arc<AttachmentImage>,
+	revealage: Arc<AttachmentImage>,
+	lighting_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	weighted_composite_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	resolve_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
 	target_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
 	history: [Arc<AttachmentImage>; 2],
 	history_index: bool,
 	history_initialized: bool,
+	upsampled: Option<Upsampled>,
+}
+impl GBuffers {
+	fn upsampled_dimensions(&self) -> Option<[u32; 2]> {
+		self.upsampled.as_ref().map(|upsampled| upsampled.images[0].dimensions().width_height())
+	}
+
+	/// For drawing history image `history_index`, or its upsampled history with temporal upsampling, to the target.
+	fn target_desc(&self, history_index: usize) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		match &self.upsampled {
+			Some(upsampled) => upsampled.target_descs[history_index].clone(),
+			None => self.target_descs[history_index].clone(),
+		}
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		let images =
+			[&self.color, &self.normal, &self.mask, &self.depth, &self.lit, &self.motion, &self.accum, &self.revealage];
+		for image in images.iter().cloned().chain(&self.history) {
+			tally.add_image(&**image);
+		}
+		// lighting, the weighted composite, two resolves and two targets
+		for _ in 0..6 {
+			tally.add_descriptor_set();
+		}
+
+		if let Some(upsampled) = &self.upsampled {
+			for image in &upsampled.images {
+				tally.add_image(&**image);
+			}
+			for _ in 0..4 {
+				tally.add_descriptor_set();
+			}
+		}
+	}
+}
+
+/// What a `MeshBatch` keeps between frames for a view besides the batch's own, i.e. the right eye in stereo.
+struct View {
+	gbuffers: GBuffers,
+	last_camera: Option<Transform>,
+	last_projection: Option<ProjectionUniform>,
+	jitter_index: u32,
+}
+
+struct Eye {
+	view: View,
+	cameras: EyeCameras,
+}
+
+/// One rendered view for `MeshBatch::post_commands` to show.
+struct PostView {
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	outline_width: i32,
+}
+
+/// History at the target's resolution, for temporal upsampling.
+#[derive(Clone)]
+struct Upsampled {
+	images: [Arc<AttachmentImage>; 2],
+	descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
+	target_descs: [Arc<DescriptorSet + Send + Sync + 'static>; 2],
+}
+
+/// Identifies a mesh added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(usize);
+
+/// Identifies a light added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LightHandle(usize);
+
+/// Perspective projection for `MeshBatch::set_overlay_projection`. The aspect ratio is the camera's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverlayProjection {
+	/// Horizontal field of view, in degrees.
+	pub fovx: f32,
+	pub znear: f32,
+	pub zfar: f32,
+}
+
+/// Where in a `MeshBatch`'s render pass a hook draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshHook {
+	/// In `MeshRenderPass::subpass_gbuffers`, after every mesh and drawable, so what it draws is lit and outlined.
+	AfterGbuffers,
+	/// In `MeshRenderPass::subpass_transparent`, after transparent drawables.
+	AfterLighting,
+}
+
+/// Identifies a drawable added to a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DrawableHandle(usize);
+
+/// Something drawn into a `MeshBatch`'s gbuffers, so custom geometry is lit and outlined like meshes are, and
+/// optionally over the lit scene for transparent effects.
+pub trait Drawable3D {
+	/// Appends work that has to happen outside the render pass to the frame's primary command buffer, like compute
+	/// culling for the view from `camera`. Called before the render pass of each view, so twice a frame in stereo.
+	/// Defaults to nothing.
+	fn record_before_render_pass(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		_render_pass: &MeshRenderPass,
+		_camera: &Camera,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		Ok(command_buffer)
+	}
+
+	/// Records a secondary command buffer for `MeshRenderPass::subpass_gbuffers`. `camera_desc` matches set 0 of
+	/// `MeshRenderPass::pipeline_gbuffers`. `highlight` is the outline color, if any.
+	fn make_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		highlight: Option<[f32; 4]>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError>;
+
+	/// Records a secondary command buffer for `MeshRenderPass::subpass_transparent`, or `None` to draw nothing there,
+	/// which is the default. `camera_desc` is the same set as for `make_commands`.
+	fn make_transparent_commands(
+		&mut self,
+		_render_pass: &MeshRenderPass,
+		_camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		_queue_family: QueueFamily,
+		_dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		Ok(None)
+	}
+
+	/// Which bucket of `RenderSettings::transparency` the drawable's transparent geometry is blended in. Defaults to
+	/// `TransparentBucket::Drawables`.
+	fn transparent_bucket(&self) -> TransparentBucket {
+		TransparentBucket::Drawables
+	}
+
+	/// Records a secondary command buffer for `MeshRenderPass::subpass_weighted_blended`, called instead of
+	/// `make_transparent_commands` while the drawable's bucket is `TransparencyMode::WeightedBlended`. Defaults to
+	/// `None`, so drawables that don't implement it draw nothing transparent in that mode.
+	fn make_weighted_blended_commands(
+		&mut self,
+		_render_pass: &MeshRenderPass,
+		_camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		_queue_family: QueueFamily,
+		_dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		Ok(None)
+	}
+
+	/// Adds the buffers, images and descriptor sets the drawable holds to `tally`, for `MeshBatch::resource_stats`.
+	/// Defaults to nothing.
+	fn resources(&self, _tally: &mut ResourceTally) {}
+}
+
+fn join_futures(a: Option<Box<GpuFuture>>, b: Option<Box<GpuFuture>>) -> Option<Box<GpuFuture>> {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(Box::new(a.join(b))),
+		(a, b) => a.or(b),
+	}
+}
+
+/// A sub-pixel offset from the (2, 3) Halton sequence, in pixels from the center. `index` starts at 1.
+fn halton_jitter(index: u32) -> [f32; 2] {
+	let halton = |mut index: u32, base: u32| {
+		let (mut fraction, mut result) = (1.0, 0.0);
+		while index > 0 {
+			fraction /= base as f32;
+			result += fraction * (index % base) as f32;
+			index /= base;
+		}
+		result
+	};
+	[halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+}
+
+/// Moves a projection's depth output from the whole depth range into `min..max`.
+fn remap_depth(projection: Vector4<f32>, min: f32, max: f32) -> Vector4<f32> {
+	let scale = max - min;
+	vec4(projection.x, projection.y, projection.z * scale - min, projection.w * scale)
+}
+
+struct MeshEntry {
+	mesh: Mesh,
+	id: StableId,
+	highlight: Option<[f32; 4]>,
+	overlay: bool,
+	snapshots: Option<TransformSnapshots>,
+	motion_trail: Option<MotionTrailState>,
+}
+
+struct DrawableEntry {
+	drawable: Box<Drawable3D>,
+	id: StableId,
+	highlight: Option<[f32; 4]>,
 }
 
 #[derive(Debug, Clone)]
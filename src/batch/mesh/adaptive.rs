@@ -0,0 +1,233 @@
+use crate::batch::mesh::RenderSettings;
+use std::{ collections::VecDeque, time::Duration };
+
+/// Render scales `AdaptiveQuality` steps through, from full resolution down.
+const RENDER_SCALES: [f32; 5] = [1.0, 0.85, 0.75, 0.65, 0.5];
+/// Shadow quality and LOD bias of each step of effects, from full quality down.
+const EFFECT_STEPS: [(ShadowQuality, u32); 4] =
+	[(ShadowQuality::High, 0), (ShadowQuality::Medium, 0), (ShadowQuality::Low, 1), (ShadowQuality::Off, 2)];
+/// A frame counts as over budget once it takes this many percent of it, so timer noise around a budget of exactly the
+/// refresh interval doesn't.
+const OVER_BUDGET_PERCENT: u32 = 110;
+/// Frames ignored after a change, since a new render scale reallocates attachments and resets history.
+const SETTLE_FRAMES: u32 = 10;
+/// Most times the wait before raising quality is doubled by raises that didn't hold.
+const MAX_RAISE_BACKOFF: u32 = 3;
+
+/// An "auto" graphics setting. It watches frame times, steps quality down when frames keep running over budget, and
+/// back up once they've stayed within it for a while. Each step is a `QualityLevel`, ordered by a `QualityPolicy`.
+///
+/// Call `record_frame` once a frame with how long it took, e.g. `Clock::real_delta`, and `apply` the level it returns
+/// to the batch's `RenderSettings`. The crate doesn't pick LODs or draw shadows, so games that do take `lod_bias` and
+/// `shadow_quality` from the same level.
+///
+/// With vsync, frames don't get shorter than the refresh interval however light they are, so quality is raised by
+/// trying the next level once frames have stayed within budget for `raise_after` frames, and stepping back if it
+/// doesn't hold. Each raise that's taken back doubles the wait before the next, at most 3 times, for 8 times the
+/// wait.
+#[derive(Clone, Debug)]
+pub struct AdaptiveQuality {
+	policy: QualityPolicy,
+	budget: Duration,
+	levels: Vec<QualityLevel>,
+	level: usize,
+	/// Whether each of the last `window` frames was over budget, oldest first.
+	recent: VecDeque<bool>,
+	window: usize,
+	raise_after: u32,
+	/// Frames in a row within budget.
+	in_budget: u32,
+	settle: u32,
+	/// Frames since quality was raised, until the raise has held for `raise_after` frames.
+	since_raise: Option<u32>,
+	backoff: u32,
+}
+impl AdaptiveQuality {
+	/// Starts at full quality, aiming for frames no longer than `budget`, e.g. `FramePacer::interval`.
+	pub fn new(policy: QualityPolicy, budget: Duration) -> Self {
+		Self {
+			policy: policy,
+			budget: budget,
+			levels: policy.levels(),
+			level: 0,
+			recent: VecDeque::with_capacity(30),
+			window: 30,
+			raise_after: 300,
+			in_budget: 0,
+			settle: 0,
+			since_raise: None,
+			backoff: 0,
+		}
+	}
+
+	pub fn policy(&self) -> QualityPolicy {
+		self.policy
+	}
+
+	/// Keeps the number of steps below full quality, so a policy changed in a menu doesn't jump to a much heavier
+	/// level.
+	pub fn set_policy(&mut self, policy: QualityPolicy) {
+		self.policy = policy;
+		self.levels = policy.levels();
+		self.level = self.level.min(self.levels.len() - 1);
+	}
+
+	pub fn budget(&self) -> Duration {
+		self.budget
+	}
+
+	pub fn set_budget(&mut self, budget: Duration) {
+		self.budget = budget;
+	}
+
+	/// Quality is lowered when more than a quarter of the last `window` frames ran over budget, and raised after
+	/// `raise_after` frames in a row within it. The defaults are 30 and 300.
+	pub fn set_sustain(&mut self, window: usize, raise_after: u32) {
+		self.window = window.max(1);
+		self.raise_after = raise_after.max(1);
+		while self.recent.len() > self.window {
+			self.recent.pop_front();
+		}
+	}
+
+	pub fn level(&self) -> QualityLevel {
+		self.levels[self.level]
+	}
+
+	/// Steps below full quality, from 0 to `level_count` - 1.
+	pub fn level_index(&self) -> usize {
+		self.level
+	}
+
+	pub fn level_count(&self) -> usize {
+		self.levels.len()
+	}
+
+	/// Counts one frame that took `frame_time`, returning the new level if it changed.
+	pub fn record_frame(&mut self, frame_time: Duration) -> Option<QualityLevel> {
+		if self.settle > 0 {
+			self.settle -= 1;
+			return None;
+		}
+
+		let over = frame_time * 100 > self.budget * OVER_BUDGET_PERCENT;
+		if self.recent.len() == self.window {
+			self.recent.pop_front();
+		}
+		self.recent.push_back(over);
+		self.in_budget = if over { 0 } else { self.in_budget + 1 };
+		if let Some(since_raise) = &mut self.since_raise {
+			*since_raise += 1;
+		}
+
+		let over_count = self.recent.iter().filter(|&&over| over).count();
+		if self.recent.len() == self.window && over_count * 4 > self.window {
+			if self.level + 1 == self.levels.len() {
+				return None;
+			}
+			if self.since_raise.take().is_some() {
+				self.backoff = (self.backoff + 1).min(MAX_RAISE_BACKOFF);
+			}
+			let level = self.level + 1;
+			return Some(self.change_level(level));
+		}
+
+		if self.since_raise.map_or(false, |since_raise| since_raise >= self.raise_after) {
+			self.since_raise = None;
+			self.backoff = 0;
+		}
+		if self.level > 0 && self.in_budget >= self.raise_after << self.backoff {
+			self.since_raise = Some(0);
+			let level = self.level - 1;
+			return Some(self.change_level(level));
+		}
+		None
+	}
+
+	/// Sets the settings the current level controls, leaving the rest.
+	pub fn apply(&self, settings: &mut RenderSettings) {
+		settings.render_scale = self.level().render_scale;
+	}
+
+	/// Forgets the frames recorded so far, e.g. after a loading screen, whose frames don't say anything about the
+	/// level's.
+	pub fn reset(&mut self) {
+		self.recent.clear();
+		self.in_budget = 0;
+		self.since_raise = None;
+	}
+
+	fn change_level(&mut self, level: usize) -> QualityLevel {
+		self.level = level;
+		self.recent.clear();
+		self.in_budget = 0;
+		self.settle = SETTLE_FRAMES;
+		self.levels[level]
+	}
+}
+
+/// Which of an `AdaptiveQuality`'s settings are given up first when frames run over budget.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QualityPolicy {
+	/// Lowers effects all the way before the render scale, for games where a sharp image matters most, like ones
+	/// with fine detail far away.
+	FavorResolution,
+	/// Takes turns lowering effects and the render scale.
+	Balanced,
+	/// Lowers the render scale all the way before effects.
+	FavorEffects,
+}
+impl QualityPolicy {
+	/// Every level the policy steps through, from full quality down.
+	fn levels(self) -> Vec<QualityLevel> {
+		let (mut scale, mut effects) = (0, 0);
+		let mut levels = vec![QualityLevel::new(scale, effects)];
+		while scale + 1 < RENDER_SCALES.len() || effects + 1 < EFFECT_STEPS.len() {
+			let can_lower_effects = effects + 1 < EFFECT_STEPS.len();
+			let lower_effects =
+				match self {
+					QualityPolicy::FavorResolution => can_lower_effects,
+					QualityPolicy::Balanced =>
+						can_lower_effects && (effects <= scale || scale + 1 == RENDER_SCALES.len()),
+					QualityPolicy::FavorEffects => scale + 1 == RENDER_SCALES.len(),
+				};
+			if lower_effects {
+				effects += 1;
+			} else {
+				scale += 1;
+			}
+			levels.push(QualityLevel::new(scale, effects));
+		}
+		levels
+	}
+}
+impl Default for QualityPolicy {
+	fn default() -> Self {
+		QualityPolicy::Balanced
+	}
+}
+
+/// One step of an `AdaptiveQuality`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityLevel {
+	/// For `RenderSettings::render_scale`.
+	pub render_scale: f32,
+	/// Levels coarser than the one distance alone picks, e.g. against `NiceModelLod::distance`. 0 at full quality.
+	pub lod_bias: u32,
+	pub shadow_quality: ShadowQuality,
+}
+impl QualityLevel {
+	fn new(scale: usize, effects: usize) -> Self {
+		let (shadow_quality, lod_bias) = EFFECT_STEPS[effects];
+		Self { render_scale: RENDER_SCALES[scale], lod_bias: lod_bias, shadow_quality: shadow_quality }
+	}
+}
+
+/// How detailed a `QualityLevel` wants the game's shadows, e.g. in shadow map resolution and cascade count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShadowQuality {
+	Off,
+	Low,
+	Medium,
+	High,
+}
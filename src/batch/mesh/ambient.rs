@@ -0,0 +1,168 @@
+use crate::anim::Lerp;
+use crate::color::Color;
+use crate::cpu_pool::{ spawn_cpu, spawn_fs };
+use crate::texture::TextureError;
+use cgmath::{ prelude::*, vec3, Vector3 };
+use futures::prelude::*;
+use image::{ self, DynamicImage, ImageFormat };
+use std::{ f32::consts::PI, fs, path::Path };
+
+/// Grid the sky is sampled on when projecting functions rather than images.
+const SAMPLES: [u32; 2] = [64, 32];
+
+/// Light arriving from every direction, e.g. from the sky, stored as 2nd order spherical harmonics of the irradiance
+/// it gives each surface direction. Unlike a flat ambient term this stays directional, so normal maps remain visible
+/// where no light reaches directly.
+///
+/// Luminances are in cd/m², converted with the batch's exposure like lights are. An overcast sky is around 2000 and
+/// a clear blue one around 5000.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientLight {
+	/// Irradiance coefficients, premultiplied by the basis constants and the cosine lobe.
+	coefficients: [[f32; 3]; 9],
+}
+impl AmbientLight {
+	pub fn none() -> Self {
+		Self { coefficients: [[0.0; 3]; 9] }
+	}
+
+	/// The same light from every direction.
+	pub fn uniform(color: Color, luminance: f32) -> Self {
+		let radiance = color.scaled(luminance).to_linear_rgb();
+		Self::project(|_| radiance, SAMPLES)
+	}
+
+	/// Blends from `ground` straight down to `horizon` and up to `sky`.
+	pub fn gradient(sky: Color, horizon: Color, ground: Color, luminance: f32, up: Vector3<f32>) -> Self {
+		let up = up.normalize();
+		let sky = sky.scaled(luminance).to_linear_rgb();
+		let horizon = horizon.scaled(luminance).to_linear_rgb();
+		let ground = ground.scaled(luminance).to_linear_rgb();
+		Self::project(
+			|dir| {
+				let height = dir.dot(up);
+				let (from, to, t) = if height >= 0.0 { (horizon, sky, height) } else { (horizon, ground, -height) };
+				[lerp(from[0], to[0], t), lerp(from[1], to[1], t), lerp(from[2], to[2], t)]
+			},
+			SAMPLES,
+		)
+	}
+
+	/// Projects an equirectangular environment map, with the top row straight up along -y and the middle column
+	/// facing +z. Colors are sRGB and scaled to `luminance`.
+	pub fn from_image(image: &DynamicImage, luminance: f32) -> Self {
+		let image = image.to_rgba();
+		let (width, height) = image.dimensions();
+		Self::project(
+			|dir| {
+				let (x, y) = equirect_pixel(dir, width, height);
+				let pixel = image.get_pixel(x, y).data;
+				Color::from_srgb8(pixel[0], pixel[1], pixel[2]).scaled(luminance).to_linear_rgb()
+			},
+			[width, height],
+		)
+	}
+
+	/// Loads and projects an environment map like `from_image`, on the crate's worker threads.
+	pub fn from_file<P>(
+		path: P,
+		format: ImageFormat,
+		luminance: f32,
+	) -> impl Future<Output = Result<Self, TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		spawn_fs(move || Ok(fs::read(path)?))
+			.then(move |bytes: Result<Vec<u8>, TextureError>| spawn_cpu(move || {
+				let image = image::load_from_memory_with_format(&bytes?, format)?;
+				Ok(Self::from_image(&image, luminance))
+			}))
+	}
+
+	/// Scaled for the lighting pass, which evaluates the sum without any constants.
+	pub(super) fn uniform_coefficients(&self, scale: f32) -> [[f32; 4]; 9] {
+		let mut ret = [[0.0; 4]; 9];
+		for (out, c) in ret.iter_mut().zip(&self.coefficients) {
+			*out = [c[0] * scale, c[1] * scale, c[2] * scale, 0.0];
+		}
+		ret
+	}
+
+	/// Integrates `radiance` over the sphere, sampled at the centers of an equirectangular grid of `size`.
+	fn project(radiance: impl Fn(Vector3<f32>) -> [f32; 3], size: [u32; 2]) -> Self {
+		let mut sh = [[0.0; 3]; 9];
+		let [width, height] = size;
+		let texel_angle = (2.0 * PI / width as f32) * (PI / height as f32);
+
+		for y in 0..height {
+			let theta = PI * (y as f32 + 0.5) / height as f32;
+			let solid_angle = texel_angle * theta.sin();
+			for x in 0..width {
+				let phi = 2.0 * PI * (x as f32 + 0.5) / width as f32;
+				let dir = vec3(theta.sin() * phi.sin(), -theta.cos(), -theta.sin() * phi.cos());
+				let radiance = radiance(dir);
+				for (coefficient, y) in sh.iter_mut().zip(&basis(dir)) {
+					for c in 0..3 {
+						coefficient[c] += radiance[c] * y * solid_angle;
+					}
+				}
+			}
+		}
+
+		// convolving with the cosine lobe turns radiance into irradiance, as in Ramamoorthi and Hanrahan's "An
+		// Efficient Representation for Irradiance Environment Maps", and the basis constants are folded in so the
+		// shader only multiplies by the polynomials
+		let (band0, band1, band2) = (PI, 2.0 * PI / 3.0, PI / 4.0);
+		let lobe = [band0, band1, band1, band1, band2, band2, band2, band2, band2];
+		let constants = [0.282095, 0.488603, 0.488603, 0.488603, 1.092548, 1.092548, 0.315392, 1.092548, 0.546274];
+		for i in 0..9 {
+			for c in 0..3 {
+				sh[i][c] *= lobe[i] * constants[i];
+			}
+		}
+
+		Self { coefficients: sh }
+	}
+}
+/// Blends the coefficients, which is the same as blending the light itself.
+impl Lerp for AmbientLight {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		let mut coefficients = self.coefficients;
+		for (out, other) in coefficients.iter_mut().zip(&other.coefficients) {
+			*out = Lerp::lerp(*out, *other, t);
+		}
+		Self { coefficients: coefficients }
+	}
+}
+impl Default for AmbientLight {
+	/// A dim uniform 1 cd/m², so surfaces out of direct light aren't fully black.
+	fn default() -> Self {
+		Self::uniform(Color::WHITE, 1.0)
+	}
+}
+
+/// The real spherical harmonics up to the 2nd order, in the order the lighting shader uses.
+fn basis(dir: Vector3<f32>) -> [f32; 9] {
+	let Vector3 { x, y, z } = dir;
+	[
+		0.282095,
+		0.488603 * y,
+		0.488603 * z,
+		0.488603 * x,
+		1.092548 * x * y,
+		1.092548 * y * z,
+		0.315392 * (3.0 * z * z - 1.0),
+		1.092548 * x * z,
+		0.546274 * (x * x - y * y),
+	]
+}
+
+fn equirect_pixel(dir: Vector3<f32>, width: u32, height: u32) -> (u32, u32) {
+	let theta = (-dir.y).max(-1.0).min(1.0).acos();
+	let phi = dir.x.atan2(-dir.z);
+	let u = if phi < 0.0 { phi / (2.0 * PI) + 1.0 } else { phi / (2.0 * PI) };
+	let v = theta / PI;
+	(((u * width as f32) as u32).min(width - 1), ((v * height as f32) as u32).min(height - 1))
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
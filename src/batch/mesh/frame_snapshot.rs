@@ -0,0 +1,186 @@
+use super::{ AmbientLight, Light, LightHandle, MeshHandle };
+use crate::math::Transform;
+use std::{ mem, sync::{ Arc, Mutex } };
+
+/// Draw state for a `MeshBatch`, written on the game thread and applied on the render thread with
+/// `MeshBatch::apply_snapshot`, so the batch is never touched from two threads. Send complete snapshots through
+/// `snapshot_channel`.
+///
+/// Values persist once set, so applying only the newest snapshot still catches up on changes in any skipped ones.
+/// Meshes and lights are still added on the render thread, and their handles sent to the game thread. Each batch
+/// should only have snapshots applied from one `FrameSnapshot`.
+#[derive(Clone, Default)]
+pub struct FrameSnapshot {
+	/// Bumped on every change, and stamped on what changed.
+	revision: u64,
+	meshes: Vec<Option<MeshState>>,
+	lights: Vec<Option<(u64, Light)>>,
+	camera: Option<(u64, CameraState)>,
+	exposure: Option<(u64, f32)>,
+	ambient: Option<(u64, AmbientLight)>,
+}
+impl FrameSnapshot {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn set_mesh_transform(&mut self, handle: MeshHandle, transform: Transform) {
+		let revision = self.bump();
+		let mesh = self.mesh(handle);
+		mesh.revision = revision;
+		mesh.transform = Some(transform);
+	}
+
+	/// Like `MeshBatch::set_highlighted`.
+	pub fn set_mesh_highlighted(&mut self, handle: MeshHandle, color: Option<[f32; 4]>) {
+		let revision = self.bump();
+		let mesh = self.mesh(handle);
+		mesh.revision = revision;
+		mesh.highlight = color;
+	}
+
+	/// Like `MeshBatch::set_overlay`.
+	pub fn set_mesh_overlay(&mut self, handle: MeshHandle, overlay: bool) {
+		let revision = self.bump();
+		let mesh = self.mesh(handle);
+		mesh.revision = revision;
+		mesh.overlay = overlay;
+	}
+
+	/// Replaces the light wholesale, like assigning through `MeshBatch::light_mut`.
+	pub fn set_light(&mut self, handle: LightHandle, light: Light) {
+		let revision = self.bump();
+		if self.lights.len() <= handle.0 {
+			self.lights.resize(handle.0 + 1, None);
+		}
+		self.lights[handle.0] = Some((revision, light));
+	}
+
+	/// Moves the camera passed to `apply_snapshot`. The scale is ignored, like with `Camera::set_transform`.
+	pub fn set_camera(&mut self, transform: Transform) {
+		let revision = self.bump();
+		let fovx = self.camera.as_ref().and_then(|&(_, camera)| camera.fovx);
+		self.camera = Some((revision, CameraState { transform: transform, fovx: fovx }));
+	}
+
+	/// Horizontal field of view, in degrees. Takes effect once the camera has been placed with `set_camera`.
+	pub fn set_camera_fovx(&mut self, fovx: f32) {
+		let revision = self.bump();
+		if let Some((camera_revision, camera)) = self.camera.as_mut() {
+			*camera_revision = revision;
+			camera.fovx = Some(fovx);
+		}
+	}
+
+	pub fn set_exposure(&mut self, ev100: f32) {
+		self.exposure = Some((self.bump(), ev100));
+	}
+
+	pub fn set_ambient(&mut self, ambient: AmbientLight) {
+		self.ambient = Some((self.bump(), ambient));
+	}
+
+	pub(super) fn revision(&self) -> u64 {
+		self.revision
+	}
+
+	/// Mesh states changed after `revision`, by handle index.
+	pub(super) fn meshes_since(&self, revision: u64) -> impl Iterator<Item = (usize, &MeshState)> {
+		self.meshes.iter()
+			.enumerate()
+			.filter_map(move |(i, mesh)| mesh.as_ref().filter(|mesh| mesh.revision > revision).map(|mesh| (i, mesh)))
+	}
+
+	pub(super) fn lights_since(&self, revision: u64) -> impl Iterator<Item = (usize, &Light)> {
+		self.lights.iter()
+			.enumerate()
+			.filter_map(move |(i, light)| match light {
+				Some((light_revision, light)) if *light_revision > revision => Some((i, light)),
+				_ => None,
+			})
+	}
+
+	pub(super) fn camera_since(&self, revision: u64) -> Option<CameraState> {
+		since(&self.camera, revision)
+	}
+
+	pub(super) fn exposure_since(&self, revision: u64) -> Option<f32> {
+		since(&self.exposure, revision)
+	}
+
+	pub(super) fn ambient_since(&self, revision: u64) -> Option<AmbientLight> {
+		since(&self.ambient, revision)
+	}
+
+	fn bump(&mut self) -> u64 {
+		self.revision += 1;
+		self.revision
+	}
+
+	fn mesh(&mut self, handle: MeshHandle) -> &mut MeshState {
+		if self.meshes.len() <= handle.0 {
+			self.meshes.resize(handle.0 + 1, None);
+		}
+		self.meshes[handle.0].get_or_insert_with(MeshState::default)
+	}
+}
+
+/// Creates the two halves that pass snapshots from the game thread to the render thread. Each half keeps a snapshot of
+/// its own and they're swapped through a shared slot, so neither waits on the other for longer than a swap.
+pub fn snapshot_channel() -> (SnapshotSender, SnapshotReceiver) {
+	let slot = Arc::new(Mutex::new(Slot { snapshot: FrameSnapshot::new(), fresh: false }));
+	(SnapshotSender { slot: slot.clone() }, SnapshotReceiver { slot: slot, current: FrameSnapshot::new() })
+}
+
+pub struct SnapshotSender {
+	slot: Arc<Mutex<Slot>>,
+}
+impl SnapshotSender {
+	/// Makes `snapshot` the latest complete one, replacing any the render thread hasn't received yet.
+	pub fn publish(&self, snapshot: &FrameSnapshot) {
+		let mut slot = self.slot.lock().unwrap();
+		slot.snapshot.clone_from(snapshot);
+		slot.fresh = true;
+	}
+}
+
+pub struct SnapshotReceiver {
+	slot: Arc<Mutex<Slot>>,
+	current: FrameSnapshot,
+}
+impl SnapshotReceiver {
+	/// The snapshot published since the last call, if any.
+	pub fn receive(&mut self) -> Option<&FrameSnapshot> {
+		let mut slot = self.slot.lock().unwrap();
+		if !slot.fresh {
+			return None;
+		}
+
+		mem::swap(&mut self.current, &mut slot.snapshot);
+		slot.fresh = false;
+		Some(&self.current)
+	}
+}
+
+#[derive(Clone, Default)]
+pub(super) struct MeshState {
+	revision: u64,
+	pub(super) transform: Option<Transform>,
+	pub(super) highlight: Option<[f32; 4]>,
+	pub(super) overlay: bool,
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct CameraState {
+	pub(super) transform: Transform,
+	pub(super) fovx: Option<f32>,
+}
+
+struct Slot {
+	snapshot: FrameSnapshot,
+	fresh: bool,
+}
+
+fn since<T: Copy>(value: &Option<(u64, T)>, revision: u64) -> Option<T> {
+	value.as_ref().filter(|&&(value_revision, _)| value_revision > revision).map(|&(_, value)| value)
+}
@@ -0,0 +1,343 @@
+use crate::batch::mesh::{ AmbientLight, LightProbeGrid };
+use crate::color::Color;
+use crate::texture::Texture;
+use cgmath::{ prelude::*, perspective, Matrix4, Point3, Rad, vec3, Vector3 };
+use std::{ f32::consts::PI, sync::Arc };
+use vulkano::{ impl_vertex, image::ImageViewAccess };
+
+/// Directional lights beyond this many are ignored.
+pub const MAX_LIGHTS: usize = 16;
+/// Point and spot lights beyond this many are ignored. They're drawn as volumes around them, so each only costs the
+/// pixels it reaches.
+pub const MAX_LOCAL_LIGHTS: usize = 64;
+/// Distinct cookie textures usable in one frame. Spot lights past this limit are drawn without their cookie.
+pub const MAX_LIGHT_COOKIES: usize = 4;
+
+const LIGHT_TYPE_DIRECTIONAL: f32 = 0.0;
+const LIGHT_TYPE_POINT: f32 = 1.0;
+const LIGHT_TYPE_SPOT: f32 = 2.0;
+const COOKIE_NEAR: f32 = 0.01;
+const SPHERE_PROXY_STACKS: u32 = 6;
+const SPHERE_PROXY_SLICES: u32 = 12;
+const CONE_PROXY_SIDES: u32 = 12;
+/// Spot lights wider than this are drawn with a sphere, since their cone would be nearly as big and much flatter.
+const CONE_PROXY_MAX_ANGLE: f32 = PI / 3.0;
+
+#[derive(Clone)]
+pub enum Light {
+	Directional(DirectionalLight),
+	Point(PointLight),
+	Spot(SpotLight),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+	/// The direction light travels in, away from the light.
+	pub direction: Vector3<f32>,
+	/// Usually no brighter than 1 in any channel, with `illuminance` setting the brightness. `Color::from_temperature`
+	/// gives the color of common light sources.
+	pub color: Color,
+	/// Illuminance on a surface facing the light, in lux. Direct sunlight is around 100000 lux and an overcast sky
+	/// around 1000.
+	pub illuminance: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+	pub position: Vector3<f32>,
+	/// Like `DirectionalLight::color`.
+	pub color: Color,
+	/// Luminous flux, in lumens. A 60 W incandescent bulb gives around 800.
+	pub lumens: f32,
+	/// Light falls off with the square of the distance, smoothly windowed to reach 0 here.
+	pub radius: f32,
+}
+
+#[derive(Clone)]
+pub struct SpotLight {
+	pub position: Vector3<f32>,
+	pub direction: Vector3<f32>,
+	/// Like `DirectionalLight::color`.
+	pub color: Color,
+	/// Luminous flux, in lumens, spread over the outer cone, so narrowing the cone makes it brighter.
+	pub lumens: f32,
+	/// Like `PointLight::radius`.
+	pub radius: f32,
+	/// Half angle of the fully lit part of the cone.
+	pub inner_angle: Rad<f32>,
+	/// Half angle where the light fades out completely.
+	pub outer_angle: Rad<f32>,
+	/// Texture projected along the cone, multiplying the light color. The top of the texture points along `up`.
+	pub cookie: Option<Arc<ImageViewAccess + Send + Sync + 'static>>,
+	pub up: Vector3<f32>,
+}
+impl SpotLight {
+	pub fn new(
+		position: Vector3<f32>,
+		direction: Vector3<f32>,
+		color: Color,
+		lumens: f32,
+		radius: f32,
+		inner_angle: Rad<f32>,
+		outer_angle: Rad<f32>,
+	) -> Self {
+		Self {
+			position: position,
+			direction: direction,
+			color: color,
+			lumens: lumens,
+			radius: radius,
+			inner_angle: inner_angle,
+			outer_angle: outer_angle,
+			cookie: None,
+			up: vec3(0.0, -1.0, 0.0),
+		}
+	}
+
+	/// Luminous intensity along the cone, in candela.
+	pub fn candela(&self) -> f32 {
+		// solid angle of the outer cone
+		self.lumens / (2.0 * PI * (1.0 - self.outer_angle.0.cos()).max(0.0001))
+	}
+
+	pub fn with_cookie(self, texture: &Texture) -> Self {
+		Self { cookie: Some(texture.image().clone()), ..self }
+	}
+
+	/// Transforms world space positions to the cookie's clip space.
+	pub fn cookie_matrix(&self) -> Matrix4<f32> {
+		let direction = self.direction.normalize();
+		let up =
+			if direction.cross(self.up).magnitude2() > 0.0001 {
+				self.up
+			} else if direction.x.abs() < 0.9 {
+				Vector3::unit_x()
+			} else {
+				Vector3::unit_z()
+			};
+		let view = Matrix4::look_at_dir(Point3::from_vec(self.position), direction, up);
+		let proj = perspective(self.outer_angle * 2.0, 1.0, COOKIE_NEAR, self.radius.max(COOKIE_NEAR * 2.0));
+		proj * view
+	}
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct LightUniform {
+	position_type: [f32; 4],
+	direction_radius: [f32; 4],
+	color_cookie: [f32; 4],
+	cone: [f32; 4],
+	cookie_matrix: [[f32; 4]; 4],
+}
+impl LightUniform {
+	const NONE: LightUniform =
+		LightUniform {
+			position_type: [0.0; 4],
+			direction_radius: [0.0; 4],
+			color_cookie: [0.0, 0.0, 0.0, -1.0],
+			cone: [0.0; 4],
+			cookie_matrix: [[0.0; 4]; 4],
+		};
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct LightsUniform {
+	count: [u32; 4],
+	ambient: [[f32; 4]; 9],
+	/// w is the exposure scale for the probes' coefficients.
+	probe_origin: [f32; 4],
+	probe_spacing: [f32; 4],
+	/// w is 1 while there's a grid.
+	probe_counts: [u32; 4],
+	lights: [LightUniform; MAX_LIGHTS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(super) struct LocalLightsUniform {
+	lights: [LightUniform; MAX_LOCAL_LIGHTS],
+}
+
+/// Geometry a point or spot light's volume is drawn with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum LightProxy {
+	Sphere,
+	Cone,
+}
+
+pub(super) struct PackedLights {
+	/// Directional lights and ambient light, for the full screen lighting pass.
+	pub(super) lights: LightsUniform,
+	pub(super) local_lights: LocalLightsUniform,
+	/// The proxy each of the local lights is drawn with, in order.
+	pub(super) proxies: Vec<LightProxy>,
+	/// Cookie textures in the order the light volume shader indexes them.
+	pub(super) cookies: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LightVolumeVertex { position: [f32; 3] }
+impl_vertex!(LightVolumeVertex, position);
+
+impl PointLight {
+	/// Luminous intensity in every direction, in candela.
+	pub fn candela(&self) -> f32 {
+		self.lumens / (4.0 * PI)
+	}
+}
+
+/// Scale from illuminance to the values the lighting pass outputs at exposure `ev100`. A white surface lit with the
+/// illuminance the exposure is metered for comes out at 1.
+pub(super) fn exposure_scale(ev100: f32) -> f32 {
+	// lambertian 1/pi, then the saturation based exposure from ISO 2720, which maps luminance 1.2 * 2^ev100 to 1
+	1.0 / (PI * 1.2 * 2f32.powf(ev100))
+}
+
+/// Packs `lights` for the full screen lighting pass and the light volumes drawn after it. `scale` is from
+/// `exposure_scale`. Only the layout of `probes` is packed; the probes themselves are bound as a buffer of their own.
+pub(super) fn pack_lights<'a>(
+	lights: impl Iterator<Item = &'a Light>,
+	ambient: &AmbientLight,
+	probes: Option<&LightProbeGrid>,
+	scale: f32,
+) -> PackedLights {
+	let (probe_origin, probe_spacing, probe_counts): ([f32; 4], [f32; 4], [u32; 4]) =
+		match probes {
+			Some(probes) => {
+				let (origin, spacing, counts) = (probes.origin(), probes.spacing(), probes.counts());
+				(origin.extend(scale).into(), spacing.extend(0.0).into(), [counts[0], counts[1], counts[2], 1])
+			},
+			None => ([0.0; 4], [0.0; 4], [0; 4]),
+		};
+
+	let mut packed =
+		PackedLights {
+			lights:
+				LightsUniform {
+					count: [0; 4],
+					ambient: ambient.uniform_coefficients(scale),
+					probe_origin: probe_origin,
+					probe_spacing: probe_spacing,
+					probe_counts: probe_counts,
+					lights: [LightUniform::NONE; MAX_LIGHTS],
+				},
+			local_lights: LocalLightsUniform { lights: [LightUniform::NONE; MAX_LOCAL_LIGHTS] },
+			proxies: vec![],
+			cookies: vec![],
+		};
+
+	for light in lights {
+		let local = packed.proxies.len();
+		match light {
+			Light::Directional(light) => {
+				let count = packed.lights.count[0] as usize;
+				if count == MAX_LIGHTS {
+					continue;
+				}
+
+				let direction = light.direction.normalize();
+				packed.lights.count[0] += 1;
+				packed.lights.lights[count] =
+					LightUniform {
+						position_type: [0.0, 0.0, 0.0, LIGHT_TYPE_DIRECTIONAL],
+						direction_radius: [direction.x, direction.y, direction.z, 0.0],
+						color_cookie: color_cookie(light.color, light.illuminance * scale, None),
+						..LightUniform::NONE
+					};
+			},
+			Light::Point(_) | Light::Spot(_) if local == MAX_LOCAL_LIGHTS => (),
+			Light::Point(light) => {
+				packed.proxies.push(LightProxy::Sphere);
+				packed.local_lights.lights[local] =
+					LightUniform {
+						position_type: [light.position.x, light.position.y, light.position.z, LIGHT_TYPE_POINT],
+						direction_radius: [0.0, 0.0, 0.0, light.radius],
+						color_cookie: color_cookie(light.color, light.candela() * scale, None),
+						..LightUniform::NONE
+					};
+			},
+			Light::Spot(light) => {
+				let direction = light.direction.normalize();
+				let cookies = &mut packed.cookies;
+				let cookie =
+					light.cookie.as_ref().and_then(|cookie| {
+						match cookies.iter().position(|other| Arc::ptr_eq(other, cookie)) {
+							Some(index) => Some(index),
+							None if cookies.len() < MAX_LIGHT_COOKIES => {
+								cookies.push(cookie.clone());
+								Some(cookies.len() - 1)
+							},
+							None => None,
+						}
+					});
+				let proxy =
+					if light.outer_angle.0 < CONE_PROXY_MAX_ANGLE { LightProxy::Cone } else { LightProxy::Sphere };
+
+				packed.proxies.push(proxy);
+				packed.local_lights.lights[local] =
+					LightUniform {
+						position_type: [light.position.x, light.position.y, light.position.z, LIGHT_TYPE_SPOT],
+						direction_radius: [direction.x, direction.y, direction.z, light.radius],
+						color_cookie: color_cookie(light.color, light.candela() * scale, cookie),
+						cone: [
+							light.inner_angle.0.cos(),
+							light.outer_angle.0.cos(),
+							if proxy == LightProxy::Cone { 1.0 } else { 0.0 },
+							0.0,
+						],
+						cookie_matrix: light.cookie_matrix().into(),
+					};
+			},
+		}
+	}
+
+	packed
+}
+
+/// A unit sphere as a triangle list, pushed out so its flat faces still enclose the round sphere.
+pub(super) fn sphere_proxy() -> Vec<LightVolumeVertex> {
+	let scale = 1.0 / ((PI / SPHERE_PROXY_SLICES as f32).cos() * (PI / (2 * SPHERE_PROXY_STACKS) as f32).cos());
+	let point = |stack: u32, slice: u32| {
+		let polar = PI * stack as f32 / SPHERE_PROXY_STACKS as f32;
+		let azimuth = 2.0 * PI * slice as f32 / SPHERE_PROXY_SLICES as f32;
+		LightVolumeVertex {
+			position: [polar.sin() * azimuth.cos() * scale, polar.sin() * azimuth.sin() * scale, polar.cos() * scale],
+		}
+	};
+
+	let mut vertices = vec![];
+	for stack in 0..SPHERE_PROXY_STACKS {
+		for slice in 0..SPHERE_PROXY_SLICES {
+			let (a, b) = (point(stack, slice), point(stack + 1, slice));
+			let (c, d) = (point(stack + 1, slice + 1), point(stack, slice + 1));
+			vertices.extend_from_slice(&[a, b, c, a, c, d]);
+		}
+	}
+	vertices
+}
+
+/// A cone with its apex at the origin, opening along z to a base of radius 1 at z = 1, as a triangle list. Pushed out
+/// like `sphere_proxy`.
+pub(super) fn cone_proxy() -> Vec<LightVolumeVertex> {
+	let scale = 1.0 / (PI / CONE_PROXY_SIDES as f32).cos();
+	let rim = |side: u32| {
+		let angle = 2.0 * PI * side as f32 / CONE_PROXY_SIDES as f32;
+		LightVolumeVertex { position: [angle.cos() * scale, angle.sin() * scale, 1.0] }
+	};
+	let apex = LightVolumeVertex { position: [0.0, 0.0, 0.0] };
+	let base = LightVolumeVertex { position: [0.0, 0.0, 1.0] };
+
+	let mut vertices = vec![];
+	for side in 0..CONE_PROXY_SIDES {
+		vertices.extend_from_slice(&[apex, rim(side), rim(side + 1), base, rim(side + 1), rim(side)]);
+	}
+	vertices
+}
+
+/// The shader falls off candela with the squared distance, giving lux.
+fn color_cookie(color: Color, intensity: f32, cookie: Option<usize>) -> [f32; 4] {
+	let [r, g, b] = color.scaled(intensity).to_linear_rgb();
+	[r, g, b, cookie.map_or(-1.0, |i| i as f32)]
+}
@@ -0,0 +1,497 @@
+use crate::batch::mesh::MeshRenderPass;
+use crate::color::Color;
+use crate::cpu_pool::{ execute_future, GpuFutureFuture };
+use crate::stats::{ self, Resource, ResourceTally, Tracked };
+use crate::texture::{ ImageFormat, ImmutableTexture, Texture, TextureError };
+use cgmath::{ vec2, Vector2, Vector3 };
+use futures::{ FutureExt, prelude::*, stream };
+use log::{ warn, log };
+use std::{
+	collections::{ HashMap, hash_map::DefaultHasher },
+	fs,
+	hash::Hasher,
+	mem,
+	path::{ Path, PathBuf },
+	sync::{ Arc, Mutex, Weak },
+	time::SystemTime,
+};
+use vulkano::{
+	buffer::{ CpuBufferPool, cpu_pool::CpuBufferPoolSubbuffer },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	device::Queue,
+	image::ImageViewAccess,
+	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
+};
+
+/// Everything that defines a material. Materials with the same values and texture contents share GPU resources, even
+/// when the textures are copies under different paths.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialDesc {
+	pub light_penetration: u8,
+	pub subsurface_scattering: u8,
+	pub emissive_brightness: u16,
+	/// Alpha is ignored.
+	pub base_color: Color,
+	pub texture1: Option<PathBuf>,
+	pub texture2: Option<PathBuf>,
+	/// Height map for parallax occlusion mapping, linear, read from the red channel. White is the surface and black is
+	/// `height_scale` below it.
+	pub texture3: Option<PathBuf>,
+	/// Depth of the height map's black, in texture coordinates. 0 turns parallax mapping off.
+	pub height_scale: f32,
+	/// Height map samples for views straight at the surface. Grazing views take more, up to `parallax_max_samples`.
+	pub parallax_min_samples: u8,
+	pub parallax_max_samples: u8,
+	/// Baked vertex positions to play back instead of the mesh's own, see `Mesh::set_vertex_animation_time`.
+	pub vertex_animation: Option<VertexAnimation>,
+}
+impl Default for MaterialDesc {
+	fn default() -> Self {
+		Self {
+			light_penetration: 0,
+			subsurface_scattering: 0,
+			emissive_brightness: 0,
+			base_color: Color::WHITE,
+			texture1: None,
+			texture2: None,
+			texture3: None,
+			height_scale: 0.0,
+			parallax_min_samples: 8,
+			parallax_max_samples: 32,
+			vertex_animation: None,
+		}
+	}
+}
+
+/// A vertex animation texture: object space positions for every vertex in every frame, e.g. baked from a cloth or
+/// destruction simulation. Each frame takes the same number of rows, with the texel for vertex `i` at column
+/// `i % width` of the frame's `i / width`th row. Colors map linearly from `bounds_min` to `bounds_max`.
+///
+/// Normals aren't animated, and culling still uses the mesh's unanimated bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexAnimation {
+	/// Linear PNG. Its height has to be a multiple of `frame_count`.
+	pub texture: PathBuf,
+	pub frame_count: u32,
+	pub fps: f32,
+	pub bounds_min: Vector3<f32>,
+	pub bounds_max: Vector3<f32>,
+	/// Wraps from the last frame back to the first, otherwise playback holds the last frame.
+	pub looping: bool,
+}
+
+/// Moves a material's texture coordinates: scaled, then rotated about the origin, then offset. Applied before parallax
+/// mapping, to every texture of the material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvTransform {
+	pub offset: Vector2<f32>,
+	pub scale: Vector2<f32>,
+	/// Counterclockwise, in radians.
+	pub rotation: f32,
+}
+impl UvTransform {
+	pub fn identity() -> Self {
+		Self { offset: vec2(0.0, 0.0), scale: vec2(1.0, 1.0), rotation: 0.0 }
+	}
+
+	/// Columns of the 2x2 matrix that scales and rotates.
+	fn matrix(&self) -> [f32; 4] {
+		let (sin, cos) = self.rotation.sin_cos();
+		[cos * self.scale.x, sin * self.scale.x, -sin * self.scale.y, cos * self.scale.y]
+	}
+}
+impl Default for UvTransform {
+	fn default() -> Self {
+		Self::identity()
+	}
+}
+
+/// Shared reference to a material registered with a `DeviceCtx`. Cheap to clone.
+#[derive(Clone)]
+pub struct MaterialHandle {
+	inner: Arc<MaterialShared>,
+}
+impl MaterialHandle {
+	pub fn desc(&self) -> &MaterialDesc {
+		&self.inner.desc
+	}
+
+	pub fn ptr_eq(&self, other: &MaterialHandle) -> bool {
+		Arc::ptr_eq(&self.inner, &other.inner)
+	}
+
+	/// Textures that failed to load, most recent attempt only. A missing albedo texture shows as a checkerboard.
+	pub fn texture_errors(&self) -> Vec<TextureLoadError> {
+		self.inner.state.lock().unwrap().errors.clone()
+	}
+
+	/// Tries loading every texture in `texture_errors` again, e.g. once a file is restored or a network share is back.
+	/// The errors are cleared until the new attempt finishes.
+	pub fn retry_missing_textures(&self) {
+		let errors = mem::replace(&mut self.inner.state.lock().unwrap().errors, vec![]);
+		if !errors.is_empty() {
+			load_textures(&self.inner, errors.into_iter().map(|err| err.slot).collect());
+		}
+	}
+
+	pub fn uv_transform(&self) -> UvTransform {
+		self.inner.state.lock().unwrap().uv_transform
+	}
+
+	/// Moves the material's textures, e.g. every frame to scroll a conveyor belt or flowing water, or once to tile
+	/// them differently than the model was exported with. Every mesh sharing the material moves with it.
+	pub fn set_uv_transform(&self, uv_transform: UvTransform) -> Result<(), DeviceMemoryAllocError> {
+		let mut state = self.inner.state.lock().unwrap();
+		let uniform =
+			MaterialUniform { uv_offset: uv_transform.offset.into(), uv_matrix: uv_transform.matrix(), ..state.values };
+		state.uniform = stats::track(Resource::Subbuffer, self.inner.uniform_pool.next(uniform)?);
+		state.values = uniform;
+		state.uv_transform = uv_transform;
+		state.descriptor_set = make_descriptor_set(&self.inner.render_pass, &state.uniform, &state.images);
+		Ok(())
+	}
+
+	pub(crate) fn descriptor_set(&self) -> Arc<DescriptorSet + Send + Sync + 'static> {
+		self.inner.state.lock().unwrap().descriptor_set.clone()
+	}
+
+	/// Counted once however many meshes share the material, by its uniform buffer.
+	pub(crate) fn resources(&self, tally: &mut ResourceTally) {
+		let state = self.inner.state.lock().unwrap();
+		if tally.add_buffer(&state.uniform) {
+			for image in &state.images {
+				tally.add_image(&**image);
+			}
+			tally.add_descriptor_set();
+		}
+	}
+}
+
+/// A material texture that couldn't be loaded.
+#[derive(Clone, Debug)]
+pub struct TextureLoadError {
+	pub path: PathBuf,
+	pub error: Arc<TextureError>,
+	slot: usize,
+}
+
+struct MaterialShared {
+	desc: MaterialDesc,
+	queue: Arc<Queue>,
+	render_pass: Arc<MeshRenderPass>,
+	uniform_pool: CpuBufferPool<MaterialUniform>,
+	state: Mutex<MaterialState>,
+}
+impl MaterialShared {
+	fn texture_path(&self, slot: usize) -> Option<&PathBuf> {
+		match slot {
+			0 => self.desc.texture1.as_ref(),
+			1 => self.desc.texture2.as_ref(),
+			2 => self.desc.texture3.as_ref(),
+			_ => self.desc.vertex_animation.as_ref().map(|animation| &animation.texture),
+		}
+	}
+
+	fn finish_loading(&self, results: Vec<(usize, TextureResult)>) {
+		let mut state = self.state.lock().unwrap();
+		let mut values = state.values;
+		for (slot, result) in results {
+			match result {
+				Ok(image) => {
+					if slot == VERTEX_ANIMATION_SLOT {
+						values.flags |= MATERIAL_VERTEX_ANIMATION;
+					}
+					state.images[slot] = image;
+				},
+				Err(err) => {
+					let path = self.texture_path(slot).unwrap().clone();
+					warn!("failed to load texture {}: {:?}", path.display(), err);
+
+					// a broken normal or height map is better left flat than checkered
+					if slot == 0 {
+						state.images[0] = self.render_pass.shaders.missing_texture.clone();
+					}
+					state.errors.push(TextureLoadError { path: path, error: Arc::new(err), slot: slot });
+				},
+			}
+		}
+
+		if values.flags != state.values.flags {
+			match self.uniform_pool.next(values) {
+				Ok(uniform) => {
+					state.uniform = stats::track(Resource::Subbuffer, uniform);
+					state.values = values;
+				},
+				Err(err) => warn!("failed to start vertex animation: {:?}", err),
+			}
+		}
+		state.descriptor_set = make_descriptor_set(&self.render_pass, &state.uniform, &state.images);
+	}
+}
+
+type TextureResult = Result<Arc<ImageViewAccess + Send + Sync + 'static>, TextureError>;
+
+struct MaterialState {
+	/// Replaced along with the descriptor set when the uv transform changes, so frames in flight keep the old one.
+	uniform: Tracked<CpuBufferPoolSubbuffer<MaterialUniform, Arc<StdMemoryPool>>>,
+	values: MaterialUniform,
+	uv_transform: UvTransform,
+	images: [Arc<ImageViewAccess + Send + Sync + 'static>; 4],
+	descriptor_set: Arc<DescriptorSet + Send + Sync + 'static>,
+	errors: Vec<TextureLoadError>,
+}
+
+#[derive(Default)]
+pub(crate) struct MaterialRegistry {
+	materials: Mutex<HashMap<MaterialKey, Weak<MaterialShared>>>,
+	/// Content keys of texture files by path, valid while the file's modification time is the same.
+	texture_keys: Mutex<HashMap<PathBuf, (SystemTime, TextureKey)>>,
+}
+impl MaterialRegistry {
+	/// Returns the existing material matching `desc`, or creates it. Textures load in the background, and the defaults
+	/// are used until they're ready.
+	pub(crate) fn get(
+		&self,
+		queue: &Arc<Queue>,
+		render_pass: &Arc<MeshRenderPass>,
+		desc: MaterialDesc,
+	) -> Result<MaterialHandle, DeviceMemoryAllocError> {
+		let desc =
+			MaterialDesc {
+				texture1: desc.texture1.map(|path| fs::canonicalize(&path).unwrap_or(path)),
+				texture2: desc.texture2.map(|path| fs::canonicalize(&path).unwrap_or(path)),
+				texture3: desc.texture3.map(|path| fs::canonicalize(&path).unwrap_or(path)),
+				vertex_animation: desc.vertex_animation.map(|animation| VertexAnimation {
+					texture: fs::canonicalize(&animation.texture).unwrap_or(animation.texture),
+					..animation
+				}),
+				..desc
+			};
+		let key = MaterialKey::new(render_pass, &desc, |path| self.texture_key(path));
+
+		let mut materials = self.materials.lock().unwrap();
+		if let Some(inner) = materials.get(&key).and_then(|material| material.upgrade()) {
+			return Ok(MaterialHandle { inner: inner });
+		}
+		materials.retain(|_, material| material.upgrade().is_some());
+
+		// host visible, so there's no upload to wait on when the material is shared by a mesh loaded later
+		let uniform_pool = CpuBufferPool::uniform_buffer(queue.device().clone());
+		let uv_transform = UvTransform::identity();
+		let animation = desc.vertex_animation.as_ref();
+		let looping = animation.map_or(false, |animation| animation.looping);
+		let values =
+			MaterialUniform {
+				light_penetration: desc.light_penetration as u32,
+				subsurface_scattering: desc.subsurface_scattering as u32,
+				emissive_brightness: desc.emissive_brightness as u32,
+				_padding: 0,
+				base_color: desc.base_color.to_linear_rgb(),
+				height_scale: desc.height_scale,
+				parallax_samples: [desc.parallax_min_samples as u32, desc.parallax_max_samples as u32],
+				uv_offset: uv_transform.offset.into(),
+				uv_matrix: uv_transform.matrix(),
+				// playback starts once the texture is loaded, until then the mesh shows its own positions
+				flags: if looping { MATERIAL_VERTEX_ANIMATION_LOOP } else { 0 },
+				vertex_animation_frame_count: animation.map_or(1, |animation| animation.frame_count.max(1)),
+				_padding2: [0; 2],
+				vertex_animation_min: animation.map_or([0.0; 3], |animation| animation.bounds_min.into()),
+				vertex_animation_fps: animation.map_or(0.0, |animation| animation.fps),
+				vertex_animation_max: animation.map_or([0.0; 3], |animation| animation.bounds_max.into()),
+			};
+		let uniform = stats::track(Resource::Subbuffer, uniform_pool.next(values)?);
+
+		// white is a flat height map. vertex animation stays off until its texture is loaded, so that default is unused
+		let images: [Arc<ImageViewAccess + Send + Sync + 'static>; 4] =
+			[
+				render_pass.shaders.texture1_default.clone(),
+				render_pass.shaders.texture2_default.clone(),
+				render_pass.shaders.white_pixel.clone(),
+				render_pass.shaders.black_pixel.clone(),
+			];
+		let inner =
+			Arc::new(MaterialShared {
+				state: Mutex::new(MaterialState {
+					descriptor_set: make_descriptor_set(render_pass, &uniform, &images),
+					uniform: uniform,
+					values: values,
+					uv_transform: uv_transform,
+					images: images,
+					errors: vec![],
+				}),
+				desc: desc,
+				queue: queue.clone(),
+				render_pass: render_pass.clone(),
+				uniform_pool: uniform_pool,
+			});
+		materials.insert(key, Arc::downgrade(&inner));
+
+		let slots = (0..4).filter(|&slot| inner.texture_path(slot).is_some()).collect::<Vec<_>>();
+		if !slots.is_empty() {
+			load_textures(&inner, slots);
+		}
+
+		Ok(MaterialHandle { inner: inner })
+	}
+
+	/// Hashes the file at `path`, reusing the last hash while the file hasn't been modified.
+	fn texture_key(&self, path: &Path) -> TextureKey {
+		let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+		let mut texture_keys = self.texture_keys.lock().unwrap();
+		if let (Some(modified), Some((hashed, key))) = (modified, texture_keys.get(path)) {
+			if modified == *hashed {
+				return key.clone();
+			}
+		}
+
+		let key =
+			match fs::read(path) {
+				Ok(data) => {
+					let mut hasher = DefaultHasher::new();
+					hasher.write(&data);
+					TextureKey::Contents { len: data.len() as u64, hash: hasher.finish() }
+				},
+				Err(_) => TextureKey::Unreadable(path.to_owned()),
+			};
+		if let Some(modified) = modified {
+			texture_keys.insert(path.to_owned(), (modified, key.clone()));
+		}
+		key
+	}
+}
+
+fn make_descriptor_set(
+	render_pass: &MeshRenderPass,
+	uniform: &Tracked<CpuBufferPoolSubbuffer<MaterialUniform, Arc<StdMemoryPool>>>,
+	images: &[Arc<ImageViewAccess + Send + Sync + 'static>; 4],
+) -> Arc<DescriptorSet + Send + Sync + 'static> {
+	Arc::new(stats::track(
+		Resource::DescriptorSet,
+		PersistentDescriptorSet::start(render_pass.pipeline_gbuffers.clone(), 2)
+			.add_buffer(uniform.clone())
+			.unwrap()
+			.add_sampled_image(images[0].clone(), render_pass.shaders.sampler.clone())
+			.unwrap()
+			.add_sampled_image(images[1].clone(), render_pass.shaders.sampler.clone())
+			.unwrap()
+			.add_sampled_image(images[2].clone(), render_pass.shaders.sampler.clone())
+			.unwrap()
+			.add_sampled_image(images[3].clone(), render_pass.shaders.sampler.clone())
+			.unwrap()
+			.build()
+			.unwrap(),
+	))
+}
+
+/// Loads the textures in `slots` in the background, then updates the material's descriptor set once they're all done.
+fn load_textures(material: &Arc<MaterialShared>, slots: Vec<usize>) {
+	let futures =
+		slots.into_iter()
+			.map(|slot| (slot, load_texture(&material.queue, material.texture_path(slot).unwrap().clone(), slot == 0)))
+			.collect::<Vec<_>>();
+	let material = Arc::downgrade(material);
+
+	execute_future(
+		stream::iter(futures)
+			.then(|(slot, future)| future.map(move |result| (slot, result)))
+			.collect::<Vec<_>>()
+			.map(move |results| {
+				if let Some(material) = material.upgrade() {
+					material.finish_loading(results);
+				}
+			})
+	);
+}
+
+fn load_texture(
+	queue: &Arc<Queue>,
+	path: PathBuf,
+	srgb: bool,
+) -> Box<Future<Output = TextureResult> + Send + Unpin> {
+	Box::new(
+		ImmutableTexture::from_file_with_format_impl(queue.clone(), path, ImageFormat::PNG, srgb)
+			.map(|result| result
+				.map(|(tex, future)| GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap())
+			)
+	)
+}
+
+/// A material's values, and the contents of its textures rather than their paths.
+#[derive(PartialEq, Eq, Hash)]
+struct MaterialKey {
+	render_pass: usize,
+	light_penetration: u8,
+	subsurface_scattering: u8,
+	emissive_brightness: u16,
+	base_color: [u32; 3],
+	texture1: Option<TextureKey>,
+	texture2: Option<TextureKey>,
+	texture3: Option<TextureKey>,
+	height_scale: u32,
+	parallax_samples: [u8; 2],
+	vertex_animation: Option<(TextureKey, u32, [u32; 7], bool)>,
+}
+impl MaterialKey {
+	fn new(render_pass: &Arc<MeshRenderPass>, desc: &MaterialDesc, texture_key: impl Fn(&Path) -> TextureKey) -> Self {
+		Self {
+			// descriptor sets are built against the render pass's pipeline layout
+			render_pass: &**render_pass as *const MeshRenderPass as usize,
+			light_penetration: desc.light_penetration,
+			subsurface_scattering: desc.subsurface_scattering,
+			emissive_brightness: desc.emissive_brightness,
+			base_color: [desc.base_color.r.to_bits(), desc.base_color.g.to_bits(), desc.base_color.b.to_bits()],
+			texture1: desc.texture1.as_ref().map(|path| texture_key(path)),
+			texture2: desc.texture2.as_ref().map(|path| texture_key(path)),
+			texture3: desc.texture3.as_ref().map(|path| texture_key(path)),
+			height_scale: desc.height_scale.to_bits(),
+			parallax_samples: [desc.parallax_min_samples, desc.parallax_max_samples],
+			vertex_animation: desc.vertex_animation.as_ref().map(|animation| {
+				let (min, max) = (animation.bounds_min, animation.bounds_max);
+				(
+					texture_key(&animation.texture),
+					animation.frame_count,
+					[
+						animation.fps.to_bits(),
+						min.x.to_bits(), min.y.to_bits(), min.z.to_bits(),
+						max.x.to_bits(), max.y.to_bits(), max.z.to_bits(),
+					],
+					animation.looping,
+				)
+			}),
+		}
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum TextureKey {
+	Contents { len: u64, hash: u64 },
+	/// The file couldn't be read, so it fails to load the same way for every material naming it.
+	Unreadable(PathBuf),
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct MaterialUniform {
+	light_penetration: u32,
+	subsurface_scattering: u32,
+	emissive_brightness: u32,
+	// std140 aligns vec3 to 16 bytes
+	_padding: u32,
+	base_color: [f32; 3],
+	// packed into the vec3's last four bytes
+	height_scale: f32,
+	parallax_samples: [u32; 2],
+	uv_offset: [f32; 2],
+	uv_matrix: [f32; 4],
+	flags: u32,
+	vertex_animation_frame_count: u32,
+	_padding2: [u32; 2],
+	vertex_animation_min: [f32; 3],
+	vertex_animation_fps: f32,
+	vertex_animation_max: [f32; 3],
+}
+
+const VERTEX_ANIMATION_SLOT: usize = 3;
+/// Bits of `MaterialUniform::flags`, matching the gbuffer vertex shader.
+const MATERIAL_VERTEX_ANIMATION: u32 = 1;
+const MATERIAL_VERTEX_ANIMATION_LOOP: u32 = 2;
@@ -1,17 +1,43 @@
 mod codec;
+mod culled;
+mod format;
+mod nmdl;
 
-use crate::batch::mesh::MeshRenderPass;
-use crate::cpu_pool::spawn_fs;
+pub use self::culled::{ GpuCulledMesh, GpuCulledMeshError };
+pub use self::format::{ MeshIndexFormat, MeshVertexFormat };
+
+pub use self::nmdl::{
+	NiceModel,
+	NiceModelBone,
+	NiceModelCollision,
+	NiceModelError,
+	NiceModelLod,
+	NiceModelMaterial,
+	NICE_MODEL_VERSION,
+};
+
+use crate::batch::mesh::{ Drawable3D, MaterialHandle, MeshRasterizer, MeshRenderPass, TextureLoadError };
+use crate::color::Color;
+use crate::cpu_pool::{ spawn_cpu, spawn_fs };
+use crate::geometry::{ bake_ambient_occlusion, AmbientOcclusionParams, MeshGeometry };
+use crate::math::{ GpuQuaternion, Transform, quat_to_xyzw };
+use crate::stats::{ self, Resource, ResourceTally, Tracked };
 use crate::window::Window;
-use atom::Atom;
-use cgmath::{ Quaternion, Vector3 };
+use cgmath::{ Quaternion, Vector3, Vector4 };
 use futures::prelude::*;
 use std::{ io, mem::size_of, path::Path, sync::Arc, vec::IntoIter as VecIntoIter, };
 use vulkano::{
-	OomError,
-	buffer::{ BufferAccess, BufferSlice, CpuBufferPool, ImmutableBuffer, cpu_pool::CpuBufferPoolSubbuffer },
+	buffer::{
+		BufferAccess,
+		BufferSlice,
+		BufferUsage,
+		CpuBufferPool,
+		ImmutableBuffer,
+		TypedBufferAccess,
+		cpu_pool::CpuBufferPoolSubbuffer,
+	},
 	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
-	descriptor::{ DescriptorSet, descriptor_set::FixedSizeDescriptorSetsPool },
+	descriptor::DescriptorSet,
 	format::Format,
 	instance::QueueFamily,
 	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
@@ -23,48 +49,235 @@ use vulkano::{
 	sync::GpuFuture,
 };
 
+/// Bytes of user params a mesh can have, see `Mesh::set_user_params`.
+pub const MESH_USER_PARAMS_SIZE: usize = 64;
+
+pub(crate) type UserParams = [u8; MESH_USER_PARAMS_SIZE];
+
+/// Layout of the `MeshPos` uniform.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct PositionUniform {
+	/// w is 1 if the mesh's ambient light comes from the light probes.
+	pub(crate) position: Vector4<f32>,
+	/// w is the vertex animation time.
+	pub(crate) scale: Vector4<f32>,
+}
+impl PositionUniform {
+	pub(crate) fn new(transform: &Transform, vertex_animation_time: f32, uses_light_probes: bool) -> Self {
+		Self {
+			position: transform.position.extend(if uses_light_probes { 1.0 } else { 0.0 }),
+			scale: transform.scale.extend(vertex_animation_time),
+		}
+	}
+}
+
 pub struct Mesh {
-	position_pool: CpuBufferPool<Vector3<f32>>,
-	rotation_pool: CpuBufferPool<Quaternion<f32>>,
-	position: CpuBufferPoolSubbuffer<Vector3<f32>, Arc<StdMemoryPool>>,
-	rotation: CpuBufferPoolSubbuffer<Quaternion<f32>, Arc<StdMemoryPool>>,
+	position_pool: CpuBufferPool<PositionUniform>,
+	rotation_pool: CpuBufferPool<GpuQuaternion>,
+	user_params_pool: CpuBufferPool<UserParams>,
+	transform: Transform,
+	vertex_animation_time: f32,
+	uses_light_probes: bool,
+	position: Tracked<CpuBufferPoolSubbuffer<PositionUniform, Arc<StdMemoryPool>>>,
+	rotation: Tracked<CpuBufferPoolSubbuffer<GpuQuaternion, Arc<StdMemoryPool>>>,
+	user_params: Option<Tracked<CpuBufferPoolSubbuffer<UserParams, Arc<StdMemoryPool>>>>,
+	pipeline: Option<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+	rasterizer: MeshRasterizer,
 	positions: Arc<ImmutableBuffer<[[f32; 3]]>>,
-	normals: Arc<ImmutableBuffer<[[f32; 3]]>>,
-	texcoords_main: Arc<ImmutableBuffer<[[f32; 2]]>>,
+	normals: Arc<BufferAccess + Send + Sync>,
+	texcoords_main: Arc<BufferAccess + Send + Sync>,
+	ambient_occlusion: Arc<ImmutableBuffer<[f32]>>,
+	vertex_format: MeshVertexFormat,
+	index_format: MeshIndexFormat,
 	materials: Vec<Material>,
+	geometry: Arc<MeshGeometry>,
 }
 impl Mesh {
 	pub fn from_file(
 		window: &Window,
 		render_pass: Arc<MeshRenderPass>,
 		path: impl AsRef<Path> + Clone + Send + 'static,
-		position: Vector3<f32>,
-		rotation: Quaternion<f32>,
+		transform: Transform,
 	) -> impl Future<Output = Result<(Self, impl GpuFuture + Send + Sync + 'static), MeshFromFileError>>
 	{
-		let device = window.device().device().clone();
+		let device = window.device().clone();
+		spawn_fs(move || codec::from_nice_model(device, render_pass, path, transform))
+	}
+
+	/// Creates a single-material mesh from CPU geometry.
+	pub fn from_geometry(
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+		geometry: MeshGeometry,
+		base_color: Color,
+		transform: Transform,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		codec::from_geometry(window.device().clone(), render_pass, Arc::new(geometry), base_color, transform)
+	}
+
+	pub fn materials(&self) -> impl Iterator<Item = &MaterialHandle> {
+		self.materials.iter().map(|mat| &mat.material)
+	}
+
+	/// Replaces the material used by the `index`th range of indices.
+	pub fn set_material(&mut self, index: usize, material: MaterialHandle) {
+		self.materials[index].material = material;
+	}
+
+	/// Textures this mesh's materials failed to load, e.g. files an nmdl references that don't exist.
+	pub fn texture_errors(&self) -> Vec<TextureLoadError> {
+		self.materials.iter().flat_map(|mat| mat.material.texture_errors()).collect()
+	}
+
+	/// Reloads the textures in `texture_errors`. Materials are shared, so this also fixes other meshes using them.
+	pub fn retry_missing_textures(&self) {
+		for mat in &self.materials {
+			mat.material.retry_missing_textures();
+		}
+	}
+
+	pub fn geometry(&self) -> &Arc<MeshGeometry> {
+		&self.geometry
+	}
+
+	pub fn vertex_format(&self) -> MeshVertexFormat {
+		self.vertex_format
+	}
+
+	pub fn index_format(&self) -> MeshIndexFormat {
+		self.index_format
+	}
+
+	/// Ray traces per-vertex ambient occlusion against this mesh's own triangles on the CPU pool. Pass the result to
+	/// `set_ambient_occlusion` once it's ready.
+	pub fn bake_ambient_occlusion(
+		&self,
+		window: &Window,
+		params: AmbientOcclusionParams,
+	) -> impl Future<Output = Result<(AmbientOcclusion, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError>>
+	{
+		let geometry = self.geometry.clone();
 		let queue = window.device().queue().clone();
-		spawn_fs(move || codec::from_nice_model(device, queue, render_pass, path, position, rotation))
+		spawn_cpu(move || {
+			let values = bake_ambient_occlusion(&geometry, &params);
+			ImmutableBuffer::from_iter(values.into_iter(), BufferUsage::vertex_buffer(), queue)
+				.map(|(buffer, future)| (AmbientOcclusion { buffer: buffer }, future))
+		})
+	}
+
+	pub fn set_ambient_occlusion(&mut self, ambient_occlusion: AmbientOcclusion) {
+		assert_eq!(ambient_occlusion.buffer.len(), self.geometry.vertex_count());
+		self.ambient_occlusion = ambient_occlusion.buffer;
+	}
+
+	pub fn transform(&self) -> Transform {
+		self.transform
+	}
+
+	pub fn set_transform(&mut self, transform: Transform) -> Result<(), DeviceMemoryAllocError> {
+		self.rotation = stats::track(Resource::Subbuffer, self.rotation_pool.next(quat_to_xyzw(transform.rotation))?);
+		self.set_position_uniform(transform)
 	}
 
 	pub fn set_position(&mut self, position: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.position = self.position_pool.next(position)?;
-		Ok(())
+		self.set_position_uniform(Transform { position: position, ..self.transform })
 	}
 
 	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.rotation = self.rotation_pool.next(rotation)?;
+		self.rotation = stats::track(Resource::Subbuffer, self.rotation_pool.next(quat_to_xyzw(rotation))?);
+		self.transform.rotation = rotation;
+		Ok(())
+	}
+
+	/// Scales the mesh per axis in object space. Normals are corrected in the shader, so non-uniform scales light
+	/// correctly.
+	pub fn set_scale(&mut self, scale: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
+		self.set_position_uniform(Transform { scale: scale, ..self.transform })
+	}
+
+	pub fn vertex_animation_time(&self) -> f32 {
+		self.vertex_animation_time
+	}
+
+	/// Seconds into the vertex animations of the mesh's materials, see `MaterialDesc::vertex_animation`. Frames in
+	/// between are interpolated. Each mesh keeps its own time, so meshes sharing a material can play it out of step,
+	/// e.g. across a crowd.
+	pub fn set_vertex_animation_time(&mut self, time: f32) -> Result<(), DeviceMemoryAllocError> {
+		self.vertex_animation_time = time;
+		let transform = self.transform;
+		self.set_position_uniform(transform)
+	}
+
+	/// Moves the vertex animation time forward by `delta` seconds.
+	pub fn advance_vertex_animation(&mut self, delta: f32) -> Result<(), DeviceMemoryAllocError> {
+		let time = self.vertex_animation_time + delta;
+		self.set_vertex_animation_time(time)
+	}
+
+	pub fn uses_light_probes(&self) -> bool {
+		self.uses_light_probes
+	}
+
+	/// Lights the mesh with the batch's `LightProbeGrid` instead of its `AmbientLight`, for meshes that move through
+	/// the level like characters. Has no effect while the batch has no grid. Overlay meshes are drawn in a depth range
+	/// of their own, which the grid would be sampled at the wrong positions for, so leave it off for them.
+	pub fn set_uses_light_probes(&mut self, uses_light_probes: bool) -> Result<(), DeviceMemoryAllocError> {
+		self.uses_light_probes = uses_light_probes;
+		let transform = self.transform;
+		self.set_position_uniform(transform)
+	}
+
+	/// Position, scale, the vertex animation time and the light probe flag share a uniform, so this uploads them all
+	/// and keeps `transform`'s rotation.
+	fn set_position_uniform(&mut self, transform: Transform) -> Result<(), DeviceMemoryAllocError> {
+		let uniform = PositionUniform::new(&transform, self.vertex_animation_time, self.uses_light_probes);
+		self.position = stats::track(Resource::Subbuffer, self.position_pool.next(uniform)?);
+		self.transform = transform;
+		Ok(())
+	}
+
+	/// Data for custom shaders, like a dissolve amount or team color, bound in the gbuffer pass at set 1 binding 3 as
+	/// a uniform block of up to `MESH_USER_PARAMS_SIZE` bytes. Missing bytes are zero. Panics if `bytes` is longer.
+	pub fn set_user_params(&mut self, bytes: &[u8]) -> Result<(), DeviceMemoryAllocError> {
+		assert!(
+			bytes.len() <= MESH_USER_PARAMS_SIZE,
+			"user params are {} bytes, the limit is {}",
+			bytes.len(),
+			MESH_USER_PARAMS_SIZE
+		);
+
+		let mut params = [0; MESH_USER_PARAMS_SIZE];
+		params[..bytes.len()].copy_from_slice(bytes);
+		self.user_params = Some(stats::track(Resource::Subbuffer, self.user_params_pool.next(params)?));
 		Ok(())
 	}
 
-	pub(super) fn make_commands(
+	/// Draws the mesh with `pipeline` instead of `MeshRenderPass::pipeline_gbuffers_for` its vertex format, e.g. with
+	/// a fragment shader that reads the user params. It must have the same vertex input and descriptor set layouts.
+	pub fn set_pipeline(&mut self, pipeline: Option<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>) {
+		self.pipeline = pipeline;
+	}
+
+	/// Draws the mesh with `MeshRenderPass::pipeline_gbuffers_rasterized`, e.g. with depth clamp for shadow casters.
+	/// Ignored while a pipeline is set with `set_pipeline`.
+	pub fn set_rasterizer(&mut self, rasterizer: MeshRasterizer) {
+		self.rasterizer = rasterizer;
+	}
+
+	pub fn rasterizer(&self) -> MeshRasterizer {
+		self.rasterizer
+	}
+}
+impl Drawable3D for Mesh {
+	fn make_commands(
 		&mut self,
 		render_pass: &MeshRenderPass,
-		camera_desc: impl DescriptorSet + Clone + Send + Sync + 'static,
-		mesh_desc_pool: &mut FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		highlight: Option<[f32; 4]>,
 		queue_family: QueueFamily,
 		dimensions: [f32; 2],
-	) -> Result<AutoCommandBuffer, OomError> {
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
 		let mut cmd = AutoCommandBufferBuilder
 			::secondary_graphics_one_time_submit(
 				render_pass.shaders.target_vertices.device().clone(),
@@ -79,41 +292,90 @@ impl Mesh {
 				scissors: None,
 			};
 
+		let user_params =
+			match &self.user_params {
+				Some(user_params) => user_params.clone(),
+				None =>
+					stats::track(Resource::Subbuffer, render_pass.user_params_pool().next([0; MESH_USER_PARAMS_SIZE])?),
+			};
+
+		let highlight_buffer =
+			stats::track(Resource::Subbuffer, render_pass.highlight_pool().next(highlight.unwrap_or([0.0; 4]))?);
+		let mesh_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				render_pass.mesh_desc_pool().lock().unwrap()
+					.next()
+					.add_buffer(self.position.clone())
+					.unwrap()
+					.add_buffer(self.rotation.clone())
+					.unwrap()
+					.add_buffer(highlight_buffer)
+					.unwrap()
+					.add_buffer(user_params)
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		let pipeline =
+			match &self.pipeline {
+				Some(pipeline) => pipeline.clone(),
+				None => render_pass.pipeline_gbuffers_rasterized(self.vertex_format, self.rasterizer),
+			};
+		let vertices: Vec<Arc<BufferAccess + Send + Sync>> =
+			vec![
+				self.positions.clone(),
+				self.normals.clone(),
+				self.texcoords_main.clone(),
+				self.ambient_occlusion.clone()
+			];
 		for mat in &self.materials {
-			let desc = mat.desc.take().unwrap();
-
-			cmd = cmd
-				.draw_indexed(
-					render_pass.pipeline_gbuffers.clone(),
-					&state,
-					vec![self.positions.clone(), self.normals.clone(), self.texcoords_main.clone()],
-					mat.indices.clone(),
-					(
-						camera_desc.clone(),
-						mesh_desc_pool.next()
-							.add_buffer(self.position.clone())
-							.unwrap()
-							.add_buffer(self.rotation.clone())
-							.unwrap()
-							.build()
-							.unwrap(),
-						desc.clone()
-					),
-					()
-				)
+			let sets = (camera_desc.clone(), mesh_desc.clone(), mat.material.descriptor_set());
+			cmd =
+				match &mat.indices {
+					MaterialIndices::U16(indices) =>
+						cmd.draw_indexed(pipeline.clone(), &state, vertices.clone(), indices.clone(), sets, ()),
+					MaterialIndices::U32(indices) =>
+						cmd.draw_indexed(pipeline.clone(), &state, vertices.clone(), indices.clone(), sets, ()),
+				}
 				.unwrap();
-
-			mat.desc.set_if_none(desc);
 		}
 
 		Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
 	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_buffer(&self.position);
+		tally.add_buffer(&self.rotation);
+		if let Some(user_params) = &self.user_params {
+			tally.add_buffer(user_params);
+		}
+		tally.add_buffer(&*self.positions);
+		tally.add_buffer(&*self.normals);
+		tally.add_buffer(&*self.texcoords_main);
+		tally.add_buffer(&*self.ambient_occlusion);
+
+		for mat in &self.materials {
+			match &mat.indices {
+				MaterialIndices::U16(indices) => tally.add_buffer(indices),
+				MaterialIndices::U32(indices) => tally.add_buffer(indices),
+			};
+			mat.material.resources(tally);
+		}
+	}
 }
 
-pub struct MeshVertexDefinition {}
+pub struct MeshVertexDefinition {
+	format: MeshVertexFormat,
+}
 impl MeshVertexDefinition {
 	pub fn new() -> Self {
-		Self {}
+		Self::with_format(MeshVertexFormat::Full)
+	}
+
+	pub fn with_format(format: MeshVertexFormat) -> Self {
+		Self { format: format }
 	}
 }
 unsafe impl<I> VertexDefinition<I> for MeshVertexDefinition {
@@ -125,16 +387,20 @@ unsafe impl<I> VertexDefinition<I> for MeshVertexDefinition {
 		_interface: &I
 	) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
 		// TODO: validate against shader
+		let (normal_format, normal_size) = self.format.normal_format();
+		let (texcoord_format, texcoord_size) = self.format.texcoord_format();
 		Ok((
 			vec![
 				(0, size_of::<[f32; 3]>(), InputRate::Vertex),
-				(1, size_of::<[f32; 3]>(), InputRate::Vertex),
-				(2, size_of::<[f32; 2]>(), InputRate::Vertex)
+				(1, normal_size, InputRate::Vertex),
+				(2, texcoord_size, InputRate::Vertex),
+				(3, size_of::<f32>(), InputRate::Vertex)
 			].into_iter(),
 			vec![
 				(0, 0, AttributeInfo { offset: 0, format: Format::R32G32B32Sfloat }),
-				(1, 1, AttributeInfo { offset: 0, format: Format::R32G32B32Sfloat }),
-				(2, 2, AttributeInfo { offset: 0, format: Format::R32G32Sfloat })
+				(1, 1, AttributeInfo { offset: 0, format: normal_format }),
+				(2, 2, AttributeInfo { offset: 0, format: texcoord_format }),
+				(3, 3, AttributeInfo { offset: 0, format: Format::R32Sfloat })
 			].into_iter()
 		))
 	}
@@ -145,16 +411,22 @@ unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for MeshVertexDef
 		&self,
 		source: Vec<Arc<BufferAccess + Send + Sync>>
 	) -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
-		assert_eq!(source.len(), 3);
+		assert_eq!(source.len(), 4);
 		let len = source[0].size() / size_of::<[f32; 3]>();
 		(source.into_iter().map(|x| Box::new(x) as _).collect(), len, 1)
 	}
 }
 
+/// Per-vertex occlusion baked by `Mesh::bake_ambient_occlusion`.
+pub struct AmbientOcclusion {
+	buffer: Arc<ImmutableBuffer<[f32]>>,
+}
+
 #[derive(Debug)]
 pub enum MeshFromFileError {
 	Io(io::Error),
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	Invalid(NiceModelError),
 }
 impl From<io::Error> for MeshFromFileError{
 	fn from(err: io::Error) -> Self {
@@ -166,23 +438,21 @@ impl From<DeviceMemoryAllocError> for MeshFromFileError{
 		MeshFromFileError::DeviceMemoryAllocError(err)
 	}
 }
-
-struct Material {
-	indices: BufferSlice<[u32], Arc<ImmutableBuffer<[u32]>>>,
-	desc: Arc<Atom<Box<Arc<DescriptorSet + Sync + Send + 'static>>>>,
+impl From<NiceModelError> for MeshFromFileError{
+	fn from(err: NiceModelError) -> Self {
+		MeshFromFileError::Invalid(err)
+	}
 }
 
-struct MaterialTextureInfo {
-	texture1_name_size: u16,
-	texture1_name_offset: u32,
-	texture2_name_size: u16,
-	texture2_name_offset: u32,
+#[derive(Clone)]
+struct Material {
+	indices: MaterialIndices,
+	material: MaterialHandle,
 }
 
-#[repr(C)]
-struct MaterialUniform {
-	light_penetration: u32,
-	subsurface_scattering: u32,
-	emissive_brightness: u32,
-	base_color: [f32; 3],
+/// The range of a mesh's index buffer drawn with one material.
+#[derive(Clone)]
+enum MaterialIndices {
+	U16(BufferSlice<[u16], Arc<ImmutableBuffer<[u16]>>>),
+	U32(BufferSlice<[u32], Arc<ImmutableBuffer<[u32]>>>),
 }
@@ -1,294 +1,255 @@
-use crate::batch::mesh::{ MeshRenderPass, mesh::{ Material, MaterialTextureInfo, MaterialUniform, Mesh, MeshFromFileError } };
-use crate::cpu_pool::{ execute_future, GpuFutureFuture };
-use crate::texture::{ ImageFormat, ImmutableTexture, Texture };
-use atom::Atom;
-use byteorder::{LE, ReadBytesExt};
-use cgmath::{ Quaternion, Vector3 };
-use futures::{ FutureExt, future::ready, prelude::* };
+use crate::batch::mesh::{
+	MaterialDesc,
+	MeshRasterizer,
+	MeshRenderPass,
+	mesh::{
+		Material,
+		MaterialIndices,
+		Mesh,
+		MeshFromFileError,
+		MeshIndexFormat,
+		MeshVertexFormat,
+		NiceModel,
+		PositionUniform,
+		format,
+	},
+};
+use crate::color::Color;
+use crate::device::DeviceCtx;
+use crate::geometry::MeshGeometry;
+use crate::math::{ Transform, quat_to_xyzw };
+use crate::stats::{ self, Resource };
+use crate::trace::Span;
 use log::{ debug, log };
-use std::{ fs::File, io::{ self, prelude::*, SeekFrom }, mem::{ size_of, transmute }, path::{ Path }, sync::Arc };
+use std::{ fs::File, io::{ self, prelude::* }, ops::Range, path::Path, sync::Arc };
 use vulkano::{
-	buffer::{ BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool, ImmutableBuffer },
-	descriptor::descriptor_set::PersistentDescriptorSet,
+	buffer::{ BufferAccess, BufferUsage, CpuBufferPool, ImmutableBuffer },
 	device::{ Device, Queue },
+	memory::DeviceMemoryAllocError,
 	sync::GpuFuture,
 };
 
 pub fn from_nice_model(
-	device: Arc<Device>,
-	queue: Arc<Queue>,
+	device: Arc<DeviceCtx>,
 	render_pass: Arc<MeshRenderPass>,
 	path: impl AsRef<Path> + Clone + Send + 'static,
-	position: Vector3<f32>,
-	rotation: Quaternion<f32>,
+	transform: Transform,
 ) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), MeshFromFileError> {
-	let mut file = File::open(path.clone())?;
-
-	let mut magic_number = [0; 4];
-	file.read_exact(&mut magic_number)?;
-	assert_eq!(&magic_number, b"nmdl");
-
-	// skip version for now
-	file.seek(SeekFrom::Current(4))?;
-
-	let vertex_count = file.read_u32::<LE>()? as usize;
-	let positions_offset = file.read_u32::<LE>()? as u64;
-	let normals_offset = file.read_u32::<LE>()? as u64;
-	let texcoords_main_offset = file.read_u32::<LE>()? as u64;
-	let _texcoords_lightmap_offset = file.read_u32::<LE>()? as u64;
-	let index_count = file.read_u32::<LE>()? as usize;
-	let indices_offset = file.read_u32::<LE>()? as u64;
-	let material_count = file.read_u8()? as usize;
-	let materials_offset = file.read_u32::<LE>()? as u64;
-
-	debug!("vertex_count: {}", vertex_count);
-	debug!("positions_offset: {}", positions_offset);
-	debug!("normals_offset: {}", normals_offset);
-	debug!("texcoords_main_offset: {}", texcoords_main_offset);
-	debug!("_texcoords_lightmap_offset: {}", _texcoords_lightmap_offset);
-	debug!("index_count: {}", index_count);
-	debug!("indices_offset: {}", indices_offset);
-	debug!("material_count: {}", material_count);
-	debug!("materials_offset: {}", materials_offset);
-
-	file.seek(SeekFrom::Start(positions_offset))?;
-	let (positions, positions_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
-
-	file.seek(SeekFrom::Start(normals_offset))?;
-	let (normals, normals_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
-
-	file.seek(SeekFrom::Start(texcoords_main_offset))?;
-	let (texcoords_main, texcoords_main_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::vertex_buffer(),
-			vertex_count,
-			&mut || Ok([file.read_f32::<LE>()?, file.read_f32::<LE>()?])
-		)?;
-
-	file.seek(SeekFrom::Start(indices_offset))?;
-	let (indices, indices_future) =
-		buffer_from_file(
-			queue.clone(),
-			BufferUsage::index_buffer(),
-			index_count,
-			&mut || file.read_u32::<LE>()
-		)?;
-
-	file.seek(SeekFrom::Start(materials_offset))?;
-
-	// round MaterialUniform size up to minimum alignment
-	let mut material_stride =
-		queue.device().physical_device().limits().min_uniform_buffer_offset_alignment() as usize;
-	material_stride = (size_of::<MaterialUniform>() + material_stride - 1) / material_stride * material_stride;
-	debug!("material stride: {}", material_stride);
-
-	let material_buf =
-		unsafe {
-			CpuAccessibleBuffer::uninitialized_array(
-				queue.device().clone(),
-				material_count * material_stride,
-				BufferUsage::transfer_source()
-			)?
-		};
-	let mut index_counts = Vec::with_capacity(material_count);
-	let mut mat_temp_datas = Vec::with_capacity(material_count);
-	{
-		let mut material_buf_lock = material_buf.write().unwrap();
-		for i in 0..material_count {
-			index_counts.push(file.read_u32::<LE>()?);
-			mat_temp_datas
-				.push(MaterialTextureInfo {
-					texture1_name_size: file.read_u16::<LE>()?,
-					texture1_name_offset: file.read_u32::<LE>()?,
-					texture2_name_size: file.read_u16::<LE>()?,
-					texture2_name_offset: file.read_u32::<LE>()?,
-				});
-
-			material_buf_lock[i * material_stride..i * material_stride + size_of::<MaterialUniform>()]
-				.copy_from_slice(
-					&unsafe {
-						transmute::<_, [u8; size_of::<MaterialUniform>()]>(
-							MaterialUniform {
-								light_penetration: file.read_u8()? as u32,
-								subsurface_scattering: file.read_u8()? as u32,
-								emissive_brightness: file.read_u16::<LE>()? as u32,
-								base_color: {
-									let mut buf = [0; 3];
-									file.read_exact(&mut buf)?;
-									[
-										(buf[0] as f32 / 255.0).powf(2.2),
-										(buf[1] as f32 / 255.0).powf(2.2),
-										(buf[2] as f32 / 255.0).powf(2.2)
-									]
-								},
-							}
-						)
-					}
-				);
-		}
-	}
+	let _span = Span::new("codec", "from_nice_model").arg("path", path.as_ref().display());
+
+	let read_span = Span::new("codec", "read_model");
+	let mut data = vec![];
+	File::open(path.clone())?.read_to_end(&mut data)?;
+	let model = NiceModel::read(&data)?;
+	drop(data);
+	drop(read_span);
+
+	debug!("vertex_count: {}", model.positions.len());
+	debug!("index_count: {}", model.indices.len());
+	debug!("material_count: {}", model.materials.len());
+	debug!("lod_count: {}", model.lods.len());
+	debug!("bone_count: {}", model.bones.len());
+
+	let dir =
+		path.as_ref()
+			.parent()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "model path has no parent directory"))?;
+	let descs: Vec<_> =
+		model.materials.iter()
+			.map(|material| MaterialDesc {
+				light_penetration: material.light_penetration,
+				subsurface_scattering: material.subsurface_scattering,
+				emissive_brightness: material.emissive_brightness,
+				// nmdl stores colors as authored, in sRGB
+				base_color: Color::from_srgb8(material.base_color[0], material.base_color[1], material.base_color[2]),
+				texture1: material.texture1.as_ref().map(|name| dir.join(name)),
+				texture2: material.texture2.as_ref().map(|name| dir.join(name)),
+				texture3: material.texture3.as_ref().map(|name| dir.join(name)),
+				height_scale: material.height_scale,
+				parallax_min_samples: material.parallax_min_samples,
+				parallax_max_samples: material.parallax_max_samples,
+				vertex_animation: None,
+			})
+			.collect();
+	let index_counts: Vec<_> = model.materials.iter().map(|material| material.index_count as usize).collect();
+
+	let geometry =
+		Arc::new(MeshGeometry {
+			positions: model.positions,
+			normals: model.normals,
+			texcoords: model.texcoords_main,
+			indices: model.indices,
+		});
 
-	let (material_buf, material_buf_future) =
-		ImmutableBuffer::from_buffer(material_buf, BufferUsage::uniform_buffer(), queue.clone())?;
+	let (buffers, buffers_future) = upload_geometry(device.queue(), &geometry)?;
 
-	let mut materials = Vec::with_capacity(material_count);
+	let mut materials = Vec::with_capacity(descs.len());
 	let mut index_start = 0;
-	for (i, index_count) in index_counts.into_iter().enumerate() {
-		let index_count = index_count as usize;
-		let material_offset = material_stride * i;
+	for (desc, index_count) in descs.into_iter().zip(index_counts) {
 		materials
 			.push(Material {
-				indices: indices.clone().into_buffer_slice().slice(index_start..index_start + index_count).unwrap(),
-				desc:
-					Arc::new(Atom::new(Box::new(Arc::new(
-						PersistentDescriptorSet::start(render_pass.pipeline_gbuffers.clone(), 2)
-							.add_buffer(
-								material_buf.clone()
-									.into_buffer_slice()
-									.slice(material_offset..material_offset + size_of::<MaterialUniform>())
-									.unwrap()
-							)
-							.unwrap()
-							.add_sampled_image(render_pass.shaders.texture1_default.clone(), render_pass.shaders.sampler.clone())
-							.unwrap()
-							.add_sampled_image(render_pass.shaders.texture2_default.clone(), render_pass.shaders.sampler.clone())
-							.unwrap()
-							.build()
-							.unwrap()
-					))))
+				indices: buffers.indices.slice(index_start..index_start + index_count),
+				material: device.get_material(&render_pass, desc)?,
 			});
 
 		index_start += index_count;
 	}
 
-	for (i, data) in mat_temp_datas.into_iter().enumerate() {
-		let texture1_default = render_pass.shaders.texture1_default.clone();
-		let future1: Box<Future<Output = _> + Send + Unpin> =
-			if data.texture1_name_size != 0 {
-				file.seek(SeekFrom::Start(data.texture1_name_offset as u64))?;
-				let mut buf = vec![0; data.texture1_name_size as usize];
-				file.read_exact(&mut buf)?;
-				let path = path.as_ref().parent().unwrap().join(String::from_utf8(buf).unwrap());
-
-				Box::new(
-					ImmutableTexture
-						::from_file_with_format_impl(queue.clone(), path.clone(), ImageFormat::PNG, true)
-						.map(|result| result
-							.map(|(tex, future)| {
-								GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap()
-							})
-							.unwrap_or_else(move |_| texture1_default)
-						)
-				)
-			} else {
-				Box::new(ready(texture1_default))
-			};
-
-		let texture2_default = render_pass.shaders.texture2_default.clone();
-		let future2: Box<Future<Output = _> + Send + Unpin> =
-			if data.texture2_name_size != 0 {
-				file.seek(SeekFrom::Start(data.texture2_name_offset as u64))?;
-				let mut buf = vec![0; data.texture2_name_size as usize];
-				file.read_exact(&mut buf)?;
-				let path = path.as_ref().parent().unwrap().join(String::from_utf8(buf).unwrap());
-
-				Box::new(
-					ImmutableTexture
-						::from_file_with_format_impl(queue.clone(), path.clone(), ImageFormat::PNG, false)
-						.map(|result| result
-							.map(|(tex, future)| {
-								GpuFutureFuture::new(future).map(|_| tex.image().clone()).unwrap()
-							})
-							.unwrap_or_else(move |_| texture2_default)
-						)
-				)
-			} else {
-				Box::new(ready(texture2_default))
-			};
+	Ok((make_mesh(device.device().clone(), buffers, materials, geometry, transform)?, buffers_future))
+}
 
-		let desc = materials[i].desc.clone();
-		let material_buf = material_buf.clone();
-		let material_offset = material_stride * i;
-		let pipeline_gbuffers = render_pass.pipeline_gbuffers.clone();
-		let sampler = render_pass.shaders.sampler.clone();
+pub fn from_geometry(
+	device: Arc<DeviceCtx>,
+	render_pass: Arc<MeshRenderPass>,
+	geometry: Arc<MeshGeometry>,
+	base_color: Color,
+	transform: Transform,
+) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+	let (buffers, buffers_future) = upload_geometry(device.queue(), &geometry)?;
+
+	let materials =
+		vec![
+			Material {
+				indices: buffers.indices.slice(0..geometry.indices.len()),
+				material: device.get_material(&render_pass, MaterialDesc { base_color: base_color, ..MaterialDesc::default() })?,
+			}
+		];
+
+	Ok((make_mesh(device.device().clone(), buffers, materials, geometry, transform)?, buffers_future))
+}
 
-		execute_future(async move {
-			let tex1 = await!(future1);
-			let tex2 = await!(future2);
+struct GeometryBuffers {
+	positions: Arc<ImmutableBuffer<[[f32; 3]]>>,
+	normals: Arc<BufferAccess + Send + Sync>,
+	texcoords_main: Arc<BufferAccess + Send + Sync>,
+	indices: IndexBuffer,
+	ambient_occlusion: Arc<ImmutableBuffer<[f32]>>,
+	vertex_format: MeshVertexFormat,
+	index_format: MeshIndexFormat,
+}
 
-			desc.swap(Box::new(Arc::new(
-				PersistentDescriptorSet::start(pipeline_gbuffers.clone(), 2)
-					.add_buffer(
-						material_buf.clone()
-							.into_buffer_slice()
-							.slice(material_offset..material_offset + size_of::<MaterialUniform>())
-							.unwrap()
-					)
-					.unwrap()
-					.add_sampled_image(tex1, sampler.clone())
-					.unwrap()
-					.add_sampled_image(tex2, sampler.clone())
-					.unwrap()
-					.build()
-					.unwrap()
-			)));
-		});
+enum IndexBuffer {
+	U16(Arc<ImmutableBuffer<[u16]>>),
+	U32(Arc<ImmutableBuffer<[u32]>>),
+}
+impl IndexBuffer {
+	fn slice(&self, range: Range<usize>) -> MaterialIndices {
+		match self {
+			IndexBuffer::U16(buffer) => MaterialIndices::U16(buffer.clone().into_buffer_slice().slice(range).unwrap()),
+			IndexBuffer::U32(buffer) => MaterialIndices::U32(buffer.clone().into_buffer_slice().slice(range).unwrap()),
+		}
 	}
+}
 
-	let position_pool = CpuBufferPool::uniform_buffer(device.clone());
-	let rotation_pool = CpuBufferPool::uniform_buffer(device);
-	let position = position_pool.next(position)?;
-	let rotation = rotation_pool.next(rotation)?;
+fn upload_geometry(
+	queue: &Arc<Queue>,
+	geometry: &MeshGeometry,
+) -> Result<(GeometryBuffers, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+	let vertex_format = MeshVertexFormat::choose(queue.device(), geometry);
+	let index_format = MeshIndexFormat::choose(geometry);
+
+	// this only times copying into staging buffers; the transfer itself happens when the returned future is flushed
+	let _span =
+		Span::new("upload", "geometry")
+			.arg("vertices", geometry.vertex_count())
+			.arg("format", format!("{:?}", vertex_format))
+			.arg(
+				"bytes",
+				geometry.vertex_count() * vertex_format.vertex_size()
+					+ geometry.indices.len() * index_format.index_size()
+			);
+	let (positions, positions_future) =
+		ImmutableBuffer::from_iter(geometry.positions.iter().cloned(), BufferUsage::vertex_buffer(), queue.clone())?;
+	let (normals, normals_future) =
+		match vertex_format {
+			MeshVertexFormat::Full => upload_vertices(geometry.normals.iter().cloned(), queue)?,
+			MeshVertexFormat::Packed =>
+				upload_vertices(geometry.normals.iter().map(|&normal| format::pack_normal(normal)), queue)?,
+		};
+	let (texcoords_main, texcoords_main_future) =
+		match vertex_format {
+			MeshVertexFormat::Full => upload_vertices(geometry.texcoords.iter().cloned(), queue)?,
+			MeshVertexFormat::Packed =>
+				upload_vertices(geometry.texcoords.iter().map(|&texcoord| format::pack_texcoord(texcoord)), queue)?,
+		};
+	let (indices, indices_future): (_, Box<GpuFuture + Send + Sync>) =
+		match index_format {
+			MeshIndexFormat::U16 => {
+				let indices = geometry.indices.iter().map(|&index| index as u16);
+				let (buffer, future) = ImmutableBuffer::from_iter(indices, BufferUsage::index_buffer(), queue.clone())?;
+				(IndexBuffer::U16(buffer), Box::new(future))
+			},
+			MeshIndexFormat::U32 => {
+				let indices = geometry.indices.iter().cloned();
+				let (buffer, future) = ImmutableBuffer::from_iter(indices, BufferUsage::index_buffer(), queue.clone())?;
+				(IndexBuffer::U32(buffer), Box::new(future))
+			},
+		};
+	let (ambient_occlusion, ambient_occlusion_future) =
+		ImmutableBuffer::from_iter(
+			(0..geometry.vertex_count()).map(|_| 1.0f32),
+			BufferUsage::vertex_buffer(),
+			queue.clone()
+		)?;
 
 	Ok((
-		Mesh {
-			position_pool: position_pool,
-			rotation_pool: rotation_pool,
-			position: position,
-			rotation: rotation,
+		GeometryBuffers {
 			positions: positions,
 			normals: normals,
 			texcoords_main: texcoords_main,
-			materials: materials,
+			indices: indices,
+			ambient_occlusion: ambient_occlusion,
+			vertex_format: vertex_format,
+			index_format: index_format,
 		},
 		positions_future
 			.join(normals_future)
 			.join(texcoords_main_future)
 			.join(indices_future)
-			.join(material_buf_future)
+			.join(ambient_occlusion_future)
 	))
 }
 
-fn buffer_from_file<T>(
-	queue: Arc<Queue>,
-	usage: BufferUsage,
-	count: usize,
-	read: &mut FnMut() -> io::Result<T>
-) -> Result<(Arc<ImmutableBuffer<[T]>>, impl GpuFuture), MeshFromFileError>
-where T: Send + Sync + 'static
-{
-	let tmpbuf =
-		unsafe {
-			CpuAccessibleBuffer::uninitialized_array(queue.device().clone(), count, BufferUsage::transfer_source())?
-		};
-	{
-		let mut tmpbuf_lock = tmpbuf.write().unwrap();
-		for i in 0..count {
-			tmpbuf_lock[i] = read()?;
-		}
-	}
-	ImmutableBuffer::from_buffer(tmpbuf, usage, queue).map_err(|e| e.into())
+fn upload_vertices<T: Send + Sync + 'static>(
+	data: impl ExactSizeIterator<Item = T>,
+	queue: &Arc<Queue>,
+) -> Result<(Arc<BufferAccess + Send + Sync>, Box<GpuFuture + Send + Sync>), DeviceMemoryAllocError> {
+	let (buffer, future) = ImmutableBuffer::from_iter(data, BufferUsage::vertex_buffer(), queue.clone())?;
+	Ok((buffer, Box::new(future)))
+}
+
+fn make_mesh(
+	device: Arc<Device>,
+	buffers: GeometryBuffers,
+	materials: Vec<Material>,
+	geometry: Arc<MeshGeometry>,
+	transform: Transform,
+) -> Result<Mesh, DeviceMemoryAllocError> {
+	let position_pool = CpuBufferPool::uniform_buffer(device.clone());
+	let rotation_pool = CpuBufferPool::uniform_buffer(device.clone());
+	let user_params_pool = CpuBufferPool::uniform_buffer(device);
+	let position = stats::track(Resource::Subbuffer, position_pool.next(PositionUniform::new(&transform, 0.0, false))?);
+	let rotation = stats::track(Resource::Subbuffer, rotation_pool.next(quat_to_xyzw(transform.rotation))?);
+
+	Ok(Mesh {
+		position_pool: position_pool,
+		rotation_pool: rotation_pool,
+		user_params_pool: user_params_pool,
+		transform: transform,
+		vertex_animation_time: 0.0,
+		uses_light_probes: false,
+		position: position,
+		rotation: rotation,
+		user_params: None,
+		pipeline: None,
+		rasterizer: MeshRasterizer::default(),
+		positions: buffers.positions,
+		normals: buffers.normals,
+		texcoords_main: buffers.texcoords_main,
+		ambient_occlusion: buffers.ambient_occlusion,
+		vertex_format: buffers.vertex_format,
+		index_format: buffers.index_format,
+		materials: materials,
+		geometry: geometry,
+	})
 }
@@ -0,0 +1,325 @@
+use crate::batch::mesh::{ Drawable3D, MeshRenderPass, shaders::cs_cull };
+use crate::camera::Camera;
+use crate::math::{ Transform, quat_to_xyzw };
+use crate::stats::{ self, Resource, ResourceTally, Tracked };
+use super::{ Material, MaterialIndices, Mesh, MeshVertexFormat, UserParams, MESH_USER_PARAMS_SIZE };
+use cgmath::prelude::*;
+use std::sync::Arc;
+use vulkano::{
+	buffer::{
+		BufferAccess,
+		BufferUsage,
+		DeviceLocalBuffer,
+		ImmutableBuffer,
+		cpu_pool::CpuBufferPoolSubbuffer,
+	},
+	command_buffer::{
+		AutoCommandBuffer,
+		AutoCommandBufferBuilder,
+		BuildError,
+		DrawIndexedIndirectCommand,
+		DynamicState,
+	},
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	instance::QueueFamily,
+	memory::{ DeviceMemoryAllocError, pool::StdMemoryPool },
+	pipeline::viewport::Viewport,
+	sync::GpuFuture,
+};
+
+/// Instances the cull shader tests per workgroup, its `local_size_x`.
+const CULL_GROUP_SIZE: u32 = 64;
+
+/// Layout of the `Instance` struct of the cull and instanced gbuffer shaders, 64 bytes like
+/// `Capabilities::max_instances_per_draw` counts.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct InstanceData {
+	/// w is 1 if the instance's ambient light comes from the light probes.
+	position: [f32; 4],
+	/// w is the vertex animation time.
+	scale: [f32; 4],
+	rotation: [f32; 4],
+	/// The world space bounding sphere, center in xyz and radius in w.
+	bounds: [f32; 4],
+}
+
+/// A mesh drawn at many fixed transforms, for very large static scenes like forests or rubble fields. Instances are
+/// culled against the view by a compute shader before the render pass, which writes the visible ones into the
+/// indirect draws of each material, so the CPU records the same few commands a frame however many instances there are.
+/// Add it to a `MeshBatch` with `add_drawable`.
+///
+/// Needs `Capabilities::supports_compute`. Instances are tested by a bounding sphere around the mesh's geometry, which
+/// vertex animations moving past it would pop out of view early.
+pub struct GpuCulledMesh {
+	positions: Arc<BufferAccess + Send + Sync>,
+	normals: Arc<BufferAccess + Send + Sync>,
+	texcoords_main: Arc<BufferAccess + Send + Sync>,
+	ambient_occlusion: Arc<BufferAccess + Send + Sync>,
+	vertex_format: MeshVertexFormat,
+	materials: Vec<Material>,
+	user_params: Tracked<CpuBufferPoolSubbuffer<UserParams, Arc<StdMemoryPool>>>,
+	instance_count: u32,
+	instances: Arc<ImmutableBuffer<[InstanceData]>>,
+	/// Indices of the instances the last cull found visible.
+	visible: Arc<DeviceLocalBuffer<[u32]>>,
+	/// One per material, with the instance count the cull adds up.
+	draws: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+	/// Copied over `draws` before each cull, to start the counts at zero.
+	draws_reset: Arc<ImmutableBuffer<[DrawIndexedIndirectCommand]>>,
+	cull_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+}
+impl GpuCulledMesh {
+	/// Instances of `mesh`'s geometry and materials at each of `transforms`, sharing its buffers. Its user params,
+	/// vertex animation time and light probe flag apply to every instance; its own transform, pipeline and
+	/// rasterizer are ignored. Without any transforms, nothing is culled or drawn.
+	pub fn new(
+		render_pass: &MeshRenderPass,
+		mesh: &Mesh,
+		transforms: impl IntoIterator<Item = Transform>,
+	) -> Result<(Self, impl GpuFuture + Send + Sync + 'static), GpuCulledMeshError> {
+		if !render_pass.shaders.device.capabilities().supports_compute {
+			return Err(GpuCulledMeshError::ComputeUnsupported);
+		}
+
+		let geometry = mesh.geometry();
+		let center = geometry.bounds().center();
+		let radius =
+			(0..geometry.vertex_count())
+				.map(|i| geometry.position(i).distance(center))
+				.fold(0.0, f32::max);
+
+		let light_probes = if mesh.uses_light_probes { 1.0 } else { 0.0 };
+		let mut instances: Vec<_> =
+			transforms.into_iter()
+				.map(|transform| {
+					let max_scale = transform.scale.x.abs().max(transform.scale.y.abs()).max(transform.scale.z.abs());
+					InstanceData {
+						position: transform.position.extend(light_probes).into(),
+						scale: transform.scale.extend(mesh.vertex_animation_time).into(),
+						rotation: quat_to_xyzw(transform.rotation),
+						bounds: transform.transform_point(center).extend(radius * max_scale).into(),
+					}
+				})
+				.collect();
+
+		let queue = &render_pass.shaders.queue;
+		let device = queue.device();
+		let instance_count = instances.len() as u32;
+		if instances.is_empty() {
+			// buffers can't be empty, so this one is never culled or drawn
+			instances.push(InstanceData::default());
+		}
+		let storage = BufferUsage { storage_buffer: true, ..BufferUsage::none() };
+		let (instances, instances_future) = ImmutableBuffer::from_iter(instances.into_iter(), storage, queue.clone())?;
+		let visible =
+			DeviceLocalBuffer::array(device.clone(), instance_count.max(1) as usize, storage, Some(queue.family()))?;
+
+		let draws_usage =
+			BufferUsage {
+				indirect_buffer: true,
+				storage_buffer: true,
+				transfer_destination: true,
+				..BufferUsage::none()
+			};
+		let draws =
+			DeviceLocalBuffer::array(device.clone(), mesh.materials.len(), draws_usage, Some(queue.family()))?;
+		let reset =
+			mesh.materials.iter()
+				.map(|mat| DrawIndexedIndirectCommand {
+					index_count: match &mat.indices {
+						MaterialIndices::U16(indices) => indices.len() as u32,
+						MaterialIndices::U32(indices) => indices.len() as u32,
+					},
+					instance_count: 0,
+					first_index: 0,
+					vertex_offset: 0,
+					first_instance: 0,
+				})
+				.collect::<Vec<_>>();
+		let (draws_reset, draws_reset_future) =
+			ImmutableBuffer::from_iter(reset.into_iter(), BufferUsage::transfer_source(), queue.clone())?;
+
+		let cull_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				PersistentDescriptorSet::start(render_pass.pipeline_cull(), 0)
+					.add_buffer(instances.clone())
+					.unwrap()
+					.add_buffer(visible.clone())
+					.unwrap()
+					.add_buffer(draws.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		let user_params =
+			match &mesh.user_params {
+				Some(user_params) => user_params.clone(),
+				None =>
+					stats::track(Resource::Subbuffer, render_pass.user_params_pool().next([0; MESH_USER_PARAMS_SIZE])?),
+			};
+
+		Ok((
+			Self {
+				positions: mesh.positions.clone(),
+				normals: mesh.normals.clone(),
+				texcoords_main: mesh.texcoords_main.clone(),
+				ambient_occlusion: mesh.ambient_occlusion.clone(),
+				vertex_format: mesh.vertex_format,
+				materials: mesh.materials.clone(),
+				user_params: user_params,
+				instance_count: instance_count,
+				instances: instances,
+				visible: visible,
+				draws: draws,
+				draws_reset: draws_reset,
+				cull_desc: cull_desc,
+			},
+			instances_future.join(draws_reset_future)
+		))
+	}
+
+	pub fn instance_count(&self) -> u32 {
+		self.instance_count
+	}
+}
+impl Drawable3D for GpuCulledMesh {
+	fn record_before_render_pass(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		render_pass: &MeshRenderPass,
+		camera: &Camera,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		if self.instance_count == 0 {
+			return Ok(command_buffer);
+		}
+
+		let transform = camera.transform();
+		let projection = camera.tiled(camera.projection_vector());
+		let cull =
+			cs_cull::ty::Cull {
+				camera_pos: transform.position.extend(0.0).into(),
+				camera_rot: quat_to_xyzw(transform.rotation),
+				camera_proj: projection.projection.into(),
+				camera_offset: projection.offset.into(),
+				instance_count: self.instance_count,
+				draw_count: self.materials.len() as u32,
+			};
+		let groups = (self.instance_count + CULL_GROUP_SIZE - 1) / CULL_GROUP_SIZE;
+
+		Ok(
+			command_buffer
+				.copy_buffer(self.draws_reset.clone(), self.draws.clone())
+				.unwrap()
+				.dispatch([groups, 1, 1], render_pass.pipeline_cull(), self.cull_desc.clone(), cull)
+				.unwrap()
+		)
+	}
+
+	fn make_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		highlight: Option<[f32; 4]>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		let mut cmd = AutoCommandBufferBuilder
+			::secondary_graphics_one_time_submit(
+				render_pass.shaders.target_vertices.device().clone(),
+				queue_family,
+				render_pass.subpass_gbuffers.clone()
+			)?;
+		if self.instance_count == 0 {
+			return Ok(
+				cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+			);
+		}
+
+		let state =
+			DynamicState {
+				line_width: None,
+				viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+				scissors: None,
+			};
+
+		let pipeline = render_pass.pipeline_gbuffers_instanced(self.vertex_format);
+		let highlight_buffer =
+			stats::track(Resource::Subbuffer, render_pass.highlight_pool().next(highlight.unwrap_or([0.0; 4]))?);
+		let instance_desc =
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
+				PersistentDescriptorSet::start(pipeline.clone(), 1)
+					.add_buffer(self.instances.clone())
+					.unwrap()
+					.add_buffer(self.visible.clone())
+					.unwrap()
+					.add_buffer(highlight_buffer)
+					.unwrap()
+					.add_buffer(self.user_params.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			));
+
+		let vertices: Vec<Arc<BufferAccess + Send + Sync>> =
+			vec![
+				self.positions.clone(),
+				self.normals.clone(),
+				self.texcoords_main.clone(),
+				self.ambient_occlusion.clone()
+			];
+		for (i, mat) in self.materials.iter().enumerate() {
+			let sets = (camera_desc.clone(), instance_desc.clone(), mat.material.descriptor_set());
+			let draw = self.draws.clone().into_buffer_slice().slice(i..i + 1).unwrap();
+			let (pipeline, vertices) = (pipeline.clone(), vertices.clone());
+			cmd =
+				match &mat.indices {
+					MaterialIndices::U16(indices) =>
+						cmd.draw_indexed_indirect(pipeline, &state, vertices, indices.clone(), draw, sets, ()),
+					MaterialIndices::U32(indices) =>
+						cmd.draw_indexed_indirect(pipeline, &state, vertices, indices.clone(), draw, sets, ()),
+				}
+				.unwrap();
+		}
+
+		Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+	}
+
+	/// The geometry and materials are shared with the mesh the instances were made from, so they're only counted once
+	/// when it's in the same batch.
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_buffer(&self.user_params);
+		tally.add_buffer(&*self.positions);
+		tally.add_buffer(&*self.normals);
+		tally.add_buffer(&*self.texcoords_main);
+		tally.add_buffer(&*self.ambient_occlusion);
+		tally.add_buffer(&*self.instances);
+		tally.add_buffer(&*self.visible);
+		tally.add_buffer(&*self.draws);
+		tally.add_buffer(&*self.draws_reset);
+		tally.add_descriptor_set();
+
+		for mat in &self.materials {
+			match &mat.indices {
+				MaterialIndices::U16(indices) => tally.add_buffer(indices),
+				MaterialIndices::U32(indices) => tally.add_buffer(indices),
+			};
+			mat.material.resources(tally);
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum GpuCulledMeshError {
+	/// The device doesn't support compute shaders, which the cull runs on. See `Capabilities::supports_compute`.
+	ComputeUnsupported,
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl From<DeviceMemoryAllocError> for GpuCulledMeshError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		GpuCulledMeshError::DeviceMemoryAllocError(val)
+	}
+}
@@ -0,0 +1,106 @@
+use crate::device::DeviceCtx;
+use crate::geometry::MeshGeometry;
+use std::{ mem::size_of, sync::Arc };
+use vulkano::{ device::Device, format::Format };
+
+/// Texture coordinates past this are stored as full floats, since half floats lose more than a texel of a 1024 pixel
+/// texture beyond it.
+const HALF_TEXCOORD_LIMIT: f32 = 2.0;
+
+/// How a mesh's vertex attributes are stored on the GPU. The codec picks one for each mesh when it's uploaded, see
+/// `Mesh::vertex_format`. Both are read by the same shaders, through different pipelines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshVertexFormat {
+	/// 32 bit floats for every attribute, 36 bytes per vertex.
+	Full,
+	/// Normals with 10 bits per axis and texture coordinates as half floats, 24 bytes per vertex. Used when the device
+	/// reads both formats from vertex buffers and no texture coordinate is further than 2 from zero.
+	Packed,
+}
+impl MeshVertexFormat {
+	pub(super) fn choose(device: &Arc<Device>, geometry: &MeshGeometry) -> Self {
+		let ctx = DeviceCtx::find(device).expect("device has no context");
+		let supported = |format: Format| ctx.format_features(format).vertex_buffer();
+		let fits_half =
+			geometry.texcoords.iter()
+				.all(|uv| uv[0].abs() <= HALF_TEXCOORD_LIMIT && uv[1].abs() <= HALF_TEXCOORD_LIMIT);
+
+		if fits_half && supported(Format::A2B10G10R10SnormPack32) && supported(Format::R16G16Sfloat) {
+			MeshVertexFormat::Packed
+		} else {
+			MeshVertexFormat::Full
+		}
+	}
+
+	pub(super) fn normal_format(self) -> (Format, usize) {
+		match self {
+			MeshVertexFormat::Full => (Format::R32G32B32Sfloat, size_of::<[f32; 3]>()),
+			MeshVertexFormat::Packed => (Format::A2B10G10R10SnormPack32, size_of::<u32>()),
+		}
+	}
+
+	pub(super) fn texcoord_format(self) -> (Format, usize) {
+		match self {
+			MeshVertexFormat::Full => (Format::R32G32Sfloat, size_of::<[f32; 2]>()),
+			MeshVertexFormat::Packed => (Format::R16G16Sfloat, size_of::<[u16; 2]>()),
+		}
+	}
+
+	/// Bytes per vertex, including the position and ambient occlusion streams.
+	pub fn vertex_size(self) -> usize {
+		size_of::<[f32; 3]>() + self.normal_format().1 + self.texcoord_format().1 + size_of::<f32>()
+	}
+}
+
+/// Width of a mesh's indices on the GPU. 16 bits are used whenever every vertex can be addressed with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshIndexFormat {
+	U16,
+	U32,
+}
+impl MeshIndexFormat {
+	pub(super) fn choose(geometry: &MeshGeometry) -> Self {
+		if geometry.vertex_count() <= u16::max_value() as usize + 1 {
+			MeshIndexFormat::U16
+		} else {
+			MeshIndexFormat::U32
+		}
+	}
+
+	pub fn index_size(self) -> usize {
+		match self {
+			MeshIndexFormat::U16 => size_of::<u16>(),
+			MeshIndexFormat::U32 => size_of::<u32>(),
+		}
+	}
+}
+
+/// Signed normalized with x in the low bits, for `A2B10G10R10SnormPack32`.
+pub(super) fn pack_normal(normal: [f32; 3]) -> u32 {
+	let axis = |value: f32| ((value.max(-1.0).min(1.0) * 511.0).round() as i32 as u32) & 0x3ff;
+	axis(normal[0]) | axis(normal[1]) << 10 | axis(normal[2]) << 20
+}
+
+pub(super) fn pack_texcoord(texcoord: [f32; 2]) -> [u16; 2] {
+	[f32_to_f16(texcoord[0]), f32_to_f16(texcoord[1])]
+}
+
+/// Rounds to the nearest half float. Values too small for a normal half are flushed to zero, which only affects
+/// texture coordinates within 0.00006 of zero.
+fn f32_to_f16(value: f32) -> u16 {
+	let bits = value.to_bits();
+	let sign = ((bits >> 16) & 0x8000) as u16;
+	let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+	let mantissa = bits & 0x7f_ffff;
+
+	if exponent <= 0 {
+		return sign;
+	}
+	if exponent >= 0x1f {
+		return sign | 0x7c00;
+	}
+
+	// adding the rounding bit can carry into the exponent, which still gives the right result
+	let half = ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+	sign | half.min(0x7c00) as u16
+}
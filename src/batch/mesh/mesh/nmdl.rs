@@ -0,0 +1,796 @@
+use byteorder::{ ByteOrder, LE, WriteBytesExt };
+use std::io::{ self, prelude::* };
+
+/// Version written by `NiceModel::write`. Versions 0 and 1 are the old fixed header layout, which is still read.
+pub const NICE_MODEL_VERSION: u32 = 2;
+const CHUNK_ALIGNMENT: usize = 16;
+const HEADER_SIZE: usize = 16;
+const CHUNK_ENTRY_SIZE: usize = 12;
+
+const TAG_POSITIONS: [u8; 4] = *b"POSN";
+const TAG_NORMALS: [u8; 4] = *b"NRML";
+const TAG_TEXCOORDS_MAIN: [u8; 4] = *b"UV0 ";
+const TAG_TEXCOORDS_LIGHTMAP: [u8; 4] = *b"UV1 ";
+const TAG_INDICES: [u8; 4] = *b"INDX";
+const TAG_MATERIALS: [u8; 4] = *b"MATL";
+const TAG_PARALLAX: [u8; 4] = *b"PRLX";
+const TAG_LODS: [u8; 4] = *b"LODS";
+const TAG_BONES: [u8; 4] = *b"BONE";
+const TAG_COLLISION: [u8; 4] = *b"COLL";
+
+/// Contents of an nmdl file.
+///
+/// Version 2 files are a table of tagged chunks, each aligned to 16 bytes. Readers skip tags they don't know, so new
+/// chunks can be added without breaking old files or old readers.
+#[derive(Clone, Debug, Default)]
+pub struct NiceModel {
+	pub positions: Vec<[f32; 3]>,
+	pub normals: Vec<[f32; 3]>,
+	pub texcoords_main: Vec<[f32; 2]>,
+	pub texcoords_lightmap: Option<Vec<[f32; 2]>>,
+	pub indices: Vec<u32>,
+	/// Each material draws the next `index_count` indices.
+	pub materials: Vec<NiceModelMaterial>,
+	/// Lower detail index sets over the same vertices, from most to least detailed.
+	pub lods: Vec<NiceModelLod>,
+	pub bones: Vec<NiceModelBone>,
+	pub collision: Option<NiceModelCollision>,
+}
+impl NiceModel {
+	pub fn read(data: &[u8]) -> Result<Self, NiceModelError> {
+		let mut header = Reader::new(data, "header");
+		let magic = header.bytes(4).map_err(truncated)?;
+		if magic != b"nmdl" {
+			return Err(NiceModelError::BadMagic([magic[0], magic[1], magic[2], magic[3]]));
+		}
+
+		let model =
+			match header.u32().map_err(truncated)? {
+				0 | 1 => Self::read_fixed(data)?,
+				2 => Self::read_chunked(data)?,
+				version => return Err(NiceModelError::UnsupportedVersion(version)),
+			};
+
+		model.validate()?;
+		Ok(model)
+	}
+
+	/// Writes the current version of the format.
+	pub fn write(&self, out: &mut Write) -> io::Result<()> {
+		let mut chunks: Vec<([u8; 4], Vec<u8>)> = vec![];
+
+		chunks.push((TAG_POSITIONS, write_vec3s(&self.positions)));
+		chunks.push((TAG_NORMALS, write_vec3s(&self.normals)));
+		chunks.push((TAG_TEXCOORDS_MAIN, write_vec2s(&self.texcoords_main)));
+		if let Some(ref texcoords) = self.texcoords_lightmap {
+			chunks.push((TAG_TEXCOORDS_LIGHTMAP, write_vec2s(texcoords)));
+		}
+		chunks.push((TAG_INDICES, write_u32s(&self.indices)));
+
+		let mut buf = vec![];
+		buf.write_u32::<LE>(self.materials.len() as u32)?;
+		for material in &self.materials {
+			buf.write_u32::<LE>(material.index_count)?;
+			buf.write_u8(material.light_penetration)?;
+			buf.write_u8(material.subsurface_scattering)?;
+			buf.write_u16::<LE>(material.emissive_brightness)?;
+			buf.write_all(&material.base_color)?;
+			buf.write_u8(0)?;
+			write_string(&mut buf, material.texture1.as_ref().map_or("", |name| name.as_str()))?;
+			write_string(&mut buf, material.texture2.as_ref().map_or("", |name| name.as_str()))?;
+		}
+		chunks.push((TAG_MATERIALS, buf));
+
+		// separate from the materials, so files from before height maps still read
+		if self.materials.iter().any(|material| material.texture3.is_some() || material.height_scale != 0.0) {
+			let mut buf = vec![];
+			for material in &self.materials {
+				write_string(&mut buf, material.texture3.as_ref().map_or("", |name| name.as_str()))?;
+				buf.write_f32::<LE>(material.height_scale)?;
+				buf.write_u8(material.parallax_min_samples)?;
+				buf.write_u8(material.parallax_max_samples)?;
+			}
+			chunks.push((TAG_PARALLAX, buf));
+		}
+
+		if !self.lods.is_empty() {
+			let mut buf = vec![];
+			buf.write_u32::<LE>(self.lods.len() as u32)?;
+			for lod in &self.lods {
+				buf.write_f32::<LE>(lod.distance)?;
+				buf.write_u32::<LE>(lod.material_index_counts.len() as u32)?;
+				buf.write_u32::<LE>(lod.indices.len() as u32)?;
+				buf.extend(write_u32s(&lod.material_index_counts));
+				buf.extend(write_u32s(&lod.indices));
+			}
+			chunks.push((TAG_LODS, buf));
+		}
+
+		if !self.bones.is_empty() {
+			let mut buf = vec![];
+			buf.write_u32::<LE>(self.bones.len() as u32)?;
+			for bone in &self.bones {
+				write_string(&mut buf, &bone.name)?;
+				buf.write_u16::<LE>(bone.parent.unwrap_or(NO_PARENT))?;
+				for &x in bone.position.iter().chain(&bone.rotation) {
+					buf.write_f32::<LE>(x)?;
+				}
+			}
+			chunks.push((TAG_BONES, buf));
+		}
+
+		if let Some(ref collision) = self.collision {
+			let mut buf = vec![];
+			buf.write_u32::<LE>(collision.positions.len() as u32)?;
+			buf.write_u32::<LE>(collision.indices.len() as u32)?;
+			buf.extend(write_vec3s(&collision.positions));
+			buf.extend(write_u32s(&collision.indices));
+			chunks.push((TAG_COLLISION, buf));
+		}
+
+		let table_end = HEADER_SIZE + chunks.len() * CHUNK_ENTRY_SIZE;
+		let mut offset = align(table_end);
+		let mut offsets = Vec::with_capacity(chunks.len());
+		for (_, data) in &chunks {
+			offsets.push(offset);
+			offset = align(offset + data.len());
+		}
+
+		out.write_all(b"nmdl")?;
+		out.write_u32::<LE>(NICE_MODEL_VERSION)?;
+		out.write_u32::<LE>(chunks.len() as u32)?;
+		out.write_u32::<LE>(0)?;
+		for ((tag, data), &offset) in chunks.iter().zip(&offsets) {
+			out.write_all(tag)?;
+			out.write_u32::<LE>(offset as u32)?;
+			out.write_u32::<LE>(data.len() as u32)?;
+		}
+
+		let mut written = table_end;
+		for ((_, data), &offset) in chunks.iter().zip(&offsets) {
+			out.write_all(&[0; CHUNK_ALIGNMENT][..offset - written])?;
+			out.write_all(data)?;
+			written = offset + data.len();
+		}
+
+		Ok(())
+	}
+
+	fn read_chunked(data: &[u8]) -> Result<Self, NiceModelError> {
+		let mut header = Reader::new(data, "header");
+		header.bytes(8)?;
+		let chunk_count = header.u32().map_err(truncated)? as usize;
+		header.u32().map_err(truncated)?;
+
+		let mut table = Reader::at(data, HEADER_SIZE, "chunk_table")?;
+		let entries = table.bytes(chunk_count.checked_mul(CHUNK_ENTRY_SIZE).unwrap_or(usize::max_value()))?;
+
+		let mut chunks: Vec<([u8; 4], &[u8])> = Vec::with_capacity(chunk_count);
+		for entry in entries.chunks(CHUNK_ENTRY_SIZE) {
+			let tag = [entry[0], entry[1], entry[2], entry[3]];
+			let offset = LE::read_u32(&entry[4..]) as usize;
+			let size = LE::read_u32(&entry[8..]) as usize;
+			if chunks.iter().any(|&(existing, _)| existing == tag) {
+				return Err(NiceModelError::DuplicateChunk(tag));
+			}
+
+			let chunk = Reader::at(data, offset, "chunk")?.bytes(size)?;
+			chunks.push((tag, chunk));
+		}
+
+		// unknown tags are left alone, so newer files still load
+		let find =
+			|tag: [u8; 4], section: &'static str| {
+				chunks.iter().find(|&&(chunk_tag, _)| chunk_tag == tag).map(|&(_, data)| Reader::new(data, section))
+			};
+		let require =
+			|tag: [u8; 4], section: &'static str| find(tag, section).ok_or(NiceModelError::MissingChunk(section));
+
+		let mut reader = require(TAG_POSITIONS, "positions")?;
+		let positions = reader.rest(12, read_vec3);
+		let mut reader = require(TAG_NORMALS, "normals")?;
+		let normals = reader.rest(12, read_vec3);
+		let mut reader = require(TAG_TEXCOORDS_MAIN, "texcoords_main")?;
+		let texcoords_main = reader.rest(8, read_vec2);
+		let texcoords_lightmap =
+			find(TAG_TEXCOORDS_LIGHTMAP, "texcoords_lightmap").map(|mut reader| reader.rest(8, read_vec2));
+		let mut reader = require(TAG_INDICES, "indices")?;
+		let indices = reader.rest(4, LE::read_u32);
+
+		let mut reader = require(TAG_MATERIALS, "materials")?;
+		let material_count = reader.u32()? as usize;
+		let mut materials = vec![];
+		for material in 0..material_count {
+			let index_count = reader.u32()?;
+			let light_penetration = reader.u8()?;
+			let subsurface_scattering = reader.u8()?;
+			let emissive_brightness = reader.u16()?;
+			let color = reader.bytes(4)?;
+			let texture1 = reader.string(|| NiceModelError::InvalidTexturePath { material: material })?;
+			let texture2 = reader.string(|| NiceModelError::InvalidTexturePath { material: material })?;
+			materials.push(NiceModelMaterial {
+				index_count: index_count,
+				light_penetration: light_penetration,
+				subsurface_scattering: subsurface_scattering,
+				emissive_brightness: emissive_brightness,
+				base_color: [color[0], color[1], color[2]],
+				texture1: Some(texture1).filter(|name| !name.is_empty()),
+				texture2: Some(texture2).filter(|name| !name.is_empty()),
+				..NiceModelMaterial::default()
+			});
+		}
+
+		// one entry per material, in the same order
+		if let Some(mut reader) = find(TAG_PARALLAX, "parallax") {
+			for (index, material) in materials.iter_mut().enumerate() {
+				let texture3 = reader.string(|| NiceModelError::InvalidTexturePath { material: index })?;
+				material.texture3 = Some(texture3).filter(|name| !name.is_empty());
+				material.height_scale = reader.f32()?;
+				material.parallax_min_samples = reader.u8()?;
+				material.parallax_max_samples = reader.u8()?;
+			}
+		}
+
+		let mut lods = vec![];
+		if let Some(mut reader) = find(TAG_LODS, "lods") {
+			for _ in 0..reader.u32()? {
+				let distance = reader.f32()?;
+				let material_count = reader.u32()? as usize;
+				let index_count = reader.u32()? as usize;
+				lods.push(NiceModelLod {
+					distance: distance,
+					material_index_counts: reader.array(material_count, 4, LE::read_u32)?,
+					indices: reader.array(index_count, 4, LE::read_u32)?,
+				});
+			}
+		}
+
+		let mut bones = vec![];
+		if let Some(mut reader) = find(TAG_BONES, "bones") {
+			for bone in 0..reader.u32()? as usize {
+				let name = reader.string(|| NiceModelError::InvalidBoneName { bone: bone })?;
+				let parent = reader.u16()?;
+				let transform = reader.array(7, 4, LE::read_f32)?;
+				bones.push(NiceModelBone {
+					name: name,
+					parent: if parent == NO_PARENT { None } else { Some(parent) },
+					position: [transform[0], transform[1], transform[2]],
+					rotation: [transform[3], transform[4], transform[5], transform[6]],
+				});
+			}
+		}
+
+		let collision =
+			match find(TAG_COLLISION, "collision") {
+				Some(mut reader) => {
+					let vertex_count = reader.u32()? as usize;
+					let index_count = reader.u32()? as usize;
+					Some(NiceModelCollision {
+						positions: reader.array(vertex_count, 12, read_vec3)?,
+						indices: reader.array(index_count, 4, LE::read_u32)?,
+					})
+				},
+				None => None,
+			};
+
+		Ok(Self {
+			positions: positions,
+			normals: normals,
+			texcoords_main: texcoords_main,
+			texcoords_lightmap: texcoords_lightmap,
+			indices: indices,
+			materials: materials,
+			lods: lods,
+			bones: bones,
+			collision: collision,
+		})
+	}
+
+	/// Versions 0 and 1: a fixed header of counts and offsets.
+	fn read_fixed(data: &[u8]) -> Result<Self, NiceModelError> {
+		let mut header = Reader::new(data, "header");
+		header.bytes(8)?;
+		let vertex_count = header.u32().map_err(truncated)? as usize;
+		let positions_offset = header.u32().map_err(truncated)? as usize;
+		let normals_offset = header.u32().map_err(truncated)? as usize;
+		let texcoords_main_offset = header.u32().map_err(truncated)? as usize;
+		let _texcoords_lightmap_offset = header.u32().map_err(truncated)? as usize;
+		let index_count = header.u32().map_err(truncated)? as usize;
+		let indices_offset = header.u32().map_err(truncated)? as usize;
+		let material_count = header.u8().map_err(truncated)? as usize;
+		let materials_offset = header.u32().map_err(truncated)? as usize;
+
+		let positions = Reader::at(data, positions_offset, "positions")?.array(vertex_count, 12, read_vec3)?;
+		let normals = Reader::at(data, normals_offset, "normals")?.array(vertex_count, 12, read_vec3)?;
+		let texcoords_main =
+			Reader::at(data, texcoords_main_offset, "texcoords_main")?.array(vertex_count, 8, read_vec2)?;
+		let indices = Reader::at(data, indices_offset, "indices")?.array(index_count, 4, LE::read_u32)?;
+
+		let mut reader = Reader::at(data, materials_offset, "materials")?;
+		let mut materials = vec![];
+		for material in 0..material_count {
+			let index_count = reader.u32()?;
+			let texture1_name_size = reader.u16()? as usize;
+			let texture1_name_offset = reader.u32()? as usize;
+			let texture2_name_size = reader.u16()? as usize;
+			let texture2_name_offset = reader.u32()? as usize;
+			let light_penetration = reader.u8()?;
+			let subsurface_scattering = reader.u8()?;
+			let emissive_brightness = reader.u16()?;
+			let color = reader.bytes(3)?;
+
+			let texture_name = |offset, size| -> Result<Option<String>, NiceModelError> {
+				if size == 0 {
+					return Ok(None);
+				}
+
+				let name = Reader::at(data, offset, "texture_name")?.bytes(size)?;
+				String::from_utf8(name.to_vec())
+					.map(Some)
+					.map_err(|_| NiceModelError::InvalidTexturePath { material: material })
+			};
+
+			materials.push(NiceModelMaterial {
+				index_count: index_count,
+				light_penetration: light_penetration,
+				subsurface_scattering: subsurface_scattering,
+				emissive_brightness: emissive_brightness,
+				base_color: [color[0], color[1], color[2]],
+				texture1: texture_name(texture1_name_offset, texture1_name_size)?,
+				texture2: texture_name(texture2_name_offset, texture2_name_size)?,
+				..NiceModelMaterial::default()
+			});
+		}
+
+		Ok(Self {
+			positions: positions,
+			normals: normals,
+			texcoords_main: texcoords_main,
+			texcoords_lightmap: None,
+			indices: indices,
+			materials: materials,
+			lods: vec![],
+			bones: vec![],
+			collision: None,
+		})
+	}
+
+	/// Catches anything that would make the GPU read out of bounds later.
+	fn validate(&self) -> Result<(), NiceModelError> {
+		let vertex_count = self.positions.len();
+		if self.normals.len() != vertex_count {
+			return Err(NiceModelError::VertexCountMismatch { section: "normals" });
+		}
+		if self.texcoords_main.len() != vertex_count {
+			return Err(NiceModelError::VertexCountMismatch { section: "texcoords_main" });
+		}
+		if self.texcoords_lightmap.as_ref().map_or(false, |texcoords| texcoords.len() != vertex_count) {
+			return Err(NiceModelError::VertexCountMismatch { section: "texcoords_lightmap" });
+		}
+
+		check_indices(&self.indices, vertex_count)?;
+		check_material_counts(self.materials.iter().map(|material| material.index_count), self.indices.len())?;
+
+		for lod in &self.lods {
+			check_indices(&lod.indices, vertex_count)?;
+			check_material_counts(lod.material_index_counts.iter().cloned(), lod.indices.len())?;
+		}
+
+		for (bone, data) in self.bones.iter().enumerate() {
+			if data.parent.map_or(false, |parent| parent as usize >= bone) {
+				return Err(NiceModelError::InvalidBoneParent { bone: bone });
+			}
+		}
+
+		if let Some(ref collision) = self.collision {
+			check_indices(&collision.indices, collision.positions.len())?;
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiceModelMaterial {
+	pub index_count: u32,
+	pub light_penetration: u8,
+	pub subsurface_scattering: u8,
+	pub emissive_brightness: u16,
+	/// sRGB.
+	pub base_color: [u8; 3],
+	/// Relative to the model's directory.
+	pub texture1: Option<String>,
+	pub texture2: Option<String>,
+	/// Height map, like `MaterialDesc::texture3`.
+	pub texture3: Option<String>,
+	pub height_scale: f32,
+	pub parallax_min_samples: u8,
+	pub parallax_max_samples: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiceModelLod {
+	/// Camera distance this level starts at.
+	pub distance: f32,
+	/// Like `NiceModelMaterial::index_count`, one per material.
+	pub material_index_counts: Vec<u32>,
+	pub indices: Vec<u32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiceModelBone {
+	pub name: String,
+	/// Always an earlier bone, so bones can be processed in order.
+	pub parent: Option<u16>,
+	pub position: [f32; 3],
+	/// Quaternion, x y z w.
+	pub rotation: [f32; 4],
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NiceModelCollision {
+	pub positions: Vec<[f32; 3]>,
+	pub indices: Vec<u32>,
+}
+
+/// Ways an nmdl file can be malformed. These are caught before anything is allocated or uploaded.
+#[derive(Debug)]
+pub enum NiceModelError {
+	BadMagic([u8; 4]),
+	UnsupportedVersion(u32),
+	/// The file ends before the header does.
+	Truncated,
+	/// A section's offset and size point past the end of the file or chunk.
+	OutOfBounds { section: &'static str, offset: u64 },
+	MissingChunk(&'static str),
+	DuplicateChunk([u8; 4]),
+	VertexCountMismatch { section: &'static str },
+	IndexOutOfRange { index: u32, vertex_count: usize },
+	/// The materials claim more indices than the file has.
+	MaterialIndicesOutOfRange,
+	InvalidTexturePath { material: usize },
+	InvalidBoneName { bone: usize },
+	InvalidBoneParent { bone: usize },
+}
+
+const NO_PARENT: u16 = 0xFFFF;
+
+/// Bounds checked little-endian reads, so a bad count fails before anything is allocated for it.
+struct Reader<'a> {
+	data: &'a [u8],
+	start: usize,
+	pos: usize,
+	section: &'static str,
+}
+impl<'a> Reader<'a> {
+	fn new(data: &'a [u8], section: &'static str) -> Self {
+		Self { data: data, start: 0, pos: 0, section: section }
+	}
+
+	fn at(data: &'a [u8], offset: usize, section: &'static str) -> Result<Self, NiceModelError> {
+		if offset > data.len() {
+			return Err(NiceModelError::OutOfBounds { section: section, offset: offset as u64 });
+		}
+		Ok(Self { data: &data[offset..], start: offset, pos: 0, section: section })
+	}
+
+	fn bytes(&mut self, len: usize) -> Result<&'a [u8], NiceModelError> {
+		match self.pos.checked_add(len) {
+			Some(end) if end <= self.data.len() => {
+				let bytes = &self.data[self.pos..end];
+				self.pos = end;
+				Ok(bytes)
+			},
+			_ => Err(NiceModelError::OutOfBounds { section: self.section, offset: (self.start + self.pos) as u64 }),
+		}
+	}
+
+	fn u8(&mut self) -> Result<u8, NiceModelError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, NiceModelError> {
+		Ok(LE::read_u16(self.bytes(2)?))
+	}
+
+	fn u32(&mut self) -> Result<u32, NiceModelError> {
+		Ok(LE::read_u32(self.bytes(4)?))
+	}
+
+	fn f32(&mut self) -> Result<f32, NiceModelError> {
+		Ok(LE::read_f32(self.bytes(4)?))
+	}
+
+	/// u16 length, then UTF-8.
+	fn string(&mut self, invalid: impl FnOnce() -> NiceModelError) -> Result<String, NiceModelError> {
+		let len = self.u16()? as usize;
+		String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| invalid())
+	}
+
+	fn array<T>(&mut self, count: usize, stride: usize, read: impl Fn(&[u8]) -> T) -> Result<Vec<T>, NiceModelError> {
+		let len = count.checked_mul(stride).unwrap_or(usize::max_value());
+		Ok(self.bytes(len)?.chunks(stride).map(read).collect())
+	}
+
+	/// Everything left, ignoring a partial element at the end.
+	fn rest<T>(&mut self, stride: usize, read: impl Fn(&[u8]) -> T) -> Vec<T> {
+		let count = (self.data.len() - self.pos) / stride;
+		self.array(count, stride, read).unwrap()
+	}
+}
+
+fn truncated(_: NiceModelError) -> NiceModelError {
+	NiceModelError::Truncated
+}
+
+fn read_vec3(bytes: &[u8]) -> [f32; 3] {
+	[LE::read_f32(bytes), LE::read_f32(&bytes[4..]), LE::read_f32(&bytes[8..])]
+}
+
+fn read_vec2(bytes: &[u8]) -> [f32; 2] {
+	[LE::read_f32(bytes), LE::read_f32(&bytes[4..])]
+}
+
+fn write_vec3s(data: &[[f32; 3]]) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(data.len() * 12);
+	for val in data {
+		buf.write_f32::<LE>(val[0]).unwrap();
+		buf.write_f32::<LE>(val[1]).unwrap();
+		buf.write_f32::<LE>(val[2]).unwrap();
+	}
+	buf
+}
+
+fn write_vec2s(data: &[[f32; 2]]) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(data.len() * 8);
+	for val in data {
+		buf.write_f32::<LE>(val[0]).unwrap();
+		buf.write_f32::<LE>(val[1]).unwrap();
+	}
+	buf
+}
+
+fn write_u32s(data: &[u32]) -> Vec<u8> {
+	let mut buf = Vec::with_capacity(data.len() * 4);
+	for &val in data {
+		buf.write_u32::<LE>(val).unwrap();
+	}
+	buf
+}
+
+fn write_string(buf: &mut Vec<u8>, val: &str) -> io::Result<()> {
+	assert!(val.len() <= u16::max_value() as usize, "nmdl strings must fit in 64 KiB: {}", val);
+	buf.write_u16::<LE>(val.len() as u16)?;
+	buf.write_all(val.as_bytes())
+}
+
+fn align(offset: usize) -> usize {
+	(offset + CHUNK_ALIGNMENT - 1) / CHUNK_ALIGNMENT * CHUNK_ALIGNMENT
+}
+
+fn check_indices(indices: &[u32], vertex_count: usize) -> Result<(), NiceModelError> {
+	match indices.iter().find(|&&index| index as usize >= vertex_count) {
+		Some(&index) => Err(NiceModelError::IndexOutOfRange { index: index, vertex_count: vertex_count }),
+		None => Ok(()),
+	}
+}
+
+fn check_material_counts(counts: impl Iterator<Item = u32>, index_count: usize) -> Result<(), NiceModelError> {
+	let total = counts.fold(Some(0usize), |sum, count| sum.and_then(|sum| sum.checked_add(count as usize)));
+	if total.map_or(true, |total| total > index_count) {
+		return Err(NiceModelError::MaterialIndicesOutOfRange);
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sample() -> NiceModel {
+		NiceModel {
+			positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.5]],
+			normals: vec![[0.0, 0.0, 1.0]; 4],
+			texcoords_main: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]],
+			texcoords_lightmap: Some(vec![[0.5, 0.5]; 4]),
+			indices: vec![0, 1, 2, 1, 3, 2],
+			materials: vec![
+				NiceModelMaterial {
+					index_count: 3,
+					base_color: [255, 128, 0],
+					texture1: Some("albedo.png".to_owned()),
+					..NiceModelMaterial::default()
+				},
+				NiceModelMaterial {
+					index_count: 3,
+					light_penetration: 10,
+					emissive_brightness: 500,
+					texture3: Some("height.png".to_owned()),
+					height_scale: 0.05,
+					parallax_min_samples: 4,
+					parallax_max_samples: 16,
+					..NiceModelMaterial::default()
+				},
+			],
+			lods: vec![NiceModelLod { distance: 20.0, material_index_counts: vec![3, 0], indices: vec![0, 1, 2] }],
+			bones: vec![bone("root", None), bone("arm", Some(0))],
+			collision:
+				Some(NiceModelCollision {
+					positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+					indices: vec![0, 1, 2],
+				}),
+		}
+	}
+
+	fn bone(name: &str, parent: Option<u16>) -> NiceModelBone {
+		NiceModelBone {
+			name: name.to_owned(),
+			parent: parent,
+			position: [1.0, 2.0, 3.0],
+			rotation: [0.0, 0.0, 0.0, 1.0],
+		}
+	}
+
+	fn write(model: &NiceModel) -> Vec<u8> {
+		let mut data = vec![];
+		model.write(&mut data).unwrap();
+		data
+	}
+
+	/// Offset of the first chunk table entry with `tag`.
+	fn chunk_entry(data: &[u8], tag: [u8; 4]) -> usize {
+		let chunk_count = LE::read_u32(&data[8..]) as usize;
+		(0..chunk_count)
+			.map(|i| HEADER_SIZE + i * CHUNK_ENTRY_SIZE)
+			.find(|&entry| data[entry..entry + 4] == tag)
+			.unwrap()
+	}
+
+	#[test]
+	fn write_read_round_trip() {
+		let model = sample();
+		let read = NiceModel::read(&write(&model)).unwrap();
+		assert_eq!(read.positions, model.positions);
+		assert_eq!(read.normals, model.normals);
+		assert_eq!(read.texcoords_main, model.texcoords_main);
+		assert_eq!(read.texcoords_lightmap, model.texcoords_lightmap);
+		assert_eq!(read.indices, model.indices);
+		assert_eq!(read.materials, model.materials);
+		assert_eq!(read.lods, model.lods);
+		assert_eq!(read.bones, model.bones);
+		assert_eq!(read.collision, model.collision);
+	}
+
+	#[test]
+	fn round_trip_without_optional_chunks() {
+		let model = NiceModel { texcoords_lightmap: None, lods: vec![], bones: vec![], collision: None, ..sample() };
+		let read = NiceModel::read(&write(&model)).unwrap();
+		assert_eq!(read.texcoords_lightmap, None);
+		assert!(read.lods.is_empty() && read.bones.is_empty() && read.collision.is_none());
+		assert_eq!(read.materials, model.materials);
+	}
+
+	#[test]
+	fn truncated_input() {
+		let data = write(&sample());
+		for len in 0..data.len() {
+			assert!(NiceModel::read(&data[..len]).is_err(), "read {} of {} bytes", len, data.len());
+		}
+		match NiceModel::read(b"nmdl") {
+			Err(NiceModelError::Truncated) => (),
+			result => panic!("{:?}", result),
+		}
+	}
+
+	#[test]
+	fn bad_magic_and_version() {
+		let mut data = write(&sample());
+		data[0..4].copy_from_slice(b"NMDL");
+		match NiceModel::read(&data) {
+			Err(NiceModelError::BadMagic(magic)) => assert_eq!(&magic, b"NMDL"),
+			result => panic!("{:?}", result),
+		}
+
+		let mut data = write(&sample());
+		LE::write_u32(&mut data[4..], NICE_MODEL_VERSION + 1);
+		match NiceModel::read(&data) {
+			Err(NiceModelError::UnsupportedVersion(version)) => assert_eq!(version, NICE_MODEL_VERSION + 1),
+			result => panic!("{:?}", result),
+		}
+	}
+
+	#[test]
+	fn huge_counts() {
+		// fails on the bounds check instead of trying to allocate for the count
+		let mut data = write(&sample());
+		LE::write_u32(&mut data[8..], u32::max_value());
+		match NiceModel::read(&data) {
+			Err(NiceModelError::OutOfBounds { section: "chunk_table", .. }) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut data = write(&sample());
+		let entry = chunk_entry(&data, TAG_COLLISION);
+		let offset = LE::read_u32(&data[entry + 4..]) as usize;
+		LE::write_u32(&mut data[offset..], u32::max_value());
+		match NiceModel::read(&data) {
+			Err(NiceModelError::OutOfBounds { section: "collision", .. }) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut data = write(&sample());
+		let entry = chunk_entry(&data, TAG_LODS);
+		let offset = LE::read_u32(&data[entry + 4..]) as usize;
+		LE::write_u32(&mut data[offset..], u32::max_value());
+		assert!(NiceModel::read(&data).is_err());
+
+		let mut data = vec![0; 64];
+		data[0..4].copy_from_slice(b"nmdl");
+		LE::write_u32(&mut data[8..], u32::max_value());
+		match NiceModel::read(&data) {
+			Err(NiceModelError::OutOfBounds { section: "positions", .. }) => (),
+			result => panic!("{:?}", result),
+		}
+	}
+
+	#[test]
+	fn out_of_range_offsets() {
+		let mut data = write(&sample());
+		let entry = chunk_entry(&data, TAG_INDICES);
+		LE::write_u32(&mut data[entry + 4..], u32::max_value());
+		match NiceModel::read(&data) {
+			Err(NiceModelError::OutOfBounds { section: "chunk", .. }) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut data = write(&sample());
+		let entry = chunk_entry(&data, TAG_INDICES);
+		LE::write_u32(&mut data[entry + 8..], u32::max_value());
+		match NiceModel::read(&data) {
+			Err(NiceModelError::OutOfBounds { section: "chunk", .. }) => (),
+			result => panic!("{:?}", result),
+		}
+	}
+
+	#[test]
+	fn invalid_contents() {
+		let model = NiceModel { indices: vec![0, 1, 4, 1, 3, 2], ..sample() };
+		match NiceModel::read(&write(&model)) {
+			Err(NiceModelError::IndexOutOfRange { index: 4, vertex_count: 4 }) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut model = sample();
+		model.materials[1].index_count = 4;
+		match NiceModel::read(&write(&model)) {
+			Err(NiceModelError::MaterialIndicesOutOfRange) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut model = sample();
+		model.bones[0].parent = Some(1);
+		match NiceModel::read(&write(&model)) {
+			Err(NiceModelError::InvalidBoneParent { bone: 0 }) => (),
+			result => panic!("{:?}", result),
+		}
+
+		let mut model = sample();
+		model.normals.pop();
+		match NiceModel::read(&write(&model)) {
+			Err(NiceModelError::VertexCountMismatch { section: "normals" }) => (),
+			result => panic!("{:?}", result),
+		}
+	}
+
+	#[test]
+	fn corrupt_bytes_dont_panic() {
+		let data = write(&sample());
+		for i in 0..data.len() {
+			for &byte in &[0x00, 0x7F, 0x80, 0xFF] {
+				let mut data = data.clone();
+				data[i] = byte;
+				let _ = NiceModel::read(&data);
+			}
+		}
+	}
+}
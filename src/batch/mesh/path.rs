@@ -0,0 +1,127 @@
+use crate::batch::mesh::{ Drawable3D, MeshRenderPass, ribbon::RibbonVertex };
+use crate::color::Color;
+use crate::math::Spline;
+use crate::stats::{ self, Resource };
+use std::sync::Arc;
+use vulkano::{
+	buffer::{ BufferAccess, CpuBufferPool },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::viewport::Viewport,
+};
+
+/// Draws a `Spline` as a thin unlit line in the transparent pass, for laying out cutscenes and patrol routes.
+pub struct DebugPath {
+	texture_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	vertex_pool: CpuBufferPool<RibbonVertex>,
+	vertices: Vec<RibbonVertex>,
+	width: f32,
+	color: [f32; 4],
+}
+impl DebugPath {
+	/// `width` is in world units.
+	pub fn new(render_pass: &MeshRenderPass, spline: &Spline, width: f32, color: Color) -> Self {
+		let mut path =
+			Self {
+				texture_desc:
+					Arc::new(stats::track(
+						Resource::DescriptorSet,
+						PersistentDescriptorSet::start(render_pass.pipeline_ribbon.clone(), 1)
+							.add_sampled_image(
+								render_pass.shaders.white_pixel.clone(),
+								render_pass.shaders.clamp_sampler.clone()
+							)
+							.unwrap()
+							.build()
+							.unwrap(),
+					)),
+				vertex_pool: CpuBufferPool::vertex_buffer(render_pass.shaders.target_vertices.device().clone()),
+				vertices: vec![],
+				width: width,
+				color: color.to_linear(),
+			};
+		path.set_spline(spline);
+		path
+	}
+
+	/// Resamples the line after the spline is edited.
+	pub fn set_spline(&mut self, spline: &Spline) {
+		let samples = spline.segment_count() * 16;
+		let length = spline.length();
+		let half_width = self.width * 0.5;
+
+		self.vertices.clear();
+		for i in 0..=samples {
+			let u = i as f32 / samples as f32;
+			let distance = length * u;
+			let position = spline.position_at_distance(distance).into();
+			let tangent = spline.direction_at_distance(distance).into();
+
+			for &(offset, v) in &[(half_width, 0.0), (-half_width, 1.0)] {
+				self.vertices.push(RibbonVertex {
+					position: position,
+					tangent: tangent,
+					offset: offset,
+					tex_coords: [u, v],
+					color: self.color,
+				});
+			}
+		}
+	}
+}
+impl Drawable3D for DebugPath {
+	fn make_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		_camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		_highlight: Option<[f32; 4]>,
+		queue_family: QueueFamily,
+		_dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		// nothing opaque
+		Ok(
+			AutoCommandBufferBuilder
+				::secondary_graphics_one_time_submit(
+					render_pass.shaders.target_vertices.device().clone(),
+					queue_family,
+					render_pass.subpass_gbuffers.clone()
+				)?
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+
+	fn make_transparent_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let vertices = stats::track(Resource::Subbuffer, self.vertex_pool.chunk(self.vertices.iter().cloned())?);
+
+		let cmd = AutoCommandBufferBuilder
+			::secondary_graphics_one_time_submit(
+				render_pass.shaders.target_vertices.device().clone(),
+				queue_family,
+				render_pass.subpass_transparent.clone()
+			)?
+			.draw(
+				render_pass.pipeline_ribbon.clone(),
+				&DynamicState {
+					line_width: None,
+					viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+					scissors: None,
+				},
+				vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+				(camera_desc.clone(), self.texture_desc.clone()),
+				()
+			)
+			.unwrap();
+
+		let cmd = cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+		Ok(Some(cmd))
+	}
+}
@@ -0,0 +1,106 @@
+use crate::anim::Lerp;
+use crate::batch::mesh::AmbientLight;
+use cgmath::{ vec3, ElementWise, Vector3 };
+
+/// Ambient light sampled on a 3D grid over a level, so meshes moving through it pick up the light around them instead
+/// of the batch's single `AmbientLight`, e.g. darker under a bridge and tinted next to a lit wall. Set it with
+/// `MeshBatch::set_light_probes`, and mark the meshes it lights with `Mesh::set_uses_light_probes`.
+///
+/// The lighting pass interpolates between the 8 probes around each pixel of those meshes. Probes don't know about
+/// walls, so place the grid densely enough that no probe between two rooms matters much.
+#[derive(Clone, Debug)]
+pub struct LightProbeGrid {
+	origin: Vector3<f32>,
+	spacing: Vector3<f32>,
+	counts: [u32; 3],
+	/// x fastest, then y, then z.
+	probes: Vec<AmbientLight>,
+}
+impl LightProbeGrid {
+	/// `counts` probes along each axis, `spacing` world units apart, starting at `origin` and all holding `ambient`.
+	pub fn new(origin: Vector3<f32>, spacing: Vector3<f32>, counts: [u32; 3], ambient: AmbientLight) -> Self {
+		assert!(counts.iter().all(|&count| count > 0), "light probe grids need a probe along each axis");
+		assert!(spacing.x > 0.0 && spacing.y > 0.0 && spacing.z > 0.0, "light probe spacing has to be positive");
+
+		let len = counts[0] as usize * counts[1] as usize * counts[2] as usize;
+		Self { origin: origin, spacing: spacing, counts: counts, probes: vec![ambient; len] }
+	}
+
+	pub fn origin(&self) -> Vector3<f32> {
+		self.origin
+	}
+
+	pub fn spacing(&self) -> Vector3<f32> {
+		self.spacing
+	}
+
+	pub fn counts(&self) -> [u32; 3] {
+		self.counts
+	}
+
+	/// Where probe `index` sits, in world space.
+	pub fn position(&self, index: [u32; 3]) -> Vector3<f32> {
+		self.origin + vec3(index[0] as f32, index[1] as f32, index[2] as f32).mul_element_wise(self.spacing)
+	}
+
+	pub fn probe(&self, index: [u32; 3]) -> &AmbientLight {
+		&self.probes[self.flat_index(index)]
+	}
+
+	/// Takes effect once the grid is given to `MeshBatch::set_light_probes` again.
+	pub fn set_probe(&mut self, index: [u32; 3], ambient: AmbientLight) {
+		let index = self.flat_index(index);
+		self.probes[index] = ambient;
+	}
+
+	/// Sets every probe to what `bake` returns for its position, e.g. the projection of an environment captured from
+	/// there with `AmbientLight::from_image`, or light gathered by a level editor.
+	pub fn bake(&mut self, mut bake: impl FnMut(Vector3<f32>) -> AmbientLight) {
+		for z in 0..self.counts[2] {
+			for y in 0..self.counts[1] {
+				for x in 0..self.counts[0] {
+					let ambient = bake(self.position([x, y, z]));
+					self.set_probe([x, y, z], ambient);
+				}
+			}
+		}
+	}
+
+	/// Interpolates the probes around `position` like the lighting pass does, for lighting things it doesn't reach,
+	/// like transparent drawables. Positions outside the grid take the nearest probes on its edge.
+	pub fn sample(&self, position: Vector3<f32>) -> AmbientLight {
+		let cell = (position - self.origin).div_element_wise(self.spacing);
+		let mut base = [0; 3];
+		let mut next = [0; 3];
+		let mut t = [0.0; 3];
+		for axis in 0..3 {
+			let max = (self.counts[axis] - 1) as f32;
+			let cell = cell[axis].max(0.0).min(max);
+			base[axis] = cell.floor() as u32;
+			next[axis] = (base[axis] + 1).min(self.counts[axis] - 1);
+			t[axis] = cell - base[axis] as f32;
+		}
+
+		let probe = |x: u32, y: u32, z: u32| *self.probe([x, y, z]);
+		let along_x = |y: u32, z: u32| probe(base[0], y, z).lerp(probe(next[0], y, z), t[0]);
+		let along_y = |z: u32| along_x(base[1], z).lerp(along_x(next[1], z), t[1]);
+		along_y(base[2]).lerp(along_y(next[2]), t[2])
+	}
+
+	/// The probes' coefficients for the lighting pass, 9 per probe in the grid's order. The exposure is applied in
+	/// the shader, so these don't change with it.
+	pub(super) fn coefficients(&self) -> Vec<[f32; 4]> {
+		self.probes.iter().flat_map(|probe| probe.uniform_coefficients(1.0).to_vec()).collect()
+	}
+
+	fn flat_index(&self, index: [u32; 3]) -> usize {
+		assert!(
+			index[0] < self.counts[0] && index[1] < self.counts[1] && index[2] < self.counts[2],
+			"light probe {:?} is outside a grid of {:?}",
+			index,
+			self.counts
+		);
+		let [x, y, z] = [index[0] as usize, index[1] as usize, index[2] as usize];
+		x + self.counts[0] as usize * (y + self.counts[1] as usize * z)
+	}
+}
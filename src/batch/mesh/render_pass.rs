@@ -1,71 +1,309 @@
-use crate::batch::mesh::{ ALBEDO_FORMAT, NORMAL_FORMAT, DEPTH_FORMAT, MeshShaders, TargetVertex, mesh::MeshVertexDefinition };
-use std::sync::Arc;
+use crate::batch::mesh::{
+	MemoryProfile,
+	MeshShaders,
+	TargetVertex,
+	light::LightVolumeVertex,
+	mesh::{ MeshVertexDefinition, MeshVertexFormat, UserParams },
+	ribbon::RibbonVertex,
+};
+use crate::device::{ DeviceCtx, FormatFeatures };
+use std::sync::{ Arc, Mutex };
 use vulkano::{
 	ordered_passes_renderpass,
+	single_pass_renderpass,
+	buffer::CpuBufferPool,
+	descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
 	format::Format,
 	framebuffer::{ RenderPassAbstract, Subpass },
-	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract },
+	pipeline::{
+		ComputePipeline,
+		ComputePipelineAbstract,
+		GraphicsPipeline,
+		GraphicsPipelineAbstract,
+		blend::{ AttachmentBlend, BlendFactor },
+		depth_stencil::{ Compare, DepthBounds, DepthStencil, Stencil, StencilOp },
+	},
 };
 
 pub struct MeshRenderPass {
 	pub(super) shaders: Arc<MeshShaders>,
+	memory_profile: MemoryProfile,
+	pub(super) gbuffer_formats: GBufferFormats,
 	pub(super) subpass_gbuffers: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
 	pub(super) pipeline_gbuffers: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
-	pub(super) pipeline_history: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pipeline_gbuffers_packed: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Built on first use by `pipeline_gbuffers_rasterized`, for rasterizers other than the default.
+	pipelines_gbuffers_rasterized:
+		Mutex<Vec<((MeshVertexFormat, MeshRasterizer), Arc<GraphicsPipelineAbstract + Send + Sync + 'static>)>>,
+	/// Built on first use by `pipeline_gbuffers_instanced`, since only `GpuCulledMesh`es need them.
+	pipelines_gbuffers_instanced: Mutex<Vec<(MeshVertexFormat, Arc<GraphicsPipelineAbstract + Send + Sync + 'static>)>>,
+	pipeline_cull: Mutex<Option<Arc<ComputePipelineAbstract + Send + Sync + 'static>>>,
+	pub(super) pipeline_lighting: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) pipeline_light_stencil: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) pipeline_light_volume: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) subpass_weighted_blended: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	pub(super) pipeline_ribbon_weighted: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) pipeline_weighted_composite: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) subpass_transparent: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	pub(super) pipeline_ribbon: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) pipeline_resolve: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) upsample_render_pass: Arc<RenderPassAbstract + Send + Sync>,
+	pub(super) pipeline_upsample: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pub(super) post_render_pass: Arc<RenderPassAbstract + Send + Sync>,
 	pub(super) pipeline_target: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	/// Like `pipeline_target`, writing only red for the left eye and only green and blue for the right.
+	pub(super) pipelines_target_anaglyph: [Arc<GraphicsPipelineAbstract + Send + Sync + 'static>; 2],
+	mesh_desc_pool: Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>>,
+	highlight_pool: CpuBufferPool<[f32; 4]>,
+	user_params_pool: CpuBufferPool<UserParams>,
 }
 impl MeshRenderPass {
+	/// Uses the `MemoryProfile` detected for the device.
 	pub fn new(shaders: Arc<MeshShaders>, format: Format) -> Arc<Self> {
+		let memory_profile = MemoryProfile::detect(&shaders.device);
+		Self::with_memory_profile(shaders, format, memory_profile)
+	}
+
+	pub fn with_memory_profile(shaders: Arc<MeshShaders>, format: Format, memory_profile: MemoryProfile) -> Arc<Self> {
+		let gbuffer_formats = GBufferFormats::choose(&shaders.device);
 		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
 			Arc::new(
 				ordered_passes_renderpass!(
 					shaders.target_vertices.device().clone(),
 					attachments: {
-						albedo: { load: Clear, store: Store, format: ALBEDO_FORMAT, samples: 1, },
-						normal: { load: Clear, store: Store, format: NORMAL_FORMAT, samples: 1, },
-						depth: { load: Clear, store: Store, format: DEPTH_FORMAT, samples: 1, },
+						albedo: { load: Clear, store: Store, format: gbuffer_formats.albedo, samples: 1, },
+						normal: { load: Clear, store: Store, format: gbuffer_formats.normal, samples: 1, },
+						mask: { load: Clear, store: Store, format: gbuffer_formats.mask, samples: 1, },
+						depth: { load: Clear, store: Store, format: gbuffer_formats.depth, samples: 1, },
+						lit: { load: DontCare, store: DontCare, format: gbuffer_formats.lit, samples: 1, },
 						history: { load: DontCare, store: Store, format: format, samples: 1, },
-						out: { load: DontCare, store: Store, format: format, samples: 1, }
+						motion: { load: DontCare, store: Store, format: gbuffer_formats.motion, samples: 1, },
+						accum: { load: Clear, store: DontCare, format: gbuffer_formats.accum, samples: 1, },
+						revealage: { load: Clear, store: DontCare, format: gbuffer_formats.revealage, samples: 1, }
 					},
 					passes: [
-						{ color: [albedo, normal], depth_stencil: {depth}, input: [] },
-						{ color: [history], depth_stencil: {}, input: [albedo, normal, depth] },
-						{ color: [out], depth_stencil: {}, input: [history] }
+						{ color: [albedo, normal, mask], depth_stencil: {depth}, input: [] },
+						// lighting, with the stencil marking light volumes
+						{ color: [lit], depth_stencil: {depth}, input: [albedo, normal, depth] },
+						// weighted blended transparency, summed and tested against the gbuffer depth
+						{ color: [accum, revealage], depth_stencil: {depth}, input: [] },
+						// the sum composited over the lit scene
+						{ color: [lit], depth_stencil: {}, input: [accum, revealage] },
+						// transparent in order, blended over the lit scene and tested against the gbuffer depth
+						{ color: [lit], depth_stencil: {depth}, input: [] },
+						// temporal resolve, from linear light to the output, with the camera's motion for upsampling
+						{ color: [history, motion], depth_stencil: {}, input: [lit, depth] }
 					]
 				)
 				.unwrap()
 			);
 
+		// at the target's resolution, accumulating frames rendered at a lower one
+		let upsample_render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.target_vertices.device().clone(),
+					attachments: { out: { load: DontCare, store: Store, format: format, samples: 1, } },
+					pass: { color: [out], depth_stencil: {} }
+				)
+				.unwrap()
+			);
+
+		// separate from the main pass, because effects like outlines need to sample neighboring pixels
+		let post_render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.target_vertices.device().clone(),
+					attachments: { out: { load: DontCare, store: Store, format: format, samples: 1, } },
+					pass: { color: [out], depth_stencil: {} }
+				)
+				.unwrap()
+			);
+
 		let subpass_gbuffers = Subpass::from(render_pass.clone(), 0).unwrap();
 
-		let pipeline_gbuffers =
+		// one pipeline per vertex format, since attribute formats are baked into them
+		let pipeline_gbuffers_with_format = |format| {
+			build_pipeline_gbuffers(&shaders, &subpass_gbuffers, format, MeshRasterizer::default())
+		};
+		let pipeline_gbuffers = pipeline_gbuffers_with_format(MeshVertexFormat::Full);
+		let pipeline_gbuffers_packed = pipeline_gbuffers_with_format(MeshVertexFormat::Packed);
+
+		let subpass_lighting = Subpass::from(render_pass.clone(), 1).unwrap();
+		let pipeline_lighting =
 			Arc::new(
 				GraphicsPipeline::start()
-					.vertex_input(MeshVertexDefinition::new())
-					.vertex_shader(shaders.shader_gbuffers_vertex.main_entry_point(), ())
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_lighting_vertex.main_entry_point(), ())
 					.triangle_list()
 					.viewports_dynamic_scissors_irrelevant(1)
-					.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
-					.render_pass(subpass_gbuffers.clone())
-					.depth_stencil_simple_depth()
+					.fragment_shader(shaders.shader_lighting_fragment.main_entry_point(), ())
+					.render_pass(subpass_lighting.clone())
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		// point and spot lights are drawn twice over a volume around them. the first draw marks the stencil of gbuffer
+		// pixels inside the volume, and the second lights the marked pixels and clears their stencil for the next one.
+		let device = shaders.target_vertices.device();
+		let light_volume_pipeline = || {
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<LightVolumeVertex>()
+				.vertex_shader(shaders.shader_light_volume_vertex.main_entry_point(), ())
+				.triangle_list()
+				.viewports_dynamic_scissors_irrelevant(1)
+				// volumes reaching past the far plane still mark the surfaces inside them
+				.depth_clamp(device.enabled_features().depth_clamp)
+				.render_pass(subpass_lighting.clone())
+		};
+		let stencil = |compare, pass_op, depth_fail_op| {
+			Stencil {
+				compare: compare,
+				pass_op: pass_op,
+				fail_op: StencilOp::Keep,
+				depth_fail_op: depth_fail_op,
+				compare_mask: Some(!0),
+				write_mask: Some(!0),
+				reference: Some(0),
+			}
+		};
+
+		// faces behind the gbuffer surface count up and faces in front of it count down, so only surfaces between a
+		// front and a back face are left nonzero. swapped windings count the other way, which is just as nonzero.
+		let pipeline_light_stencil =
+			Arc::new(
+				light_volume_pipeline()
+					.fragment_shader(shaders.shader_light_stencil_fragment.main_entry_point(), ())
+					.depth_stencil(DepthStencil {
+						depth_compare: Compare::Less,
+						depth_write: false,
+						depth_bounds_test: DepthBounds::Disabled,
+						stencil_front: stencil(Compare::Always, StencilOp::Keep, StencilOp::DecrementAndWrap),
+						stencil_back: stencil(Compare::Always, StencilOp::Keep, StencilOp::IncrementAndWrap),
+					})
+					.blend_collective(AttachmentBlend {
+						mask_red: false,
+						mask_green: false,
+						mask_blue: false,
+						mask_alpha: false,
+						..AttachmentBlend::pass_through()
+					})
+					.build(device.clone())
+					.expect("failed to create pipeline")
+			);
+
+		// nothing is culled, so the camera can be inside the volume. clearing the stencil keeps pixels covered by both
+		// faces from being lit twice.
+		let pipeline_light_volume =
+			Arc::new(
+				light_volume_pipeline()
+					.fragment_shader(shaders.shader_light_volume_fragment.main_entry_point(), ())
+					.depth_stencil(DepthStencil {
+						depth_compare: Compare::Always,
+						depth_write: false,
+						depth_bounds_test: DepthBounds::Disabled,
+						stencil_front: stencil(Compare::NotEqual, StencilOp::Zero, StencilOp::Keep),
+						stencil_back: stencil(Compare::NotEqual, StencilOp::Zero, StencilOp::Keep),
+					})
+					.blend_collective(AttachmentBlend {
+						color_source: BlendFactor::One,
+						color_destination: BlendFactor::One,
+						alpha_source: BlendFactor::One,
+						alpha_destination: BlendFactor::One,
+						..AttachmentBlend::alpha_blending()
+					})
+					.build(device.clone())
+					.expect("failed to create pipeline")
+			);
+
+		let subpass_weighted_blended = Subpass::from(render_pass.clone(), 2).unwrap();
+
+		// revealage is multiplied by one minus each coverage, and the rest is summed
+		let pipeline_ribbon_weighted =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<RibbonVertex>()
+					.vertex_shader(shaders.shader_ribbon_vertex.main_entry_point(), ())
+					.triangle_strip()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(shaders.shader_ribbon_weighted_fragment.main_entry_point(), ())
+					.render_pass(subpass_weighted_blended.clone())
+					.depth_stencil(DepthStencil { depth_write: false, ..DepthStencil::simple_depth_test() })
+					.blend_individual(weighted_blend().iter().cloned())
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		// what's revealed of the scene is kept, and the average color is added over the rest
+		let pipeline_weighted_composite =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_lighting_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(shaders.shader_weighted_composite_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass.clone(), 3).unwrap())
+					.blend_collective(AttachmentBlend {
+						color_source: BlendFactor::One,
+						color_destination: BlendFactor::SrcAlpha,
+						alpha_source: BlendFactor::Zero,
+						alpha_destination: BlendFactor::One,
+						..AttachmentBlend::alpha_blending()
+					})
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let subpass_transparent = Subpass::from(render_pass.clone(), 4).unwrap();
+
+		// premultiplied, so zero alpha adds light instead of covering it
+		let pipeline_ribbon =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<RibbonVertex>()
+					.vertex_shader(shaders.shader_ribbon_vertex.main_entry_point(), ())
+					.triangle_strip()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(shaders.shader_ribbon_fragment.main_entry_point(), ())
+					.render_pass(subpass_transparent.clone())
+					.depth_stencil(DepthStencil { depth_write: false, ..DepthStencil::simple_depth_test() })
+					.blend_collective(AttachmentBlend {
+						color_source: BlendFactor::One,
+						alpha_source: BlendFactor::One,
+						..AttachmentBlend::alpha_blending()
+					})
+					.build(shaders.target_vertices.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let pipeline_resolve =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<TargetVertex>()
+					.vertex_shader(shaders.shader_resolve_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(shaders.shader_resolve_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass.clone(), 5).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
 			);
 
-		let pipeline_history =
+		let pipeline_upsample =
 			Arc::new(
 				GraphicsPipeline::start()
 					.vertex_input_single_buffer::<TargetVertex>()
-					.vertex_shader(shaders.shader_history_vertex.main_entry_point(), ())
+					.vertex_shader(shaders.shader_upsample_vertex.main_entry_point(), ())
 					.triangle_list()
 					.viewports_dynamic_scissors_irrelevant(1)
-					.fragment_shader(shaders.shader_history_fragment.main_entry_point(), ())
-					.render_pass(Subpass::from(render_pass.clone(), 1).unwrap())
+					.fragment_shader(shaders.shader_upsample_fragment.main_entry_point(), ())
+					.render_pass(Subpass::from(upsample_render_pass.clone(), 0).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
 			);
 
-		let pipeline_target =
+		let pipeline_target_with_blend = |blend| -> Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
 			Arc::new(
 				GraphicsPipeline::start()
 					.vertex_input_single_buffer::<TargetVertex>()
@@ -73,21 +311,330 @@ impl MeshRenderPass {
 					.triangle_list()
 					.viewports_dynamic_scissors_irrelevant(1)
 					.fragment_shader(shaders.shader_target_fragment.main_entry_point(), ())
-					.render_pass(Subpass::from(render_pass, 2).unwrap())
+					.blend_collective(blend)
+					.render_pass(Subpass::from(post_render_pass.clone(), 0).unwrap())
 					.build(shaders.target_vertices.device().clone())
 					.expect("failed to create pipeline")
-			);
+			)
+		};
+		let pipeline_target = pipeline_target_with_blend(AttachmentBlend::pass_through());
+		// the left eye is drawn first and also writes alpha, so every channel is written once
+		let pipelines_target_anaglyph =
+			[
+				pipeline_target_with_blend(AttachmentBlend {
+					mask_green: false,
+					mask_blue: false,
+					..AttachmentBlend::pass_through()
+				}),
+				pipeline_target_with_blend(AttachmentBlend {
+					mask_red: false,
+					mask_alpha: false,
+					..AttachmentBlend::pass_through()
+				}),
+			];
+
+		let mesh_desc_pool = Mutex::new(FixedSizeDescriptorSetsPool::new(pipeline_gbuffers.clone(), 1));
+		let highlight_pool = CpuBufferPool::uniform_buffer(shaders.target_vertices.device().clone());
+		let user_params_pool = CpuBufferPool::uniform_buffer(shaders.target_vertices.device().clone());
 
 		Arc::new(Self {
 			shaders: shaders,
+			memory_profile: memory_profile,
+			gbuffer_formats: gbuffer_formats,
 			subpass_gbuffers: subpass_gbuffers,
 			pipeline_gbuffers: pipeline_gbuffers,
-			pipeline_history: pipeline_history,
+			pipeline_gbuffers_packed: pipeline_gbuffers_packed,
+			pipelines_gbuffers_rasterized: Mutex::new(vec![]),
+			pipelines_gbuffers_instanced: Mutex::new(vec![]),
+			pipeline_cull: Mutex::new(None),
+			pipeline_lighting: pipeline_lighting,
+			pipeline_light_stencil: pipeline_light_stencil,
+			pipeline_light_volume: pipeline_light_volume,
+			subpass_weighted_blended: subpass_weighted_blended,
+			pipeline_ribbon_weighted: pipeline_ribbon_weighted,
+			pipeline_weighted_composite: pipeline_weighted_composite,
+			subpass_transparent: subpass_transparent,
+			pipeline_ribbon: pipeline_ribbon,
+			pipeline_resolve: pipeline_resolve,
+			upsample_render_pass: upsample_render_pass,
+			pipeline_upsample: pipeline_upsample,
+			post_render_pass: post_render_pass,
 			pipeline_target: pipeline_target,
+			pipelines_target_anaglyph: pipelines_target_anaglyph,
+			mesh_desc_pool: mesh_desc_pool,
+			highlight_pool: highlight_pool,
+			user_params_pool: user_params_pool,
 		})
 	}
 
 	pub(crate) fn render_pass(&self) -> &Arc<RenderPassAbstract + Send + Sync> {
 		self.subpass_gbuffers.render_pass()
 	}
+
+	pub fn memory_profile(&self) -> MemoryProfile {
+		self.memory_profile
+	}
+
+	pub fn shaders(&self) -> &Arc<MeshShaders> {
+		&self.shaders
+	}
+
+	/// The subpass `Drawable3D`s record their commands for.
+	pub fn subpass_gbuffers(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
+		&self.subpass_gbuffers
+	}
+
+	/// Writes albedo, normal and mask. Set 0 is the camera, set 1 is the mesh transform, highlight and user params, and
+	/// set 2 is the material. Custom pipelines for the same subpass should output the same attachments. Rotations are
+	/// laid out as `math::GpuQuaternion`s. Normals are in camera space, octahedrally encoded into `[0, 1]` in rg, with
+	/// ambient occlusion in b.
+	pub fn pipeline_gbuffers(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipeline_gbuffers
+	}
+
+	/// `pipeline_gbuffers` for meshes stored in `format`. The descriptor set layouts are the same.
+	pub fn pipeline_gbuffers_for(
+		&self,
+		format: MeshVertexFormat,
+	) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		match format {
+			MeshVertexFormat::Full => &self.pipeline_gbuffers,
+			MeshVertexFormat::Packed => &self.pipeline_gbuffers_packed,
+		}
+	}
+
+	/// `pipeline_gbuffers_for` `format`, with fixed function state from `rasterizer` instead of the default's. Built
+	/// the first time each combination is asked for, so meshes sharing a rasterizer share the pipeline too.
+	pub fn pipeline_gbuffers_rasterized(
+		&self,
+		format: MeshVertexFormat,
+		rasterizer: MeshRasterizer,
+	) -> Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		if rasterizer == MeshRasterizer::default() {
+			return self.pipeline_gbuffers_for(format).clone();
+		}
+
+		let mut pipelines = self.pipelines_gbuffers_rasterized.lock().unwrap();
+		if let Some((_, pipeline)) = pipelines.iter().find(|(key, _)| *key == (format, rasterizer)) {
+			return pipeline.clone();
+		}
+
+		let pipeline = build_pipeline_gbuffers(&self.shaders, &self.subpass_gbuffers, format, rasterizer);
+		pipelines.push(((format, rasterizer), pipeline.clone()));
+		pipeline
+	}
+
+	/// Like `pipeline_gbuffers_for` `format`, with set 1 holding the instances of a `GpuCulledMesh` and the indices of
+	/// the visible ones instead of a single transform.
+	pub(super) fn pipeline_gbuffers_instanced(
+		&self,
+		format: MeshVertexFormat,
+	) -> Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		let mut pipelines = self.pipelines_gbuffers_instanced.lock().unwrap();
+		if let Some((_, pipeline)) = pipelines.iter().find(|(key, _)| *key == format) {
+			return pipeline.clone();
+		}
+
+		let device = self.shaders.target_vertices.device();
+		let pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static> =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input(MeshVertexDefinition::with_format(format))
+					.vertex_shader(self.shaders.shader_gbuffers_instanced_vertex.main_entry_point(), ())
+					.triangle_list()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(self.shaders.shader_gbuffers_fragment.main_entry_point(), ())
+					.render_pass(self.subpass_gbuffers.clone())
+					.depth_stencil_simple_depth()
+					.cull_mode_disabled()
+					.build(device.clone())
+					.expect("failed to create pipeline")
+			);
+		pipelines.push((format, pipeline.clone()));
+		pipeline
+	}
+
+	/// Tests the instances of a `GpuCulledMesh` against the view, writing the visible ones and their count for its
+	/// indirect draws.
+	pub(super) fn pipeline_cull(&self) -> Arc<ComputePipelineAbstract + Send + Sync + 'static> {
+		let mut pipeline = self.pipeline_cull.lock().unwrap();
+		if let Some(pipeline) = &*pipeline {
+			return pipeline.clone();
+		}
+
+		let device = self.shaders.target_vertices.device();
+		let cull: Arc<ComputePipelineAbstract + Send + Sync + 'static> =
+			Arc::new(
+				ComputePipeline::new(device.clone(), &self.shaders.shader_cull_compute.main_entry_point(), &())
+					.expect("failed to create pipeline")
+			);
+		*pipeline = Some(cull.clone());
+		cull
+	}
+
+	/// Builds the pipelines that are otherwise built on first use, for `Context::warm_pipelines`: gbuffers for every
+	/// vertex format and rasterizer, and the instanced gbuffers and cull of `GpuCulledMesh`es where they're supported.
+	pub(crate) fn warm(&self) {
+		let depth_clamp_enabled = self.shaders.target_vertices.device().enabled_features().depth_clamp;
+		let depth_clamps: &[bool] = if depth_clamp_enabled { &[false, true] } else { &[false] };
+		for &format in &[MeshVertexFormat::Full, MeshVertexFormat::Packed] {
+			for &depth_clamp in depth_clamps {
+				for &cull in &[MeshCull::None, MeshCull::Back, MeshCull::Front] {
+					self.pipeline_gbuffers_rasterized(format, MeshRasterizer { depth_clamp: depth_clamp, cull: cull });
+				}
+			}
+		}
+
+		if self.shaders.device.capabilities().supports_compute {
+			for &format in &[MeshVertexFormat::Full, MeshVertexFormat::Packed] {
+				self.pipeline_gbuffers_instanced(format);
+			}
+			self.pipeline_cull();
+		}
+	}
+
+	/// The subpass `Drawable3D::make_transparent_commands` records for. It renders into linear HDR light after the
+	/// opaque geometry is lit, with the gbuffer depth bound for testing only.
+	pub fn subpass_transparent(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
+		&self.subpass_transparent
+	}
+
+	/// The subpass `Drawable3D::make_weighted_blended_commands` records for, with the gbuffer depth bound for testing
+	/// only. Pipelines for it write premultiplied linear light times a weight to location 0 and coverage to the single
+	/// channel at location 1, blended like `weighted_blend`. The weight should include the coverage, so the sum can
+	/// be divided by its alpha for the average color.
+	pub fn subpass_weighted_blended(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
+		&self.subpass_weighted_blended
+	}
+
+	pub(crate) fn mesh_desc_pool(
+		&self,
+	) -> &Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>> {
+		&self.mesh_desc_pool
+	}
+
+	pub(crate) fn highlight_pool(&self) -> &CpuBufferPool<[f32; 4]> {
+		&self.highlight_pool
+	}
+
+	/// For meshes without user params of their own.
+	pub(crate) fn user_params_pool(&self) -> &CpuBufferPool<UserParams> {
+		&self.user_params_pool
+	}
+}
+
+/// Blending for the two attachments of `MeshRenderPass::subpass_weighted_blended`: the weighted colors are summed, and
+/// what's revealed of the scene behind is multiplied by one minus each coverage.
+pub fn weighted_blend() -> [AttachmentBlend; 2] {
+	[
+		AttachmentBlend {
+			color_source: BlendFactor::One,
+			color_destination: BlendFactor::One,
+			alpha_source: BlendFactor::One,
+			alpha_destination: BlendFactor::One,
+			..AttachmentBlend::alpha_blending()
+		},
+		AttachmentBlend {
+			color_source: BlendFactor::Zero,
+			color_destination: BlendFactor::OneMinusSrcColor,
+			alpha_source: BlendFactor::Zero,
+			alpha_destination: BlendFactor::OneMinusSrcAlpha,
+			..AttachmentBlend::alpha_blending()
+		},
+	]
+}
+
+/// Fixed function state of a gbuffer pipeline, for groups of meshes that need to be rasterized differently from the
+/// rest. Give it to `Mesh::set_rasterizer`, or build a pipeline with `MeshRenderPass::pipeline_gbuffers_rasterized`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshRasterizer {
+	/// Clamps depth to the depth range instead of clipping at the near and far planes, so geometry past them still
+	/// writes depth, e.g. to pancake shadow casters behind a light's near plane onto it. Needs the device's depth clamp
+	/// feature, see `Capabilities::supports_depth_clamp`, and is ignored without it.
+	pub depth_clamp: bool,
+	pub cull: MeshCull,
+}
+impl Default for MeshRasterizer {
+	fn default() -> Self {
+		Self { depth_clamp: false, cull: MeshCull::None }
+	}
+}
+
+/// Which faces a `MeshRasterizer` discards. Front faces wind counter clockwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshCull {
+	None,
+	Back,
+	Front,
 }
+
+fn build_pipeline_gbuffers(
+	shaders: &MeshShaders,
+	subpass: &Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	format: MeshVertexFormat,
+	rasterizer: MeshRasterizer,
+) -> Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+	let device = shaders.target_vertices.device();
+	let builder =
+		GraphicsPipeline::start()
+			.vertex_input(MeshVertexDefinition::with_format(format))
+			.vertex_shader(shaders.shader_gbuffers_vertex.main_entry_point(), ())
+			.triangle_list()
+			.viewports_dynamic_scissors_irrelevant(1)
+			.fragment_shader(shaders.shader_gbuffers_fragment.main_entry_point(), ())
+			.render_pass(subpass.clone())
+			.depth_stencil_simple_depth()
+			.depth_clamp(rasterizer.depth_clamp && device.enabled_features().depth_clamp);
+	let builder =
+		match rasterizer.cull {
+			MeshCull::None => builder.cull_mode_disabled(),
+			MeshCull::Back => builder.cull_mode_back(),
+			MeshCull::Front => builder.cull_mode_front(),
+		};
+	Arc::new(builder.build(device.clone()).expect("failed to create pipeline"))
+}
+
+/// Formats of the attachments the main render pass keeps within a frame. The preferred formats aren't guaranteed for
+/// every use the pass makes of them, so devices without them, like software implementations, get wider or narrower
+/// fallbacks that Vulkan does guarantee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct GBufferFormats {
+	pub(super) albedo: Format,
+	pub(super) normal: Format,
+	pub(super) mask: Format,
+	pub(super) depth: Format,
+	pub(super) lit: Format,
+	pub(super) motion: Format,
+	pub(super) accum: Format,
+	pub(super) revealage: Format,
+}
+impl GBufferFormats {
+	fn choose(device: &DeviceCtx) -> Self {
+		let first_supported = |candidates: &[Format], supported: fn(FormatFeatures) -> bool| {
+			candidates.iter()
+				.cloned()
+				.find(|&format| supported(device.format_features(format)))
+				.unwrap_or(candidates[candidates.len() - 1])
+		};
+		let color = |f: FormatFeatures| f.color_attachment();
+		let sampled_color = |f: FormatFeatures| f.color_attachment() && f.sampled_image();
+		let blended = |f: FormatFeatures| f.color_attachment() && f.color_attachment_blend();
+		let depth_stencil = |f: FormatFeatures| f.depth_stencil_attachment();
+
+		Self {
+			albedo: first_supported(&[Format::A2B10G10R10UnormPack32, Format::R8G8B8A8Unorm], color),
+			// octahedral normal in rg and ambient occlusion in b, a quarter the size of a full float normal
+			normal: first_supported(&[Format::A2B10G10R10UnormPack32, Format::R16G16B16A16Sfloat], color),
+			mask: first_supported(&[Format::R8G8B8A8Unorm], sampled_color),
+			// the stencil marks light volumes. every device has one of these
+			depth: first_supported(&[Format::D24Unorm_S8Uint, Format::D32Sfloat_S8Uint], depth_stencil),
+			// transparent geometry blends into it
+			lit: first_supported(&[Format::R16G16B16A16Sfloat, Format::R32G32B32A32Sfloat], blended),
+			motion: first_supported(&[Format::R16G16Sfloat, Format::R32G32Sfloat], sampled_color),
+			// weighted blended transparency sums into these
+			accum: first_supported(&[Format::R16G16B16A16Sfloat, Format::R32G32B32A32Sfloat], blended),
+			revealage: first_supported(&[Format::R8Unorm, Format::R16Sfloat], blended),
+		}
+	}
+}
+
@@ -0,0 +1,243 @@
+use crate::batch::mesh::{ Drawable3D, MeshRenderPass, TransparentBucket };
+use crate::clock::duration_secs;
+use crate::color::Color;
+use crate::stats::{ self, Resource };
+use crate::texture::Texture;
+use cgmath::{ prelude::*, Vector3 };
+use std::{ collections::VecDeque, sync::Arc, time::Duration };
+use vulkano::{
+	impl_vertex,
+	buffer::{ BufferAccess, CpuBufferPool },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	framebuffer::{ RenderPassAbstract, Subpass },
+	image::ImageViewAccess,
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
+};
+
+/// Camera-facing strip through recently pushed positions, for sword swipes and missile trails. It's drawn in the
+/// transparent pass, so it isn't lit or outlined. Texture coordinates run from 0 at the head to 1 at the tail along u,
+/// and across the ribbon along v.
+pub struct Ribbon3D {
+	texture_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	vertex_pool: CpuBufferPool<RibbonVertex>,
+	points: VecDeque<(Vector3<f32>, f32)>,
+	capacity: usize,
+	time: f32,
+	lifetime: Duration,
+	head_width: f32,
+	tail_width: f32,
+	head_color: [f32; 4],
+	tail_color: [f32; 4],
+}
+impl Ribbon3D {
+	/// A white ribbon if `texture` is `None`. Once `capacity` points have been pushed, the oldest are dropped.
+	pub fn new(render_pass: &MeshRenderPass, texture: Option<&Texture>, capacity: usize) -> Self {
+		assert!(capacity >= 2, "ribbons need at least two points");
+
+		let image: Arc<ImageViewAccess + Send + Sync + 'static> =
+			match texture {
+				Some(texture) => texture.image().clone(),
+				None => render_pass.shaders.white_pixel.clone(),
+			};
+
+		Self {
+			texture_desc:
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(render_pass.pipeline_ribbon.clone(), 1)
+						.add_sampled_image(image, render_pass.shaders.clamp_sampler.clone())
+						.unwrap()
+						.build()
+						.unwrap(),
+				)),
+			vertex_pool: CpuBufferPool::vertex_buffer(render_pass.shaders.target_vertices.device().clone()),
+			points: VecDeque::with_capacity(capacity),
+			capacity: capacity,
+			time: 0.0,
+			lifetime: Duration::from_millis(500),
+			head_width: 0.5,
+			tail_width: 0.0,
+			head_color: [1.0; 4],
+			tail_color: [0.0; 4],
+		}
+	}
+
+	/// Adds a point at the head of the ribbon, in world space.
+	pub fn push(&mut self, position: Vector3<f32>) {
+		if self.points.len() == self.capacity {
+			self.points.pop_back();
+		}
+		self.points.push_front((position, self.time));
+	}
+
+	/// Ages the points, usually by `Clock::delta`, so the ribbon follows the engine's time scale and pauses.
+	pub fn update(&mut self, dt: Duration) {
+		self.time += duration_secs(dt);
+	}
+
+	pub fn clear(&mut self) {
+		self.points.clear();
+	}
+
+	/// How long a point lasts before it's removed. Defaults to 500ms.
+	pub fn set_lifetime(&mut self, lifetime: Duration) {
+		self.lifetime = lifetime;
+	}
+
+	/// Width in world units, interpolated from `head` for new points to `tail` at the end of their lifetime. Defaults
+	/// to 0.5 narrowing to 0.
+	pub fn set_width(&mut self, head: f32, tail: f32) {
+		self.head_width = head;
+		self.tail_width = tail;
+	}
+
+	/// Colors are interpolated by age like width, and multiplied with the texture. RGB is linear light added to the
+	/// scene and may exceed 1 for glowing trails, and alpha is how much of the scene behind is covered. Defaults to
+	/// white fading to nothing.
+	pub fn set_colors(&mut self, head: Color, tail: Color) {
+		self.head_color = head.to_linear();
+		self.tail_color = tail.to_linear();
+	}
+
+	fn vertices(&self) -> Vec<RibbonVertex> {
+		let lifetime = duration_secs(self.lifetime).max(1e-6);
+		let count = self.points.len();
+		let mut vertices = Vec::with_capacity(count * 2);
+
+		for (i, &(position, time)) in self.points.iter().enumerate() {
+			// tangent from the neighbors, so joints bend smoothly instead of pinching
+			let prev = self.points[i.saturating_sub(1)].0;
+			let next = self.points[(i + 1).min(count - 1)].0;
+			let tangent = next - prev;
+			let tangent = if tangent.magnitude2() > 0.0 { tangent.normalize() } else { Vector3::zero() };
+
+			let age = ((self.time - time) / lifetime).min(1.0);
+			let half_width = (self.head_width + (self.tail_width - self.head_width) * age) * 0.5;
+			let mut color = [0.0; 4];
+			for c in 0..4 {
+				color[c] = self.head_color[c] + (self.tail_color[c] - self.head_color[c]) * age;
+			}
+			let u = i as f32 / (count - 1) as f32;
+
+			for &(offset, v) in &[(half_width, 0.0), (-half_width, 1.0)] {
+				vertices.push(RibbonVertex {
+					position: position.into(),
+					tangent: tangent.into(),
+					offset: offset,
+					tex_coords: [u, v],
+					color: color,
+				});
+			}
+		}
+
+		vertices
+	}
+
+	/// Drops expired points and draws the rest with `pipeline`, which both transparent subpasses have a version of.
+	fn draw_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		subpass: &Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+		pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let (now, lifetime) = (self.time, duration_secs(self.lifetime));
+		while self.points.back().map_or(false, |&(_, time)| now - time >= lifetime) {
+			self.points.pop_back();
+		}
+
+		if self.points.len() < 2 {
+			return Ok(None);
+		}
+
+		let vertices = stats::track(Resource::Subbuffer, self.vertex_pool.chunk(self.vertices())?);
+
+		let cmd = AutoCommandBufferBuilder
+			::secondary_graphics_one_time_submit(
+				render_pass.shaders.target_vertices.device().clone(),
+				queue_family,
+				subpass.clone()
+			)?
+			.draw(
+				pipeline,
+				&DynamicState {
+					line_width: None,
+					viewports: Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+					scissors: None,
+				},
+				vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+				(camera_desc.clone(), self.texture_desc.clone()),
+				()
+			)
+			.unwrap();
+
+		let cmd = cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+		Ok(Some(cmd))
+	}
+}
+impl Drawable3D for Ribbon3D {
+	fn make_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		_camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		_highlight: Option<[f32; 4]>,
+		queue_family: QueueFamily,
+		_dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		// nothing opaque
+		Ok(
+			AutoCommandBufferBuilder
+				::secondary_graphics_one_time_submit(
+					render_pass.shaders.target_vertices.device().clone(),
+					queue_family,
+					render_pass.subpass_gbuffers.clone()
+				)?
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+
+	fn make_transparent_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let pipeline = render_pass.pipeline_ribbon.clone();
+		let subpass = &render_pass.subpass_transparent;
+		self.draw_commands(render_pass, subpass, pipeline, camera_desc, queue_family, dimensions)
+	}
+
+	fn transparent_bucket(&self) -> TransparentBucket {
+		TransparentBucket::Ribbons
+	}
+
+	fn make_weighted_blended_commands(
+		&mut self,
+		render_pass: &MeshRenderPass,
+		camera_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<Option<AutoCommandBuffer>, DeviceMemoryAllocError> {
+		let pipeline = render_pass.pipeline_ribbon_weighted.clone();
+		let subpass = &render_pass.subpass_weighted_blended;
+		self.draw_commands(render_pass, subpass, pipeline, camera_desc, queue_family, dimensions)
+	}
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct RibbonVertex {
+	pub(super) position: [f32; 3],
+	pub(super) tangent: [f32; 3],
+	/// Signed distance from the center line, across the ribbon.
+	pub(super) offset: f32,
+	pub(super) tex_coords: [f32; 2],
+	pub(super) color: [f32; 4],
+}
+impl_vertex!(RibbonVertex, position, tangent, offset, tex_coords, color);
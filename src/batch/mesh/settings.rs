@@ -0,0 +1,143 @@
+use crate::device::DeviceCtx;
+use crate::batch::mesh::Stereo;
+
+/// Largest device local heap below which `MemoryProfile::detect` picks `Low`, in bytes.
+pub const LOW_MEMORY_HEAP_SIZE: usize = 2 << 30;
+
+/// Quality settings for a `MeshBatch`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+	/// Resolution the scene is rendered at, relative to the target. The result is scaled to the target with
+	/// `upscale_filter`.
+	pub render_scale: f32,
+	pub upscale_filter: UpscaleFilter,
+	/// Jitters the camera by a different sub-pixel offset every frame and accumulates the frames at the target's
+	/// resolution, so a reduced `render_scale` still resolves detail finer than its pixels. Surfaces moving relative
+	/// to the camera, rather than with it, can smear. Keeps two images at the target's resolution, in every
+	/// `MemoryProfile`. The result is already at the target's resolution, so of `upscale_filter` only the sharpening of
+	/// `UpscaleFilter::Sharpen` applies.
+	pub temporal_upsampling: bool,
+	/// Renders each eye of a stereo pair from its own camera, with attachments and history of its own, and shows them
+	/// together. A debug view for stereo rendering; `None` by default.
+	pub stereo: Option<Stereo>,
+	/// Noise added when the lit image is written to the target, so smooth gradients don't band in its 8 bits per
+	/// channel. `Dither::Noise` by default.
+	pub dither: Dither,
+	/// How each bucket of transparent drawables is blended over the scene.
+	pub transparency: TransparencySettings,
+}
+impl Default for RenderSettings {
+	fn default() -> Self {
+		Self {
+			render_scale: 1.0,
+			upscale_filter: UpscaleFilter::Linear,
+			temporal_upsampling: false,
+			stereo: None,
+			dither: Dither::Noise,
+			transparency: TransparencySettings::default(),
+		}
+	}
+}
+
+/// A `TransparencyMode` for each `TransparentBucket`. Every bucket is `InOrder` by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TransparencySettings {
+	pub ribbons: TransparencyMode,
+	pub drawables: TransparencyMode,
+}
+impl TransparencySettings {
+	pub fn mode(&self, bucket: TransparentBucket) -> TransparencyMode {
+		match bucket {
+			TransparentBucket::Ribbons => self.ribbons,
+			TransparentBucket::Drawables => self.drawables,
+		}
+	}
+}
+
+/// Groups of transparent drawables that are blended the same way, see `Drawable3D::transparent_bucket`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransparentBucket {
+	/// `Ribbon3D`s, for trails and smoke.
+	Ribbons,
+	/// Every other drawable, unless it picks a bucket of its own.
+	Drawables,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TransparencyMode {
+	/// Blended over the scene one drawable after the other, in the order they were added to the batch. Exact where
+	/// nothing overlaps out of that order.
+	InOrder,
+	/// Weighted blended order independent transparency: every surface is summed into an accumulation image, weighted
+	/// by its coverage and distance, and the sum is composited over the scene once. Overlaps look plausible in any
+	/// order, at the cost of a full screen pass in frames that draw any. Light from surfaces without coverage, like
+	/// additive glows with zero alpha, is dropped, so keep those `InOrder`. The weighted buckets are drawn before the
+	/// `InOrder` ones.
+	WeightedBlended,
+}
+impl Default for TransparencyMode {
+	fn default() -> Self {
+		TransparencyMode::InOrder
+	}
+}
+
+/// Patterns for `RenderSettings::dither`. Each changes a pixel by less than one step of an 8-bit sRGB target, so
+/// they're invisible except as the smoothing of bands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dither {
+	Off,
+	/// An 8x8 Bayer matrix. Regular, so it can show as a faint grid in flat areas.
+	Ordered,
+	/// Interleaved gradient noise, which has little low frequency content like blue noise, without needing a texture.
+	Noise,
+}
+impl Dither {
+	pub(super) fn push_constant(self) -> i32 {
+		match self {
+			Dither::Off => 0,
+			Dither::Ordered => 1,
+			Dither::Noise => 2,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpscaleFilter {
+	/// Keeps hard pixel edges, for pixel art.
+	Nearest,
+	Linear,
+	/// Linear, followed by contrast adaptive sharpening like the second half of FSR 1. `0` is the mildest and `1` the
+	/// strongest.
+	Sharpen(f32),
+}
+impl UpscaleFilter {
+	pub(super) fn push_constants(self) -> (i32, f32) {
+		match self {
+			UpscaleFilter::Nearest => (0, 0.0),
+			UpscaleFilter::Linear => (1, 0.0),
+			UpscaleFilter::Sharpen(sharpness) => (2, sharpness.max(0.0).min(1.0)),
+		}
+	}
+}
+
+/// How much device memory a `MeshRenderPass` and the batches drawing with it spend on precision and buffering. Fixed
+/// when the render pass is created, since it decides attachment formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryProfile {
+	Standard,
+	/// For GPUs with little memory. Each batch keeps one output image instead of two, since the resolve doesn't read
+	/// the previous frame.
+	Low,
+}
+impl MemoryProfile {
+	/// `Low` when the device's largest device local heap is smaller than `LOW_MEMORY_HEAP_SIZE`.
+	pub fn detect(device: &DeviceCtx) -> Self {
+		let largest_heap =
+			device.device().physical_device().memory_heaps()
+				.filter(|heap| heap.is_device_local())
+				.map(|heap| heap.size())
+				.max()
+				.unwrap_or(0);
+		if largest_heap < LOW_MEMORY_HEAP_SIZE { MemoryProfile::Low } else { MemoryProfile::Standard }
+	}
+}
@@ -1,4 +1,5 @@
-use crate::batch::mesh::{ TargetVertex };
+use crate::batch::mesh::{ TargetVertex, light::{ self, LightVolumeVertex } };
+use crate::device::{ DeviceCtx, ShaderModuleError };
 use crate::window::Window;
 use std::sync::Arc;
 use vulkano::{
@@ -8,23 +9,46 @@ use vulkano::{
 	format::Format,
 	image::{ Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage },
 	memory::DeviceMemoryAllocError,
-	sampler::{ Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
+	sampler::{ BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
 	sync::GpuFuture,
 };
 
+const MISSING_TEXTURE_SIZE: u32 = 64;
+const MISSING_CHECKER_SIZE: u32 = 8;
+
 pub struct MeshShaders {
+	pub(super) device: Arc<DeviceCtx>,
 	pub(super) queue: Arc<Queue>,
 	pub(super) target_vertices: Arc<ImmutableBuffer<[TargetVertex; 6]>>,
 	pub(super) shader_gbuffers_vertex: vs_gbuffers::Shader,
 	pub(super) shader_gbuffers_fragment: fs_gbuffers::Shader,
-	pub(super) shader_history_vertex: vs_history::Shader,
-	pub(super) shader_history_fragment: fs_history::Shader,
+	pub(super) shader_gbuffers_instanced_vertex: vs_gbuffers_instanced::Shader,
+	pub(super) shader_cull_compute: cs_cull::Shader,
+	pub(super) shader_lighting_vertex: vs_lighting::Shader,
+	pub(super) shader_lighting_fragment: fs_lighting::Shader,
+	pub(super) shader_light_volume_vertex: vs_light_volume::Shader,
+	pub(super) shader_light_stencil_fragment: fs_light_stencil::Shader,
+	pub(super) shader_light_volume_fragment: fs_light_volume::Shader,
+	pub(super) light_sphere_vertices: Arc<ImmutableBuffer<[LightVolumeVertex]>>,
+	pub(super) light_cone_vertices: Arc<ImmutableBuffer<[LightVolumeVertex]>>,
+	pub(super) shader_ribbon_vertex: vs_ribbon::Shader,
+	pub(super) shader_ribbon_fragment: fs_ribbon::Shader,
+	pub(super) shader_ribbon_weighted_fragment: fs_ribbon_weighted::Shader,
+	pub(super) shader_weighted_composite_fragment: fs_weighted_composite::Shader,
+	pub(super) shader_resolve_vertex: vs_resolve::Shader,
+	pub(super) shader_resolve_fragment: fs_resolve::Shader,
+	pub(super) shader_upsample_vertex: vs_upsample::Shader,
+	pub(super) shader_upsample_fragment: fs_upsample::Shader,
 	pub(super) shader_target_vertex: vs_target::Shader,
 	pub(super) shader_target_fragment: fs_target::Shader,
 	pub(super) black_pixel: Arc<ImageViewAccess + Send + Sync + 'static>,
+	pub(super) white_pixel: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) texture1_default: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) texture2_default: Arc<ImageViewAccess + Send + Sync + 'static>,
+	pub(super) missing_texture: Arc<ImageViewAccess + Send + Sync + 'static>,
 	pub(super) sampler: Arc<Sampler>,
+	pub(super) cookie_sampler: Arc<Sampler>,
+	pub(super) clamp_sampler: Arc<Sampler>,
 }
 impl MeshShaders {
 	pub fn new(window: &Window) -> Result<(Arc<Self>, impl GpuFuture), MeshShadersError> {
@@ -41,6 +65,18 @@ impl MeshShaders {
 				BufferUsage::vertex_buffer(),
 				window.device().queue().clone(),
 			)?;
+		let (light_sphere_vertices, light_sphere_vertices_future) =
+			ImmutableBuffer::from_iter(
+				light::sphere_proxy().into_iter(),
+				BufferUsage::vertex_buffer(),
+				window.device().queue().clone(),
+			)?;
+		let (light_cone_vertices, light_cone_vertices_future) =
+			ImmutableBuffer::from_iter(
+				light::cone_proxy().into_iter(),
+				BufferUsage::vertex_buffer(),
+				window.device().queue().clone(),
+			)?;
 
 		let (black_pixel, black_pixel_future) =
 				ImmutableImage::from_iter(
@@ -50,6 +86,14 @@ impl MeshShaders {
 					window.device().queue().clone(),
 				)?;
 
+		let (white_pixel, white_pixel_future) =
+				ImmutableImage::from_iter(
+					vec![(255u8, 255u8, 255u8, 255u8)].into_iter(),
+					Dimensions::Dim2d { width: 1, height: 1 },
+					Format::R8G8B8A8Unorm,
+					window.device().queue().clone(),
+				)?;
+
 		let (texture1_default, texture1_default_future) =
 				ImmutableImage::from_iter(
 					vec![(0u8, 0u8, 255u8, 0u8)].into_iter(),
@@ -66,19 +110,53 @@ impl MeshShaders {
 					window.device().queue().clone(),
 				)?;
 
+		// magenta and black checkers, so a texture that failed to load is hard to miss
+		let (missing_texture, missing_texture_future) =
+				ImmutableImage::from_iter(
+					(0..MISSING_TEXTURE_SIZE * MISSING_TEXTURE_SIZE)
+						.map(|i| {
+							let x = i % MISSING_TEXTURE_SIZE / MISSING_CHECKER_SIZE;
+							let y = i / MISSING_TEXTURE_SIZE / MISSING_CHECKER_SIZE;
+							if (x + y) % 2 == 0 { (255u8, 0u8, 255u8, 255u8) } else { (0u8, 0u8, 0u8, 255u8) }
+						}),
+					Dimensions::Dim2d { width: MISSING_TEXTURE_SIZE, height: MISSING_TEXTURE_SIZE },
+					Format::R8G8B8A8Unorm,
+					window.device().queue().clone(),
+				)?;
+
 		Ok((
 			Arc::new(Self {
+				device: window.device().clone(),
 				queue: window.device().queue().clone(),
 				target_vertices: target_vertices,
 				shader_gbuffers_vertex: vs_gbuffers::Shader::load(window.device().device().clone())?,
 				shader_gbuffers_fragment: fs_gbuffers::Shader::load(window.device().device().clone())?,
-				shader_history_vertex: vs_history::Shader::load(window.device().device().clone())?,
-				shader_history_fragment: fs_history::Shader::load(window.device().device().clone())?,
+				shader_gbuffers_instanced_vertex:
+					vs_gbuffers_instanced::Shader::load(window.device().device().clone())?,
+				shader_cull_compute: cs_cull::Shader::load(window.device().device().clone())?,
+				shader_lighting_vertex: vs_lighting::Shader::load(window.device().device().clone())?,
+				shader_lighting_fragment: fs_lighting::Shader::load(window.device().device().clone())?,
+				shader_light_volume_vertex: vs_light_volume::Shader::load(window.device().device().clone())?,
+				shader_light_stencil_fragment: fs_light_stencil::Shader::load(window.device().device().clone())?,
+				shader_light_volume_fragment: fs_light_volume::Shader::load(window.device().device().clone())?,
+				light_sphere_vertices: light_sphere_vertices,
+				light_cone_vertices: light_cone_vertices,
+				shader_ribbon_vertex: vs_ribbon::Shader::load(window.device().device().clone())?,
+				shader_ribbon_fragment: fs_ribbon::Shader::load(window.device().device().clone())?,
+				shader_ribbon_weighted_fragment: fs_ribbon_weighted::Shader::load(window.device().device().clone())?,
+				shader_weighted_composite_fragment:
+					fs_weighted_composite::Shader::load(window.device().device().clone())?,
+				shader_resolve_vertex: vs_resolve::Shader::load(window.device().device().clone())?,
+				shader_resolve_fragment: fs_resolve::Shader::load(window.device().device().clone())?,
+				shader_upsample_vertex: vs_upsample::Shader::load(window.device().device().clone())?,
+				shader_upsample_fragment: fs_upsample::Shader::load(window.device().device().clone())?,
 				shader_target_vertex: vs_target::Shader::load(window.device().device().clone())?,
 				shader_target_fragment: fs_target::Shader::load(window.device().device().clone())?,
 				black_pixel: black_pixel,
+				white_pixel: white_pixel,
 				texture1_default: texture1_default,
 				texture2_default: texture2_default,
+				missing_texture: missing_texture,
 				sampler:
 					Sampler::new(
 						window.device().device().clone(),
@@ -89,8 +167,36 @@ impl MeshShaders {
 						SamplerAddressMode::Repeat,
 						0.0, 1.0, 0.0, 0.0
 					)?,
+				// black outside the cookie, so light doesn't leak past the edges of the texture
+				cookie_sampler:
+					Sampler::new(
+						window.device().device().clone(),
+						Filter::Linear,
+						Filter::Linear, MipmapMode::Nearest,
+						SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueBlack),
+						SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueBlack),
+						SamplerAddressMode::ClampToBorder(BorderColor::FloatOpaqueBlack),
+						0.0, 1.0, 0.0, 0.0
+					)?,
+				clamp_sampler:
+					Sampler::new(
+						window.device().device().clone(),
+						Filter::Linear,
+						Filter::Linear, MipmapMode::Nearest,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						0.0, 1.0, 0.0, 0.0
+					)?,
 			}),
-			target_vertices_future.join(black_pixel_future).join(texture1_default_future).join(texture2_default_future)
+			target_vertices_future
+				.join(light_sphere_vertices_future)
+				.join(light_cone_vertices_future)
+				.join(black_pixel_future)
+				.join(white_pixel_future)
+				.join(texture1_default_future)
+				.join(texture2_default_future)
+				.join(missing_texture_future)
 		))
 	}
 }
@@ -101,6 +207,8 @@ pub enum MeshShadersError {
 	ImageCreationError(ImageCreationError),
 	OomError(OomError),
 	SamplerCreationError(SamplerCreationError),
+	/// A shader couldn't be loaded, without the `builtin-shaders` feature.
+	ShaderModuleError(ShaderModuleError),
 	TooManyObjects,
 }
 impl From<DeviceMemoryAllocError> for MeshShadersError {
@@ -118,6 +226,11 @@ impl From<OomError> for MeshShadersError {
 		MeshShadersError::OomError(val)
 	}
 }
+impl From<ShaderModuleError> for MeshShadersError {
+	fn from(val: ShaderModuleError) -> Self {
+		MeshShadersError::ShaderModuleError(val)
+	}
+}
 impl From<SamplerCreationError> for MeshShadersError {
 	fn from(val: SamplerCreationError) -> Self {
 		MeshShadersError::SamplerCreationError(val)
@@ -125,205 +238,482 @@ impl From<SamplerCreationError> for MeshShadersError {
 }
 
 mod vs_gbuffers {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "vertex",
-		src: "#version 450
-layout(location = 0) in vec3 position_os;
-layout(location = 1) in vec3 normal_os;
-layout(location = 2) in vec2 texcoord;
-
-layout(location = 0) out vec3 out_position_cs;
-layout(location = 1) out vec3 out_normal_cs;
-layout(location = 2) out vec2 out_texcoord;
-layout(location = 3) out vec3 out_base_albedo;
-
-layout(set = 0, binding = 0) uniform CameraPos { vec3 camera_pos; };
-layout(set = 0, binding = 1) uniform CameraRot { vec4 camera_rot; };
-layout(set = 0, binding = 2) uniform CameraProj { vec4 camera_proj; };
-
-layout(set = 1, binding = 0) uniform MeshPos { vec3 mesh_pos; };
-layout(set = 1, binding = 1) uniform MeshRot { vec4 mesh_rot; };
-
-layout(set = 2, binding = 0) uniform Material {
-	uint light_penetration;
-	uint subsurface_scattering;
-	uint emissive_brightness;
-	vec3 base_albedo;
-};
-layout(set = 2, binding = 1) uniform sampler2D tex1;
-layout(set = 2, binding = 2) uniform sampler2D tex2;
-
-vec4 quat_inv(vec4 quat) {
-	return vec4(-quat.xyz, quat.w) / dot(quat, quat);
-}
-
-vec3 quat_mul(vec4 quat, vec3 vec) {
-	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
+		path: "shaders/vs_gbuffers.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_gbuffers",
+		path: "shaders/vs_gbuffers.vert",
+		ty: vertex,
+		inputs: [
+			0 => R32G32B32Sfloat position_os,
+			1 => R32G32B32Sfloat normal_os,
+			2 => R32G32Sfloat texcoord,
+			3 => R32Sfloat ambient_occlusion,
+		],
+		outputs: [
+			0 => R32G32B32Sfloat out_position_cs,
+			1 => R32G32B32Sfloat out_normal_cs,
+			2 => R32G32Sfloat out_texcoord,
+			3 => R32G32B32Sfloat out_base_albedo,
+			4 => R32Sfloat out_ambient_occlusion,
+			5 => R32Sfloat out_height_scale,
+			6 => R32G32Uint out_parallax_samples,
+			7 => R32Sfloat out_light_probes,
+		],
+		descriptors: [
+			(0, 0) => Uniform,
+			(0, 1) => Uniform,
+			(0, 2) => Uniform,
+			(1, 0) => Uniform,
+			(1, 1) => Uniform,
+			(2, 0) => Uniform,
+			(2, 1) => Sampler,
+			(2, 2) => Sampler,
+			(2, 3) => Sampler,
+			(2, 4) => Sampler,
+		],
+		push_constants: [],
+	}
 }
 
-vec4 perspective(vec4 proj, vec3 pos) {
-	return vec4(pos.xy * proj.xy, pos.z * proj.z + proj.w, -pos.z);
+mod vs_gbuffers_instanced {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/vs_gbuffers_instanced.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_gbuffers_instanced",
+		path: "shaders/vs_gbuffers_instanced.vert",
+		ty: vertex,
+		inputs: [
+			0 => R32G32B32Sfloat position_os,
+			1 => R32G32B32Sfloat normal_os,
+			2 => R32G32Sfloat texcoord,
+			3 => R32Sfloat ambient_occlusion,
+		],
+		outputs: [
+			0 => R32G32B32Sfloat out_position_cs,
+			1 => R32G32B32Sfloat out_normal_cs,
+			2 => R32G32Sfloat out_texcoord,
+			3 => R32G32B32Sfloat out_base_albedo,
+			4 => R32Sfloat out_ambient_occlusion,
+			5 => R32Sfloat out_height_scale,
+			6 => R32G32Uint out_parallax_samples,
+			7 => R32Sfloat out_light_probes,
+		],
+		descriptors: [
+			(0, 0) => Uniform,
+			(0, 1) => Uniform,
+			(0, 2) => Uniform,
+			(1, 0) => Storage,
+			(1, 1) => Storage,
+			(2, 0) => Uniform,
+			(2, 1) => Sampler,
+			(2, 2) => Sampler,
+			(2, 3) => Sampler,
+			(2, 4) => Sampler,
+		],
+		push_constants: [],
+	}
 }
 
-void main() {
-	// stupid math library puts w first, so we flip it here
-	vec4 camera_rot = camera_rot.yzwx;
-	vec4 mesh_rot = mesh_rot.yzwx;
-
-	vec3 normal_ws = quat_mul(mesh_rot, normal_os);
-	out_normal_cs = quat_mul(quat_inv(camera_rot), normal_ws);
-	vec3 position_ws = quat_mul(mesh_rot, position_os) + mesh_pos;
-	out_position_cs = quat_mul(quat_inv(camera_rot), position_ws - camera_pos);
-	out_base_albedo = base_albedo;
-	out_texcoord = texcoord;
-	gl_Position = perspective(camera_proj, out_position_cs);
-}
-"
+pub(super) mod cs_cull {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "compute",
+		path: "shaders/cs_cull.comp"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "cs_cull",
+		path: "shaders/cs_cull.comp",
+		ty: compute,
+		descriptors: [(0, 0) => Storage, (0, 1) => Storage, (0, 2) => Storage],
+		push_constants: [
+			Cull {
+				camera_pos: [f32; 4],
+				camera_rot: [f32; 4],
+				camera_proj: [f32; 4],
+				camera_offset: [f32; 4],
+				instance_count: u32,
+				draw_count: u32,
+			},
+		],
 	}
 }
 
 mod fs_gbuffers {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "fragment",
-		src: "#version 450
-layout(location = 0) in vec3 position_cs;
-layout(location = 1) in vec3 normal_cs;
-layout(location = 2) in vec2 texcoord;
-layout(location = 3) in vec3 base_albedo;
-
-layout(location = 0) out vec4 out_albedo;
-layout(location = 1) out vec4 out_normal_cs;
-
-layout(set = 2, binding = 1) uniform sampler2D tex_albedo;
-layout(set = 2, binding = 2) uniform sampler2D tex_normal;
-
-mat3 tangent_frame(vec3 fWorldNormal, vec3 vPosition, vec2 vTexCoord) {
-	vec3 dxPosition = dFdx(vPosition);
-	vec3 dyPosition = dFdy(vPosition);
-	vec2 dxTexCoord = dFdx(vTexCoord);
-	vec2 dyTexCoord = dFdy(vTexCoord);
-	if (dot(dxTexCoord, dxTexCoord) == 0) dxTexCoord = vec2(1, 0);
-	if (dot(dyTexCoord, dyTexCoord) == 0) dyTexCoord = vec2(0, -1);
-	vec3 dxPosPerp = cross(fWorldNormal, dxPosition);
-	vec3 dyPosPerp = cross(dyPosition, fWorldNormal);
-	vec3 fTangent = dxPosPerp * dyTexCoord.x + dyPosPerp * dxTexCoord.x;
-	vec3 fBitangent = dxPosPerp * dyTexCoord.y + dyPosPerp * dxTexCoord.y;
-	float tangentScale = inversesqrt(max(dot(fTangent, fTangent), dot(fBitangent, fBitangent)));
-	return mat3(fTangent * tangentScale, fBitangent * tangentScale, fWorldNormal);
+		path: "shaders/fs_gbuffers.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_gbuffers",
+		path: "shaders/fs_gbuffers.frag",
+		ty: fragment,
+		inputs: [
+			0 => R32G32B32Sfloat position_cs,
+			1 => R32G32B32Sfloat normal_cs,
+			2 => R32G32Sfloat texcoord,
+			3 => R32G32B32Sfloat base_albedo,
+			4 => R32Sfloat ambient_occlusion,
+			5 => R32Sfloat height_scale,
+			6 => R32G32Uint parallax_samples,
+			7 => R32Sfloat light_probes,
+		],
+		outputs: [
+			0 => R32G32B32A32Sfloat out_albedo,
+			1 => R32G32B32A32Sfloat out_normal_cs,
+			2 => R32G32B32A32Sfloat out_mask,
+		],
+		descriptors: [(1, 2) => Uniform, (1, 3) => Uniform, (2, 1) => Sampler, (2, 2) => Sampler, (2, 3) => Sampler],
+		push_constants: [],
+	}
 }
 
-void main() {
-	vec4 albedo = texture(tex_albedo, texcoord);
-	vec3 normal_ts = texture(tex_normal, texcoord).xyz * 2.0 - 1.0;
-	mat3 tbn = tangent_frame(normalize(normal_cs), position_cs, texcoord);
-	vec3 normal_cs = normalize(tbn * normal_ts);
-	albedo.rgb = mix(base_albedo, albedo.rgb, albedo.a);
-	out_albedo = vec4(sqrt(albedo.rgb), 0);
-	out_normal_cs = vec4(normalize(normal_cs), 1);
+mod vs_lighting {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/vs_lighting.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_lighting",
+		path: "shaders/vs_lighting.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [],
+		descriptors: [],
+		push_constants: [],
+	}
 }
-"
+
+mod fs_lighting {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_lighting.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_lighting",
+		path: "shaders/fs_lighting.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [0 => R32G32B32A32Sfloat out_lit],
+		descriptors: [
+			(0, 0) => Uniform,
+			(0, 1) => InputAttachment,
+			(0, 2) => InputAttachment,
+			(0, 3) => InputAttachment,
+			(1, 0) => Uniform,
+			(1, 1) => Uniform,
+			(1, 2) => Uniform,
+			(2, 0) => Uniform,
+			(2, 1) => Storage,
+		],
+		push_constants: [],
 	}
 }
 
-mod vs_history {
+pub(super) mod vs_light_volume {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "vertex",
-		src: "#version 450
-layout(location = 0) in vec2 position;
-
-void main() {
-	gl_Position = vec4(position * 2 - 1, 0.0, 1.0);
-}
-"
+		path: "shaders/vs_light_volume.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_light_volume",
+		path: "shaders/vs_light_volume.vert",
+		ty: vertex,
+		inputs: [0 => R32G32B32Sfloat position],
+		outputs: [0 => R32Uint out_light_index],
+		descriptors: [(1, 0) => Uniform, (1, 1) => Uniform, (1, 2) => Uniform, (2, 0) => Uniform],
+		push_constants: [LightVolume { light_index: u32 }],
 	}
 }
 
-mod fs_history {
+// only marks the stencil, but declares everything fs_light_volume does, so both pipelines share their set layouts
+mod fs_light_stencil {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "fragment",
-		src: "#version 450
-layout(location = 0) out vec4 out_color;
-
-layout(set = 0, binding = 0) uniform Resolution { vec4 resolution; };
-layout(set = 0, binding = 1) uniform sampler2D prevOut;
-layout(set = 0, binding = 2, input_attachment_index = 0) uniform subpassInput albedo;
-layout(set = 0, binding = 3, input_attachment_index = 1) uniform subpassInput normal;
-layout(set = 0, binding = 4, input_attachment_index = 2) uniform subpassInput depth;
-layout(set = 1, binding = 0) uniform CameraPos { vec3 camera_pos; };
-layout(set = 1, binding = 1) uniform CameraRot { vec4 camera_rot; };
-layout(set = 1, binding = 2) uniform CameraProj { vec4 camera_proj; };
-
-vec3 quat_mul(vec4 q, vec3 v) {
-	return cross(q.xyz, cross(q.xyz, v) + v * q.w) * 2.0 + v;
+		path: "shaders/fs_light_stencil.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_light_stencil",
+		path: "shaders/fs_light_stencil.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [],
+		descriptors: [
+			(0, 0) => Uniform,
+			(0, 1) => InputAttachment,
+			(0, 2) => InputAttachment,
+			(0, 3) => InputAttachment,
+			(1, 0) => Uniform,
+			(1, 1) => Uniform,
+			(1, 2) => Uniform,
+			(2, 0) => Uniform,
+			(2, 1) => Sampler,
+			(2, 2) => Sampler,
+			(2, 3) => Sampler,
+			(2, 4) => Sampler,
+		],
+		push_constants: [],
+	}
 }
 
-void main() {
-	// stupid math library puts w first, so we flip it here
-	vec4 camera_rot = camera_rot.yzwx;
-
-	vec3 g_position_ds = vec3(gl_FragCoord.xy * resolution.zw, 2.0 * subpassLoad(depth).x) - 1.0;
-	vec3 g_position_cs = vec3(g_position_ds.xy / camera_proj.xy, -1.0) * camera_proj.w / (g_position_ds.z + camera_proj.z);
-	vec3 g_position_ws = quat_mul(camera_rot, g_position_cs) + camera_pos;
+mod fs_light_volume {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_light_volume.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_light_volume",
+		path: "shaders/fs_light_volume.frag",
+		ty: fragment,
+		inputs: [0 => R32Uint light_index],
+		outputs: [0 => R32G32B32A32Sfloat out_lit],
+		descriptors: [
+			(0, 0) => Uniform,
+			(0, 1) => InputAttachment,
+			(0, 2) => InputAttachment,
+			(0, 3) => InputAttachment,
+			(1, 0) => Uniform,
+			(1, 1) => Uniform,
+			(1, 2) => Uniform,
+			(2, 0) => Uniform,
+			(2, 1) => Sampler,
+			(2, 2) => Sampler,
+			(2, 3) => Sampler,
+			(2, 4) => Sampler,
+		],
+		push_constants: [],
+	}
+}
 
-	vec3 g_normal_cs = subpassLoad(normal).xyz;
-	vec3 g_normal_ws = quat_mul(camera_rot, g_normal_cs);
+mod vs_ribbon {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/vs_ribbon.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_ribbon",
+		path: "shaders/vs_ribbon.vert",
+		ty: vertex,
+		inputs: [
+			0 => R32G32B32Sfloat position,
+			1 => R32G32B32Sfloat tangent,
+			2 => R32Sfloat offset,
+			3 => R32G32Sfloat tex_coords,
+			4 => R32G32B32A32Sfloat color,
+		],
+		outputs: [0 => R32G32Sfloat out_tex_coords, 1 => R32G32B32A32Sfloat out_color],
+		descriptors: [(0, 0) => Uniform, (0, 1) => Uniform, (0, 2) => Uniform],
+		push_constants: [],
+	}
+}
 
-	vec3 g_albedo = subpassLoad(albedo).rgb;
-	g_albedo *= g_albedo;
+mod fs_ribbon {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_ribbon.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_ribbon",
+		path: "shaders/fs_ribbon.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords, 1 => R32G32B32A32Sfloat color],
+		outputs: [0 => R32G32B32A32Sfloat out_lit],
+		descriptors: [(1, 0) => Sampler],
+		push_constants: [],
+	}
+}
 
-	vec3 light = vec3(0);
+mod fs_ribbon_weighted {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_ribbon_weighted.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_ribbon_weighted",
+		path: "shaders/fs_ribbon_weighted.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords, 1 => R32G32B32A32Sfloat color],
+		outputs: [0 => R32G32B32A32Sfloat out_accum, 1 => R32Sfloat out_coverage],
+		descriptors: [(1, 0) => Sampler],
+		push_constants: [],
+	}
+}
 
-	// sunlight
-	vec3 sunColor = vec3(1.0, 0.85, 0.7) * 0.5;
-	vec3 sunDir = normalize(vec3(-1, -4, 2));
-	light += sunColor * max(0, dot(g_normal_ws, sunDir));
+mod fs_weighted_composite {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_weighted_composite.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_weighted_composite",
+		path: "shaders/fs_weighted_composite.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [0 => R32G32B32A32Sfloat out_lit],
+		descriptors: [(0, 0) => InputAttachment, (0, 1) => InputAttachment],
+		push_constants: [],
+	}
+}
 
-	// point light
-	float lightRadius = 5.0;
-	vec3 lightColor = vec3(0.7, 0.85, 1.0) * sqrt(lightRadius);
-	vec3 lightPos = vec3(14.5, -11, -28.5);
-	float lightDistance = distance(lightPos, g_position_ws);
-	vec3 lightDir = normalize(lightPos - g_position_ws);
-	float lightIntensity = max(0, dot(g_normal_ws, lightDir));
-	lightIntensity *= sqrt(max(0, (lightRadius - lightDistance) / lightRadius));
-	light += lightColor * lightIntensity / (lightDistance * lightDistance);
+mod vs_resolve {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/vs_resolve.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_resolve",
+		path: "shaders/vs_resolve.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [],
+		descriptors: [],
+		push_constants: [],
+	}
+}
 
-	// ambient
-	light = max(light, 0.001);
+pub(super) mod fs_resolve {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_resolve.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_resolve",
+		path: "shaders/fs_resolve.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [0 => R32G32B32A32Sfloat out_color, 1 => R32G32Sfloat out_motion],
+		descriptors: [
+			(0, 0) => Sampler,
+			(0, 1) => InputAttachment,
+			(0, 2) => InputAttachment,
+			(1, 0) => Uniform,
+			(1, 1) => Uniform,
+			(1, 2) => Uniform,
+		],
+		push_constants: [
+			Reprojection {
+				prev_camera_pos: [f32; 4],
+				prev_camera_rot: [f32; 4],
+				prev_camera_proj: [f32; 4],
+				prev_camera_offset: [f32; 4],
+				resolution: [f32; 2],
+				jitter: [f32; 2],
+				overlay_depth: f32,
+			},
+		],
+	}
+}
 
-	float exposure = 1.618;
-	vec3 out_hdr = g_albedo * light * exposure;
-	vec3 out_tonemapped = out_hdr / (1 + out_hdr);
-	out_color = vec4(out_tonemapped, 1);
+mod vs_upsample {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/vs_upsample.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_upsample",
+		path: "shaders/vs_upsample.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [],
+		descriptors: [],
+		push_constants: [],
+	}
 }
-"
+
+pub(super) mod fs_upsample {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/fs_upsample.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_upsample",
+		path: "shaders/fs_upsample.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [0 => R32G32B32A32Sfloat out_color],
+		descriptors: [(0, 0) => Sampler, (0, 1) => Sampler, (0, 2) => Sampler],
+		push_constants: [Upsample { target_size: [f32; 2], jitter: [f32; 2], history_weight: f32 }],
 	}
 }
 
 mod vs_target {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "vertex",
-		src: "#version 450
-layout(location = 0) in vec2 position;
-
-void main() {
-	gl_Position = vec4(position * 2 - 1, 0.0, 1.0);
-}
-"
+		path: "shaders/vs_target.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "vs_target",
+		path: "shaders/vs_target.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [],
+		descriptors: [],
+		push_constants: [],
 	}
 }
 
-mod fs_target {
+pub(super) mod fs_target {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "fragment",
-		src: "#version 450
-layout(location = 0) out vec4 out_color;
-
-layout(set = 0, binding = 0, input_attachment_index = 0) uniform subpassInput color;
-
-void main() {
-	out_color = subpassLoad(color);
-}
-"
+		path: "shaders/fs_target.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "fs_target",
+		path: "shaders/fs_target.frag",
+		ty: fragment,
+		inputs: [],
+		outputs: [0 => R32G32B32A32Sfloat out_color],
+		descriptors: [(0, 0) => Sampler, (0, 1) => Sampler],
+		push_constants: [
+			Post {
+				target_offset: [f32; 2],
+				target_size: [f32; 2],
+				outline_width: i32,
+				upscale_filter: i32,
+				sharpness: f32,
+				dither: i32,
+			},
+		],
 	}
 }
@@ -0,0 +1,65 @@
+use crate::anim::Lerp;
+use crate::math::Transform;
+use std::collections::VecDeque;
+
+/// Oldest snapshots are dropped past this, in case nothing samples the buffer for a while.
+const MAX_SNAPSHOTS: usize = 32;
+
+/// Timestamped transforms from a simulation running at its own tick rate, e.g. a remote entity from the network,
+/// sampled at render time so it moves smoothly between ticks.
+#[derive(Clone, Debug)]
+pub struct TransformSnapshots {
+	snapshots: VecDeque<Snapshot>,
+}
+impl TransformSnapshots {
+	pub fn new() -> Self {
+		Self { snapshots: VecDeque::new() }
+	}
+
+	/// `time` is in seconds, on the same clock as the times passed to `sample`. Snapshots may arrive out of order,
+	/// but ones older than everything already sampled past are ignored.
+	pub fn push(&mut self, time: f64, transform: Transform) {
+		let snapshot = Snapshot { time: time, transform: transform };
+		match self.snapshots.iter().rposition(|snapshot| snapshot.time <= time) {
+			Some(index) if self.snapshots[index].time == time => self.snapshots[index] = snapshot,
+			Some(index) => self.snapshots.insert(index + 1, snapshot),
+			None if self.snapshots.is_empty() => self.snapshots.push_back(snapshot),
+			None => (),
+		}
+
+		if self.snapshots.len() > MAX_SNAPSHOTS {
+			self.snapshots.pop_front();
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.snapshots.clear();
+	}
+
+	/// Interpolates the transform at `time`. Past the newest snapshot, it extrapolates from the last two for up to
+	/// `max_extrapolation` seconds, then holds. Snapshots no longer needed for later times are dropped.
+	pub fn sample(&mut self, time: f64, max_extrapolation: f64) -> Option<Transform> {
+		// keep the newest snapshot at or before `time`, since it's the start of the current span
+		while self.snapshots.len() > 2 && self.snapshots[1].time <= time {
+			self.snapshots.pop_front();
+		}
+
+		match self.snapshots.len() {
+			0 => None,
+			1 => Some(self.snapshots[0].transform),
+			_ => {
+				let (a, b) = (&self.snapshots[0], &self.snapshots[1]);
+				let span = b.time - a.time;
+				let t = if span > 0.0 { ((time - a.time) / span).max(0.0) } else { 1.0 };
+				let t = t.min(1.0 + if span > 0.0 { max_extrapolation.max(0.0) / span } else { 0.0 }) as f32;
+				Some(a.transform.lerp(b.transform, t))
+			},
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+struct Snapshot {
+	time: f64,
+	transform: Transform,
+}
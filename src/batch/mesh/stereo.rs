@@ -0,0 +1,74 @@
+use crate::camera::Camera;
+use crate::math::Transform;
+use crate::window::Window;
+use cgmath::{ prelude::*, vec3 };
+use vulkano::memory::DeviceMemoryAllocError;
+
+/// Renders the scene once per eye, from two cameras side by side, for checking stereo rendering without a headset.
+/// See `RenderSettings::stereo`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+	pub mode: StereoMode,
+	/// Distance between the eyes, in world units. The eyes are moved apart along the camera's x axis.
+	pub eye_separation: f32,
+}
+impl Stereo {
+	pub fn new(mode: StereoMode) -> Self {
+		Self { mode: mode, eye_separation: 0.064 }
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StereoMode {
+	/// Both eyes over the whole target, the left in red and the right in green and blue, for red-cyan glasses.
+	Anaglyph,
+	/// The left eye in the left half of the target and the right eye in the right half, each with half the aspect
+	/// ratio.
+	SideBySide,
+}
+
+/// The cameras of both eyes, following the camera a `MeshBatch` is given.
+pub(super) struct EyeCameras {
+	cameras: [Camera; 2],
+}
+impl EyeCameras {
+	pub(super) fn new(window: &Window, camera: &Camera, stereo: Stereo) -> Result<Self, DeviceMemoryAllocError> {
+		let make_camera = || {
+			Camera::new(window, camera.transform(), camera.aspect(), camera.fovx(), camera.znear(), camera.zfar())
+		};
+		let mut eyes = Self { cameras: [make_camera()?, make_camera()?] };
+		eyes.update(camera, stereo)?;
+		Ok(eyes)
+	}
+
+	pub(super) fn left(&self) -> &Camera {
+		&self.cameras[0]
+	}
+
+	pub(super) fn right(&self) -> &Camera {
+		&self.cameras[1]
+	}
+
+	/// Moves the eyes to either side of `camera`. Projections are only replaced when they change.
+	pub(super) fn update(&mut self, camera: &Camera, stereo: Stereo) -> Result<(), DeviceMemoryAllocError> {
+		let aspect =
+			match stereo.mode {
+				StereoMode::Anaglyph => camera.aspect(),
+				StereoMode::SideBySide => camera.aspect() * 0.5,
+			};
+
+		for (eye, side) in self.cameras.iter_mut().zip(&[-0.5f32, 0.5]) {
+			let offset = camera.rotation().rotate_vector(vec3(side * stereo.eye_separation, 0.0, 0.0));
+			eye.set_transform(Transform::new(camera.position() + offset, camera.rotation()))?;
+
+			if (eye.aspect(), eye.fovx(), eye.znear(), eye.zfar())
+				!= (aspect, camera.fovx(), camera.znear(), camera.zfar()) {
+				eye.set_projection(aspect, camera.fovx(), camera.znear(), camera.zfar())?;
+			}
+			if eye.tile() != camera.tile() {
+				eye.set_tile(camera.tile())?;
+			}
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,82 @@
+use crate::batch::mesh::ribbon::RibbonVertex;
+use crate::color::Color;
+use cgmath::{ prelude::*, Vector3 };
+use std::collections::VecDeque;
+
+/// How a mesh's motion trail looks, for `MeshBatch::set_motion_trail`. The trail is a line through the positions the
+/// mesh was drawn at in recent frames, fading out toward the oldest, for telling apart jitter, stalls and snapping
+/// when syncing meshes to physics or interpolating them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MotionTrail {
+	pub color: Color,
+	/// In world units.
+	pub width: f32,
+	/// Positions kept. Frames the mesh didn't move in aren't recorded, so the trail stays put while the mesh does.
+	pub length: usize,
+}
+impl MotionTrail {
+	pub fn new(color: Color) -> Self {
+		Self { color: color, width: 0.05, length: 64 }
+	}
+}
+
+/// The positions a `MotionTrail` has recorded for one mesh, newest first.
+pub(super) struct MotionTrailState {
+	trail: MotionTrail,
+	positions: VecDeque<Vector3<f32>>,
+}
+impl MotionTrailState {
+	pub(super) fn new(trail: MotionTrail) -> Self {
+		Self { trail: trail, positions: VecDeque::with_capacity(trail.length) }
+	}
+
+	/// Keeps the recorded positions when only the look changes.
+	pub(super) fn set_trail(&mut self, trail: MotionTrail) {
+		self.positions.truncate(trail.length);
+		self.trail = trail;
+	}
+
+	pub(super) fn record(&mut self, position: Vector3<f32>) {
+		if self.positions.front() == Some(&position) || self.trail.length == 0 {
+			return;
+		}
+		if self.positions.len() >= self.trail.length {
+			self.positions.truncate(self.trail.length - 1);
+		}
+		self.positions.push_front(position);
+	}
+
+	/// A strip for the ribbon pipeline, or `None` until two positions have been recorded.
+	pub(super) fn vertices(&self) -> Option<Vec<RibbonVertex>> {
+		let count = self.positions.len();
+		if count < 2 {
+			return None;
+		}
+
+		let color = self.trail.color.to_linear();
+		let half_width = self.trail.width * 0.5;
+		let mut vertices = Vec::with_capacity(count * 2);
+		for (i, &position) in self.positions.iter().enumerate() {
+			let prev = self.positions[i.saturating_sub(1)];
+			let next = self.positions[(i + 1).min(count - 1)];
+			let tangent = next - prev;
+			let tangent = if tangent.magnitude2() > 0.0 { tangent.normalize() } else { Vector3::zero() };
+
+			// colors are premultiplied, so the whole color fades
+			let u = i as f32 / (count - 1) as f32;
+			let fade = 1.0 - u;
+			let color = [color[0] * fade, color[1] * fade, color[2] * fade, color[3] * fade];
+
+			for &(offset, v) in &[(half_width, 0.0), (-half_width, 1.0)] {
+				vertices.push(RibbonVertex {
+					position: position.into(),
+					tangent: tangent.into(),
+					offset: offset,
+					tex_coords: [u, v],
+					color: color,
+				});
+			}
+		}
+		Some(vertices)
+	}
+}
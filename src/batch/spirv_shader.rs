@@ -0,0 +1,236 @@
+use vulkano::descriptor::descriptor::{
+	DescriptorBufferDesc,
+	DescriptorDesc,
+	DescriptorDescTy,
+	DescriptorImageDesc,
+	DescriptorImageDescArray,
+	DescriptorImageDescDimensions,
+	ShaderStages,
+};
+
+/// Kinds of descriptors the crate's shaders bind, described the way `vulkano_shaders::shader!` describes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// only the mesh batch's shaders use storage buffers and input attachments
+#[cfg_attr(not(feature = "mesh"), allow(dead_code))]
+pub(crate) enum Descriptor {
+	Uniform,
+	Storage,
+	/// A `sampler2D`.
+	Sampler,
+	InputAttachment,
+}
+impl Descriptor {
+	pub(crate) fn desc(self, stages: ShaderStages) -> DescriptorDesc {
+		let (ty, readonly) =
+			match self {
+				Descriptor::Uniform =>
+					(DescriptorDescTy::Buffer(DescriptorBufferDesc { dynamic: Some(false), storage: false }), true),
+				Descriptor::Storage =>
+					(DescriptorDescTy::Buffer(DescriptorBufferDesc { dynamic: Some(false), storage: true }), false),
+				Descriptor::Sampler => (
+					DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+						sampled: true,
+						dimensions: DescriptorImageDescDimensions::TwoDimensional,
+						format: None,
+						multisampled: false,
+						array_layers: DescriptorImageDescArray::NonArrayed,
+					}),
+					true,
+				),
+				Descriptor::InputAttachment => (
+					DescriptorDescTy::InputAttachment {
+						multisampled: false,
+						array_layers: DescriptorImageDescArray::NonArrayed,
+					},
+					true,
+				),
+			};
+		DescriptorDesc { ty: ty, array_count: 1, stages: stages, readonly: readonly }
+	}
+}
+
+/// Stands in for `vulkano_shaders::shader!` without the `builtin-shaders` feature, with the same `Shader`, `Layout`
+/// and push constant types, but with the interface written out by hand instead of read from SPIR-V compiled with the
+/// crate. `Shader::load` gets the module named `name` from the device's `ShaderModules`, so a precompiled `.spv` in a
+/// SPIR-V directory is used as is, and otherwise the GLSL at `path` is compiled into the cache directory on first use.
+macro_rules! spirv_shader {
+	(
+		name: $name:expr,
+		path: $path:expr,
+		ty: compute,
+		descriptors: [$(($set:expr, $binding:expr) => $descriptor:ident),* $(,)*],
+		push_constants: [$($pc_name:ident { $($pc_field:ident: $pc_ty:ty),* $(,)* }),* $(,)*] $(,)*
+	) => {
+		spirv_shader!(
+			@common $name,
+			$path,
+			compute,
+			[$(($set, $binding) => $descriptor),*],
+			[$($pc_name { $($pc_field: $pc_ty),* }),*]
+		);
+
+		impl Shader {
+			pub fn main_entry_point(&self) -> ::vulkano::pipeline::shader::ComputeEntryPoint<'_, (), Layout> {
+				unsafe {
+					self.module.compute_entry_point(
+						::std::ffi::CStr::from_bytes_with_nul_unchecked(b"main\0"),
+						Layout(::vulkano::descriptor::descriptor::ShaderStages {
+							compute: true,
+							..::vulkano::descriptor::descriptor::ShaderStages::none()
+						}),
+					)
+				}
+			}
+		}
+	};
+	(
+		name: $name:expr,
+		path: $path:expr,
+		ty: $ty:ident,
+		inputs: [$($in_location:expr => $in_format:ident $in_name:ident),* $(,)*],
+		outputs: [$($out_location:expr => $out_format:ident $out_name:ident),* $(,)*],
+		descriptors: [$(($set:expr, $binding:expr) => $descriptor:ident),* $(,)*],
+		push_constants: [$($pc_name:ident { $($pc_field:ident: $pc_ty:ty),* $(,)* }),* $(,)*] $(,)*
+	) => {
+		spirv_shader!(
+			@common $name,
+			$path,
+			$ty,
+			[$(($set, $binding) => $descriptor),*],
+			[$($pc_name { $($pc_field: $pc_ty),* }),*]
+		);
+		spirv_shader!(@interface MainInput, [$($in_location => $in_format $in_name),*]);
+		spirv_shader!(@interface MainOutput, [$($out_location => $out_format $out_name),*]);
+
+		impl Shader {
+			pub fn main_entry_point(
+				&self,
+			) -> ::vulkano::pipeline::shader::GraphicsEntryPoint<'_, (), MainInput, MainOutput, Layout> {
+				unsafe {
+					self.module.graphics_entry_point(
+						::std::ffi::CStr::from_bytes_with_nul_unchecked(b"main\0"),
+						MainInput,
+						MainOutput,
+						Layout(spirv_shader!(@stages $ty)),
+						spirv_shader!(@graphics_type $ty),
+					)
+				}
+			}
+		}
+	};
+
+	(@stages vertex) => {
+		::vulkano::descriptor::descriptor::ShaderStages {
+			vertex: true,
+			..::vulkano::descriptor::descriptor::ShaderStages::none()
+		}
+	};
+	(@stages fragment) => {
+		::vulkano::descriptor::descriptor::ShaderStages {
+			fragment: true,
+			..::vulkano::descriptor::descriptor::ShaderStages::none()
+		}
+	};
+	(@graphics_type vertex) => { ::vulkano::pipeline::shader::GraphicsShaderType::Vertex };
+	(@graphics_type fragment) => { ::vulkano::pipeline::shader::GraphicsShaderType::Fragment };
+	(@shader_stage vertex) => { $crate::device::ShaderStage::Vertex };
+	(@shader_stage fragment) => { $crate::device::ShaderStage::Fragment };
+	(@shader_stage compute) => { $crate::device::ShaderStage::Compute };
+
+	(@interface $name:ident, [$($location:expr => $format:ident $field:ident),*]) => {
+		#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+		pub struct $name;
+		unsafe impl ::vulkano::pipeline::shader::ShaderInterfaceDef for $name {
+			type Iter = ::std::vec::IntoIter<::vulkano::pipeline::shader::ShaderInterfaceDefEntry>;
+
+			fn elements(&self) -> Self::Iter {
+				vec![
+					$(::vulkano::pipeline::shader::ShaderInterfaceDefEntry {
+						location: $location..$location + 1,
+						format: ::vulkano::format::Format::$format,
+						name: Some(::std::borrow::Cow::Borrowed(stringify!($field))),
+					}),*
+				].into_iter()
+			}
+		}
+	};
+
+	(
+		@common $name:expr,
+		$path:expr,
+		$ty:ident,
+		[$(($set:expr, $binding:expr) => $descriptor:ident),*],
+		[$($pc_name:ident { $($pc_field:ident: $pc_ty:ty),* }),*]
+	) => {
+		const DESCRIPTORS: &[(usize, usize, $crate::batch::spirv_shader::Descriptor)] =
+			&[$(($set, $binding, $crate::batch::spirv_shader::Descriptor::$descriptor)),*];
+
+		pub mod ty {
+			$(
+				#[derive(Clone, Copy, Debug)]
+				#[repr(C)]
+				pub struct $pc_name {
+					$(pub $pc_field: $pc_ty),*
+				}
+			)*
+		}
+
+		const PUSH_CONSTANT_SIZES: &[usize] = &[$(::std::mem::size_of::<ty::$pc_name>()),*];
+
+		pub struct Shader {
+			module: ::std::sync::Arc<::vulkano::pipeline::shader::ShaderModule>,
+		}
+		impl Shader {
+			pub fn load(
+				device: ::std::sync::Arc<::vulkano::device::Device>,
+			) -> Result<Self, $crate::device::ShaderModuleError> {
+				let ctx = $crate::device::DeviceCtx::find(&device).expect("device has no context");
+				let source = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $path));
+				let module = ctx.shader_modules().get_or_compile($name, spirv_shader!(@shader_stage $ty), source)?;
+				Ok(Self { module: module })
+			}
+		}
+
+		#[derive(Debug, Clone)]
+		pub struct Layout(pub ::vulkano::descriptor::descriptor::ShaderStages);
+		unsafe impl ::vulkano::descriptor::pipeline_layout::PipelineLayoutDesc for Layout {
+			fn num_sets(&self) -> usize {
+				DESCRIPTORS.iter().map(|&(set, _, _)| set + 1).max().unwrap_or(0)
+			}
+
+			fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+				if set >= self.num_sets() {
+					return None;
+				}
+				Some(DESCRIPTORS.iter().filter(|desc| desc.0 == set).map(|desc| desc.1 + 1).max().unwrap_or(0))
+			}
+
+			fn descriptor(
+				&self,
+				set: usize,
+				binding: usize,
+			) -> Option<::vulkano::descriptor::descriptor::DescriptorDesc> {
+				DESCRIPTORS.iter()
+					.find(|desc| desc.0 == set && desc.1 == binding)
+					.map(|desc| desc.2.desc(self.0.clone()))
+			}
+
+			fn num_push_constants_ranges(&self) -> usize {
+				PUSH_CONSTANT_SIZES.len()
+			}
+
+			fn push_constants_range(
+				&self,
+				num: usize,
+			) -> Option<::vulkano::descriptor::pipeline_layout::PipelineLayoutDescPcRange> {
+				PUSH_CONSTANT_SIZES.get(num).map(|&size| {
+					::vulkano::descriptor::pipeline_layout::PipelineLayoutDescPcRange {
+						offset: 0,
+						size: size,
+						stages: ::vulkano::descriptor::descriptor::ShaderStages::all(),
+					}
+				})
+			}
+		}
+	};
+}
@@ -1,13 +1,36 @@
+mod backdrop;
+mod blur;
+mod cursor;
 mod font;
+mod rect;
 mod shaders;
 mod shared;
 mod sprite;
+mod stats_overlay;
+mod trail;
+mod ui_cache;
+mod video;
 
-pub use self::font::Font;
-pub use self::shaders::SpriteBatchShaders;
+pub use self::backdrop::{ BackdropBlur, BackdropBlurSprite };
+pub use self::blur::Blur;
+pub use self::cursor::SoftwareCursor;
+pub use self::font::{ Font, TextSprite };
+pub(crate) use self::font::TextLayoutCache;
+pub use self::rect::Rect;
+pub use self::shaders::{ SpriteBatchShaders, SpriteBatchShadersError };
+pub(crate) use self::shared::SpritePipelineCache;
 pub use self::shared::SpriteBatchShared;
-pub use self::sprite::Sprite;
-use crate::{ ImageFramebuffer, ObjectId, RenderTarget, window::Window };
+pub use self::sprite::{ Sprite, SpriteFilter };
+pub use self::stats_overlay::{ StatsOverlay, StatsOverlayToggle };
+pub use self::trail::Trail2D;
+pub use self::ui_cache::UiCache;
+pub use self::video::VideoSprite;
+use crate::{ DuplicateId, ImageFramebuffer, ObjectId, RenderTarget, StableId, StableIds, window::Window };
+use crate::batch::{ HookCommands, HookError, RenderHook, hook };
+use crate::color::Color;
+use crate::compositor::{ Compositor, Layer };
+use crate::device::DeviceCtx;
+use crate::stats::{ self, BatchResources, Resource, ResourceTally };
 use std::sync::Arc;
 use vulkano::{
 	OomError,
@@ -15,6 +38,7 @@ use vulkano::{
 	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError },
 	descriptor::{ DescriptorSet, PipelineLayoutAbstract, descriptor_set::PersistentDescriptorSet },
 	device::Queue,
+	format::ClearValue,
 	framebuffer::{ Framebuffer, FramebufferAbstract, FramebufferCreationError },
 	image::ImageViewAccess,
 	instance::QueueFamily,
@@ -25,32 +49,56 @@ use vulkano::{
 pub struct SpriteBatch {
 	shared: Arc<SpriteBatchShared>,
 	sprites: Vec<Box<Drawable2D>>,
-	framebuffers: Vec<ImageFramebuffer>,
+	sprite_stable_ids: Vec<StableId>,
+	sprite_ids: StableIds,
+	framebuffers: Vec<Option<ImageFramebuffer>>,
+	/// Commands of static sprites, per target image, drawn again until the sprite is borrowed mutably or the image's
+	/// framebuffer changes.
+	recorded: Vec<Vec<Option<Arc<AutoCommandBuffer>>>>,
+	clear_color: [f32; 4],
+	layer: Layer,
+	blend: LayerBlend,
+	pixel_snap: bool,
 	target_id: ObjectId,
 	target_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	hook: Option<RenderHook>,
 }
 impl SpriteBatch {
 	pub fn new(
 		window: &Window,
 		target: &RenderTarget,
 		shared: Arc<SpriteBatchShared>
+	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
+		Self::from_device(window.device(), target, shared)
+	}
+
+	/// A batch on `device` without a window, e.g. drawing to a `TargetTexture` on a `Context::headless_device`. Draw
+	/// it with `composite_on_device` or `commands_on_device`.
+	pub fn from_device(
+		device: &DeviceCtx,
+		target: &RenderTarget,
+		shared: Arc<SpriteBatchShared>
 	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
 		let dimensions = target.images()[0].dimensions();
 		let (target_descs, future) =
 			Self::make_target_desc(
-				window.device().queue().clone(),
+				device.queue().clone(),
 				shared.pipeline_sprite().clone(),
 				dimensions.width(),
-				dimensions.height()
+				dimensions.height(),
+				false
 			)?;
 
 		let framebuffers =
 			target.images().iter()
 				.map(|image| {
-					Framebuffer::start(shared.subpass().render_pass().clone())
+					Framebuffer::start(shared.render_pass(LayerBlend::Replace).clone())
 						.add(image.clone())
 						.and_then(|fb| fb.build())
-						.map(|fb| ImageFramebuffer::new(Arc::downgrade(&image), Arc::new(fb)))
+						.map(|fb| {
+							let fb = Arc::new(stats::track(Resource::Framebuffer, fb));
+							Some(ImageFramebuffer::new(Arc::downgrade(&image), fb))
+						})
 						.map_err(|err| match err {
 							FramebufferCreationError::OomError(err) => err,
 							err => unreachable!("{:?}", err),
@@ -62,34 +110,171 @@ impl SpriteBatch {
 			Self {
 				shared: shared,
 				sprites: vec![],
+				sprite_stable_ids: vec![],
+				sprite_ids: StableIds::default(),
 				framebuffers: framebuffers,
+				recorded: vec![],
+				clear_color: [0.1, 0.1, 0.1, 1.0],
+				layer: Layer::HUD,
+				blend: LayerBlend::Replace,
+				pixel_snap: false,
 				target_id: target.id_root().make_id(),
 				target_desc: target_descs,
+				hook: None,
 			},
 			future
 		))
 	}
 
-	pub fn add_sprite(&mut self, sprite: Box<Drawable2D>) {
+	pub fn add_sprite(&mut self, sprite: Box<Drawable2D>) -> SpriteHandle {
+		let id = self.sprite_ids.insert(self.sprites.len());
+		self.insert_sprite(sprite, id)
+	}
+
+	/// Like `add_sprite`, with an id saved from an earlier run. Fails if the id is already used for a sprite.
+	pub fn add_sprite_with_id(&mut self, sprite: Box<Drawable2D>, id: StableId) -> Result<SpriteHandle, DuplicateId> {
+		self.sprite_ids.insert_with_id(id, self.sprites.len())?;
+		Ok(self.insert_sprite(sprite, id))
+	}
+
+	/// Borrowing a sprite mutably counts as changing it, so its commands are recorded again on the next frame.
+	pub fn sprite_mut(&mut self, handle: SpriteHandle) -> &mut Drawable2D {
+		for recorded in &mut self.recorded {
+			if let Some(commands) = recorded.get_mut(handle.0) {
+				*commands = None;
+			}
+		}
+		&mut *self.sprites[handle.0]
+	}
+
+	pub fn sprite_id(&self, handle: SpriteHandle) -> StableId {
+		self.sprite_stable_ids[handle.0]
+	}
+
+	pub fn find_sprite(&self, id: StableId) -> Option<SpriteHandle> {
+		self.sprite_ids.index(id).map(SpriteHandle)
+	}
+
+	/// Invalidates every `SpriteHandle` from this batch, and frees their ids.
+	pub fn clear_sprites(&mut self) {
+		self.sprites.clear();
+		self.sprite_stable_ids.clear();
+		self.sprite_ids.clear();
+		self.recorded.clear();
+	}
+
+	/// Color the target is cleared to before drawing, with `LayerBlend::Replace`. Defaults to dark grey.
+	pub fn set_clear_color(&mut self, color: Color) {
+		// the targets are sRGB, so clear values are linear and encoded on write
+		self.clear_color = color.to_linear();
+	}
+
+	/// Calls `hook` every frame after the sprites are drawn, to draw over them in the same render pass.
+	pub fn set_hook(&mut self, hook: impl FnMut(&mut HookCommands) -> Result<(), HookError> + 'static) {
+		self.hook = Some(Box::new(hook));
+	}
+
+	pub fn clear_hook(&mut self) {
+		self.hook = None;
+	}
+
+	pub fn layer(&self) -> Layer {
+		self.layer
+	}
+
+	/// Layer `composite` adds this batch's commands to. Defaults to `Layer::HUD`.
+	pub fn set_layer(&mut self, layer: Layer) {
+		self.layer = layer;
+	}
+
+	pub fn blend(&self) -> LayerBlend {
+		self.blend
+	}
+
+	pub fn set_blend(&mut self, blend: LayerBlend) {
+		if blend != self.blend {
+			// framebuffers are created against the render pass for the blend mode, and so are sprite commands
+			self.framebuffers.clear();
+			self.recorded.clear();
+			self.blend = blend;
+		}
+	}
+
+	/// Buffers, images and descriptor sets held by the batch and its sprites. Targets aren't included, since they
+	/// belong to whoever created them.
+	pub fn resource_stats(&self) -> BatchResources {
+		let mut tally = ResourceTally::new();
+		tally.add_descriptor_set();
+		for sprite in &self.sprites {
+			sprite.resources(&mut tally);
+		}
+		tally.finish()
+	}
+
+	pub fn pixel_snap(&self) -> bool {
+		self.pixel_snap
+	}
+
+	/// Rounds sprite and text positions to whole target pixels when drawing, so UI and pixel art moving by fractions
+	/// of a pixel don't shimmer. Trails and the sprites' own sizes aren't changed. Defaults to false.
+	pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+		if pixel_snap != self.pixel_snap {
+			// the flag lives in the target descriptor, which is rebuilt along with the framebuffers
+			self.framebuffers.clear();
+			self.recorded.clear();
+			self.pixel_snap = pixel_snap;
+		}
+	}
+
+	/// Adds this batch's commands to `compositor` on its layer, so it draws after anything on lower layers.
+	pub fn composite(
+		&mut self,
+		window: &Window,
+		target: &RenderTarget,
+		image_num: usize,
+		compositor: &mut Compositor,
+	) -> Result<(), DeviceMemoryAllocError> {
+		self.composite_on_device(window.device(), target, image_num, compositor)
+	}
+
+	pub fn composite_on_device(
+		&mut self,
+		device: &DeviceCtx,
+		target: &RenderTarget,
+		image_num: usize,
+		compositor: &mut Compositor,
+	) -> Result<(), DeviceMemoryAllocError> {
+		let (commands, future) = self.commands_on_device(device, target, image_num)?;
+		compositor.add(self.layer, commands, future);
+		Ok(())
+	}
+
+	fn insert_sprite(&mut self, sprite: Box<Drawable2D>, id: StableId) -> SpriteHandle {
 		self.sprites.push(sprite);
+		self.sprite_stable_ids.push(id);
+		SpriteHandle(self.sprites.len() - 1)
 	}
 
 	fn make_target_desc(
 		queue: Arc<Queue>,
 		pipeline: impl PipelineLayoutAbstract + Send + Sync + 'static,
 		width: u32,
-		height: u32
+		height: u32,
+		pixel_snap: bool,
 	) -> Result<(Arc<DescriptorSet + Send + Sync + 'static>, impl GpuFuture), DeviceMemoryAllocError> {
-		let (target_size, future) = ImmutableBuffer::from_data([width, height], BufferUsage::uniform_buffer(), queue)?;
+		// matches `Target` in the sprite shaders, which only snap when asked to
+		let target = [width, height, pixel_snap as u32];
+		let (target_size, future) = ImmutableBuffer::from_data(target, BufferUsage::uniform_buffer(), queue)?;
 
 		Ok((
-			Arc::new(
+			Arc::new(stats::track(
+				Resource::DescriptorSet,
 				PersistentDescriptorSet::start(pipeline, 0)
 					.add_buffer(target_size.clone())
 					.unwrap()
 					.build()
-					.unwrap()
-			),
+					.unwrap(),
+			)),
 			future
 		))
 	}
@@ -99,38 +284,57 @@ impl SpriteBatch {
 		window: &Window,
 		target: &RenderTarget,
 		image_num: usize,
+	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), DeviceMemoryAllocError> {
+		self.commands_on_device(window.device(), target, image_num)
+	}
+
+	pub fn commands_on_device(
+		&mut self,
+		device: &DeviceCtx,
+		target: &RenderTarget,
+		image_num: usize,
 	) -> Result<(AutoCommandBuffer, Option<impl GpuFuture>), DeviceMemoryAllocError> {
 		assert!(self.target_id.is_child_of(target.id_root()));
 
-		let framebuffer = self.framebuffers[image_num].image
-			.upgrade()
-			.iter()
-			.filter(|old_image| Arc::ptr_eq(&target.images()[image_num], &old_image))
-			.next()
-			.map(|_| self.framebuffers[image_num].framebuffer.clone());
+		// the swapchain may have been recreated with a different number of images
+		self.framebuffers.resize(target.images().len(), None);
+		self.recorded.resize(target.images().len(), vec![]);
+
+		let framebuffer = self.framebuffers[image_num].as_ref()
+			.and_then(|framebuffer| {
+				framebuffer.image
+					.upgrade()
+					.filter(|old_image| Arc::ptr_eq(&target.images()[image_num], &old_image))
+					.map(|_| framebuffer.framebuffer.clone())
+			});
 		let (framebuffer, future) =
 			if let Some(framebuffer) = framebuffer {
 				(framebuffer, None)
 			} else {
-				let framebuffer = Framebuffer::start(self.shared.subpass().render_pass().clone())
+				let framebuffer = Framebuffer::start(self.shared.render_pass(self.blend).clone())
 					.add(target.images()[image_num].clone())
 					.and_then(|fb| fb.build())
-					.map(|fb| Arc::new(fb))
+					.map(|fb| Arc::new(stats::track(Resource::Framebuffer, fb)))
 					.map_err(|err| {
 						match err { FramebufferCreationError::OomError(err) => err, err => unreachable!("{:?}", err) }
 					})?;
 				self.framebuffers[image_num] =
-					ImageFramebuffer::new(Arc::downgrade(&target.images()[image_num]), framebuffer.clone());
+					Some(ImageFramebuffer::new(Arc::downgrade(&target.images()[image_num]), framebuffer.clone()));
 
 				let (target_desc, future) =
 					Self::make_target_desc(
-						window.device().queue().clone(),
+						device.queue().clone(),
 						self.shared.pipeline_sprite().clone(),
 						framebuffer.width(),
-						framebuffer.height()
+						framebuffer.height(),
+						self.pixel_snap
 					)?;
 
 				self.target_desc = target_desc;
+				// recorded sprites bind the old target descriptor
+				for recorded in &mut self.recorded {
+					recorded.clear();
+				}
 
 				(framebuffer as _, Some(future))
 			};
@@ -138,19 +342,45 @@ impl SpriteBatch {
 		let dimensions = [framebuffer.width() as f32, framebuffer.height() as f32];
 
 		let mut command_buffer =
-			AutoCommandBufferBuilder::primary_one_time_submit(self.shared.shaders().device().clone(), window.device().queue().family())?
-				.begin_render_pass(framebuffer, true, vec![[0.1, 0.1, 0.1, 1.0].into()])
+			AutoCommandBufferBuilder::primary_one_time_submit(self.shared.shaders().device().clone(), device.queue().family())?
+				.begin_render_pass(
+					framebuffer,
+					true,
+					vec![match self.blend { LayerBlend::Replace => self.clear_color.into(), LayerBlend::Over => ClearValue::None }]
+				)
 				.unwrap();
 
-		for sprite in &mut self.sprites {
-			command_buffer =
-				unsafe {
-					command_buffer
-						.execute_commands(
-							sprite.make_commands(&self.shared, &self.target_desc, window.device().queue().family(), dimensions)?
-						)
-						.unwrap()
+		let recorded = &mut self.recorded[image_num];
+		recorded.resize(self.sprites.len(), None);
+		for (sprite, recorded) in self.sprites.iter_mut().zip(recorded.iter_mut()) {
+			let commands =
+				if let Some(commands) = recorded.clone() {
+					commands
+				} else {
+					let queue_family = device.queue().family();
+					let commands =
+						Arc::new(sprite.make_commands(&self.shared, &self.target_desc, queue_family, dimensions)?);
+					if sprite.is_static() {
+						*recorded = Some(commands.clone());
+					}
+					commands
 				};
+			command_buffer = unsafe { command_buffer.execute_commands(commands).unwrap() };
+		}
+
+		if let Some(after_sprites) = &mut self.hook {
+			let commands =
+				hook::record(
+					after_sprites,
+					self.shared.shaders().device().clone(),
+					device.queue().family(),
+					self.shared.subpass().clone(),
+					self.target_desc.clone(),
+					dimensions
+				)?;
+			if let Some(commands) = commands {
+				command_buffer = unsafe { command_buffer.execute_commands(commands).unwrap() };
+			}
 		}
 
 		Ok((
@@ -162,6 +392,19 @@ impl SpriteBatch {
 	}
 }
 
+/// Identifies a sprite added to a `SpriteBatch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SpriteHandle(usize);
+
+/// How a sprite batch combines with what lower layers already drew to the same target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerBlend {
+	/// Clears the target to the batch's clear color before drawing. The default.
+	Replace,
+	/// Keeps the target's contents and alpha blends sprites over them, e.g. a HUD over a 3D scene.
+	Over,
+}
+
 pub trait Drawable2D {
 	fn make_commands(
 		&mut self,
@@ -170,4 +413,16 @@ pub trait Drawable2D {
 		queue_family: QueueFamily,
 		dimensions: [f32; 2],
 	) -> Result<AutoCommandBuffer, OomError>;
+
+	/// Whether the commands from the last `make_commands` stay valid until the sprite is borrowed mutably from its
+	/// batch, so the batch can draw them again instead of asking for new ones. Static sprites must build their
+	/// commands for simultaneous use rather than one time submit. Defaults to false, for sprites that animate on
+	/// their own.
+	fn is_static(&self) -> bool {
+		false
+	}
+
+	/// Adds the buffers, images and descriptor sets the drawable holds to `tally`, for `SpriteBatch::resource_stats`.
+	/// Defaults to nothing.
+	fn resources(&self, _tally: &mut ResourceTally) {}
 }
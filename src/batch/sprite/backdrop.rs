@@ -0,0 +1,222 @@
+use super::{ Blur, Drawable2D, Rect };
+use super::shaders::backdrop_vs;
+use super::shared::SpriteBatchShared;
+use crate::RenderTarget;
+use crate::color::Color;
+use crate::stats::{ self, Resource, ResourceTally };
+use crate::texture::ViewImage;
+use std::sync::{ Arc, Mutex };
+use vulkano::{
+	OomError,
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	format::Format,
+	image::{ AttachmentImage, ImageCreationError, ImageUsage, ImageViewAccess },
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::viewport::Viewport,
+	sampler::Filter,
+};
+
+/// The backdrop is resolved and blurred at this fraction of the target's size, which is cheaper and lets the blur's
+/// kernel cover twice the distance.
+const BACKDROP_DOWNSCALE: u32 = 2;
+
+/// A blurred copy of what's been rendered to a target so far, shown through `BackdropBlurSprite`s for translucent
+/// "frosted glass" panels over the scene.
+///
+/// Add its `commands` to the compositor between the layers that should show through and the sprite batch holding the
+/// panels, e.g. on `Layer::POST` with the panels on `Layer::HUD`. The batch should use `LayerBlend::Over`.
+pub struct BackdropBlur {
+	shared: Arc<SpriteBatchShared>,
+	blur: Blur,
+	resolved: Arc<AttachmentImage>,
+	blurred: Arc<AttachmentImage>,
+	/// Shared with the sprites, so they follow the blurred image when it's reallocated for another target size.
+	desc: Arc<Mutex<Arc<DescriptorSet + Send + Sync + 'static>>>,
+	sigma: f32,
+}
+impl BackdropBlur {
+	/// For targets like `target`. Its images have to be usable as transfer sources, which windows' and
+	/// `TargetTexture`s' are.
+	pub fn new(shared: Arc<SpriteBatchShared>, target: &RenderTarget) -> Result<Self, DeviceMemoryAllocError> {
+		let dimensions = backdrop_dimensions(target.images()[0].dimensions().width_height());
+		let format = target.format();
+		let (resolved, blurred) = make_images(&shared, dimensions, format)?;
+		let desc = make_desc(&shared, &blurred);
+
+		Ok(Self {
+			blur: Blur::new(shared.shaders().clone(), format, dimensions)?,
+			shared: shared,
+			resolved: resolved,
+			blurred: blurred,
+			desc: Arc::new(Mutex::new(desc)),
+			sigma: 12.0,
+		})
+	}
+
+	/// How strongly the backdrop is blurred, as a gaussian sigma in target pixels. Defaults to 12.
+	pub fn set_sigma(&mut self, sigma: f32) {
+		self.sigma = sigma;
+	}
+
+	/// A panel over `bounds`, in target pixels, showing this backdrop. Add it to a `SpriteBatch` like any sprite.
+	pub fn make_sprite(&self, bounds: Rect) -> BackdropBlurSprite {
+		BackdropBlurSprite { desc: self.desc.clone(), bounds: bounds, tint: Color::TRANSPARENT }
+	}
+
+	/// Copies what's been rendered to image `image_num` of `target` so far and blurs it. The target's size may change
+	/// between calls; the copies follow it.
+	pub fn commands(
+		&mut self,
+		target: &RenderTarget,
+		image_num: usize,
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		let image = &target.images()[image_num];
+		let [width, height] = image.dimensions().width_height();
+		let dimensions = backdrop_dimensions([width, height]);
+		if self.resolved.dimensions().width_height() != dimensions || self.resolved.format() != target.format() {
+			let (resolved, blurred) = make_images(&self.shared, dimensions, target.format())?;
+			*self.desc.lock().unwrap() = make_desc(&self.shared, &blurred);
+			self.resolved = resolved;
+			self.blurred = blurred;
+		}
+
+		let command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(
+				self.shared.shaders().device().clone(),
+				self.shared.shaders().queue().family()
+			)?
+			.blit_image(
+				ViewImage(image.clone()),
+				[0, 0, 0],
+				[width as i32, height as i32, 1],
+				0,
+				0,
+				self.resolved.clone(),
+				[0, 0, 0],
+				[dimensions[0] as i32, dimensions[1] as i32, 1],
+				0,
+				0,
+				1,
+				Filter::Linear
+			)
+			.unwrap();
+
+		let resolved: Arc<ImageViewAccess + Send + Sync + 'static> = self.resolved.clone();
+		let blurred: Arc<ImageViewAccess + Send + Sync + 'static> = self.blurred.clone();
+		let sigma = self.sigma / BACKDROP_DOWNSCALE as f32;
+		let command_buffer = self.blur.record(command_buffer, &resolved, &blurred, sigma)?;
+
+		Ok(
+			command_buffer.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+}
+
+/// A rectangle showing the blurred backdrop of a `BackdropBlur` where it's drawn, with a tint mixed over it. Draw
+/// borders, text and the rest of the panel over it with other sprites in the same batch.
+pub struct BackdropBlurSprite {
+	desc: Arc<Mutex<Arc<DescriptorSet + Send + Sync + 'static>>>,
+	bounds: Rect,
+	tint: Color,
+}
+impl BackdropBlurSprite {
+	pub fn bounds(&self) -> Rect {
+		self.bounds
+	}
+
+	pub fn set_bounds(&mut self, bounds: Rect) {
+		self.bounds = bounds;
+	}
+
+	pub fn tint(&self) -> Color {
+		self.tint
+	}
+
+	/// Mixed over the blurred backdrop by its alpha, e.g. a white with an alpha of 0.2 for light glass. Defaults to
+	/// transparent, showing only the backdrop.
+	pub fn set_tint(&mut self, tint: Color) {
+		self.tint = tint;
+	}
+}
+impl Drawable2D for BackdropBlurSprite {
+	fn make_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, OomError> {
+		let desc = self.desc.lock().unwrap().clone();
+		let mut cmd =
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?;
+
+		if !self.bounds.is_empty() {
+			cmd = cmd
+				.draw(
+					shared.pipeline_backdrop().clone(),
+					&DynamicState {
+						line_width: None,
+						viewports:
+							Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+						scissors: None,
+					},
+					vec![shared.shaders().vertices().clone()],
+					(target_desc.clone(), desc),
+					backdrop_vs::ty::Backdrop {
+						min: self.bounds.min,
+						size: self.bounds.size(),
+						tint: self.tint.to_linear(),
+					}
+				)
+				.unwrap();
+		}
+
+		Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+	}
+
+	/// The blurred images belong to the `BackdropBlur`, so only the descriptor set is counted.
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_descriptor_set();
+	}
+}
+
+fn backdrop_dimensions([width, height]: [u32; 2]) -> [u32; 2] {
+	[(width / BACKDROP_DOWNSCALE).max(1), (height / BACKDROP_DOWNSCALE).max(1)]
+}
+
+fn make_images(
+	shared: &SpriteBatchShared,
+	dimensions: [u32; 2],
+	format: Format,
+) -> Result<(Arc<AttachmentImage>, Arc<AttachmentImage>), DeviceMemoryAllocError> {
+	let map_err =
+		|err: ImageCreationError| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() };
+	let device = shared.shaders().device();
+
+	// blitted into from the target, so it can be scaled down on the way
+	let usage = ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() };
+	let resolved = AttachmentImage::with_usage(device.clone(), dimensions, format, usage).map_err(map_err)?;
+	let blurred = AttachmentImage::sampled(device.clone(), dimensions, format).map_err(map_err)?;
+	Ok((resolved, blurred))
+}
+
+fn make_desc(
+	shared: &SpriteBatchShared,
+	blurred: &Arc<AttachmentImage>,
+) -> Arc<DescriptorSet + Send + Sync + 'static> {
+	Arc::new(stats::track(
+		Resource::DescriptorSet,
+		PersistentDescriptorSet::start(shared.pipeline_backdrop().clone(), 1)
+			.add_sampled_image(blurred.clone(), shared.shaders().blur_sampler().clone())
+			.unwrap()
+			.build()
+			.unwrap(),
+	))
+}
@@ -0,0 +1,165 @@
+use crate::RenderTarget;
+use crate::stats::{ self, Resource };
+use crate::texture::{ TargetTexture, Texture };
+use super::shaders::{ SpriteBatchShaders, SpriteVertex, blur_fs };
+use std::sync::Arc;
+use vulkano::{
+	single_pass_renderpass,
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::descriptor_set::PersistentDescriptorSet,
+	format::{ ClearValue, Format },
+	framebuffer::{ Framebuffer, FramebufferAbstract, FramebufferCreationError, RenderPassAbstract, Subpass },
+	image::{ AttachmentImage, ImageCreationError, ImageViewAccess },
+	memory::DeviceMemoryAllocError,
+	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract, viewport::Viewport },
+};
+
+/// Blurs a texture into a `TargetTexture` with a gaussian, horizontally and then vertically, for effects like UI
+/// backdrop blur and glows. Owns its pipeline and intermediate image, so one blur serves any number of sigmas. The
+/// intermediate image follows the size of the target it's given, and is only reallocated when that changes.
+pub struct Blur {
+	shaders: Arc<SpriteBatchShaders>,
+	render_pass: Arc<RenderPassAbstract + Send + Sync>,
+	pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	intermediate: Arc<AttachmentImage>,
+}
+impl Blur {
+	/// For targets of `format`, starting with an intermediate image of `dimensions`.
+	pub fn new(
+		shaders: Arc<SpriteBatchShaders>,
+		format: Format,
+		dimensions: [u32; 2],
+	) -> Result<Self, DeviceMemoryAllocError> {
+		let render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.device().clone(),
+					attachments: { color: { load: DontCare, store: Store, format: format, samples: 1, } },
+					pass: { color: [color], depth_stencil: {} }
+				).expect("failed to create render pass")
+			);
+
+		let pipeline =
+			Arc::new(
+				GraphicsPipeline::start()
+					.vertex_input_single_buffer::<SpriteVertex>()
+					.vertex_shader(shaders.blur_vertex_shader().main_entry_point(), ())
+					.triangle_list()
+					.viewports_dynamic_scissors_irrelevant(1)
+					.fragment_shader(shaders.blur_fragment_shader().main_entry_point(), ())
+					.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+					.build(shaders.device().clone())
+					.expect("failed to create pipeline")
+			);
+
+		let intermediate = make_intermediate(&shaders, dimensions, format)?;
+		Ok(Self { shaders: shaders, render_pass: render_pass, pipeline: pipeline, intermediate: intermediate })
+	}
+
+	/// Blurs `source` into `target` with a sigma of `sigma` target pixels, scaling it to the target's size. A sigma of
+	/// 0 only scales. Kernels are cut off at 32 pixels on either side, so sigmas above about 10 lose their tails; blur
+	/// a smaller target and scale it up for wider blurs.
+	pub fn commands(
+		&mut self,
+		source: &Texture,
+		target: &TargetTexture,
+		sigma: f32,
+	) -> Result<AutoCommandBuffer, DeviceMemoryAllocError> {
+		let command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(
+				self.shaders.device().clone(),
+				self.shaders.queue().family()
+			)?;
+		let command_buffer = self.record(command_buffer, source.image(), &target.images()[0], sigma)?;
+
+		Ok(
+			command_buffer.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+
+	/// Like `commands`, appending to `command_buffer`, for other effects that blur along with their own work.
+	pub(super) fn record(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		source: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		target: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		sigma: f32,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		let [width, height] = target.dimensions().width_height();
+		if self.intermediate.dimensions().width_height() != [width, height] {
+			self.intermediate = make_intermediate(&self.shaders, [width, height], target.format())?;
+		}
+
+		let intermediate: Arc<ImageViewAccess + Send + Sync + 'static> = self.intermediate.clone();
+		let command_buffer = self.pass(command_buffer, source, &intermediate, [1.0 / width as f32, 0.0], sigma)?;
+		self.pass(command_buffer, &intermediate, target, [0.0, 1.0 / height as f32], sigma)
+	}
+
+	fn pass(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		source: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		destination: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		step: [f32; 2],
+		sigma: f32,
+	) -> Result<AutoCommandBufferBuilder, DeviceMemoryAllocError> {
+		let [width, height] = destination.dimensions().width_height();
+		let dynamic_state =
+			DynamicState {
+				line_width: None,
+				viewports:
+					Some(vec![Viewport {
+						origin: [0.0, 0.0],
+						dimensions: [width as f32, height as f32],
+						depth_range: 0.0..1.0,
+					}]),
+				scissors: None,
+			};
+
+		let framebuffer: Arc<FramebufferAbstract + Send + Sync> =
+			Arc::new(stats::track(
+				Resource::Framebuffer,
+				Framebuffer::start(self.render_pass.clone())
+					.add(destination.clone())
+					.and_then(|fb| fb.build())
+					.map_err(|err| match err {
+						FramebufferCreationError::OomError(err) => err,
+						err => unreachable!("{:?}", err),
+					})?,
+			));
+
+		let desc =
+			PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+				.add_sampled_image(source.clone(), self.shaders.blur_sampler().clone())
+				.unwrap()
+				.build()
+				.unwrap();
+		let desc = stats::track(Resource::DescriptorSet, desc);
+
+		Ok(
+			command_buffer
+				.begin_render_pass(framebuffer, false, vec![ClearValue::None])
+				.unwrap()
+				.draw(
+					self.pipeline.clone(),
+					&dynamic_state,
+					vec![self.shaders.vertices().clone()],
+					desc,
+					blur_fs::ty::Blur { step: step, sigma: sigma }
+				)
+				.unwrap()
+				.end_render_pass()
+				.unwrap()
+		)
+	}
+}
+
+fn make_intermediate(
+	shaders: &SpriteBatchShaders,
+	dimensions: [u32; 2],
+	format: Format,
+) -> Result<Arc<AttachmentImage>, DeviceMemoryAllocError> {
+	AttachmentImage::sampled(shaders.device().clone(), dimensions, format)
+		.map_err(|err| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() })
+}
@@ -0,0 +1,128 @@
+use super::Drawable2D;
+use super::shared::SpriteBatchShared;
+use crate::stats::{ self, Resource, ResourceTally };
+use crate::window::{ Window, WindowState };
+use std::sync::Arc;
+use vulkano::{
+	OomError,
+	buffer::CpuBufferPool,
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	image::ImageViewAccess,
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::viewport::Viewport,
+	swapchain::Surface,
+};
+
+/// Draws the image set with `Window::set_cursor_image` at the cursor. Add it to the sprite batch drawn last, after
+/// everything else, so it stays on top. Nothing is drawn while the window has no cursor image or the cursor is outside
+/// the window.
+pub struct SoftwareCursor {
+	surface: Arc<Surface<winit::Window>>,
+	state: Arc<WindowState>,
+	position_pool: CpuBufferPool<[f32; 2]>,
+	static_desc: Option<(Arc<ImageViewAccess + Send + Sync + 'static>, Arc<DescriptorSet + Send + Sync + 'static>)>,
+}
+impl SoftwareCursor {
+	pub(crate) fn new(shared: &SpriteBatchShared, window: &Window) -> Self {
+		Self {
+			surface: window.surface().clone(),
+			state: window.state().clone(),
+			position_pool: CpuBufferPool::uniform_buffer(shared.shaders().device().clone()),
+			static_desc: None,
+		}
+	}
+}
+impl Drawable2D for SoftwareCursor {
+	fn make_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, OomError> {
+		let mut cmd =
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?;
+
+		let current = {
+			let cursor = self.state.cursor.lock().unwrap();
+			match (&cursor.image, cursor.position) {
+				(Some((image, hotspot)), Some(position)) => Some((image.clone(), *hotspot, position)),
+				_ => None,
+			}
+		};
+		let (image, hotspot, position) =
+			match current {
+				Some(current) => current,
+				None => return build(cmd),
+			};
+
+		let rebuild = self.static_desc.as_ref().map_or(true, |(old_image, _)| !Arc::ptr_eq(old_image, &image));
+		if rebuild {
+			let desc =
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(shared.pipeline_sprite().clone(), 2)
+						.add_sampled_image(image.clone(), shared.shaders().sprite_sampler().clone())
+						.unwrap()
+						.build()
+						.unwrap(),
+				));
+			self.static_desc = Some((image, desc));
+		}
+		let static_desc = self.static_desc.as_ref().unwrap().1.clone();
+
+		// cursor events are in logical pixels, sprites in physical ones
+		let position = position.to_physical(self.surface.window().get_hidpi_factor());
+		let corner = [position.x as f32 - hotspot[0] as f32, position.y as f32 - hotspot[1] as f32];
+		let position =
+			self.position_pool.next(corner)
+				.map(|position| stats::track(Resource::Subbuffer, position))
+				.map_err(|err| match err { DeviceMemoryAllocError::OomError(err) => err, err => unreachable!("{}", err) })?;
+
+		cmd = cmd
+			.draw(
+				shared.pipeline_sprite().clone(),
+				&DynamicState {
+					line_width: None,
+					viewports:
+						Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+					scissors: None,
+				},
+				vec![shared.shaders().vertices().clone()],
+				(
+					target_desc.clone(),
+					stats::track(
+						Resource::DescriptorSet,
+						shared.sprite_desc_pool().lock().unwrap()
+							.next()
+							.add_buffer(position)
+							.unwrap()
+							.build()
+							.unwrap(),
+					),
+					static_desc,
+				),
+				()
+			)
+			.unwrap();
+
+		build(cmd)
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		if let Some((image, _)) = &self.static_desc {
+			tally.add_image(&**image);
+			tally.add_descriptor_set();
+		}
+	}
+}
+
+fn build(cmd: AutoCommandBufferBuilder) -> Result<AutoCommandBuffer, OomError> {
+	Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+}
@@ -1,71 +1,141 @@
-use crate::batch::sprite::{ Drawable2D, SpriteBatchShared };
+use crate::batch::sprite::{ Drawable2D, Rect, SpriteBatchShared, shaders::text_vs };
+use crate::device::DeviceCtx;
+use crate::stats::{ self, Resource, ResourceTally };
 use crate::texture::{ Texture, ImmutableTexture };
+use decorum::R32;
 use rusttype::{ Font as RtFont, GlyphId, Point, Scale };
-use std::{ collections::HashMap, fs::File, io::{ self, prelude::* }, path::Path, sync::{ Arc, Mutex } };
+use std::{
+	collections::HashMap,
+	fs::File,
+	hash::Hash,
+	io::{ self, prelude::* },
+	ops::Range,
+	path::Path,
+	sync::{ Arc, Mutex, atomic::{ AtomicUsize, Ordering } },
+};
 use vulkano::{
 	OomError,
-	buffer::{ BufferUsage, ImmutableBuffer },
-	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, CommandBufferExecFuture, DynamicState },
+	buffer::{ BufferAccess, BufferUsage, CpuAccessibleBuffer, ImmutableBuffer },
+	command_buffer::{
+		AutoCommandBuffer,
+		AutoCommandBufferBuilder,
+		BuildError,
+		CommandBuffer,
+		CommandBufferExecFuture,
+		DynamicState,
+	},
 	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
 	device::Queue,
 	format::Format,
-	image::{ Dimensions, ImageCreationError, ImmutableImage },
+	image::{ Dimensions, ImageCreationError, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount },
 	instance::QueueFamily,
 	memory::DeviceMemoryAllocError,
 	pipeline::viewport::Viewport,
-	sync::{ FenceSignalFuture, FlushError, GpuFuture, JoinFuture, NowFuture },
+	sync::{ FenceSignalFuture, FlushError, GpuFuture, NowFuture },
 };
 
+/// Most text layouts a device's `TextLayoutCache` keeps. The least recently used one is dropped to make room.
+const TEXT_LAYOUT_CACHE_CAPACITY: usize = 256;
+
+/// Gives every font an id for the text layout cache, since fonts at the same address may be different fonts over time.
+static NEXT_FONT_ID: AtomicUsize = AtomicUsize::new(0);
+
 pub struct Font {
+	id: usize,
 	queue: Arc<Queue>,
 	scale: f32,
 	font: RtFont<'static>,
 	glyphs: Mutex<HashMap<GlyphId, Option<Glyph>>>,
-	futures: Mutex<HashMap<GlyphId, Arc<FenceSignalFuture<GlyphFuture>>>>,
+	/// Glyph uploads that haven't been seen to finish yet. Retired whenever more glyphs are loaded.
+	uploads: Mutex<Vec<Arc<GlyphUpload>>>,
 }
 impl Font {
+	/// Text laid out before with this font is taken from the device's cache along with its buffers, so labels created
+	/// again every frame, like in immediate mode UI, aren't uploaded again.
 	pub fn make_sprite(
 		&self,
 		text: &str,
 		shared: &SpriteBatchShared,
-		[x, y]: [f32; 2],
+		position: [f32; 2],
 	) -> Result<TextSprite, DeviceMemoryAllocError> {
-		self.load_chars(text.chars())?;
+		let layout =
+			match DeviceCtx::find(self.queue.device()) {
+				Some(device) => device.text_layouts().get_or_insert(self, text, || self.layout(text, shared))?,
+				None => Arc::new(self.layout(text, shared)?),
+			};
 
-		let mut positions = vec![];
+		let uploads =
+			self.uploads.lock().unwrap().iter()
+				.filter(|upload| layout.runs.iter().any(|run| upload.glyphs.contains(&run.id)))
+				.cloned()
+				.collect();
 
-		let mut static_descs = HashMap::new();
-		let mut glyph_futures = HashMap::new();
-		let glyphs = self.glyphs.lock().unwrap();
-		let futures = self.futures.lock().unwrap();
+		Ok(TextSprite { layout: layout, position: position, uploads: uploads })
+	}
 
-		for glyph in self.font.layout(text, Scale::uniform(self.scale), Point { x: x, y: y }) {
-			let id = glyph.id();
+	/// Lays out `text` from the origin and uploads where its glyphs go.
+	fn layout(&self, text: &str, shared: &SpriteBatchShared) -> Result<TextLayout, DeviceMemoryAllocError> {
+		self.load_chars(text.chars())?;
 
+		let glyphs = self.glyphs.lock().unwrap();
+		let mut placed = vec![];
+		let mut bounds = Rect::empty();
+		for glyph in self.font.layout(text, Scale::uniform(self.scale), Point { x: 0.0, y: 0.0 }) {
+			let id = glyph.id();
 			let point = glyph.position();
-			let (position, pos_future) =
-				ImmutableBuffer::from_data([point.x, point.y], BufferUsage::uniform_buffer(), self.queue.clone())?;
-			positions.push((id, position, Some(pos_future.then_signal_fence_and_flush().unwrap())));
 
 			if let Some(glyph) = glyphs.get(&id).unwrap() {
-				static_descs.entry(id)
-					.or_insert_with(|| Arc::new(
-						PersistentDescriptorSet::start(shared.pipeline_text().clone(), 2)
-							.add_buffer(glyph.offset.clone())
-							.unwrap()
-							.add_sampled_image(glyph.texture.image().clone(), shared.shaders().text_sampler().clone())
-							.unwrap()
-							.build()
-							.unwrap()
-					) as Arc<DescriptorSet + Send + Sync + 'static>);
-
-				if let Some(fut) = futures.get(&id) {
-					glyph_futures.insert(id, fut.clone());
-				}
+				bounds = bounds.union(Rect::new(
+					[point.x + glyph.bounds.min[0], point.y + glyph.bounds.min[1]],
+					[point.x + glyph.bounds.max[0], point.y + glyph.bounds.max[1]],
+				));
+				placed.push((id, [point.x + glyph.bounds.min[0], point.y + glyph.bounds.min[1]]));
 			}
 		}
 
-		Ok(TextSprite { static_descs: static_descs, positions: positions, futures: glyph_futures })
+		// grouped by glyph, so each distinct glyph is one instanced draw
+		placed.sort_by_key(|&(id, _)| id.0);
+		let mut runs: Vec<GlyphRun> = vec![];
+		for (i, &(id, _)) in placed.iter().enumerate() {
+			if let Some(run) = runs.last_mut().filter(|run| run.id == id) {
+				run.instances.end = i + 1;
+				continue;
+			}
+
+			let glyph = glyphs[&id].as_ref().unwrap();
+			let desc =
+				PersistentDescriptorSet::start(shared.pipeline_text().clone(), 1)
+					.add_sampled_image(glyph.texture.image().clone(), shared.shaders().text_sampler().clone())
+					.unwrap()
+					.build()
+					.unwrap();
+			runs.push(GlyphRun {
+				id: id,
+				desc: Arc::new(stats::track(Resource::DescriptorSet, desc)),
+				instances: i..i + 1,
+			});
+		}
+
+		let (instances, instances_future) =
+			if placed.is_empty() {
+				// buffers can't be empty
+				(None, None)
+			} else {
+				let (instances, future) =
+					ImmutableBuffer::from_iter(
+						placed.into_iter().map(|(_, position)| position),
+						BufferUsage::vertex_buffer(),
+						self.queue.clone(),
+					)?;
+				(Some(instances), Some(future.then_signal_fence_and_flush().unwrap()))
+			};
+
+		Ok(TextLayout {
+			runs: runs,
+			instances: instances,
+			instances_future: Mutex::new(instances_future),
+			bounds: bounds,
+		})
 	}
 
 	pub(crate) fn from_file<P: AsRef<Path>>(queue: Arc<Queue>, path: P, scale: f32) -> Result<Arc<Self>, io::Error> {
@@ -75,69 +145,125 @@ impl Font {
 		let font = RtFont::from_bytes(bytes).unwrap();
 
 		Ok(Arc::new(Self {
+			id: NEXT_FONT_ID.fetch_add(1, Ordering::Relaxed),
 			queue: queue,
 			font: font,
 			glyphs: Mutex::default(),
-			futures: Mutex::default(),
+			uploads: Mutex::default(),
 			scale: scale
 		}))
 	}
 
+	/// Rasterizes the glyphs of `chars` that aren't loaded yet, and uploads them all with one command buffer.
 	fn load_chars(&self, chars: impl Iterator<Item = char>) -> Result<(), DeviceMemoryAllocError> {
 		let mut glyphs = self.glyphs.lock().unwrap();
-		let mut futures = self.futures.lock().unwrap();
+		let mut uploads = self.uploads.lock().unwrap();
+
+		let ctx = DeviceCtx::find(self.queue.device());
+		let before = uploads.len();
+		uploads.retain(|upload| !upload.is_done());
+		if let Some(ctx) = &ctx {
+			ctx.resource_stats().remove_live(Resource::GlyphFuture, before - uploads.len());
+		}
 
+		let mut pixels = vec![];
+		let mut copies = vec![];
 		for ch in chars {
 			let id = self.font.glyph(ch).id();
-
-			if !glyphs.contains_key(&id) {
-				let glyph = self.font.glyph(id).scaled(Scale::uniform(self.scale)).positioned(Point { x: 0.0, y: 0.0 });
-
-				if let Some(bb) = glyph.pixel_bounding_box() {
-					let bblen = bb.width() as usize * bb.height() as usize;
-					let mut pixels = Vec::with_capacity(bblen);
-					unsafe { pixels.set_len(bblen); }
-
-					glyph.draw(|x, y, v| {
-						pixels[y as usize * bb.width() as usize + x as usize] = (255.0 * v) as u8;
-					});
-
-					let (position, pos_future) =
-						ImmutableBuffer::from_data([bb.min.x, bb.min.y], BufferUsage::uniform_buffer(), self.queue.clone())?;
-
-					let (image, image_future) =
-						ImmutableImage
-							::from_iter(
-								pixels.into_iter(),
-								Dimensions::Dim2d { width: bb.width() as u32, height: bb.height() as u32 },
-								Format::R8Unorm,
-								self.queue.clone(),
-							)
-							.map_err(|err| match err {
-								ImageCreationError::AllocError(err) => err,
-								_ => unreachable!(),
-							})?;
-
-					glyphs.insert(id, Some(Glyph { texture: ImmutableTexture::from_image(image), offset: position }));
-					futures.insert(id, Arc::new(pos_future.join(image_future).then_signal_fence_and_flush().unwrap()));
-				} else {
-					glyphs.insert(id, None);
-				}
+			if glyphs.contains_key(&id) {
+				continue;
 			}
+
+			let glyph = self.font.glyph(id).scaled(Scale::uniform(self.scale)).positioned(Point { x: 0.0, y: 0.0 });
+			let bb =
+				match glyph.pixel_bounding_box() {
+					Some(bb) => bb,
+					None => {
+						glyphs.insert(id, None);
+						continue;
+					},
+				};
+
+			// copies have to start at multiples of 4 bytes
+			pixels.resize((pixels.len() + 3) / 4 * 4, 0);
+			let start = pixels.len();
+			pixels.resize(start + bb.width() as usize * bb.height() as usize, 0);
+			glyph.draw(|x, y, v| pixels[start + y as usize * bb.width() as usize + x as usize] = (255.0 * v) as u8);
+
+			let (image, init) =
+				ImmutableImage::uninitialized(
+					self.queue.device().clone(),
+					Dimensions::Dim2d { width: bb.width() as u32, height: bb.height() as u32 },
+					Format::R8Unorm,
+					MipmapsCount::One,
+					ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() },
+					ImageLayout::ShaderReadOnlyOptimal,
+					Some(self.queue.family()),
+				)
+				.map_err(|err| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() })?;
+
+			let bounds = Rect::new([bb.min.x as f32, bb.min.y as f32], [bb.max.x as f32, bb.max.y as f32]);
+			glyphs.insert(id, Some(Glyph { texture: ImmutableTexture::from_image(image), bounds: bounds }));
+			copies.push((id, start..pixels.len(), init));
+		}
+
+		if copies.is_empty() {
+			return Ok(());
+		}
+
+		let device = self.queue.device();
+		let pixels =
+			CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(), pixels.into_iter())?;
+		let mut commands = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), self.queue.family())?;
+		let mut ids = vec![];
+		for (id, range, init) in copies {
+			let source = pixels.clone().into_buffer_slice().slice(range).unwrap();
+			commands = commands.copy_buffer_to_image(source, init).unwrap();
+			ids.push(id);
+		}
+		let commands =
+			commands.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+
+		let future = commands.execute(self.queue.clone()).unwrap().then_signal_fence_and_flush().unwrap();
+		uploads.push(Arc::new(GlyphUpload { glyphs: ids, future: future }));
+		if let Some(ctx) = &ctx {
+			ctx.resource_stats().add_live(Resource::GlyphFuture, 1);
 		}
 
 		Ok(())
 	}
 }
 
+impl Drop for Font {
+	fn drop(&mut self) {
+		if let Some(device) = DeviceCtx::find(self.queue.device()) {
+			device.resource_stats().remove_live(Resource::GlyphFuture, self.uploads.lock().unwrap().len());
+			device.text_layouts().remove_font(self.id);
+		}
+	}
+}
+
 pub struct TextSprite {
-	static_descs: HashMap<GlyphId, Arc<DescriptorSet + Send + Sync + 'static>>,
-	positions: Vec<(
-		GlyphId,
-		Arc<ImmutableBuffer<[f32; 2]>>,
-		Option<FenceSignalFuture<CommandBufferExecFuture<NowFuture, AutoCommandBuffer>>>
-	)>,
-	futures: HashMap<GlyphId, Arc<FenceSignalFuture<GlyphFuture>>>,
+	layout: Arc<TextLayout>,
+	/// Where the text's origin is, in target pixels.
+	position: [f32; 2],
+	/// Uploads of this text's glyphs that hadn't finished when it was created. Glyphs still uploading aren't drawn.
+	uploads: Vec<Arc<GlyphUpload>>,
+}
+impl TextSprite {
+	/// Area covered by the glyphs' pixels, in target pixels. Spaces at either end don't count, and text with no visible
+	/// glyphs has empty bounds.
+	pub fn bounds(&self) -> Rect {
+		let Rect { min, max } = self.layout.bounds;
+		let [x, y] = self.position;
+		Rect::new([min[0] + x, min[1] + y], [max[0] + x, max[1] + y])
+	}
+
+	/// Whether `point`, in target pixels, is within the text's bounds, so gaps between letters still count as hits.
+	pub fn hit_test(&self, point: [f32; 2]) -> bool {
+		self.bounds().contains(point)
+	}
 }
 impl Drawable2D for TextSprite {
 	fn make_commands(
@@ -147,7 +273,12 @@ impl Drawable2D for TextSprite {
 		queue_family: QueueFamily,
 		dimensions: [f32; 2],
 	) -> Result<AutoCommandBuffer, OomError> {
-		let mut cmds = AutoCommandBufferBuilder::secondary_graphics_one_time_submit(shared.shaders().device().clone(), queue_family, shared.subpass().clone())?;
+		let mut cmds =
+			AutoCommandBufferBuilder::secondary_graphics_simultaneous_use(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?;
 
 		let state =
 			DynamicState {
@@ -156,40 +287,24 @@ impl Drawable2D for TextSprite {
 				scissors: None,
 			};
 
-		for (id, pos, future) in &mut self.positions {
-			if let Some(inner) = future.take() {
-				match inner.wait(Some(Default::default())) {
-					Ok(()) => (),
-					Err(FlushError::Timeout) => { *future = Some(inner); },
-					Err(err) => panic!(err),
-				}
-			}
+		self.uploads.retain(|upload| !upload.is_done());
 
-			if let Some(future) = self.futures.get(&id).map(|f| f.clone()) {
-				match future.wait(Some(Default::default())) {
-					Ok(()) => { self.futures.remove(&id); },
-					Err(FlushError::Timeout) => { continue; },
-					Err(err) => panic!(err),
+		let instances = self.layout.instances.as_ref().filter(|_| self.layout.is_uploaded());
+		if let Some(instances) = instances {
+			for run in &self.layout.runs {
+				if self.uploads.iter().any(|upload| upload.glyphs.contains(&run.id)) {
+					continue;
 				}
-			}
 
-			if let Some(static_desc) = self.static_descs.get(id) {
+				let vertices: Arc<BufferAccess + Send + Sync> = shared.shaders().vertices().clone();
+				let instances = instances.clone().into_buffer_slice().slice(run.instances.clone()).unwrap();
 				cmds = cmds
 					.draw(
 						shared.pipeline_text().clone(),
 						&state,
-						vec![shared.shaders().vertices().clone()],
-						(
-							target_desc.clone(),
-							shared.sprite_desc_pool().lock().unwrap()
-								.next()
-								.add_buffer(pos.clone())
-								.unwrap()
-								.build()
-								.unwrap(),
-							static_desc.clone(),
-						),
-						()
+						vec![vertices, Arc::new(instances)],
+						(target_desc.clone(), run.desc.clone()),
+						text_vs::ty::Text { origin: self.position }
 					)
 					.unwrap();
 			}
@@ -197,15 +312,216 @@ impl Drawable2D for TextSprite {
 
 		Ok(cmds.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
 	}
+
+	/// Static once the text and all its glyphs are uploaded, since glyphs still uploading are left out of the commands.
+	fn is_static(&self) -> bool {
+		self.layout.is_uploaded() && self.uploads.is_empty()
+	}
+
+	/// Glyph textures belong to the font, so only the layout's instances and descriptor sets are counted.
+	fn resources(&self, tally: &mut ResourceTally) {
+		if let Some(instances) = &self.layout.instances {
+			tally.add_buffer(&**instances);
+		}
+		for _ in &self.layout.runs {
+			tally.add_descriptor_set();
+		}
+	}
 }
 
-type GlyphFuture =
-	JoinFuture<
-		CommandBufferExecFuture<NowFuture, AutoCommandBuffer>,
-		CommandBufferExecFuture<NowFuture, AutoCommandBuffer>
-	>;
+/// Text laid out from the origin, shared by every `TextSprite` of the same font and string.
+struct TextLayout {
+	/// Each distinct glyph in the text, with its range of `instances`.
+	runs: Vec<GlyphRun>,
+	/// Where each glyph's bitmap starts, relative to the origin, grouped by glyph. `None` without visible glyphs.
+	instances: Option<Arc<ImmutableBuffer<[[f32; 2]]>>>,
+	instances_future: Mutex<Option<FenceSignalFuture<CommandBufferExecFuture<NowFuture, AutoCommandBuffer>>>>,
+	/// Relative to the origin.
+	bounds: Rect,
+}
+impl TextLayout {
+	fn is_uploaded(&self) -> bool {
+		let mut future = self.instances_future.lock().unwrap();
+		if future.as_ref().map_or(false, is_signaled) {
+			*future = None;
+		}
+		future.is_none()
+	}
+}
+
+/// Text layouts `Font::make_sprite` has made on a device, keyed by font, size and string.
+pub(crate) struct TextLayoutCache {
+	layouts: Mutex<Lru<(usize, R32, String), Arc<TextLayout>>>,
+}
+impl TextLayoutCache {
+	pub(crate) fn len(&self) -> usize {
+		self.layouts.lock().unwrap().len()
+	}
+
+	fn get_or_insert(
+		&self,
+		font: &Font,
+		text: &str,
+		make: impl FnOnce() -> Result<TextLayout, DeviceMemoryAllocError>,
+	) -> Result<Arc<TextLayout>, DeviceMemoryAllocError> {
+		let key = (font.id, R32::from(font.scale), text.to_owned());
+		if let Some(layout) = self.layouts.lock().unwrap().get(&key) {
+			return Ok(layout);
+		}
+
+		// not locked while laying out, since that uploads
+		let layout = Arc::new(make()?);
+		self.layouts.lock().unwrap().insert(key, layout.clone());
+		Ok(layout)
+	}
+
+	fn remove_font(&self, font_id: usize) {
+		self.layouts.lock().unwrap().retain(|&(id, _, _)| id != font_id);
+	}
+}
+impl Default for TextLayoutCache {
+	fn default() -> Self {
+		Self { layouts: Mutex::new(Lru::new(TEXT_LAYOUT_CACHE_CAPACITY)) }
+	}
+}
+
+/// A map that drops its least recently used entry to stay within its capacity.
+struct Lru<K, V> {
+	entries: HashMap<K, (V, u64)>,
+	capacity: usize,
+	/// Counts lookups and inserts, to find the least recently used entry.
+	clock: u64,
+}
+impl<K: Clone + Eq + Hash, V: Clone> Lru<K, V> {
+	fn new(capacity: usize) -> Self {
+		assert!(capacity > 0, "LRU capacity must be at least one");
+		Self { entries: HashMap::new(), capacity: capacity, clock: 0 }
+	}
+
+	fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Marks the entry as used.
+	fn get(&mut self, key: &K) -> Option<V> {
+		self.clock += 1;
+		let clock = self.clock;
+		self.entries.get_mut(key).map(|(value, last_used)| {
+			*last_used = clock;
+			value.clone()
+		})
+	}
+
+	/// Drops the least recently used entry first if there's no room, unless `key` is already there to be replaced.
+	fn insert(&mut self, key: K, value: V) {
+		self.clock += 1;
+		if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+			let oldest = self.entries.iter().min_by_key(|(_, &(_, last_used))| last_used).map(|(key, _)| key.clone());
+			if let Some(oldest) = oldest {
+				self.entries.remove(&oldest);
+			}
+		}
+		self.entries.insert(key, (value, self.clock));
+	}
+
+	fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+		self.entries.retain(|key, _| keep(key));
+	}
+}
+
+/// Glyph bitmaps uploaded together by one `Font::load_chars`.
+struct GlyphUpload {
+	glyphs: Vec<GlyphId>,
+	future: FenceSignalFuture<CommandBufferExecFuture<NowFuture, AutoCommandBuffer>>,
+}
+impl GlyphUpload {
+	fn is_done(&self) -> bool {
+		is_signaled(&self.future)
+	}
+}
+
+struct GlyphRun {
+	id: GlyphId,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	instances: Range<usize>,
+}
 
 struct Glyph {
 	texture: ImmutableTexture,
-	offset: Arc<ImmutableBuffer<[i32; 2]>>,
+	/// Pixel bounding box relative to the glyph's origin on the baseline.
+	bounds: Rect,
+}
+
+fn is_signaled<F: GpuFuture>(future: &FenceSignalFuture<F>) -> bool {
+	match future.wait(Some(Default::default())) {
+		Ok(()) => true,
+		Err(FlushError::Timeout) => false,
+		Err(err) => panic!(err),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Lru;
+
+	#[test]
+	fn hits_and_misses() {
+		let mut lru = Lru::new(4);
+		assert_eq!(lru.get(&"a"), None);
+		lru.insert("a", 1);
+		lru.insert("b", 2);
+		assert_eq!(lru.get(&"a"), Some(1));
+		assert_eq!(lru.get(&"b"), Some(2));
+		assert_eq!(lru.get(&"c"), None);
+		assert_eq!(lru.len(), 2);
+
+		// replacing an entry doesn't take more room
+		lru.insert("a", 3);
+		assert_eq!(lru.get(&"a"), Some(3));
+		assert_eq!(lru.len(), 2);
+	}
+
+	#[test]
+	fn evicts_the_least_recently_used() {
+		let mut lru = Lru::new(3);
+		lru.insert("a", 1);
+		lru.insert("b", 2);
+		lru.insert("c", 3);
+		// "a" is used again, so "b" is now the oldest
+		lru.get(&"a");
+
+		lru.insert("d", 4);
+		assert_eq!(lru.len(), 3);
+		assert_eq!(lru.get(&"b"), None);
+		assert_eq!(lru.get(&"a"), Some(1));
+		assert_eq!(lru.get(&"c"), Some(3));
+		assert_eq!(lru.get(&"d"), Some(4));
+
+		// misses don't count as uses, and "a" was used before "c" and "d"
+		lru.get(&"x");
+		lru.insert("e", 5);
+		assert_eq!(lru.get(&"a"), None);
+		assert_eq!(lru.get(&"e"), Some(5));
+	}
+
+	#[test]
+	fn replacing_a_full_cache_entry_keeps_the_others() {
+		let mut lru = Lru::new(2);
+		lru.insert("a", 1);
+		lru.insert("b", 2);
+		lru.insert("a", 3);
+		assert_eq!(lru.get(&"a"), Some(3));
+		assert_eq!(lru.get(&"b"), Some(2));
+	}
+
+	#[test]
+	fn retain_drops_matching_entries() {
+		let mut lru = Lru::new(4);
+		for (i, key) in ["a1", "a2", "b1"].iter().enumerate() {
+			lru.insert(*key, i);
+		}
+		lru.retain(|key| !key.starts_with('a'));
+		assert_eq!(lru.len(), 1);
+		assert_eq!(lru.get(&"b1"), Some(2));
+	}
 }
@@ -0,0 +1,43 @@
+/// Axis-aligned rectangle in target pixels, with the origin in the top left corner like sprite positions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+	pub min: [f32; 2],
+	pub max: [f32; 2],
+}
+impl Rect {
+	pub fn new(min: [f32; 2], max: [f32; 2]) -> Self {
+		Self { min: min, max: max }
+	}
+
+	pub fn from_position_size(position: [f32; 2], size: [f32; 2]) -> Self {
+		Self { min: position, max: [position[0] + size[0], position[1] + size[1]] }
+	}
+
+	pub fn empty() -> Self {
+		Self { min: [std::f32::INFINITY; 2], max: [std::f32::NEG_INFINITY; 2] }
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.min[0] > self.max[0] || self.min[1] > self.max[1]
+	}
+
+	pub fn union(self, other: Rect) -> Self {
+		if other.is_empty() {
+			return self;
+		}
+
+		Self {
+			min: [self.min[0].min(other.min[0]), self.min[1].min(other.min[1])],
+			max: [self.max[0].max(other.max[0]), self.max[1].max(other.max[1])],
+		}
+	}
+
+	pub fn size(&self) -> [f32; 2] {
+		[self.max[0] - self.min[0], self.max[1] - self.min[1]]
+	}
+
+	/// The left and top edges are inside and the right and bottom ones aren't, so adjacent rects never share a point.
+	pub fn contains(&self, point: [f32; 2]) -> bool {
+		point[0] >= self.min[0] && point[1] >= self.min[1] && point[0] < self.max[0] && point[1] < self.max[1]
+	}
+}
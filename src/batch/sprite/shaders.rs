@@ -1,11 +1,16 @@
+use crate::device::{ DeviceCtx, ShaderModuleError };
 use crate::window::Window;
-use std::sync::Arc;
+use super::SpriteFilter;
+use std::{ mem::size_of, sync::Arc, vec::IntoIter as VecIntoIter };
 use vulkano::{
 	impl_vertex,
 	OomError,
-	buffer::{ BufferUsage, ImmutableBuffer },
+	buffer::{ BufferAccess, BufferUsage, ImmutableBuffer },
 	device::{ Device, Queue },
+	format::Format,
+	image::{ Dimensions, ImageCreationError, ImmutableImage },
 	memory::DeviceMemoryAllocError,
+	pipeline::vertex::{ AttributeInfo, IncompatibleVertexDefinitionError, InputRate, VertexDefinition, VertexSource },
 	sampler::{ BorderColor, Filter, MipmapMode, Sampler, SamplerAddressMode, SamplerCreationError },
 	sync::GpuFuture,
 };
@@ -17,12 +22,26 @@ pub struct SpriteBatchShaders {
 	sprite_vertex_shader: sprite_vs::Shader,
 	sprite_fragment_shader: sprite_fs::Shader,
 	sprite_sampler: Arc<Sampler>,
+	sprite_sampler_nearest: Arc<Sampler>,
 	text_vertex_shader: text_vs::Shader,
 	text_fragment_shader: text_fs::Shader,
 	text_sampler: Arc<Sampler>,
+	trail_vertex_shader: trail_vs::Shader,
+	trail_fragment_shader: trail_fs::Shader,
+	blur_vertex_shader: blur_vs::Shader,
+	blur_fragment_shader: blur_fs::Shader,
+	blur_sampler: Arc<Sampler>,
+	backdrop_vertex_shader: backdrop_vs::Shader,
+	backdrop_fragment_shader: backdrop_fs::Shader,
+	white_pixel: Arc<ImmutableImage<Format>>,
 }
 impl SpriteBatchShaders {
 	pub fn new(window: &mut Window) -> Result<(Arc<Self>, impl GpuFuture), SpriteBatchShadersError> {
+		Self::from_device(window.device())
+	}
+
+	/// Shaders on `device` without a window, e.g. a `Context::headless_device`.
+	pub fn from_device(device: &DeviceCtx) -> Result<(Arc<Self>, impl GpuFuture), SpriteBatchShadersError> {
 		let (vertices, future) =
 			ImmutableBuffer::from_data(
 				[
@@ -34,19 +53,28 @@ impl SpriteBatchShaders {
 					SpriteVertex { position: [1.0, 1.0] },
 				],
 				BufferUsage::vertex_buffer(),
-				window.device().queue().clone(),
+				device.queue().clone(),
+			)?;
+
+		// stands in for a texture on untextured trails
+		let (white_pixel, white_pixel_future) =
+			ImmutableImage::from_iter(
+				[255u8; 4].iter().cloned(),
+				Dimensions::Dim2d { width: 1, height: 1 },
+				Format::R8G8B8A8Unorm,
+				device.queue().clone(),
 			)?;
 
 		Ok((
 			Arc::new(Self {
-				device: window.device().device().clone(),
-				queue: window.device().queue().clone(),
+				device: device.device().clone(),
+				queue: device.queue().clone(),
 				vertices: vertices,
-				sprite_vertex_shader: sprite_vs::Shader::load(window.device().device().clone())?,
-				sprite_fragment_shader: sprite_fs::Shader::load(window.device().device().clone())?,
+				sprite_vertex_shader: sprite_vs::Shader::load(device.device().clone())?,
+				sprite_fragment_shader: sprite_fs::Shader::load(device.device().clone())?,
 				sprite_sampler:
 					Sampler::new(
-						window.device().device().clone(),
+						device.device().clone(),
 						Filter::Linear,
 						Filter::Linear, MipmapMode::Nearest,
 						SamplerAddressMode::Repeat,
@@ -54,11 +82,21 @@ impl SpriteBatchShaders {
 						SamplerAddressMode::Repeat,
 						0.0, 1.0, 0.0, 0.0
 					)?,
-				text_vertex_shader: text_vs::Shader::load(window.device().device().clone())?,
-				text_fragment_shader: text_fs::Shader::load(window.device().device().clone())?,
+				sprite_sampler_nearest:
+					Sampler::new(
+						device.device().clone(),
+						Filter::Nearest,
+						Filter::Nearest, MipmapMode::Nearest,
+						SamplerAddressMode::Repeat,
+						SamplerAddressMode::Repeat,
+						SamplerAddressMode::Repeat,
+						0.0, 1.0, 0.0, 0.0
+					)?,
+				text_vertex_shader: text_vs::Shader::load(device.device().clone())?,
+				text_fragment_shader: text_fs::Shader::load(device.device().clone())?,
 				text_sampler:
 					Sampler::new(
-						window.device().device().clone(),
+						device.device().clone(),
 						Filter::Linear,
 						Filter::Linear, MipmapMode::Nearest,
 						SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
@@ -66,8 +104,26 @@ impl SpriteBatchShaders {
 						SamplerAddressMode::ClampToBorder(BorderColor::FloatTransparentBlack),
 						0.0, 1.0, 0.0, 0.0
 					)?,
+				trail_vertex_shader: trail_vs::Shader::load(device.device().clone())?,
+				trail_fragment_shader: trail_fs::Shader::load(device.device().clone())?,
+				blur_vertex_shader: blur_vs::Shader::load(device.device().clone())?,
+				blur_fragment_shader: blur_fs::Shader::load(device.device().clone())?,
+				// clamped, so edges don't blur in the other side of the image
+				blur_sampler:
+					Sampler::new(
+						device.device().clone(),
+						Filter::Linear,
+						Filter::Linear, MipmapMode::Nearest,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						SamplerAddressMode::ClampToEdge,
+						0.0, 1.0, 0.0, 0.0
+					)?,
+				backdrop_vertex_shader: backdrop_vs::Shader::load(device.device().clone())?,
+				backdrop_fragment_shader: backdrop_fs::Shader::load(device.device().clone())?,
+				white_pixel: white_pixel,
 			}),
-			future
+			future.join(white_pixel_future)
 		))
 	}
 
@@ -99,10 +155,49 @@ impl SpriteBatchShaders {
 		&self.text_fragment_shader
 	}
 
+	pub(crate) fn trail_vertex_shader(&self) -> &trail_vs::Shader {
+		&self.trail_vertex_shader
+	}
+
+	pub(crate) fn trail_fragment_shader(&self) -> &trail_fs::Shader {
+		&self.trail_fragment_shader
+	}
+
+	pub(crate) fn blur_vertex_shader(&self) -> &blur_vs::Shader {
+		&self.blur_vertex_shader
+	}
+
+	pub(crate) fn blur_fragment_shader(&self) -> &blur_fs::Shader {
+		&self.blur_fragment_shader
+	}
+
+	pub(crate) fn blur_sampler(&self) -> &Arc<Sampler> {
+		&self.blur_sampler
+	}
+
+	pub(crate) fn backdrop_vertex_shader(&self) -> &backdrop_vs::Shader {
+		&self.backdrop_vertex_shader
+	}
+
+	pub(crate) fn backdrop_fragment_shader(&self) -> &backdrop_fs::Shader {
+		&self.backdrop_fragment_shader
+	}
+
+	pub(crate) fn white_pixel(&self) -> &Arc<ImmutableImage<Format>> {
+		&self.white_pixel
+	}
+
 	pub(crate) fn sprite_sampler(&self) -> &Arc<Sampler> {
 		&self.sprite_sampler
 	}
 
+	pub(crate) fn sprite_sampler_with_filter(&self, filter: SpriteFilter) -> &Arc<Sampler> {
+		match filter {
+			SpriteFilter::Linear => &self.sprite_sampler,
+			SpriteFilter::Nearest => &self.sprite_sampler_nearest,
+		}
+	}
+
 	pub(crate) fn text_sampler(&self) -> &Arc<Sampler> {
 		&self.text_sampler
 	}
@@ -112,6 +207,8 @@ impl SpriteBatchShaders {
 pub enum SpriteBatchShadersError {
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
 	OomError(OomError),
+	/// A shader couldn't be loaded, without the `builtin-shaders` feature.
+	ShaderModuleError(ShaderModuleError),
 	TooManyObjects,
 }
 impl From<DeviceMemoryAllocError> for SpriteBatchShadersError {
@@ -124,6 +221,19 @@ impl From<OomError> for SpriteBatchShadersError {
 		SpriteBatchShadersError::OomError(val)
 	}
 }
+impl From<ShaderModuleError> for SpriteBatchShadersError {
+	fn from(val: ShaderModuleError) -> Self {
+		SpriteBatchShadersError::ShaderModuleError(val)
+	}
+}
+impl From<ImageCreationError> for SpriteBatchShadersError {
+	fn from(val: ImageCreationError) -> Self {
+		match val {
+			ImageCreationError::AllocError(err) => SpriteBatchShadersError::DeviceMemoryAllocError(err),
+			_ => unreachable!(),
+		}
+	}
+}
 impl From<SamplerCreationError> for SpriteBatchShadersError {
 	fn from(val: SamplerCreationError) -> Self {
 		match val {
@@ -138,79 +248,222 @@ impl From<SamplerCreationError> for SpriteBatchShadersError {
 pub(crate) struct SpriteVertex { position: [f32; 2] }
 impl_vertex!(SpriteVertex, position);
 
-mod sprite_vs {
-	::vulkano_shaders::shader!{
-		ty: "vertex",
-		src: "#version 450
-layout(location = 0) in vec2 position;
-layout(location = 0) out vec2 tex_coords;
-
-layout(set = 0, binding = 0) uniform Target {
-	uvec2 size;
-} target;
+/// The sprite quad, instanced once per glyph from a second buffer of where each glyph's bitmap starts in target pixels.
+pub(crate) struct GlyphVertexDefinition;
+unsafe impl<I> VertexDefinition<I> for GlyphVertexDefinition {
+	type BuffersIter = VecIntoIter<(u32, usize, InputRate)>;
+	type AttribsIter = VecIntoIter<(u32, u32, AttributeInfo)>;
 
-layout(set = 1, binding = 0) uniform SpriteDynamic {
-	vec2 pos;
-} sprite_dynamic;
+	fn definition(
+		&self,
+		_interface: &I
+	) -> Result<(Self::BuffersIter, Self::AttribsIter), IncompatibleVertexDefinitionError> {
+		Ok((
+			vec![
+				(0, size_of::<SpriteVertex>(), InputRate::Vertex),
+				(1, size_of::<[f32; 2]>(), InputRate::Instance),
+			].into_iter(),
+			vec![
+				(0, 0, AttributeInfo { offset: 0, format: Format::R32G32Sfloat }),
+				(1, 1, AttributeInfo { offset: 0, format: Format::R32G32Sfloat }),
+			].into_iter()
+		))
+	}
+}
+unsafe impl VertexSource<Vec<Arc<BufferAccess + Send + Sync>>> for GlyphVertexDefinition {
+	#[inline]
+	fn decode(
+		&self,
+		source: Vec<Arc<BufferAccess + Send + Sync>>
+	) -> (Vec<Box<BufferAccess + Send + Sync>>, usize, usize) {
+		assert_eq!(source.len(), 2);
+		let len = source[0].size() / size_of::<SpriteVertex>();
+		let instances = source[1].size() / size_of::<[f32; 2]>();
+		(source.into_iter().map(|x| Box::new(x) as _).collect(), len, instances)
+	}
+}
 
-layout(set = 2, binding = 0) uniform sampler2D tex;
+/// `position` is in pixels, `color` is multiplied with the texture.
+#[derive(Debug, Clone)]
+pub(crate) struct TrailVertex { pub position: [f32; 2], pub tex_coords: [f32; 2], pub color: [f32; 4] }
+impl_vertex!(TrailVertex, position, tex_coords, color);
 
-void main() {
-	tex_coords = position;
-	gl_Position = vec4(2 * (sprite_dynamic.pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
-}
-"
+mod sprite_vs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/sprite_vs.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "sprite_vs",
+		path: "shaders/sprite_vs.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [0 => R32G32Sfloat tex_coords],
+		descriptors: [(0, 0) => Uniform, (1, 0) => Uniform, (2, 0) => Sampler],
+		push_constants: [],
 	}
 }
 
 mod sprite_fs {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "fragment",
-		src: "#version 450
-layout(location = 0) in vec2 tex_coords;
-layout(location = 0) out vec4 f_color;
-
-layout(set = 2, binding = 0) uniform sampler2D tex;
+		path: "shaders/sprite_fs.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "sprite_fs",
+		path: "shaders/sprite_fs.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords],
+		outputs: [0 => R32G32B32A32Sfloat f_color],
+		descriptors: [(2, 0) => Sampler],
+		push_constants: [],
+	}
+}
 
-void main() {
-	f_color = texture(tex, tex_coords);
+pub(super) mod text_vs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/text_vs.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "text_vs",
+		path: "shaders/text_vs.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position, 1 => R32G32Sfloat glyph_pos],
+		outputs: [0 => R32G32Sfloat tex_coords],
+		descriptors: [(0, 0) => Uniform, (1, 0) => Sampler],
+		push_constants: [Text { origin: [f32; 2] }],
+	}
 }
-"
+
+mod text_fs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/text_fs.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "text_fs",
+		path: "shaders/text_fs.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords],
+		outputs: [0 => R32G32B32A32Sfloat f_color],
+		descriptors: [(1, 0) => Sampler],
+		push_constants: [],
 	}
 }
 
-mod text_vs {
+mod trail_vs {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "vertex",
-		src: "#version 450
-layout(location = 0) in vec2 position;
-layout(location = 0) out vec2 tex_coords;
-
-layout(set = 0, binding = 0) uniform Target { uvec2 size; } target;
-layout(set = 1, binding = 0) uniform SpriteDynamic { vec2 pos; } sprite_dynamic;
-layout(set = 2, binding = 0) uniform GlyphStatic { ivec2 pos; } glyph_static;
-layout(set = 2, binding = 1) uniform sampler2D tex;
+		path: "shaders/trail_vs.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "trail_vs",
+		path: "shaders/trail_vs.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position, 1 => R32G32Sfloat tex_coords, 2 => R32G32B32A32Sfloat color],
+		outputs: [0 => R32G32Sfloat out_tex_coords, 1 => R32G32B32A32Sfloat out_color],
+		descriptors: [(0, 0) => Uniform],
+		push_constants: [],
+	}
+}
 
-void main() {
-	tex_coords = position;
-	gl_Position = vec4(2 * (sprite_dynamic.pos + glyph_static.pos + textureSize(tex, 0) * position) / target.size - 1, 0.0, 1.0);
+mod trail_fs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/trail_fs.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "trail_fs",
+		path: "shaders/trail_fs.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords, 1 => R32G32B32A32Sfloat color],
+		outputs: [0 => R32G32B32A32Sfloat f_color],
+		descriptors: [(1, 0) => Sampler],
+		push_constants: [],
+	}
 }
-"
+
+mod blur_vs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/blur_vs.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "blur_vs",
+		path: "shaders/blur_vs.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [0 => R32G32Sfloat tex_coords],
+		descriptors: [],
+		push_constants: [],
 	}
 }
 
-mod text_fs {
+pub(super) mod blur_fs {
+	#[cfg(feature = "builtin-shaders")]
 	::vulkano_shaders::shader!{
 		ty: "fragment",
-		src: "#version 450
-layout(location = 0) in vec2 tex_coords;
-layout(location = 0) out vec4 f_color;
-
-layout(set = 2, binding = 1) uniform sampler2D tex;
+		path: "shaders/blur_fs.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "blur_fs",
+		path: "shaders/blur_fs.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat tex_coords],
+		outputs: [0 => R32G32B32A32Sfloat f_color],
+		descriptors: [(0, 0) => Sampler],
+		push_constants: [Blur { step: [f32; 2], sigma: f32 }],
+	}
+}
 
-void main() {
-	f_color = vec4(1, 1, 1, texture(tex, tex_coords).r);
+pub(super) mod backdrop_vs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "vertex",
+		path: "shaders/backdrop_vs.vert"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "backdrop_vs",
+		path: "shaders/backdrop_vs.vert",
+		ty: vertex,
+		inputs: [0 => R32G32Sfloat position],
+		outputs: [0 => R32G32Sfloat backdrop_coords, 1 => R32G32B32A32Sfloat out_tint],
+		descriptors: [(0, 0) => Uniform],
+		push_constants: [Backdrop { min: [f32; 2], size: [f32; 2], tint: [f32; 4] }],
+	}
 }
-"
+
+mod backdrop_fs {
+	#[cfg(feature = "builtin-shaders")]
+	::vulkano_shaders::shader!{
+		ty: "fragment",
+		path: "shaders/backdrop_fs.frag"
+	}
+	#[cfg(not(feature = "builtin-shaders"))]
+	spirv_shader!{
+		name: "backdrop_fs",
+		path: "shaders/backdrop_fs.frag",
+		ty: fragment,
+		inputs: [0 => R32G32Sfloat backdrop_coords, 1 => R32G32B32A32Sfloat tint],
+		outputs: [0 => R32G32B32A32Sfloat f_color],
+		descriptors: [(1, 0) => Sampler],
+		push_constants: [],
 	}
 }
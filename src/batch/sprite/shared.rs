@@ -1,26 +1,161 @@
-use crate::texture::Texture;
-use super::shaders::{ SpriteBatchShaders, SpriteVertex };
-use super::sprite::Sprite;
-use std::sync::{ Arc, Mutex };
+use crate::batch::sprite::LayerBlend;
+use crate::device::DeviceCtx;
+use crate::texture::{ Texture, TextureDeviceError };
+use crate::window::Window;
+use super::cursor::SoftwareCursor;
+use super::shaders::{ GlyphVertexDefinition, SpriteBatchShaders, SpriteVertex, TrailVertex };
+use super::sprite::{ Sprite, SpriteFilter };
+use super::trail::Trail2D;
+use std::sync::{ Arc, Mutex, Weak };
 use vulkano::{
 	single_pass_renderpass,
 	descriptor::descriptor_set::FixedSizeDescriptorSetsPool,
 	format::Format,
 	framebuffer::{ RenderPassAbstract, Subpass },
-	memory::DeviceMemoryAllocError,
-	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract },
+	pipeline::{ GraphicsPipeline, GraphicsPipelineAbstract, blend::{ AttachmentBlend, BlendFactor } },
 	sync::GpuFuture,
 };
 
 pub struct SpriteBatchShared {
 	shaders: Arc<SpriteBatchShaders>,
-	subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
-	pipeline_sprite: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
-	pipeline_text: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	pipelines: Arc<SpritePipelines>,
 	sprite_desc_pool: Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>>,
 }
 impl SpriteBatchShared {
+	/// Render passes and pipelines are shared with other `SpriteBatchShared`s for the same shaders and format, so
+	/// creating one per window or render target only builds them the first time.
 	pub fn new(shaders: Arc<SpriteBatchShaders>, format: Format) -> Arc<Self> {
+		let pipelines =
+			match DeviceCtx::find(shaders.device()) {
+				Some(ctx) => ctx.sprite_pipelines().get(&shaders, format),
+				None => Arc::new(SpritePipelines::new(shaders.clone(), format)),
+			};
+
+		Arc::new(Self {
+			sprite_desc_pool: Mutex::new(FixedSizeDescriptorSetsPool::new(pipelines.sprite.clone(), 1)),
+			shaders: shaders,
+			pipelines: pipelines,
+		})
+	}
+
+	pub fn create_sprite(
+		&self,
+		texture: &Texture,
+		position: [f32; 2],
+	) -> Result<(Sprite, impl GpuFuture), TextureDeviceError> {
+		self.create_sprite_with_filter(texture, position, SpriteFilter::Linear)
+	}
+
+	/// Like `create_sprite`, sampling the texture with `filter`, e.g. `SpriteFilter::Nearest` for pixel art. Sprites
+	/// of the same texture may use different filters.
+	pub fn create_sprite_with_filter(
+		&self,
+		texture: &Texture,
+		position: [f32; 2],
+		filter: SpriteFilter,
+	) -> Result<(Sprite, impl GpuFuture), TextureDeviceError> {
+		Sprite::new(
+			self.shaders.queue().clone(),
+			self.pipelines.sprite.clone(),
+			self.shaders.sprite_sampler_with_filter(filter).clone(),
+			texture,
+			position,
+		)
+	}
+
+	/// Creates the drawable that shows `window`'s cursor image, see `Window::set_cursor_image`.
+	pub fn create_software_cursor(&self, window: &Window) -> SoftwareCursor {
+		SoftwareCursor::new(self, window)
+	}
+
+	/// Creates a trail of up to `capacity` points. Without a texture, it's drawn in its vertex colors.
+	pub fn create_trail(&self, texture: Option<&Texture>, capacity: usize) -> Result<Trail2D, TextureDeviceError> {
+		Trail2D::new(self, texture, capacity)
+	}
+
+	pub(crate) fn shaders(&self) -> &Arc<SpriteBatchShaders> {
+		&self.shaders
+	}
+
+	/// The subpass sprites and `SpriteBatch` hooks draw in. Build custom pipelines for it to use them in a hook, with
+	/// either `LayerBlend`.
+	pub fn subpass(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
+		&self.pipelines.subpass
+	}
+
+	pub(crate) fn render_pass(&self, blend: LayerBlend) -> &Arc<RenderPassAbstract + Send + Sync> {
+		match blend {
+			LayerBlend::Replace => self.pipelines.subpass.render_pass(),
+			LayerBlend::Over => &self.pipelines.load_render_pass,
+		}
+	}
+
+	pub(crate) fn pipeline_sprite(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.sprite
+	}
+
+	pub(crate) fn pipeline_sprite_premultiplied(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.sprite_premultiplied
+	}
+
+	pub(crate) fn pipeline_text(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.text
+	}
+
+	pub(crate) fn pipeline_trail(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.trail
+	}
+
+	pub(crate) fn pipeline_backdrop(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
+		&self.pipelines.backdrop
+	}
+
+	pub(crate) fn sprite_desc_pool(
+		&self
+	) -> &Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>> {
+		&self.sprite_desc_pool
+	}
+}
+
+/// `SpritePipelines` of a device, one per shaders and target format, kept while any `SpriteBatchShared` uses them.
+#[derive(Default)]
+pub(crate) struct SpritePipelineCache {
+	pipelines: Mutex<Vec<Weak<SpritePipelines>>>,
+}
+impl SpritePipelineCache {
+	fn get(&self, shaders: &Arc<SpriteBatchShaders>, format: Format) -> Arc<SpritePipelines> {
+		let mut cached = self.pipelines.lock().unwrap();
+		let existing =
+			cached.iter()
+				.filter_map(|pipelines| pipelines.upgrade())
+				.find(|pipelines| pipelines.format == format && Arc::ptr_eq(&pipelines.shaders, shaders));
+		if let Some(pipelines) = existing {
+			return pipelines;
+		}
+		cached.retain(|pipelines| pipelines.upgrade().is_some());
+
+		let pipelines = Arc::new(SpritePipelines::new(shaders.clone(), format));
+		cached.push(Arc::downgrade(&pipelines));
+		pipelines
+	}
+}
+
+/// The render passes for one target format and every pipeline built for them. Vulkano only lets pipelines be drawn in
+/// the render pass they were built with, so the two are cached together.
+struct SpritePipelines {
+	/// Keeps the shaders alive, so they can be compared by address.
+	shaders: Arc<SpriteBatchShaders>,
+	format: Format,
+	subpass: Subpass<Arc<RenderPassAbstract + Send + Sync>>,
+	load_render_pass: Arc<RenderPassAbstract + Send + Sync>,
+	sprite: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	sprite_premultiplied: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	text: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	trail: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	backdrop: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+}
+impl SpritePipelines {
+	fn new(shaders: Arc<SpriteBatchShaders>, format: Format) -> Self {
 		let subpass =
 			Subpass::from(
 				Arc::new(
@@ -33,6 +168,17 @@ impl SpriteBatchShared {
 				0
 			).expect("failed to create subpass");
 
+		// only differs in load op, so it's compatible with the pipelines built for `subpass`
+		let load_render_pass: Arc<RenderPassAbstract + Send + Sync> =
+			Arc::new(
+				single_pass_renderpass!(
+					shaders.device().clone(),
+					attachments: { color: { load: Load, store: Store, format: format, samples: 1, } },
+					pass: { color: [color], depth_stencil: {} }
+				).expect("failed to create render pass")
+			);
+
+		// these keep coverage in the alpha channel, so drawing into transparent targets gives premultiplied alpha
 		let pipeline_sprite = Arc::new(
 			GraphicsPipeline::start()
 				.vertex_input_single_buffer::<SpriteVertex>()
@@ -41,65 +187,78 @@ impl SpriteBatchShared {
 				.viewports_dynamic_scissors_irrelevant(1)
 				.fragment_shader(shaders.sprite_fragment_shader().main_entry_point(), ())
 				.render_pass(subpass.clone())
+				.blend_collective(AttachmentBlend { alpha_source: BlendFactor::One, ..AttachmentBlend::alpha_blending() })
 				.build(shaders.device().clone())
 				.expect("failed to create pipeline")
 		);
 
-		let pipeline_text = Arc::new(
+		// for textures whose color is already multiplied by alpha, like the contents of a `UiCache`
+		let pipeline_sprite_premultiplied = Arc::new(
 			GraphicsPipeline::start()
 				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.sprite_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_dynamic_scissors_irrelevant(1)
+				.fragment_shader(shaders.sprite_fragment_shader().main_entry_point(), ())
+				.render_pass(subpass.clone())
+				.blend_collective(AttachmentBlend {
+					color_source: BlendFactor::One,
+					alpha_source: BlendFactor::One,
+					..AttachmentBlend::alpha_blending()
+				})
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
+
+		let pipeline_text = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input(GlyphVertexDefinition)
 				.vertex_shader(shaders.text_vertex_shader().main_entry_point(), ())
 				.triangle_list()
 				.viewports_dynamic_scissors_irrelevant(1)
 				.fragment_shader(shaders.text_fragment_shader().main_entry_point(), ())
 				.render_pass(subpass.clone())
-				.blend_alpha_blending()
+				.blend_collective(AttachmentBlend { alpha_source: BlendFactor::One, ..AttachmentBlend::alpha_blending() })
 				.build(shaders.device().clone())
 				.expect("failed to create pipeline")
 		);
 
-		Arc::new(Self {
-			shaders: shaders,
-			subpass: subpass,
-			pipeline_sprite: pipeline_sprite.clone(),
-			pipeline_text: pipeline_text,
-			sprite_desc_pool: Mutex::new(FixedSizeDescriptorSetsPool::new(pipeline_sprite, 1)),
-		})
-	}
-
-	pub fn create_sprite(
-		&self,
-		texture: &Texture,
-		position: [f32; 2],
-	) -> Result<(Sprite, impl GpuFuture), DeviceMemoryAllocError> {
-		Sprite::new(
-			self.shaders.queue().clone(),
-			self.pipeline_sprite.clone(),
-			self.shaders.sprite_sampler().clone(),
-			texture,
-			position,
-		)
-	}
-
-	pub(crate) fn shaders(&self) -> &Arc<SpriteBatchShaders> {
-		&self.shaders
-	}
-
-	pub(crate) fn subpass(&self) -> &Subpass<Arc<RenderPassAbstract + Send + Sync>> {
-		&self.subpass
-	}
-
-	pub(crate) fn pipeline_sprite(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
-		&self.pipeline_sprite
-	}
+		let pipeline_trail = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<TrailVertex>()
+				.vertex_shader(shaders.trail_vertex_shader().main_entry_point(), ())
+				.triangle_strip()
+				.viewports_dynamic_scissors_irrelevant(1)
+				.fragment_shader(shaders.trail_fragment_shader().main_entry_point(), ())
+				.render_pass(subpass.clone())
+				.blend_collective(AttachmentBlend { alpha_source: BlendFactor::One, ..AttachmentBlend::alpha_blending() })
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
 
-	pub(crate) fn pipeline_text(&self) -> &Arc<GraphicsPipelineAbstract + Send + Sync + 'static> {
-		&self.pipeline_text
-	}
+		// opaque, the panel replaces what's beneath it with the blurred copy
+		let pipeline_backdrop = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input_single_buffer::<SpriteVertex>()
+				.vertex_shader(shaders.backdrop_vertex_shader().main_entry_point(), ())
+				.triangle_list()
+				.viewports_dynamic_scissors_irrelevant(1)
+				.fragment_shader(shaders.backdrop_fragment_shader().main_entry_point(), ())
+				.render_pass(subpass.clone())
+				.build(shaders.device().clone())
+				.expect("failed to create pipeline")
+		);
 
-	pub(crate) fn sprite_desc_pool(
-		&self
-	) -> &Mutex<FixedSizeDescriptorSetsPool<Arc<GraphicsPipelineAbstract + Send + Sync + 'static>>> {
-		&self.sprite_desc_pool
+		Self {
+			shaders: shaders,
+			format: format,
+			subpass: subpass,
+			load_render_pass: load_render_pass,
+			sprite: pipeline_sprite,
+			sprite_premultiplied: pipeline_sprite_premultiplied,
+			text: pipeline_text,
+			trail: pipeline_trail,
+			backdrop: pipeline_backdrop,
+		}
 	}
 }
@@ -1,6 +1,7 @@
-use super::Drawable2D;
+use super::{ Drawable2D, Rect };
 use super::shared::SpriteBatchShared;
-use crate::texture::Texture;
+use crate::stats::{ self, Resource, ResourceTally };
+use crate::texture::{ self, Texture, TextureDeviceError };
 use std::sync::Arc;
 use vulkano::{
 	OomError,
@@ -8,38 +9,74 @@ use vulkano::{
 	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
 	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
 	device::Queue,
+	image::ImageViewAccess,
 	instance::QueueFamily,
-	memory::DeviceMemoryAllocError,
 	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
 	sampler::Sampler,
 	sync::GpuFuture,
 };
 
+/// How a sprite's texture is sampled between texels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpriteFilter {
+	/// Blends neighboring texels, for smooth art. The default.
+	Linear,
+	/// Takes the closest texel, for pixel art, which linear filtering smears when sprites sit between pixels.
+	Nearest,
+}
+impl Default for SpriteFilter {
+	fn default() -> Self {
+		SpriteFilter::Linear
+	}
+}
+
 pub struct Sprite {
+	pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
 	static_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	/// Also held by `static_desc`, kept here for `resources`.
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
 	position: Arc<ImmutableBuffer<[f32; 2]>>,
+	bounds: Rect,
 }
 impl Sprite {
+	/// Area the sprite covers, in pixels of the target it's drawn to.
+	pub fn bounds(&self) -> Rect {
+		self.bounds
+	}
+
+	/// Whether `point`, in target pixels, is over the sprite. Transparent parts of the texture count as hits. To test a
+	/// cursor from window events, convert it with `Window::to_target_position` first.
+	pub fn hit_test(&self, point: [f32; 2]) -> bool {
+		self.bounds.contains(point)
+	}
+
 	pub(crate) fn new(
 		queue: Arc<Queue>,
 		pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
 		sampler: Arc<Sampler>,
 		texture: &Texture,
 		position: [f32; 2]
-	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
+	) -> Result<(Self, impl GpuFuture), TextureDeviceError> {
+		texture::check_same_device(texture.image(), queue.device(), "sprite texture")?;
+		let [width, height] = texture.image().dimensions().width_height();
+		let bounds = Rect::from_position_size(position, [width as f32, height as f32]);
 		let (position, future) = ImmutableBuffer::from_data(position, BufferUsage::uniform_buffer(), queue)?;
 
 		Ok((
 			Self {
 				static_desc:
-					Arc::new(
-						PersistentDescriptorSet::start(pipeline, 2)
+					Arc::new(stats::track(
+						Resource::DescriptorSet,
+						PersistentDescriptorSet::start(pipeline.clone(), 2)
 							.add_sampled_image(texture.image().clone(), sampler)
 							.unwrap()
 							.build()
-							.unwrap()
-					),
-				position: position
+							.unwrap(),
+					)),
+				image: texture.image().clone(),
+				position: position,
+				pipeline: pipeline,
+				bounds: bounds,
 			},
 			future
 		))
@@ -54,9 +91,13 @@ impl Drawable2D for Sprite {
 		dimensions: [f32; 2],
 	) -> Result<AutoCommandBuffer, OomError> {
 		Ok(
-			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(shared.shaders().device().clone(), queue_family, shared.subpass().clone())?
+			AutoCommandBufferBuilder::secondary_graphics_simultaneous_use(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?
 				.draw(
-					shared.pipeline_sprite().clone(),
+					self.pipeline.clone(),
 					&DynamicState {
 						line_width: None,
 						viewports:
@@ -66,12 +107,15 @@ impl Drawable2D for Sprite {
 					vec![shared.shaders().vertices().clone()],
 					(
 						target_desc.clone(),
-						shared.sprite_desc_pool().lock().unwrap()
-							.next()
-							.add_buffer(self.position.clone())
-							.unwrap()
-							.build()
-							.unwrap(),
+						stats::track(
+							Resource::DescriptorSet,
+							shared.sprite_desc_pool().lock().unwrap()
+								.next()
+								.add_buffer(self.position.clone())
+								.unwrap()
+								.build()
+								.unwrap(),
+						),
 						self.static_desc.clone(),
 					),
 					()
@@ -81,4 +125,15 @@ impl Drawable2D for Sprite {
 				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
 		)
 	}
+
+	/// The texture and position never change.
+	fn is_static(&self) -> bool {
+		true
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_buffer(&self.position);
+		tally.add_image(&*self.image);
+		tally.add_descriptor_set();
+	}
 }
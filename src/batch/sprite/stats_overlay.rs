@@ -0,0 +1,132 @@
+use super::{ Drawable2D, Font, TextSprite };
+use super::shared::SpriteBatchShared;
+use crate::device::DeviceCtx;
+use crate::stats::{ ResourceCounts, ResourceTally };
+use std::{ sync::{ Arc, atomic::{ AtomicBool, Ordering } }, time::{ Duration, Instant } };
+use vulkano::{
+	OomError,
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError },
+	descriptor::DescriptorSet,
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+};
+
+/// How often the text is laid out again. Every frame would make the overlay itself show up in the counts.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Text showing the frame rate and what the batches on its device created per frame, from the device's `ResourceStats`,
+/// averaged over the overlay's frames since it was last laid out. Add it to the sprite batch drawn last, and toggle it
+/// with the handle from `toggle`, e.g. on a debug key.
+pub struct StatsOverlay {
+	font: Arc<Font>,
+	position: [f32; 2],
+	visible: StatsOverlayToggle,
+	text: Option<TextSprite>,
+	frames: u32,
+	last_refresh: Instant,
+	/// The device's counts when the text was last laid out, or `None` while hidden.
+	created_at_refresh: Option<ResourceCounts>,
+}
+impl StatsOverlay {
+	/// Starts hidden.
+	pub fn new(font: Arc<Font>, position: [f32; 2]) -> Self {
+		Self {
+			font: font,
+			position: position,
+			visible: StatsOverlayToggle(Arc::new(AtomicBool::new(false))),
+			text: None,
+			frames: 0,
+			last_refresh: Instant::now(),
+			created_at_refresh: None,
+		}
+	}
+
+	/// Shows and hides the overlay after it's been added to a batch.
+	pub fn toggle(&self) -> StatsOverlayToggle {
+		self.visible.clone()
+	}
+
+	fn refresh(&mut self, shared: &SpriteBatchShared) -> Result<(), DeviceMemoryAllocError> {
+		let elapsed = self.last_refresh.elapsed();
+		let seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0;
+		let (created, live) =
+			match DeviceCtx::find(shared.shaders().device()) {
+				Some(ctx) => (ctx.resource_stats().created(), ctx.resource_stats().live()),
+				None => (ResourceCounts::default(), ResourceCounts::default()),
+			};
+		// nothing to average over on the first frame shown
+		let frame =
+			self.created_at_refresh
+				.map(|start| created.sub(&start).div(self.frames.max(1) as usize))
+				.unwrap_or_default();
+		let text =
+			format!(
+				"{:.0} fps | per frame: {} sets, {} framebuffers, {} subbuffers | live glyph uploads: {}",
+				self.frames as f32 / seconds,
+				frame.descriptor_sets,
+				frame.framebuffers,
+				frame.subbuffers,
+				live.glyph_futures,
+			);
+
+		self.text = Some(self.font.make_sprite(&text, shared, self.position)?);
+		self.frames = 0;
+		self.last_refresh = Instant::now();
+		self.created_at_refresh = Some(created);
+		Ok(())
+	}
+}
+impl Drawable2D for StatsOverlay {
+	fn make_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, OomError> {
+		self.frames += 1;
+		if !self.visible.is_visible() {
+			self.text = None;
+			self.created_at_refresh = None;
+			return AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) });
+		}
+
+		if self.text.is_none() || self.last_refresh.elapsed() >= REFRESH_INTERVAL {
+			self.refresh(shared).map_err(|err| match err {
+				DeviceMemoryAllocError::OomError(err) => err,
+				err => unreachable!("{}", err),
+			})?;
+		}
+
+		self.text.as_mut().unwrap().make_commands(shared, target_desc, queue_family, dimensions)
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		if let Some(text) = &self.text {
+			text.resources(tally);
+		}
+	}
+}
+
+/// Shared visibility flag for a `StatsOverlay`.
+#[derive(Clone)]
+pub struct StatsOverlayToggle(Arc<AtomicBool>);
+impl StatsOverlayToggle {
+	pub fn is_visible(&self) -> bool {
+		self.0.load(Ordering::Relaxed)
+	}
+
+	pub fn set_visible(&self, visible: bool) {
+		self.0.store(visible, Ordering::Relaxed);
+	}
+
+	pub fn toggle(&self) {
+		self.0.fetch_xor(true, Ordering::Relaxed);
+	}
+}
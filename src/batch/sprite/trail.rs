@@ -0,0 +1,190 @@
+use super::Drawable2D;
+use super::shaders::TrailVertex;
+use super::shared::SpriteBatchShared;
+use crate::clock::duration_secs;
+use crate::color::Color;
+use crate::stats::{ self, Resource, ResourceTally };
+use crate::texture::{ self, Texture, TextureDeviceError };
+use std::{ collections::VecDeque, sync::Arc, time::Duration };
+use vulkano::{
+	OomError,
+	buffer::{ BufferAccess, CpuBufferPool },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	image::ImageViewAccess,
+	instance::QueueFamily,
+	memory::DeviceMemoryAllocError,
+	pipeline::viewport::Viewport,
+};
+
+/// Fading strip through recently pushed positions, for projectile and cursor trails. Each point narrows and fades out
+/// as it ages, and the oldest are dropped once `capacity` is reached. Texture coordinates run from 0 at the head to 1
+/// at the tail along u, and across the strip along v.
+pub struct Trail2D {
+	static_desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	vertex_pool: CpuBufferPool<TrailVertex>,
+	points: VecDeque<([f32; 2], f32)>,
+	capacity: usize,
+	time: f32,
+	width: f32,
+	lifetime: Duration,
+	head_color: [f32; 4],
+	tail_color: [f32; 4],
+}
+impl Trail2D {
+	pub(crate) fn new(
+		shared: &SpriteBatchShared,
+		texture: Option<&Texture>,
+		capacity: usize,
+	) -> Result<Self, TextureDeviceError> {
+		assert!(capacity >= 2, "trails need at least two points");
+
+		let image: Arc<ImageViewAccess + Send + Sync + 'static> =
+			match texture {
+				Some(texture) => {
+					texture::check_same_device(texture.image(), shared.shaders().device(), "trail texture")?;
+					texture.image().clone()
+				},
+				None => shared.shaders().white_pixel().clone(),
+			};
+
+		Ok(Self {
+			static_desc:
+				Arc::new(stats::track(
+					Resource::DescriptorSet,
+					PersistentDescriptorSet::start(shared.pipeline_trail().clone(), 1)
+						.add_sampled_image(image, shared.shaders().sprite_sampler().clone())
+						.unwrap()
+						.build()
+						.unwrap(),
+				)),
+			vertex_pool: CpuBufferPool::vertex_buffer(shared.shaders().device().clone()),
+			points: VecDeque::with_capacity(capacity),
+			capacity: capacity,
+			time: 0.0,
+			width: 8.0,
+			lifetime: Duration::from_millis(300),
+			head_color: [1.0; 4],
+			tail_color: [1.0, 1.0, 1.0, 0.0],
+		})
+	}
+
+	/// Adds a point at the head of the trail, in pixels.
+	pub fn push(&mut self, position: [f32; 2]) {
+		if self.points.len() == self.capacity {
+			self.points.pop_back();
+		}
+		self.points.push_front((position, self.time));
+	}
+
+	/// Ages the points, usually by `Clock::delta`, so the trail follows the engine's time scale and pauses.
+	pub fn update(&mut self, dt: Duration) {
+		self.time += duration_secs(dt);
+	}
+
+	pub fn clear(&mut self) {
+		self.points.clear();
+	}
+
+	/// Width at the head, in pixels. Defaults to 8.
+	pub fn set_width(&mut self, width: f32) {
+		self.width = width;
+	}
+
+	/// How long a point lasts before it's fully faded. Defaults to 300ms.
+	pub fn set_lifetime(&mut self, lifetime: Duration) {
+		self.lifetime = lifetime;
+	}
+
+	/// Colors are interpolated from head to tail by age, and multiplied with the texture. Defaults to white fading to
+	/// transparent.
+	pub fn set_colors(&mut self, head: Color, tail: Color) {
+		self.head_color = head.to_linear();
+		self.tail_color = tail.to_linear();
+	}
+
+	fn vertices(&self) -> Vec<TrailVertex> {
+		let lifetime = duration_secs(self.lifetime).max(1e-6);
+		let count = self.points.len();
+		let mut vertices = Vec::with_capacity(count * 2);
+
+		for (i, &(position, time)) in self.points.iter().enumerate() {
+			// tangent from the neighbors, so joints bend smoothly instead of pinching
+			let prev = self.points[i.saturating_sub(1)].0;
+			let next = self.points[(i + 1).min(count - 1)].0;
+			let (dx, dy) = (next[0] - prev[0], next[1] - prev[1]);
+			let len = (dx * dx + dy * dy).sqrt();
+			let normal = if len > 0.0 { [-dy / len, dx / len] } else { [0.0, 0.0] };
+
+			let age = ((self.time - time) / lifetime).min(1.0);
+			let half_width = self.width * 0.5 * (1.0 - age);
+			let mut color = [0.0; 4];
+			for c in 0..4 {
+				color[c] = self.head_color[c] + (self.tail_color[c] - self.head_color[c]) * age;
+			}
+			let u = i as f32 / (count - 1) as f32;
+
+			vertices.push(TrailVertex {
+				position: [position[0] + normal[0] * half_width, position[1] + normal[1] * half_width],
+				tex_coords: [u, 0.0],
+				color: color,
+			});
+			vertices.push(TrailVertex {
+				position: [position[0] - normal[0] * half_width, position[1] - normal[1] * half_width],
+				tex_coords: [u, 1.0],
+				color: color,
+			});
+		}
+
+		vertices
+	}
+}
+impl Drawable2D for Trail2D {
+	fn make_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, OomError> {
+		let (now, lifetime) = (self.time, duration_secs(self.lifetime));
+		while self.points.back().map_or(false, |&(_, time)| now - time >= lifetime) {
+			self.points.pop_back();
+		}
+
+		let mut cmd =
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+				shared.shaders().device().clone(),
+				queue_family,
+				shared.subpass().clone()
+			)?;
+
+		if self.points.len() >= 2 {
+			let vertices =
+				self.vertex_pool.chunk(self.vertices())
+					.map(|vertices| stats::track(Resource::Subbuffer, vertices))
+					.map_err(|err| match err { DeviceMemoryAllocError::OomError(err) => err, err => unreachable!("{}", err) })?;
+
+			cmd = cmd
+				.draw(
+					shared.pipeline_trail().clone(),
+					&DynamicState {
+						line_width: None,
+						viewports:
+							Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+						scissors: None,
+					},
+					vec![Arc::new(vertices) as Arc<BufferAccess + Send + Sync>],
+					(target_desc.clone(), self.static_desc.clone()),
+					()
+				)
+				.unwrap();
+		}
+
+		Ok(cmd.build().map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?)
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_descriptor_set();
+	}
+}
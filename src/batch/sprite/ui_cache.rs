@@ -0,0 +1,76 @@
+use super::{ Drawable2D, SpriteBatch };
+use super::shared::SpriteBatchShared;
+use super::sprite::Sprite;
+use crate::compositor::{ Compositor, Layer };
+use crate::color::Color;
+use crate::texture::{ TargetTexture, TextureDeviceError };
+use crate::window::Window;
+use std::sync::Arc;
+use vulkano::{ memory::DeviceMemoryAllocError, sync::GpuFuture };
+
+/// Renders sprites into a texture once, and only again after they change. Draw the result with a sprite from
+/// `make_sprite`, so a complex but mostly static UI costs a single sprite per frame.
+pub struct UiCache {
+	shared: Arc<SpriteBatchShared>,
+	target: TargetTexture,
+	batch: SpriteBatch,
+	dirty: bool,
+}
+impl UiCache {
+	pub fn new(
+		window: &Window,
+		shared: Arc<SpriteBatchShared>,
+		dimensions: [u32; 2],
+	) -> Result<(Self, impl GpuFuture), DeviceMemoryAllocError> {
+		let target = TargetTexture::new(window, dimensions)?;
+		let (mut batch, future) = SpriteBatch::new(window, &target, shared.clone())?;
+		batch.set_clear_color(Color::TRANSPARENT);
+
+		Ok((Self { shared: shared, target: target, batch: batch, dirty: true }, future))
+	}
+
+	/// Creates a sprite that draws the cached contents, blended over whatever is below it.
+	pub fn make_sprite(&self, position: [f32; 2]) -> Result<(Sprite, impl GpuFuture), TextureDeviceError> {
+		Sprite::new(
+			self.shared.shaders().queue().clone(),
+			self.shared.pipeline_sprite_premultiplied().clone(),
+			self.shared.shaders().sprite_sampler().clone(),
+			&self.target,
+			position,
+		)
+	}
+
+	pub fn add_sprite(&mut self, sprite: Box<Drawable2D>) {
+		self.batch.add_sprite(sprite);
+		self.dirty = true;
+	}
+
+	pub fn clear_sprites(&mut self) {
+		self.batch.clear_sprites();
+		self.dirty = true;
+	}
+
+	/// Re-renders the cache the next time it's composited. Sprites are boxed and can't be seen from here, so call this
+	/// after anything drawn into the cache changes in place.
+	pub fn mark_dirty(&mut self) {
+		self.dirty = true;
+	}
+
+	pub fn is_dirty(&self) -> bool {
+		self.dirty
+	}
+
+	/// Re-renders the cache on `Layer::OFFSCREEN` if it's dirty, so sprites drawing it later in the frame see the new
+	/// contents. Does nothing otherwise.
+	pub fn composite(&mut self, window: &Window, compositor: &mut Compositor) -> Result<(), DeviceMemoryAllocError> {
+		if !self.dirty {
+			return Ok(());
+		}
+
+		let (commands, future) = self.batch.commands(window, &self.target, 0)?;
+		compositor.add(Layer::OFFSCREEN, commands, future);
+		self.dirty = false;
+
+		Ok(())
+	}
+}
@@ -0,0 +1,412 @@
+use super::Drawable2D;
+use super::shared::SpriteBatchShared;
+use crate::cpu_pool::{ Canceled, CpuFuture, spawn_cpu, spawn_fs };
+use crate::stats::{ self, Resource, ResourceTally };
+use crate::texture::{ MutableTexture, MutableTextureError, Texture, TextureError };
+use crate::trace::Span;
+use crate::window::Window;
+use futures::prelude::*;
+use image::{ self, ImageError, ImageFormat };
+use log::{ log, warn };
+use std::{
+	cmp::min,
+	fs::File,
+	io::{ self, prelude::* },
+	ops::Range,
+	path::Path,
+	sync::Arc,
+	time::Duration,
+};
+use vulkano::{
+	OomError,
+	buffer::{ BufferUsage, ImmutableBuffer },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError, DynamicState },
+	descriptor::{ DescriptorSet, descriptor_set::PersistentDescriptorSet },
+	device::Queue,
+	instance::QueueFamily,
+	pipeline::{ GraphicsPipelineAbstract, viewport::Viewport },
+	sampler::Sampler,
+	sync::{ FenceSignalFuture, FlushError, GpuFuture },
+};
+
+// one being shown, one possibly still sampled by a frame in flight, and one to upload into
+const RING_SIZE: usize = 3;
+const DECODE_AHEAD: usize = 4;
+
+/// Plays a motion JPEG stream (concatenated JPEG frames, as written by `ffmpeg -f mjpeg`). Frames are decoded on the
+/// CPU pool ahead of the clock and uploaded into a small ring of `MutableTexture`s.
+///
+/// Playback starts paused. After `play`, it advances by whatever is passed to `update`, usually `Clock::delta`; to stay
+/// in sync with a soundtrack, call `set_time` with the audio position every frame instead.
+pub struct VideoSprite {
+	queue: Arc<Queue>,
+	pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+	data: Arc<Vec<u8>>,
+	frames: Vec<Range<usize>>,
+	frame_rate: f32,
+	looping: bool,
+	ring: Vec<RingSlot>,
+	displayed: usize,
+	shown_count: u64,
+	decoding: Vec<(usize, CpuFuture<Vec<u8>, ImageError>)>,
+	decoded: Vec<(usize, Vec<u8>)>,
+	failed: Vec<usize>,
+	position: Arc<ImmutableBuffer<[f32; 2]>>,
+	playing: bool,
+	time: Duration,
+}
+impl VideoSprite {
+	pub fn from_mjpeg_file<P>(
+		window: &Window,
+		shared: &SpriteBatchShared,
+		path: P,
+		frame_rate: f32,
+		position: [f32; 2],
+	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		let queue = window.device().queue().clone();
+		let pipeline = shared.pipeline_sprite().clone();
+		let sampler = shared.shaders().sprite_sampler().clone();
+
+		spawn_fs(|| {
+			let mut span = Span::new("video", "read").arg("path", path.as_ref().display());
+			let mut bytes = vec![];
+			File::open(path)?.read_to_end(&mut bytes)?;
+			span.record("bytes", bytes.len());
+			Ok(bytes)
+		})
+			.then(move |bytes: Result<Vec<u8>, io::Error>| spawn_cpu(move || {
+				Self::from_mjpeg(queue, pipeline, sampler, bytes?, frame_rate, position)
+			}))
+	}
+
+	fn from_mjpeg(
+		queue: Arc<Queue>,
+		pipeline: Arc<GraphicsPipelineAbstract + Send + Sync + 'static>,
+		sampler: Arc<Sampler>,
+		data: Vec<u8>,
+		frame_rate: f32,
+		position: [f32; 2],
+	) -> Result<(Self, impl GpuFuture), TextureError> {
+		if !(frame_rate > 0.0) {
+			return Err(TextureError::InvalidFrameRate(frame_rate));
+		}
+
+		let frames = split_mjpeg(&data);
+		if frames.is_empty() {
+			return Err(TextureError::ImageError(ImageError::FormatError("no JPEG frames in stream".to_owned())));
+		}
+
+		let first = image::load_from_memory_with_format(&data[frames[0].clone()], ImageFormat::JPEG)?.to_rgba();
+		let dimensions = [first.width(), first.height()];
+
+		let mut ring =
+			(0..RING_SIZE)
+				.map(|_| {
+					MutableTexture::from_queue(&queue, dimensions, true).map(|texture| RingSlot {
+						desc:
+							Arc::new(stats::track(
+								Resource::DescriptorSet,
+								PersistentDescriptorSet::start(pipeline.clone(), 2)
+									.add_sampled_image(texture.image().clone(), sampler.clone())
+									.unwrap()
+									.build()
+									.unwrap(),
+							)),
+						texture: texture,
+						frame: None,
+						upload: None,
+						last_shown: 0,
+					})
+				})
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(TextureError::DeviceMemoryAllocError)?;
+
+		let upload_future =
+			ring[0].texture.write(&queue, first.into_raw().into_iter())
+				.map_err(|err| match err {
+					MutableTextureError::DeviceMemoryAllocError(err) => TextureError::DeviceMemoryAllocError(err),
+					err => unreachable!("{:?}", err),
+				})?;
+		let (position, position_future) =
+			ImmutableBuffer::from_data(position, BufferUsage::uniform_buffer(), queue.clone())
+				.map_err(TextureError::DeviceMemoryAllocError)?;
+
+		ring[0].frame = Some(0);
+
+		Ok((
+			Self {
+				queue: queue,
+				pipeline: pipeline,
+				data: Arc::new(data),
+				frames: frames,
+				frame_rate: frame_rate,
+				looping: false,
+				ring: ring,
+				displayed: 0,
+				shown_count: 0,
+				decoding: vec![],
+				decoded: vec![],
+				failed: vec![],
+				position: position,
+				playing: false,
+				time: Duration::default(),
+			},
+			upload_future.join(position_future)
+		))
+	}
+
+	pub fn play(&mut self) {
+		self.playing = true;
+	}
+
+	pub fn pause(&mut self) {
+		self.playing = false;
+	}
+
+	pub fn is_playing(&self) -> bool {
+		self.playing
+	}
+
+	/// Advances playback by `dt` while playing.
+	pub fn update(&mut self, dt: Duration) {
+		if self.playing {
+			self.time += dt;
+		}
+	}
+
+	/// Sets the playback position. Call every frame with the audio clock to keep the video in sync with it.
+	pub fn set_time(&mut self, time: Duration) {
+		self.time = time;
+	}
+
+	pub fn time(&self) -> Duration {
+		self.time
+	}
+
+	pub fn set_looping(&mut self, looping: bool) {
+		self.looping = looping;
+	}
+
+	pub fn frame_count(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn duration(&self) -> Duration {
+		let secs = self.frames.len() as f64 / self.frame_rate as f64;
+		Duration::new(secs as u64, (secs.fract() * 1e9) as u32)
+	}
+
+	/// True once a non-looping video has played past its last frame.
+	pub fn is_finished(&self) -> bool {
+		!self.looping && self.time() >= self.duration()
+	}
+
+	fn frame_at(&self, time: Duration) -> usize {
+		let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9;
+		let frame = (secs * self.frame_rate as f64) as usize;
+		if self.looping { frame % self.frames.len() } else { min(frame, self.frames.len() - 1) }
+	}
+
+	/// Advances decoding and uploading, and picks the frame to draw this time.
+	fn update_frames(&mut self) {
+		let current = self.frame_at(self.time());
+		let frame_count = self.frames.len();
+		let looping = self.looping;
+		let upcoming: Vec<usize> =
+			(current..current + DECODE_AHEAD)
+				.filter_map(|frame| if looping { Some(frame % frame_count) } else if frame < frame_count { Some(frame) } else { None })
+				.collect();
+
+		for slot in &mut self.ring {
+			let finished = match slot.upload.as_ref().map(|upload| upload.wait(Some(Duration::from_secs(0)))) {
+				None | Some(Err(FlushError::Timeout)) => false,
+				Some(Ok(())) => true,
+				Some(Err(err)) => {
+					warn!("video frame upload failed: {:?}", err);
+					slot.frame = None;
+					true
+				},
+			};
+			if finished {
+				slot.upload = None;
+			}
+		}
+
+		if let Some(index) = self.ring.iter().position(|slot| slot.upload.is_none() && slot.frame == Some(current)) {
+			if index != self.displayed {
+				self.shown_count += 1;
+				self.displayed = index;
+			}
+			self.ring[index].last_shown = self.shown_count;
+		}
+
+		let mut i = 0;
+		while i < self.decoding.len() {
+			match self.decoding[i].1.try_get() {
+				Ok(Some(Ok(pixels))) => {
+					let (frame, _) = self.decoding.swap_remove(i);
+					self.decoded.push((frame, pixels));
+				},
+				Ok(Some(Err(err))) => {
+					let (frame, _) = self.decoding.swap_remove(i);
+					warn!("failed to decode video frame {}: {:?}", frame, err);
+					self.failed.push(frame);
+				},
+				Err(Canceled) => {
+					let (frame, _) = self.decoding.swap_remove(i);
+					warn!("decoding video frame {} panicked", frame);
+					self.failed.push(frame);
+				},
+				Ok(None) => i += 1,
+			}
+		}
+
+		// anything the clock has passed is useless now
+		self.decoding.retain(|(frame, _)| upcoming.contains(frame));
+		self.decoded.retain(|(frame, _)| upcoming.contains(frame));
+		self.failed.retain(|frame| upcoming.contains(frame));
+
+		self.upload_next(&upcoming);
+
+		for &frame in &upcoming {
+			let queued =
+				self.ring.iter().any(|slot| slot.frame == Some(frame)) ||
+				self.decoding.iter().any(|&(decoding, _)| decoding == frame) ||
+				self.decoded.iter().any(|&(decoded, _)| decoded == frame) ||
+				self.failed.contains(&frame);
+			if queued {
+				continue;
+			}
+
+			let data = self.data.clone();
+			let range = self.frames[frame].clone();
+			self.decoding.push((
+				frame,
+				spawn_cpu(move || Ok(image::load_from_memory_with_format(&data[range], ImageFormat::JPEG)?.to_rgba().into_raw()))
+			));
+		}
+	}
+
+	/// Uploads the soonest decoded frame that isn't in the ring yet, into the least recently shown free slot.
+	fn upload_next(&mut self, upcoming: &[usize]) {
+		let order = |frame: Option<usize>| frame.and_then(|frame| upcoming.iter().position(|&upcoming| upcoming == frame));
+
+		let next =
+			upcoming.iter()
+				.filter(|&&frame| !self.ring.iter().any(|slot| slot.frame == Some(frame)))
+				.filter_map(|&frame| self.decoded.iter().position(|&(decoded, _)| decoded == frame))
+				.next();
+		let next = match next { Some(next) => next, None => return };
+		let next_order = order(Some(self.decoded[next].0));
+
+		let displayed = self.displayed;
+		let slot =
+			self.ring.iter().enumerate()
+				.filter(|&(index, slot)| {
+					index != displayed && slot.upload.is_none() && order(slot.frame).map_or(true, |order| Some(order) > next_order)
+				})
+				.min_by_key(|&(_, slot)| slot.last_shown)
+				.map(|(index, _)| index);
+		let slot = match slot { Some(slot) => slot, None => return };
+
+		// copied, since the frame stays decoded if the slot turns out to be in use
+		let pixels = self.decoded[next].1.clone();
+		let result =
+			self.ring[slot].texture.write(&self.queue, pixels.into_iter())
+				.and_then(|future| {
+					let future: Box<GpuFuture + Send + Sync> = Box::new(future);
+					Ok(future.then_signal_fence_and_flush()?)
+				});
+
+		match result {
+			Ok(upload) => {
+				let (frame, _) = self.decoded.swap_remove(next);
+				self.ring[slot].frame = Some(frame);
+				self.ring[slot].upload = Some(upload);
+			},
+			// still sampled by a frame in flight, so try again next time
+			Err(MutableTextureError::InUse) => (),
+			Err(err) => warn!("failed to upload video frame: {:?}", err),
+		}
+	}
+}
+impl Drawable2D for VideoSprite {
+	fn make_commands(
+		&mut self,
+		shared: &SpriteBatchShared,
+		target_desc: &Arc<DescriptorSet + Send + Sync + 'static>,
+		queue_family: QueueFamily,
+		dimensions: [f32; 2],
+	) -> Result<AutoCommandBuffer, OomError> {
+		self.update_frames();
+
+		Ok(
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(shared.shaders().device().clone(), queue_family, shared.subpass().clone())?
+				.draw(
+					self.pipeline.clone(),
+					&DynamicState {
+						line_width: None,
+						viewports:
+							Some(vec![Viewport { origin: [0.0, 0.0], dimensions: dimensions, depth_range: 0.0..1.0 }]),
+						scissors: None,
+					},
+					vec![shared.shaders().vertices().clone()],
+					(
+						target_desc.clone(),
+						stats::track(
+							Resource::DescriptorSet,
+							shared.sprite_desc_pool().lock().unwrap()
+								.next()
+								.add_buffer(self.position.clone())
+								.unwrap()
+								.build()
+								.unwrap(),
+						),
+						self.ring[self.displayed].desc.clone(),
+					),
+					()
+				)
+				.unwrap()
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?
+		)
+	}
+
+	fn resources(&self, tally: &mut ResourceTally) {
+		tally.add_buffer(&self.position);
+		for slot in &self.ring {
+			tally.add_image(&**slot.texture.image());
+			tally.add_descriptor_set();
+		}
+	}
+}
+
+struct RingSlot {
+	texture: MutableTexture,
+	desc: Arc<DescriptorSet + Send + Sync + 'static>,
+	frame: Option<usize>,
+	upload: Option<FenceSignalFuture<Box<GpuFuture + Send + Sync>>>,
+	last_shown: u64,
+}
+
+/// Splits a motion JPEG stream at its start and end of image markers.
+fn split_mjpeg(data: &[u8]) -> Vec<Range<usize>> {
+	let mut frames = vec![];
+	let mut start = None;
+	for i in 0..data.len().saturating_sub(1) {
+		if data[i] != 0xFF {
+			continue;
+		}
+
+		match (data[i + 1], start) {
+			(0xD8, None) => start = Some(i),
+			(0xD9, Some(frame_start)) => {
+				frames.push(frame_start..i + 2);
+				start = None;
+			},
+			_ => (),
+		}
+	}
+	frames
+}
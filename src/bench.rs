@@ -0,0 +1,319 @@
+use crate::RenderTarget;
+use crate::batch::mesh::{ Mesh, MeshBatch, MeshRenderPass };
+use crate::batch::sprite::{ Font, SpriteBatch, SpriteBatchShared };
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::geometry::MeshGeometry;
+use crate::math::Transform;
+use crate::texture::{ TargetTexture, Texture, TextureDeviceError };
+use crate::window::Window;
+use cgmath::vec3;
+use std::{ fmt, sync::Arc, time::{ Duration, Instant } };
+use vulkano::{
+	command_buffer::AutoCommandBuffer,
+	memory::DeviceMemoryAllocError,
+	sync::{ self, GpuFuture },
+};
+
+/// Frames run before timing starts, so pipelines, descriptor pools and caches are warm.
+const WARMUP_FRAMES: usize = 3;
+
+/// A scene that records the same amount of work every frame, for timing the batch and command paths.
+pub trait StressScene {
+	fn name(&self) -> String;
+
+	/// Records one frame. The future, if any, must complete before the commands run.
+	fn record(&mut self, window: &Window) -> Result<(AutoCommandBuffer, Option<Box<GpuFuture>>), TextureDeviceError>;
+
+	/// Approximate bytes of device memory held by the scene's own buffers and images. Driver overhead and pool slack
+	/// aren't included.
+	fn memory_bytes(&self) -> u64;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DurationStats {
+	pub min: Duration,
+	pub mean: Duration,
+	pub max: Duration,
+}
+impl DurationStats {
+	fn new(samples: &[Duration]) -> Self {
+		if samples.is_empty() {
+			return Self { min: Duration::default(), mean: Duration::default(), max: Duration::default() };
+		}
+
+		Self {
+			min: *samples.iter().min().unwrap(),
+			mean: samples.iter().sum::<Duration>() / samples.len() as u32,
+			max: *samples.iter().max().unwrap(),
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+	pub name: String,
+	pub frames: usize,
+	/// CPU time spent recording command buffers.
+	pub record: DurationStats,
+	/// Time from submitting a frame until its fence signals. This includes submission overhead, so it's an upper
+	/// bound on GPU time.
+	pub gpu: DurationStats,
+	pub memory_bytes: u64,
+}
+impl fmt::Display for BenchReport {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{}: {} frames, record {:.3}/{:.3}/{:.3}ms, gpu {:.3}/{:.3}/{:.3}ms (min/mean/max), {:.1}MiB",
+			self.name,
+			self.frames,
+			as_millis(self.record.min),
+			as_millis(self.record.mean),
+			as_millis(self.record.max),
+			as_millis(self.gpu.min),
+			as_millis(self.gpu.mean),
+			as_millis(self.gpu.max),
+			self.memory_bytes as f64 / (1024.0 * 1024.0),
+		)
+	}
+}
+
+/// Records and submits `frames` frames of `scene`, waiting for each to finish before starting the next, so frames
+/// don't overlap and each one is timed on its own.
+pub fn run(window: &Window, scene: &mut StressScene, frames: usize) -> Result<BenchReport, TextureDeviceError> {
+	let mut record = Vec::with_capacity(frames);
+	let mut gpu = Vec::with_capacity(frames);
+
+	for frame in 0..WARMUP_FRAMES + frames {
+		let start = Instant::now();
+		let (commands, future) = scene.record(window)?;
+		let recorded = Instant::now();
+
+		let queue = window.device().queue().clone();
+		let mut before: Box<GpuFuture> = Box::new(sync::now(queue.device().clone()));
+		if let Some(future) = future {
+			before = Box::new(before.join(future));
+		}
+		before.then_execute(queue, commands)
+			.unwrap()
+			.then_signal_fence_and_flush()
+			.expect("failed to submit bench frame")
+			.wait(None)
+			.expect("failed to wait for bench frame");
+
+		if frame >= WARMUP_FRAMES {
+			record.push(recorded - start);
+			gpu.push(recorded.elapsed());
+		}
+	}
+
+	Ok(BenchReport {
+		name: scene.name(),
+		frames: frames,
+		record: DurationStats::new(&record),
+		gpu: DurationStats::new(&gpu),
+		memory_bytes: scene.memory_bytes(),
+	})
+}
+
+/// `count` sprites of one texture in a grid, drawn to an offscreen target.
+pub struct SpriteScene {
+	target: TargetTexture,
+	batch: SpriteBatch,
+	count: usize,
+}
+impl SpriteScene {
+	pub fn new(
+		window: &Window,
+		shared: &Arc<SpriteBatchShared>,
+		texture: &Texture,
+		count: usize,
+		dimensions: [u32; 2],
+	) -> Result<Self, TextureDeviceError> {
+		let target = TargetTexture::new(window, dimensions)?;
+		let (mut batch, batch_future) = SpriteBatch::new(window, &target, shared.clone())?;
+		let mut future: Box<GpuFuture> = Box::new(batch_future);
+
+		let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+		for i in 0..count {
+			let position = [
+				(i % columns) as f32 / columns as f32 * dimensions[0] as f32,
+				(i / columns) as f32 / columns as f32 * dimensions[1] as f32,
+			];
+			let (sprite, sprite_future) = shared.create_sprite(texture, position)?;
+			batch.add_sprite(Box::new(sprite));
+			future = Box::new(future.join(sprite_future));
+		}
+		wait(future);
+
+		Ok(Self { target: target, batch: batch, count: count })
+	}
+}
+impl StressScene for SpriteScene {
+	fn name(&self) -> String {
+		format!("{} sprites", self.count)
+	}
+
+	fn record(&mut self, window: &Window) -> Result<(AutoCommandBuffer, Option<Box<GpuFuture>>), TextureDeviceError> {
+		let (commands, future) = self.batch.commands(window, &self.target, 0)?;
+		Ok((commands, future.map(|future| Box::new(future) as _)))
+	}
+
+	fn memory_bytes(&self) -> u64 {
+		target_bytes(&self.target) + self.count as u64 * 8
+	}
+}
+
+/// Lines of text totalling `glyphs` characters, drawn to an offscreen target.
+pub struct TextScene {
+	target: TargetTexture,
+	batch: SpriteBatch,
+	glyphs: usize,
+}
+impl TextScene {
+	pub fn new(
+		window: &Window,
+		shared: &Arc<SpriteBatchShared>,
+		font: &Font,
+		glyphs: usize,
+		dimensions: [u32; 2],
+	) -> Result<Self, DeviceMemoryAllocError> {
+		const LINE: &str = "The quick brown fox jumps over the lazy dog. 0123456789";
+		const LINE_HEIGHT: f32 = 16.0;
+
+		let target = TargetTexture::new(window, dimensions)?;
+		let (mut batch, batch_future) = SpriteBatch::new(window, &target, shared.clone())?;
+		wait(Box::new(batch_future));
+
+		let mut remaining = glyphs;
+		let mut line = 0;
+		while remaining > 0 {
+			let len = remaining.min(LINE.len());
+			let y = (line as f32 * LINE_HEIGHT) % dimensions[1] as f32 + LINE_HEIGHT;
+			batch.add_sprite(Box::new(font.make_sprite(&LINE[..len], shared, [0.0, y])?));
+			remaining -= len;
+			line += 1;
+		}
+
+		Ok(Self { target: target, batch: batch, glyphs: glyphs })
+	}
+}
+impl StressScene for TextScene {
+	fn name(&self) -> String {
+		format!("{} glyphs", self.glyphs)
+	}
+
+	fn record(&mut self, window: &Window) -> Result<(AutoCommandBuffer, Option<Box<GpuFuture>>), TextureDeviceError> {
+		let (commands, future) = self.batch.commands(window, &self.target, 0)?;
+		Ok((commands, future.map(|future| Box::new(future) as _)))
+	}
+
+	fn memory_bytes(&self) -> u64 {
+		// glyph images are shared through the font, so only the per-glyph positions count
+		target_bytes(&self.target) + self.glyphs as u64 * 8
+	}
+}
+
+/// `meshes` cubes in a grid, cycling through `materials` distinct materials, drawn to an offscreen target.
+pub struct MeshScene {
+	target: TargetTexture,
+	batch: MeshBatch,
+	camera: Camera,
+	meshes: usize,
+	materials: usize,
+	mesh_bytes: u64,
+}
+impl MeshScene {
+	pub fn new(
+		window: &Window,
+		render_pass: &Arc<MeshRenderPass>,
+		meshes: usize,
+		materials: usize,
+		dimensions: [u32; 2],
+	) -> Result<Self, DeviceMemoryAllocError> {
+		assert!(materials > 0, "mesh scene needs at least one material");
+
+		let target = TargetTexture::new(window, dimensions)?;
+		let (mut batch, batch_future) = MeshBatch::new(&target, render_pass.clone())?;
+		let mut future: Box<GpuFuture> = Box::new(batch_future);
+
+		let geometry = MeshGeometry::cube();
+		let mesh_bytes =
+			geometry.vertex_count() as u64 * (12 + 12 + 8 + 4) + geometry.indices.len() as u64 * 4 + 32;
+
+		let columns = (meshes as f32).sqrt().ceil().max(1.0) as usize;
+		for i in 0..meshes {
+			let hue = (i % materials) as f32 / materials as f32;
+			let position =
+				vec3(
+					(i % columns) as f32 * 2.0 - columns as f32,
+					(i / columns) as f32 * 2.0 - columns as f32,
+					-2.0 * columns as f32,
+				);
+			let (mesh, mesh_future) =
+				Mesh::from_geometry(
+					window,
+					render_pass.clone(),
+					geometry.clone(),
+					Color::from_linear(hue, 1.0 - hue, 0.5, 1.0),
+					Transform::from_position(position),
+				)?;
+			batch.add_mesh(mesh);
+			future = Box::new(future.join(mesh_future));
+		}
+		wait(future);
+
+		let camera =
+			Camera::new(
+				window,
+				Transform::identity(),
+				dimensions[0] as f32 / dimensions[1] as f32,
+				90.0,
+				0.1,
+				1000.0,
+			)?;
+
+		Ok(Self {
+			target: target,
+			batch: batch,
+			camera: camera,
+			meshes: meshes,
+			materials: materials,
+			mesh_bytes: mesh_bytes * meshes as u64,
+		})
+	}
+}
+impl StressScene for MeshScene {
+	fn name(&self) -> String {
+		format!("{} meshes, {} materials", self.meshes, self.materials)
+	}
+
+	fn record(&mut self, window: &Window) -> Result<(AutoCommandBuffer, Option<Box<GpuFuture>>), TextureDeviceError> {
+		let (commands, future) = self.batch.commands(window, &self.target, 0, &self.camera)?;
+		Ok((commands, future.map(|future| Box::new(future) as _)))
+	}
+
+	fn memory_bytes(&self) -> u64 {
+		// albedo, normal, mask, depth and lit, plus two history images
+		let [width, height] = self.target.images()[0].dimensions().width_height();
+		let gbuffers = width as u64 * height as u64 * (4 + 16 + 4 + 2 + 8 + 4 * 2);
+		target_bytes(&self.target) + gbuffers + self.mesh_bytes + self.materials as u64 * 32
+	}
+}
+
+fn wait(future: Box<GpuFuture>) {
+	future.then_signal_fence_and_flush()
+		.expect("failed to flush bench scene uploads")
+		.wait(None)
+		.expect("failed to wait for bench scene uploads");
+}
+
+fn target_bytes(target: &TargetTexture) -> u64 {
+	let [width, height] = target.images()[0].dimensions().width_height();
+	width as u64 * height as u64 * 4
+}
+
+fn as_millis(duration: Duration) -> f64 {
+	duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
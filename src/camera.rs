@@ -1,5 +1,13 @@
+mod effects;
+mod photo_mode;
+
+pub use self::effects::{ CameraShake, FovKick, SmoothFollow };
+pub use self::photo_mode::{ PhotoMode, TiledCapture };
+use crate::StableId;
+use crate::math::{ GpuQuaternion, Transform, quat_to_xyzw };
+use crate::stats::{ self, Resource, Tracked };
 use crate::window::Window;
-use cgmath::{ vec4, Quaternion, Vector3, Vector4 };
+use cgmath::{ prelude::*, vec4, Quaternion, Vector3, Vector4 };
 use std::{ f32::consts::PI, sync::Arc };
 use vulkano::{
 	buffer::{ CpuBufferPool, cpu_pool::CpuBufferPoolSubbuffer },
@@ -8,17 +16,19 @@ use vulkano::{
 
 pub struct Camera {
 	position_pool: CpuBufferPool<Vector3<f32>>,
-	rotation_pool: CpuBufferPool<Quaternion<f32>>,
-	projection_pool: CpuBufferPool<Vector4<f32>>,
-	pub(crate) position_buffer: CpuBufferPoolSubbuffer<Vector3<f32>, Arc<StdMemoryPool>>,
-	pub(crate) rotation_buffer: CpuBufferPoolSubbuffer<Quaternion<f32>, Arc<StdMemoryPool>>,
-	pub(crate) projection_buffer: CpuBufferPoolSubbuffer<Vector4<f32>, Arc<StdMemoryPool>>,
+	rotation_pool: CpuBufferPool<GpuQuaternion>,
+	projection_pool: CpuBufferPool<ProjectionUniform>,
+	transform: Transform,
+	projection: Projection,
+	id: Option<StableId>,
+	pub(crate) position_buffer: Tracked<CpuBufferPoolSubbuffer<Vector3<f32>, Arc<StdMemoryPool>>>,
+	pub(crate) rotation_buffer: Tracked<CpuBufferPoolSubbuffer<GpuQuaternion, Arc<StdMemoryPool>>>,
+	pub(crate) projection_buffer: Tracked<CpuBufferPoolSubbuffer<ProjectionUniform, Arc<StdMemoryPool>>>,
 }
 impl Camera {
 	pub fn new(
 		window: &Window,
-		position: Vector3<f32>,
-		rotation: Quaternion<f32>,
+		transform: Transform,
 		aspect: f32,
 		fovx: f32,
 		znear: f32,
@@ -28,22 +38,55 @@ impl Camera {
 		let rotation_pool = CpuBufferPool::uniform_buffer(window.device().device().clone());
 		let projection_pool = CpuBufferPool::uniform_buffer(window.device().device().clone());
 
-		let position_buffer = position_pool.next(position)?;
-		let rotation_buffer = rotation_pool.next(rotation)?;
-		let projection_buffer = projection_pool.next(Self::projection(aspect, fovx, znear, zfar))?;
+		let position_buffer = stats::track(Resource::Subbuffer, position_pool.next(transform.position)?);
+		let rotation_buffer = stats::track(Resource::Subbuffer, rotation_pool.next(quat_to_xyzw(transform.rotation))?);
+		let projection = Projection { aspect: aspect, fovx: fovx, znear: znear, zfar: zfar, tile: None };
+		let projection_buffer = stats::track(Resource::Subbuffer, projection_pool.next(projection.uniform())?);
 
 		Ok(Self {
 			position_pool: position_pool,
 			rotation_pool: rotation_pool,
 			projection_pool: projection_pool,
+			transform: Transform::new(transform.position, transform.rotation),
+			projection: projection,
+			id: None,
 			position_buffer: position_buffer,
 			rotation_buffer: rotation_buffer,
 			projection_buffer: projection_buffer,
 		})
 	}
 
+	/// Cameras aren't kept in a batch, so they only have an id if one is given, e.g. from a save game.
+	pub fn id(&self) -> Option<StableId> {
+		self.id
+	}
+
+	pub fn set_id(&mut self, id: Option<StableId>) {
+		self.id = id;
+	}
+
+	/// Cameras don't scale the view, so the scale is always one.
+	pub fn transform(&self) -> Transform {
+		self.transform
+	}
+
+	/// Moves and turns the camera at once. The scale is ignored.
+	pub fn set_transform(&mut self, transform: Transform) -> Result<(), DeviceMemoryAllocError> {
+		self.set_position(transform.position)?;
+		self.set_rotation(transform.rotation)
+	}
+
+	pub fn position(&self) -> Vector3<f32> {
+		self.transform.position
+	}
+
+	pub fn rotation(&self) -> Quaternion<f32> {
+		self.transform.rotation
+	}
+
 	pub fn set_position(&mut self, position: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.position_buffer = self.position_pool.next(position)?;
+		self.position_buffer = stats::track(Resource::Subbuffer, self.position_pool.next(position)?);
+		self.transform.position = position;
 		Ok(())
 	}
 
@@ -54,17 +97,119 @@ impl Camera {
 		znear: f32,
 		zfar: f32
 	) -> Result<(), DeviceMemoryAllocError> {
-		self.projection_buffer = self.projection_pool.next(Self::projection(aspect, fovx, znear, zfar))?;
-		Ok(())
+		let projection = Projection { aspect: aspect, fovx: fovx, znear: znear, zfar: zfar, ..self.projection };
+		self.set_projection_state(projection)
+	}
+
+	/// Horizontal field of view, in degrees.
+	pub fn fovx(&self) -> f32 {
+		self.projection.fovx
+	}
+
+	/// Changes only the field of view, keeping the aspect ratio and clip planes.
+	pub fn set_fovx(&mut self, fovx: f32) -> Result<(), DeviceMemoryAllocError> {
+		let Projection { aspect, znear, zfar, .. } = self.projection;
+		self.set_projection(aspect, fovx, znear, zfar)
+	}
+
+	pub fn aspect(&self) -> f32 {
+		self.projection.aspect
+	}
+
+	pub fn znear(&self) -> f32 {
+		self.projection.znear
+	}
+
+	pub fn zfar(&self) -> f32 {
+		self.projection.zfar
+	}
+
+	pub fn tile(&self) -> Option<ProjectionTile> {
+		self.projection.tile
+	}
+
+	/// Narrows the view to one tile of a grid, so the tiles rendered one by one make up a frame at the grid's size
+	/// times the target's. `None`, the default, renders the whole view.
+	pub fn set_tile(&mut self, tile: Option<ProjectionTile>) -> Result<(), DeviceMemoryAllocError> {
+		self.set_projection_state(Projection { tile: tile, ..self.projection })
 	}
 
 	pub fn set_rotation(&mut self, rotation: Quaternion<f32>) -> Result<(), DeviceMemoryAllocError> {
-		self.rotation_buffer = self.rotation_pool.next(rotation)?;
+		self.rotation_buffer = stats::track(Resource::Subbuffer, self.rotation_pool.next(quat_to_xyzw(rotation))?);
+		self.transform.rotation = rotation;
+		Ok(())
+	}
+
+	/// Without the tile, see `tiled`.
+	pub(crate) fn projection_vector(&self) -> Vector4<f32> {
+		let Projection { aspect, fovx, znear, zfar, .. } = self.projection;
+		Self::projection(aspect, fovx, znear, zfar)
+	}
+
+	/// `projection` narrowed to the camera's tile, for other projections drawn into the same frame.
+	pub(crate) fn tiled(&self, projection: Vector4<f32>) -> ProjectionUniform {
+		tile_projection(self.projection.tile, projection)
+	}
+
+	fn set_projection_state(&mut self, projection: Projection) -> Result<(), DeviceMemoryAllocError> {
+		self.projection_buffer = stats::track(Resource::Subbuffer, self.projection_pool.next(projection.uniform())?);
+		self.projection = projection;
 		Ok(())
 	}
 
-	fn projection(aspect: f32, fovx: f32, znear: f32, zfar: f32) -> Vector4<f32> {
+	pub(crate) fn projection(aspect: f32, fovx: f32, znear: f32, zfar: f32) -> Vector4<f32> {
 		let f = 1.0 / (fovx * (PI / 360.0)).tan();
 		vec4(f / aspect, f, (zfar + znear) / (znear - zfar), 2.0 * zfar * znear / (znear - zfar))
 	}
 }
+
+/// One cell of a grid the view is split into, for frames larger than any one target. See `Camera::set_tile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProjectionTile {
+	/// Columns and rows in the grid.
+	pub grid: [u32; 2],
+	/// Column and row of this tile, from the top left.
+	pub cell: [u32; 2],
+}
+impl ProjectionTile {
+	fn apply(self, projection: Vector4<f32>) -> ProjectionUniform {
+		// each tile covers 2 / grid of normalized device coordinates, blown up to the whole target and moved so the
+		// tile's part of the view lands on it
+		let (columns, rows) = (self.grid[0] as f32, self.grid[1] as f32);
+		let (column, row) = (self.cell[0] as f32, self.cell[1] as f32);
+		ProjectionUniform {
+			projection: vec4(projection.x * columns, projection.y * rows, projection.z, projection.w),
+			offset: vec4(columns - 1.0 - 2.0 * column, rows - 1.0 - 2.0 * row, 0.0, 0.0),
+		}
+	}
+}
+
+/// Layout of the `CameraProj` uniform.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub(crate) struct ProjectionUniform {
+	pub(crate) projection: Vector4<f32>,
+	/// Only xy is used.
+	pub(crate) offset: Vector4<f32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Projection {
+	aspect: f32,
+	fovx: f32,
+	znear: f32,
+	zfar: f32,
+	tile: Option<ProjectionTile>,
+}
+impl Projection {
+	fn uniform(self) -> ProjectionUniform {
+		tile_projection(self.tile, Camera::projection(self.aspect, self.fovx, self.znear, self.zfar))
+	}
+}
+
+fn tile_projection(tile: Option<ProjectionTile>, projection: Vector4<f32>) -> ProjectionUniform {
+	match tile {
+		Some(tile) => tile.apply(projection),
+		None => ProjectionUniform { projection: projection, offset: Vector4::zero() },
+	}
+}
@@ -0,0 +1,177 @@
+use crate::camera::Camera;
+use crate::clock::duration_secs;
+use crate::math::Transform;
+use crate::random::Rng;
+use cgmath::{ prelude::*, vec3, Euler, Quaternion, Rad, Vector3 };
+use std::time::Duration;
+use vulkano::memory::DeviceMemoryAllocError;
+
+/// Trauma-based shake. Trauma decays over time, and the shake strength is trauma squared, so small hits barely move
+/// the camera and big ones feel violent. Offsets come from smooth noise rather than random jumps each frame.
+#[derive(Clone, Debug)]
+pub struct CameraShake {
+	trauma: f32,
+	decay: f32,
+	frequency: f32,
+	max_offset: f32,
+	max_angle: Rad<f32>,
+	seed: u64,
+	time: f32,
+	offset: Vector3<f32>,
+	angles: Euler<Rad<f32>>,
+}
+impl CameraShake {
+	/// `max_offset` is in world units and `max_angle` applies to each of pitch, yaw and roll, both at full trauma.
+	pub fn new(max_offset: f32, max_angle: Rad<f32>, seed: u64) -> Self {
+		Self {
+			trauma: 0.0,
+			decay: 1.0,
+			frequency: 15.0,
+			max_offset: max_offset,
+			max_angle: max_angle,
+			seed: seed,
+			time: 0.0,
+			offset: Vector3::zero(),
+			angles: Euler::new(Rad(0.0), Rad(0.0), Rad(0.0)),
+		}
+	}
+
+	/// Trauma lost per second. Defaults to 1, so full trauma settles in a second.
+	pub fn set_decay(&mut self, decay: f32) {
+		self.decay = decay;
+	}
+
+	/// How quickly the noise changes, in cycles per second. Defaults to 15.
+	pub fn set_frequency(&mut self, frequency: f32) {
+		self.frequency = frequency;
+	}
+
+	/// Adds trauma, clamped to 1.
+	pub fn add_trauma(&mut self, amount: f32) {
+		self.trauma = (self.trauma + amount).min(1.0).max(0.0);
+	}
+
+	pub fn trauma(&self) -> f32 {
+		self.trauma
+	}
+
+	pub fn update(&mut self, dt: Duration) {
+		let dt = duration_secs(dt);
+		self.time += dt * self.frequency;
+		self.trauma = (self.trauma - self.decay * dt).max(0.0);
+
+		let (seed, time, shake) = (self.seed, self.time, self.trauma * self.trauma);
+		let sample = |channel| noise(seed.wrapping_add(channel), time) * shake;
+		self.offset = vec3(sample(0), sample(1), sample(2)) * self.max_offset;
+		self.angles = Euler::new(self.max_angle * sample(3), self.max_angle * sample(4), self.max_angle * sample(5));
+	}
+
+	/// Places the camera at `transform`, plus the current shake. The offset is in camera space, so it looks the same
+	/// whichever way the camera faces.
+	pub fn apply(&self, camera: &mut Camera, transform: Transform) -> Result<(), DeviceMemoryAllocError> {
+		camera.set_transform(Transform {
+			position: transform.position + transform.rotation.rotate_vector(self.offset),
+			rotation: transform.rotation * Quaternion::from(self.angles),
+			..transform
+		})
+	}
+}
+
+/// Exponential smoothing towards a target, which is frame rate independent unlike lerping by a fixed factor.
+#[derive(Clone, Debug)]
+pub struct SmoothFollow {
+	position_half_life: f32,
+	rotation_half_life: f32,
+	current: Option<Transform>,
+}
+impl SmoothFollow {
+	/// Half lives are the seconds it takes to cover half the remaining distance. Zero follows rigidly.
+	pub fn new(position_half_life: f32, rotation_half_life: f32) -> Self {
+		Self { position_half_life: position_half_life, rotation_half_life: rotation_half_life, current: None }
+	}
+
+	pub fn set_damping(&mut self, position_half_life: f32, rotation_half_life: f32) {
+		self.position_half_life = position_half_life;
+		self.rotation_half_life = rotation_half_life;
+	}
+
+	/// Jumps straight to the target, e.g. after a cut.
+	pub fn snap(&mut self, transform: Transform) {
+		self.current = Some(transform);
+	}
+
+	/// Moves towards the target, and returns the smoothed transform. The first call snaps. The scale follows the
+	/// target's rigidly.
+	pub fn update(&mut self, target: Transform, dt: Duration) -> Transform {
+		let dt = duration_secs(dt);
+		let Transform { position, rotation, .. } = self.current.unwrap_or(target);
+
+		let position = position.lerp(target.position, smoothing(self.position_half_life, dt));
+		// q and -q are the same rotation, so take the short way around
+		let target_rotation = if rotation.dot(target.rotation) < 0.0 { -target.rotation } else { target.rotation };
+		let rotation = rotation.nlerp(target_rotation, smoothing(self.rotation_half_life, dt));
+
+		let transform = Transform { position: position, rotation: rotation, scale: target.scale };
+		self.current = Some(transform);
+		transform
+	}
+
+	/// `update`, then moves the camera to the result.
+	pub fn apply(
+		&mut self,
+		camera: &mut Camera,
+		target: Transform,
+		dt: Duration,
+	) -> Result<(), DeviceMemoryAllocError> {
+		let transform = self.update(target, dt);
+		camera.set_transform(transform)
+	}
+}
+
+/// Brief field of view changes, e.g. when sprinting or getting hit, that ease back to the camera's own FOV.
+#[derive(Clone, Debug)]
+pub struct FovKick {
+	kick: f32,
+	recovery_half_life: f32,
+}
+impl FovKick {
+	pub fn new(recovery_half_life: f32) -> Self {
+		Self { kick: 0.0, recovery_half_life: recovery_half_life }
+	}
+
+	/// Adds `degrees` to the horizontal FOV. Negative values zoom in.
+	pub fn kick(&mut self, degrees: f32) {
+		self.kick += degrees;
+	}
+
+	pub fn current(&self) -> f32 {
+		self.kick
+	}
+
+	pub fn update(&mut self, dt: Duration) {
+		self.kick *= 1.0 - smoothing(self.recovery_half_life, duration_secs(dt));
+	}
+
+	/// Sets the camera's projection to `base_fovx` plus the current kick, keeping its other projection parameters.
+	pub fn apply(&self, camera: &mut Camera, base_fovx: f32) -> Result<(), DeviceMemoryAllocError> {
+		camera.set_fovx((base_fovx + self.kick).max(1.0).min(179.0))
+	}
+}
+
+/// Fraction of the remaining distance to cover this frame.
+fn smoothing(half_life: f32, dt: f32) -> f32 {
+	if half_life <= 0.0 { 1.0 } else { 1.0 - 0.5f32.powf(dt / half_life) }
+}
+
+/// 1D Perlin noise in -1..1. Gradients come from `Rng::for_frame`, so shakes replay identically from the same seed.
+fn noise(seed: u64, t: f32) -> f32 {
+	let cell = t.floor();
+	let frac = t - cell;
+	let gradient = |cell: f32| Rng::for_frame(seed, cell as u64).range_f32(-1.0, 1.0);
+
+	let a = gradient(cell) * frac;
+	let b = gradient(cell + 1.0) * (frac - 1.0);
+	let fade = frac * frac * frac * (frac * (frac * 6.0 - 15.0) + 10.0);
+	// the raw range is -0.5..0.5
+	(a + (b - a) * fade) * 2.0
+}
@@ -0,0 +1,171 @@
+use crate::RenderTarget;
+use crate::camera::{ Camera, ProjectionTile };
+use crate::capture::{ Capture, CaptureError, CaptureOptions };
+use crate::compositor::Compositor;
+use crate::texture::TargetTexture;
+use crate::window::Window;
+use cgmath::{ prelude::*, vec3, Quaternion, Rad, Vector3 };
+use futures::{ prelude::*, task::{ Context, Poll } };
+use image::{ RgbaImage, imageops };
+use std::pin::Pin;
+use vulkano::memory::DeviceMemoryAllocError;
+
+/// Freezes the game and flies a camera of its own, for photo modes. Create it with `enter` and give its camera to
+/// batches instead of the game's until `exit`.
+pub struct PhotoMode {
+	camera: Camera,
+	was_paused: bool,
+}
+impl PhotoMode {
+	/// Pauses `window`'s clock and starts a free camera where `camera` is, with the same projection.
+	pub fn enter(window: &mut Window, camera: &Camera) -> Result<Self, DeviceMemoryAllocError> {
+		let was_paused = window.clock().is_paused();
+		window.clock_mut().set_paused(true);
+
+		let mut free_camera =
+			Camera::new(
+				window,
+				camera.transform(),
+				camera.aspect(),
+				camera.fovx(),
+				camera.znear(),
+				camera.zfar(),
+			)?;
+		free_camera.set_id(camera.id());
+
+		Ok(Self { camera: free_camera, was_paused: was_paused })
+	}
+
+	/// Resumes the clock, unless it was already paused before `enter`.
+	pub fn exit(self, window: &mut Window) {
+		window.clock_mut().set_paused(self.was_paused);
+	}
+
+	pub fn camera(&self) -> &Camera {
+		&self.camera
+	}
+
+	pub fn camera_mut(&mut self) -> &mut Camera {
+		&mut self.camera
+	}
+
+	/// Moves the camera by `offset` in camera space, so -z is forward.
+	pub fn fly(&mut self, offset: Vector3<f32>) -> Result<(), DeviceMemoryAllocError> {
+		let position = self.camera.position() + self.camera.rotation().rotate_vector(offset);
+		self.camera.set_position(position)
+	}
+
+	/// Turns `yaw` around the world's up axis and `pitch` around the camera's own x axis, so the horizon stays level.
+	pub fn look(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) -> Result<(), DeviceMemoryAllocError> {
+		let rotation =
+			Quaternion::from_axis_angle(vec3(0.0, -1.0, 0.0), yaw)
+				* self.camera.rotation()
+				* Quaternion::from_axis_angle(Vector3::unit_x(), pitch);
+		self.camera.set_rotation(rotation.normalize())
+	}
+
+	/// Renders the view at `scale` times the window's size in each direction, as `scale` by `scale` window sized
+	/// tiles stitched together, so the result can be far larger than any image the device supports. `build` is called
+	/// once per tile, like the `build` of `Window::capture`, and gets the camera narrowed to that tile. Each tile is
+	/// submitted after the one before it, and batches start each tile with fresh history, so temporal effects don't
+	/// smear one tile into the next.
+	///
+	/// Screen space effects like bloom only see their own tile, so they may show seams at the edges of tiles.
+	pub fn capture<F>(
+		&mut self,
+		window: &mut Window,
+		scale: u32,
+		options: &CaptureOptions,
+		mut build: F,
+	) -> Result<TiledCapture, CaptureError>
+	where
+		F: FnMut(&mut Window, &TargetTexture, &Camera, &mut Compositor) -> Result<(), DeviceMemoryAllocError>
+	{
+		let scale = scale.max(1);
+		let tile_dimensions = options.dimensions_or(window.images()[0].dimensions().width_height());
+		let options = options.clone().dimensions(tile_dimensions);
+
+		let mut tiles = vec![];
+		let mut result = Ok(());
+		'tiles: for row in 0..scale {
+			for column in 0..scale {
+				let tile = ProjectionTile { grid: [scale, scale], cell: [column, row] };
+				if let Err(err) = self.camera.set_tile(Some(tile)) {
+					result = Err(err.into());
+					break 'tiles;
+				}
+
+				let camera = &self.camera;
+				match window.capture(&options, |window, target, compositor| build(window, target, camera, compositor)) {
+					Ok(capture) => tiles.push(Tile { cell: [column, row], capture: capture, image: None }),
+					Err(err) => {
+						result = Err(err);
+						break 'tiles;
+					},
+				}
+			}
+		}
+		// the camera goes back to the whole view even if a tile failed
+		self.camera.set_tile(None)?;
+		result?;
+
+		Ok(TiledCapture { tiles: tiles, tile_dimensions: tile_dimensions, scale: scale })
+	}
+}
+
+/// The tiles of a `PhotoMode::capture`, stitched together once the GPU finishes all of them.
+pub struct TiledCapture {
+	tiles: Vec<Tile>,
+	tile_dimensions: [u32; 2],
+	scale: u32,
+}
+impl TiledCapture {
+	pub fn dimensions(&self) -> [u32; 2] {
+		[self.tile_dimensions[0] * self.scale, self.tile_dimensions[1] * self.scale]
+	}
+
+	/// Takes the stitched image if every tile is done, without blocking. Returns `None` again after it is taken.
+	pub fn try_get(&mut self) -> Option<Result<RgbaImage, CaptureError>> {
+		if self.tiles.is_empty() {
+			return None;
+		}
+
+		for tile in self.tiles.iter_mut().filter(|tile| tile.image.is_none()) {
+			match tile.capture.try_get()? {
+				Ok(image) => tile.image = Some(image),
+				Err(err) => {
+					self.tiles.clear();
+					return Some(Err(err));
+				},
+			}
+		}
+
+		let [width, height] = self.dimensions();
+		let mut stitched = RgbaImage::new(width, height);
+		for tile in self.tiles.drain(..) {
+			let x = tile.cell[0] * self.tile_dimensions[0];
+			let y = tile.cell[1] * self.tile_dimensions[1];
+			imageops::replace(&mut stitched, &tile.image.unwrap(), x, y);
+		}
+		Some(Ok(stitched))
+	}
+}
+impl Future for TiledCapture {
+	type Output = Result<RgbaImage, CaptureError>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		match self.try_get() {
+			Some(result) => Poll::Ready(result),
+			None => {
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			},
+		}
+	}
+}
+
+struct Tile {
+	cell: [u32; 2],
+	capture: Capture,
+	image: Option<RgbaImage>,
+}
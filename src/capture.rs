@@ -0,0 +1,192 @@
+use crate::RenderTarget;
+use crate::compositor::Layer;
+use crate::device::DeviceCtx;
+use crate::texture::{ TargetTexture, Texture, ViewImage };
+use futures::{ prelude::*, task::{ Context, Poll } };
+use image::RgbaImage;
+use std::{ pin::Pin, sync::Arc };
+use vulkano::{
+	OomError,
+	buffer::{ BufferUsage, CpuAccessibleBuffer, cpu_access::ReadLockError },
+	command_buffer::{ AutoCommandBuffer, AutoCommandBufferBuilder, BuildError },
+	format::Format,
+	memory::DeviceMemoryAllocError,
+	sync::{ AccessError, FenceSignalFuture, FlushError, GpuFuture },
+};
+
+/// What `Window::capture` draws.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CaptureOptions {
+	excluded: Vec<Layer>,
+	dimensions: Option<[u32; 2]>,
+}
+impl CaptureOptions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Leaves out every stage added on `layer`, e.g. `Layer::HUD` for screenshots without the UI.
+	pub fn exclude(mut self, layer: Layer) -> Self {
+		if !self.excluded.contains(&layer) {
+			self.excluded.push(layer);
+		}
+		self
+	}
+
+	/// Size of the captured image. Defaults to the window's size; larger sizes give high resolution screenshots, as
+	/// long as every stage draws to the target it's given rather than to window sized images of its own.
+	pub fn dimensions(mut self, dimensions: [u32; 2]) -> Self {
+		self.dimensions = Some(dimensions);
+		self
+	}
+
+	pub fn excluded(&self) -> &[Layer] {
+		&self.excluded
+	}
+
+	pub(crate) fn dimensions_or(&self, default: [u32; 2]) -> [u32; 2] {
+		self.dimensions.unwrap_or(default)
+	}
+}
+
+/// A frame rendered by `Window::capture`, read back once the GPU finishes it. Poll it as a future, or check it once a
+/// frame with `try_get`; the game keeps presenting meanwhile.
+pub struct Capture {
+	/// Shared with the window, whose next frame waits on the capture.
+	future: Option<Arc<FenceSignalFuture<Box<GpuFuture>>>>,
+	buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+	dimensions: [u32; 2],
+	format: Format,
+}
+impl Capture {
+	/// The future this capture resolves with, for the window to chain its next frame after.
+	pub(crate) fn frame_future(&self) -> Option<Box<GpuFuture>> {
+		self.future.clone().map(|future| Box::new(future) as Box<GpuFuture>)
+	}
+
+	pub fn dimensions(&self) -> [u32; 2] {
+		self.dimensions
+	}
+
+	/// Takes the image if the GPU has finished it, without blocking. Returns `None` again after the image is taken.
+	pub fn try_get(&mut self) -> Option<Result<RgbaImage, CaptureError>> {
+		let ready =
+			match self.future.as_ref()?.wait(Some(Default::default())) {
+				Ok(()) => Ok(()),
+				Err(FlushError::Timeout) => return None,
+				Err(err) => Err(err.into()),
+			};
+		self.future = None;
+		Some(ready.and_then(|()| self.image()))
+	}
+
+	fn image(&self) -> Result<RgbaImage, CaptureError> {
+		let mut pixels = self.buffer.read()?.to_vec();
+		match self.format {
+			Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => {
+				for pixel in pixels.chunks_mut(4) {
+					pixel.swap(0, 2);
+				}
+			},
+			_ => (),
+		}
+		Ok(RgbaImage::from_raw(self.dimensions[0], self.dimensions[1], pixels).unwrap())
+	}
+}
+impl Future for Capture {
+	type Output = Result<RgbaImage, CaptureError>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		match self.try_get() {
+			Some(result) => Poll::Ready(result),
+			None => {
+				// there's nothing to wake on when the fence signals, so ask to be polled again
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			},
+		}
+	}
+}
+
+/// The copy of a target to host memory, made before the frame it reads is submitted so that failing to make it
+/// doesn't lose the frame's future.
+pub(crate) struct ReadBack {
+	commands: AutoCommandBuffer,
+	buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+	dimensions: [u32; 2],
+	format: Format,
+}
+impl ReadBack {
+	pub(crate) fn new(device: &DeviceCtx, target: &TargetTexture) -> Result<Self, CaptureError> {
+		let format = target.format();
+		if format.size() != Some(4) {
+			return Err(CaptureError::UnsupportedFormat(format));
+		}
+
+		let [width, height] = target.image().dimensions().width_height();
+		let len = width as usize * height as usize * 4;
+		let buffer =
+			CpuAccessibleBuffer::from_iter(
+				device.device().clone(),
+				BufferUsage::transfer_destination(),
+				(0..len).map(|_| 0u8),
+			)?;
+		let commands =
+			AutoCommandBufferBuilder::primary_one_time_submit(device.device().clone(), device.queue().family())?
+				.copy_image_to_buffer(ViewImage(target.image().clone()), buffer.clone())
+				.unwrap()
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+
+		Ok(Self { commands: commands, buffer: buffer, dimensions: [width, height], format: format })
+	}
+
+	/// Submits the copy after `future`.
+	pub(crate) fn submit(self, device: &DeviceCtx, future: Box<GpuFuture>) -> Result<Capture, CaptureError> {
+		let future: Box<GpuFuture> = Box::new(future.then_execute(device.queue().clone(), self.commands).unwrap());
+
+		Ok(Capture {
+			future: Some(Arc::new(future.then_signal_fence_and_flush()?)),
+			buffer: self.buffer,
+			dimensions: self.dimensions,
+			format: self.format,
+		})
+	}
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+	/// Only targets with 4 byte pixels can be read back.
+	UnsupportedFormat(Format),
+	DeviceLost,
+	/// A stage's commands used a resource in a way that conflicts with another submission.
+	AccessError(AccessError),
+	/// The captured image's buffer was still locked when it was read, e.g. by a stage that wrote to it.
+	BufferLocked(ReadLockError),
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl From<DeviceMemoryAllocError> for CaptureError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		CaptureError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<OomError> for CaptureError {
+	fn from(val: OomError) -> Self {
+		CaptureError::DeviceMemoryAllocError(val.into())
+	}
+}
+impl From<FlushError> for CaptureError {
+	fn from(val: FlushError) -> Self {
+		match val {
+			FlushError::OomError(err) => err.into(),
+			FlushError::DeviceLost => CaptureError::DeviceLost,
+			FlushError::AccessError(err) => CaptureError::AccessError(err),
+			err => unreachable!("{}", err),
+		}
+	}
+}
+impl From<ReadLockError> for CaptureError {
+	fn from(val: ReadLockError) -> Self {
+		CaptureError::BufferLocked(val)
+	}
+}
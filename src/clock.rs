@@ -0,0 +1,327 @@
+use crate::window::Monitor;
+use std::{ thread, time::{ Duration, Instant } };
+
+/// How long before a deadline `FramePacer` stops sleeping and spins instead, since sleeps overshoot by up to a
+/// scheduler tick.
+const PACER_SPIN: Duration = Duration::from_millis(2);
+/// Lowest target `FramePacer` paces to, so a zero, negative or NaN target from a settings file doesn't stall a frame
+/// forever.
+const MIN_TARGET_FPS: f64 = 1.0;
+
+/// Frame timing for engine-driven animation. Each window has one, ticked at the start of `Window::present`, and its
+/// `delta` is what tweens, trails, videos and camera effects should be updated with, so `set_time_scale` and `set_paused`
+/// apply to all of them alike.
+///
+/// It also accumulates time for fixed-step simulation: drain `fixed_steps` after each tick, then interpolate rendering
+/// with `fixed_alpha`.
+#[derive(Clone, Debug)]
+pub struct Clock {
+	last_tick: Option<Instant>,
+	time: Duration,
+	real_time: Duration,
+	delta: Duration,
+	real_delta: Duration,
+	time_scale: f32,
+	paused: bool,
+	max_delta: Duration,
+	fixed_step: Duration,
+	max_fixed_steps: u32,
+	accumulator: Duration,
+}
+impl Clock {
+	pub fn new() -> Self {
+		Self {
+			last_tick: None,
+			time: Duration::default(),
+			real_time: Duration::default(),
+			delta: Duration::default(),
+			real_delta: Duration::default(),
+			time_scale: 1.0,
+			paused: false,
+			max_delta: Duration::from_millis(250),
+			fixed_step: Duration::from_nanos(1_000_000_000 / 60),
+			max_fixed_steps: 8,
+			accumulator: Duration::default(),
+		}
+	}
+
+	/// Measures the wall time since the last tick and advances by it. The first tick advances by nothing.
+	pub fn tick(&mut self) -> Duration {
+		let now = Instant::now();
+		let real_delta = self.last_tick.map_or(Duration::default(), |last_tick| now.duration_since(last_tick));
+		self.last_tick = Some(now);
+		self.advance(real_delta)
+	}
+
+	/// Advances by `real_delta` instead of measuring it, e.g. for replays and offline rendering. Returns the scaled
+	/// delta.
+	pub fn advance(&mut self, real_delta: Duration) -> Duration {
+		// a breakpoint or a stalled frame shouldn't teleport everything
+		let real_delta = real_delta.min(self.max_delta);
+		self.real_delta = real_delta;
+		self.real_time += real_delta;
+
+		self.delta = if self.paused { Duration::default() } else { scale(real_delta, self.time_scale) };
+		self.time += self.delta;
+		self.accumulator += self.delta;
+		self.delta
+	}
+
+	/// Scaled time since the last tick. Zero while paused.
+	pub fn delta(&self) -> Duration {
+		self.delta
+	}
+
+	/// `delta` in seconds.
+	pub fn delta_secs(&self) -> f32 {
+		duration_secs(self.delta)
+	}
+
+	/// Unscaled time since the last tick, for things that shouldn't slow down, like menus over a paused game.
+	pub fn real_delta(&self) -> Duration {
+		self.real_delta
+	}
+
+	/// Scaled time since the clock was created, not counting pauses.
+	pub fn time(&self) -> Duration {
+		self.time
+	}
+
+	pub fn real_time(&self) -> Duration {
+		self.real_time
+	}
+
+	pub fn time_scale(&self) -> f32 {
+		self.time_scale
+	}
+
+	/// Multiplies the speed of scaled time, e.g. 0.25 for slow motion. Defaults to 1.
+	pub fn set_time_scale(&mut self, time_scale: f32) {
+		self.time_scale = time_scale.max(0.0);
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	/// Stops scaled time, without affecting `real_delta`.
+	pub fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+	}
+
+	/// Longest delta a single tick can advance by. Defaults to 250ms.
+	pub fn set_max_delta(&mut self, max_delta: Duration) {
+		self.max_delta = max_delta;
+	}
+
+	pub fn fixed_step(&self) -> Duration {
+		self.fixed_step
+	}
+
+	/// Length of each step returned by `fixed_steps`, in scaled time. Defaults to 1/60th of a second.
+	pub fn set_fixed_step(&mut self, fixed_step: Duration) {
+		assert!(fixed_step > Duration::default(), "fixed step must be longer than zero");
+		self.fixed_step = fixed_step;
+	}
+
+	/// Most steps `fixed_steps` returns at once. If the simulation can't keep up, the rest of the time is dropped
+	/// instead of piling up. Defaults to 8.
+	pub fn set_max_fixed_steps(&mut self, max_fixed_steps: u32) {
+		self.max_fixed_steps = max_fixed_steps;
+	}
+
+	/// How many fixed steps to simulate for the time accumulated so far, consuming it.
+	pub fn fixed_steps(&mut self) -> u32 {
+		let mut steps = 0;
+		while self.accumulator >= self.fixed_step {
+			self.accumulator -= self.fixed_step;
+			steps += 1;
+			if steps == self.max_fixed_steps {
+				while self.accumulator >= self.fixed_step {
+					self.accumulator -= self.fixed_step;
+				}
+				break;
+			}
+		}
+		steps
+	}
+
+	/// How far between the last fixed step and the next one the current time is, from 0 to 1.
+	pub fn fixed_alpha(&self) -> f32 {
+		(duration_secs(self.accumulator) / duration_secs(self.fixed_step)).min(1.0)
+	}
+}
+
+/// Waits between frames so they start a fixed interval apart, for games that shouldn't rely on `PresentMode::Fifo`
+/// blocking for their timing, e.g. with vsync off or on a monitor faster than the simulation is tuned for. Give it to
+/// `Window::set_frame_pacer` to wait at the start of each `present`, or call `wait` yourself.
+///
+/// It sleeps until shortly before each deadline and spins for the rest, so frames land within a few microseconds of
+/// it. A frame that runs late moves the following deadlines instead of being made up with shorter frames.
+#[derive(Clone, Debug)]
+pub struct FramePacer {
+	interval: Duration,
+	next: Option<Instant>,
+}
+impl FramePacer {
+	/// Targets below 1 FPS, including zero and NaN, are paced at 1 FPS.
+	pub fn new(fps: f64) -> Self {
+		Self { interval: fps_interval(fps), next: None }
+	}
+
+	/// Paces to `monitor`'s refresh rate, or 60 FPS where it isn't known, like on Wayland.
+	pub fn for_monitor(monitor: &Monitor) -> Self {
+		Self::new(monitor.refresh_rate().unwrap_or(60.0))
+	}
+
+	pub fn interval(&self) -> Duration {
+		self.interval
+	}
+
+	/// Takes effect from the next frame. Clamped like `new`.
+	pub fn set_target_fps(&mut self, fps: f64) {
+		self.interval = fps_interval(fps);
+	}
+
+	/// Waits until the current frame's deadline, then starts the next frame. The first call returns immediately.
+	pub fn wait(&mut self) {
+		let now = Instant::now();
+		let deadline = match self.next {
+			Some(next) if next > now => next,
+			_ => {
+				self.next = Some(now + self.interval);
+				return;
+			},
+		};
+
+		if let Some(sleep) = (deadline - now).checked_sub(PACER_SPIN) {
+			thread::sleep(sleep);
+		}
+		while Instant::now() < deadline {
+			thread::yield_now();
+		}
+		self.next = Some(deadline + self.interval);
+	}
+
+	/// Forgets the deadline, e.g. after a loading screen, so the next `wait` doesn't block.
+	pub fn reset(&mut self) {
+		self.next = None;
+	}
+}
+
+fn fps_interval(fps: f64) -> Duration {
+	let fps = fps.max(MIN_TARGET_FPS);
+	Duration::from_nanos((1e9 / fps) as u64)
+}
+
+fn scale(duration: Duration, scale: f32) -> Duration {
+	let nanos = (duration.as_secs() as f64 * 1e9 + duration.subsec_nanos() as f64) * scale as f64;
+	Duration::from_nanos(nanos as u64)
+}
+
+/// `duration` as fractional seconds, for the `f32` times the crate's animations and effects keep.
+pub(crate) fn duration_secs(duration: Duration) -> f32 {
+	duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{ f64, thread, time::{ Duration, Instant } };
+	use super::{ Clock, FramePacer };
+
+	fn ms(ms: u64) -> Duration {
+		Duration::from_millis(ms)
+	}
+
+	fn clock() -> Clock {
+		let mut clock = Clock::new();
+		clock.set_fixed_step(ms(10));
+		clock
+	}
+
+	#[test]
+	fn fixed_steps_accumulate_leftover_time() {
+		let mut clock = clock();
+		clock.advance(ms(25));
+		assert_eq!(clock.fixed_steps(), 2);
+		assert!((clock.fixed_alpha() - 0.5).abs() < 1e-4);
+
+		// the leftover 5ms and these 5ms make one more step
+		clock.advance(ms(5));
+		assert_eq!(clock.fixed_steps(), 1);
+		assert_eq!(clock.fixed_alpha(), 0.0);
+		assert_eq!(clock.fixed_steps(), 0);
+	}
+
+	#[test]
+	fn fixed_steps_drop_time_past_the_limit() {
+		let mut clock = clock();
+		clock.set_max_fixed_steps(3);
+		clock.advance(ms(95));
+		assert_eq!(clock.fixed_steps(), 3);
+		// only what's left of a step is kept
+		assert!((clock.fixed_alpha() - 0.5).abs() < 1e-4);
+	}
+
+	#[test]
+	fn long_frames_are_clamped() {
+		let mut clock = clock();
+		clock.set_max_delta(ms(50));
+		assert_eq!(clock.advance(ms(1000)), ms(50));
+		assert_eq!(clock.real_delta(), ms(50));
+		assert_eq!(clock.fixed_steps(), 5);
+	}
+
+	#[test]
+	fn pausing_and_scaling_only_affect_scaled_time() {
+		let mut clock = clock();
+		clock.set_time_scale(0.5);
+		assert_eq!(clock.advance(ms(40)), ms(20));
+		assert_eq!(clock.real_delta(), ms(40));
+		assert_eq!(clock.fixed_steps(), 2);
+
+		clock.set_paused(true);
+		assert_eq!(clock.advance(ms(40)), Duration::default());
+		assert_eq!(clock.real_delta(), ms(40));
+		assert_eq!(clock.fixed_steps(), 0);
+		assert_eq!(clock.time(), ms(20));
+		assert_eq!(clock.real_time(), ms(80));
+	}
+
+	#[test]
+	fn pacer_clamps_bad_targets() {
+		for &fps in &[0.0, -60.0, f64::NAN] {
+			assert_eq!(FramePacer::new(fps).interval(), Duration::from_secs(1));
+		}
+		assert_eq!(FramePacer::new(100.0).interval(), ms(10));
+	}
+
+	#[test]
+	fn pacer_spaces_frames_by_the_interval() {
+		let mut pacer = FramePacer::new(100.0);
+		let start = Instant::now();
+		pacer.wait();
+		assert!(start.elapsed() < ms(5), "the first wait blocked");
+
+		for _ in 0..5 {
+			pacer.wait();
+		}
+		let elapsed = start.elapsed();
+		// generous upper bound, for loaded CI machines
+		assert!(elapsed >= ms(50) && elapsed < ms(250), "5 frames took {:?}", elapsed);
+	}
+
+	#[test]
+	fn pacer_doesnt_make_up_late_frames() {
+		let mut pacer = FramePacer::new(50.0);
+		pacer.wait();
+		thread::sleep(ms(70));
+
+		// the late frame moves the deadline instead of the next few frames being shorter
+		let start = Instant::now();
+		pacer.wait();
+		assert!(start.elapsed() < ms(5), "waited after a late frame");
+		pacer.wait();
+		assert!(start.elapsed() >= ms(20), "next frame was short: {:?}", start.elapsed());
+	}
+}
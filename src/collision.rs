@@ -0,0 +1,411 @@
+mod character;
+
+pub use self::character::{ CharacterController, CharacterControllerConfig };
+
+#[cfg(feature = "mesh")]
+use crate::batch::mesh::Mesh;
+use crate::geometry::{ Aabb, Bvh, MeshGeometry, RayHit, Triangle };
+use crate::math::Transform;
+use cgmath::{ prelude::*, Quaternion, Vector3 };
+use std::sync::Arc;
+
+const SWEEP_ITERATIONS: usize = 32;
+const SWEEP_EPSILON: f32 = 0.0001;
+
+/// Collision shape built from the triangles of a visual mesh. Only supports queries; there's no simulation.
+///
+/// Colliders ignore the scale of their transform, since spheres and capsules don't stay round in a non-uniformly
+/// scaled space. Scale the geometry itself instead.
+#[derive(Clone)]
+pub struct MeshCollider {
+	bvh: Arc<Bvh>,
+	position: Vector3<f32>,
+	rotation: Quaternion<f32>,
+	inv_rotation: Quaternion<f32>,
+}
+impl MeshCollider {
+	pub fn new(geometry: &MeshGeometry, transform: Transform) -> Self {
+		Self {
+			bvh: Arc::new(Bvh::new(geometry)),
+			position: transform.position,
+			rotation: transform.rotation,
+			inv_rotation: transform.rotation.invert(),
+		}
+	}
+
+	/// Places the collider where `mesh` is.
+	#[cfg(feature = "mesh")]
+	pub fn from_mesh(mesh: &Mesh) -> Self {
+		Self::new(mesh.geometry(), mesh.transform())
+	}
+
+	/// Moves the collider. This is cheap; the BVH is built in object space and shared between clones.
+	pub fn set_transform(&mut self, transform: Transform) {
+		self.position = transform.position;
+		self.rotation = transform.rotation;
+		self.inv_rotation = transform.rotation.invert();
+	}
+
+	/// The scale is always one.
+	pub fn transform(&self) -> Transform {
+		Transform::new(self.position, self.rotation)
+	}
+
+	pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_distance: f32) -> Option<RayHit> {
+		self.bvh
+			.raycast(self.to_local(origin), self.inv_rotation.rotate_vector(dir.normalize()), max_distance)
+			.map(|hit| RayHit { normal: self.rotation.rotate_vector(hit.normal), ..hit })
+	}
+
+	/// Returns the deepest contact of a sphere with the mesh, if they overlap.
+	pub fn overlap_sphere(&self, center: Vector3<f32>, radius: f32) -> Option<Contact> {
+		self.overlap_capsule(center, center, radius)
+	}
+
+	/// Returns the deepest contact of a capsule (segment `a`-`b` inflated by `radius`) with the mesh, if they overlap.
+	pub fn overlap_capsule(&self, a: Vector3<f32>, b: Vector3<f32>, radius: f32) -> Option<Contact> {
+		self.capsule_contacts(a, b, radius)
+			.into_iter()
+			.fold(None, |deepest: Option<Contact>, contact| match deepest {
+				Some(deepest) if deepest.depth >= contact.depth => Some(deepest),
+				_ => Some(contact),
+			})
+	}
+
+	pub fn sphere_contacts(&self, center: Vector3<f32>, radius: f32) -> Vec<Contact> {
+		self.capsule_contacts(center, center, radius)
+	}
+
+	/// Returns one contact per overlapping triangle, in world space.
+	pub fn capsule_contacts(&self, a: Vector3<f32>, b: Vector3<f32>, radius: f32) -> Vec<Contact> {
+		let (a, b) = (self.to_local(a), self.to_local(b));
+		let bounds = Aabb::empty().grow(a).grow(b).expand(radius);
+
+		let mut contacts = vec![];
+		self.bvh.query_aabb(&bounds, &mut |_, tri| {
+			if let Some(contact) = capsule_triangle_contact(a, b, radius, tri) {
+				contacts.push(Contact {
+					point: self.to_world(contact.point),
+					normal: self.rotation.rotate_vector(contact.normal),
+					depth: contact.depth,
+				});
+			}
+		});
+		contacts
+	}
+
+	/// Moves a sphere along `motion` and returns the first time of impact, in `[0, 1]`.
+	pub fn sweep_sphere(&self, center: Vector3<f32>, radius: f32, motion: Vector3<f32>) -> Option<SweepHit> {
+		self.sweep_capsule(center, center, radius, motion)
+	}
+
+	/// Moves a capsule along `motion` and returns the first time of impact, in `[0, 1]`. Uses conservative
+	/// advancement, so thin features are never tunneled through but the reported time may be slightly early.
+	pub fn sweep_capsule(
+		&self,
+		a: Vector3<f32>,
+		b: Vector3<f32>,
+		radius: f32,
+		motion: Vector3<f32>,
+	) -> Option<SweepHit> {
+		let (a, b) = (self.to_local(a), self.to_local(b));
+		let motion = self.inv_rotation.rotate_vector(motion);
+		let length = motion.magnitude();
+
+		let bounds = Aabb::empty().grow(a).grow(b).grow(a + motion).grow(b + motion).expand(radius);
+		let mut candidates = vec![];
+		self.bvh.query_aabb(&bounds, &mut |_, tri| candidates.push(*tri));
+		if candidates.is_empty() {
+			return None;
+		}
+
+		let mut time = 0.0;
+		for _ in 0..SWEEP_ITERATIONS {
+			let offset = motion * time;
+			let (seg_point, tri_point) =
+				candidates.iter()
+					.map(|tri| segment_triangle_closest(a + offset, b + offset, tri))
+					.min_by(|x, y| {
+						(x.0 - x.1).magnitude2().partial_cmp(&(y.0 - y.1).magnitude2()).unwrap()
+					})
+					.unwrap();
+
+			let distance = (seg_point - tri_point).magnitude() - radius;
+			if distance <= SWEEP_EPSILON {
+				let normal = contact_normal(seg_point, tri_point, &candidates);
+				return Some(SweepHit {
+					time: time,
+					point: self.to_world(tri_point),
+					normal: self.rotation.rotate_vector(normal),
+				});
+			}
+
+			if length == 0.0 {
+				return None;
+			}
+
+			time += distance / length;
+			if time > 1.0 {
+				return None;
+			}
+		}
+
+		None
+	}
+
+	fn to_local(&self, point: Vector3<f32>) -> Vector3<f32> {
+		self.inv_rotation.rotate_vector(point - self.position)
+	}
+
+	fn to_world(&self, point: Vector3<f32>) -> Vector3<f32> {
+		self.rotation.rotate_vector(point) + self.position
+	}
+}
+
+/// A set of colliders queried together, e.g. all static level geometry.
+#[derive(Clone, Default)]
+pub struct CollisionWorld {
+	colliders: Vec<MeshCollider>,
+}
+impl CollisionWorld {
+	pub fn new() -> Self {
+		Self { colliders: vec![] }
+	}
+
+	pub fn add_collider(&mut self, collider: MeshCollider) -> usize {
+		self.colliders.push(collider);
+		self.colliders.len() - 1
+	}
+
+	pub fn collider(&self, index: usize) -> &MeshCollider {
+		&self.colliders[index]
+	}
+
+	pub fn collider_mut(&mut self, index: usize) -> &mut MeshCollider {
+		&mut self.colliders[index]
+	}
+
+	pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_distance: f32) -> Option<RayHit> {
+		self.colliders.iter()
+			.filter_map(|collider| collider.raycast(origin, dir, max_distance))
+			.min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+	}
+
+	pub fn capsule_contacts(&self, a: Vector3<f32>, b: Vector3<f32>, radius: f32) -> Vec<Contact> {
+		self.colliders.iter().flat_map(|collider| collider.capsule_contacts(a, b, radius)).collect()
+	}
+
+	pub fn sweep_capsule(
+		&self,
+		a: Vector3<f32>,
+		b: Vector3<f32>,
+		radius: f32,
+		motion: Vector3<f32>,
+	) -> Option<SweepHit> {
+		self.colliders.iter()
+			.filter_map(|collider| collider.sweep_capsule(a, b, radius, motion))
+			.min_by(|a, b| a.time.partial_cmp(&b.time).unwrap())
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+	/// Point on the mesh surface.
+	pub point: Vector3<f32>,
+	/// Direction to push the shape out of the mesh.
+	pub normal: Vector3<f32>,
+	pub depth: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SweepHit {
+	/// Fraction of the motion that can be applied before touching the mesh.
+	pub time: f32,
+	pub point: Vector3<f32>,
+	pub normal: Vector3<f32>,
+}
+
+fn capsule_triangle_contact(a: Vector3<f32>, b: Vector3<f32>, radius: f32, tri: &Triangle) -> Option<Contact> {
+	let (seg_point, tri_point) = segment_triangle_closest(a, b, tri);
+	let distance = (seg_point - tri_point).magnitude();
+	if distance >= radius {
+		return None;
+	}
+
+	let normal = if distance > 0.0 { (seg_point - tri_point) / distance } else { tri.normal() };
+	Some(Contact { point: tri_point, normal: normal, depth: radius - distance })
+}
+
+fn contact_normal(seg_point: Vector3<f32>, tri_point: Vector3<f32>, candidates: &[Triangle]) -> Vector3<f32> {
+	let delta = seg_point - tri_point;
+	if delta.magnitude2() > 0.0 {
+		delta.normalize()
+	} else {
+		candidates.iter()
+			.find(|tri| (tri.closest_point(tri_point) - tri_point).magnitude2() == 0.0)
+			.map_or(Vector3::zero(), |tri| tri.normal())
+	}
+}
+
+/// Closest points between segment `p0`-`p1` and a triangle, as (point on segment, point on triangle).
+fn segment_triangle_closest(p0: Vector3<f32>, p1: Vector3<f32>, tri: &Triangle) -> (Vector3<f32>, Vector3<f32>) {
+	let dir = p1 - p0;
+	if dir.magnitude2() > 0.0 {
+		if let Some(t) = tri.intersect_ray(p0, dir, 1.0) {
+			let point = p0 + dir * t;
+			return (point, point);
+		}
+	}
+
+	let mut best = (p0, tri.closest_point(p0));
+	let mut consider = |candidate: (Vector3<f32>, Vector3<f32>)| {
+		if (candidate.0 - candidate.1).magnitude2() < (best.0 - best.1).magnitude2() {
+			best = candidate;
+		}
+	};
+
+	consider((p1, tri.closest_point(p1)));
+	consider(segment_segment_closest(p0, p1, tri.a, tri.b));
+	consider(segment_segment_closest(p0, p1, tri.b, tri.c));
+	consider(segment_segment_closest(p0, p1, tri.c, tri.a));
+
+	best
+}
+
+/// Closest points between two segments (Ericson, Real-Time Collision Detection 5.1.9).
+fn segment_segment_closest(
+	p1: Vector3<f32>,
+	q1: Vector3<f32>,
+	p2: Vector3<f32>,
+	q2: Vector3<f32>,
+) -> (Vector3<f32>, Vector3<f32>) {
+	let d1 = q1 - p1;
+	let d2 = q2 - p2;
+	let r = p1 - p2;
+	let a = d1.magnitude2();
+	let e = d2.magnitude2();
+	let f = d2.dot(r);
+
+	let (s, t) =
+		if a <= SWEEP_EPSILON && e <= SWEEP_EPSILON {
+			(0.0, 0.0)
+		} else if a <= SWEEP_EPSILON {
+			(0.0, clamp01(f / e))
+		} else {
+			let c = d1.dot(r);
+			if e <= SWEEP_EPSILON {
+				(clamp01(-c / a), 0.0)
+			} else {
+				let b = d1.dot(d2);
+				let denom = a * e - b * b;
+				let s = if denom != 0.0 { clamp01((b * f - c * e) / denom) } else { 0.0 };
+				let t = (b * s + f) / e;
+				if t < 0.0 {
+					(clamp01(-c / a), 0.0)
+				} else if t > 1.0 {
+					(clamp01((b - c) / a), 1.0)
+				} else {
+					(s, t)
+				}
+			}
+		};
+
+	(p1 + d1 * s, p2 + d2 * t)
+}
+
+fn clamp01(val: f32) -> f32 {
+	val.max(0.0).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::geometry::MeshGeometry;
+	use crate::math::Transform;
+	use cgmath::{ prelude::*, vec3, Quaternion, Rad, Vector3 };
+	use super::MeshCollider;
+
+	/// Two by two floor in xz at the origin, facing +Y.
+	fn floor(transform: Transform) -> MeshCollider {
+		let mut geometry = MeshGeometry::default();
+		geometry.positions = vec![[0.0, 0.0, 0.0], [2.0, 0.0, 0.0], [2.0, 0.0, 2.0], [0.0, 0.0, 2.0]];
+		geometry.indices = vec![0, 2, 1, 0, 3, 2];
+		MeshCollider::new(&geometry, transform)
+	}
+
+	fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+		assert!((actual - expected).magnitude() < 1e-4, "{:?} isn't {:?}", actual, expected);
+	}
+
+	#[test]
+	fn raycast_hits_and_misses() {
+		let collider = floor(Transform::identity());
+		let hit = collider.raycast(vec3(1.0, 5.0, 1.5), vec3(0.0, -2.0, 0.0), 10.0).unwrap();
+		assert!((hit.distance - 5.0).abs() < 1e-4);
+		assert_near(hit.normal, Vector3::unit_y());
+
+		assert!(collider.raycast(vec3(2.5, 5.0, 1.0), vec3(0.0, -1.0, 0.0), 10.0).is_none());
+		assert!(collider.raycast(vec3(1.0, 5.0, 1.0), vec3(0.0, 1.0, 0.0), 10.0).is_none());
+		assert!(collider.raycast(vec3(1.0, 5.0, 1.0), vec3(0.0, -1.0, 0.0), 4.0).is_none());
+	}
+
+	#[test]
+	fn raycast_touching_an_edge_hits() {
+		let collider = floor(Transform::identity());
+		for &(x, z) in &[(2.0, 1.0), (0.0, 1.0), (1.0, 0.0), (1.0, 2.0), (1.0, 1.0), (2.0, 2.0)] {
+			let hit = collider.raycast(vec3(x, 1.0, z), vec3(0.0, -1.0, 0.0), 10.0);
+			assert!(hit.is_some(), "missed the edge at ({}, {})", x, z);
+		}
+	}
+
+	#[test]
+	fn raycast_follows_the_transform() {
+		// stood upright, the floor becomes a wall in xy
+		let rotation = Quaternion::from_angle_x(Rad(-std::f32::consts::FRAC_PI_2));
+		let collider = floor(Transform::new(vec3(10.0, 0.0, 0.0), rotation));
+		let hit = collider.raycast(vec3(11.0, 1.0, -5.0), vec3(0.0, 0.0, 1.0), 10.0).unwrap();
+		assert!((hit.distance - 5.0).abs() < 1e-4);
+		assert_near(hit.normal.map(f32::abs), Vector3::unit_z());
+		assert!(collider.raycast(vec3(1.0, 1.0, -5.0), vec3(0.0, 0.0, 1.0), 10.0).is_none());
+	}
+
+	#[test]
+	fn sphere_overlaps_and_misses() {
+		let collider = floor(Transform::identity());
+		let contact = collider.overlap_sphere(vec3(1.0, 0.25, 1.0), 1.0).unwrap();
+		assert_near(contact.point, vec3(1.0, 0.0, 1.0));
+		assert_near(contact.normal, Vector3::unit_y());
+		assert!((contact.depth - 0.75).abs() < 1e-4);
+
+		assert!(collider.overlap_sphere(vec3(1.0, 2.0, 1.0), 1.0).is_none());
+		assert!(collider.overlap_sphere(vec3(5.0, 0.0, 1.0), 1.0).is_none());
+	}
+
+	#[test]
+	fn sphere_past_an_edge_pushes_out_sideways() {
+		let collider = floor(Transform::identity());
+		let contact = collider.overlap_sphere(vec3(2.25, 0.0, 1.0), 0.5).unwrap();
+		assert_near(contact.point, vec3(2.0, 0.0, 1.0));
+		assert_near(contact.normal, Vector3::unit_x());
+		assert!((contact.depth - 0.25).abs() < 1e-4);
+	}
+
+	#[test]
+	fn sphere_exactly_touching_doesnt_overlap() {
+		let collider = floor(Transform::identity());
+		assert!(collider.overlap_sphere(vec3(1.0, 0.5, 1.0), 0.5).is_none());
+		assert!(collider.overlap_sphere(vec3(2.5, 0.0, 1.0), 0.5).is_none());
+	}
+
+	#[test]
+	fn sweep_stops_at_the_surface() {
+		let collider = floor(Transform::identity());
+		let hit = collider.sweep_sphere(vec3(1.0, 3.0, 1.0), 0.5, vec3(0.0, -5.0, 0.0)).unwrap();
+		// conservative advancement reports the time slightly early, never late
+		assert!(hit.time <= 0.5 && hit.time > 0.49, "hit at {}", hit.time);
+		assert_near(hit.point, vec3(1.0, 0.0, 1.0));
+		assert_near(hit.normal, Vector3::unit_y());
+
+		assert!(collider.sweep_sphere(vec3(1.0, 3.0, 1.0), 0.5, vec3(0.0, -2.0, 0.0)).is_none());
+		assert!(collider.sweep_sphere(vec3(5.0, 3.0, 1.0), 0.5, vec3(0.0, -5.0, 0.0)).is_none());
+	}
+}
@@ -0,0 +1,313 @@
+use crate::collision::{ CollisionWorld, Contact };
+use crate::math::Transform;
+use cgmath::{ prelude::*, Quaternion, Rad, vec3, Vector3 };
+use std::f32::consts::PI;
+
+const SLIDE_ITERATIONS: usize = 4;
+const DEPENETRATE_ITERATIONS: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CharacterControllerConfig {
+	pub radius: f32,
+	/// Total height of the capsule, including both caps.
+	pub height: f32,
+	/// Obstacles up to this height are climbed without jumping.
+	pub step_offset: f32,
+	/// Steepest walkable slope, in radians.
+	pub max_slope: f32,
+	/// While grounded, the character is pulled down onto ground up to this far below it, so it doesn't launch off
+	/// of ramps and stairs.
+	pub snap_distance: f32,
+	/// Distance kept between the capsule and geometry, so resting contacts don't count as penetration.
+	pub skin_width: f32,
+	pub gravity: f32,
+	pub jump_speed: f32,
+	pub up: Vector3<f32>,
+}
+impl Default for CharacterControllerConfig {
+	fn default() -> Self {
+		Self {
+			radius: 0.3,
+			height: 1.8,
+			step_offset: 0.3,
+			max_slope: PI / 4.0,
+			snap_distance: 0.3,
+			skin_width: 0.01,
+			gravity: 9.81,
+			jump_speed: 4.5,
+			up: vec3(0.0, -1.0, 0.0),
+		}
+	}
+}
+
+/// Kinematic capsule that walks on a `CollisionWorld`. `position` is the bottom of the capsule.
+#[derive(Clone, Debug)]
+pub struct CharacterController {
+	config: CharacterControllerConfig,
+	position: Vector3<f32>,
+	vertical_speed: f32,
+	grounded: bool,
+	ground_normal: Vector3<f32>,
+	yaw: Rad<f32>,
+}
+impl CharacterController {
+	pub fn new(config: CharacterControllerConfig, position: Vector3<f32>) -> Self {
+		let up = config.up.normalize();
+		Self {
+			config: CharacterControllerConfig { up: up, ..config },
+			position: position,
+			vertical_speed: 0.0,
+			grounded: false,
+			ground_normal: up,
+			yaw: Rad(0.0),
+		}
+	}
+
+	pub fn config(&self) -> &CharacterControllerConfig {
+		&self.config
+	}
+
+	pub fn position(&self) -> Vector3<f32> {
+		self.position
+	}
+
+	/// Teleports the character, without checking for collisions.
+	pub fn set_position(&mut self, position: Vector3<f32>) {
+		self.position = position;
+		self.vertical_speed = 0.0;
+		self.grounded = false;
+	}
+
+	/// Rotation about the up axis, applied to `rotation()`. Doesn't affect collision.
+	pub fn yaw(&self) -> Rad<f32> {
+		self.yaw
+	}
+
+	pub fn set_yaw(&mut self, yaw: Rad<f32>) {
+		self.yaw = yaw;
+	}
+
+	pub fn rotation(&self) -> Quaternion<f32> {
+		Quaternion::from_axis_angle(self.config.up, self.yaw)
+	}
+
+	/// `position()` and `rotation()` together, e.g. for the character's `Mesh`.
+	pub fn transform(&self) -> Transform {
+		Transform::new(self.position, self.rotation())
+	}
+
+	/// Returns the point `height` above `position()`, e.g. for a first person `Camera`.
+	pub fn eye_position(&self, height: f32) -> Vector3<f32> {
+		self.position + self.config.up * height
+	}
+
+	pub fn is_grounded(&self) -> bool {
+		self.grounded
+	}
+
+	pub fn ground_normal(&self) -> Option<Vector3<f32>> {
+		if self.grounded { Some(self.ground_normal) } else { None }
+	}
+
+	/// Speed along the up axis. Positive when moving up.
+	pub fn vertical_speed(&self) -> f32 {
+		self.vertical_speed
+	}
+
+	/// Starts a jump if the character is standing on something. Returns true if it jumped.
+	pub fn jump(&mut self) -> bool {
+		if self.grounded {
+			self.vertical_speed = self.config.jump_speed;
+			self.grounded = false;
+			true
+		} else {
+			false
+		}
+	}
+
+	/// Advances the character by `dt` seconds. `velocity` is the desired movement, and any part of it along the up
+	/// axis is ignored; vertical motion comes from gravity and `jump`.
+	pub fn update(&mut self, world: &CollisionWorld, velocity: Vector3<f32>, dt: f32) {
+		let up = self.config.up;
+		let was_grounded = self.grounded;
+
+		self.depenetrate(world);
+
+		let horizontal = velocity - up * velocity.dot(up);
+		if horizontal.magnitude2() > 0.0 {
+			self.move_horizontal(world, horizontal * dt, was_grounded);
+		}
+
+		if !was_grounded || self.vertical_speed > 0.0 {
+			self.vertical_speed -= self.config.gravity * dt;
+		}
+
+		self.grounded = false;
+		let vertical = up * (self.vertical_speed * dt);
+		if self.vertical_speed <= 0.0 {
+			let snap = if was_grounded { self.config.snap_distance } else { 0.0 };
+			self.move_down(world, vertical, snap);
+		} else if let Some(normal) = self.slide(world, vertical) {
+			if normal.dot(up) < 0.0 {
+				// bumped a ceiling
+				self.vertical_speed = 0.0;
+			}
+		}
+
+		self.depenetrate(world);
+	}
+
+	fn move_horizontal(&mut self, world: &CollisionWorld, motion: Vector3<f32>, grounded: bool) {
+		let start = self.position;
+		self.slide(world, motion);
+
+		if !grounded || self.config.step_offset <= 0.0 || self.travelled(start, motion) >= 0.999 {
+			return;
+		}
+
+		// blocked by something; retry the move from `step_offset` higher and keep that if it gets further
+		let slid = self.position;
+		self.position = start;
+		let raised = self.config.up * self.config.step_offset;
+		self.slide(world, raised);
+		let step_height = (self.position - start).dot(self.config.up);
+		self.slide(world, motion);
+
+		let landed = self.snap_down(world, step_height);
+		if landed && self.travelled(start, motion) > self.travelled_to(start, slid, motion) {
+			return;
+		}
+
+		self.position = slid;
+	}
+
+	/// Moves down by `motion`, reaching up to `snap` further for ground, and lands on anything walkable.
+	fn move_down(&mut self, world: &CollisionWorld, motion: Vector3<f32>, snap: f32) {
+		let start = self.position;
+
+		if self.snap_down(world, motion.magnitude() + snap) {
+			self.vertical_speed = 0.0;
+			return;
+		}
+
+		// nothing walkable below; fall freely, sliding off steep slopes
+		self.position = start;
+		if let Some(normal) = self.slide(world, motion) {
+			if self.is_walkable(normal) {
+				self.grounded = true;
+				self.ground_normal = normal;
+				self.vertical_speed = 0.0;
+			} else {
+				// keep accelerating down the slope instead of sticking to it
+				self.vertical_speed = self.vertical_speed.min(0.0);
+			}
+		}
+	}
+
+	/// Sweeps straight down by up to `distance`. On walkable ground, lands on it and returns true; otherwise leaves the
+	/// position unchanged.
+	fn snap_down(&mut self, world: &CollisionWorld, distance: f32) -> bool {
+		if distance <= 0.0 {
+			return false;
+		}
+
+		let up = self.config.up;
+		let (a, b) = self.capsule(self.position);
+		let motion = -up * (distance + self.config.skin_width);
+		match world.sweep_capsule(a, b, self.config.radius, motion) {
+			Some(hit) if self.is_walkable(hit.normal) => {
+				self.position += motion * hit.time + up * self.config.skin_width;
+				self.grounded = true;
+				self.ground_normal = hit.normal;
+				true
+			},
+			_ => false,
+		}
+	}
+
+	/// Moves along `motion`, sliding along anything hit. Returns the normal of the last surface touched.
+	fn slide(&mut self, world: &CollisionWorld, motion: Vector3<f32>) -> Option<Vector3<f32>> {
+		let up = self.config.up;
+		let skin = self.config.skin_width;
+		let mut motion = motion;
+		let mut last_normal = None;
+
+		for _ in 0..SLIDE_ITERATIONS {
+			let length = motion.magnitude();
+			if length <= skin * 0.01 {
+				break;
+			}
+
+			let (a, b) = self.capsule(self.position);
+			let hit = match world.sweep_capsule(a, b, self.config.radius, motion) {
+				Some(hit) => hit,
+				None => {
+					self.position += motion;
+					break;
+				},
+			};
+
+			// stop a skin width short of the surface
+			let advance = (hit.time - skin / length).max(0.0);
+			self.position += motion * advance;
+
+			let mut normal = hit.normal;
+			if !self.is_walkable(normal) && motion.dot(up) <= 0.0 {
+				// treat steep slopes as vertical walls so they can't be walked up
+				let flat = normal - up * normal.dot(up);
+				if flat.magnitude2() > 0.0 {
+					normal = flat.normalize();
+				}
+			}
+			last_normal = Some(hit.normal);
+
+			let remaining = motion * (1.0 - advance);
+			motion = remaining - normal * remaining.dot(normal);
+		}
+
+		last_normal
+	}
+
+	/// Pushes the capsule out of any geometry it overlaps.
+	fn depenetrate(&mut self, world: &CollisionWorld) {
+		for _ in 0..DEPENETRATE_ITERATIONS {
+			let (a, b) = self.capsule(self.position);
+			let contacts = world.capsule_contacts(a, b, self.config.radius);
+			let deepest =
+				contacts.iter().fold(None, |deepest: Option<&Contact>, contact| match deepest {
+					Some(deepest) if deepest.depth >= contact.depth => Some(deepest),
+					_ => Some(contact),
+				});
+
+			match deepest {
+				Some(contact) => self.position += contact.normal * (contact.depth + self.config.skin_width * 0.5),
+				None => break,
+			}
+		}
+	}
+
+	fn is_walkable(&self, normal: Vector3<f32>) -> bool {
+		normal.dot(self.config.up) >= self.config.max_slope.cos()
+	}
+
+	/// Fraction of `motion` (ignoring the up axis) covered since `start`.
+	fn travelled(&self, start: Vector3<f32>, motion: Vector3<f32>) -> f32 {
+		self.travelled_to(start, self.position, motion)
+	}
+
+	fn travelled_to(&self, start: Vector3<f32>, end: Vector3<f32>, motion: Vector3<f32>) -> f32 {
+		let up = self.config.up;
+		let flat = |vec: Vector3<f32>| vec - up * vec.dot(up);
+		let motion = flat(motion);
+		let length2 = motion.magnitude2();
+		if length2 == 0.0 { 1.0 } else { flat(end - start).dot(motion) / length2 }
+	}
+
+	/// Segment endpoints of the capsule standing at `position`.
+	fn capsule(&self, position: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+		let up = self.config.up;
+		let radius = self.config.radius;
+		let top = (self.config.height - radius).max(radius);
+		(position + up * radius, position + up * top)
+	}
+}
@@ -0,0 +1,107 @@
+/// Color in linear light, the space lighting and blending happen in, with straight alpha. Colors picked in an image
+/// editor or color picker are almost always sRGB, so construct those with `from_srgb8` or `from_srgb` rather than
+/// passing their values straight through.
+///
+/// RGB may exceed 1 where the API takes light, e.g. for glowing ribbons.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+	pub r: f32,
+	pub g: f32,
+	pub b: f32,
+	pub a: f32,
+}
+impl Color {
+	pub const WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0, a: 1.0 };
+	pub const BLACK: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 1.0 };
+	pub const TRANSPARENT: Color = Color { r: 0.0, g: 0.0, b: 0.0, a: 0.0 };
+
+	pub fn from_linear(r: f32, g: f32, b: f32, a: f32) -> Self {
+		Self { r: r, g: g, b: b, a: a }
+	}
+
+	/// sRGB components from 0 to 1. Alpha is linear in every color space, so it's passed through.
+	pub fn from_srgb(r: f32, g: f32, b: f32, a: f32) -> Self {
+		Self { r: srgb_to_linear(r), g: srgb_to_linear(g), b: srgb_to_linear(b), a: a }
+	}
+
+	/// Opaque 8 bit sRGB, as in `#rrggbb`.
+	pub fn from_srgb8(r: u8, g: u8, b: u8) -> Self {
+		Self::from_srgba8(r, g, b, 255)
+	}
+
+	pub fn from_srgba8(r: u8, g: u8, b: u8, a: u8) -> Self {
+		Self::from_srgb(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, a as f32 / 255.0)
+	}
+
+	/// `0xrrggbb`, in sRGB.
+	pub fn from_hex(rgb: u32) -> Self {
+		Self::from_srgb8((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+	}
+
+	/// Color of a black body at `kelvin`, normalized so the brightest channel is 1. Candles are around 1900 K,
+	/// incandescent bulbs 2700 K, daylight 6500 K and clear blue sky 10000 K. Accurate from 1000 K to 40000 K.
+	pub fn from_temperature(kelvin: f32) -> Self {
+		// Tanner Helland's fit to the CIE 1964 black body colors, in sRGB from 0 to 255
+		let t = kelvin.max(1000.0).min(40000.0) / 100.0;
+		let r = if t <= 66.0 { 255.0 } else { 329.698727446 * (t - 60.0).powf(-0.1332047592) };
+		let g =
+			if t <= 66.0 {
+				99.4708025861 * t.ln() - 161.1195681661
+			} else {
+				288.1221695283 * (t - 60.0).powf(-0.0755148492)
+			};
+		let b =
+			if t >= 66.0 {
+				255.0
+			} else if t <= 19.0 {
+				0.0
+			} else {
+				138.5177312231 * (t - 10.0).ln() - 305.0447927307
+			};
+		let channel = |c: f32| c.max(0.0).min(255.0) / 255.0;
+		Self::from_srgb(channel(r), channel(g), channel(b), 1.0)
+	}
+
+	pub fn with_alpha(self, a: f32) -> Self {
+		Self { a: a, ..self }
+	}
+
+	/// Multiplies RGB, e.g. to turn a color into a light intensity.
+	pub fn scaled(self, scale: f32) -> Self {
+		Self { r: self.r * scale, g: self.g * scale, b: self.b * scale, a: self.a }
+	}
+
+	pub fn to_linear(self) -> [f32; 4] {
+		[self.r, self.g, self.b, self.a]
+	}
+
+	/// For the API's `[f32; 3]` colors, which are all linear.
+	pub fn to_linear_rgb(self) -> [f32; 3] {
+		[self.r, self.g, self.b]
+	}
+
+	/// RGB past 1 is clamped.
+	pub fn to_srgb(self) -> [f32; 4] {
+		[linear_to_srgb(self.r), linear_to_srgb(self.g), linear_to_srgb(self.b), self.a.max(0.0).min(1.0)]
+	}
+
+	pub fn to_srgb8(self) -> [u8; 4] {
+		let srgb = self.to_srgb();
+		let quantize = |c: f32| (c * 255.0 + 0.5) as u8;
+		[quantize(srgb[0]), quantize(srgb[1]), quantize(srgb[2]), quantize(srgb[3])]
+	}
+}
+impl Default for Color {
+	fn default() -> Self {
+		Color::WHITE
+	}
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+	if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+	let c = c.max(0.0).min(1.0);
+	if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
@@ -0,0 +1,101 @@
+use std::sync::Arc;
+use vulkano::{ command_buffer::AutoCommandBuffer, device::Queue, sync::GpuFuture };
+
+/// Execution order of a stage in a `Compositor`. Lower layers run first; stages on the same layer run in the order
+/// they were added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Layer(pub i32);
+impl Layer {
+	/// Rendering to textures that later layers sample from.
+	pub const OFFSCREEN: Layer = Layer(-100);
+	pub const SCENE: Layer = Layer(0);
+	pub const POST: Layer = Layer(100);
+	pub const HUD: Layer = Layer(200);
+}
+
+/// Collects the command buffers of one frame and submits them in layer order, with a semaphore between each stage so
+/// later stages see the results of earlier ones.
+pub struct Compositor {
+	stages: Vec<Stage>,
+	excluded: Vec<Layer>,
+	/// Futures of excluded stages, which still have to run for later frames that depend on them.
+	excluded_futures: Vec<Box<GpuFuture>>,
+}
+impl Compositor {
+	pub(crate) fn new() -> Self {
+		Self::excluding(vec![])
+	}
+
+	/// Drops the commands of stages added on `excluded`, for captures that leave out layers like the HUD.
+	pub(crate) fn excluding(excluded: Vec<Layer>) -> Self {
+		Self { stages: vec![], excluded: excluded, excluded_futures: vec![] }
+	}
+
+	/// Adds commands to run on `layer`. `future` is waited on before the commands run, e.g. the upload future
+	/// returned alongside a batch's commands.
+	pub fn add<F>(&mut self, layer: Layer, commands: AutoCommandBuffer, future: Option<F>)
+	where
+		F: GpuFuture + 'static
+	{
+		if self.is_excluded(layer) {
+			if let Some(future) = future {
+				self.excluded_futures.push(Box::new(future));
+			}
+			return;
+		}
+
+		self.stages.push(Stage {
+			layer: layer,
+			commands: commands,
+			future: future.map(|future| Box::new(future) as _),
+		});
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.stages.is_empty()
+	}
+
+	/// Whether stages on `layer` are left out of this frame, so their commands needn't be recorded at all.
+	pub fn is_excluded(&self, layer: Layer) -> bool {
+		self.excluded.contains(&layer)
+	}
+
+	/// Moves `other`'s stages after this one's, so they run after the stages already added to the same layers.
+	pub(crate) fn append(&mut self, other: Compositor) {
+		for stage in other.stages {
+			if self.is_excluded(stage.layer) {
+				self.excluded_futures.extend(stage.future);
+			} else {
+				self.stages.push(stage);
+			}
+		}
+		self.excluded_futures.extend(other.excluded_futures);
+	}
+
+	pub(crate) fn submit(mut self, queue: &Arc<Queue>, future: Box<GpuFuture>) -> Box<GpuFuture> {
+		// stable, so insertion order is kept within a layer
+		self.stages.sort_by_key(|stage| stage.layer);
+
+		let count = self.stages.len();
+		let mut future = future;
+		for excluded_future in self.excluded_futures {
+			future = Box::new(future.join(excluded_future));
+		}
+		for (i, stage) in self.stages.into_iter().enumerate() {
+			if let Some(stage_future) = stage.future {
+				future = Box::new(future.join(stage_future));
+			}
+
+			let executed = future.then_execute(queue.clone(), stage.commands).unwrap();
+			future = if i + 1 < count { Box::new(executed.then_signal_semaphore()) } else { Box::new(executed) };
+		}
+
+		future
+	}
+}
+
+struct Stage {
+	layer: Layer,
+	commands: AutoCommandBuffer,
+	future: Option<Box<GpuFuture>>,
+}
@@ -0,0 +1,362 @@
+use crate::geometry::MeshGeometry;
+use cgmath::{ prelude::*, Vector3 };
+use std::{ cmp::Ordering, collections::{ BinaryHeap, HashMap }, mem, ops::Add, u32 };
+
+/// Open edges are held in place by a plane through the edge, weighted this much more than the faces beside it, so
+/// borders and texture seams (which are split vertices, and so borders to the simplifier) keep their shape.
+const BOUNDARY_WEIGHT: f64 = 100.0;
+
+/// Reduces `geometry` to about `target_ratio` of its triangles with quadric error metrics (Garland and Heckbert), for
+/// collision proxies and meshes of their own. Vertices are only ever merged into each other, never moved, so normals
+/// and texture coordinates stay exact. Stops early when no more edges can be collapsed without folding triangles
+/// over.
+pub fn simplify(geometry: &MeshGeometry, target_ratio: f32) -> MeshGeometry {
+	let indices = simplify_indices(&geometry.positions, &geometry.indices, target_ratio);
+
+	let mut simplified = MeshGeometry::default();
+	let mut remap = vec![u32::MAX; geometry.vertex_count()];
+	for index in indices {
+		let index = index as usize;
+		if remap[index] == u32::MAX {
+			remap[index] = simplified.positions.len() as u32;
+			simplified.positions.push(geometry.positions[index]);
+			if let Some(&normal) = geometry.normals.get(index) {
+				simplified.normals.push(normal);
+			}
+			if let Some(&texcoord) = geometry.texcoords.get(index) {
+				simplified.texcoords.push(texcoord);
+			}
+		}
+		simplified.indices.push(remap[index]);
+	}
+
+	simplified
+}
+
+/// Like `simplify`, but returns indices into the same `positions`, for levels of detail that share a vertex buffer
+/// like `NiceModelLod`. Run it once per material, on that material's range of indices, to keep them apart.
+/// Triangles keep their order.
+pub fn simplify_indices(positions: &[[f32; 3]], indices: &[u32], target_ratio: f32) -> Vec<u32> {
+	let target = ((indices.len() / 3) as f32 * target_ratio.max(0.0).min(1.0)).ceil() as usize;
+	let mut collapser = Collapser::new(positions, indices);
+	collapser.collapse_to(target);
+	collapser.indices()
+}
+
+struct Collapser {
+	positions: Vec<Vector3<f64>>,
+	triangles: Vec<[usize; 3]>,
+	live: Vec<bool>,
+	live_count: usize,
+	/// Triangles using each vertex. May still list triangles that have been removed.
+	vertex_triangles: Vec<Vec<usize>>,
+	quadrics: Vec<Quadric>,
+	collapsed: Vec<bool>,
+	/// Bumped whenever a vertex's quadric changes, to recognize stale candidates.
+	versions: Vec<u32>,
+	candidates: BinaryHeap<Candidate>,
+}
+impl Collapser {
+	fn new(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+		let positions: Vec<_> =
+			positions.iter().map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+		let triangles: Vec<_> =
+			indices.chunks(3)
+				.filter(|tri| tri.len() == 3 && tri[0] != tri[1] && tri[1] != tri[2] && tri[2] != tri[0])
+				.map(|tri| [tri[0] as usize, tri[1] as usize, tri[2] as usize])
+				.collect();
+
+		let mut vertex_triangles = vec![vec![]; positions.len()];
+		let mut quadrics = vec![Quadric::default(); positions.len()];
+		let mut edges = HashMap::new();
+		for (t, tri) in triangles.iter().enumerate() {
+			let normal = face_normal(&positions, tri);
+			let area = normal.magnitude() * 0.5;
+			if area > 0.0 {
+				let normal = normal.normalize();
+				let plane = Quadric::plane(normal, -normal.dot(positions[tri[0]]), area);
+				for &vertex in tri {
+					quadrics[vertex] = quadrics[vertex] + plane;
+				}
+			}
+
+			for i in 0..3 {
+				vertex_triangles[tri[i]].push(t);
+				let edge = (tri[i].min(tri[(i + 1) % 3]), tri[i].max(tri[(i + 1) % 3]));
+				edges.entry(edge).or_insert((0, t)).0 += 1;
+			}
+		}
+
+		// sorted, so the quadrics sum up and ties between candidates break the same way every run
+		let mut edges: Vec<_> = edges.into_iter().collect();
+		edges.sort_by_key(|&(edge, _)| edge);
+
+		for &((a, b), (count, t)) in &edges {
+			if count != 1 {
+				continue;
+			}
+
+			let edge = positions[b] - positions[a];
+			let normal = edge.cross(face_normal(&positions, &triangles[t]));
+			if normal.magnitude2() > 0.0 {
+				let normal = normal.normalize();
+				let plane = Quadric::plane(normal, -normal.dot(positions[a]), BOUNDARY_WEIGHT * edge.magnitude2());
+				quadrics[a] = quadrics[a] + plane;
+				quadrics[b] = quadrics[b] + plane;
+			}
+		}
+
+		let vertex_count = positions.len();
+		let triangle_count = triangles.len();
+		let mut collapser = Self {
+			positions: positions,
+			triangles: triangles,
+			live: vec![true; triangle_count],
+			live_count: triangle_count,
+			vertex_triangles: vertex_triangles,
+			quadrics: quadrics,
+			collapsed: vec![false; vertex_count],
+			versions: vec![0; vertex_count],
+			candidates: BinaryHeap::new(),
+		};
+		for &((a, b), _) in &edges {
+			collapser.push_edge(a, b);
+		}
+
+		collapser
+	}
+
+	fn collapse_to(&mut self, target: usize) {
+		while self.live_count > target {
+			let candidate =
+				match self.candidates.pop() {
+					Some(candidate) => candidate,
+					None => break,
+				};
+
+			let stale =
+				self.collapsed[candidate.from] || self.collapsed[candidate.to]
+					|| self.versions[candidate.from] != candidate.from_version
+					|| self.versions[candidate.to] != candidate.to_version;
+			if !stale && self.can_collapse(candidate.from, candidate.to) {
+				self.collapse(candidate.from, candidate.to);
+			}
+		}
+	}
+
+	fn indices(&self) -> Vec<u32> {
+		self.triangles.iter()
+			.zip(&self.live)
+			.filter(|&(_, &live)| live)
+			.flat_map(|(tri, _)| tri.iter().map(|&vertex| vertex as u32))
+			.collect()
+	}
+
+	/// Queues the cheaper direction of merging one end of the edge into the other.
+	fn push_edge(&mut self, a: usize, b: usize) {
+		let quadric = self.quadrics[a] + self.quadrics[b];
+		let into_a = quadric.error(self.positions[a]);
+		let into_b = quadric.error(self.positions[b]);
+		let (from, to, cost) = if into_b <= into_a { (a, b, into_b) } else { (b, a, into_a) };
+		self.candidates.push(Candidate {
+			cost: cost,
+			from: from,
+			to: to,
+			from_version: self.versions[from],
+			to_version: self.versions[to],
+		});
+	}
+
+	/// Rejects collapses that would flip a triangle or pinch the surface into a non-manifold edge.
+	fn can_collapse(&self, from: usize, to: usize) -> bool {
+		let mut shared_triangles = 0;
+		for &t in &self.vertex_triangles[from] {
+			if !self.live[t] {
+				continue;
+			}
+
+			let tri = self.triangles[t];
+			if tri.contains(&to) {
+				shared_triangles += 1;
+				continue;
+			}
+
+			let before = face_normal(&self.positions, &tri);
+			let mut moved = tri;
+			for vertex in moved.iter_mut().filter(|vertex| **vertex == from) {
+				*vertex = to;
+			}
+			let after = face_normal(&self.positions, &moved);
+			if after.magnitude2() == 0.0 || before.dot(after) <= 0.0 {
+				return false;
+			}
+		}
+
+		let to_neighbors = self.neighbors(to);
+		let shared_neighbors =
+			self.neighbors(from).iter().filter(|&vertex| to_neighbors.binary_search(vertex).is_ok()).count();
+		shared_neighbors <= shared_triangles
+	}
+
+	fn collapse(&mut self, from: usize, to: usize) {
+		for t in mem::replace(&mut self.vertex_triangles[from], vec![]) {
+			if !self.live[t] {
+				continue;
+			}
+
+			if self.triangles[t].contains(&to) {
+				self.live[t] = false;
+				self.live_count -= 1;
+			} else {
+				for vertex in self.triangles[t].iter_mut().filter(|vertex| **vertex == from) {
+					*vertex = to;
+				}
+				self.vertex_triangles[to].push(t);
+			}
+		}
+
+		let live = &self.live;
+		self.vertex_triangles[to].retain(|&t| live[t]);
+		self.quadrics[to] = self.quadrics[to] + self.quadrics[from];
+		self.collapsed[from] = true;
+		self.versions[to] += 1;
+
+		for neighbor in self.neighbors(to) {
+			self.push_edge(to, neighbor);
+		}
+	}
+
+	/// Sorted vertices sharing a live triangle with `vertex`.
+	fn neighbors(&self, vertex: usize) -> Vec<usize> {
+		let mut neighbors: Vec<_> =
+			self.vertex_triangles[vertex].iter()
+				.filter(|&&t| self.live[t])
+				.flat_map(|&t| self.triangles[t].iter().cloned())
+				.filter(|&other| other != vertex)
+				.collect();
+		neighbors.sort();
+		neighbors.dedup();
+		neighbors
+	}
+}
+
+/// Symmetric 4x4 matrix measuring squared distance to a sum of planes, upper triangle only.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+impl Quadric {
+	fn plane(normal: Vector3<f64>, d: f64, weight: f64) -> Self {
+		let (a, b, c) = (normal.x, normal.y, normal.z);
+		let q = [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d];
+		let mut weighted = [0.0; 10];
+		for (weighted, q) in weighted.iter_mut().zip(q.iter()) {
+			*weighted = q * weight;
+		}
+		Quadric(weighted)
+	}
+
+	fn error(&self, p: Vector3<f64>) -> f64 {
+		let q = &self.0;
+		let (x, y, z) = (p.x, p.y, p.z);
+		q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+			+ q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+			+ q[7] * z * z + 2.0 * q[8] * z
+			+ q[9]
+	}
+}
+impl Add for Quadric {
+	type Output = Self;
+
+	fn add(self, other: Self) -> Self {
+		let mut sum = self.0;
+		for (sum, other) in sum.iter_mut().zip(other.0.iter()) {
+			*sum += other;
+		}
+		Quadric(sum)
+	}
+}
+
+/// Ordered so the cheapest collapse comes out of the heap first, and of equally cheap ones the one between the lowest
+/// vertices.
+struct Candidate {
+	cost: f64,
+	from: usize,
+	to: usize,
+	from_version: u32,
+	to_version: u32,
+}
+impl PartialEq for Candidate {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+impl Ord for Candidate {
+	fn cmp(&self, other: &Self) -> Ordering {
+		other.cost.partial_cmp(&self.cost)
+			.unwrap_or(Ordering::Equal)
+			.then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+			.then_with(|| (other.from_version, other.to_version).cmp(&(self.from_version, self.to_version)))
+	}
+}
+
+/// Unnormalized, with twice the triangle's area as its length.
+fn face_normal(positions: &[Vector3<f64>], tri: &[usize; 3]) -> Vector3<f64> {
+	(positions[tri[1]] - positions[tri[0]]).cross(positions[tri[2]] - positions[tri[0]])
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::geometry::MeshGeometry;
+	use super::simplify;
+
+	/// Flat square of `size` by `size` quads in xy, from the origin.
+	fn grid(size: u32) -> MeshGeometry {
+		let mut geometry = MeshGeometry::default();
+		for y in 0..=size {
+			for x in 0..=size {
+				geometry.positions.push([x as f32, y as f32, 0.0]);
+			}
+		}
+		for y in 0..size {
+			for x in 0..size {
+				let i = y * (size + 1) + x;
+				geometry.indices.extend(&[i, i + 1, i + size + 2, i, i + size + 2, i + size + 1]);
+			}
+		}
+		geometry
+	}
+
+	#[test]
+	fn decimated_grid_keeps_bounds_and_borders() {
+		let geometry = grid(16);
+		let simplified = simplify(&geometry, 0.1);
+
+		assert!(simplified.triangle_count() < geometry.triangle_count() / 2);
+		assert_eq!(simplified.bounds(), geometry.bounds());
+		for corner in &[[0.0, 0.0, 0.0], [16.0, 0.0, 0.0], [0.0, 16.0, 0.0], [16.0, 16.0, 0.0]] {
+			assert!(simplified.positions.contains(corner), "lost corner {:?}", corner);
+		}
+		// the square is still covered exactly once, so its borders didn't move in and no triangle folded over
+		for triangle in simplified.triangles() {
+			assert!(triangle.normal().z > 0.0, "flipped or degenerate triangle {:?}", triangle);
+		}
+		let area: f32 = simplified.triangles().map(|triangle| triangle.area()).sum();
+		assert!((area - 256.0).abs() < 1e-3, "area changed to {}", area);
+	}
+
+	#[test]
+	fn simplification_is_deterministic() {
+		let geometry = grid(12);
+		let first = simplify(&geometry, 0.25);
+		for _ in 0..8 {
+			let again = simplify(&geometry, 0.25);
+			assert_eq!(again.positions, first.positions);
+			assert_eq!(again.indices, first.indices);
+		}
+	}
+}
@@ -1,16 +1,18 @@
+pub use futures::channel::oneshot::Canceled;
+
 use futures::{
 	channel::oneshot,
 	executor::ThreadPool,
 	future::lazy,
 	prelude::*,
-	task::{ LocalWaker, Poll, SpawnExt }
+	task::{ Context, Poll, SpawnExt }
 };
 use lazy_static::lazy_static;
-use std::{ cmp::min, pin::Pin, sync::Mutex };
+use std::{ cmp::max, pin::Pin, sync::Mutex };
 use vulkano::sync::{ FenceSignalFuture, FlushError, GpuFuture };
 
 lazy_static! {
-	static ref CPU_POOL: Mutex<CpuPool> = Mutex::new(CpuPool::new(min(1, num_cpus::get() - 1)));
+	static ref CPU_POOL: Mutex<CpuPool> = Mutex::new(CpuPool::new(max(1, num_cpus::get() - 1)));
 	static ref EXECUTOR_POOL: Mutex<ThreadPool> = Mutex::new(ThreadPool::builder().pool_size(1).create().unwrap());
 	static ref FS_POOL: Mutex<CpuPool> = Mutex::new(CpuPool::new(1));
 }
@@ -57,11 +59,18 @@ impl CpuPool {
 pub struct CpuFuture<T, E> {
 	recv: oneshot::Receiver<Result<T, E>>,
 }
+impl<T, E> CpuFuture<T, E> {
+	/// Takes the result if the work has finished, without blocking. Errs with `Canceled` if the work panicked, so it
+	/// never will.
+	pub fn try_get(&mut self) -> Result<Option<Result<T, E>>, Canceled> {
+		self.recv.try_recv()
+	}
+}
 impl<T, E> Future for CpuFuture<T, E> {
 	type Output = Result<T, E>;
 
-	fn poll(mut self: Pin<&mut Self>, lw: &LocalWaker) -> Poll<Self::Output> {
-		oneshot::Receiver::poll(Pin::new(&mut self.recv), lw).map(|val| val.unwrap())
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		Pin::new(&mut self.recv).poll(cx).map(|val| val.unwrap())
 	}
 }
 
@@ -76,10 +85,14 @@ impl<T: GpuFuture> GpuFutureFuture<T> {
 impl<T: GpuFuture> Future for GpuFutureFuture<T> {
 	type Output = Result<(), FlushError>;
 
-	fn poll(self: Pin<&mut Self>, _lw: &LocalWaker) -> Poll<Self::Output> {
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
 		match self.future.wait(Some(Default::default())) {
 			Ok(()) => Poll::Ready(Ok(())),
-			Err(FlushError::Timeout) => Poll::Pending,
+			Err(FlushError::Timeout) => {
+				// there's nothing to wake on when the fence signals, so ask to be polled again
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			},
 			Err(err) => Poll::Ready(Err(err)),
 		}
 	}
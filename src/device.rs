@@ -1,12 +1,63 @@
-use crate::batch::sprite::Font;
+#[cfg(feature = "mesh")]
+mod attachments;
+mod capabilities;
+mod external_memory;
+mod pipeline_cache;
+mod raw;
+mod shader_modules;
+
+#[cfg(feature = "mesh")]
+pub(crate) use self::attachments::{ AttachmentPool, AttachmentScope, AttachmentUsage };
+pub use self::capabilities::Capabilities;
+pub(crate) use self::external_memory::ExternalMemorySupport;
+pub(crate) use self::pipeline_cache::CachingLoader;
+pub(crate) use self::raw::FormatFeatures;
+#[cfg(unix)]
+pub(crate) use self::raw::{ ExternalHandleType, IMPORTED_IMAGE_USAGE, ImportedImage, QUEUE_FAMILY_EXTERNAL };
+use self::raw::RawVk;
+pub use self::shader_modules::{ compile_shader_dir, ShaderModuleError, ShaderModules, ShaderStage };
+#[cfg(feature = "mesh")]
+use crate::batch::mesh::{ MaterialDesc, MaterialHandle, MaterialRegistry, MeshRenderPass };
+use crate::batch::sprite::{ Font, SpritePipelineCache, TextLayoutCache };
+use crate::stats::ResourceStats;
+#[cfg(unix)]
+use crate::texture::ExternalTextureError;
+#[cfg(unix)]
+use std::fs::File;
 use decorum::R32;
+use lazy_static::lazy_static;
 use std::{ collections::HashMap, fs, io, path::{ Path, PathBuf }, sync::{ Arc, Mutex, Weak } };
-use vulkano::device::{ Device, Queue };
+#[cfg(feature = "mesh")]
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::{
+	OomError,
+	device::{ Device, Features, Queue },
+	format::Format,
+	instance::Limits,
+	pipeline::cache::PipelineCache,
+};
+
+lazy_static! {
+	/// Every live device context, so resources can find the context of the device they were created on.
+	static ref DEVICES: Mutex<Vec<Weak<DeviceCtx>>> = Mutex::default();
+}
 
 pub struct DeviceCtx {
 	device: Arc<Device>,
 	queue: Arc<Queue>,
 	fonts: Mutex<HashMap<(PathBuf, R32), Weak<Font>>>,
+	text_layouts: TextLayoutCache,
+	#[cfg(feature = "mesh")]
+	materials: MaterialRegistry,
+	#[cfg(feature = "mesh")]
+	attachments: AttachmentPool,
+	capabilities: Capabilities,
+	/// Every pipeline created on the device goes through it, see `pipeline_cache::CachingLoader`.
+	pipeline_cache: Arc<PipelineCache>,
+	sprite_pipelines: SpritePipelineCache,
+	shader_modules: ShaderModules,
+	resource_stats: Arc<ResourceStats>,
+	raw: RawVk,
 }
 impl DeviceCtx {
 	pub fn get_font<P: AsRef<Path>>(&self, path: P, scale: f32) -> Result<Arc<Font>, io::Error> {
@@ -26,15 +77,177 @@ impl DeviceCtx {
 			})
 	}
 
-	pub(crate) fn new(device: Arc<Device>, queue: Arc<Queue>) -> Arc<Self> {
-		Arc::new(Self { device: device, queue: queue, fonts: Mutex::default() })
+	/// Returns a material matching `desc`, shared with any other meshes using an identical one.
+	#[cfg(feature = "mesh")]
+	pub fn get_material(
+		&self,
+		render_pass: &Arc<MeshRenderPass>,
+		desc: MaterialDesc,
+	) -> Result<MaterialHandle, DeviceMemoryAllocError> {
+		self.materials.get(&self.queue, render_pass, desc)
+	}
+
+	pub(crate) fn new(device: Arc<Device>, queue: Arc<Queue>, external_memory: ExternalMemorySupport) -> Arc<Self> {
+		let pipeline_cache = PipelineCache::empty(device.clone()).expect("failed to create pipeline cache");
+		pipeline_cache::register(&device, &pipeline_cache);
+
+		let ret = Arc::new(Self {
+			capabilities: Capabilities::new(&device, &queue, external_memory),
+			pipeline_cache: pipeline_cache,
+			raw: RawVk::load(&device),
+			shader_modules: ShaderModules::new(device.clone()),
+			device: device,
+			queue: queue,
+			fonts: Mutex::default(),
+			text_layouts: TextLayoutCache::default(),
+			#[cfg(feature = "mesh")]
+			materials: MaterialRegistry::default(),
+			#[cfg(feature = "mesh")]
+			attachments: AttachmentPool::default(),
+			sprite_pipelines: SpritePipelineCache::default(),
+			resource_stats: Arc::new(ResourceStats::new()),
+		});
+
+		let mut devices = DEVICES.lock().unwrap();
+		devices.retain(|device| device.upgrade().is_some());
+		devices.push(Arc::downgrade(&ret));
+
+		ret
+	}
+
+	pub(crate) fn find(device: &Arc<Device>) -> Option<Arc<Self>> {
+		DEVICES.lock().unwrap().iter()
+			.filter_map(|ctx| ctx.upgrade())
+			.find(|ctx| Arc::ptr_eq(&ctx.device, device))
+	}
+
+	/// Name of the physical device, as shown in errors about resources used on the wrong device.
+	pub fn name(&self) -> String {
+		self.device.physical_device().name()
+	}
+
+	/// Number of attachments currently shared between batches through the device's pool. Batches rendering at the same
+	/// size share their intermediate attachments instead of each allocating their own.
+	#[cfg(feature = "mesh")]
+	pub fn pooled_attachment_count(&self) -> usize {
+		self.attachments.len()
+	}
+
+	/// Number of laid out strings kept for `Font::make_sprite`, along with their buffers. The least recently used are
+	/// dropped once there are too many, and a font's are dropped with it.
+	pub fn cached_text_count(&self) -> usize {
+		self.text_layouts.len()
+	}
+
+	/// Merges a pipeline cache saved by `save_pipeline_cache` on an earlier run, so pipelines created from now on skip
+	/// the driver's compilation. Load it before `Context::warm_pipelines`. The driver ignores data from another driver
+	/// version or device, so a stale file is harmless, and a missing one isn't an error.
+	pub fn load_pipeline_cache<P: AsRef<Path>>(&self, path: P) -> Result<(), PipelineCacheError> {
+		let data =
+			match fs::read(path) {
+				Ok(data) => data,
+				Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+				Err(err) => return Err(err.into()),
+			};
+
+		// the driver checks the header against its own vendor, device and cache uuid before using any of it
+		let loaded = unsafe { PipelineCache::with_data(self.device.clone(), &data)? };
+		pipeline_cache::synchronized(|| self.pipeline_cache.merge(&[&loaded]))?;
+		Ok(())
+	}
+
+	/// Writes every pipeline created on the device so far to `path`, for `load_pipeline_cache` on the next run.
+	pub fn save_pipeline_cache<P: AsRef<Path>>(&self, path: P) -> Result<(), PipelineCacheError> {
+		let data = self.pipeline_cache.get_data()?;
+		fs::write(path, data)?;
+		Ok(())
+	}
+
+	/// Shader modules loaded from SPIR-V files instead of compiled into the crate.
+	pub fn shader_modules(&self) -> &ShaderModules {
+		&self.shader_modules
+	}
+
+	/// Descriptor sets, framebuffers and subbuffers created by the batches on this device.
+	pub fn resource_stats(&self) -> &Arc<ResourceStats> {
+		&self.resource_stats
+	}
+
+	/// What `format` supports on the device, which vulkano 0.11 has no query for.
+	pub(crate) fn format_features(&self, format: Format) -> FormatFeatures {
+		self.raw.format_features(format)
+	}
+
+	/// Imports memory for `ExternalTexture::import`, which vulkano 0.11 can't.
+	#[cfg(unix)]
+	pub(crate) unsafe fn import_image(
+		&self,
+		handle_type: ExternalHandleType,
+		fd: File,
+		allocation_size: u64,
+		dimensions: [u32; 2],
+		format: Format,
+	) -> Result<ImportedImage, ExternalTextureError> {
+		self.raw.import_image(&self.device, handle_type, fd, allocation_size, dimensions, format)
 	}
 
 	pub(crate) fn device(&self) -> &Arc<Device> {
 		&self.device
 	}
 
+	pub(crate) fn text_layouts(&self) -> &TextLayoutCache {
+		&self.text_layouts
+	}
+
+	pub(crate) fn sprite_pipelines(&self) -> &SpritePipelineCache {
+		&self.sprite_pipelines
+	}
+
+	#[cfg(feature = "mesh")]
+	pub(crate) fn attachments(&self) -> &AttachmentPool {
+		&self.attachments
+	}
+
+	pub fn capabilities(&self) -> &Capabilities {
+		&self.capabilities
+	}
+
+	pub fn limits(&self) -> Limits {
+		self.device.physical_device().limits()
+	}
+
+	/// Features enabled on the device. Only what the crate itself needs is enabled.
+	pub fn features(&self) -> &Features {
+		self.device.enabled_features()
+	}
+
+	/// Features the device could support, including ones that aren't enabled.
+	pub fn supported_features(&self) -> &Features {
+		self.device.physical_device().supported_features()
+	}
+
 	pub fn queue(&self) -> &Arc<Queue> {
 		&self.queue
 	}
 }
+impl Drop for DeviceCtx {
+	fn drop(&mut self) {
+		pipeline_cache::unregister(&self.device);
+	}
+}
+
+#[derive(Debug)]
+pub enum PipelineCacheError {
+	Io(io::Error),
+	OomError(OomError),
+}
+impl From<io::Error> for PipelineCacheError {
+	fn from(val: io::Error) -> Self {
+		PipelineCacheError::Io(val)
+	}
+}
+impl From<OomError> for PipelineCacheError {
+	fn from(val: OomError) -> Self {
+		PipelineCacheError::OomError(val)
+	}
+}
@@ -0,0 +1,90 @@
+use std::sync::{ Arc, Mutex, Weak };
+use vulkano::{
+	device::Device,
+	format::Format,
+	image::{ AttachmentImage, ImageCreationError },
+	memory::DeviceMemoryAllocError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AttachmentUsage {
+	TransientInput,
+	Sampled,
+}
+
+/// How long what's drawn to an attachment has to last, which decides what else can share it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AttachmentScope {
+	/// Written and last read while one view is rendered, so every view of every batch can share it.
+	View,
+	/// Read after the batch's other views are rendered, up to the end of its command buffer, like the mask the target
+	/// outlines from. Only the same view of other batches can share it.
+	Batch { view: usize },
+}
+
+/// Attachments shared between batches. Everything submitted to a device's queue runs in order, and batches in a
+/// `Compositor` are separated by semaphores, so batches in the same frame never use these at the same time. Only
+/// attachments that are written and last read inside one command buffer can be pooled; anything read in a later frame,
+/// like history, has to stay owned by its batch.
+#[derive(Default)]
+pub(crate) struct AttachmentPool {
+	attachments: Mutex<Vec<PooledAttachment>>,
+}
+impl AttachmentPool {
+	/// Returns the attachment shared by every caller asking for the same `name`, scope, dimensions, format and usage,
+	/// or creates it. It's freed when the last user drops it.
+	pub(crate) fn get(
+		&self,
+		device: &Arc<Device>,
+		name: &'static str,
+		scope: AttachmentScope,
+		dimensions: [u32; 2],
+		format: Format,
+		usage: AttachmentUsage,
+	) -> Result<Arc<AttachmentImage>, DeviceMemoryAllocError> {
+		let mut attachments = self.attachments.lock().unwrap();
+
+		let existing =
+			attachments.iter()
+				.filter(|att| att.name == name && att.scope == scope && att.dimensions == dimensions)
+				.filter(|att| att.format == format && att.usage == usage)
+				.filter_map(|att| att.image.upgrade())
+				.next();
+		if let Some(image) = existing {
+			return Ok(image);
+		}
+		attachments.retain(|att| att.image.upgrade().is_some());
+
+		let image =
+			match usage {
+				AttachmentUsage::TransientInput => AttachmentImage::transient_input_attachment(device.clone(), dimensions, format),
+				AttachmentUsage::Sampled => AttachmentImage::sampled(device.clone(), dimensions, format),
+			}
+			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!(err) })?;
+
+		attachments.push(PooledAttachment {
+			name: name,
+			scope: scope,
+			dimensions: dimensions,
+			format: format,
+			usage: usage,
+			image: Arc::downgrade(&image),
+		});
+
+		Ok(image)
+	}
+
+	/// Number of distinct attachments currently alive in the pool.
+	pub(crate) fn len(&self) -> usize {
+		self.attachments.lock().unwrap().iter().filter(|att| att.image.upgrade().is_some()).count()
+	}
+}
+
+struct PooledAttachment {
+	name: &'static str,
+	scope: AttachmentScope,
+	dimensions: [u32; 2],
+	format: Format,
+	usage: AttachmentUsage,
+	image: Weak<AttachmentImage>,
+}
@@ -0,0 +1,42 @@
+use crate::device::ExternalMemorySupport;
+use vulkano::device::{ Device, Queue };
+
+/// Summary of what a device can do, for choosing between code paths without digging through Vulkan limits.
+#[derive(Clone, Copy, Debug)]
+pub struct Capabilities {
+	/// Largest width or height of a 2D texture or render target.
+	pub max_texture_size: u32,
+	/// Vulkan doesn't limit instance counts directly, only the buffers holding per-instance data. This is how many
+	/// 64 byte instances fit in one storage buffer binding.
+	pub max_instances_per_draw: u32,
+	/// Whether the device's queue can run compute shaders.
+	pub supports_compute: bool,
+	/// Whether shaders can index arrays of textures with dynamically uniform values. Non-uniform indexing from
+	/// `VK_EXT_descriptor_indexing` isn't checked.
+	pub supports_descriptor_indexing: bool,
+	/// Whether depth clamp is enabled, for `MeshRasterizer::depth_clamp`.
+	pub supports_depth_clamp: bool,
+	/// Whether `ExternalTexture::import` can import `ExternalMemoryHandle::OpaqueFd`s, from
+	/// `VK_KHR_external_memory_fd`.
+	/// Always false outside of Unix.
+	pub supports_external_memory: bool,
+	/// Whether `ExternalTexture::import` can import `ExternalMemoryHandle::DmaBuf`s, from
+	/// `VK_EXT_external_memory_dma_buf`.
+	pub supports_dma_buf_import: bool,
+}
+impl Capabilities {
+	pub(crate) fn new(device: &Device, queue: &Queue, external_memory: ExternalMemorySupport) -> Self {
+		let pdevice = device.physical_device();
+		let limits = pdevice.limits();
+
+		Self {
+			max_texture_size: limits.max_image_dimension_2d(),
+			max_instances_per_draw: limits.max_storage_buffer_range() / 64,
+			supports_compute: queue.family().supports_compute(),
+			supports_descriptor_indexing: pdevice.supported_features().shader_sampled_image_array_dynamic_indexing,
+			supports_depth_clamp: device.enabled_features().depth_clamp,
+			supports_external_memory: external_memory.opaque_fd,
+			supports_dma_buf_import: external_memory.dma_buf,
+		}
+	}
+}
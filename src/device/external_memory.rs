@@ -0,0 +1,59 @@
+use std::ffi::CString;
+use vulkano::{ device::RawDeviceExtensions, instance::{ PhysicalDevice, RawInstanceExtensions } };
+
+/// Instance extensions the device extensions below depend on, without Vulkan 1.1.
+const INSTANCE_EXTENSIONS: &[&str] = &["VK_KHR_get_physical_device_properties2", "VK_KHR_external_memory_capabilities"];
+
+const EXTERNAL_MEMORY: &[&str] = &["VK_KHR_external_memory", "VK_KHR_external_memory_fd"];
+const DMA_BUF: &str = "VK_EXT_external_memory_dma_buf";
+
+/// Which kinds of external memory a device was created to import, for `ExternalTexture::import`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ExternalMemorySupport {
+	pub(crate) opaque_fd: bool,
+	pub(crate) dma_buf: bool,
+}
+impl ExternalMemorySupport {
+	/// Adds the instance extensions external memory needs to `extensions`, if `supported` has them all, and returns
+	/// whether it did.
+	pub(crate) fn add_instance_extensions(
+		extensions: &mut RawInstanceExtensions,
+		supported: &RawInstanceExtensions,
+	) -> bool {
+		if !INSTANCE_EXTENSIONS.iter().all(|name| supported.iter().any(|ext| ext.to_bytes() == name.as_bytes())) {
+			return false;
+		}
+
+		for name in INSTANCE_EXTENSIONS {
+			extensions.insert(CString::new(*name).unwrap());
+		}
+		true
+	}
+
+	/// Adds the device extensions for importing external memory that `pdevice` supports to `extensions`. Handles are
+	/// file descriptors, so there are none outside of Unix.
+	pub(crate) fn add_device_extensions(
+		extensions: &mut RawDeviceExtensions,
+		pdevice: PhysicalDevice,
+	) -> Self {
+		if !cfg!(unix) {
+			return Self::default();
+		}
+
+		let supported = RawDeviceExtensions::supported_by_device(pdevice);
+		let has = |name: &str| supported.iter().any(|ext| ext.to_bytes() == name.as_bytes());
+
+		let opaque_fd = EXTERNAL_MEMORY.iter().all(|name| has(name));
+		if opaque_fd {
+			for name in EXTERNAL_MEMORY {
+				extensions.insert(CString::new(*name).unwrap());
+			}
+		}
+		let dma_buf = opaque_fd && has(DMA_BUF);
+		if dma_buf {
+			extensions.insert(CString::new(DMA_BUF).unwrap());
+		}
+
+		Self { opaque_fd: opaque_fd, dma_buf: dma_buf }
+	}
+}
@@ -0,0 +1,239 @@
+use lazy_static::lazy_static;
+use std::{
+	collections::HashMap,
+	ffi::CStr,
+	mem,
+	os::raw::c_char,
+	sync::{ RwLock, atomic::{ AtomicUsize, Ordering } },
+};
+use vk_sys as vk;
+use vulkano::{
+	VulkanObject,
+	device::Device,
+	instance::loader::{ self, FunctionPointers, Loader, LoadingError },
+	pipeline::cache::PipelineCache,
+};
+
+type GetDeviceProcAddr = extern "system" fn(vk::Device, *const c_char) -> Option<vk::PFN_vkVoidFunction>;
+type CreateGraphicsPipelines =
+	extern "system" fn(
+		vk::Device,
+		vk::PipelineCache,
+		u32,
+		*const vk::GraphicsPipelineCreateInfo,
+		*const vk::AllocationCallbacks,
+		*mut vk::Pipeline,
+	) -> vk::Result;
+type CreateComputePipelines =
+	extern "system" fn(
+		vk::Device,
+		vk::PipelineCache,
+		u32,
+		*const vk::ComputePipelineCreateInfo,
+		*const vk::AllocationCallbacks,
+		*mut vk::Pipeline,
+	) -> vk::Result;
+
+/// The driver's `vkGetDeviceProcAddr`, behind the one `CachingLoader` hands out.
+static DRIVER_GET_DEVICE_PROC_ADDR: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+	/// The driver's pipeline creation functions for each device, and the cache registered for it. Pipelines are
+	/// created under the read lock, and the write lock is held while the cache is merged into, which Vulkan needs
+	/// externally synchronized.
+	static ref HOOKS: RwLock<HashMap<vk::Device, DeviceHooks>> = RwLock::default();
+}
+
+#[derive(Default)]
+struct DeviceHooks {
+	create_graphics_pipelines: Option<CreateGraphicsPipelines>,
+	create_compute_pipelines: Option<CreateComputePipelines>,
+	cache: vk::PipelineCache,
+}
+
+/// Loads Vulkan like vulkano's default loader, except that devices create pipelines through the cache registered for
+/// them with `register`. vulkano 0.11 always passes a null cache, so it's filled in here on the way to the driver.
+pub(crate) struct CachingLoader {
+	loader: &'static FunctionPointers<Box<Loader + Send + Sync>>,
+}
+impl CachingLoader {
+	pub(crate) fn load() -> Result<FunctionPointers<Box<Loader + Send + Sync>>, LoadingError> {
+		let loader = CachingLoader { loader: loader::auto_loader()? };
+		Ok(FunctionPointers::new(Box::new(loader) as Box<Loader + Send + Sync>))
+	}
+}
+unsafe impl Loader for CachingLoader {
+	fn get_instance_proc_addr(&self, instance: vk::Instance, name: *const c_char) -> vk::PFN_vkVoidFunction {
+		let function = self.loader.get_instance_proc_addr(instance, name);
+		if unsafe { CStr::from_ptr(name) }.to_bytes() != b"vkGetDeviceProcAddr" {
+			return function;
+		}
+
+		DRIVER_GET_DEVICE_PROC_ADDR.store(function as usize, Ordering::Relaxed);
+		unsafe { mem::transmute(get_device_proc_addr as GetDeviceProcAddr) }
+	}
+}
+
+/// Has pipelines created on `device` without a cache use `cache`, until `unregister`.
+pub(crate) fn register(device: &Device, cache: &PipelineCache) {
+	let mut hooks = HOOKS.write().unwrap();
+	hooks.entry(device.internal_object()).or_default().cache = cache.internal_object();
+}
+
+/// Stops using the cache registered for `device`, before it's destroyed. The device may outlive its context, so its
+/// pipelines still go through the hooks, without a cache.
+pub(crate) fn unregister(device: &Device) {
+	if let Some(hooks) = HOOKS.write().unwrap().get_mut(&device.internal_object()) {
+		hooks.cache = 0;
+	}
+}
+
+/// Runs `merge` while no pipelines are being created on any device.
+pub(crate) fn synchronized<T>(merge: impl FnOnce() -> T) -> T {
+	let _hooks = HOOKS.write().unwrap();
+	merge()
+}
+
+extern "system" fn get_device_proc_addr(device: vk::Device, name: *const c_char) -> Option<vk::PFN_vkVoidFunction> {
+	let driver: GetDeviceProcAddr = unsafe { mem::transmute(DRIVER_GET_DEVICE_PROC_ADDR.load(Ordering::Relaxed)) };
+	let function = driver(device, name)?;
+
+	let mut hooks = HOOKS.write().unwrap();
+	match unsafe { CStr::from_ptr(name) }.to_bytes() {
+		b"vkCreateGraphicsPipelines" => unsafe {
+			hooks.entry(device).or_default().create_graphics_pipelines = Some(mem::transmute(function));
+			Some(mem::transmute(create_graphics_pipelines as CreateGraphicsPipelines))
+		},
+		b"vkCreateComputePipelines" => unsafe {
+			hooks.entry(device).or_default().create_compute_pipelines = Some(mem::transmute(function));
+			Some(mem::transmute(create_compute_pipelines as CreateComputePipelines))
+		},
+		_ => Some(function),
+	}
+}
+
+extern "system" fn create_graphics_pipelines(
+	device: vk::Device,
+	cache: vk::PipelineCache,
+	count: u32,
+	infos: *const vk::GraphicsPipelineCreateInfo,
+	allocator: *const vk::AllocationCallbacks,
+	pipelines: *mut vk::Pipeline,
+) -> vk::Result {
+	let devices = HOOKS.read().unwrap();
+	let hooks = &devices[&device];
+	let cache = if cache == 0 { hooks.cache } else { cache };
+	(hooks.create_graphics_pipelines.unwrap())(device, cache, count, infos, allocator, pipelines)
+}
+
+extern "system" fn create_compute_pipelines(
+	device: vk::Device,
+	cache: vk::PipelineCache,
+	count: u32,
+	infos: *const vk::ComputePipelineCreateInfo,
+	allocator: *const vk::AllocationCallbacks,
+	pipelines: *mut vk::Pipeline,
+) -> vk::Result {
+	let devices = HOOKS.read().unwrap();
+	let hooks = &devices[&device];
+	let cache = if cache == 0 { hooks.cache } else { cache };
+	(hooks.create_compute_pipelines.unwrap())(device, cache, count, infos, allocator, pipelines)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		CreateComputePipelines,
+		CreateGraphicsPipelines,
+		DRIVER_GET_DEVICE_PROC_ADDR,
+		GetDeviceProcAddr,
+		HOOKS,
+		get_device_proc_addr,
+	};
+	use std::{ ffi::CStr, mem, os::raw::c_char, ptr, sync::atomic::{ AtomicU64, Ordering } };
+	use vk_sys as vk;
+
+	/// The cache the fake driver was last given.
+	static DRIVER_CACHE: AtomicU64 = AtomicU64::new(!0);
+
+	extern "system" fn driver_create_graphics_pipelines(
+		_device: vk::Device,
+		cache: vk::PipelineCache,
+		_count: u32,
+		_infos: *const vk::GraphicsPipelineCreateInfo,
+		_allocator: *const vk::AllocationCallbacks,
+		_pipelines: *mut vk::Pipeline,
+	) -> vk::Result {
+		DRIVER_CACHE.store(cache, Ordering::SeqCst);
+		vk::SUCCESS
+	}
+
+	extern "system" fn driver_create_compute_pipelines(
+		_device: vk::Device,
+		cache: vk::PipelineCache,
+		_count: u32,
+		_infos: *const vk::ComputePipelineCreateInfo,
+		_allocator: *const vk::AllocationCallbacks,
+		_pipelines: *mut vk::Pipeline,
+	) -> vk::Result {
+		DRIVER_CACHE.store(cache, Ordering::SeqCst);
+		vk::SUCCESS
+	}
+
+	extern "system" fn driver_destroy_pipeline() {}
+
+	extern "system" fn driver_get_device_proc_addr(
+		_device: vk::Device,
+		name: *const c_char,
+	) -> Option<vk::PFN_vkVoidFunction> {
+		unsafe {
+			match CStr::from_ptr(name).to_bytes() {
+				b"vkCreateGraphicsPipelines" =>
+					Some(mem::transmute(driver_create_graphics_pipelines as CreateGraphicsPipelines)),
+				b"vkCreateComputePipelines" =>
+					Some(mem::transmute(driver_create_compute_pipelines as CreateComputePipelines)),
+				b"vkDestroyPipeline" => Some(driver_destroy_pipeline),
+				_ => None,
+			}
+		}
+	}
+
+	/// Looks `name` up through the hooks on `device`, with the fake driver behind them.
+	fn load(device: vk::Device, name: &[u8]) -> Option<vk::PFN_vkVoidFunction> {
+		let driver = driver_get_device_proc_addr as GetDeviceProcAddr;
+		DRIVER_GET_DEVICE_PROC_ADDR.store(driver as usize, Ordering::SeqCst);
+		get_device_proc_addr(device, name.as_ptr() as *const c_char)
+	}
+
+	#[test]
+	fn fills_in_registered_cache() {
+		let device = 0x1000;
+		let create: CreateGraphicsPipelines =
+			unsafe { mem::transmute(load(device, b"vkCreateGraphicsPipelines\0").unwrap()) };
+		let compute: CreateComputePipelines =
+			unsafe { mem::transmute(load(device, b"vkCreateComputePipelines\0").unwrap()) };
+
+		HOOKS.write().unwrap().get_mut(&device).unwrap().cache = 7;
+		create(device, 0, 0, ptr::null(), ptr::null(), ptr::null_mut());
+		assert_eq!(DRIVER_CACHE.load(Ordering::SeqCst), 7);
+		compute(device, 0, 0, ptr::null(), ptr::null(), ptr::null_mut());
+		assert_eq!(DRIVER_CACHE.load(Ordering::SeqCst), 7);
+
+		// a cache passed explicitly is kept
+		create(device, 9, 0, ptr::null(), ptr::null(), ptr::null_mut());
+		assert_eq!(DRIVER_CACHE.load(Ordering::SeqCst), 9);
+
+		// unregistered devices go straight through without one
+		HOOKS.write().unwrap().get_mut(&device).unwrap().cache = 0;
+		compute(device, 0, 0, ptr::null(), ptr::null(), ptr::null_mut());
+		assert_eq!(DRIVER_CACHE.load(Ordering::SeqCst), 0);
+	}
+
+	#[test]
+	fn passes_other_functions_through() {
+		let device = 0x2000;
+		let destroy = load(device, b"vkDestroyPipeline\0").unwrap();
+		assert_eq!(destroy as usize, (driver_destroy_pipeline as vk::PFN_vkVoidFunction) as usize);
+		assert!(load(device, b"vkNotAFunction\0").is_none());
+	}
+}
@@ -0,0 +1,288 @@
+#[cfg(unix)]
+use crate::texture::ExternalTextureError;
+use std::{ mem, os::raw::c_void };
+#[cfg(unix)]
+use std::{ fs::File, os::unix::io::{ AsRawFd, IntoRawFd }, ptr, sync::Arc };
+use vk_sys as vk;
+#[cfg(unix)]
+use vulkano::OomError;
+use vulkano::{ VulkanObject, device::Device, format::Format, instance::loader };
+
+#[cfg(unix)]
+const STRUCTURE_TYPE_EXTERNAL_MEMORY_IMAGE_CREATE_INFO: vk::StructureType = 1000072001;
+#[cfg(unix)]
+const STRUCTURE_TYPE_IMPORT_MEMORY_FD_INFO_KHR: vk::StructureType = 1000074000;
+#[cfg(unix)]
+const STRUCTURE_TYPE_MEMORY_FD_PROPERTIES_KHR: vk::StructureType = 1000074001;
+#[cfg(unix)]
+const ERROR_INVALID_EXTERNAL_HANDLE: vk::Result = -1000072003i32 as u32;
+#[cfg(unix)]
+const EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_FD_BIT: u32 = 0x1;
+#[cfg(unix)]
+const EXTERNAL_MEMORY_HANDLE_TYPE_DMA_BUF_BIT_EXT: u32 = 0x200;
+/// The queue family of whatever another process or API used the memory on, which imported images are acquired from.
+#[cfg(unix)]
+pub(crate) const QUEUE_FAMILY_EXTERNAL: u32 = !0 - 1;
+
+#[cfg(unix)]
+type GetMemoryFdProperties = extern "system" fn(vk::Device, u32, i32, *mut MemoryFdProperties) -> vk::Result;
+
+// structs from VK_KHR_external_memory and VK_KHR_external_memory_fd, which vk-sys 0.4 predates
+#[cfg(unix)]
+#[repr(C)]
+struct ExternalMemoryImageCreateInfo {
+	s_type: vk::StructureType,
+	p_next: *const c_void,
+	handle_types: u32,
+}
+
+#[cfg(unix)]
+#[repr(C)]
+struct ImportMemoryFdInfo {
+	s_type: vk::StructureType,
+	p_next: *const c_void,
+	handle_type: u32,
+	fd: i32,
+}
+
+#[cfg(unix)]
+#[repr(C)]
+struct MemoryFdProperties {
+	s_type: vk::StructureType,
+	p_next: *mut c_void,
+	memory_type_bits: u32,
+}
+
+/// Vulkan functions vulkano 0.11 doesn't wrap, like format feature queries and external memory imports, loaded for
+/// one device.
+pub(crate) struct RawVk {
+	instance: vk::InstancePointers,
+	physical_device: vk::PhysicalDevice,
+	#[cfg_attr(not(unix), allow(dead_code))]
+	device: vk::DevicePointers,
+	/// `None` unless the device has `VK_KHR_external_memory_fd`.
+	#[cfg(unix)]
+	get_memory_fd_properties: Option<GetMemoryFdProperties>,
+}
+impl RawVk {
+	pub(crate) fn load(device: &Device) -> Self {
+		// the instance was created through the same loader, so loading it again can't fail
+		let entry = loader::auto_loader().expect("failed to load Vulkan");
+		let instance = device.instance().internal_object();
+		let pointers =
+			vk::InstancePointers::load(|name| unsafe {
+				mem::transmute(entry.get_instance_proc_addr(instance, name.as_ptr()))
+			});
+		let get_device_proc_addr = |name: &[u8]| -> *const c_void {
+			unsafe { pointers.GetDeviceProcAddr(device.internal_object(), name.as_ptr() as _) as *const c_void }
+		};
+		let device_pointers = vk::DevicePointers::load(|name| get_device_proc_addr(name.to_bytes_with_nul()));
+		#[cfg(unix)]
+		let get_memory_fd_properties = get_device_proc_addr(b"vkGetMemoryFdPropertiesKHR\0");
+
+		Self {
+			physical_device: device.physical_device().internal_object(),
+			device: device_pointers,
+			#[cfg(unix)]
+			get_memory_fd_properties:
+				if get_memory_fd_properties.is_null() {
+					None
+				} else {
+					Some(unsafe { mem::transmute::<*const c_void, GetMemoryFdProperties>(get_memory_fd_properties) })
+				},
+			instance: pointers,
+		}
+	}
+
+	/// Creates a sampled 2D image bound to the memory `fd` exports, which `ExternalTexture::import` describes. `fd` is
+	/// closed if this fails before the memory is imported, and owned by the memory after.
+	#[cfg(unix)]
+	pub(crate) unsafe fn import_image(
+		&self,
+		device: &Arc<Device>,
+		handle_type: ExternalHandleType,
+		fd: File,
+		allocation_size: u64,
+		dimensions: [u32; 2],
+		format: Format,
+	) -> Result<ImportedImage, ExternalTextureError> {
+		let vk_device = device.internal_object();
+		let (handle_type, tiling) =
+			match handle_type {
+				ExternalHandleType::OpaqueFd => (EXTERNAL_MEMORY_HANDLE_TYPE_OPAQUE_FD_BIT, vk::IMAGE_TILING_OPTIMAL),
+				// without VK_EXT_image_drm_format_modifier, only linear dma-bufs can be described
+				ExternalHandleType::DmaBuf => (EXTERNAL_MEMORY_HANDLE_TYPE_DMA_BUF_BIT_EXT, vk::IMAGE_TILING_LINEAR),
+			};
+
+		// opaque handles can be imported as any memory type the image can use, dma-bufs only as some
+		let mut handle_memory_types = !0;
+		if handle_type == EXTERNAL_MEMORY_HANDLE_TYPE_DMA_BUF_BIT_EXT {
+			let get_memory_fd_properties = self.get_memory_fd_properties.ok_or(ExternalTextureError::Unsupported)?;
+			let mut properties =
+				MemoryFdProperties {
+					s_type: STRUCTURE_TYPE_MEMORY_FD_PROPERTIES_KHR,
+					p_next: ptr::null_mut(),
+					memory_type_bits: 0,
+				};
+			check_import(get_memory_fd_properties(vk_device, handle_type, fd.as_raw_fd(), &mut properties))?;
+			handle_memory_types = properties.memory_type_bits;
+		}
+
+		let external_info =
+			ExternalMemoryImageCreateInfo {
+				s_type: STRUCTURE_TYPE_EXTERNAL_MEMORY_IMAGE_CREATE_INFO,
+				p_next: ptr::null(),
+				handle_types: handle_type,
+			};
+		let image_info =
+			vk::ImageCreateInfo {
+				sType: vk::STRUCTURE_TYPE_IMAGE_CREATE_INFO,
+				pNext: &external_info as *const _ as *const c_void,
+				flags: 0,
+				imageType: vk::IMAGE_TYPE_2D,
+				format: format as u32,
+				extent: vk::Extent3D { width: dimensions[0], height: dimensions[1], depth: 1 },
+				mipLevels: 1,
+				arrayLayers: 1,
+				samples: vk::SAMPLE_COUNT_1_BIT,
+				tiling: tiling,
+				usage: IMPORTED_IMAGE_USAGE,
+				sharingMode: vk::SHARING_MODE_EXCLUSIVE,
+				queueFamilyIndexCount: 0,
+				pQueueFamilyIndices: ptr::null(),
+				initialLayout: vk::IMAGE_LAYOUT_UNDEFINED,
+			};
+		let mut image = 0;
+		check_import(self.device.CreateImage(vk_device, &image_info, ptr::null(), &mut image))?;
+		let mut imported =
+			ImportedImage {
+				device: device.clone(),
+				image: image,
+				memory: 0,
+				destroy_image: self.device.DestroyImage,
+				free_memory: self.device.FreeMemory,
+			};
+
+		let mut requirements = mem::zeroed();
+		self.device.GetImageMemoryRequirements(vk_device, image, &mut requirements);
+		if allocation_size < requirements.size {
+			return Err(
+				ExternalTextureError::AllocationTooSmall { required: requirements.size, actual: allocation_size }
+			);
+		}
+		let memory_types = requirements.memoryTypeBits & handle_memory_types;
+		let memory_type =
+			device.physical_device().memory_types()
+				.filter(|ty| memory_types & (1 << ty.id()) != 0)
+				.max_by_key(|ty| ty.is_device_local())
+				.ok_or(ExternalTextureError::NoMemoryType)?;
+
+		let import_info =
+			ImportMemoryFdInfo {
+				s_type: STRUCTURE_TYPE_IMPORT_MEMORY_FD_INFO_KHR,
+				p_next: ptr::null(),
+				handle_type: handle_type,
+				fd: fd.as_raw_fd(),
+			};
+		let allocate_info =
+			vk::MemoryAllocateInfo {
+				sType: vk::STRUCTURE_TYPE_MEMORY_ALLOCATE_INFO,
+				pNext: &import_info as *const _ as *const c_void,
+				allocationSize: allocation_size,
+				memoryTypeIndex: memory_type.id(),
+			};
+		check_import(self.device.AllocateMemory(vk_device, &allocate_info, ptr::null(), &mut imported.memory))?;
+		// the memory owns it now
+		let _ = fd.into_raw_fd();
+		check_import(self.device.BindImageMemory(vk_device, image, imported.memory, 0))?;
+
+		Ok(imported)
+	}
+
+	pub(crate) fn format_features(&self, format: Format) -> FormatFeatures {
+		unsafe {
+			let mut properties: vk::FormatProperties = mem::zeroed();
+			self.instance.GetPhysicalDeviceFormatProperties(self.physical_device, format as u32, &mut properties);
+			FormatFeatures { optimal_tiling: properties.optimalTilingFeatures, buffer: properties.bufferFeatures }
+		}
+	}
+}
+
+/// What a format supports on a device, as raw `VkFormatFeatureFlags`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FormatFeatures {
+	optimal_tiling: u32,
+	buffer: u32,
+}
+impl FormatFeatures {
+	pub(crate) fn sampled_image(self) -> bool {
+		self.optimal_tiling & vk::FORMAT_FEATURE_SAMPLED_IMAGE_BIT != 0
+	}
+
+	pub(crate) fn color_attachment(self) -> bool {
+		self.optimal_tiling & vk::FORMAT_FEATURE_COLOR_ATTACHMENT_BIT != 0
+	}
+
+	pub(crate) fn color_attachment_blend(self) -> bool {
+		self.optimal_tiling & vk::FORMAT_FEATURE_COLOR_ATTACHMENT_BLEND_BIT != 0
+	}
+
+	pub(crate) fn depth_stencil_attachment(self) -> bool {
+		self.optimal_tiling & vk::FORMAT_FEATURE_DEPTH_STENCIL_ATTACHMENT_BIT != 0
+	}
+
+	pub(crate) fn vertex_buffer(self) -> bool {
+		self.buffer & vk::FORMAT_FEATURE_VERTEX_BUFFER_BIT != 0
+	}
+}
+
+/// Usage of imported images: sampled by sprites, and copied from by `Texture::clone_to`.
+#[cfg(unix)]
+pub(crate) const IMPORTED_IMAGE_USAGE: u32 = vk::IMAGE_USAGE_SAMPLED_BIT | vk::IMAGE_USAGE_TRANSFER_SRC_BIT;
+
+/// What kind of memory `RawVk::import_image` is given a file descriptor of.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ExternalHandleType {
+	OpaqueFd,
+	DmaBuf,
+}
+
+/// An image created by `RawVk::import_image` and the memory imported for it, both freed when it's dropped.
+#[cfg(unix)]
+pub(crate) struct ImportedImage {
+	device: Arc<Device>,
+	image: vk::Image,
+	/// 0 until the import succeeds.
+	memory: vk::DeviceMemory,
+	destroy_image: extern "system" fn(vk::Device, vk::Image, *const vk::AllocationCallbacks),
+	free_memory: extern "system" fn(vk::Device, vk::DeviceMemory, *const vk::AllocationCallbacks),
+}
+#[cfg(unix)]
+impl ImportedImage {
+	pub(crate) fn image(&self) -> vk::Image {
+		self.image
+	}
+}
+#[cfg(unix)]
+impl Drop for ImportedImage {
+	fn drop(&mut self) {
+		let device = self.device.internal_object();
+		(self.destroy_image)(device, self.image, ptr::null());
+		if self.memory != 0 {
+			(self.free_memory)(device, self.memory, ptr::null());
+		}
+	}
+}
+
+#[cfg(unix)]
+fn check_import(result: vk::Result) -> Result<(), ExternalTextureError> {
+	match result {
+		vk::SUCCESS => Ok(()),
+		vk::ERROR_OUT_OF_HOST_MEMORY => Err(ExternalTextureError::OomError(OomError::OutOfHostMemory)),
+		vk::ERROR_OUT_OF_DEVICE_MEMORY => Err(ExternalTextureError::OomError(OomError::OutOfDeviceMemory)),
+		vk::ERROR_TOO_MANY_OBJECTS => Err(ExternalTextureError::TooManyAllocations),
+		ERROR_INVALID_EXTERNAL_HANDLE => Err(ExternalTextureError::InvalidHandle),
+		err => unreachable!("unexpected Vulkan error {}", err as i32),
+	}
+}
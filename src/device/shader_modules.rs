@@ -0,0 +1,220 @@
+use byteorder::{ ByteOrder, LE };
+use std::{
+	collections::HashMap,
+	env,
+	fs,
+	io,
+	path::{ Path, PathBuf },
+	process::Command,
+	sync::{ Arc, Mutex },
+};
+use vulkano::{ OomError, device::Device, pipeline::shader::ShaderModule };
+
+/// First word of every SPIR-V module, in the byte order it's stored with.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+/// glslang's command line compiler, looked up on the path unless `ShaderModules::set_compiler` says otherwise.
+const DEFAULT_COMPILER: &str = "glslangValidator";
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Pipeline stage a GLSL source is compiled for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderStage {
+	Vertex,
+	Fragment,
+	Compute,
+}
+impl ShaderStage {
+	/// The extension glslang picks the stage from, which `compile_shader_dir` also reads.
+	pub fn extension(self) -> &'static str {
+		match self {
+			ShaderStage::Vertex => "vert",
+			ShaderStage::Fragment => "frag",
+			ShaderStage::Compute => "comp",
+		}
+	}
+
+	fn from_extension(extension: &str) -> Option<Self> {
+		match extension {
+			"vert" => Some(ShaderStage::Vertex),
+			"frag" => Some(ShaderStage::Fragment),
+			"comp" => Some(ShaderStage::Compute),
+			_ => None,
+		}
+	}
+}
+
+/// Shader modules loaded from SPIR-V files at runtime, for shaders that don't go through the `shader!` macro, so
+/// changing them doesn't rebuild the crate that uses them. Modules are compiled ahead of time with
+/// `compile_shader_dir`, e.g. from a build script, or on first use into a cache directory.
+///
+/// The modules carry no interface or layout information, so entry points are created with
+/// `ShaderModule::graphics_entry_point` and descriptions matching the GLSL. Without the `builtin-shaders` feature, the
+/// crate's own shaders are loaded through here too, by the names of the files in its `shaders` directory, so custom
+/// modules shouldn't reuse those names.
+pub struct ShaderModules {
+	device: Arc<Device>,
+	state: Mutex<State>,
+}
+impl ShaderModules {
+	pub(crate) fn new(device: Arc<Device>) -> Self {
+		Self {
+			device: device,
+			state: Mutex::new(State {
+				spirv_dirs: vec![],
+				cache_dir: env::temp_dir().join("nice-game-shaders"),
+				compiler: PathBuf::from(DEFAULT_COMPILER),
+				modules: HashMap::new(),
+			}),
+		}
+	}
+
+	/// Adds a directory searched for `<name>.spv` files, e.g. the output directory of `compile_shader_dir`.
+	/// Directories added first are searched first.
+	pub fn add_spirv_dir(&self, dir: impl Into<PathBuf>) {
+		self.state.lock().unwrap().spirv_dirs.push(dir.into());
+	}
+
+	/// Where `get_or_compile` puts what it compiles. Defaults to a directory in the system's temporary directory.
+	pub fn set_cache_dir(&self, dir: impl Into<PathBuf>) {
+		self.state.lock().unwrap().cache_dir = dir.into();
+	}
+
+	/// The glslang compiler `get_or_compile` runs. Defaults to `glslangValidator` on the path.
+	pub fn set_compiler(&self, compiler: impl Into<PathBuf>) {
+		self.state.lock().unwrap().compiler = compiler.into();
+	}
+
+	/// Loads `<name>.spv` from the SPIR-V directories, or returns the module already loaded under `name`.
+	pub fn get(&self, name: &str) -> Result<Arc<ShaderModule>, ShaderModuleError> {
+		let mut state = self.state.lock().unwrap();
+		if let Some(module) = state.modules.get(name) {
+			return Ok(module.clone());
+		}
+
+		let file_name = format!("{}.spv", name);
+		let path =
+			state.spirv_dirs.iter()
+				.map(|dir| dir.join(&file_name))
+				.find(|path| path.is_file())
+				.ok_or_else(|| ShaderModuleError::NotFound(name.to_owned()))?;
+		let module = self.load(&path)?;
+		state.modules.insert(name.to_owned(), module.clone());
+		Ok(module)
+	}
+
+	/// Like `get`, but falls back to compiling `source` when there's no precompiled module. Compiled modules are
+	/// kept in the cache directory under a hash of the source, so they're only compiled again when it changes.
+	pub fn get_or_compile(
+		&self,
+		name: &str,
+		stage: ShaderStage,
+		source: &str,
+	) -> Result<Arc<ShaderModule>, ShaderModuleError> {
+		match self.get(name) {
+			Err(ShaderModuleError::NotFound(_)) => (),
+			result => return result,
+		}
+
+		let mut state = self.state.lock().unwrap();
+		let hash = fnv1a(fnv1a(FNV_OFFSET_BASIS, source.as_bytes()), stage.extension().as_bytes());
+		let cached = state.cache_dir.join(format!("{}-{:016x}.spv", name, hash));
+
+		if !cached.is_file() {
+			fs::create_dir_all(&state.cache_dir)?;
+			let source_path = cached.with_extension(stage.extension());
+			fs::write(&source_path, source)?;
+			let compiled = compile(&state.compiler, &source_path, &cached);
+			let _ = fs::remove_file(&source_path);
+			compiled?;
+		}
+
+		let module = self.load(&cached)?;
+		state.modules.insert(name.to_owned(), module.clone());
+		Ok(module)
+	}
+
+	fn load(&self, path: &Path) -> Result<Arc<ShaderModule>, ShaderModuleError> {
+		let spirv = fs::read(path)?;
+		let magic = spirv.get(..4).map(LE::read_u32);
+		if spirv.len() % 4 != 0 || magic != Some(SPIRV_MAGIC) {
+			return Err(ShaderModuleError::InvalidSpirv(path.to_owned()));
+		}
+
+		// the header is checked above, and drivers validate the rest of the module as far as they need to
+		Ok(unsafe { ShaderModule::new(self.device.clone(), &spirv) }?)
+	}
+}
+
+/// Compiles every `.vert`, `.frag` and `.comp` file in `src_dir` to `<out_dir>/<file stem>.spv`, for loading with
+/// `ShaderModules::get`. Meant for build scripts and asset pipelines, so shaders are compiled once per change
+/// instead of with every build of the crate embedding them. Returns the paths written.
+pub fn compile_shader_dir(
+	compiler: impl AsRef<Path>,
+	src_dir: impl AsRef<Path>,
+	out_dir: impl AsRef<Path>,
+) -> Result<Vec<PathBuf>, ShaderModuleError> {
+	let out_dir = out_dir.as_ref();
+	fs::create_dir_all(out_dir)?;
+
+	let mut written = vec![];
+	for entry in fs::read_dir(src_dir)? {
+		let path = entry?.path();
+		let stage = path.extension().and_then(|ext| ext.to_str()).and_then(ShaderStage::from_extension);
+		let stem = path.file_stem().and_then(|stem| stem.to_str());
+		if let (Some(_), Some(stem)) = (stage, stem) {
+			let out = out_dir.join(format!("{}.spv", stem));
+			compile(compiler.as_ref(), &path, &out)?;
+			written.push(out);
+		}
+	}
+	Ok(written)
+}
+
+/// FNV-1a, which unlike `DefaultHasher` hashes the same on every build and platform, so cached modules are found again
+/// after the crate is rebuilt.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+	bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+fn compile(compiler: &Path, source: &Path, out: &Path) -> Result<(), ShaderModuleError> {
+	let output = Command::new(compiler).arg("-V").arg("-o").arg(out).arg(source).output()?;
+	if output.status.success() {
+		Ok(())
+	} else {
+		// glslang writes its errors to stdout
+		Err(ShaderModuleError::Compile {
+			source: source.to_owned(),
+			log: String::from_utf8_lossy(&output.stdout).into_owned() + &String::from_utf8_lossy(&output.stderr),
+		})
+	}
+}
+
+struct State {
+	spirv_dirs: Vec<PathBuf>,
+	cache_dir: PathBuf,
+	compiler: PathBuf,
+	modules: HashMap<String, Arc<ShaderModule>>,
+}
+
+#[derive(Debug)]
+pub enum ShaderModuleError {
+	/// No SPIR-V directory has a module with this name.
+	NotFound(String),
+	/// The file at this path isn't a SPIR-V module.
+	InvalidSpirv(PathBuf),
+	/// The compiler rejected the source, with its output.
+	Compile { source: PathBuf, log: String },
+	Io(io::Error),
+	OomError(OomError),
+}
+impl From<io::Error> for ShaderModuleError {
+	fn from(val: io::Error) -> Self {
+		ShaderModuleError::Io(val)
+	}
+}
+impl From<OomError> for ShaderModuleError {
+	fn from(val: OomError) -> Self {
+		ShaderModuleError::OomError(val)
+	}
+}
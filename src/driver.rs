@@ -0,0 +1,233 @@
+mod null;
+mod vulkan;
+
+pub use self::null::{ NULL_RENDER_ENGINE_NAME, NullDriver, NullRenderEngine };
+pub use self::vulkan::{ VULKAN_RENDER_ENGINE_NAME, VULKAN_RENDER_ENGINE_PRIORITY, VulkanDriver, VulkanRenderEngine };
+
+use crate::window::Window;
+use cgmath::Vector3;
+use std::time::Duration;
+use vulkano::{ memory::DeviceMemoryAllocError, sync::GpuFuture };
+
+/// Version of the driver interface. Drivers compare it against `DriverContext::version` before registering anything,
+/// like `GGD_DriverMain` does with `GGD_API_VERSION`, and bump it whenever the engine traits change.
+pub const DRIVER_API_VERSION: u64 = 0;
+
+/// A plugin providing render and physics engines, the native counterpart of a C driver's `GGD_DriverMain`.
+pub trait Driver {
+	/// Registers the driver's engines with `ctx`. Engines registered by a driver that doesn't return `Ready` are
+	/// discarded.
+	fn main(&self, ctx: &mut DriverContext) -> DriverStatus;
+}
+
+/// What a `Driver` returns from `main`. Mirrors `GGDriverStatus`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DriverStatus {
+	Invalid,
+	Ready,
+	Error,
+	VersionInvalid,
+}
+
+/// Handed to `Driver::main` for registering engines. Mirrors `GGD_DriverContext`.
+pub struct DriverContext<'a> {
+	version: u64,
+	registry: &'a mut DriverRegistry,
+	errors: Vec<DriverError>,
+}
+impl<'a> DriverContext<'a> {
+	pub fn version(&self) -> u64 {
+		self.version
+	}
+
+	pub fn register_render_engine(&mut self, engine: Box<RenderEngine + Send>) {
+		let names = self.registry.render_engines.iter().map(|engine| engine.name());
+		match check_name(engine.name(), names) {
+			Ok(()) => self.registry.render_engines.push(engine),
+			Err(err) => self.errors.push(err),
+		}
+	}
+
+	pub fn register_physics_engine(&mut self, engine: Box<PhysicsEngine + Send>) {
+		let names = self.registry.physics_engines.iter().map(|engine| engine.name());
+		match check_name(engine.name(), names) {
+			Ok(()) => self.registry.physics_engines.push(engine),
+			Err(err) => self.errors.push(err),
+		}
+	}
+}
+
+/// A rendering backend, e.g. the `VulkanRenderEngine`, a null renderer for servers or a software rasterizer for CI.
+/// Mirrors the identity and lifecycle of `GGD_RenderEngine`; the rest of the C interface maps onto the crate's own
+/// types and isn't repeated here. `Context` presents its windows through the engine selected in its registry.
+pub trait RenderEngine {
+	fn name(&self) -> &str;
+
+	/// Engines with higher priorities are selected first.
+	fn priority(&self) -> u64;
+
+	/// Whether the engine can run on this machine, e.g. whether a device it needs is present. Called when engines are
+	/// selected, not when they're registered.
+	fn validate(&self) -> bool {
+		true
+	}
+
+	fn shutdown(&mut self) {}
+
+	fn resize(&mut self, dimensions: [u32; 2]);
+
+	/// Draws a frame that isn't presented to a window.
+	fn draw(&mut self);
+
+	/// Presents a frame of `window` with the commands from `get_commands`, which is called like the callback of
+	/// `Window::present`. Engines that don't render into the crate's windows, like `NullRenderEngine`, only `draw`.
+	fn present(
+		&mut self,
+		window: &mut Window,
+		get_commands: &mut FnMut(&mut Window, usize, Box<GpuFuture>) -> Box<GpuFuture>,
+	) -> Result<(), DeviceMemoryAllocError> {
+		let _ = (window, get_commands);
+		self.draw();
+		Ok(())
+	}
+}
+
+/// A physics backend. Mirrors the identity and lifecycle of `GGD_PhysicsEngine`.
+pub trait PhysicsEngine {
+	fn name(&self) -> &str;
+
+	/// Engines with higher priorities are selected first.
+	fn priority(&self) -> u64;
+
+	/// Whether the engine can run on this machine. Called when engines are selected, not when they're registered.
+	fn validate(&self) -> bool {
+		true
+	}
+
+	fn shutdown(&mut self) {}
+
+	fn set_gravity(&mut self, gravity: Vector3<f32>);
+
+	fn update(&mut self, delta: Duration);
+}
+
+/// Engines registered by loaded drivers, and the ones selected among them.
+pub struct DriverRegistry {
+	render_engines: Vec<Box<RenderEngine + Send>>,
+	physics_engines: Vec<Box<PhysicsEngine + Send>>,
+	render_engine: Option<usize>,
+	physics_engine: Option<usize>,
+}
+impl DriverRegistry {
+	pub fn new() -> Self {
+		Self { render_engines: vec![], physics_engines: vec![], render_engine: None, physics_engine: None }
+	}
+
+	/// Runs `driver`'s `main`. Its engines are kept only if it returns `Ready` and registers them under names that
+	/// aren't taken yet.
+	pub fn load(&mut self, driver: &Driver) -> Result<(), DriverError> {
+		let render_count = self.render_engines.len();
+		let physics_count = self.physics_engines.len();
+
+		let (status, errors) = {
+			let mut ctx = DriverContext { version: DRIVER_API_VERSION, registry: self, errors: vec![] };
+			let status = driver.main(&mut ctx);
+			(status, ctx.errors)
+		};
+
+		let result =
+			match (status, errors.into_iter().next()) {
+				(DriverStatus::Ready, None) => return Ok(()),
+				(DriverStatus::Ready, Some(err)) => Err(err),
+				(status, _) => Err(DriverError::Status(status)),
+			};
+		self.render_engines.truncate(render_count);
+		self.physics_engines.truncate(physics_count);
+		result
+	}
+
+	pub fn render_engines(&self) -> impl Iterator<Item = &RenderEngine> {
+		self.render_engines.iter().map(|engine| &**engine as &RenderEngine)
+	}
+
+	pub fn physics_engines(&self) -> impl Iterator<Item = &PhysicsEngine> {
+		self.physics_engines.iter().map(|engine| &**engine as &PhysicsEngine)
+	}
+
+	/// Selects the render engine with the highest priority that validates, the one registered first among equals.
+	/// Replaces any engine selected before, shutting it down.
+	pub fn select_render_engine(&mut self) -> Option<&mut RenderEngine> {
+		if let Some(engine) = self.render_engine.take() {
+			self.render_engines[engine].shutdown();
+		}
+
+		self.render_engine =
+			select(self.render_engines.iter().map(|engine| (engine.priority(), engine.validate())));
+		self.render_engine()
+	}
+
+	/// Selects the physics engine with the highest priority that validates, the one registered first among equals.
+	/// Replaces any engine selected before, shutting it down.
+	pub fn select_physics_engine(&mut self) -> Option<&mut PhysicsEngine> {
+		if let Some(engine) = self.physics_engine.take() {
+			self.physics_engines[engine].shutdown();
+		}
+
+		self.physics_engine =
+			select(self.physics_engines.iter().map(|engine| (engine.priority(), engine.validate())));
+		self.physics_engine()
+	}
+
+	/// The engine chosen by the last `select_render_engine`.
+	pub fn render_engine(&mut self) -> Option<&mut RenderEngine> {
+		let engines = &mut self.render_engines;
+		self.render_engine.map(move |engine| &mut *engines[engine] as &mut RenderEngine)
+	}
+
+	/// The engine chosen by the last `select_physics_engine`.
+	pub fn physics_engine(&mut self) -> Option<&mut PhysicsEngine> {
+		let engines = &mut self.physics_engines;
+		self.physics_engine.map(move |engine| &mut *engines[engine] as &mut PhysicsEngine)
+	}
+}
+impl Drop for DriverRegistry {
+	fn drop(&mut self) {
+		if let Some(engine) = self.render_engine {
+			self.render_engines[engine].shutdown();
+		}
+		if let Some(engine) = self.physics_engine {
+			self.physics_engines[engine].shutdown();
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum DriverError {
+	/// The driver's `main` returned something other than `Ready`.
+	Status(DriverStatus),
+	/// An engine was registered without a name.
+	EmptyName,
+	/// An engine of the same kind is already registered under this name.
+	DuplicateName(String),
+}
+
+fn check_name<'a>(name: &str, mut names: impl Iterator<Item = &'a str>) -> Result<(), DriverError> {
+	if name.is_empty() {
+		Err(DriverError::EmptyName)
+	} else if names.any(|other| other == name) {
+		Err(DriverError::DuplicateName(name.to_owned()))
+	} else {
+		Ok(())
+	}
+}
+
+/// Index of the valid engine with the highest priority, the first among equals.
+fn select(engines: impl Iterator<Item = (u64, bool)>) -> Option<usize> {
+	let mut best: Option<(usize, u64)> = None;
+	for (i, (priority, valid)) in engines.enumerate() {
+		if valid && best.map_or(true, |(_, best_priority)| priority > best_priority) {
+			best = Some((i, priority));
+		}
+	}
+	best.map(|(i, _)| i)
+}
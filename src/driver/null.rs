@@ -0,0 +1,82 @@
+use crate::driver::{ DRIVER_API_VERSION, Driver, DriverContext, DriverStatus, RenderEngine };
+
+/// Name the null renderer registers under.
+pub const NULL_RENDER_ENGINE_NAME: &str = "null";
+
+/// A render engine that draws nothing and never touches Vulkan, for driving a game loop through the registry on
+/// dedicated servers and CI machines without a GPU. `Context::new` registers one with the lowest priority, so it's
+/// selected when no real renderer validates, including when Vulkan isn't installed. It counts frames and remembers its
+/// size, so code driving it with `Context::draw` behaves the same from run to run.
+///
+/// Only the engine is null. Windows, textures and batches are Vulkan objects and still need a device, so code that
+/// creates them has to check `Context::has_vulkan` first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NullRenderEngine {
+	dimensions: [u32; 2],
+	frames: u64,
+}
+impl NullRenderEngine {
+	pub fn new(dimensions: [u32; 2]) -> Self {
+		Self { dimensions: dimensions, frames: 0 }
+	}
+
+	pub fn dimensions(&self) -> [u32; 2] {
+		self.dimensions
+	}
+
+	/// Number of frames drawn so far.
+	pub fn frames(&self) -> u64 {
+		self.frames
+	}
+}
+impl RenderEngine for NullRenderEngine {
+	fn name(&self) -> &str {
+		NULL_RENDER_ENGINE_NAME
+	}
+
+	fn priority(&self) -> u64 {
+		0
+	}
+
+	fn resize(&mut self, dimensions: [u32; 2]) {
+		self.dimensions = dimensions;
+	}
+
+	fn draw(&mut self) {
+		self.frames += 1;
+	}
+}
+
+/// Registers a `NullRenderEngine` of the given size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NullDriver {
+	pub dimensions: [u32; 2],
+}
+impl Driver for NullDriver {
+	fn main(&self, ctx: &mut DriverContext) -> DriverStatus {
+		if ctx.version() != DRIVER_API_VERSION {
+			return DriverStatus::VersionInvalid;
+		}
+
+		ctx.register_render_engine(Box::new(NullRenderEngine::new(self.dimensions)));
+		DriverStatus::Ready
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::Context;
+	use super::NULL_RENDER_ENGINE_NAME;
+
+	#[test]
+	fn context_without_vulkan_selects_null_engine() {
+		let mut ctx = Context::new(None, None).unwrap();
+		if ctx.has_vulkan() {
+			return;
+		}
+
+		assert!(ctx.headless_device().is_err());
+		ctx.draw();
+		assert_eq!(ctx.render_engine().unwrap().name(), NULL_RENDER_ENGINE_NAME);
+	}
+}
@@ -0,0 +1,84 @@
+use crate::driver::{ DRIVER_API_VERSION, Driver, DriverContext, DriverStatus, RenderEngine };
+use crate::window::Window;
+use std::sync::Arc;
+use vulkano::{ instance::{ Instance, PhysicalDevice }, memory::DeviceMemoryAllocError, sync::GpuFuture };
+use winit::dpi::LogicalSize;
+
+/// Name the Vulkan renderer registers under.
+pub const VULKAN_RENDER_ENGINE_NAME: &str = "vulkan";
+
+/// Priority of the Vulkan renderer. Drivers register engines above it to be selected in its place.
+pub const VULKAN_RENDER_ENGINE_PRIORITY: u64 = 100;
+
+/// The crate's own renderer, presenting windows through `Window::present`. `Context::new` registers it when Vulkan
+/// loads, so it's selected whenever the instance has a device and no driver registers a valid engine of higher
+/// priority.
+pub struct VulkanRenderEngine {
+	instance: Arc<Instance>,
+	/// Size asked for with `resize`, applied to the next window presented.
+	resize: Option<[u32; 2]>,
+	frames: u64,
+}
+impl VulkanRenderEngine {
+	pub fn new(instance: Arc<Instance>) -> Self {
+		Self { instance: instance, resize: None, frames: 0 }
+	}
+
+	/// Number of frames presented so far.
+	pub fn frames(&self) -> u64 {
+		self.frames
+	}
+}
+impl RenderEngine for VulkanRenderEngine {
+	fn name(&self) -> &str {
+		VULKAN_RENDER_ENGINE_NAME
+	}
+
+	fn priority(&self) -> u64 {
+		VULKAN_RENDER_ENGINE_PRIORITY
+	}
+
+	fn validate(&self) -> bool {
+		PhysicalDevice::enumerate(&self.instance).next().is_some()
+	}
+
+	/// Windows are resized along with their swapchain, so this resizes the next window presented.
+	fn resize(&mut self, dimensions: [u32; 2]) {
+		self.resize = Some(dimensions);
+	}
+
+	/// Every frame is drawn into a window, so there's nothing to draw without one.
+	fn draw(&mut self) {}
+
+	fn present(
+		&mut self,
+		window: &mut Window,
+		get_commands: &mut FnMut(&mut Window, usize, Box<GpuFuture>) -> Box<GpuFuture>,
+	) -> Result<(), DeviceMemoryAllocError> {
+		if let Some([width, height]) = self.resize.take() {
+			let winit_window = window.surface().window();
+			let size = LogicalSize::from_physical((width, height), winit_window.get_hidpi_factor());
+			winit_window.set_inner_size(size);
+		}
+
+		window.present(|window, image_num, future| get_commands(window, image_num, future))?;
+		self.frames += 1;
+		Ok(())
+	}
+}
+
+/// Registers a `VulkanRenderEngine` rendering with `instance`.
+#[derive(Clone)]
+pub struct VulkanDriver {
+	pub instance: Arc<Instance>,
+}
+impl Driver for VulkanDriver {
+	fn main(&self, ctx: &mut DriverContext) -> DriverStatus {
+		if ctx.version() != DRIVER_API_VERSION {
+			return DriverStatus::VersionInvalid;
+		}
+
+		ctx.register_render_engine(Box::new(VulkanRenderEngine::new(self.instance.clone())));
+		DriverStatus::Ready
+	}
+}
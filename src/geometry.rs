@@ -0,0 +1,266 @@
+#[cfg(feature = "mesh")]
+mod ao;
+mod bvh;
+
+#[cfg(feature = "mesh")]
+pub use self::ao::AmbientOcclusionParams;
+#[cfg(feature = "mesh")]
+pub(crate) use self::ao::bake_ambient_occlusion;
+pub use self::bvh::{ Bvh, RayHit };
+
+use cgmath::{ prelude::*, vec3, Vector3 };
+use std::f32;
+
+/// CPU-side copy of the triangle data of a mesh, in object space.
+#[derive(Clone, Debug, Default)]
+pub struct MeshGeometry {
+	pub positions: Vec<[f32; 3]>,
+	pub normals: Vec<[f32; 3]>,
+	pub texcoords: Vec<[f32; 2]>,
+	pub indices: Vec<u32>,
+}
+impl MeshGeometry {
+	/// Unit cube centered on the origin, with a separate set of vertices for each face.
+	pub fn cube() -> Self {
+		let mut geometry = Self::default();
+		let faces = [
+			([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+			([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+			([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+			([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+			([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+			([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0]),
+		];
+
+		for (normal, tangent) in faces.iter() {
+			let normal = vec3(normal[0], normal[1], normal[2]);
+			let tangent = vec3(tangent[0], tangent[1], tangent[2]);
+			let bitangent = normal.cross(tangent);
+			let base = geometry.positions.len() as u32;
+
+			for (u, v) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter() {
+				let position = (normal + tangent * *u + bitangent * *v) * 0.5;
+				geometry.positions.push(position.into());
+				geometry.normals.push(normal.into());
+				geometry.texcoords.push([(u + 1.0) / 2.0, (v + 1.0) / 2.0]);
+			}
+
+			geometry.indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+		}
+
+		geometry
+	}
+
+	pub fn vertex_count(&self) -> usize {
+		self.positions.len()
+	}
+
+	pub fn triangle_count(&self) -> usize {
+		self.indices.len() / 3
+	}
+
+	pub fn position(&self, index: usize) -> Vector3<f32> {
+		self.positions[index].into()
+	}
+
+	pub fn normal(&self, index: usize) -> Vector3<f32> {
+		self.normals[index].into()
+	}
+
+	pub fn triangle_indices(&self, triangle: usize) -> [usize; 3] {
+		[
+			self.indices[triangle * 3] as usize,
+			self.indices[triangle * 3 + 1] as usize,
+			self.indices[triangle * 3 + 2] as usize,
+		]
+	}
+
+	pub fn triangle(&self, triangle: usize) -> Triangle {
+		let [a, b, c] = self.triangle_indices(triangle);
+		Triangle { a: self.position(a), b: self.position(b), c: self.position(c) }
+	}
+
+	pub fn triangles<'a>(&'a self) -> impl Iterator<Item = Triangle> + 'a {
+		(0..self.triangle_count()).map(move |i| self.triangle(i))
+	}
+
+	pub fn bounds(&self) -> Aabb {
+		self.positions.iter().fold(Aabb::empty(), |aabb, &pos| aabb.grow(pos.into()))
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Triangle {
+	pub a: Vector3<f32>,
+	pub b: Vector3<f32>,
+	pub c: Vector3<f32>,
+}
+impl Triangle {
+	pub fn normal(&self) -> Vector3<f32> {
+		let normal = (self.b - self.a).cross(self.c - self.a);
+		if normal.magnitude2() > 0.0 { normal.normalize() } else { Vector3::zero() }
+	}
+
+	pub fn area(&self) -> f32 {
+		(self.b - self.a).cross(self.c - self.a).magnitude() * 0.5
+	}
+
+	pub fn centroid(&self) -> Vector3<f32> {
+		(self.a + self.b + self.c) / 3.0
+	}
+
+	pub fn bounds(&self) -> Aabb {
+		Aabb::empty().grow(self.a).grow(self.b).grow(self.c)
+	}
+
+	/// Möller–Trumbore intersection. Returns the distance along `dir` if the ray hits the front or back face.
+	pub fn intersect_ray(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_distance: f32) -> Option<f32> {
+		let edge1 = self.b - self.a;
+		let edge2 = self.c - self.a;
+		let pvec = dir.cross(edge2);
+		let det = edge1.dot(pvec);
+		if det.abs() < 1e-8 {
+			return None;
+		}
+
+		let inv_det = 1.0 / det;
+		let tvec = origin - self.a;
+		let u = tvec.dot(pvec) * inv_det;
+		if u < 0.0 || u > 1.0 {
+			return None;
+		}
+
+		let qvec = tvec.cross(edge1);
+		let v = dir.dot(qvec) * inv_det;
+		if v < 0.0 || u + v > 1.0 {
+			return None;
+		}
+
+		let t = edge2.dot(qvec) * inv_det;
+		if t >= 0.0 && t <= max_distance { Some(t) } else { None }
+	}
+
+	/// Closest point on the triangle to `point` (Ericson, Real-Time Collision Detection 5.1.5).
+	pub fn closest_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+		let ab = self.b - self.a;
+		let ac = self.c - self.a;
+		let ap = point - self.a;
+		let d1 = ab.dot(ap);
+		let d2 = ac.dot(ap);
+		if d1 <= 0.0 && d2 <= 0.0 {
+			return self.a;
+		}
+
+		let bp = point - self.b;
+		let d3 = ab.dot(bp);
+		let d4 = ac.dot(bp);
+		if d3 >= 0.0 && d4 <= d3 {
+			return self.b;
+		}
+
+		let vc = d1 * d4 - d3 * d2;
+		if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+			return self.a + ab * (d1 / (d1 - d3));
+		}
+
+		let cp = point - self.c;
+		let d5 = ab.dot(cp);
+		let d6 = ac.dot(cp);
+		if d6 >= 0.0 && d5 <= d6 {
+			return self.c;
+		}
+
+		let vb = d5 * d2 - d1 * d6;
+		if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+			return self.a + ac * (d2 / (d2 - d6));
+		}
+
+		let va = d3 * d6 - d5 * d4;
+		if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+			return self.b + (self.c - self.b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+		}
+
+		let denom = 1.0 / (va + vb + vc);
+		self.a + ab * (vb * denom) + ac * (vc * denom)
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+	pub min: Vector3<f32>,
+	pub max: Vector3<f32>,
+}
+impl Aabb {
+	pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+		Self { min: min, max: max }
+	}
+
+	pub fn empty() -> Self {
+		Self {
+			min: vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+			max: vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+	}
+
+	pub fn grow(self, point: Vector3<f32>) -> Self {
+		Self {
+			min: vec3(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+			max: vec3(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+		}
+	}
+
+	pub fn union(self, other: Aabb) -> Self {
+		if other.is_empty() { self } else { self.grow(other.min).grow(other.max) }
+	}
+
+	pub fn expand(self, amount: f32) -> Self {
+		let amount = vec3(amount, amount, amount);
+		Self { min: self.min - amount, max: self.max + amount }
+	}
+
+	pub fn center(&self) -> Vector3<f32> {
+		(self.min + self.max) * 0.5
+	}
+
+	pub fn extent(&self) -> Vector3<f32> {
+		self.max - self.min
+	}
+
+	pub fn contains(&self, point: Vector3<f32>) -> bool {
+		point.x >= self.min.x && point.y >= self.min.y && point.z >= self.min.z &&
+			point.x <= self.max.x && point.y <= self.max.y && point.z <= self.max.z
+	}
+
+	pub fn intersects(&self, other: &Aabb) -> bool {
+		self.min.x <= other.max.x && self.max.x >= other.min.x &&
+			self.min.y <= other.max.y && self.max.y >= other.min.y &&
+			self.min.z <= other.max.z && self.max.z >= other.min.z
+	}
+
+	/// Slab test. `inv_dir` is the componentwise reciprocal of the ray direction.
+	pub fn intersect_ray(&self, origin: Vector3<f32>, inv_dir: Vector3<f32>, max_distance: f32) -> Option<f32> {
+		let mut tmin = 0.0f32;
+		let mut tmax = max_distance;
+
+		for axis in 0..3 {
+			// parallel to the slab, where a face the ray runs along would give 0 * inf
+			if inv_dir[axis].is_infinite() {
+				if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+					return None;
+				}
+				continue;
+			}
+
+			let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+			let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+			tmin = tmin.max(t1.min(t2));
+			tmax = tmax.min(t1.max(t2));
+		}
+
+		if tmin <= tmax { Some(tmin) } else { None }
+	}
+}
@@ -0,0 +1,66 @@
+use crate::geometry::{ Bvh, MeshGeometry };
+use cgmath::{ prelude::*, vec3, Vector3 };
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusionParams {
+	/// Number of hemisphere rays traced per vertex.
+	pub samples: u32,
+	/// Occluders further away than this don't darken the vertex.
+	pub max_distance: f32,
+	/// Offset along the normal to avoid self-intersection at the ray origin.
+	pub bias: f32,
+}
+impl Default for AmbientOcclusionParams {
+	fn default() -> Self {
+		Self { samples: 64, max_distance: 1.0, bias: 0.001 }
+	}
+}
+
+/// Returns one occlusion value per vertex, where 1.0 is fully unoccluded. This is slow; run it on the CPU pool.
+pub(crate) fn bake_ambient_occlusion(geometry: &MeshGeometry, params: &AmbientOcclusionParams) -> Vec<f32> {
+	let bvh = Bvh::new(geometry);
+	let samples = hemisphere_samples(params.samples.max(1));
+
+	(0..geometry.vertex_count())
+		.map(|i| {
+			let normal = geometry.normal(i);
+			if normal.magnitude2() == 0.0 {
+				return 1.0;
+			}
+
+			let normal = normal.normalize();
+			let (tangent, bitangent) = basis(normal);
+			let origin = geometry.position(i) + normal * params.bias;
+
+			let hits =
+				samples.iter()
+					.filter(|sample| {
+						let dir = tangent * sample.x + bitangent * sample.y + normal * sample.z;
+						bvh.occluded(origin, dir, params.max_distance)
+					})
+					.count();
+
+			1.0 - hits as f32 / samples.len() as f32
+		})
+		.collect()
+}
+
+/// Cosine-weighted directions on the +Z hemisphere, spread with a golden angle spiral so results are deterministic.
+fn hemisphere_samples(count: u32) -> Vec<Vector3<f32>> {
+	let golden_angle = PI * (3.0 - 5.0f32.sqrt());
+
+	(0..count)
+		.map(|i| {
+			let r = ((i as f32 + 0.5) / count as f32).sqrt();
+			let theta = i as f32 * golden_angle;
+			vec3(r * theta.cos(), r * theta.sin(), (1.0 - r * r).max(0.0).sqrt())
+		})
+		.collect()
+}
+
+fn basis(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+	let up = if normal.z.abs() < 0.999 { Vector3::unit_z() } else { Vector3::unit_x() };
+	let tangent = up.cross(normal).normalize();
+	(tangent, normal.cross(tangent))
+}
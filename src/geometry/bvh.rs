@@ -0,0 +1,194 @@
+use crate::geometry::{ Aabb, MeshGeometry, Triangle };
+use cgmath::{ vec3, Vector3 };
+
+const LEAF_SIZE: usize = 4;
+
+/// Bounding volume hierarchy over the triangles of a `MeshGeometry`.
+pub struct Bvh {
+	nodes: Vec<Node>,
+	triangles: Vec<Triangle>,
+	triangle_ids: Vec<usize>,
+}
+impl Bvh {
+	pub fn new(geometry: &MeshGeometry) -> Self {
+		Self::from_triangles(geometry.triangles().collect())
+	}
+
+	pub fn from_triangles(triangles: Vec<Triangle>) -> Self {
+		let mut triangle_ids: Vec<usize> = (0..triangles.len()).collect();
+		let centroids: Vec<Vector3<f32>> = triangles.iter().map(|tri| tri.centroid()).collect();
+		let mut nodes = vec![];
+
+		if !triangles.is_empty() {
+			Self::build(&mut nodes, &triangles, &centroids, &mut triangle_ids, 0, triangles.len());
+		}
+
+		Self { nodes: nodes, triangles: triangles, triangle_ids: triangle_ids }
+	}
+
+	pub fn bounds(&self) -> Aabb {
+		self.nodes.first().map_or(Aabb::empty(), |node| node.bounds)
+	}
+
+	pub fn triangle(&self, id: usize) -> &Triangle {
+		&self.triangles[id]
+	}
+
+	pub fn triangle_count(&self) -> usize {
+		self.triangles.len()
+	}
+
+	/// Finds the nearest triangle hit by the ray within `max_distance`. `dir` should be normalized.
+	pub fn raycast(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_distance: f32) -> Option<RayHit> {
+		let mut nearest: Option<RayHit> = None;
+		self.traverse_ray(origin, dir, max_distance, &mut |tri_id, tri, max| {
+			if let Some(distance) = tri.intersect_ray(origin, dir, max) {
+				nearest = Some(RayHit { distance: distance, triangle: tri_id, normal: tri.normal() });
+				Some(distance)
+			} else {
+				None
+			}
+		});
+		nearest
+	}
+
+	/// Returns true if anything is hit within `max_distance`. Cheaper than `raycast`.
+	pub fn occluded(&self, origin: Vector3<f32>, dir: Vector3<f32>, max_distance: f32) -> bool {
+		let mut hit = false;
+		self.traverse_ray(origin, dir, max_distance, &mut |_, tri, max| {
+			if tri.intersect_ray(origin, dir, max).is_some() {
+				hit = true;
+				Some(0.0)
+			} else {
+				None
+			}
+		});
+		hit
+	}
+
+	/// Calls `callback` with every triangle whose bounds intersect `aabb`.
+	pub fn query_aabb(&self, aabb: &Aabb, callback: &mut FnMut(usize, &Triangle)) {
+		if self.nodes.is_empty() {
+			return;
+		}
+
+		let mut stack = vec![0];
+		while let Some(index) = stack.pop() {
+			let node = &self.nodes[index];
+			if !node.bounds.intersects(aabb) {
+				continue;
+			}
+
+			match node.kind {
+				NodeKind::Leaf { start, count } => {
+					for &tri_id in &self.triangle_ids[start..start + count] {
+						let tri = &self.triangles[tri_id];
+						if tri.bounds().intersects(aabb) {
+							callback(tri_id, tri);
+						}
+					}
+				},
+				NodeKind::Branch { left, right } => {
+					stack.push(left);
+					stack.push(right);
+				},
+			}
+		}
+	}
+
+	/// Walks every leaf the ray passes through. `test` returns a new (shorter) maximum distance on a hit.
+	fn traverse_ray(
+		&self,
+		origin: Vector3<f32>,
+		dir: Vector3<f32>,
+		max_distance: f32,
+		test: &mut FnMut(usize, &Triangle, f32) -> Option<f32>,
+	) {
+		if self.nodes.is_empty() {
+			return;
+		}
+
+		let inv_dir = vec3(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+		let mut max_distance = max_distance;
+		let mut stack = vec![0];
+		while let Some(index) = stack.pop() {
+			let node = &self.nodes[index];
+			if node.bounds.intersect_ray(origin, inv_dir, max_distance).is_none() {
+				continue;
+			}
+
+			match node.kind {
+				NodeKind::Leaf { start, count } => {
+					for &tri_id in &self.triangle_ids[start..start + count] {
+						if let Some(distance) = test(tri_id, &self.triangles[tri_id], max_distance) {
+							max_distance = distance;
+							if max_distance <= 0.0 {
+								return;
+							}
+						}
+					}
+				},
+				NodeKind::Branch { left, right } => {
+					stack.push(left);
+					stack.push(right);
+				},
+			}
+		}
+	}
+
+	fn build(
+		nodes: &mut Vec<Node>,
+		triangles: &[Triangle],
+		centroids: &[Vector3<f32>],
+		triangle_ids: &mut [usize],
+		start: usize,
+		count: usize,
+	) -> usize {
+		let bounds =
+			triangle_ids[start..start + count].iter()
+				.fold(Aabb::empty(), |aabb, &id| aabb.union(triangles[id].bounds()));
+
+		let index = nodes.len();
+		nodes.push(Node { bounds: bounds, kind: NodeKind::Leaf { start: start, count: count } });
+
+		if count <= LEAF_SIZE {
+			return index;
+		}
+
+		// median split along the longest axis of the centroid bounds
+		let centroid_bounds =
+			triangle_ids[start..start + count].iter().fold(Aabb::empty(), |aabb, &id| aabb.grow(centroids[id]));
+		let extent = centroid_bounds.extent();
+		let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+		if extent[axis] <= 0.0 {
+			return index;
+		}
+
+		triangle_ids[start..start + count]
+			.sort_unstable_by(|&a, &b| centroids[a][axis].partial_cmp(&centroids[b][axis]).unwrap());
+
+		let half = count / 2;
+		let left = Self::build(nodes, triangles, centroids, triangle_ids, start, half);
+		let right = Self::build(nodes, triangles, centroids, triangle_ids, start + half, count - half);
+		nodes[index].kind = NodeKind::Branch { left: left, right: right };
+
+		index
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+	pub distance: f32,
+	pub triangle: usize,
+	pub normal: Vector3<f32>,
+}
+
+struct Node {
+	bounds: Aabb,
+	kind: NodeKind,
+}
+
+enum NodeKind {
+	Leaf { start: usize, count: usize },
+	Branch { left: usize, right: usize },
+}
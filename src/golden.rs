@@ -0,0 +1,123 @@
+use crate::capture::{ CaptureError, CaptureOptions, ReadBack };
+use crate::compositor::Compositor;
+use crate::device::DeviceCtx;
+use crate::texture::TargetTexture;
+use crate::window::Window;
+use futures::executor::block_on;
+use image::{ ImageError, RgbaImage };
+use std::{ env, fs, io, path::PathBuf };
+use vulkano::{ memory::DeviceMemoryAllocError, sync };
+
+/// When set, `GoldenImages::check` replaces golden images with what it's given instead of comparing against them.
+pub const BLESS_VAR: &str = "NICE_GAME_BLESS";
+
+/// A directory of expected images to compare offscreen renders against, e.g. in CI on a software Vulkan
+/// implementation found with `software::use_software_icd`. The images are PNGs named after their scene.
+#[derive(Clone, Debug)]
+pub struct GoldenImages {
+	dir: PathBuf,
+	tolerance: u8,
+	max_differing: f32,
+}
+impl GoldenImages {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self { dir: dir.into(), tolerance: 2, max_differing: 0.001 }
+	}
+
+	/// How far each channel of a pixel may be from the golden image before the pixel counts as different. Defaults
+	/// to 2, since implementations round and filter slightly differently.
+	pub fn tolerance(mut self, tolerance: u8) -> Self {
+		self.tolerance = tolerance;
+		self
+	}
+
+	/// Share of pixels that may be different before the image doesn't match. Defaults to 0.1%, which is enough for
+	/// edges rasterized differently.
+	pub fn max_differing(mut self, max_differing: f32) -> Self {
+		self.max_differing = max_differing;
+		self
+	}
+
+	/// Compares `image` to the golden image called `name`. When they don't match, `image` is written next to it as
+	/// `<name>.actual.png` for inspection. With `NICE_GAME_BLESS` set, `image` becomes the golden image instead.
+	pub fn check(&self, name: &str, image: &RgbaImage) -> Result<(), GoldenError> {
+		let path = self.dir.join(format!("{}.png", name));
+		if env::var_os(BLESS_VAR).is_some() {
+			fs::create_dir_all(&self.dir)?;
+			image.save(&path)?;
+			return Ok(());
+		}
+
+		let actual = self.dir.join(format!("{}.actual.png", name));
+		if !path.is_file() {
+			fs::create_dir_all(&self.dir)?;
+			image.save(&actual)?;
+			return Err(GoldenError::Missing(path));
+		}
+
+		let golden = image::open(&path)?.to_rgba();
+		let pixels = image.width() as usize * image.height() as usize;
+		let differing =
+			if golden.dimensions() != image.dimensions() {
+				pixels
+			} else {
+				let tolerance = self.tolerance;
+				let differs = |a: u8, b: u8| a.max(b) - a.min(b) > tolerance;
+				golden.pixels()
+					.zip(image.pixels())
+					.filter(|(a, b)| a.data.iter().zip(b.data.iter()).any(|(&a, &b)| differs(a, b)))
+					.count()
+			};
+
+		if differing as f32 > pixels as f32 * self.max_differing {
+			image.save(&actual)?;
+			Err(GoldenError::Mismatch { golden: path, actual: actual, differing: differing })
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Renders a frame with `Window::capture` and waits for it, for scenes compared with `GoldenImages`.
+pub fn capture_blocking(
+	window: &mut Window,
+	options: &CaptureOptions,
+	build: impl FnOnce(&mut Window, &TargetTexture, &mut Compositor) -> Result<(), DeviceMemoryAllocError>,
+) -> Result<RgbaImage, CaptureError> {
+	block_on(window.capture(options, build)?)
+}
+
+/// Renders a frame into `target` and waits for it, like `capture_blocking` but without a window or a surface, so it
+/// runs where there's no display. `device` is usually a `Context::headless_device`, with batches created for `target`.
+pub fn render_blocking(
+	device: &DeviceCtx,
+	target: &TargetTexture,
+	build: impl FnOnce(&TargetTexture, &mut Compositor) -> Result<(), DeviceMemoryAllocError>,
+) -> Result<RgbaImage, CaptureError> {
+	let read_back = ReadBack::new(device, target)?;
+	let mut compositor = Compositor::new();
+	build(target, &mut compositor)?;
+	let future = compositor.submit(device.queue(), Box::new(sync::now(device.device().clone())));
+	block_on(read_back.submit(device, future)?)
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+	/// There's no golden image at this path yet. The image checked was written next to it; run again with
+	/// `NICE_GAME_BLESS` to keep it.
+	Missing(PathBuf),
+	/// More pixels than allowed are different, or the sizes are.
+	Mismatch { golden: PathBuf, actual: PathBuf, differing: usize },
+	Io(io::Error),
+	Image(ImageError),
+}
+impl From<io::Error> for GoldenError {
+	fn from(val: io::Error) -> Self {
+		GoldenError::Io(val)
+	}
+}
+impl From<ImageError> for GoldenError {
+	fn from(val: ImageError) -> Self {
+		GoldenError::Image(val)
+	}
+}
@@ -1,24 +1,55 @@
-#![feature(await_macro, async_await, futures_api)]
-
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod anim;
+#[cfg(feature = "mesh")]
 pub mod camera;
+pub mod capture;
+pub mod clock;
+#[cfg(feature = "collision")]
+pub mod collision;
+pub mod color;
+pub mod compositor;
+pub mod cook;
 pub mod cpu_pool;
 pub mod batch;
 pub mod device;
+pub mod driver;
+pub mod geometry;
+#[cfg(feature = "golden")]
+pub mod golden;
+pub mod math;
+#[cfg(feature = "mesh")]
+pub mod nav;
+pub mod random;
+pub mod readback;
+pub mod renderer;
+pub mod replay;
+pub mod software;
+pub mod stats;
+#[cfg(feature = "mesh")]
+pub mod streaming;
 pub mod texture;
+pub mod trace;
+#[cfg(feature = "ui")]
+pub mod ui;
 pub mod window;
 
-pub use vulkano::{ command_buffer::CommandBuffer, instance::Version, sync::GpuFuture };
+pub use vulkano::{ command_buffer::CommandBuffer, format::Format, instance::Version, sync::GpuFuture };
 
-use self::device::DeviceCtx;
-use self::window::Window;
-use log::{ info, log };
-use std::{ collections::HashMap, sync::{ Arc, Weak, atomic::{ AtomicBool, Ordering } } };
+#[cfg(feature = "mesh")]
+use self::batch::mesh::{ MeshRenderPass, MeshShaders, MeshShadersError };
+use self::batch::sprite::{ SpriteBatchShaders, SpriteBatchShadersError, SpriteBatchShared };
+use self::device::{ CachingLoader, DeviceCtx, ExternalMemorySupport };
+use self::driver::{ Driver, DriverError, DriverRegistry, NullDriver, PhysicsEngine, RenderEngine, VulkanDriver };
+use self::window::{ Monitor, MonitorSource, Window, WindowState };
+use log::{ info, log, warn };
+use std::{ collections::HashMap, sync::{ Arc, Weak, atomic::Ordering }, time::Duration };
 use vulkano::{
-	device::{ Device, DeviceExtensions, Features },
-	format::Format,
+	device::{ Device, DeviceCreationError, DeviceExtensions, Features, RawDeviceExtensions },
 	framebuffer::FramebufferAbstract,
 	image::ImageViewAccess,
-	instance::{ ApplicationInfo, Instance, InstanceCreationError, PhysicalDevice },
+	instance::{ ApplicationInfo, Instance, InstanceCreationError, PhysicalDevice, QueueFamily, RawInstanceExtensions },
+	memory::DeviceMemoryAllocError,
 	swapchain::Surface,
 };
 use vulkano_win::VkSurfaceBuild;
@@ -26,100 +57,402 @@ use winit::{ Event, WindowEvent, WindowId };
 
 /// Root struct for this library. Any windows that are created using the same context will share some resources.
 pub struct Context {
-	events: EventsLoop,
-	instance: Arc<Instance>,
+	/// Created along with the first window, so contexts that only render offscreen work without a display.
+	events: Option<EventsLoop>,
+	/// `None` without Vulkan, e.g. on a dedicated server, where only the `NullRenderEngine` runs.
+	instance: Option<Arc<Instance>>,
+	/// Whether the instance has what devices need to import external memory.
+	external_memory: bool,
 	devices: Vec<Arc<DeviceCtx>>,
+	throttle: Throttle,
+	throttle_interval: Duration,
+	drivers: DriverRegistry,
 }
 impl Context {
-	pub fn new(name: Option<&str>, version: Option<Version>) -> Result<Self, InstanceCreationError> {
+	/// Registers the `VulkanRenderEngine`, and the `NullRenderEngine` below it, and selects the render engine `present`
+	/// uses. Without Vulkan, e.g. on dedicated servers and CI machines without a GPU, the context is still created and
+	/// the null engine is selected, but windows and devices can't be created; see `has_vulkan`.
+	pub fn new(name: Option<&str>, version: Option<Version>) -> Result<Self, ContextCreationError> {
+		let (instance, external_memory) =
+			match Self::create_instance(name, version) {
+				Ok((instance, external_memory)) => (Some(instance), external_memory),
+				Err(err @ InstanceCreationError::LoadingError(_))
+				| Err(err @ InstanceCreationError::InitializationFailed)
+				| Err(err @ InstanceCreationError::IncompatibleDriver) => {
+					warn!("Vulkan isn't available, only the null render engine will run: {}", err);
+					(None, false)
+				},
+				Err(err) => return Err(err.into()),
+			};
+
+		let mut drivers = DriverRegistry::new();
+		if let Some(instance) = &instance {
+			drivers.load(&VulkanDriver { instance: instance.clone() })?;
+		}
+		drivers.load(&NullDriver { dimensions: [0, 0] })?;
+		drivers.select_render_engine();
+
 		Ok(Self {
-			events: EventsLoop::new(),
-			instance:
-				Instance::new(
-					Some(&ApplicationInfo {
-						application_name: name.map(|x| x.into()),
-						application_version: version,
-						engine_name: Some("nIce Game".into()),
-						engine_version: Some(Version {
-							major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-							minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-							patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-						}),
-					}),
-					&vulkano_win::required_extensions(),
-					None
-				)?,
+			events: None,
+			instance: instance,
+			external_memory: external_memory,
 			devices: vec![],
+			throttle: Throttle::Off,
+			throttle_interval: Duration::from_millis(100),
+			drivers: drivers,
 		})
 	}
 
+	/// Also returns whether the instance has the extensions for importing external memory.
+	fn create_instance(
+		name: Option<&str>,
+		version: Option<Version>,
+	) -> Result<(Arc<Instance>, bool), InstanceCreationError> {
+		let loader = CachingLoader::load().map_err(InstanceCreationError::LoadingError)?;
+		let supported =
+			RawInstanceExtensions::supported_by_core_with_loader(&loader).map_err(InstanceCreationError::LoadingError)?;
+		let mut extensions = RawInstanceExtensions::from(&vulkano_win::required_extensions());
+		let external_memory = ExternalMemorySupport::add_instance_extensions(&mut extensions, &supported);
+
+		let instance =
+			Instance::with_loader(
+				loader,
+				Some(&ApplicationInfo {
+					application_name: name.map(|x| x.into()),
+					application_version: version,
+					engine_name: Some("nIce Game".into()),
+					engine_version: Some(Version {
+						major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+						minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+						patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+					}),
+				}),
+				extensions,
+				None
+			)?;
+		Ok((instance, external_memory))
+	}
+
+	/// Whether Vulkan loaded. Without it, `create_window` panics and `headless_device` fails.
+	pub fn has_vulkan(&self) -> bool {
+		self.instance.is_some()
+	}
+
+	/// Loads `driver` into the context's registry, then selects the render and physics engines again, so an engine
+	/// it registers is used if it has the highest priority.
+	pub fn load_driver(&mut self, driver: &Driver) -> Result<(), DriverError> {
+		self.drivers.load(driver)?;
+		self.drivers.select_render_engine();
+		self.drivers.select_physics_engine();
+		Ok(())
+	}
+
+	/// Every engine loaded so far, including the `NullRenderEngine` and, with Vulkan, the `VulkanRenderEngine`.
+	pub fn drivers(&self) -> &DriverRegistry {
+		&self.drivers
+	}
+
+	/// The engine `present` draws with. The `NullRenderEngine` on machines without a Vulkan device, unless a loaded
+	/// driver registers another engine that validates.
+	pub fn render_engine(&mut self) -> Option<&mut RenderEngine> {
+		self.drivers.render_engine()
+	}
+
+	pub fn physics_engine(&mut self) -> Option<&mut PhysicsEngine> {
+		self.drivers.physics_engine()
+	}
+
+	/// Presents a frame of `window` through the selected render engine. With the `VulkanRenderEngine`, that's
+	/// `Window::present` with `get_commands`. Does nothing if no engine is selected.
+	pub fn present(
+		&mut self,
+		window: &mut Window,
+		mut get_commands: impl FnMut(&mut Window, usize, Box<GpuFuture>) -> Box<GpuFuture>,
+	) -> Result<(), DeviceMemoryAllocError> {
+		match self.drivers.render_engine() {
+			Some(engine) => engine.present(window, &mut get_commands),
+			None => Ok(()),
+		}
+	}
+
+	/// Draws a frame through the selected render engine without a window, e.g. to step the `NullRenderEngine` where
+	/// there's no Vulkan. Does nothing if no engine is selected.
+	pub fn draw(&mut self) {
+		if let Some(engine) = self.drivers.render_engine() {
+			engine.draw();
+		}
+	}
+
+	/// Limits how often windows render, e.g. to stop burning GPU time in menus or while another window has focus.
+	/// Throttled windows sleep in `present` so frames are at least `set_throttle_interval` apart, and `MeshBatch`
+	/// shows its previous frame instead of rendering the scene again. Events are still polled as usual.
+	pub fn set_throttle(&mut self, throttle: Throttle) {
+		self.throttle = throttle;
+		self.update_throttle();
+	}
+
+	/// Shortest time between presents while throttled. Defaults to 100ms.
+	pub fn set_throttle_interval(&mut self, interval: Duration) {
+		self.throttle_interval = interval;
+		self.update_throttle();
+	}
+
+	fn update_throttle(&self) {
+		for state in self.events.iter().flat_map(|events| events.windows.values()) {
+			*state.throttle.lock().unwrap() = (self.throttle, self.throttle_interval);
+		}
+	}
+
+	fn events(&mut self) -> &mut EventsLoop {
+		self.events.get_or_insert_with(EventsLoop::new)
+	}
+
+	/// Panics without Vulkan.
 	pub fn create_window<T: Into<String>>(&mut self, title: T) -> Window {
+		let instance = self.instance.clone().expect("Vulkan isn't available");
 		let surface = winit::WindowBuilder::new()
 			.with_title(title)
-			.build_vk_surface(&self.events.events, self.instance.clone())
+			.build_vk_surface(&self.events().events, instance)
 			.expect("failed to create window");
 
 		let device = self.get_device_for_surface(&surface);
 
-		let resized = Arc::<AtomicBool>::default();
-		self.events.resized.insert(surface.window().id(), resized.clone());
+		let state = Arc::new(WindowState::new(self.throttle, self.throttle_interval));
+		self.events().windows.insert(surface.window().id(), state.clone());
 
-		Window::new(surface, device, resized)
+		Window::new(surface, device, state)
 	}
 
 	pub fn poll_events<F: FnMut(Event)>(&mut self, callback: F) {
-		self.events.poll_events(callback)
+		self.events().poll_events(callback)
+	}
+
+	/// Every monitor connected to the system.
+	pub fn monitors(&mut self) -> Vec<Monitor> {
+		let events = &self.events().events;
+		events.get_available_monitors().map(|id| Monitor::new(id, MonitorSource::Events(events))).collect()
+	}
+
+	pub fn primary_monitor(&mut self) -> Monitor {
+		let events = &self.events().events;
+		Monitor::new(events.get_primary_monitor(), MonitorSource::Events(events))
+	}
+
+	/// A device for rendering offscreen, e.g. into a `TargetTexture` for golden images in CI, without a window or a
+	/// surface. Shared with any window whose device has a graphical queue.
+	pub fn headless_device(&mut self) -> Result<Arc<DeviceCtx>, HeadlessDeviceError> {
+		for device in &self.devices {
+			if device.queue().family().supports_graphics() {
+				return Ok(device.clone());
+			}
+		}
+
+		// a local handle, since the queue family borrows it while the device is added to `self`
+		let instance = self.instance.clone().ok_or(HeadlessDeviceError::NoDevice)?;
+		let pdevice = PhysicalDevice::enumerate(&instance).next().ok_or(HeadlessDeviceError::NoDevice)?;
+		info!("Using device: {} ({:?})", pdevice.name(), pdevice.ty());
+
+		let qfam = pdevice.queue_families()
+			.find(|&q| q.supports_graphics())
+			.ok_or(HeadlessDeviceError::NoGraphicsQueue)?;
+		Ok(self.create_device(qfam, &DeviceExtensions::none())?)
+	}
+
+	/// Creates every pipeline `window` needs up front, so drivers compile them during loading instead of on the first
+	/// frame that uses them. That's both batches' shaders, and the render passes for the window's format, including one
+	/// per sprite layer blend mode, along with the mesh pipelines otherwise built on first use: one per vertex format
+	/// and `MeshRasterizer`, and the instanced and cull pipelines of `GpuCulledMesh`es. Use the returned objects in
+	/// place of creating your own.
+	///
+	/// Material shaders aren't separate pipelines and there's no multisampling, so there are no other variants. Without
+	/// the `mesh` feature, only the sprite batches' are created. Everything created goes into the device's pipeline
+	/// cache, so with `DeviceCtx::load_pipeline_cache` before this and `save_pipeline_cache` after, later runs only
+	/// read the compiled pipelines back.
+	pub fn warm_pipelines(&self, window: &mut Window) -> Result<(WarmPipelines, impl GpuFuture), WarmPipelinesError> {
+		let format = window.format();
+		let (sprite_shaders, sprite_future) = SpriteBatchShaders::new(window)?;
+		#[cfg(feature = "mesh")]
+		let (mesh_shaders, mesh_future) = MeshShaders::new(window)?;
+		#[cfg(feature = "mesh")]
+		let future = sprite_future.join(mesh_future);
+		#[cfg(not(feature = "mesh"))]
+		let future = sprite_future;
+		#[cfg(feature = "mesh")]
+		let mesh_render_pass = MeshRenderPass::new(mesh_shaders.clone(), format);
+		#[cfg(feature = "mesh")]
+		mesh_render_pass.warm();
+
+		Ok((
+			WarmPipelines {
+				sprite_shared: SpriteBatchShared::new(sprite_shaders.clone(), format),
+				#[cfg(feature = "mesh")]
+				mesh_render_pass: mesh_render_pass,
+				sprite_shaders: sprite_shaders,
+				#[cfg(feature = "mesh")]
+				mesh_shaders: mesh_shaders,
+			},
+			future,
+		))
 	}
 
 	fn get_device_for_surface<T>(&mut self, surface: &Surface<T>) -> Arc<DeviceCtx> {
 		for device in &self.devices {
 			let qfam = device.queue().family();
-			if qfam.supports_graphics() && surface.is_supported(qfam).unwrap() {
+			// headless devices don't have swapchains
+			let swapchain = device.device().loaded_extensions().khr_swapchain;
+			if swapchain && qfam.supports_graphics() && surface.is_supported(qfam).unwrap() {
 				return device.clone();
 			}
 		}
 
-		let pdevice = PhysicalDevice::enumerate(&self.instance).next().expect("no device available");
+		// a local handle, since the queue family borrows it while the device is added to `self`. surfaces are only
+		// created with the instance.
+		let instance = self.instance.clone().unwrap();
+		let pdevice = PhysicalDevice::enumerate(&instance).next().expect("no device available");
 		info!("Using device: {} ({:?})", pdevice.name(), pdevice.ty());
 
 		let qfam = pdevice.queue_families()
 			.find(|&q| q.supports_graphics() && surface.is_supported(q).unwrap())
 			.expect("failed to find a graphical queue family");
+		self.create_device(qfam, &DeviceExtensions { khr_swapchain: true, .. DeviceExtensions::none() })
+			.expect("failed to create device")
+	}
 
+	fn create_device(
+		&mut self,
+		qfam: QueueFamily,
+		extensions: &DeviceExtensions,
+	) -> Result<Arc<DeviceCtx>, DeviceCreationError> {
+		let pdevice = qfam.physical_device();
+		let mut extensions = RawDeviceExtensions::from(extensions);
+		let external_memory =
+			if self.external_memory {
+				ExternalMemorySupport::add_device_extensions(&mut extensions, pdevice)
+			} else {
+				ExternalMemorySupport::default()
+			};
 		let (device, mut queues) =
 			Device::new(
 				pdevice,
-				&Features::none(),
-				&DeviceExtensions { khr_swapchain: true, .. DeviceExtensions::none() },
+				&Features { depth_clamp: pdevice.supported_features().depth_clamp, ..Features::none() },
+				extensions,
 				[(qfam, 1.0)].iter().cloned()
-			)
-			.expect("failed to create device");
+			)?;
 		let queue = queues.next().unwrap();
 
-		let ret = DeviceCtx::new(device, queue);
+		let ret = DeviceCtx::new(device, queue, external_memory);
 		self.devices.push(ret.clone());
-		ret
+		Ok(ret)
+	}
+}
+
+#[derive(Debug)]
+pub enum ContextCreationError {
+	Instance(InstanceCreationError),
+	/// The `VulkanDriver` or the `NullDriver` didn't load.
+	Driver(DriverError),
+}
+impl From<InstanceCreationError> for ContextCreationError {
+	fn from(val: InstanceCreationError) -> Self {
+		ContextCreationError::Instance(val)
+	}
+}
+impl From<DriverError> for ContextCreationError {
+	fn from(val: DriverError) -> Self {
+		ContextCreationError::Driver(val)
+	}
+}
+
+#[derive(Debug)]
+pub enum HeadlessDeviceError {
+	/// There's no Vulkan device, not even a software one, or Vulkan isn't available at all.
+	NoDevice,
+	/// The first device can't draw.
+	NoGraphicsQueue,
+	DeviceCreation(DeviceCreationError),
+}
+impl From<DeviceCreationError> for HeadlessDeviceError {
+	fn from(val: DeviceCreationError) -> Self {
+		HeadlessDeviceError::DeviceCreation(val)
+	}
+}
+
+/// Everything created by `Context::warm_pipelines`.
+pub struct WarmPipelines {
+	pub sprite_shaders: Arc<SpriteBatchShaders>,
+	pub sprite_shared: Arc<SpriteBatchShared>,
+	#[cfg(feature = "mesh")]
+	pub mesh_shaders: Arc<MeshShaders>,
+	#[cfg(feature = "mesh")]
+	pub mesh_render_pass: Arc<MeshRenderPass>,
+}
+
+#[derive(Debug)]
+pub enum WarmPipelinesError {
+	SpriteBatchShaders(SpriteBatchShadersError),
+	#[cfg(feature = "mesh")]
+	MeshShaders(MeshShadersError),
+}
+impl From<SpriteBatchShadersError> for WarmPipelinesError {
+	fn from(val: SpriteBatchShadersError) -> Self {
+		WarmPipelinesError::SpriteBatchShaders(val)
+	}
+}
+#[cfg(feature = "mesh")]
+impl From<MeshShadersError> for WarmPipelinesError {
+	fn from(val: MeshShadersError) -> Self {
+		WarmPipelinesError::MeshShaders(val)
 	}
 }
 
+/// When `Context::set_throttle` applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Throttle {
+	/// Never throttle. The default.
+	Off,
+	/// Throttle windows that aren't focused, or whose clock is paused.
+	Background,
+	/// Throttle every window, e.g. while a static menu is showing.
+	Always,
+}
+
 pub struct EventsLoop {
 	events: winit::EventsLoop,
-	resized: HashMap<WindowId, Arc<AtomicBool>>,
+	windows: HashMap<WindowId, Arc<WindowState>>,
 }
 impl EventsLoop {
 	pub fn new() -> Self {
-		Self { events: winit::EventsLoop::new(), resized: HashMap::new() }
+		Self { events: winit::EventsLoop::new(), windows: HashMap::new() }
 	}
 
 	pub fn poll_events(&mut self, mut callback: impl FnMut(Event)) {
-		let resized = &mut self.resized;
+		let windows = &mut self.windows;
 		self.events.poll_events(|event| {
 			match event {
 				Event::WindowEvent { event: WindowEvent::CloseRequested, window_id } => {
-					resized.remove(&window_id);
+					windows.remove(&window_id);
 				},
-				Event::WindowEvent { event: WindowEvent::Resized(_), window_id } => {
-					resized[&window_id].store(true, Ordering::Relaxed);
+				Event::WindowEvent { event: WindowEvent::Resized(size), window_id } => {
+					if let Some(state) = windows.get(&window_id) {
+						state.resized.store(true, Ordering::Relaxed);
+						// winit has no minimize event, but minimized windows are resized to nothing
+						state.minimized.store(size.width <= 0.0 || size.height <= 0.0, Ordering::Relaxed);
+					}
+				},
+				Event::WindowEvent { event: WindowEvent::Focused(focused), window_id } => {
+					if let Some(state) = windows.get(&window_id) {
+						state.focused.store(focused, Ordering::Relaxed);
+					}
+				},
+				Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, window_id } => {
+					if let Some(state) = windows.get(&window_id) {
+						state.cursor.lock().unwrap().position = Some(position);
+					}
+				},
+				Event::WindowEvent { event: WindowEvent::CursorLeft { .. }, window_id } => {
+					if let Some(state) = windows.get(&window_id) {
+						state.cursor.lock().unwrap().position = None;
+					}
 				},
 				_ => (),
 			}
@@ -149,6 +482,67 @@ impl ObjectIdRoot {
 	pub fn make_id(&self) -> ObjectId {
 		ObjectId { val: Arc::downgrade(&self.val) }
 	}
+
+	/// Another root that ids made from this one are children of.
+	pub(crate) fn share(&self) -> Self {
+		Self { val: self.val.clone() }
+	}
+}
+
+/// Identifies an engine object across runs, unlike handles, which are only valid for the batch they came from. Save
+/// games and network replication can store the raw value and find the object again with the batch's lookup methods,
+/// as long as objects are re-added with the same ids.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StableId(u64);
+impl StableId {
+	pub fn new(raw: u64) -> Self {
+		StableId(raw)
+	}
+
+	pub fn raw(self) -> u64 {
+		self.0
+	}
+}
+
+/// An object was added with a `StableId` another object in the batch already has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DuplicateId(pub StableId);
+
+/// Assigns `StableId`s to the indices of a batch's objects. Ids picked automatically skip every id in use, but an id
+/// given explicitly can be one picked automatically before it, so objects with saved ids should be re-added before
+/// any without.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct StableIds {
+	next: u64,
+	indices: HashMap<StableId, usize>,
+}
+impl StableIds {
+	pub(crate) fn insert(&mut self, index: usize) -> StableId {
+		while self.indices.contains_key(&StableId(self.next)) {
+			self.next += 1;
+		}
+		let id = StableId(self.next);
+		self.next = self.next.saturating_add(1);
+		self.indices.insert(id, index);
+		id
+	}
+
+	pub(crate) fn insert_with_id(&mut self, id: StableId, index: usize) -> Result<(), DuplicateId> {
+		if self.indices.contains_key(&id) {
+			return Err(DuplicateId(id));
+		}
+		self.next = self.next.max(id.0.saturating_add(1));
+		self.indices.insert(id, index);
+		Ok(())
+	}
+
+	pub(crate) fn index(&self, id: StableId) -> Option<usize> {
+		self.indices.get(&id).cloned()
+	}
+
+	pub(crate) fn clear(&mut self) {
+		self.indices.clear();
+	}
 }
 
 #[derive(Clone)]
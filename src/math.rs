@@ -0,0 +1,7 @@
+mod quaternion;
+mod spline;
+mod transform;
+
+pub use self::quaternion::{ GpuQuaternion, quat_from_xyzw, quat_to_xyzw };
+pub use self::spline::{ PathEnd, PathFollower, Spline, SplineKind };
+pub use self::transform::Transform;
@@ -0,0 +1,15 @@
+use cgmath::Quaternion;
+
+/// A quaternion as shaders see it: x, y, z, then w, so `xyz` is the vector part and `w` the scalar. Every rotation
+/// the crate uploads, like `CameraRot` and `MeshRot`, has this layout. cgmath keeps the scalar first, so rotations
+/// go through `quat_to_xyzw` on the way to the GPU instead of being uploaded as `Quaternion`s.
+pub type GpuQuaternion = [f32; 4];
+
+pub fn quat_to_xyzw(quat: Quaternion<f32>) -> GpuQuaternion {
+	[quat.v.x, quat.v.y, quat.v.z, quat.s]
+}
+
+/// The inverse of `quat_to_xyzw`, e.g. for rotations read back from buffers or files like nmdl bones.
+pub fn quat_from_xyzw(xyzw: GpuQuaternion) -> Quaternion<f32> {
+	Quaternion::new(xyzw[3], xyzw[0], xyzw[1], xyzw[2])
+}
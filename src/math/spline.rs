@@ -0,0 +1,406 @@
+#[cfg(feature = "mesh")]
+use crate::batch::mesh::Mesh;
+#[cfg(feature = "mesh")]
+use crate::camera::Camera;
+use cgmath::{ prelude::*, Matrix3, Quaternion, Vector3 };
+use std::time::Duration;
+#[cfg(feature = "mesh")]
+use vulkano::memory::DeviceMemoryAllocError;
+
+/// Arc length table resolution. Distances are exact at the samples and linearly interpolated between them.
+const SAMPLES_PER_SEGMENT: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplineKind {
+	/// Passes through every point, with tangents from the neighboring points.
+	CatmullRom,
+	/// Cubic segments sharing their end points. Each segment after the first anchor is two control points then the
+	/// next anchor, and only the anchors are on the curve.
+	Bezier,
+}
+
+/// Curve through 3D points, with an arc length table so things can move along it at constant speed. Parameters from 0
+/// to `segment_count` are cheap but don't advance evenly, distances from 0 to `length` do.
+#[derive(Clone, Debug)]
+pub struct Spline {
+	kind: SplineKind,
+	points: Vec<Vector3<f32>>,
+	looped: bool,
+	lengths: Vec<f32>,
+}
+impl Spline {
+	/// A looped spline also joins the last point back to the first, smoothly.
+	pub fn catmull_rom(points: Vec<Vector3<f32>>, looped: bool) -> Self {
+		assert!(points.len() >= 2, "splines need at least two points");
+		Self::new(SplineKind::CatmullRom, points, looped)
+	}
+
+	/// `points` is an anchor followed by two control points and an anchor for each segment.
+	pub fn bezier(points: Vec<Vector3<f32>>) -> Self {
+		assert!(points.len() >= 4 && (points.len() - 1) % 3 == 0, "bezier splines need 3n + 1 points");
+		Self::new(SplineKind::Bezier, points, false)
+	}
+
+	fn new(kind: SplineKind, points: Vec<Vector3<f32>>, looped: bool) -> Self {
+		let mut spline = Self { kind: kind, points: points, looped: looped, lengths: vec![] };
+
+		let samples = spline.segment_count() * SAMPLES_PER_SEGMENT;
+		let mut lengths = Vec::with_capacity(samples + 1);
+		let mut length = 0.0;
+		let mut prev = spline.position(0.0);
+		lengths.push(length);
+		for i in 1..=samples {
+			let position = spline.position(i as f32 / SAMPLES_PER_SEGMENT as f32);
+			length += position.distance(prev);
+			lengths.push(length);
+			prev = position;
+		}
+
+		spline.lengths = lengths;
+		spline
+	}
+
+	pub fn kind(&self) -> SplineKind {
+		self.kind
+	}
+
+	pub fn points(&self) -> &[Vector3<f32>] {
+		&self.points
+	}
+
+	pub fn is_looped(&self) -> bool {
+		self.looped
+	}
+
+	pub fn segment_count(&self) -> usize {
+		match self.kind {
+			SplineKind::CatmullRom => if self.looped { self.points.len() } else { self.points.len() - 1 },
+			SplineKind::Bezier => (self.points.len() - 1) / 3,
+		}
+	}
+
+	/// Position at parameter `t`, clamped to the ends.
+	pub fn position(&self, t: f32) -> Vector3<f32> {
+		let (segment, t) = self.segment(t);
+		let [a, b, c, d] = self.coefficients(segment);
+		a + b * t + c * (t * t) + d * (t * t * t)
+	}
+
+	/// Derivative at parameter `t`, so its length is the speed along the curve per unit of `t`.
+	pub fn tangent(&self, t: f32) -> Vector3<f32> {
+		let (segment, t) = self.segment(t);
+		let [_, b, c, d] = self.coefficients(segment);
+		b + c * (2.0 * t) + d * (3.0 * t * t)
+	}
+
+	pub fn length(&self) -> f32 {
+		*self.lengths.last().unwrap()
+	}
+
+	/// The parameter `distance` along the curve. Distances wrap around looped splines, and are clamped otherwise.
+	pub fn parameter_at_distance(&self, distance: f32) -> f32 {
+		let distance = self.wrap_distance(distance);
+
+		// the last sample at or before `distance`
+		let sample =
+			match self.lengths.binary_search_by(|length| length.partial_cmp(&distance).unwrap()) {
+				Ok(sample) => sample,
+				Err(sample) => sample - 1,
+			}
+			.min(self.lengths.len() - 2);
+
+		let (start, end) = (self.lengths[sample], self.lengths[sample + 1]);
+		let fraction = if end > start { ((distance - start) / (end - start)).min(1.0) } else { 0.0 };
+		(sample as f32 + fraction) / SAMPLES_PER_SEGMENT as f32
+	}
+
+	pub fn position_at_distance(&self, distance: f32) -> Vector3<f32> {
+		self.position(self.parameter_at_distance(distance))
+	}
+
+	/// Unit direction of travel, or zero where the curve has no direction, like between coincident points.
+	pub fn direction_at_distance(&self, distance: f32) -> Vector3<f32> {
+		let tangent = self.tangent(self.parameter_at_distance(distance));
+		if tangent.magnitude2() > 0.0 { tangent.normalize() } else { Vector3::zero() }
+	}
+
+	/// Rotation that looks along the curve at `distance`, with `up` kept as upright as possible. This is the same
+	/// convention as `Camera`, so it can be used directly for cameras.
+	pub fn rotation_at_distance(&self, distance: f32, up: Vector3<f32>) -> Quaternion<f32> {
+		look_along(self.direction_at_distance(distance), up)
+	}
+
+	fn wrap_distance(&self, distance: f32) -> f32 {
+		let length = self.length();
+		if self.looped && length > 0.0 {
+			let distance = distance % length;
+			if distance < 0.0 { distance + length } else { distance }
+		} else {
+			distance.max(0.0).min(length)
+		}
+	}
+
+	fn segment(&self, t: f32) -> (usize, f32) {
+		let count = self.segment_count();
+		let t = t.max(0.0).min(count as f32);
+		let segment = (t.floor() as usize).min(count - 1);
+		(segment, t - segment as f32)
+	}
+
+	/// Cubic polynomial coefficients of a segment, from the constant term up.
+	fn coefficients(&self, segment: usize) -> [Vector3<f32>; 4] {
+		let [p0, p1, p2, p3] = self.segment_points(segment);
+		match self.kind {
+			SplineKind::CatmullRom =>
+				[
+					p1,
+					(p2 - p0) * 0.5,
+					(p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * 0.5,
+					(p1 * 3.0 - p0 - p2 * 3.0 + p3) * 0.5,
+				],
+			SplineKind::Bezier =>
+				[
+					p0,
+					(p1 - p0) * 3.0,
+					(p0 - p1 * 2.0 + p2) * 3.0,
+					p1 * 3.0 - p0 - p2 * 3.0 + p3,
+				],
+		}
+	}
+
+	fn segment_points(&self, segment: usize) -> [Vector3<f32>; 4] {
+		let points = &self.points;
+		match self.kind {
+			SplineKind::CatmullRom => {
+				let len = points.len();
+				let (p1, p2) = (points[segment], points[(segment + 1) % len]);
+				if self.looped {
+					[points[(segment + len - 1) % len], p1, p2, points[(segment + 2) % len]]
+				} else {
+					// mirror the missing neighbors at the ends, so the curve leaves them heading at the next point
+					let p0 = if segment == 0 { p1 * 2.0 - p2 } else { points[segment - 1] };
+					let p3 = if segment + 2 >= len { p2 * 2.0 - p1 } else { points[segment + 2] };
+					[p0, p1, p2, p3]
+				}
+			},
+			SplineKind::Bezier => {
+				let i = segment * 3;
+				[points[i], points[i + 1], points[i + 2], points[i + 3]]
+			},
+		}
+	}
+}
+
+/// What a `PathFollower` does when it reaches the end of a spline that isn't looped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathEnd {
+	Stop,
+	/// Jumps back to the start.
+	Restart,
+	/// Turns around and heads back.
+	PingPong,
+}
+
+/// Moves along a `Spline` at constant speed, for cutscene cameras and patrol routes.
+#[derive(Clone, Debug)]
+pub struct PathFollower {
+	distance: f32,
+	speed: f32,
+	end: PathEnd,
+	reversed: bool,
+	up: Vector3<f32>,
+}
+impl PathFollower {
+	/// `speed` is in units per second. Rotations keep -Y up, unless changed with `set_up`.
+	pub fn new(speed: f32, end: PathEnd) -> Self {
+		Self { distance: 0.0, speed: speed, end: end, reversed: false, up: -Vector3::unit_y() }
+	}
+
+	pub fn set_speed(&mut self, speed: f32) {
+		self.speed = speed;
+	}
+
+	pub fn set_up(&mut self, up: Vector3<f32>) {
+		self.up = up;
+	}
+
+	pub fn distance(&self) -> f32 {
+		self.distance
+	}
+
+	/// Jumps to `distance` along the spline, heading forwards.
+	pub fn set_distance(&mut self, distance: f32) {
+		self.distance = distance;
+		self.reversed = false;
+	}
+
+	/// True once a `PathEnd::Stop` follower reaches the end. Looped splines never finish.
+	pub fn is_finished(&self, spline: &Spline) -> bool {
+		!spline.is_looped() && self.end == PathEnd::Stop && self.distance >= spline.length()
+	}
+
+	pub fn update(&mut self, spline: &Spline, dt: Duration) {
+		let step = self.speed * (dt.as_secs() as f32 + dt.subsec_nanos() as f32 * 1e-9);
+		self.distance += if self.reversed { -step } else { step };
+
+		let length = spline.length();
+		if spline.is_looped() {
+			self.distance = spline.wrap_distance(self.distance);
+			return;
+		}
+
+		match self.end {
+			PathEnd::Stop => self.distance = self.distance.max(0.0).min(length),
+			PathEnd::Restart =>
+				if self.distance > length {
+					self.distance = if length > 0.0 { self.distance % length } else { 0.0 };
+				},
+			PathEnd::PingPong => {
+				if self.distance > length {
+					self.distance = (length * 2.0 - self.distance).max(0.0);
+					self.reversed = true;
+				} else if self.distance < 0.0 {
+					self.distance = (-self.distance).min(length);
+					self.reversed = false;
+				}
+			},
+		}
+	}
+
+	pub fn position(&self, spline: &Spline) -> Vector3<f32> {
+		spline.position_at_distance(self.distance)
+	}
+
+	/// Faces the way the follower is moving, so it turns around at the ends of a `PathEnd::PingPong` path.
+	pub fn rotation(&self, spline: &Spline) -> Quaternion<f32> {
+		let direction = spline.direction_at_distance(self.distance);
+		look_along(if self.reversed { -direction } else { direction }, self.up)
+	}
+
+	#[cfg(feature = "mesh")]
+	pub fn apply_to_camera(&self, spline: &Spline, camera: &mut Camera) -> Result<(), DeviceMemoryAllocError> {
+		camera.set_position(self.position(spline))?;
+		camera.set_rotation(self.rotation(spline))
+	}
+
+	#[cfg(feature = "mesh")]
+	pub fn apply_to_mesh(&self, spline: &Spline, mesh: &mut Mesh) -> Result<(), DeviceMemoryAllocError> {
+		mesh.set_position(self.position(spline))?;
+		mesh.set_rotation(self.rotation(spline))
+	}
+}
+
+/// Rotation taking -Z to `direction` and -Y towards `up`.
+fn look_along(direction: Vector3<f32>, up: Vector3<f32>) -> Quaternion<f32> {
+	let side = up.cross(direction);
+	if direction.magnitude2() == 0.0 || side.magnitude2() == 0.0 {
+		return Quaternion::one();
+	}
+
+	let side = side.normalize();
+	let up = direction.cross(side);
+	Quaternion::from(Matrix3::from_cols(side, -up, -direction))
+}
+
+#[cfg(test)]
+mod tests {
+	use cgmath::{ prelude::*, vec3, Vector3 };
+	use super::Spline;
+
+	fn points() -> Vec<Vector3<f32>> {
+		vec![
+			vec3(0.0, 0.0, 0.0),
+			vec3(2.0, 1.0, 0.0),
+			vec3(3.0, 0.0, -2.0),
+			vec3(1.0, -1.0, -4.0),
+			vec3(-1.0, 0.0, -3.0),
+		]
+	}
+
+	fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+		assert!((actual - expected).magnitude() < 1e-4, "{:?} isn't {:?}", actual, expected);
+	}
+
+	/// Position and derivative at the end of `segment` and at the start of the next, evaluated on each segment's own
+	/// polynomial, since `position` and `tangent` would pick the same segment for both.
+	fn joint(spline: &Spline, segment: usize) -> ([Vector3<f32>; 2], [Vector3<f32>; 2]) {
+		let [a, b, c, d] = spline.coefficients(segment);
+		let [next_a, next_b, _, _] = spline.coefficients((segment + 1) % spline.segment_count());
+		([a + b + c + d, b + c * 2.0 + d * 3.0], [next_a, next_b])
+	}
+
+	#[test]
+	fn catmull_rom_passes_through_its_points() {
+		let spline = Spline::catmull_rom(points(), false);
+		assert_eq!(spline.segment_count(), 4);
+		for (i, &point) in points().iter().enumerate() {
+			assert_near(spline.position(i as f32), point);
+		}
+		// clamped past the ends
+		assert_near(spline.position(-1.0), points()[0]);
+		assert_near(spline.position(10.0), points()[4]);
+	}
+
+	#[test]
+	fn bezier_passes_through_its_anchors() {
+		let spline = Spline::bezier(vec![
+			vec3(0.0, 0.0, 0.0), vec3(1.0, 2.0, 0.0), vec3(3.0, 2.0, 0.0),
+			vec3(4.0, 0.0, 0.0), vec3(5.0, -2.0, 0.0), vec3(7.0, -1.0, 1.0),
+			vec3(8.0, 0.0, 0.0),
+		]);
+		assert_eq!(spline.segment_count(), 2);
+		assert_near(spline.position(0.0), vec3(0.0, 0.0, 0.0));
+		assert_near(spline.position(1.0), vec3(4.0, 0.0, 0.0));
+		assert_near(spline.position(2.0), vec3(8.0, 0.0, 0.0));
+		// leaving each anchor towards its control point
+		assert_near(spline.tangent(0.0), vec3(3.0, 6.0, 0.0));
+		assert_near(spline.tangent(2.0), vec3(3.0, 3.0, -3.0));
+	}
+
+	#[test]
+	fn catmull_rom_is_smooth_at_segment_boundaries() {
+		for &looped in &[false, true] {
+			let spline = Spline::catmull_rom(points(), looped);
+			let joints = if looped { spline.segment_count() } else { spline.segment_count() - 1 };
+			for segment in 0..joints {
+				let ([end, end_tangent], [start, start_tangent]) = joint(&spline, segment);
+				assert_near(end, start);
+				assert_near(end_tangent, start_tangent);
+			}
+		}
+	}
+
+	#[test]
+	fn bezier_is_continuous_at_segment_boundaries() {
+		// the control points around the middle anchor aren't collinear with it, so only the position is continuous
+		let spline = Spline::bezier(vec![
+			vec3(0.0, 0.0, 0.0), vec3(1.0, 2.0, 0.0), vec3(3.0, 2.0, 0.0),
+			vec3(4.0, 0.0, 0.0), vec3(4.0, -2.0, 0.0), vec3(7.0, -1.0, 0.0),
+			vec3(8.0, 0.0, 0.0),
+		]);
+		let ([end, end_tangent], [start, start_tangent]) = joint(&spline, 0);
+		assert_near(end, start);
+		assert_near(end_tangent, vec3(3.0, -6.0, 0.0));
+		assert_near(start_tangent, vec3(0.0, -6.0, 0.0));
+	}
+
+	#[test]
+	fn looped_spline_closes() {
+		let spline = Spline::catmull_rom(points(), true);
+		assert_eq!(spline.segment_count(), 5);
+		assert_near(spline.position(5.0), points()[0]);
+		assert_near(spline.position_at_distance(spline.length() + 0.5), spline.position_at_distance(0.5));
+		assert_near(spline.position_at_distance(-0.5), spline.position_at_distance(spline.length() - 0.5));
+	}
+
+	#[test]
+	fn distances_advance_evenly() {
+		let spline = Spline::catmull_rom(vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(4.0, 0.0, 0.0)], false);
+		assert!((spline.length() - 4.0).abs() < 1e-3, "length {}", spline.length());
+		for i in 0..=8 {
+			let distance = i as f32 * 0.5;
+			assert!((spline.position_at_distance(distance).x - distance).abs() < 0.02, "at {}", distance);
+		}
+		assert_near(spline.position_at_distance(100.0), vec3(4.0, 0.0, 0.0));
+	}
+}
@@ -0,0 +1,93 @@
+use crate::anim::Lerp;
+use cgmath::{ prelude::*, ElementWise, Matrix4, Quaternion, Vector3 };
+use std::ops::Mul;
+
+/// Where something is in its parent's space: scaled, then rotated, then moved to `position`. Used for meshes, cameras,
+/// colliders and everything that syncs between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+	pub position: Vector3<f32>,
+	pub rotation: Quaternion<f32>,
+	/// Per axis, in object space.
+	pub scale: Vector3<f32>,
+}
+impl Transform {
+	/// A transform with a scale of one.
+	pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+		Self { position: position, rotation: rotation, scale: Vector3::new(1.0, 1.0, 1.0) }
+	}
+
+	pub fn identity() -> Self {
+		Self::new(Vector3::zero(), Quaternion::one())
+	}
+
+	pub fn from_position(position: Vector3<f32>) -> Self {
+		Self::new(position, Quaternion::one())
+	}
+
+	pub fn with_scale(self, scale: Vector3<f32>) -> Self {
+		Self { scale: scale, ..self }
+	}
+
+	pub fn to_matrix(&self) -> Matrix4<f32> {
+		Matrix4::from_translation(self.position)
+			* Matrix4::from(self.rotation)
+			* Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+	}
+
+	pub fn transform_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+		self.rotation.rotate_vector(point.mul_element_wise(self.scale)) + self.position
+	}
+
+	/// Like `transform_point`, without the translation.
+	pub fn transform_vector(&self, vector: Vector3<f32>) -> Vector3<f32> {
+		self.rotation.rotate_vector(vector.mul_element_wise(self.scale))
+	}
+
+	/// Transforms a surface normal, which scales inversely to the surface so it stays perpendicular. Not normalized.
+	pub fn transform_normal(&self, normal: Vector3<f32>) -> Vector3<f32> {
+		self.rotation.rotate_vector(normal.div_element_wise(self.scale))
+	}
+
+	/// Takes a point back from this transform's parent space into its object space.
+	pub fn inverse_transform_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+		self.inverse_transform_vector(point - self.position)
+	}
+
+	pub fn inverse_transform_vector(&self, vector: Vector3<f32>) -> Vector3<f32> {
+		self.rotation.invert().rotate_vector(vector).div_element_wise(self.scale)
+	}
+}
+impl Default for Transform {
+	fn default() -> Self {
+		Self::identity()
+	}
+}
+/// `parent * child` places `child`, given in `parent`'s object space, in `parent`'s own parent space. Scales are
+/// multiplied per axis, which is only exact when the parent's scale is uniform or the child isn't rotated.
+impl Mul for Transform {
+	type Output = Transform;
+
+	fn mul(self, child: Transform) -> Transform {
+		Transform {
+			position: self.transform_point(child.position),
+			rotation: self.rotation * child.rotation,
+			scale: self.scale.mul_element_wise(child.scale),
+		}
+	}
+}
+impl From<Transform> for Matrix4<f32> {
+	fn from(val: Transform) -> Self {
+		val.to_matrix()
+	}
+}
+impl Lerp for Transform {
+	fn lerp(self, other: Self, t: f32) -> Self {
+		Transform {
+			// `VectorSpace::lerp` from the prelude has the same name
+			position: Lerp::lerp(self.position, other.position, t),
+			rotation: Lerp::lerp(self.rotation, other.rotation, t),
+			scale: Lerp::lerp(self.scale, other.scale, t),
+		}
+	}
+}
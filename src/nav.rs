@@ -0,0 +1,440 @@
+use crate::batch::mesh::{ Mesh, MeshRenderPass };
+use crate::color::Color;
+use crate::geometry::{ MeshGeometry, Triangle };
+use crate::math::Transform;
+use crate::window::Window;
+use cgmath::{ prelude::*, vec2, vec3, Vector2, Vector3 };
+use std::{ cmp::Ordering, collections::{ BinaryHeap, HashMap }, f32::{ self, consts::PI }, sync::Arc };
+use vulkano::{ memory::DeviceMemoryAllocError, sync::GpuFuture };
+
+#[derive(Clone, Copy, Debug)]
+pub struct NavMeshConfig {
+	/// Portals narrower than twice this are not traversable, and paths keep this far from corners of the walkable area.
+	pub agent_radius: f32,
+	/// Steepest walkable slope, in radians.
+	pub max_slope: f32,
+	/// World up. The examples use -Y as up, matching the camera conventions there.
+	pub up: Vector3<f32>,
+	/// Vertices closer than this are merged so separately exported pieces of a level connect.
+	pub weld_distance: f32,
+	/// `find_path` endpoints further than this from the navmesh are rejected.
+	pub max_snap_distance: f32,
+}
+impl Default for NavMeshConfig {
+	fn default() -> Self {
+		Self {
+			agent_radius: 0.3,
+			max_slope: PI / 4.0,
+			up: vec3(0.0, -1.0, 0.0),
+			weld_distance: 0.001,
+			max_snap_distance: 1.0,
+		}
+	}
+}
+
+/// Triangle navigation mesh built from the walkable surfaces of level geometry.
+pub struct NavMesh {
+	config: NavMeshConfig,
+	vertices: Vec<Vector3<f32>>,
+	polys: Vec<NavPoly>,
+	/// Whether each vertex is on the edge of the walkable area, where paths keep `agent_radius` away from it.
+	boundary: Vec<bool>,
+	axes: [Vector3<f32>; 2],
+}
+impl NavMesh {
+	/// Builds a navmesh from geometry placed with the given transforms.
+	pub fn build(config: NavMeshConfig, sources: &[(&MeshGeometry, Transform)]) -> Self {
+		let up = config.up.normalize();
+		let cos_slope = config.max_slope.cos();
+		let mut vertices = vec![];
+		let mut welded = HashMap::new();
+		let mut polys = vec![];
+
+		for &(geometry, transform) in sources {
+			let mut remap = Vec::with_capacity(geometry.vertex_count());
+			for i in 0..geometry.vertex_count() {
+				let world = transform.transform_point(geometry.position(i));
+				let key = (
+					(world.x / config.weld_distance).round() as i64,
+					(world.y / config.weld_distance).round() as i64,
+					(world.z / config.weld_distance).round() as i64,
+				);
+				let index = *welded.entry(key).or_insert_with(|| {
+					vertices.push(world);
+					vertices.len() - 1
+				});
+				remap.push(index);
+			}
+
+			let has_normals = geometry.normals.len() == geometry.vertex_count();
+			for t in 0..geometry.triangle_count() {
+				let local = geometry.triangle_indices(t);
+				let verts = [remap[local[0]], remap[local[1]], remap[local[2]]];
+				if verts[0] == verts[1] || verts[1] == verts[2] || verts[0] == verts[2] {
+					continue;
+				}
+
+				let tri = Triangle { a: vertices[verts[0]], b: vertices[verts[1]], c: vertices[verts[2]] };
+				let mut normal = tri.normal();
+				if normal == Vector3::zero() {
+					continue;
+				}
+
+				// orient by the vertex normals so the winding convention of the source doesn't matter
+				if has_normals {
+					let normals = geometry.normal(local[0]) + geometry.normal(local[1]) + geometry.normal(local[2]);
+					let vertex_normal = transform.transform_normal(normals);
+					if vertex_normal.dot(normal) < 0.0 {
+						normal = -normal;
+					}
+				}
+
+				if normal.dot(up) >= cos_slope {
+					polys.push(NavPoly { verts: verts, centroid: tri.centroid(), neighbors: vec![] });
+				}
+			}
+		}
+
+		let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+		for (i, poly) in polys.iter().enumerate() {
+			for e in 0..3 {
+				let (v0, v1) = (poly.verts[e], poly.verts[(e + 1) % 3]);
+				edges.entry((v0.min(v1), v0.max(v1))).or_insert_with(Vec::new).push(i);
+			}
+		}
+
+		let mut boundary = vec![false; vertices.len()];
+		for (&(v0, v1), shared) in &edges {
+			if shared.len() < 2 || (vertices[v0] - vertices[v1]).magnitude() < config.agent_radius * 2.0 {
+				boundary[v0] = true;
+				boundary[v1] = true;
+				continue;
+			}
+
+			for &a in shared {
+				for &b in shared {
+					if a != b {
+						polys[a].neighbors.push(NavLink { poly: b, edge: [v0, v1] });
+					}
+				}
+			}
+		}
+
+		Self { config: config, vertices: vertices, polys: polys, boundary: boundary, axes: plane_axes(up) }
+	}
+
+	pub fn config(&self) -> &NavMeshConfig {
+		&self.config
+	}
+
+	pub fn poly_count(&self) -> usize {
+		self.polys.len()
+	}
+
+	/// Returns the nearest point on the navmesh, if one is within `max_snap_distance`.
+	pub fn nearest_point(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+		self.find_poly(point).map(|(_, point)| point)
+	}
+
+	/// Finds a path from `start` to `end` along the navmesh. The first and last points are `start` and `end` snapped
+	/// onto the navmesh.
+	pub fn find_path(&self, start: Vector3<f32>, end: Vector3<f32>) -> Option<Vec<Vector3<f32>>> {
+		let (start_poly, start) = self.find_poly(start)?;
+		let (end_poly, end) = self.find_poly(end)?;
+		if start_poly == end_poly {
+			return Some(vec![start, end]);
+		}
+
+		let corridor = self.find_corridor(start_poly, end_poly, end)?;
+		let portals =
+			corridor.windows(2)
+				.map(|pair| self.portal(pair[0], pair[1]))
+				.collect::<Vec<_>>();
+
+		Some(self.string_pull(start, end, &portals))
+	}
+
+	/// Builds a flat, slightly lifted mesh of the walkable area for debugging.
+	pub fn debug_mesh(
+		&self,
+		window: &Window,
+		render_pass: Arc<MeshRenderPass>,
+	) -> Result<(Mesh, impl GpuFuture + Send + Sync + 'static), DeviceMemoryAllocError> {
+		let up = self.config.up.normalize();
+		let lift = up * 0.02;
+		let mut geometry = MeshGeometry::default();
+
+		for poly in &self.polys {
+			for &vert in &poly.verts {
+				geometry.indices.push(geometry.positions.len() as u32);
+				geometry.positions.push((self.vertices[vert] + lift).into());
+				geometry.normals.push(up.into());
+				geometry.texcoords.push([0.0, 0.0]);
+			}
+		}
+
+		let color = Color::from_linear(0.1, 0.5, 1.0, 1.0);
+		Mesh::from_geometry(window, render_pass, geometry, color, Transform::identity())
+	}
+
+	fn find_poly(&self, point: Vector3<f32>) -> Option<(usize, Vector3<f32>)> {
+		self.polys.iter()
+			.enumerate()
+			.map(|(i, poly)| {
+				let closest = self.triangle(poly).closest_point(point);
+				(i, closest, (closest - point).magnitude2())
+			})
+			.filter(|&(_, _, dist2)| dist2 <= self.config.max_snap_distance * self.config.max_snap_distance)
+			.min_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal))
+			.map(|(i, closest, _)| (i, closest))
+	}
+
+	/// A* over triangle centroids.
+	fn find_corridor(&self, start: usize, goal: usize, end: Vector3<f32>) -> Option<Vec<usize>> {
+		let mut cost = vec![f32::INFINITY; self.polys.len()];
+		let mut parent = vec![None; self.polys.len()];
+		let mut closed = vec![false; self.polys.len()];
+		let mut open = BinaryHeap::new();
+
+		cost[start] = 0.0;
+		open.push(OpenPoly { estimate: (self.polys[start].centroid - end).magnitude(), poly: start });
+
+		while let Some(OpenPoly { poly, .. }) = open.pop() {
+			if poly == goal {
+				let mut corridor = vec![goal];
+				while let Some(prev) = parent[*corridor.last().unwrap()] {
+					corridor.push(prev);
+				}
+				corridor.reverse();
+				return Some(corridor);
+			}
+
+			if closed[poly] {
+				continue;
+			}
+			closed[poly] = true;
+
+			for link in &self.polys[poly].neighbors {
+				let next_cost = cost[poly] + (self.polys[link.poly].centroid - self.polys[poly].centroid).magnitude();
+				if next_cost < cost[link.poly] {
+					cost[link.poly] = next_cost;
+					parent[link.poly] = Some(poly);
+					open.push(OpenPoly {
+						estimate: next_cost + (self.polys[link.poly].centroid - end).magnitude(),
+						poly: link.poly,
+					});
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Returns the (left, right) ends of the edge shared by two adjacent polys, as seen walking from `from` to `to`.
+	/// Ends on the boundary are pulled inwards by the agent radius; ends in the middle of the walkable area aren't, so
+	/// paths across open ground stay straight.
+	fn portal(&self, from: usize, to: usize) -> (Vector3<f32>, Vector3<f32>) {
+		let link = self.polys[from].neighbors.iter().find(|link| link.poly == to).unwrap();
+		let (a, b) = (link.edge[0], link.edge[1]);
+		let (from, to) = (self.polys[from].centroid, self.polys[to].centroid);
+
+		let a_left = self.area2(from, to, self.vertices[a]) > self.area2(from, to, self.vertices[b]);
+		let (left, right) = if a_left { (a, b) } else { (b, a) };
+		let inset = (self.vertices[right] - self.vertices[left]).normalize() * self.config.agent_radius;
+		(
+			if self.boundary[left] { self.vertices[left] + inset } else { self.vertices[left] },
+			if self.boundary[right] { self.vertices[right] - inset } else { self.vertices[right] },
+		)
+	}
+
+	/// Simple stupid funnel algorithm (Mononen).
+	fn string_pull(
+		&self,
+		start: Vector3<f32>,
+		end: Vector3<f32>,
+		portals: &[(Vector3<f32>, Vector3<f32>)],
+	) -> Vec<Vector3<f32>> {
+		let mut all = Vec::with_capacity(portals.len() + 2);
+		all.push((start, start));
+		all.extend_from_slice(portals);
+		all.push((end, end));
+
+		let mut path = vec![start];
+		let (mut apex, mut left, mut right) = (start, start, start);
+		let (mut left_index, mut right_index) = (0, 0);
+		let mut i = 1;
+
+		while i < all.len() {
+			let (new_left, new_right) = all[i];
+
+			if self.area2(apex, right, new_right) >= 0.0 {
+				if self.same_point(apex, right) || self.area2(apex, left, new_right) < 0.0 {
+					right = new_right;
+					right_index = i;
+				} else {
+					path.push(left);
+					apex = left;
+					right = apex;
+					right_index = left_index;
+					i = left_index + 1;
+					continue;
+				}
+			}
+
+			if self.area2(apex, left, new_left) <= 0.0 {
+				if self.same_point(apex, left) || self.area2(apex, right, new_left) > 0.0 {
+					left = new_left;
+					left_index = i;
+				} else {
+					path.push(right);
+					apex = right;
+					left = apex;
+					left_index = right_index;
+					i = right_index + 1;
+					continue;
+				}
+			}
+
+			i += 1;
+		}
+
+		if !self.same_point(*path.last().unwrap(), end) {
+			path.push(end);
+		}
+
+		path
+	}
+
+	fn triangle(&self, poly: &NavPoly) -> Triangle {
+		Triangle { a: self.vertices[poly.verts[0]], b: self.vertices[poly.verts[1]], c: self.vertices[poly.verts[2]] }
+	}
+
+	fn project(&self, point: Vector3<f32>) -> Vector2<f32> {
+		vec2(point.dot(self.axes[0]), point.dot(self.axes[1]))
+	}
+
+	/// Twice the signed area of the triangle projected onto the ground plane. Positive when `c` is left of `a -> b`.
+	fn area2(&self, a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> f32 {
+		let (a, b, c) = (self.project(a), self.project(b), self.project(c));
+		let (ab, ac) = (b - a, c - a);
+		ab.x * ac.y - ab.y * ac.x
+	}
+
+	fn same_point(&self, a: Vector3<f32>, b: Vector3<f32>) -> bool {
+		(a - b).magnitude2() < self.config.weld_distance * self.config.weld_distance
+	}
+}
+
+struct NavPoly {
+	verts: [usize; 3],
+	centroid: Vector3<f32>,
+	neighbors: Vec<NavLink>,
+}
+
+struct NavLink {
+	poly: usize,
+	edge: [usize; 2],
+}
+
+#[derive(PartialEq)]
+struct OpenPoly {
+	estimate: f32,
+	poly: usize,
+}
+impl Eq for OpenPoly {}
+impl Ord for OpenPoly {
+	fn cmp(&self, other: &Self) -> Ordering {
+		// reversed so BinaryHeap pops the cheapest estimate first
+		other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+	}
+}
+impl PartialOrd for OpenPoly {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+fn plane_axes(up: Vector3<f32>) -> [Vector3<f32>; 2] {
+	let reference = if up.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_z() };
+	let u = reference.cross(up).normalize();
+	[u, up.cross(u)]
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::geometry::MeshGeometry;
+	use crate::math::Transform;
+	use cgmath::{ prelude::*, vec3, Vector3 };
+	use super::{ NavMesh, NavMeshConfig };
+
+	/// Floor of `size` by `size` unit cells in xz, from the origin, facing the default -Y up. Only cells `keep` returns
+	/// true for are walkable.
+	fn floor(size: u32, keep: impl Fn(u32, u32) -> bool) -> NavMesh {
+		let mut geometry = MeshGeometry::default();
+		for z in 0..=size {
+			for x in 0..=size {
+				geometry.positions.push([x as f32, 0.0, z as f32]);
+			}
+		}
+		for z in 0..size {
+			for x in 0..size {
+				if keep(x, z) {
+					let i = z * (size + 1) + x;
+					geometry.indices.extend(&[i, i + 1, i + size + 2, i, i + size + 2, i + size + 1]);
+				}
+			}
+		}
+		NavMesh::build(NavMeshConfig::default(), &[(&geometry, Transform::identity())])
+	}
+
+	fn length(path: &[Vector3<f32>]) -> f32 {
+		path.windows(2).map(|pair| (pair[1] - pair[0]).magnitude()).sum()
+	}
+
+	#[test]
+	fn open_floor_path_is_straight() {
+		let navmesh = floor(6, |_, _| true);
+		assert_eq!(navmesh.poly_count(), 72);
+
+		let (start, end) = (vec3(0.5, 0.0, 0.5), vec3(5.5, 0.0, 4.5));
+		let path = navmesh.find_path(start, end).unwrap();
+		assert_eq!(path, vec![start, end]);
+	}
+
+	#[test]
+	fn path_goes_around_a_wall() {
+		// a wall along x = 3 with a gap at the far end
+		let navmesh = floor(6, |x, z| x != 3 || z == 5);
+		let (start, end) = (vec3(1.5, 0.0, 0.5), vec3(5.5, 0.0, 0.5));
+		let path = navmesh.find_path(start, end).unwrap();
+
+		assert_eq!(path.first(), Some(&start));
+		assert_eq!(path.last(), Some(&end));
+		assert!(path.iter().any(|point| point.z > 5.0), "path doesn't use the gap: {:?}", path);
+		assert!(length(&path) > 8.0, "path cuts through the wall: {:?}", path);
+		// every segment stays on the floor
+		for pair in path.windows(2) {
+			for &t in &[0.25, 0.5, 0.75] {
+				let point = pair[0].lerp(pair[1], t);
+				let nearest = navmesh.nearest_point(point).unwrap();
+				assert!((nearest - point).magnitude() < 1e-4, "{:?} is off the navmesh", point);
+			}
+		}
+	}
+
+	#[test]
+	fn disconnected_areas_are_unreachable() {
+		let navmesh = floor(6, |x, _| x != 3);
+		assert_eq!(navmesh.find_path(vec3(1.5, 0.0, 0.5), vec3(5.5, 0.0, 0.5)), None);
+	}
+
+	#[test]
+	fn endpoints_off_the_navmesh_are_rejected() {
+		let navmesh = floor(6, |_, _| true);
+		assert_eq!(navmesh.find_path(vec3(0.5, 0.0, 0.5), vec3(0.5, -5.0, 0.5)), None);
+
+		// within max_snap_distance, the endpoint is snapped down onto the floor
+		let path = navmesh.find_path(vec3(0.5, 0.0, 0.5), vec3(4.5, -0.5, 0.5)).unwrap();
+		assert_eq!(path.last(), Some(&vec3(4.5, 0.0, 0.5)));
+	}
+}
@@ -0,0 +1,171 @@
+use crate::device::DeviceCtx;
+use crate::texture::ViewImage;
+use std::sync::Arc;
+use vulkano::{
+	buffer::{ BufferUsage, CpuAccessibleBuffer, TypedBufferAccess },
+	command_buffer::{ AutoCommandBufferBuilder, CopyBufferError, CopyBufferImageError },
+	format::{ AcceptsPixels, Format },
+	image::ImageViewAccess,
+	memory::DeviceMemoryAllocError,
+};
+
+/// Copies GPU data to the CPU every frame without waiting for it, e.g. the object id under the cursor for picking,
+/// the average luminance for auto exposure, or anything a shader writes for the game to react to. Each copy goes to
+/// the next of a ring of host visible buffers, and comes out of `try_take` once the GPU is done with it, usually a few
+/// frames later.
+///
+/// Copies are recorded into command buffers the caller submits, so a copy is only taken once the next one has been
+/// recorded; record one a frame, before taking.
+pub struct Readback<T> {
+	buffers: Vec<Slot<T>>,
+	next: usize,
+	frame: u64,
+}
+impl<T: Copy + Default + Send + Sync + 'static> Readback<T> {
+	/// Reads `len` elements at a time, with `frames` copies in flight. 3 covers a window with triple buffering.
+	pub fn new(device: &DeviceCtx, len: usize, frames: usize) -> Result<Self, DeviceMemoryAllocError> {
+		let buffers =
+			(0..frames.max(2))
+				.map(|_| {
+					let buffer =
+						CpuAccessibleBuffer::from_iter(
+							device.device().clone(),
+							BufferUsage::transfer_destination(),
+							(0..len).map(|_| T::default()),
+						)?;
+					Ok(Slot { buffer: buffer, state: SlotState::Free })
+				})
+				.collect::<Result<_, DeviceMemoryAllocError>>()?;
+
+		Ok(Self { buffers: buffers, next: 0, frame: 0 })
+	}
+
+	/// Elements in each copy.
+	pub fn len(&self) -> usize {
+		self.buffers[0].buffer.len()
+	}
+
+	/// Appends a copy of `source` to `command_buffer`. It has to be `len` elements long.
+	///
+	/// When every buffer in the ring is still waiting on the GPU or to be taken, nothing is copied and `Skipped` is
+	/// returned with the command buffer; the oldest copy isn't overwritten.
+	pub fn copy_buffer<B>(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		source: B,
+	) -> Result<ReadbackCopy, CopyBufferError>
+	where B: TypedBufferAccess<Content = [T]> + Send + Sync + 'static {
+		let buffer =
+			match self.start_copy() {
+				Some(buffer) => buffer,
+				None => return Ok(ReadbackCopy::Skipped(command_buffer)),
+			};
+		Ok(ReadbackCopy::Recorded(command_buffer.copy_buffer(source, buffer)?))
+	}
+
+	/// Appends a copy of the `dimensions` pixels of `image` at `offset` to `command_buffer`, a row at a time. They have
+	/// to add up to `len` elements of `T`, e.g. `[u8; 4]` for each pixel of an 8 bit RGBA image.
+	///
+	/// Skips the copy like `copy_buffer` does.
+	pub fn copy_image(
+		&mut self,
+		command_buffer: AutoCommandBufferBuilder,
+		image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+		offset: [u32; 2],
+		dimensions: [u32; 2],
+	) -> Result<ReadbackCopy, CopyBufferImageError>
+	where Format: AcceptsPixels<T> {
+		let buffer =
+			match self.start_copy() {
+				Some(buffer) => buffer,
+				None => return Ok(ReadbackCopy::Skipped(command_buffer)),
+			};
+		Ok(ReadbackCopy::Recorded(
+			command_buffer.copy_image_to_buffer_dimensions(
+				ViewImage(image.clone()),
+				buffer,
+				[offset[0], offset[1], 0],
+				[dimensions[0], dimensions[1], 1],
+				0,
+				1,
+				0,
+			)?
+		))
+	}
+
+	/// Takes the oldest copy the GPU has finished, without blocking.
+	pub fn try_take(&mut self) -> Option<ReadbackResult<T>> {
+		let frame = self.frame;
+		// the latest copy may not have been submitted yet, and an unsubmitted buffer reads as finished
+		let (slot, recorded) =
+			self.buffers.iter_mut()
+				.filter_map(|slot| match slot.state {
+					SlotState::Recorded(recorded) if recorded < frame => Some((slot, recorded)),
+					_ => None,
+				})
+				.min_by_key(|&(_, recorded)| recorded)?;
+
+		// the buffer stays locked until the GPU is done and the frame's future is cleaned up
+		let data = slot.buffer.read().ok()?.to_vec();
+		slot.state = SlotState::Free;
+		Some(ReadbackResult { frame: recorded, data: data })
+	}
+
+	/// Takes the newest finished copy, dropping any older ones, for values where only the latest matters.
+	pub fn take_latest(&mut self) -> Option<ReadbackResult<T>> {
+		let mut latest = None;
+		while let Some(result) = self.try_take() {
+			latest = Some(result);
+		}
+		latest
+	}
+
+	/// The buffer for the next copy, if it's free.
+	fn start_copy(&mut self) -> Option<Arc<CpuAccessibleBuffer<[T]>>> {
+		let len = self.buffers.len();
+		let slot = &mut self.buffers[self.next];
+		match slot.state {
+			SlotState::Free => (),
+			SlotState::Recorded(_) => return None,
+		}
+
+		self.frame += 1;
+		slot.state = SlotState::Recorded(self.frame);
+		self.next = (self.next + 1) % len;
+		Some(slot.buffer.clone())
+	}
+}
+
+/// Whether `Readback::copy_buffer` or `copy_image` recorded a copy. Either way, the command buffer is handed back.
+pub enum ReadbackCopy {
+	Recorded(AutoCommandBufferBuilder),
+	/// Every buffer was in use.
+	Skipped(AutoCommandBufferBuilder),
+}
+impl ReadbackCopy {
+	pub fn into_inner(self) -> AutoCommandBufferBuilder {
+		match self {
+			ReadbackCopy::Recorded(command_buffer) | ReadbackCopy::Skipped(command_buffer) => command_buffer,
+		}
+	}
+}
+
+/// A copy taken from a `Readback`.
+#[derive(Clone, Debug)]
+pub struct ReadbackResult<T> {
+	/// Counts the copies recorded, starting at 1, so results can be matched to the frame that asked for them.
+	pub frame: u64,
+	pub data: Vec<T>,
+}
+
+struct Slot<T> {
+	buffer: Arc<CpuAccessibleBuffer<[T]>>,
+	state: SlotState,
+}
+
+#[derive(Clone, Copy)]
+enum SlotState {
+	Free,
+	/// Holds the copy numbered this.
+	Recorded(u64),
+}
@@ -0,0 +1,7 @@
+mod renderer2d;
+#[cfg(feature = "mesh")]
+mod renderer3d;
+
+pub use self::renderer2d::{ DrawParams, Renderer2D };
+#[cfg(feature = "mesh")]
+pub use self::renderer3d::Renderer3D;
@@ -0,0 +1,94 @@
+use crate::RenderTarget;
+use crate::batch::sprite::{ Font, SpriteBatch, SpriteBatchShaders, SpriteBatchShadersError, SpriteBatchShared };
+use crate::color::Color;
+use crate::texture::{ Texture, TextureDeviceError };
+use crate::window::Window;
+use std::sync::Arc;
+use vulkano::{ memory::DeviceMemoryAllocError, sync::GpuFuture };
+
+/// Draws textures and text straight to a window, for prototypes and small tools. It owns the window and does the
+/// setup, future joining and presenting a `SpriteBatch` otherwise needs, so a frame is just `draw_texture` and
+/// `draw_text` calls followed by `end_frame`.
+///
+/// Everything is drawn for one frame only, in the order it was drawn, and recreated the next. That costs an upload
+/// per draw, so larger games should keep sprites in a `SpriteBatch` instead.
+pub struct Renderer2D {
+	window: Window,
+	shared: Arc<SpriteBatchShared>,
+	batch: SpriteBatch,
+}
+impl Renderer2D {
+	pub fn new(mut window: Window) -> Result<Self, SpriteBatchShadersError> {
+		let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window)?;
+		let shared = SpriteBatchShared::new(shaders, window.format());
+		let (batch, batch_future) = SpriteBatch::new(&window, &window, shared.clone())?;
+		window.join_future(shaders_future.join(batch_future));
+
+		Ok(Self { window: window, shared: shared, batch: batch })
+	}
+
+	pub fn window(&self) -> &Window {
+		&self.window
+	}
+
+	pub fn window_mut(&mut self) -> &mut Window {
+		&mut self.window
+	}
+
+	/// Shared pipelines, for drawables made outside the renderer.
+	pub fn shared(&self) -> &Arc<SpriteBatchShared> {
+		&self.shared
+	}
+
+	/// Color the window is cleared to at the start of each frame. Defaults to dark grey.
+	pub fn set_clear_color(&mut self, color: Color) {
+		self.batch.set_clear_color(color);
+	}
+
+	/// Draws `texture` this frame, with `position` in window pixels.
+	pub fn draw_texture(
+		&mut self,
+		texture: &Texture,
+		position: [f32; 2],
+		params: DrawParams,
+	) -> Result<(), TextureDeviceError> {
+		let [width, height] = texture.image().dimensions().width_height();
+		let position =
+			[position[0] - params.anchor[0] * width as f32, position[1] - params.anchor[1] * height as f32];
+		let (sprite, future) = self.shared.create_sprite(texture, position)?;
+		self.batch.add_sprite(Box::new(sprite));
+		self.window.join_future(future);
+		Ok(())
+	}
+
+	/// Draws `text` this frame, with `position` at the start of its baseline.
+	pub fn draw_text(&mut self, font: &Font, text: &str, position: [f32; 2]) -> Result<(), DeviceMemoryAllocError> {
+		let sprite = font.make_sprite(text, &self.shared, position)?;
+		self.batch.add_sprite(Box::new(sprite));
+		Ok(())
+	}
+
+	/// Presents everything drawn since the last call, and starts the next frame.
+	pub fn end_frame(&mut self) -> Result<(), DeviceMemoryAllocError> {
+		let batch = &mut self.batch;
+		let result =
+			self.window.present_composited(|window, image_num, compositor| {
+				batch.composite(window, window, image_num, compositor)
+			});
+		self.batch.clear_sprites();
+		result
+	}
+}
+
+/// How `Renderer2D::draw_texture` places a texture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawParams {
+	/// Point in the texture placed at the position, as a fraction of its size. `[0.0, 0.0]`, the default, is the top
+	/// left corner and `[0.5, 0.5]` the center.
+	pub anchor: [f32; 2],
+}
+impl Default for DrawParams {
+	fn default() -> Self {
+		Self { anchor: [0.0, 0.0] }
+	}
+}
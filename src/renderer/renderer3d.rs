@@ -0,0 +1,151 @@
+use crate::RenderTarget;
+use crate::batch::mesh::{
+	AmbientLight,
+	DirectionalLight,
+	Light,
+	Mesh,
+	MeshBatch,
+	MeshFromFileError,
+	MeshHandle,
+	MeshRenderPass,
+	MeshShaders,
+	MeshShadersError,
+};
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::compositor::Layer;
+use crate::math::Transform;
+use crate::texture::TextureDeviceError;
+use crate::window::Window;
+use cgmath::{ prelude::*, vec3 };
+use futures::executor::block_on;
+use std::{ path::Path, sync::Arc };
+use vulkano::{ memory::DeviceMemoryAllocError, sync::GpuFuture };
+
+const DEFAULT_FOVX: f32 = 90.0;
+const ZNEAR: f32 = 0.05;
+const ZFAR: f32 = 1500.0;
+
+/// Renders models to a window with a camera, a sun and a sky, for prototypes and as a starting point for using
+/// `MeshBatch` directly. It owns the window and the shaders, render pass and batch, which stay reachable through
+/// `batch_mut` to add lights or change render settings.
+pub struct Renderer3D {
+	window: Window,
+	render_pass: Arc<MeshRenderPass>,
+	batch: MeshBatch,
+	camera: Camera,
+}
+impl Renderer3D {
+	pub fn new(mut window: Window) -> Result<Self, MeshShadersError> {
+		let (shaders, shaders_future) = MeshShaders::new(&window)?;
+		let render_pass = MeshRenderPass::new(shaders, window.format());
+		let (mut batch, batch_future) = MeshBatch::new(&window, render_pass.clone())?;
+
+		// late afternoon sun under a pale sky, for daylight at the batch's default exposure
+		batch.add_light(Light::Directional(DirectionalLight {
+			direction: vec3(0.3, 1.0, -0.5).normalize(),
+			color: Color::from_temperature(5000.0),
+			illuminance: 2000.0,
+		}));
+		batch.set_ambient(
+			AmbientLight::gradient(
+				Color::from_hex(0x87a8d0),
+				Color::from_hex(0xc8c8c8),
+				Color::from_hex(0x4a4038),
+				200.0,
+				vec3(0.0, -1.0, 0.0),
+			)
+		);
+
+		let [width, height] = window.images()[0].dimensions().width_height();
+		let camera =
+			Camera::new(
+				&window,
+				Transform::identity(),
+				width as f32 / height as f32,
+				DEFAULT_FOVX,
+				ZNEAR,
+				ZFAR,
+			)?;
+
+		window.join_future(shaders_future.join(batch_future));
+
+		Ok(Self { window: window, render_pass: render_pass, batch: batch, camera: camera })
+	}
+
+	pub fn window(&self) -> &Window {
+		&self.window
+	}
+
+	pub fn window_mut(&mut self) -> &mut Window {
+		&mut self.window
+	}
+
+	pub fn batch(&self) -> &MeshBatch {
+		&self.batch
+	}
+
+	pub fn batch_mut(&mut self) -> &mut MeshBatch {
+		&mut self.batch
+	}
+
+	/// The render pass models are loaded for, to create meshes outside the renderer.
+	pub fn render_pass(&self) -> &Arc<MeshRenderPass> {
+		&self.render_pass
+	}
+
+	pub fn camera(&self) -> &Camera {
+		&self.camera
+	}
+
+	/// Loads a nice model at the origin, waiting until it's read. Move it with `set_model_transform`.
+	pub fn load_model(
+		&mut self,
+		path: impl AsRef<Path> + Clone + Send + 'static,
+	) -> Result<MeshHandle, MeshFromFileError> {
+		let render_pass = self.render_pass.clone();
+		let (mesh, future) =
+			block_on(Mesh::from_file(&self.window, render_pass, path, Transform::identity()))?;
+		self.window.join_future(future);
+		Ok(self.batch.add_mesh(mesh))
+	}
+
+	pub fn set_model_transform(
+		&mut self,
+		handle: MeshHandle,
+		transform: Transform,
+	) -> Result<(), DeviceMemoryAllocError> {
+		self.batch.mesh_mut(handle).set_transform(transform)
+	}
+
+	/// `fovx` is the horizontal field of view in degrees. Defaults to the origin looking down -z, with a 90 degree
+	/// field of view.
+	pub fn set_camera(&mut self, transform: Transform, fovx: f32) -> Result<(), DeviceMemoryAllocError> {
+		self.camera.set_transform(transform)?;
+		if fovx != self.camera.fovx() {
+			self.camera.set_fovx(fovx)?;
+		}
+		Ok(())
+	}
+
+	/// Draws the models and presents the frame. The camera's aspect ratio follows the window's size.
+	pub fn render(&mut self) -> Result<(), TextureDeviceError> {
+		let [width, height] = self.window.images()[0].dimensions().width_height();
+		let aspect = width as f32 / height as f32;
+		if aspect != self.camera.aspect() {
+			let fovx = self.camera.fovx();
+			self.camera.set_projection(aspect, fovx, ZNEAR, ZFAR)?;
+		}
+
+		let (batch, camera) = (&mut self.batch, &self.camera);
+		let mut result = Ok(());
+		self.window.present_composited(|window, image_num, compositor| {
+			match batch.commands(window, window, image_num, camera) {
+				Ok((commands, future)) => compositor.add(Layer::SCENE, commands, future),
+				Err(err) => result = Err(err),
+			}
+			Ok(())
+		})?;
+		result
+	}
+}
@@ -0,0 +1,244 @@
+use crate::random::Rng;
+use byteorder::{ LE, ReadBytesExt, WriteBytesExt };
+use std::{
+	fs::File,
+	io::{ self, BufReader, BufWriter, prelude::* },
+	path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"NGRP";
+const VERSION: u32 = 1;
+
+/// Input state for one frame that can be written to a replay. Implement this for whatever a game reads from its
+/// input devices each frame.
+pub trait ReplayState: Sized {
+	fn write_state(&self, out: &mut Write) -> io::Result<()>;
+	fn read_state(input: &mut Read) -> io::Result<Self>;
+}
+
+/// The seed of a run and the input state of every frame in it. Feeding the same states to a game that only draws
+/// random numbers from `rng` reproduces the run.
+#[derive(Clone, Debug)]
+pub struct Replay<T> {
+	seed: u64,
+	frames: Vec<T>,
+}
+impl<T> Replay<T> {
+	pub fn new(seed: u64) -> Self {
+		Self { seed: seed, frames: vec![] }
+	}
+
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	pub fn frames(&self) -> &[T] {
+		&self.frames
+	}
+
+	/// Random number generator for `frame` of this run.
+	pub fn rng(&self, frame: u64) -> Rng {
+		Rng::for_frame(self.seed, frame)
+	}
+
+	/// Appends the input state of the next frame.
+	pub fn record(&mut self, state: T) {
+		self.frames.push(state);
+	}
+
+	pub fn play(&self) -> ReplayPlayer<T> {
+		ReplayPlayer { replay: self, frame: 0 }
+	}
+}
+impl<T: ReplayState> Replay<T> {
+	pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+		Self::read_from(&mut BufReader::new(File::open(path)?))
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		let mut out = BufWriter::new(File::create(path)?);
+		self.write_to(&mut out)?;
+		out.flush()
+	}
+
+	pub fn read_from(input: &mut Read) -> io::Result<Self> {
+		let mut magic = [0; 4];
+		input.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "not a replay file"));
+		}
+
+		let version = input.read_u32::<LE>()?;
+		if version != VERSION {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported replay version {}", version)));
+		}
+
+		let seed = input.read_u64::<LE>()?;
+		let frame_count = input.read_u64::<LE>()? as usize;
+		let frames = (0..frame_count).map(|_| T::read_state(input)).collect::<Result<_, _>>()?;
+
+		Ok(Self { seed: seed, frames: frames })
+	}
+
+	pub fn write_to(&self, out: &mut Write) -> io::Result<()> {
+		out.write_all(MAGIC)?;
+		out.write_u32::<LE>(VERSION)?;
+		out.write_u64::<LE>(self.seed)?;
+		out.write_u64::<LE>(self.frames.len() as u64)?;
+		for state in &self.frames {
+			state.write_state(out)?;
+		}
+		Ok(())
+	}
+}
+
+/// Steps through a replay one frame at a time.
+pub struct ReplayPlayer<'a, T> {
+	replay: &'a Replay<T>,
+	frame: usize,
+}
+impl<'a, T> ReplayPlayer<'a, T> {
+	/// Index of the frame `next` returns next.
+	pub fn frame(&self) -> usize {
+		self.frame
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.frame >= self.replay.frames.len()
+	}
+
+	/// Random number generator for the frame `next` returns next.
+	pub fn rng(&self) -> Rng {
+		self.replay.rng(self.frame as u64)
+	}
+}
+impl<'a, T> Iterator for ReplayPlayer<'a, T> {
+	type Item = &'a T;
+
+	fn next(&mut self) -> Option<&'a T> {
+		let ret = self.replay.frames.get(self.frame);
+		if ret.is_some() {
+			self.frame += 1;
+		}
+		ret
+	}
+}
+
+impl ReplayState for bool {
+	fn write_state(&self, out: &mut Write) -> io::Result<()> {
+		out.write_u8(*self as u8)
+	}
+
+	fn read_state(input: &mut Read) -> io::Result<Self> {
+		Ok(input.read_u8()? != 0)
+	}
+}
+
+impl ReplayState for u8 {
+	fn write_state(&self, out: &mut Write) -> io::Result<()> {
+		out.write_u8(*self)
+	}
+
+	fn read_state(input: &mut Read) -> io::Result<Self> {
+		input.read_u8()
+	}
+}
+
+macro_rules! impl_replay_state {
+	($ty:ty, $write:ident, $read:ident) => {
+		impl ReplayState for $ty {
+			fn write_state(&self, out: &mut Write) -> io::Result<()> {
+				out.$write::<LE>(*self)
+			}
+
+			fn read_state(input: &mut Read) -> io::Result<Self> {
+				input.$read::<LE>()
+			}
+		}
+	};
+}
+impl_replay_state!(u16, write_u16, read_u16);
+impl_replay_state!(u32, write_u32, read_u32);
+impl_replay_state!(u64, write_u64, read_u64);
+impl_replay_state!(i32, write_i32, read_i32);
+impl_replay_state!(f32, write_f32, read_f32);
+impl_replay_state!(f64, write_f64, read_f64);
+
+impl<A: ReplayState, B: ReplayState> ReplayState for (A, B) {
+	fn write_state(&self, out: &mut Write) -> io::Result<()> {
+		self.0.write_state(out)?;
+		self.1.write_state(out)
+	}
+
+	fn read_state(input: &mut Read) -> io::Result<Self> {
+		Ok((A::read_state(input)?, B::read_state(input)?))
+	}
+}
+
+impl<A: ReplayState, B: ReplayState, C: ReplayState> ReplayState for (A, B, C) {
+	fn write_state(&self, out: &mut Write) -> io::Result<()> {
+		self.0.write_state(out)?;
+		self.1.write_state(out)?;
+		self.2.write_state(out)
+	}
+
+	fn read_state(input: &mut Read) -> io::Result<Self> {
+		Ok((A::read_state(input)?, B::read_state(input)?, C::read_state(input)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Replay;
+	use std::io::{ Cursor, ErrorKind };
+
+	fn replay() -> Replay<(u32, f32, bool)> {
+		let mut replay = Replay::new(0xDEAD_BEEF);
+		for frame in 0..10 {
+			replay.record((frame, frame as f32 * 0.5, frame % 3 == 0));
+		}
+		replay
+	}
+
+	#[test]
+	fn write_read_round_trip() {
+		let replay = replay();
+		let mut bytes = vec![];
+		replay.write_to(&mut bytes).unwrap();
+
+		let read = Replay::<(u32, f32, bool)>::read_from(&mut Cursor::new(bytes)).unwrap();
+		assert_eq!(read.seed(), replay.seed());
+		assert_eq!(read.frames(), replay.frames());
+	}
+
+	#[test]
+	fn read_rejects_other_files() {
+		let mut bytes = vec![];
+		replay().write_to(&mut bytes).unwrap();
+		bytes[0] = b'X';
+		let err = Replay::<(u32, f32, bool)>::read_from(&mut Cursor::new(bytes)).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn read_fails_on_truncated_frames() {
+		let mut bytes = vec![];
+		replay().write_to(&mut bytes).unwrap();
+		bytes.truncate(bytes.len() - 1);
+		let err = Replay::<(u32, f32, bool)>::read_from(&mut Cursor::new(bytes)).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+	}
+
+	#[test]
+	fn player_steps_through_frames_with_their_rngs() {
+		let replay = replay();
+		let mut player = replay.play();
+		for (frame, state) in replay.frames().iter().enumerate() {
+			assert_eq!(player.frame(), frame);
+			assert_eq!(player.rng().next_u64(), replay.rng(frame as u64).next_u64());
+			assert_eq!(player.next(), Some(state));
+		}
+		assert!(player.is_finished());
+		assert_eq!(player.next(), None);
+	}
+}
@@ -0,0 +1,50 @@
+use log::{ info, log };
+use std::{ env, fs, path::{ Path, PathBuf } };
+
+/// Environment variable naming a software Vulkan ICD manifest to use instead of searching for one.
+pub const SOFTWARE_ICD_VAR: &str = "NICE_GAME_SOFTWARE_ICD";
+
+/// Where Linux Vulkan loaders look for ICD manifests.
+const ICD_DIRS: &[&str] = &["/usr/local/share/vulkan/icd.d", "/usr/share/vulkan/icd.d", "/etc/vulkan/icd.d"];
+/// Manifest name prefixes of lavapipe and SwiftShader, in order of preference.
+const SOFTWARE_ICD_PREFIXES: &[&str] = &["lvp_icd", "vk_swiftshader_icd"];
+
+/// Finds the manifest of a software Vulkan implementation, for running on CI machines without a GPU. Uses
+/// `NICE_GAME_SOFTWARE_ICD` if it's set, and otherwise looks for lavapipe, then SwiftShader, where the loader keeps
+/// its manifests.
+pub fn find_software_icd() -> Option<PathBuf> {
+	if let Some(path) = env::var_os(SOFTWARE_ICD_VAR) {
+		let path = PathBuf::from(path);
+		return if path.is_file() { Some(path) } else { None };
+	}
+
+	SOFTWARE_ICD_PREFIXES.iter()
+		.filter_map(|prefix| ICD_DIRS.iter().filter_map(|dir| find_manifest(Path::new(dir), prefix)).next())
+		.next()
+}
+
+/// Restricts the Vulkan loader to the software implementation `find_software_icd` finds, so every `Context` created
+/// afterwards runs on it. Returns the manifest used, or `None` if there's none and nothing was changed.
+///
+/// The loader reads its environment when the first instance is created, so this has to be called before that.
+pub fn use_software_icd() -> Option<PathBuf> {
+	let icd = find_software_icd()?;
+	info!("Using software Vulkan ICD: {}", icd.display());
+	env::set_var("VK_ICD_FILENAMES", &icd);
+	Some(icd)
+}
+
+fn find_manifest(dir: &Path, prefix: &str) -> Option<PathBuf> {
+	let mut manifests: Vec<_> =
+		fs::read_dir(dir).ok()?
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.path())
+			.filter(|path| {
+				let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+				name.starts_with(prefix) && name.ends_with(".json")
+			})
+			.collect();
+	// names are suffixed with the architecture, so sorting keeps the pick stable between runs
+	manifests.sort();
+	manifests.into_iter().next()
+}
@@ -0,0 +1,283 @@
+use crate::device::DeviceCtx;
+use log::{ warn, log };
+use std::{ collections::HashSet, ops::Deref, sync::{ Arc, atomic::{ AtomicUsize, Ordering } } };
+use vulkano::{ SafeDeref, buffer::BufferAccess, device::DeviceOwned, image::ImageViewAccess };
+
+/// Consecutive frames a count has to grow for before it's reported as a likely leak.
+const LEAK_FRAMES: usize = 120;
+
+/// Kinds of objects the batches create.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+	DescriptorSet,
+	Framebuffer,
+	/// Chunks taken from the crate's `CpuBufferPool`s.
+	Subbuffer,
+	/// Uploads of glyphs a font is holding on to until they complete.
+	GlyphFuture,
+}
+impl Resource {
+	const COUNT: usize = 4;
+	const ALL: [Resource; Resource::COUNT] =
+		[Resource::DescriptorSet, Resource::Framebuffer, Resource::Subbuffer, Resource::GlyphFuture];
+}
+
+/// A count for each kind of `Resource`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+	pub descriptor_sets: usize,
+	pub framebuffers: usize,
+	pub subbuffers: usize,
+	pub glyph_futures: usize,
+}
+impl ResourceCounts {
+	pub fn get(&self, resource: Resource) -> usize {
+		match resource {
+			Resource::DescriptorSet => self.descriptor_sets,
+			Resource::Framebuffer => self.framebuffers,
+			Resource::Subbuffer => self.subbuffers,
+			Resource::GlyphFuture => self.glyph_futures,
+		}
+	}
+
+	fn load(counters: &[AtomicUsize; Resource::COUNT]) -> Self {
+		Self {
+			descriptor_sets: counters[Resource::DescriptorSet as usize].load(Ordering::Relaxed),
+			framebuffers: counters[Resource::Framebuffer as usize].load(Ordering::Relaxed),
+			subbuffers: counters[Resource::Subbuffer as usize].load(Ordering::Relaxed),
+			glyph_futures: counters[Resource::GlyphFuture as usize].load(Ordering::Relaxed),
+		}
+	}
+
+	pub(crate) fn sub(&self, other: &Self) -> Self {
+		Self {
+			descriptor_sets: self.descriptor_sets - other.descriptor_sets,
+			framebuffers: self.framebuffers - other.framebuffers,
+			subbuffers: self.subbuffers - other.subbuffers,
+			glyph_futures: self.glyph_futures - other.glyph_futures,
+		}
+	}
+
+	pub(crate) fn div(&self, divisor: usize) -> Self {
+		Self {
+			descriptor_sets: self.descriptor_sets / divisor,
+			framebuffers: self.framebuffers / divisor,
+			subbuffers: self.subbuffers / divisor,
+			glyph_futures: self.glyph_futures / divisor,
+		}
+	}
+}
+
+/// What the batches on one device have created, and how much of it is still alive. Each `DeviceCtx` has its own, from
+/// `DeviceCtx::resource_stats`, and each `Window` keeps per frame counts of its device's, from
+/// `Window::resources_created_last_frame`.
+pub struct ResourceStats {
+	created: [AtomicUsize; Resource::COUNT],
+	live: [AtomicUsize; Resource::COUNT],
+}
+impl ResourceStats {
+	pub(crate) fn new() -> Self {
+		Self {
+			created: [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)],
+			live: [AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0)],
+		}
+	}
+
+	/// Everything the batches have created since the device was opened.
+	pub fn created(&self) -> ResourceCounts {
+		ResourceCounts::load(&self.created)
+	}
+
+	/// Objects still alive, whether kept by a batch or by a command buffer that hasn't finished, e.g. descriptor sets
+	/// of frames in flight and glyph uploads held by fonts.
+	pub fn live(&self) -> ResourceCounts {
+		ResourceCounts::load(&self.live)
+	}
+
+	pub(crate) fn add_live(&self, resource: Resource, count: usize) {
+		self.live[resource as usize].fetch_add(count, Ordering::Relaxed);
+		self.created[resource as usize].fetch_add(count, Ordering::Relaxed);
+	}
+
+	pub(crate) fn remove_live(&self, resource: Resource, count: usize) {
+		self.live[resource as usize].fetch_sub(count, Ordering::Relaxed);
+	}
+}
+
+/// Counts `object` as a live `resource` of its device until the last clone of the returned `Tracked` is dropped.
+/// Objects of devices without a `DeviceCtx` aren't counted.
+pub(crate) fn track<T: DeviceOwned>(resource: Resource, object: T) -> Tracked<T> {
+	let live =
+		DeviceCtx::find(object.device()).map(|ctx| {
+			let stats = ctx.resource_stats().clone();
+			stats.add_live(resource, 1);
+			Arc::new(Live { stats: stats, resource: resource })
+		});
+	Tracked { object: object, _live: live }
+}
+
+/// A descriptor set, framebuffer or subbuffer counted in `ResourceStats::live` while it's alive. It can be used
+/// wherever the object it wraps can, since vulkano implements its traits for anything `SafeDeref`.
+#[derive(Clone)]
+pub(crate) struct Tracked<T> {
+	object: T,
+	/// Only kept for its drop, after the last clone.
+	_live: Option<Arc<Live>>,
+}
+impl<T> Deref for Tracked<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.object
+	}
+}
+unsafe impl<T> SafeDeref for Tracked<T> {}
+
+struct Live {
+	stats: Arc<ResourceStats>,
+	resource: Resource,
+}
+impl Drop for Live {
+	fn drop(&mut self) {
+		self.stats.remove_live(self.resource, 1);
+	}
+}
+
+/// Device memory and descriptor sets held by one batch, from `SpriteBatch::resource_stats` or
+/// `MeshBatch::resource_stats`, for telling which scene or UI to unload. Sizes are computed from lengths, dimensions
+/// and formats, so alignment, pool slack and driver overhead aren't included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BatchResources {
+	pub buffers: usize,
+	pub buffer_bytes: u64,
+	pub images: usize,
+	pub image_bytes: u64,
+	pub descriptor_sets: usize,
+}
+impl BatchResources {
+	pub fn bytes(&self) -> u64 {
+		self.buffer_bytes + self.image_bytes
+	}
+}
+
+/// Collects `BatchResources` from a batch and everything drawn by it. Buffers and images are counted once, however
+/// many sprites or meshes share them, but a texture shared between two batches counts towards both.
+pub struct ResourceTally {
+	resources: BatchResources,
+	buffers: HashSet<(usize, usize)>,
+	images: HashSet<u64>,
+}
+impl ResourceTally {
+	pub(crate) fn new() -> Self {
+		Self { resources: BatchResources::default(), buffers: HashSet::new(), images: HashSet::new() }
+	}
+
+	/// Returns false if the buffer was already counted.
+	pub fn add_buffer(&mut self, buffer: &BufferAccess) -> bool {
+		let inner = buffer.inner();
+		// pools hand out ranges of one buffer, so the range tells them apart
+		if !self.buffers.insert((inner.buffer as *const _ as usize, inner.offset)) {
+			return false;
+		}
+
+		self.resources.buffers += 1;
+		self.resources.buffer_bytes += buffer.size() as u64;
+		true
+	}
+
+	/// Counts the whole image `image` views, with every mip level and layer. Returns false if it was already counted.
+	pub fn add_image(&mut self, image: &ImageViewAccess) -> bool {
+		if !self.images.insert(image.parent().conflict_key()) {
+			return false;
+		}
+
+		let dimensions = image.dimensions();
+		let texel_bytes = image.format().size().unwrap_or(4) as u64;
+		let (mut width, mut height, mut depth) =
+			(dimensions.width() as u64, dimensions.height() as u64, dimensions.depth() as u64);
+		let mut bytes = 0;
+		for _ in 0..image.parent().mipmap_levels() {
+			bytes += width * height * depth * texel_bytes;
+			width = (width / 2).max(1);
+			height = (height / 2).max(1);
+			depth = (depth / 2).max(1);
+		}
+
+		self.resources.images += 1;
+		self.resources.image_bytes += bytes * dimensions.array_layers() as u64 * image.parent().samples() as u64;
+		true
+	}
+
+	/// Descriptor sets aren't compared, so each one added is counted.
+	pub fn add_descriptor_set(&mut self) {
+		self.resources.descriptor_sets += 1;
+	}
+
+	pub(crate) fn finish(self) -> BatchResources {
+		self.resources
+	}
+}
+
+/// One window's frames of its device's `ResourceStats`. Frames of two windows on one device overlap, so each window
+/// counts its own, from one of its presents to the next.
+pub(crate) struct FrameHistory {
+	frame_start: ResourceCounts,
+	last_frame: ResourceCounts,
+	prev_live: ResourceCounts,
+	/// Consecutive frames of growth for each resource, per frame and live.
+	growth: [(usize, usize); Resource::COUNT],
+}
+impl FrameHistory {
+	pub(crate) fn new(stats: &ResourceStats) -> Self {
+		Self {
+			frame_start: stats.created(),
+			last_frame: ResourceCounts::default(),
+			prev_live: stats.live(),
+			growth: [(0, 0); Resource::COUNT],
+		}
+	}
+
+	/// What the batches created between the last two presents.
+	pub(crate) fn last_frame(&self) -> ResourceCounts {
+		self.last_frame
+	}
+
+	/// Called once per present. Warns about any count, live or per frame, that has grown every frame for
+	/// `LEAK_FRAMES` frames in a row, and again only after it stops growing and starts again.
+	pub(crate) fn end_frame(&mut self, stats: &ResourceStats) {
+		let created = stats.created();
+		let frame = created.sub(&self.frame_start);
+		let live = stats.live();
+
+		for &resource in &Resource::ALL {
+			let growth = &mut self.growth[resource as usize];
+			growth.0 = grow(growth.0, frame.get(resource) > self.last_frame.get(resource));
+			growth.1 = grow(growth.1, live.get(resource) > self.prev_live.get(resource));
+
+			if growth.0 == LEAK_FRAMES {
+				warn!(
+					"{:?}s created per frame grew for {} frames in a row, to {}. Something may be leaking.",
+					resource,
+					LEAK_FRAMES,
+					frame.get(resource),
+				);
+			}
+			if growth.1 == LEAK_FRAMES {
+				warn!(
+					"live {:?}s grew for {} frames in a row, to {}. Something may be leaking.",
+					resource,
+					LEAK_FRAMES,
+					live.get(resource),
+				);
+			}
+		}
+
+		self.frame_start = created;
+		self.last_frame = frame;
+		self.prev_live = live;
+	}
+}
+
+fn grow(frames: usize, grew: bool) -> usize {
+	if grew { frames + 1 } else { 0 }
+}
@@ -0,0 +1,371 @@
+use crate::batch::mesh::{ Mesh, MeshFromFileError, MeshRenderPass };
+use crate::camera::Camera;
+use crate::cpu_pool::execute_future;
+use crate::math::Transform;
+use crate::texture::{ ImageFormat, ImmutableTexture, TextureError };
+use crate::window::Window;
+use cgmath::{ prelude::*, Vector3 };
+use futures::{ prelude::*, task::{ Context, Poll } };
+use std::{ cmp::Ordering, collections::VecDeque, path::PathBuf, pin::Pin, sync::{ Arc, mpsc::{ self, Receiver, Sender } } };
+use vulkano::{ device::Queue, sync::GpuFuture };
+
+/// Schedules mesh and texture loads so the ones nearest the camera start first. Requests in a higher `StreamPriority`
+/// tier always start before lower ones, so a loading screen can wait on just the `Blocking` tier.
+pub struct Streamer {
+	max_in_flight: usize,
+	pending: Vec<Request>,
+	in_flight: Vec<(StreamHandle, StreamPriority)>,
+	volumes: Vec<Option<StreamingVolume>>,
+	next_handle: u64,
+	requested: usize,
+	finished: usize,
+	send: Sender<(StreamHandle, Result<Streamed, StreamError>)>,
+	recv: Receiver<(StreamHandle, Result<Streamed, StreamError>)>,
+}
+impl Streamer {
+	/// `max_in_flight` limits how many loads run at once, so a late request near the camera doesn't wait behind
+	/// everything requested before it.
+	pub fn new(max_in_flight: usize) -> Self {
+		assert!(max_in_flight > 0, "streamer must allow at least one load in flight");
+
+		let (send, recv) = mpsc::channel();
+		Self {
+			max_in_flight: max_in_flight,
+			pending: vec![],
+			in_flight: vec![],
+			volumes: vec![],
+			next_handle: 0,
+			requested: 0,
+			finished: 0,
+			send: send,
+			recv: recv,
+		}
+	}
+
+	/// Queues a mesh, prioritized by the distance from the camera to where `transform` places it.
+	pub fn request_mesh(
+		&mut self,
+		render_pass: Arc<MeshRenderPass>,
+		path: impl Into<PathBuf>,
+		transform: Transform,
+		priority: StreamPriority,
+	) -> StreamHandle {
+		self.push(
+			RequestAsset::Mesh { render_pass: render_pass, path: path.into(), transform: transform },
+			transform.position,
+			priority,
+		)
+	}
+
+	/// Queues a texture, prioritized by the distance from the camera to `position`, where it's first needed.
+	pub fn request_texture(
+		&mut self,
+		path: impl Into<PathBuf>,
+		format: ImageFormat,
+		srgb: bool,
+		position: Vector3<f32>,
+		priority: StreamPriority,
+	) -> StreamHandle {
+		self.push(RequestAsset::Texture { path: path.into(), format: format, srgb: srgb }, position, priority)
+	}
+
+	/// Removes a request that hasn't started yet. Returns false if it already started or finished.
+	pub fn cancel(&mut self, handle: StreamHandle) -> bool {
+		match self.pending.iter().position(|request| request.handle == handle) {
+			Some(index) => {
+				self.pending.swap_remove(index);
+				self.requested -= 1;
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Treats everything inside `volume` as if the camera were already there, so an area can be loaded before the
+	/// player arrives.
+	pub fn add_volume(&mut self, volume: StreamingVolume) -> VolumeHandle {
+		if let Some(index) = self.volumes.iter().position(|volume| volume.is_none()) {
+			self.volumes[index] = Some(volume);
+			VolumeHandle(index)
+		} else {
+			self.volumes.push(Some(volume));
+			VolumeHandle(self.volumes.len() - 1)
+		}
+	}
+
+	pub fn remove_volume(&mut self, handle: VolumeHandle) {
+		self.volumes[handle.0] = None;
+	}
+
+	/// Starts the most important pending loads, and returns the ones that finished since the last call. Join the
+	/// returned futures into the window before using the assets.
+	pub fn update(&mut self, window: &Window, camera: &Camera) -> Vec<(StreamHandle, Result<Streamed, StreamError>)> {
+		let finished: Vec<_> = self.recv.try_iter().collect();
+		self.in_flight.retain(|&(handle, _)| !finished.iter().any(|&(finished, _)| finished == handle));
+		self.finished += finished.len();
+
+		let camera_position = camera.position();
+		while self.in_flight.len() < self.max_in_flight {
+			match self.next_request(camera_position) {
+				Some(next) => {
+					let request = self.pending.swap_remove(next);
+					self.start(window, request);
+				},
+				None => break,
+			}
+		}
+
+		finished
+	}
+
+	/// Requests that haven't finished yet, in any tier.
+	pub fn pending_count(&self) -> usize {
+		self.requested - self.finished
+	}
+
+	/// True while anything in the `Blocking` tier hasn't been returned from `update` yet.
+	pub fn is_blocked(&self) -> bool {
+		self.pending.iter().any(|request| request.priority == StreamPriority::Blocking) ||
+			self.in_flight.iter().any(|&(_, priority)| priority == StreamPriority::Blocking)
+	}
+
+	/// Fraction of requests finished since the last `reset_progress`, for loading screens.
+	pub fn progress(&self) -> f32 {
+		if self.requested == 0 { 1.0 } else { self.finished as f32 / self.requested as f32 }
+	}
+
+	pub fn reset_progress(&mut self) {
+		self.requested -= self.finished;
+		self.finished = 0;
+	}
+
+	fn next_request(&self, camera_position: Vector3<f32>) -> Option<usize> {
+		let key =
+			|request: &Request| {
+				let distance =
+					self.volumes.iter()
+						.filter_map(|volume| volume.as_ref())
+						.map(|volume| volume.distance(request.position))
+						.fold(request.position.distance(camera_position), f32::min);
+				(request.priority, distance)
+			};
+
+		(0..self.pending.len())
+			.min_by(|&a, &b| {
+				let (priority_a, distance_a) = key(&self.pending[a]);
+				let (priority_b, distance_b) = key(&self.pending[b]);
+				// a NaN distance, e.g. from a NaN camera position, sorts with everything rather than panicking
+				priority_a.cmp(&priority_b).then(distance_a.partial_cmp(&distance_b).unwrap_or(Ordering::Equal))
+			})
+	}
+
+	fn push(&mut self, asset: RequestAsset, position: Vector3<f32>, priority: StreamPriority) -> StreamHandle {
+		let handle = StreamHandle(self.next_handle);
+		self.next_handle += 1;
+		self.requested += 1;
+		self.pending.push(Request { handle: handle, asset: asset, position: position, priority: priority });
+		handle
+	}
+
+	fn start(&mut self, window: &Window, request: Request) {
+		self.in_flight.push((request.handle, request.priority));
+		let send = self.send.clone();
+		let handle = request.handle;
+
+		match request.asset {
+			RequestAsset::Mesh { render_pass, path, transform } => {
+				let future = Mesh::from_file(window, render_pass, path, transform);
+				execute_future(future.map(move |result| {
+					let result =
+						result
+							.map(|(mesh, future)| Streamed::Mesh(mesh, Box::new(future)))
+							.map_err(StreamError::Mesh);
+					send.send((handle, result)).ok();
+				}));
+			},
+			RequestAsset::Texture { path, format, srgb } => {
+				let future = ImmutableTexture::from_file_with_format(window, path, format, srgb);
+				execute_future(future.map(move |result| {
+					let result =
+						result
+							.map(|(texture, future)| Streamed::Texture(texture, Box::new(future)))
+							.map_err(StreamError::Texture);
+					send.send((handle, result)).ok();
+				}));
+			},
+		}
+	}
+}
+
+/// Identifies a request made to a `Streamer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StreamHandle(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VolumeHandle(usize);
+
+/// Scheduling tiers, most important first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StreamPriority {
+	/// Needed before the loading screen can go away.
+	Blocking,
+	Normal,
+	/// Only loaded once nothing else is waiting.
+	Background,
+}
+
+/// Spherical area whose assets are prefetched while it's registered with a `Streamer`.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamingVolume {
+	pub center: Vector3<f32>,
+	pub radius: f32,
+}
+impl StreamingVolume {
+	pub fn new(center: Vector3<f32>, radius: f32) -> Self {
+		Self { center: center, radius: radius }
+	}
+
+	fn distance(&self, position: Vector3<f32>) -> f32 {
+		(position.distance(self.center) - self.radius).max(0.0)
+	}
+}
+
+pub enum Streamed {
+	Mesh(Mesh, Box<GpuFuture + Send + Sync>),
+	Texture(ImmutableTexture, Box<GpuFuture + Send + Sync>),
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+	Mesh(MeshFromFileError),
+	Texture(TextureError),
+}
+
+struct Request {
+	handle: StreamHandle,
+	asset: RequestAsset,
+	position: Vector3<f32>,
+	priority: StreamPriority,
+}
+
+enum RequestAsset {
+	Mesh { render_pass: Arc<MeshRenderPass>, path: PathBuf, transform: Transform },
+	Texture { path: PathBuf, format: ImageFormat, srgb: bool },
+}
+
+/// Something `load_all` can load from a file.
+pub trait Asset: Sized + Send + 'static {
+	/// Settings shared by every file in a `load_all` call, e.g. the image format.
+	type Params: Clone;
+	type Error: Send + 'static;
+
+	fn load(queue: &Arc<Queue>, path: PathBuf, params: Self::Params) -> Box<AssetFuture<Self>>;
+}
+impl Asset for ImmutableTexture {
+	type Params = (ImageFormat, bool);
+	type Error = TextureError;
+
+	fn load(queue: &Arc<Queue>, path: PathBuf, (format, srgb): Self::Params) -> Box<AssetFuture<Self>> {
+		Box::new(
+			ImmutableTexture::from_file_with_format_impl(queue.clone(), path, format, srgb)
+				.map(|result| {
+					result.map(|(texture, future)| (texture, Box::new(future) as Box<GpuFuture + Send + Sync>))
+				})
+		)
+	}
+}
+
+pub type AssetFuture<A> =
+	Future<Output = Result<(A, Box<GpuFuture + Send + Sync>), <A as Asset>::Error>> + Send + Unpin;
+
+/// Loads every file in `paths`, yielding each as soon as it's ready rather than in order. Reads run on the file system
+/// thread while earlier files decode on the CPU pool, with at most `max_in_flight` loads started at once, so memory
+/// stays bounded however many paths there are.
+///
+/// e.g. `load_all::<ImmutableTexture>(&window, paths, (ImageFormat::PNG, true), 8)`. Join each returned future into the
+/// window before using the asset.
+pub fn load_all<A: Asset>(
+	window: &Window,
+	paths: impl IntoIterator<Item = impl Into<PathBuf>>,
+	params: A::Params,
+	max_in_flight: usize,
+) -> LoadAll<A> {
+	assert!(max_in_flight > 0, "loads must allow at least one in flight");
+
+	let pending: VecDeque<_> = paths.into_iter().map(|path| path.into()).enumerate().collect();
+	LoadAll {
+		queue: window.device().queue().clone(),
+		params: params,
+		max_in_flight: max_in_flight,
+		total: pending.len(),
+		pending: pending,
+		in_flight: vec![],
+	}
+}
+
+/// Stream returned by `load_all`.
+pub struct LoadAll<A: Asset> {
+	queue: Arc<Queue>,
+	params: A::Params,
+	max_in_flight: usize,
+	total: usize,
+	pending: VecDeque<(usize, PathBuf)>,
+	in_flight: Vec<(usize, PathBuf, Box<AssetFuture<A>>)>,
+}
+impl<A: Asset> LoadAll<A> {
+	pub fn total(&self) -> usize {
+		self.total
+	}
+
+	/// Files that haven't been yielded yet.
+	pub fn remaining(&self) -> usize {
+		self.pending.len() + self.in_flight.len()
+	}
+
+	/// Fraction of files yielded so far, for loading screens.
+	pub fn progress(&self) -> f32 {
+		if self.total == 0 { 1.0 } else { 1.0 - self.remaining() as f32 / self.total as f32 }
+	}
+
+	fn start_pending(&mut self) {
+		while self.in_flight.len() < self.max_in_flight {
+			match self.pending.pop_front() {
+				Some((index, path)) => {
+					let future = A::load(&self.queue, path.clone(), self.params.clone());
+					self.in_flight.push((index, path, future));
+				},
+				None => break,
+			}
+		}
+	}
+}
+// nothing is pinned in place, the futures are boxed
+impl<A: Asset> Unpin for LoadAll<A> {}
+impl<A: Asset> Stream for LoadAll<A> {
+	type Item = Loaded<A>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		self.start_pending();
+		if self.in_flight.is_empty() {
+			return Poll::Ready(None);
+		}
+
+		for i in 0..self.in_flight.len() {
+			if let Poll::Ready(result) = Pin::new(&mut self.in_flight[i].2).poll(cx) {
+				let (index, path, _) = self.in_flight.swap_remove(i);
+				self.start_pending();
+				return Poll::Ready(Some(Loaded { index: index, path: path, result: result }));
+			}
+		}
+
+		Poll::Pending
+	}
+}
+
+/// One file loaded by `load_all`.
+pub struct Loaded<A: Asset> {
+	/// Position of the path in the list passed to `load_all`.
+	pub index: usize,
+	pub path: PathBuf,
+	pub result: Result<(A, Box<GpuFuture + Send + Sync>), A::Error>,
+}
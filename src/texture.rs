@@ -1,12 +1,28 @@
+#[cfg(unix)]
+mod external;
 mod immutable;
+mod mutable;
 mod target;
+mod transfer;
 
+#[cfg(unix)]
+pub use self::external::{ ExternalMemoryHandle, ExternalTexture, ExternalTextureError };
 pub use self::immutable::{ ImmutableTexture, TextureError };
+pub use self::mutable::{ MutableTexture, MutableTextureError };
 pub use self::target::TargetTexture;
+pub use self::transfer::{ CloneTextureError, TextureDeviceError, WrongDevice };
+pub(crate) use self::transfer::{ check_same_device, ViewImage };
 pub use image::ImageFormat;
+use crate::device::DeviceCtx;
 use std::sync::Arc;
-use vulkano::image::ImageViewAccess;
+use vulkano::{ image::ImageViewAccess, sync::GpuFuture };
 
 pub trait Texture {
 	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static>;
+
+	/// Makes the texture usable on `device`, e.g. for a window on another GPU. On the texture's own device this shares
+	/// the image; otherwise the pixels are read back and uploaded again, blocking until the readback completes.
+	fn clone_to(&self, device: &DeviceCtx) -> Result<(ImmutableTexture, Box<GpuFuture>), CloneTextureError> {
+		transfer::clone_image(self.image(), device)
+	}
 }
@@ -0,0 +1,256 @@
+use crate::device::{ DeviceCtx, ExternalHandleType, IMPORTED_IMAGE_USAGE, ImportedImage, QUEUE_FAMILY_EXTERNAL };
+use crate::texture::Texture;
+use std::{ fs::File, os::unix::io::{ FromRawFd, RawFd }, sync::Arc };
+use vulkano::{
+	OomError,
+	buffer::BufferAccess,
+	command_buffer::{
+		submit::{ SubmitCommandBufferBuilder, SubmitCommandBufferError },
+		sys::{ Flags, Kind, UnsafeCommandBufferBuilder, UnsafeCommandBufferBuilderPipelineBarrier },
+	},
+	device::Device,
+	format::Format,
+	image::{
+		Dimensions,
+		ImageAccess,
+		ImageDimensions,
+		ImageInner,
+		ImageLayout,
+		ImageViewAccess,
+		ViewType,
+		sys::{ UnsafeImage, UnsafeImageView },
+	},
+	sync::{ AccessError, AccessFlagBits, Fence, FenceWaitError, PipelineStages },
+};
+
+/// Memory another process or API exported, like a video decoder's output, for `ExternalTexture::import`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExternalMemoryHandle {
+	/// From `vkGetMemoryFdKHR` on another Vulkan device, or an OpenGL or CUDA export of the same kind. Needs
+	/// `Capabilities::supports_external_memory`.
+	OpaqueFd(RawFd),
+	/// A Linux dma-buf laid out linearly, like VA-API and V4L2 decoders can export frames as. Needs
+	/// `Capabilities::supports_dma_buf_import`.
+	DmaBuf(RawFd),
+}
+
+/// An image drawn by another process or API and sampled where it is, without a round trip through the CPU, so
+/// `SpriteBatch` can draw a decoder's frames like any other texture.
+#[derive(Clone)]
+pub struct ExternalTexture {
+	image: Arc<ImageViewAccess + Send + Sync + 'static>,
+}
+impl ExternalTexture {
+	/// Imports the memory behind `handle`, `allocation_size` bytes holding a 2D image of `dimensions` and `format`,
+	/// and acquires it from its producer, which left it in `layout`. Blocks until it's acquired. The file descriptor
+	/// is the texture's from then on, and it's closed if the import fails.
+	///
+	/// # Safety
+	///
+	/// The memory has to hold an image created with the same dimensions and format, tiled optimally for opaque
+	/// handles and linearly for dma-bufs, and `allocation_size` has to be the size of the exported allocation. Vulkan
+	/// can't check either. Only the memory is shared, not synchronization: the producer has to have finished writing
+	/// before the texture is drawn, and mustn't write while it might be.
+	pub unsafe fn import(
+		device: &DeviceCtx,
+		handle: ExternalMemoryHandle,
+		allocation_size: u64,
+		dimensions: [u32; 2],
+		format: Format,
+		layout: ImageLayout,
+	) -> Result<Self, ExternalTextureError> {
+		let capabilities = device.capabilities();
+		let (handle_type, fd, supported) =
+			match handle {
+				ExternalMemoryHandle::OpaqueFd(fd) =>
+					(ExternalHandleType::OpaqueFd, fd, capabilities.supports_external_memory),
+				ExternalMemoryHandle::DmaBuf(fd) =>
+					(ExternalHandleType::DmaBuf, fd, capabilities.supports_dma_buf_import),
+			};
+		let fd = File::from_raw_fd(fd);
+		if !supported {
+			return Err(ExternalTextureError::Unsupported);
+		}
+
+		let imported = device.import_image(handle_type, fd, allocation_size, dimensions, format)?;
+		let [width, height] = dimensions;
+		let image =
+			UnsafeImage::from_raw(
+				device.device().clone(),
+				imported.image(),
+				IMPORTED_IMAGE_USAGE,
+				format,
+				ImageDimensions::Dim2d { width: width, height: height, array_layers: 1, cubemap_compatible: false },
+				1,
+				1
+			);
+		let view = UnsafeImageView::raw(&image, ViewType::Dim2d, 0..1, 0..1)?;
+		let image =
+			Arc::new(ExternalImage {
+				view: view,
+				image: image,
+				_imported: imported,
+				dimensions: Dimensions::Dim2d { width: width, height: height },
+			});
+
+		acquire(device, &image, layout)?;
+		Ok(Self { image: image })
+	}
+}
+impl Texture for ExternalTexture {
+	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.image
+	}
+}
+
+/// Moves `image` from the producer's queue family and `layout` to the device's queue in `ImageLayout::General`.
+unsafe fn acquire(device: &DeviceCtx, image: &ExternalImage, layout: ImageLayout) -> Result<(), ExternalTextureError> {
+	let queue = device.queue();
+	let pool = Device::standard_command_pool(device.device(), queue.family());
+	let mut command_buffer = UnsafeCommandBufferBuilder::new(&pool, Kind::primary(), Flags::OneTimeSubmit)?;
+	let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+	barrier.add_image_memory_barrier(
+		image,
+		0..1,
+		0..1,
+		PipelineStages { top_of_pipe: true, ..PipelineStages::none() },
+		AccessFlagBits::none(),
+		PipelineStages { all_commands: true, ..PipelineStages::none() },
+		AccessFlagBits { shader_read: true, transfer_read: true, ..AccessFlagBits::none() },
+		false,
+		Some((QUEUE_FAMILY_EXTERNAL, queue.family().id())),
+		layout,
+		ImageLayout::General
+	);
+	command_buffer.pipeline_barrier(&barrier);
+	let command_buffer = command_buffer.build()?;
+
+	let fence = Fence::alloc(device.device().clone())?;
+	let mut submit = SubmitCommandBufferBuilder::new();
+	submit.add_command_buffer(&command_buffer);
+	submit.set_fence_signal(&fence);
+	submit.submit(queue)?;
+	fence.wait(None)?;
+	Ok(())
+}
+
+/// The image behind an `ExternalTexture`. Always in `ImageLayout::General`, and only ever read.
+struct ExternalImage {
+	// dropped in order, so the view goes before the image, which goes before the imported image and memory
+	view: UnsafeImageView,
+	image: UnsafeImage,
+	_imported: ImportedImage,
+	dimensions: Dimensions,
+}
+unsafe impl ImageAccess for ExternalImage {
+	fn inner(&self) -> ImageInner<'_> {
+		ImageInner { image: &self.image, first_layer: 0, num_layers: 1, first_mipmap_level: 0, num_mipmap_levels: 1 }
+	}
+
+	fn initial_layout_requirement(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn final_layout_requirement(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn conflicts_buffer(&self, _other: &BufferAccess) -> bool {
+		false
+	}
+
+	fn conflicts_image(&self, other: &ImageAccess) -> bool {
+		self.conflict_key() == other.conflict_key()
+	}
+
+	fn conflict_key(&self) -> u64 {
+		self.image.key()
+	}
+
+	fn try_gpu_lock(&self, exclusive_access: bool, expected_layout: ImageLayout) -> Result<(), AccessError> {
+		if expected_layout != ImageLayout::General && expected_layout != ImageLayout::Undefined {
+			return Err(
+				AccessError::UnexpectedImageLayout { requested: expected_layout, allowed: ImageLayout::General }
+			);
+		}
+		if exclusive_access {
+			return Err(AccessError::ExclusiveDenied);
+		}
+		Ok(())
+	}
+
+	unsafe fn increase_gpu_lock(&self) {}
+
+	unsafe fn unlock(&self, _transitioned_layout: Option<ImageLayout>) {}
+}
+unsafe impl ImageViewAccess for ExternalImage {
+	fn parent(&self) -> &ImageAccess {
+		self
+	}
+
+	fn dimensions(&self) -> Dimensions {
+		self.dimensions
+	}
+
+	fn inner(&self) -> &UnsafeImageView {
+		&self.view
+	}
+
+	fn descriptor_set_storage_image_layout(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn descriptor_set_combined_image_sampler_layout(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn descriptor_set_sampled_image_layout(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn descriptor_set_input_attachment_layout(&self) -> ImageLayout {
+		ImageLayout::General
+	}
+
+	fn identity_swizzle(&self) -> bool {
+		true
+	}
+}
+
+#[derive(Debug)]
+pub enum ExternalTextureError {
+	/// The device wasn't created to import this kind of handle, see `Capabilities::supports_external_memory`.
+	Unsupported,
+	/// The driver doesn't accept the handle, e.g. a file descriptor that isn't exported memory of its kind.
+	InvalidHandle,
+	/// The allocation is smaller than the driver says the image needs.
+	AllocationTooSmall { required: u64, actual: u64 },
+	/// None of the memory types the handle can be imported as can hold the image.
+	NoMemoryType,
+	/// The device already has as many allocations as it allows.
+	TooManyAllocations,
+	OomError(OomError),
+	DeviceLost,
+}
+impl From<OomError> for ExternalTextureError {
+	fn from(val: OomError) -> Self {
+		ExternalTextureError::OomError(val)
+	}
+}
+impl From<SubmitCommandBufferError> for ExternalTextureError {
+	fn from(val: SubmitCommandBufferError) -> Self {
+		match val {
+			SubmitCommandBufferError::OomError(err) => ExternalTextureError::OomError(err),
+			SubmitCommandBufferError::DeviceLost => ExternalTextureError::DeviceLost,
+		}
+	}
+}
+impl From<FenceWaitError> for ExternalTextureError {
+	fn from(val: FenceWaitError) -> Self {
+		match val {
+			FenceWaitError::OomError(err) => ExternalTextureError::OomError(err),
+			FenceWaitError::DeviceLostError => ExternalTextureError::DeviceLost,
+			FenceWaitError::Timeout => unreachable!("waited without a timeout"),
+		}
+	}
+}
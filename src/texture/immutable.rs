@@ -1,5 +1,7 @@
 use crate::cpu_pool::{ spawn_cpu, spawn_fs };
+use crate::device::DeviceCtx;
 use crate::texture::Texture;
+use crate::trace::Span;
 use crate::window::Window;
 use futures::prelude::*;
 use image::{ self, ImageError, ImageFormat };
@@ -41,6 +43,17 @@ impl ImmutableTexture {
 		Self::from_file_with_format_impl(window.device().queue().clone(), path, format, srgb)
 	}
 
+	/// Loads a texture onto `device` without a window, e.g. a `Context::headless_device`.
+	pub fn from_file_with_format_on_device<P>(
+		device: &DeviceCtx,
+		path: P,
+		format: ImageFormat,
+		srgb: bool,
+	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
+	where P: AsRef<Path> + Send + 'static {
+		Self::from_file_with_format_impl(device.queue().clone(), path, format, srgb)
+	}
+
 	pub(crate) fn from_file_with_format_impl<P>(
 		queue: Arc<Queue>,
 		path: P,
@@ -49,16 +62,22 @@ impl ImmutableTexture {
 	) -> impl Future<Output = Result<(Self, impl GpuFuture), TextureError>>
 	where P: AsRef<Path> + Send + 'static {
 		spawn_fs(|| {
+			let mut span = Span::new("texture", "read").arg("path", path.as_ref().display());
 			let mut bytes = vec![];
 			File::open(path)?.read_to_end(&mut bytes)?;
+			span.record("bytes", bytes.len());
 			Ok(bytes)
 		})
 			.then(move |bytes: Result<Vec<u8>, io::Error>| spawn_cpu(move || {
 				let bytes = bytes?;
+				let decode_span = Span::new("texture", "decode").arg("bytes", bytes.len());
 				let img = image::load_from_memory_with_format(&bytes, format)?.to_rgba();
 				let (width, height) = img.dimensions();
 				let img = img.into_raw();
+				drop(decode_span);
 
+				let _upload_span =
+					Span::new("upload", "texture").arg("width", width).arg("height", height).arg("bytes", img.len());
 				let (img, future) =
 					ImmutableImage::from_iter(
 						img.into_iter(),
@@ -88,6 +107,8 @@ pub enum TextureError {
 	DeviceLost,
 	DeviceMemoryAllocError(DeviceMemoryAllocError),
 	OomError(OomError),
+	/// A `VideoSprite` was given a frame rate that isn't positive.
+	InvalidFrameRate(f32),
 }
 impl From<FlushError> for TextureError {
 	fn from(val: FlushError) -> Self {
@@ -0,0 +1,108 @@
+use crate::texture::Texture;
+use crate::window::Window;
+use std::sync::Arc;
+use vulkano::{
+	OomError,
+	buffer::{ BufferUsage, CpuAccessibleBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, BuildError, CommandBufferExecError },
+	device::Queue,
+	format::Format,
+	image::{ Dimensions, ImageCreationError, ImageUsage, ImageViewAccess, StorageImage },
+	memory::DeviceMemoryAllocError,
+	sync::{ self, FlushError, GpuFuture },
+};
+
+/// Texture whose contents can be replaced after creation, e.g. for video frames.
+#[derive(Clone)]
+pub struct MutableTexture {
+	image: Arc<StorageImage<Format>>,
+	view: Arc<ImageViewAccess + Send + Sync + 'static>,
+	dimensions: [u32; 2],
+}
+impl MutableTexture {
+	pub fn new(window: &Window, dimensions: [u32; 2], srgb: bool) -> Result<Self, DeviceMemoryAllocError> {
+		Self::from_queue(window.device().queue(), dimensions, srgb)
+	}
+
+	pub(crate) fn from_queue(queue: &Arc<Queue>, dimensions: [u32; 2], srgb: bool) -> Result<Self, DeviceMemoryAllocError> {
+		assert!(dimensions[0] != 0 && dimensions[1] != 0, "mutable texture dimensions must be non-zero: {:?}", dimensions);
+
+		let image =
+			StorageImage::with_usage(
+				queue.device().clone(),
+				Dimensions::Dim2d { width: dimensions[0], height: dimensions[1] },
+				if srgb { Format::R8G8B8A8Srgb } else { Format::R8G8B8A8Unorm },
+				ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() },
+				Some(queue.family()),
+			)
+			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() })?;
+
+		Ok(Self { view: image.clone(), image: image, dimensions: dimensions })
+	}
+
+	pub fn dimensions(&self) -> [u32; 2] {
+		self.dimensions
+	}
+
+	/// Replaces the contents with `pixels`, tightly packed RGBA8. The texture must not be in use by another submission
+	/// while the copy runs, or flushing the returned future fails with an access error.
+	pub fn write<I>(&self, queue: &Arc<Queue>, pixels: I) -> Result<impl GpuFuture, MutableTextureError>
+	where I: ExactSizeIterator<Item = u8> {
+		let expected = self.dimensions[0] as usize * self.dimensions[1] as usize * 4;
+		if pixels.len() != expected {
+			return Err(MutableTextureError::DimensionMismatch { expected: expected, actual: pixels.len() });
+		}
+
+		let buffer = CpuAccessibleBuffer::from_iter(queue.device().clone(), BufferUsage::transfer_source(), pixels)?;
+		let commands =
+			AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family())?
+				.copy_buffer_to_image(buffer, self.image.clone())
+				.unwrap()
+				.build()
+				.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+
+		Ok(sync::now(queue.device().clone()).then_execute(queue.clone(), commands)?)
+	}
+}
+impl Texture for MutableTexture {
+	fn image(&self) -> &Arc<ImageViewAccess + Send + Sync + 'static> {
+		&self.view
+	}
+}
+
+#[derive(Debug)]
+pub enum MutableTextureError {
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+	/// The texture is still in use by an earlier submission. Try again once it's finished.
+	InUse,
+	/// The pixel data passed to `write` was `actual` bytes long, not the `expected` 4 per texel.
+	DimensionMismatch { expected: usize, actual: usize },
+	/// Flushing the write failed for a reason other than the texture being in use, e.g. a lost device.
+	FlushError(FlushError),
+}
+impl From<DeviceMemoryAllocError> for MutableTextureError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		MutableTextureError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<OomError> for MutableTextureError {
+	fn from(val: OomError) -> Self {
+		MutableTextureError::DeviceMemoryAllocError(val.into())
+	}
+}
+impl From<FlushError> for MutableTextureError {
+	fn from(val: FlushError) -> Self {
+		match val {
+			FlushError::AccessError(_) => MutableTextureError::InUse,
+			err => MutableTextureError::FlushError(err),
+		}
+	}
+}
+impl From<CommandBufferExecError> for MutableTextureError {
+	fn from(val: CommandBufferExecError) -> Self {
+		match val {
+			CommandBufferExecError::AccessError { .. } => MutableTextureError::InUse,
+			err => unreachable!("{}", err),
+		}
+	}
+}
@@ -1,10 +1,11 @@
 use crate::{ ObjectIdRoot, RenderTarget };
+use crate::device::DeviceCtx;
 use crate::texture::Texture;
 use crate::window::Window;
 use std::sync::Arc;
 use vulkano::{
 	format::Format,
-	image::{ AttachmentImage, ImageCreationError, ImageViewAccess },
+	image::{ AttachmentImage, ImageCreationError, ImageUsage, ImageViewAccess },
 	memory::DeviceMemoryAllocError,
 };
 
@@ -13,9 +14,35 @@ pub struct TargetTexture {
 	id_root: ObjectIdRoot,
 }
 impl TargetTexture {
+	/// Zero dimensions are made 1, so a target sized after a minimized window is still valid to draw to.
 	pub fn new(window: &Window, dimensions: [u32; 2]) -> Result<Self, DeviceMemoryAllocError> {
-		AttachmentImage::sampled(window.device().device().clone(), dimensions, window.format())
-			.map(|image| Self { image: [image], id_root: ObjectIdRoot::new() })
+		Self::with_id_root(window.device(), dimensions, window.format(), ObjectIdRoot::new())
+	}
+
+	/// A target on `device` without a window to take the format from, e.g. a `Context::headless_device`.
+	pub fn from_device(
+		device: &DeviceCtx,
+		dimensions: [u32; 2],
+		format: Format,
+	) -> Result<Self, DeviceMemoryAllocError> {
+		Self::with_id_root(device, dimensions, format, ObjectIdRoot::new())
+	}
+
+	/// A target that batches created for another one accept as their own, e.g. the window's for captures.
+	pub(crate) fn with_id_root(
+		device: &DeviceCtx,
+		dimensions: [u32; 2],
+		format: Format,
+		id_root: ObjectIdRoot,
+	) -> Result<Self, DeviceMemoryAllocError> {
+		// attachments can't be empty
+		let dimensions = [dimensions[0].max(1), dimensions[1].max(1)];
+
+		// transfer source, so the pixels can be read back by captures and `clone_to`
+		let usage =
+			ImageUsage { color_attachment: true, sampled: true, transfer_source: true, ..ImageUsage::none() };
+		AttachmentImage::with_usage(device.device().clone(), dimensions, format, usage)
+			.map(|image| Self { image: [image], id_root: id_root })
 			.map_err(|err| match err { ImageCreationError::AllocError(err) => err, _ => unreachable!() })
 	}
 }
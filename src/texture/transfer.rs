@@ -0,0 +1,195 @@
+use crate::device::DeviceCtx;
+use crate::texture::ImmutableTexture;
+use std::sync::Arc;
+use vulkano::{
+	OomError,
+	buffer::{ BufferAccess, BufferUsage, CpuAccessibleBuffer },
+	command_buffer::{ AutoCommandBufferBuilder, BuildError, CommandBufferExecError },
+	device::Device,
+	format::Format,
+	image::{ Dimensions, ImageAccess, ImageCreationError, ImageInner, ImageLayout, ImageViewAccess, ImmutableImage },
+	memory::DeviceMemoryAllocError,
+	sync::{ self, AccessError, FlushError, GpuFuture },
+};
+
+/// Copies `image` to `target`, through host memory when it's on another device.
+pub(super) fn clone_image(
+	image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+	target: &DeviceCtx,
+) -> Result<(ImmutableTexture, Box<GpuFuture>), CloneTextureError> {
+	let source = image_device(image);
+	if Arc::ptr_eq(source, target.device()) {
+		return Ok((ImmutableTexture::from_image(image.clone()), Box::new(sync::now(source.clone()))));
+	}
+
+	let format = image.format();
+	let dimensions = image.dimensions();
+	if format.size() != Some(4) || dimensions.depth() != 1 || dimensions.array_layers() != 1 {
+		return Err(CloneTextureError::UnsupportedFormat(format));
+	}
+	let source_ctx = DeviceCtx::find(source).ok_or(CloneTextureError::UnknownDevice)?;
+	let queue = source_ctx.queue();
+
+	let (width, height) = (dimensions.width(), dimensions.height());
+	let len = width as usize * height as usize * 4;
+	let buffer =
+		CpuAccessibleBuffer::from_iter(source.clone(), BufferUsage::transfer_destination(), (0..len).map(|_| 0u8))?;
+	let commands =
+		AutoCommandBufferBuilder::primary_one_time_submit(source.clone(), queue.family())?
+			.copy_image_to_buffer(ViewImage(image.clone()), buffer.clone())
+			.unwrap()
+			.build()
+			.map_err(|err| match err { BuildError::OomError(err) => err, err => unreachable!("{}", err) })?;
+	sync::now(source.clone())
+		.then_execute(queue.clone(), commands)?
+		.then_signal_fence_and_flush()?
+		.wait(None)?;
+
+	let pixels = buffer.read().expect("readback buffer is still in use").to_vec();
+	let (image, future) =
+		ImmutableImage::from_iter(
+			pixels.into_iter(),
+			Dimensions::Dim2d { width: width, height: height },
+			format,
+			target.queue().clone(),
+		)
+		.map_err(|err| match err { ImageCreationError::AllocError(err) => err, err => unreachable!("{}", err) })?;
+
+	Ok((ImmutableTexture::from_image(image), Box::new(future)))
+}
+
+/// Errs with the names of both devices if `image` isn't on `device`, instead of the validation error vulkano panics
+/// with when the image is added to a descriptor set.
+pub(crate) fn check_same_device(
+	image: &Arc<ImageViewAccess + Send + Sync + 'static>,
+	device: &Arc<Device>,
+	what: &'static str,
+) -> Result<(), WrongDevice> {
+	let owner = image_device(image);
+	if Arc::ptr_eq(owner, device) {
+		Ok(())
+	} else {
+		Err(WrongDevice {
+			what: what,
+			owner: owner.physical_device().name(),
+			device: device.physical_device().name(),
+		})
+	}
+}
+
+fn image_device<'a>(image: &'a Arc<ImageViewAccess + Send + Sync + 'static>) -> &'a Arc<Device> {
+	image.parent().inner().image.device()
+}
+
+/// A texture was used on another device than the one it belongs to. `Texture::clone_to` copies textures between
+/// devices.
+#[derive(Debug)]
+pub struct WrongDevice {
+	/// What the texture was used as, e.g. "sprite texture".
+	pub what: &'static str,
+	/// Name of the device the texture belongs to.
+	pub owner: String,
+	/// Name of the device it was used on.
+	pub device: String,
+}
+
+/// Error making or drawing something that samples textures.
+#[derive(Debug)]
+pub enum TextureDeviceError {
+	WrongDevice(WrongDevice),
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl From<WrongDevice> for TextureDeviceError {
+	fn from(val: WrongDevice) -> Self {
+		TextureDeviceError::WrongDevice(val)
+	}
+}
+impl From<DeviceMemoryAllocError> for TextureDeviceError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		TextureDeviceError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<OomError> for TextureDeviceError {
+	fn from(val: OomError) -> Self {
+		TextureDeviceError::DeviceMemoryAllocError(val.into())
+	}
+}
+
+#[derive(Debug)]
+pub enum CloneTextureError {
+	/// The texture's device was created outside the crate, or all its windows are gone.
+	UnknownDevice,
+	/// Only 2D textures with 4 byte pixels can be copied between devices.
+	UnsupportedFormat(Format),
+	/// The texture is being written by an earlier submission, e.g. a target texture still rendering.
+	InUse,
+	DeviceLost,
+	DeviceMemoryAllocError(DeviceMemoryAllocError),
+}
+impl From<DeviceMemoryAllocError> for CloneTextureError {
+	fn from(val: DeviceMemoryAllocError) -> Self {
+		CloneTextureError::DeviceMemoryAllocError(val)
+	}
+}
+impl From<OomError> for CloneTextureError {
+	fn from(val: OomError) -> Self {
+		CloneTextureError::DeviceMemoryAllocError(val.into())
+	}
+}
+impl From<CommandBufferExecError> for CloneTextureError {
+	fn from(val: CommandBufferExecError) -> Self {
+		match val {
+			CommandBufferExecError::AccessError { .. } => CloneTextureError::InUse,
+			err => unreachable!("{}", err),
+		}
+	}
+}
+impl From<FlushError> for CloneTextureError {
+	fn from(val: FlushError) -> Self {
+		match val {
+			FlushError::OomError(err) => err.into(),
+			FlushError::DeviceLost => CloneTextureError::DeviceLost,
+			err => unreachable!("{}", err),
+		}
+	}
+}
+
+/// The image behind a view, for commands that take whole images.
+pub(crate) struct ViewImage(pub(crate) Arc<ImageViewAccess + Send + Sync + 'static>);
+unsafe impl ImageAccess for ViewImage {
+	fn inner(&self) -> ImageInner {
+		self.0.parent().inner()
+	}
+
+	fn initial_layout_requirement(&self) -> ImageLayout {
+		self.0.parent().initial_layout_requirement()
+	}
+
+	fn final_layout_requirement(&self) -> ImageLayout {
+		self.0.parent().final_layout_requirement()
+	}
+
+	fn conflicts_buffer(&self, other: &BufferAccess) -> bool {
+		self.0.parent().conflicts_buffer(other)
+	}
+
+	fn conflicts_image(&self, other: &ImageAccess) -> bool {
+		self.0.parent().conflicts_image(other)
+	}
+
+	fn conflict_key(&self) -> u64 {
+		self.0.parent().conflict_key()
+	}
+
+	fn try_gpu_lock(&self, exclusive_access: bool, expected_layout: ImageLayout) -> Result<(), AccessError> {
+		self.0.parent().try_gpu_lock(exclusive_access, expected_layout)
+	}
+
+	unsafe fn increase_gpu_lock(&self) {
+		self.0.parent().increase_gpu_lock()
+	}
+
+	unsafe fn unlock(&self, transitioned_layout: Option<ImageLayout>) {
+		self.0.parent().unlock(transitioned_layout)
+	}
+}
@@ -0,0 +1,166 @@
+use lazy_static::lazy_static;
+use log::{ debug, log };
+use std::{
+	fmt::Display,
+	fs::File,
+	io::{ self, BufWriter, prelude::* },
+	path::Path,
+	sync::{ Mutex, atomic::{ AtomicBool, AtomicUsize, Ordering } },
+	time::{ Duration, Instant },
+};
+
+lazy_static! {
+	static ref SESSION: Mutex<Option<(Instant, Vec<TraceEvent>)>> = Mutex::new(None);
+}
+static SESSION_ACTIVE: AtomicBool = AtomicBool::new(false);
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+	static THREAD_ID: usize = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Starts recording spans, discarding any session already in progress. The result of `end_session` can be viewed in
+/// `chrome://tracing`.
+pub fn start_session() {
+	*SESSION.lock().unwrap() = Some((Instant::now(), vec![]));
+	SESSION_ACTIVE.store(true, Ordering::Relaxed);
+}
+
+/// Stops recording and returns everything recorded since `start_session`. Spans still open are not included.
+pub fn end_session() -> Option<TraceSession> {
+	SESSION_ACTIVE.store(false, Ordering::Relaxed);
+	SESSION.lock().unwrap().take().map(|(_, events)| TraceSession { events: events })
+}
+
+/// Times the scope it lives in, and logs the duration at debug level when dropped.
+pub struct Span {
+	category: &'static str,
+	name: &'static str,
+	start: Instant,
+	args: Vec<(&'static str, String)>,
+}
+impl Span {
+	pub fn new(category: &'static str, name: &'static str) -> Self {
+		Self { category: category, name: name, start: Instant::now(), args: vec![] }
+	}
+
+	/// Attaches a value to the span, e.g. a path or a byte count.
+	pub fn arg(mut self, key: &'static str, value: impl Display) -> Self {
+		self.record(key, value);
+		self
+	}
+
+	pub fn record(&mut self, key: &'static str, value: impl Display) {
+		self.args.push((key, value.to_string()));
+	}
+}
+impl Drop for Span {
+	fn drop(&mut self) {
+		let end = Instant::now();
+		let duration = end - self.start;
+
+		debug!(
+			"{}/{} took {:.3}ms{}",
+			self.category,
+			self.name,
+			as_micros(duration) as f64 / 1000.0,
+			self.args.iter().map(|(key, value)| format!(" {}={}", key, value)).collect::<String>()
+		);
+
+		if !SESSION_ACTIVE.load(Ordering::Relaxed) {
+			return;
+		}
+
+		if let Some((session_start, events)) = SESSION.lock().unwrap().as_mut() {
+			if self.start < *session_start {
+				return;
+			}
+
+			events.push(TraceEvent {
+				category: self.category,
+				name: self.name,
+				thread: THREAD_ID.with(|id| *id),
+				start: self.start - *session_start,
+				duration: duration,
+				args: self.args.drain(..).collect(),
+			});
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceEvent {
+	pub category: &'static str,
+	pub name: &'static str,
+	/// Small per-thread number, stable for the life of the process.
+	pub thread: usize,
+	/// Time from the start of the session to the start of the span.
+	pub start: Duration,
+	pub duration: Duration,
+	pub args: Vec<(&'static str, String)>,
+}
+
+pub struct TraceSession {
+	events: Vec<TraceEvent>,
+}
+impl TraceSession {
+	pub fn events(&self) -> &[TraceEvent] {
+		&self.events
+	}
+
+	/// Sum of the durations of every span with `category`. Spans on different threads overlap, so this can exceed the
+	/// session's wall time.
+	pub fn total(&self, category: &str) -> Duration {
+		self.events.iter().filter(|event| event.category == category).map(|event| event.duration).sum()
+	}
+
+	pub fn save_chrome_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		self.write_chrome_trace(BufWriter::new(File::create(path)?))
+	}
+
+	pub fn write_chrome_trace(&self, mut out: impl Write) -> io::Result<()> {
+		write!(out, "{{\"traceEvents\":[")?;
+		for (i, event) in self.events.iter().enumerate() {
+			if i != 0 {
+				write!(out, ",")?;
+			}
+
+			write!(
+				out,
+				"{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{",
+				escape(event.name),
+				escape(event.category),
+				event.thread,
+				as_micros(event.start),
+				as_micros(event.duration),
+			)?;
+			for (j, (key, value)) in event.args.iter().enumerate() {
+				if j != 0 {
+					write!(out, ",")?;
+				}
+				write!(out, "\"{}\":\"{}\"", escape(key), escape(value))?;
+			}
+			write!(out, "}}}}")?;
+		}
+		write!(out, "]}}")?;
+		out.flush()
+	}
+}
+
+fn as_micros(duration: Duration) -> u64 {
+	duration.as_secs() * 1_000_000 + duration.subsec_micros() as u64
+}
+
+fn escape(val: &str) -> String {
+	let mut ret = String::with_capacity(val.len());
+	for c in val.chars() {
+		match c {
+			'"' => ret.push_str("\\\""),
+			'\\' => ret.push_str("\\\\"),
+			'\n' => ret.push_str("\\n"),
+			c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+			c => ret.push(c),
+		}
+	}
+	ret
+}
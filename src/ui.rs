@@ -0,0 +1,262 @@
+use crate::batch::sprite::Rect;
+use crate::window::{ MouseButton, Window, WindowEvent };
+use winit::{ ElementState, KeyboardInput, VirtualKeyCode };
+
+/// What happened to a UI element.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UiEvent {
+	/// The cursor moved over the element.
+	Enter,
+	/// The cursor moved off the element, or left the window.
+	Leave,
+	Press(MouseButton),
+	/// A button pressed over the element was released, wherever the cursor is now.
+	Release(MouseButton),
+	/// A button was pressed and released over the element.
+	Click(MouseButton),
+	Focus,
+	Blur,
+	/// A key was pressed or released while the element had focus.
+	Key(KeyboardInput),
+	/// Text typed while the element had focus.
+	Char(char),
+}
+
+/// Identifies an element added to a `UiRouter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UiElementId(usize);
+
+/// Routes window events to rectangles on screen, usually the bounds of sprites and text in a `SpriteBatch`. Elements
+/// with a higher z get events first where they overlap, and focusable ones take keyboard focus when clicked or tabbed
+/// to.
+///
+/// Handlers get `&mut C` like timeline steps, so they can change whatever the game passes to `handle_event`. The router
+/// doesn't draw anything, and elements don't move with what they sit over, so call `set_bounds` when they do.
+pub struct UiRouter<C> {
+	elements: Vec<Option<UiElement<C>>>,
+	cursor: Option<[f32; 2]>,
+	hovered: Option<usize>,
+	pressed: Option<(usize, MouseButton)>,
+	focused: Option<usize>,
+}
+impl<C> UiRouter<C> {
+	pub fn new() -> Self {
+		Self { elements: vec![], cursor: None, hovered: None, pressed: None, focused: None }
+	}
+
+	/// Adds an element covering `bounds`, in target pixels, e.g. from `Sprite::bounds`.
+	pub fn add(
+		&mut self,
+		bounds: Rect,
+		z: i32,
+		focusable: bool,
+		handler: impl FnMut(UiEvent, &mut C) + 'static,
+	) -> UiElementId {
+		let element =
+			UiElement { bounds: bounds, z: z, focusable: focusable, enabled: true, handler: Box::new(handler) };
+		if let Some(index) = self.elements.iter().position(|element| element.is_none()) {
+			self.elements[index] = Some(element);
+			UiElementId(index)
+		} else {
+			self.elements.push(Some(element));
+			UiElementId(self.elements.len() - 1)
+		}
+	}
+
+	/// Removes an element without sending it `Leave` or `Blur`.
+	pub fn remove(&mut self, id: UiElementId) {
+		self.elements[id.0] = None;
+		self.forget(id.0);
+	}
+
+	pub fn clear(&mut self) {
+		self.elements.clear();
+		self.hovered = None;
+		self.pressed = None;
+		self.focused = None;
+	}
+
+	pub fn set_bounds(&mut self, id: UiElementId, bounds: Rect) {
+		self.element_mut(id).bounds = bounds;
+	}
+
+	pub fn set_z(&mut self, id: UiElementId, z: i32) {
+		self.element_mut(id).z = z;
+	}
+
+	/// Disabled elements get no events and let the cursor through to whatever is below them. Disabling the focused or
+	/// hovered element doesn't send it `Blur` or `Leave`.
+	pub fn set_enabled(&mut self, id: UiElementId, enabled: bool) {
+		self.element_mut(id).enabled = enabled;
+		if !enabled {
+			self.forget(id.0);
+		}
+	}
+
+	pub fn hovered(&self) -> Option<UiElementId> {
+		self.hovered.map(UiElementId)
+	}
+
+	pub fn focused(&self) -> Option<UiElementId> {
+		self.focused.map(UiElementId)
+	}
+
+	/// Moves keyboard focus, sending `Blur` and `Focus` as needed. `None` clears it.
+	pub fn set_focus(&mut self, id: Option<UiElementId>, context: &mut C) {
+		let index = id.map(|id| id.0).filter(|&index| self.is_focusable(index));
+		if index == self.focused {
+			return;
+		}
+
+		if let Some(old) = self.focused.take() {
+			self.send(old, UiEvent::Blur, context);
+		}
+		if let Some(index) = index {
+			self.focused = Some(index);
+			self.send(index, UiEvent::Focus, context);
+		}
+	}
+
+	/// Checks element bounds against where the cursor last was, e.g. after moving elements around, so `Enter` and
+	/// `Leave` are sent without waiting for the cursor to move.
+	pub fn update_hover(&mut self, context: &mut C) {
+		let hovered = self.cursor.and_then(|cursor| self.topmost_at(cursor));
+		if hovered == self.hovered {
+			return;
+		}
+
+		if let Some(old) = self.hovered.take() {
+			self.send(old, UiEvent::Leave, context);
+		}
+		if let Some(index) = hovered {
+			self.hovered = Some(index);
+			self.send(index, UiEvent::Enter, context);
+		}
+	}
+
+	/// Routes one event from the window's event loop. Returns whether an element used it, so the game can skip its
+	/// own handling of clicks and keys meant for the UI.
+	pub fn handle_event(&mut self, window: &Window, event: &WindowEvent, context: &mut C) -> bool {
+		match *event {
+			WindowEvent::CursorMoved { position, .. } => {
+				self.cursor = Some(window.to_target_position(position));
+				self.update_hover(context);
+				self.hovered.is_some()
+			},
+			WindowEvent::CursorLeft { .. } => {
+				self.cursor = None;
+				self.update_hover(context);
+				false
+			},
+			WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
+				let hovered = self.hovered;
+				self.set_focus(hovered.map(UiElementId), context);
+				if let Some(index) = hovered {
+					self.pressed = Some((index, button));
+					self.send(index, UiEvent::Press(button), context);
+				}
+				hovered.is_some()
+			},
+			WindowEvent::MouseInput { state: ElementState::Released, button, .. } => {
+				match self.pressed {
+					Some((index, pressed_button)) if pressed_button == button => {
+						self.pressed = None;
+						self.send(index, UiEvent::Release(button), context);
+						if self.hovered == Some(index) {
+							self.send(index, UiEvent::Click(button), context);
+						}
+						true
+					},
+					_ => false,
+				}
+			},
+			WindowEvent::KeyboardInput { input, .. } => {
+				if input.state == ElementState::Pressed && input.virtual_keycode == Some(VirtualKeyCode::Tab) {
+					self.focus_next(input.modifiers.shift, context);
+					return self.focused.is_some();
+				}
+
+				match self.focused {
+					Some(index) => {
+						self.send(index, UiEvent::Key(input), context);
+						true
+					},
+					None => false,
+				}
+			},
+			WindowEvent::ReceivedCharacter(ch) => {
+				match self.focused {
+					// tab moves focus instead of typing
+					Some(_) if ch == '\t' => true,
+					Some(index) => {
+						self.send(index, UiEvent::Char(ch), context);
+						true
+					},
+					None => false,
+				}
+			},
+			_ => false,
+		}
+	}
+
+	/// Cycles focus through focusable elements in the order they were added.
+	fn focus_next(&mut self, backwards: bool, context: &mut C) {
+		let count = self.elements.len();
+		if count == 0 {
+			return;
+		}
+
+		let start = self.focused.unwrap_or(if backwards { 0 } else { count - 1 });
+		let next =
+			(1..=count)
+				.map(|offset| if backwards { (start + count - offset) % count } else { (start + offset) % count })
+				.find(|&index| self.is_focusable(index));
+		self.set_focus(next.map(UiElementId), context);
+	}
+
+	/// Enabled element at `point` with the highest z. Ties go to the higher id, usually the one added later.
+	fn topmost_at(&self, point: [f32; 2]) -> Option<usize> {
+		self.elements.iter()
+			.enumerate()
+			.filter_map(|(index, element)| element.as_ref().map(|element| (index, element)))
+			.filter(|(_, element)| element.enabled && element.bounds.contains(point))
+			.max_by_key(|(index, element)| (element.z, *index))
+			.map(|(index, _)| index)
+	}
+
+	fn is_focusable(&self, index: usize) -> bool {
+		self.elements.get(index)
+			.and_then(|element| element.as_ref())
+			.map_or(false, |element| element.enabled && element.focusable)
+	}
+
+	fn send(&mut self, index: usize, event: UiEvent, context: &mut C) {
+		if let Some(element) = &mut self.elements[index] {
+			(element.handler)(event, context);
+		}
+	}
+
+	fn forget(&mut self, index: usize) {
+		if self.hovered == Some(index) {
+			self.hovered = None;
+		}
+		if self.pressed.map(|(pressed, _)| pressed) == Some(index) {
+			self.pressed = None;
+		}
+		if self.focused == Some(index) {
+			self.focused = None;
+		}
+	}
+
+	fn element_mut(&mut self, id: UiElementId) -> &mut UiElement<C> {
+		self.elements[id.0].as_mut().expect("ui element was removed")
+	}
+}
+
+struct UiElement<C> {
+	bounds: Rect,
+	z: i32,
+	focusable: bool,
+	enabled: bool,
+	handler: Box<FnMut(UiEvent, &mut C)>,
+}
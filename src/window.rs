@@ -1,33 +1,80 @@
-pub use winit::{ Event, MouseButton, MouseCursor, WindowEvent, WindowId, dpi::{ LogicalPosition, LogicalSize } };
+mod platform;
 
-use crate::{ ObjectIdRoot, RenderTarget };
+pub(crate) use self::platform::MonitorSource;
+pub use winit::{
+	BadIcon,
+	ElementState,
+	Event,
+	KeyboardInput,
+	MouseButton,
+	MouseCursor,
+	VirtualKeyCode,
+	WindowEvent,
+	WindowId,
+	dpi::{ LogicalPosition, LogicalSize },
+};
+pub use vulkano::swapchain::PresentMode;
+
+use crate::{ ObjectIdRoot, RenderTarget, Throttle };
+use crate::capture::{ Capture, CaptureError, CaptureOptions, ReadBack };
+use crate::clock::{ Clock, FramePacer };
+use crate::compositor::{ Compositor, Layer };
 use crate::device::DeviceCtx;
-use std::{ iter::Iterator, sync::{ Arc, atomic::{ AtomicBool, Ordering } }};
+use crate::stats::{ FrameHistory, ResourceCounts };
+use crate::texture::TargetTexture;
+use log::{ info, log };
+use std::{
+	iter::Iterator,
+	mem,
+	sync::{ Arc, Mutex, atomic::{ AtomicBool, Ordering } },
+	thread,
+	time::{ Duration, Instant },
+};
 use vulkano::{
+	command_buffer::AutoCommandBuffer,
 	format::Format,
-	image::ImageViewAccess,
+	image::{ Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage, SwapchainImage },
 	memory::DeviceMemoryAllocError,
 	swapchain::{
 		acquire_next_image,
 		AcquireError,
-		PresentMode,
+		Capabilities,
 		Surface,
 		SurfaceTransform,
 		Swapchain,
-		SwapchainCreationError
+		SwapchainCreationError,
 	},
-	sync::{ FlushError, GpuFuture },
+	sync::{ self, FlushError, GpuFuture },
 };
 use winit;
 
+/// How long `present` sleeps instead of rendering while the window is hidden, so the loop doesn't spin.
+const HIDDEN_THROTTLE: Duration = Duration::from_millis(50);
+/// Where `submit_before_present` puts its commands, after every other layer.
+const BEFORE_PRESENT: Layer = Layer(std::i32::MAX);
+
 pub struct Window {
 	surface: Arc<Surface<winit::Window>>,
 	device: Arc<DeviceCtx>,
 	swapchain: Arc<Swapchain<winit::Window>>,
 	images: Vec<Arc<ImageViewAccess + Send + Sync + 'static>>,
 	previous_frame_end: Option<Box<GpuFuture>>,
-	resized: Arc<AtomicBool>,
+	/// Commands submitted by the user for the next frame presented.
+	submitted: Compositor,
+	state: Arc<WindowState>,
+	pause_when_hidden: bool,
+	clock: Clock,
+	frame_pacer: Option<FramePacer>,
+	last_present: Option<Instant>,
 	id_root: ObjectIdRoot,
+	latency: LatencyPreference,
+	image_count: Option<u32>,
+	swapchain_settings_changed: bool,
+	/// Bumped around each capture, so batches don't blend captured frames with presented ones.
+	history_generation: u64,
+	resource_history: FrameHistory,
+	/// The platform cursor made by `set_cursor_image`, where there is one.
+	native_cursor: Option<platform::NativeCursor>,
 }
 impl Window {
 	pub fn join_future(&mut self, future: impl GpuFuture + 'static) {
@@ -45,30 +92,67 @@ impl Window {
 	where
 		F: GpuFuture + 'static
 	{
-		if self.resized.swap(false, Ordering::Relaxed) {
-			let dimensions = self.surface.capabilities(self.device.device().physical_device())
-				.expect("failed to get surface capabilities")
-				.current_extent
-				.unwrap_or(
-					self.surface.window()
-						.get_inner_size()
-						.map(|size| {
-							let size: (u32, u32) = size.into();
-							[size.0, size.1]
-						})
-						.unwrap()
-				);
+		if let Some(frame_pacer) = &mut self.frame_pacer {
+			frame_pacer.wait();
+		}
+		self.clock.tick();
+		self.resource_history.end_frame(self.device.resource_stats());
 
+		if self.is_focused() && self.state.attention_requested.swap(false, Ordering::Relaxed) {
+			platform::clear_attention(self.surface.window());
+		}
+
+		if self.is_throttled() {
+			let interval = self.state.throttle.lock().unwrap().1;
+			if let Some(wait) = self.last_present.and_then(|last| interval.checked_sub(last.elapsed())) {
+				thread::sleep(wait);
+			}
+		}
+		self.last_present = Some(Instant::now());
+
+		if self.pause_when_hidden && self.is_minimized() {
+			thread::sleep(HIDDEN_THROTTLE);
+			return Ok(());
+		}
+
+		if self.state.resized.swap(false, Ordering::Relaxed) || self.swapchain_settings_changed {
+			// some platforms report a zero extent while minimized, which can't be used to create a swapchain. the
+			// window may also have been closed.
+			let dimensions =
+				match Self::surface_dimensions(&self.surface, &self.device) {
+					Some(dimensions) => dimensions,
+					None => {
+						self.state.resized.store(true, Ordering::Relaxed);
+						if self.pause_when_hidden {
+							thread::sleep(HIDDEN_THROTTLE);
+						}
+						return Ok(());
+					},
+				};
+
+			let created =
+				Self::create_swapchain(
+					&self.surface,
+					&self.device,
+					dimensions,
+					self.latency,
+					self.image_count,
+					Some(&self.swapchain),
+				);
 			let (swapchain, images) =
-				match self.swapchain.recreate_with_dimension(dimensions) {
+				match created {
 					Ok(ret) => ret,
 					Err(SwapchainCreationError::UnsupportedDimensions) => {
-						self.resized.store(true, Ordering::Relaxed);
+						self.state.resized.store(true, Ordering::Relaxed);
 						return Ok(());
 					},
 					Err(err) => unreachable!(err),
 				};
 
+			if self.swapchain_settings_changed {
+				Self::report_swapchain(&swapchain, self.latency);
+				self.swapchain_settings_changed = false;
+			}
 			self.swapchain = swapchain;
 			self.images = images.into_iter().map(|x| x as _).collect();
 		}
@@ -77,7 +161,7 @@ impl Window {
 			match acquire_next_image(self.swapchain.clone(), None) {
 				Ok(val) => val,
 				Err(AcquireError::OutOfDate) => {
-					self.resized.store(true, Ordering::Relaxed);
+					self.state.resized.store(true, Ordering::Relaxed);
 					return Ok(());
 				},
 				Err(err) => unreachable!(err)
@@ -91,13 +175,17 @@ impl Window {
 				Box::new(acquire_future)
 			};
 		future = Box::new(get_commands(self, image_num, future));
+		let submitted = mem::replace(&mut self.submitted, Compositor::new());
+		if !submitted.is_empty() {
+			future = submitted.submit(self.device.queue(), Box::new(future.then_signal_semaphore()));
+		}
 		let future = future.then_swapchain_present(self.device.queue().clone(), self.swapchain.clone(), image_num)
 			.then_signal_fence_and_flush();
 		self.previous_frame_end =
 			match future {
 				Ok(future) => Some(Box::new(future)),
 				Err(FlushError::OutOfDate) => {
-					self.resized.store(true, Ordering::Relaxed);
+					self.state.resized.store(true, Ordering::Relaxed);
 					return Ok(());
 				},
 				Err(err) => unreachable!(err),
@@ -106,12 +194,264 @@ impl Window {
 		Ok(())
 	}
 
+	/// Like `present`, but `build` adds each batch's commands to a `Compositor` on a layer instead of chaining futures
+	/// by hand. The compositor runs them in layer order and handles synchronization between them.
+	pub fn present_composited(
+		&mut self,
+		build: impl FnOnce(&mut Self, usize, &mut Compositor) -> Result<(), DeviceMemoryAllocError>,
+	) -> Result<(), DeviceMemoryAllocError> {
+		let mut result = Ok(());
+		self.present(|window, image_num, future| {
+			let mut compositor = Compositor::new();
+			match build(window, image_num, &mut compositor) {
+				Ok(()) => {
+					compositor.append(mem::replace(&mut window.submitted, Compositor::new()));
+					compositor.submit(window.device().queue(), future)
+				},
+				Err(err) => {
+					result = Err(err);
+					future
+				},
+			}
+		})?;
+		result
+	}
+
+	/// Runs `commands` on `layer` in the next frame presented, after the stages `present_composited` adds to the same
+	/// layer, so custom passes can be slotted between the crate's. `future` is waited on before they run. With plain
+	/// `present`, they run in layer order after the frame's own commands. Captures leave them out.
+	pub fn submit_on_layer<F>(&mut self, layer: Layer, commands: AutoCommandBuffer, future: Option<F>)
+	where
+		F: GpuFuture + 'static
+	{
+		self.submitted.add(layer, commands, future);
+	}
+
+	/// Runs `commands` after the scene has been drawn, before post processing and the HUD.
+	pub fn after_scene(&mut self, commands: AutoCommandBuffer) {
+		self.submit_on_layer(Layer::SCENE, commands, None::<Box<GpuFuture>>);
+	}
+
+	/// Runs `commands` after everything else in the next frame, right before the image is presented.
+	pub fn submit_before_present(&mut self, commands: AutoCommandBuffer) {
+		self.submit_on_layer(BEFORE_PRESENT, commands, None::<Box<GpuFuture>>);
+	}
+
+	/// Renders a frame to an offscreen image and reads it back, without presenting it, for screenshots and photo modes.
+	/// `build` adds stages like it does for `present_composited`, but to the target texture it's given instead of the
+	/// swapchain image. Batches created for the window accept that texture, and keep their own retained images like
+	/// gbuffers and history. Stages on layers `options` excludes are left out, so the same `build` can hide the HUD.
+	///
+	/// The capture is submitted before returning, and its image is ready once the returned `Capture` resolves. The
+	/// window's next frame, or next capture, waits on it. Batches reset their history for the capture and again for
+	/// the frame after, so neither blends with the other.
+	pub fn capture(
+		&mut self,
+		options: &CaptureOptions,
+		build: impl FnOnce(&mut Self, &TargetTexture, &mut Compositor) -> Result<(), DeviceMemoryAllocError>,
+	) -> Result<Capture, CaptureError> {
+		let dimensions = options.dimensions_or(self.images[0].dimensions().width_height());
+		let target = TargetTexture::with_id_root(&self.device, dimensions, self.format(), self.id_root.share())?;
+		let read_back = ReadBack::new(&self.device, &target)?;
+
+		let mut future: Box<GpuFuture> = Box::new(sync::now(self.device.device().clone()));
+		if let Some(previous_frame_end) = self.previous_frame_end.take() {
+			future = Box::new(future.join(previous_frame_end));
+		}
+
+		let mut compositor = Compositor::excluding(options.excluded().to_vec());
+		self.history_generation += 1;
+		let built = build(self, &target, &mut compositor);
+		self.history_generation += 1;
+		if let Err(err) = built {
+			self.previous_frame_end = Some(future);
+			return Err(err.into());
+		}
+		let future = compositor.submit(self.device.queue(), future);
+		let capture = read_back.submit(&self.device, future)?;
+		self.previous_frame_end = capture.frame_future();
+		Ok(capture)
+	}
+
+	pub fn is_focused(&self) -> bool {
+		self.state.focused.load(Ordering::Relaxed)
+	}
+
+	/// True while the window is minimized. Winit doesn't report occlusion by other windows, so a window that is merely
+	/// covered still counts as visible.
+	pub fn is_minimized(&self) -> bool {
+		self.state.minimized.load(Ordering::Relaxed)
+	}
+
+	/// True while `Context::set_throttle` limits this window, so expensive rendering can be skipped.
+	pub fn is_throttled(&self) -> bool {
+		match self.state.throttle.lock().unwrap().0 {
+			Throttle::Off => false,
+			Throttle::Background => !self.state.focused.load(Ordering::Relaxed) || self.clock.is_paused(),
+			Throttle::Always => true,
+		}
+	}
+
+	/// Ticked at the start of every `present`, so its delta is the time between frames.
+	pub fn clock(&self) -> &Clock {
+		&self.clock
+	}
+
+	pub fn clock_mut(&mut self) -> &mut Clock {
+		&mut self.clock
+	}
+
+	pub fn frame_pacer(&self) -> Option<&FramePacer> {
+		self.frame_pacer.as_ref()
+	}
+
+	/// Waits with `pacer` at the start of every `present`, before the clock is ticked. Pairs best with a present mode
+	/// that doesn't block, like `LatencyPreference::LowLatency`'s. Defaults to none.
+	pub fn set_frame_pacer(&mut self, pacer: Option<FramePacer>) {
+		self.frame_pacer = pacer;
+	}
+
+	/// The monitor most of the window is on.
+	pub fn current_monitor(&self) -> Monitor {
+		let window = self.surface.window();
+		Monitor::new(window.get_current_monitor(), MonitorSource::Window(window))
+	}
+
+	/// When enabled (the default), `present` doesn't render while the window is minimized, and sleeps briefly instead
+	/// so an unthrottled loop doesn't spin.
+	pub fn set_pause_when_hidden(&mut self, pause: bool) {
+		self.pause_when_hidden = pause;
+	}
+
+	pub fn latency_preference(&self) -> LatencyPreference {
+		self.latency
+	}
+
+	/// Picks the present mode and number of back buffers from what the surface supports, falling back as described on
+	/// `LatencyPreference`. The swapchain is recreated on the next `present`, after which `present_mode` and
+	/// `image_count` report what was chosen. Defaults to `PowerSave`.
+	pub fn set_latency_preference(&mut self, latency: LatencyPreference) {
+		if latency != self.latency {
+			self.latency = latency;
+			self.swapchain_settings_changed = true;
+		}
+	}
+
+	/// Overrides the number of back buffers the latency preference picks. It's clamped to what the surface supports.
+	/// More images smooth over uneven frame times at the cost of latency. `None` goes back to the preference's count.
+	pub fn set_image_count(&mut self, count: Option<u32>) {
+		if count != self.image_count {
+			self.image_count = count;
+			self.swapchain_settings_changed = true;
+		}
+	}
+
+	/// The present mode the swapchain was created with.
+	pub fn present_mode(&self) -> PresentMode {
+		self.swapchain.present_mode()
+	}
+
+	/// Number of images in the swapchain, which may be more than requested.
+	pub fn image_count(&self) -> u32 {
+		self.swapchain.num_images()
+	}
+
 	pub fn get_inner_size(&self) -> Option<LogicalSize> {
 		self.surface.window().get_inner_size()
 	}
 
+	/// Takes effect once any cursor image is cleared.
 	pub fn set_cursor(&self, cursor: MouseCursor) {
-		self.surface.window().set_cursor(cursor)
+		self.state.cursor.lock().unwrap().stock = cursor;
+		if self.native_cursor.is_none() {
+			self.surface.window().set_cursor(cursor)
+		}
+	}
+
+	/// Replaces the cursor with an image from tightly packed 8-bit RGBA pixels, with `hotspot` being the pixel in it
+	/// that points. On X11 and Windows this makes a platform cursor. On Wayland and macOS, where winit 0.18 keeps the
+	/// cursor to itself, the OS cursor is hidden instead and the image is drawn in its place by a `SoftwareCursor`,
+	/// which has to be added to the sprite batch drawn last.
+	pub fn set_cursor_image(
+		&mut self,
+		rgba: Vec<u8>,
+		dimensions: [u32; 2],
+		hotspot: [u32; 2],
+	) -> Result<(), CursorImageError> {
+		let [width, height] = dimensions;
+		let expected = width as usize * height as usize * 4;
+		if expected == 0 || rgba.len() != expected {
+			return Err(CursorImageError::DimensionMismatch { expected: expected, actual: rgba.len() });
+		}
+		if hotspot[0] >= width || hotspot[1] >= height {
+			return Err(CursorImageError::HotspotOutside);
+		}
+
+		self.drop_cursor_image();
+		self.native_cursor = platform::create_cursor(self.surface.window(), &rgba, dimensions, hotspot);
+		if self.native_cursor.is_none() {
+			let (image, future) =
+				ImmutableImage::from_iter(
+					rgba.into_iter(),
+					Dimensions::Dim2d { width: width, height: height },
+					Format::R8G8B8A8Srgb,
+					self.device.queue().clone(),
+				)?;
+			self.join_future(future);
+			self.state.cursor.lock().unwrap().image = Some((image, hotspot));
+			self.surface.window().hide_cursor(true);
+		}
+		Ok(())
+	}
+
+	/// Goes back to the OS cursor set with `set_cursor`.
+	pub fn clear_cursor_image(&mut self) {
+		self.drop_cursor_image();
+	}
+
+	fn drop_cursor_image(&mut self) {
+		let mut cursor = self.state.cursor.lock().unwrap();
+		if cursor.image.take().is_some() {
+			self.surface.window().hide_cursor(false);
+		}
+		if self.native_cursor.take().is_some() {
+			self.surface.window().set_cursor(cursor.stock);
+		}
+	}
+
+	/// Sets the icon shown in the title bar and taskbar, from tightly packed 8-bit RGBA pixels, instead of the
+	/// executable's default. Has no effect on macOS, where the icon comes from the application bundle.
+	pub fn set_icon(&self, rgba: Vec<u8>, dimensions: [u32; 2]) -> Result<(), BadIcon> {
+		let icon = winit::Icon::from_rgba(rgba, dimensions[0], dimensions[1])?;
+		self.surface.window().set_window_icon(Some(icon));
+		Ok(())
+	}
+
+	/// Goes back to the executable's default icon.
+	pub fn clear_icon(&self) {
+		self.surface.window().set_window_icon(None);
+	}
+
+	/// Gets the user's attention without taking focus: once, or until the window is focused if `critical`. Bounces
+	/// the dock icon on macOS and flashes the taskbar button on Windows. On X11 this sets the urgency hint, which
+	/// lasts until the window is focused either way, and is cleared by the next `present` after that. Does nothing on
+	/// Wayland.
+	pub fn request_attention(&self, critical: bool) {
+		if platform::request_attention(self.surface.window(), critical) {
+			self.state.attention_requested.store(true, Ordering::Relaxed);
+		}
+	}
+
+	/// Shows progress on the window's taskbar button, e.g. while loading. Only Windows has a taskbar that shows
+	/// progress; this does nothing elsewhere, including before Windows has created the button.
+	pub fn set_taskbar_progress(&self, progress: TaskbarProgress) {
+		platform::set_taskbar_progress(self.surface.window(), progress);
+	}
+
+	/// Converts a position from window events, in logical pixels, to the physical pixels sprites are positioned in.
+	pub fn to_target_position(&self, pos: LogicalPosition) -> [f32; 2] {
+		let pos = pos.to_physical(self.surface.window().get_hidpi_factor());
+		[pos.x as f32, pos.y as f32]
 	}
 
 	pub fn set_cursor_position(&self, pos: LogicalPosition) -> Result<(), String> {
@@ -122,46 +462,118 @@ impl Window {
 		&self.device
 	}
 
-	pub(crate) fn new(surface: Arc<Surface<winit::Window>>, device: Arc<DeviceCtx>, resized: Arc<AtomicBool>) -> Self {
-		let (swapchain, images) = {
-			let caps = surface.capabilities(device.device().physical_device()).expect("failed to get surface capabilities");
-			Swapchain::new(
-				device.device().clone(),
-				surface.clone(),
-				caps.min_image_count,
-				Format::B8G8R8A8Srgb,
-				caps.current_extent
-					.unwrap_or(
-						surface.window()
-							.get_inner_size()
-							.map(|size| {
-								let size: (u32, u32) = size.into();
-								[size.0, size.1]
-							})
-							.unwrap()
-					),
-				1,
-				caps.supported_usage_flags,
-				device.queue(),
-				SurfaceTransform::Identity,
-				caps.supported_composite_alpha.iter().next().unwrap(),
-				PresentMode::Fifo,
-				true,
-				None
-			).expect("failed to create swapchain")
-		};
+	/// What the batches on the window's device created between its last two presents, including for other windows
+	/// on the device. Most descriptor sets, framebuffers and subbuffers are rebuilt every frame, so this is where
+	/// runaway allocation shows up.
+	pub fn resources_created_last_frame(&self) -> ResourceCounts {
+		self.resource_history.last_frame()
+	}
+
+	pub(crate) fn new(surface: Arc<Surface<winit::Window>>, device: Arc<DeviceCtx>, state: Arc<WindowState>) -> Self {
+		// a window created minimized has no usable size yet. use a placeholder and recreate on the first present.
+		let dimensions = Self::surface_dimensions(&surface, &device);
+		if dimensions.is_none() {
+			state.resized.store(true, Ordering::Relaxed);
+		}
+
+		let latency = LatencyPreference::default();
+		let (swapchain, images) =
+			Self::create_swapchain(&surface, &device, dimensions.unwrap_or([1, 1]), latency, None, None)
+				.expect("failed to create swapchain");
+		Self::report_swapchain(&swapchain, latency);
 		let images = images.into_iter().map(|x| x as _).collect();
 
 		Self {
+			resource_history: FrameHistory::new(device.resource_stats()),
 			surface: surface,
 			device: device,
 			swapchain: swapchain,
 			images: images,
 			previous_frame_end: None,
-			resized: resized,
+			submitted: Compositor::new(),
+			state: state,
+			pause_when_hidden: true,
+			clock: Clock::new(),
+			frame_pacer: None,
+			last_present: None,
 			id_root: ObjectIdRoot::new(),
+			latency: latency,
+			image_count: None,
+			swapchain_settings_changed: false,
+			history_generation: 0,
+			native_cursor: None,
 		}
 	}
+
+	pub(crate) fn surface(&self) -> &Arc<Surface<winit::Window>> {
+		&self.surface
+	}
+
+	/// Changes when frames drawn from now on shouldn't reuse the history of frames drawn before, e.g. with
+	/// temporal upsampling.
+	pub(crate) fn history_generation(&self) -> u64 {
+		self.history_generation
+	}
+
+	pub(crate) fn state(&self) -> &Arc<WindowState> {
+		&self.state
+	}
+
+	fn create_swapchain(
+		surface: &Arc<Surface<winit::Window>>,
+		device: &DeviceCtx,
+		dimensions: [u32; 2],
+		latency: LatencyPreference,
+		image_count: Option<u32>,
+		old_swapchain: Option<&Arc<Swapchain<winit::Window>>>,
+	) -> Result<(Arc<Swapchain<winit::Window>>, Vec<Arc<SwapchainImage<winit::Window>>>), SwapchainCreationError> {
+		let caps = surface.capabilities(device.device().physical_device()).expect("failed to get surface capabilities");
+		let present_mode = latency.present_mode(&caps);
+		let image_count = image_count.unwrap_or_else(|| latency.image_count(&caps, present_mode));
+		let image_count =
+			caps.max_image_count.map_or(image_count, |max| image_count.min(max)).max(caps.min_image_count);
+
+		Swapchain::new(
+			device.device().clone(),
+			surface.clone(),
+			image_count,
+			Format::B8G8R8A8Srgb,
+			dimensions,
+			1,
+			caps.supported_usage_flags,
+			device.queue(),
+			SurfaceTransform::Identity,
+			caps.supported_composite_alpha.iter().next().unwrap(),
+			present_mode,
+			true,
+			old_swapchain,
+		)
+	}
+
+	fn report_swapchain(swapchain: &Swapchain<winit::Window>, latency: LatencyPreference) {
+		info!(
+			"presenting with {:?} and {} images for {:?} latency",
+			swapchain.present_mode(),
+			swapchain.num_images(),
+			latency,
+		);
+	}
+
+	/// Returns the size the swapchain should be, or `None` if it's zero or unknown.
+	fn surface_dimensions(surface: &Surface<winit::Window>, device: &DeviceCtx) -> Option<[u32; 2]> {
+		surface.capabilities(device.device().physical_device())
+			.expect("failed to get surface capabilities")
+			.current_extent
+			.or_else(|| {
+				surface.window()
+					.get_inner_size()
+					.map(|size| {
+						let size: (u32, u32) = size.into();
+						[size.0, size.1]
+					})
+			})
+			.filter(|dimensions| dimensions[0] != 0 && dimensions[1] != 0)
+	}
 }
 impl RenderTarget for Window {
 	fn format(&self) -> Format {
@@ -176,3 +588,209 @@ impl RenderTarget for Window {
 		&self.images
 	}
 }
+
+/// Trade-off between input latency, tearing and power use that picks a window's present mode and back buffer count.
+/// Modes the surface doesn't support fall back to the next one listed, ending in FIFO, which is always available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatencyPreference {
+	/// Mailbox, then immediate, with one image more than the minimum. Frames are shown as soon as they're done, which
+	/// may tear with immediate.
+	LowLatency,
+	/// FIFO relaxed with triple buffering. Vsynced, but a late frame is shown right away and tears instead of waiting a
+	/// whole refresh and stuttering.
+	Smooth,
+	/// FIFO with the fewest images the surface allows. Frames never tear and rendering is capped to the refresh rate.
+	PowerSave,
+}
+impl LatencyPreference {
+	fn present_mode(self, caps: &Capabilities) -> PresentMode {
+		let preferred: &[PresentMode] =
+			match self {
+				LatencyPreference::LowLatency => &[PresentMode::Mailbox, PresentMode::Immediate],
+				LatencyPreference::Smooth => &[PresentMode::Relaxed],
+				LatencyPreference::PowerSave => &[],
+			};
+		preferred.iter().cloned().find(|&mode| caps.present_modes.supports(mode)).unwrap_or(PresentMode::Fifo)
+	}
+
+	fn image_count(self, caps: &Capabilities, present_mode: PresentMode) -> u32 {
+		match (self, present_mode) {
+			// mailbox needs a spare image to render into while one waits to be shown
+			(LatencyPreference::LowLatency, PresentMode::Mailbox) => caps.min_image_count + 1,
+			(LatencyPreference::LowLatency, _) => caps.min_image_count,
+			(LatencyPreference::Smooth, _) => caps.min_image_count.max(3),
+			(LatencyPreference::PowerSave, _) => caps.min_image_count,
+		}
+	}
+}
+impl Default for LatencyPreference {
+	fn default() -> Self {
+		LatencyPreference::PowerSave
+	}
+}
+
+#[derive(Debug)]
+pub enum CursorImageError {
+	/// The pixel data passed to `set_cursor_image` was `actual` bytes long, not the `expected` 4 per pixel, or the
+	/// image was empty.
+	DimensionMismatch { expected: usize, actual: usize },
+	/// The hotspot isn't a pixel in the image.
+	HotspotOutside,
+	/// Uploading the image for the software cursor failed.
+	ImageCreationError(ImageCreationError),
+}
+impl From<ImageCreationError> for CursorImageError {
+	fn from(val: ImageCreationError) -> Self {
+		CursorImageError::ImageCreationError(val)
+	}
+}
+
+/// What `Window::set_taskbar_progress` shows. Fractions are clamped to 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TaskbarProgress {
+	/// No progress bar, the default.
+	Hidden,
+	/// Busy for an unknown amount of time.
+	Indeterminate,
+	Normal(f32),
+	Paused(f32),
+	Error(f32),
+}
+
+/// A display connected to the system, from `Context::monitors` or `Window::current_monitor`.
+#[derive(Clone, Debug)]
+pub struct Monitor {
+	id: winit::MonitorId,
+	refresh_rate: Option<f64>,
+}
+impl Monitor {
+	/// Queries the refresh rate up front, while the connection it came through is at hand.
+	pub(crate) fn new(id: winit::MonitorId, source: MonitorSource) -> Self {
+		let refresh_rate = platform::refresh_rate(&id, source);
+		Self { id: id, refresh_rate: refresh_rate }
+	}
+
+	pub fn name(&self) -> Option<String> {
+		self.id.get_name()
+	}
+
+	/// Resolution in physical pixels.
+	pub fn dimensions(&self) -> [u32; 2] {
+		let size = self.id.get_dimensions();
+		[size.width as u32, size.height as u32]
+	}
+
+	/// Top left corner on the desktop, in physical pixels.
+	pub fn position(&self) -> [i32; 2] {
+		let position = self.id.get_position();
+		[position.x as i32, position.y as i32]
+	}
+
+	/// Physical pixels per logical pixel.
+	pub fn hidpi_factor(&self) -> f64 {
+		self.id.get_hidpi_factor()
+	}
+
+	/// Refresh rate in Hz of the current video mode, as of when the monitor was looked up. `None` on Wayland and
+	/// wherever the platform doesn't report one; `FramePacer::for_monitor` falls back to 60 Hz.
+	pub fn refresh_rate(&self) -> Option<f64> {
+		self.refresh_rate
+	}
+}
+
+/// Window state updated by `EventsLoop` and read by the `Window`.
+pub(crate) struct WindowState {
+	pub(crate) resized: AtomicBool,
+	pub(crate) focused: AtomicBool,
+	pub(crate) minimized: AtomicBool,
+	/// Set while `request_attention` needs clearing once the window is focused.
+	pub(crate) attention_requested: AtomicBool,
+	pub(crate) throttle: Mutex<(Throttle, Duration)>,
+	pub(crate) cursor: Mutex<CursorState>,
+}
+impl WindowState {
+	pub(crate) fn new(throttle: Throttle, throttle_interval: Duration) -> Self {
+		Self {
+			resized: AtomicBool::new(false),
+			focused: AtomicBool::new(true),
+			minimized: AtomicBool::new(false),
+			attention_requested: AtomicBool::new(false),
+			throttle: Mutex::new((throttle, throttle_interval)),
+			cursor: Mutex::new(CursorState { position: None, image: None, stock: MouseCursor::Default }),
+		}
+	}
+}
+
+pub(crate) struct CursorState {
+	/// Where the cursor was last seen over the window, in logical pixels.
+	pub(crate) position: Option<LogicalPosition>,
+	/// The image drawn by `SoftwareCursor`, and its hotspot.
+	pub(crate) image: Option<(Arc<ImageViewAccess + Send + Sync + 'static>, [u32; 2])>,
+	/// The OS cursor from `set_cursor`, to go back to when a cursor image is cleared.
+	pub(crate) stock: MouseCursor,
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{ Context, RenderTarget };
+	use crate::batch::sprite::{ SpriteBatch, SpriteBatchShaders, SpriteBatchShared };
+	use crate::texture::TargetTexture;
+	use vulkano::sync::GpuFuture;
+	use winit::dpi::LogicalSize;
+
+	const SIZES: [[u32; 2]; 8] = [[800, 600], [0, 0], [1, 1], [1920, 1080], [0, 600], [3, 2000], [640, 0], [320, 240]];
+
+	#[test]
+	#[ignore] // needs a display and a Vulkan device, run with `cargo test -- --ignored`
+	fn spam_window_resizes_while_rendering() {
+		let mut ctx = Context::new(None, None).unwrap();
+		let mut window = ctx.create_window("resize test");
+		let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+		let shared = SpriteBatchShared::new(shaders, window.format());
+		let (mut batch, batch_future) = SpriteBatch::new(&window, &window, shared.clone()).unwrap();
+		window.join_future(shaders_future.join(batch_future));
+
+		for frame in 0..SIZES.len() * 25 {
+			let [width, height] = SIZES[frame % SIZES.len()];
+			window.surface().window().set_inner_size(LogicalSize::new(width as f64, height as f64));
+			// present before the resize events arrive as often as after
+			if frame % 2 == 0 {
+				ctx.poll_events(|_| ());
+			}
+			window
+				.present_composited(|window, image_num, compositor| {
+					batch.composite(window, window, image_num, compositor)
+				})
+				.unwrap();
+		}
+	}
+
+	#[test]
+	#[ignore] // needs a display and a Vulkan device, run with `cargo test -- --ignored`
+	fn spam_target_texture_sizes_while_rendering() {
+		let mut ctx = Context::new(None, None).unwrap();
+		let mut window = ctx.create_window("target resize test");
+		let (shaders, shaders_future) = SpriteBatchShaders::new(&mut window).unwrap();
+		let shared = SpriteBatchShared::new(shaders, window.format());
+		window.join_future(shaders_future);
+
+		for frame in 0..SIZES.len() * 25 {
+			let target = TargetTexture::new(&window, SIZES[frame % SIZES.len()]).unwrap();
+			let (mut batch, batch_future) = SpriteBatch::new(&window, &target, shared.clone()).unwrap();
+			let (commands, commands_future) = batch.commands(&window, &target, 0).unwrap();
+			let queue = window.device().queue().clone();
+			let mut future: Box<GpuFuture> = Box::new(batch_future);
+			if let Some(commands_future) = commands_future {
+				future = Box::new(future.join(commands_future));
+			}
+			future
+				.then_execute(queue, commands)
+				.unwrap()
+				.then_signal_fence_and_flush()
+				.unwrap()
+				.wait(None)
+				.unwrap();
+			ctx.poll_events(|_| ());
+		}
+	}
+}
@@ -0,0 +1,574 @@
+use super::TaskbarProgress;
+use winit::MonitorId;
+
+/// What a monitor was found through, which X11 queries go through the display connection of.
+#[cfg_attr(any(target_os = "windows", target_os = "macos"), allow(dead_code))]
+#[derive(Clone, Copy)]
+pub(crate) enum MonitorSource<'a> {
+	Events(&'a winit::EventsLoop),
+	Window(&'a winit::Window),
+}
+
+/// The refresh rate of the monitor's current video mode from XRandR, or `None` on Wayland, which winit doesn't
+/// give a connection for.
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+pub(crate) fn refresh_rate(monitor: &MonitorId, source: MonitorSource) -> Option<f64> {
+	use winit::os::unix::{ EventsLoopExt, WindowExt };
+
+	let xconn = match source {
+		MonitorSource::Events(events) => events.get_xlib_xconnection(),
+		MonitorSource::Window(window) => window.get_xlib_xconnection(),
+	}?;
+	let position = monitor.get_position();
+
+	unsafe {
+		let root = (xconn.xlib.XDefaultRootWindow)(xconn.display);
+		let resources = (xconn.xrandr.XRRGetScreenResourcesCurrent)(xconn.display, root);
+		if resources.is_null() {
+			return None;
+		}
+
+		// winit's monitor ids are monitor indices on XRandR 1.5 and crtc ids before it, so the crtc is found by where
+		// it is on the desktop instead
+		let mut rate = None;
+		for crtc_index in 0..(*resources).ncrtc {
+			let crtc_id = *(*resources).crtcs.offset(crtc_index as isize);
+			let crtc = (xconn.xrandr.XRRGetCrtcInfo)(xconn.display, resources, crtc_id);
+			if crtc.is_null() {
+				continue;
+			}
+			if (*crtc).mode != 0 && (*crtc).x == position.x as i32 && (*crtc).y == position.y as i32 {
+				rate = (0..(*resources).nmode)
+					.map(|mode_index| &*(*resources).modes.offset(mode_index as isize))
+					.find(|mode| mode.id == (*crtc).mode)
+					.and_then(x11_mode_refresh_rate);
+			}
+			(xconn.xrandr.XRRFreeCrtcInfo)(crtc);
+			if rate.is_some() {
+				break;
+			}
+		}
+
+		(xconn.xrandr.XRRFreeScreenResources)(resources);
+		rate
+	}
+}
+
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+fn x11_mode_refresh_rate(mode: &winit::os::unix::x11::ffi::XRRModeInfo) -> Option<f64> {
+	use std::os::raw::c_ulong;
+	use winit::os::unix::x11::ffi::{ RR_DoubleScan, RR_Interlace };
+
+	let mut lines = mode.vTotal as f64;
+	if mode.modeFlags & RR_DoubleScan as c_ulong != 0 {
+		lines *= 2.0;
+	}
+	if mode.modeFlags & RR_Interlace as c_ulong != 0 {
+		lines /= 2.0;
+	}
+
+	let pixels = mode.hTotal as f64 * lines;
+	if pixels > 0.0 { Some(mode.dotClock as f64 / pixels) } else { None }
+}
+
+/// The refresh rate of the monitor's current display settings.
+#[cfg(target_os = "windows")]
+pub(crate) fn refresh_rate(monitor: &MonitorId, _source: MonitorSource) -> Option<f64> {
+	use std::{ ffi::OsStr, mem, os::windows::ffi::OsStrExt, ptr };
+	use winapi::um::{ wingdi::DEVMODEW, winuser::{ EnumDisplaySettingsW, ENUM_CURRENT_SETTINGS } };
+	use winit::os::windows::MonitorIdExt;
+
+	let device: Vec<u16> = OsStr::new(&monitor.native_id()).encode_wide().chain(Some(0)).collect();
+	unsafe {
+		let mut settings: DEVMODEW = mem::zeroed();
+		settings.dmSize = mem::size_of::<DEVMODEW>() as u16;
+		let device = if device.len() > 1 { device.as_ptr() } else { ptr::null() };
+		if EnumDisplaySettingsW(device, ENUM_CURRENT_SETTINGS, &mut settings) == 0 {
+			return None;
+		}
+
+		// 0 and 1 both mean the hardware's default rate
+		match settings.dmDisplayFrequency {
+			0 | 1 => None,
+			hz => Some(hz as f64),
+		}
+	}
+}
+
+/// The refresh rate of the display's current mode.
+#[cfg(target_os = "macos")]
+pub(crate) fn refresh_rate(monitor: &MonitorId, _source: MonitorSource) -> Option<f64> {
+	use core_graphics::display::CGDisplay;
+	use winit::os::macos::MonitorIdExt;
+
+	// built in panels report 0 instead of their rate
+	CGDisplay::new(monitor.native_id()).display_mode().map(|mode| mode.refresh_rate()).filter(|&hz| hz > 0.0)
+}
+
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+	target_os = "windows",
+	target_os = "macos",
+)))]
+pub(crate) fn refresh_rate(_monitor: &MonitorId, _source: MonitorSource) -> Option<f64> {
+	None
+}
+
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+pub(crate) fn request_attention(window: &winit::Window, _critical: bool) -> bool {
+	use winit::os::unix::WindowExt;
+
+	if window.get_xlib_window().is_none() {
+		return false;
+	}
+	// the urgency hint stays set until the client clears it
+	window.set_urgent(true);
+	true
+}
+
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+pub(crate) fn clear_attention(window: &winit::Window) {
+	use winit::os::unix::WindowExt;
+
+	window.set_urgent(false);
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn request_attention(window: &winit::Window, critical: bool) -> bool {
+	use std::mem;
+	use winapi::um::winuser::{ FlashWindowEx, FLASHWINFO, FLASHW_ALL, FLASHW_TIMERNOFG, FLASHW_TRAY };
+	use winit::os::windows::WindowExt;
+
+	let (flags, count) = if critical { (FLASHW_ALL | FLASHW_TIMERNOFG, 0) } else { (FLASHW_TRAY, 1) };
+	let mut info = FLASHWINFO {
+		cbSize: mem::size_of::<FLASHWINFO>() as u32,
+		hwnd: window.get_hwnd() as _,
+		dwFlags: flags,
+		uCount: count,
+		dwTimeout: 0,
+	};
+	// windows stops flashing by itself once the window comes to the foreground
+	unsafe { FlashWindowEx(&mut info) };
+	false
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn request_attention(window: &winit::Window, critical: bool) -> bool {
+	use winit::os::macos::WindowExt;
+
+	window.request_user_attention(critical);
+	false
+}
+
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+	target_os = "windows",
+	target_os = "macos",
+)))]
+pub(crate) fn request_attention(_window: &winit::Window, _critical: bool) -> bool {
+	false
+}
+
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+)))]
+pub(crate) fn clear_attention(_window: &winit::Window) {
+}
+
+/// Through `ITaskbarList3`, made for each call since the COM object can't leave the thread that made it.
+#[cfg(target_os = "windows")]
+pub(crate) fn set_taskbar_progress(window: &winit::Window, progress: TaskbarProgress) {
+	use log::{ warn, log };
+	use std::ptr;
+	use winapi::{
+		Interface,
+		shared::{ winerror::SUCCEEDED, wtypesbase::CLSCTX_INPROC_SERVER },
+		um::{
+			combaseapi::{ CoCreateInstance, CoInitializeEx, CoUninitialize },
+			objbase::COINIT_APARTMENTTHREADED,
+			shobjidl_core::{
+				CLSID_TaskbarList,
+				ITaskbarList3,
+				TBPF_ERROR,
+				TBPF_INDETERMINATE,
+				TBPF_NOPROGRESS,
+				TBPF_NORMAL,
+				TBPF_PAUSED,
+			},
+		},
+	};
+	use winit::os::windows::WindowExt;
+
+	const TOTAL: u64 = 10000;
+
+	let (state, completed) = match progress {
+		TaskbarProgress::Hidden => (TBPF_NOPROGRESS, None),
+		TaskbarProgress::Indeterminate => (TBPF_INDETERMINATE, None),
+		TaskbarProgress::Normal(fraction) => (TBPF_NORMAL, Some(fraction)),
+		TaskbarProgress::Paused(fraction) => (TBPF_PAUSED, Some(fraction)),
+		TaskbarProgress::Error(fraction) => (TBPF_ERROR, Some(fraction)),
+	};
+	let hwnd = window.get_hwnd() as _;
+
+	unsafe {
+		// S_FALSE for a thread that already had COM still needs balancing. RPC_E_CHANGED_MODE means it's already
+		// multithreaded, which the taskbar list works in too, and mustn't be balanced.
+		let initialized = SUCCEEDED(CoInitializeEx(ptr::null_mut(), COINIT_APARTMENTTHREADED));
+
+		let mut taskbar: *mut ITaskbarList3 = ptr::null_mut();
+		let result =
+			CoCreateInstance(
+				&CLSID_TaskbarList,
+				ptr::null_mut(),
+				CLSCTX_INPROC_SERVER,
+				&ITaskbarList3::uuidof(),
+				&mut taskbar as *mut _ as *mut _,
+			);
+		if SUCCEEDED(result) {
+			(*taskbar).HrInit();
+			// setting the state first keeps paused and error bars from turning normal when the value is set
+			(*taskbar).SetProgressState(hwnd, state);
+			if let Some(fraction) = completed {
+				let fraction = if fraction.is_nan() { 0.0 } else { fraction.max(0.0).min(1.0) };
+				(*taskbar).SetProgressValue(hwnd, (fraction as f64 * TOTAL as f64) as u64, TOTAL);
+			}
+			(*taskbar).Release();
+		} else {
+			warn!("failed to create the taskbar list: {:#x}", result);
+		}
+
+		if initialized {
+			CoUninitialize();
+		}
+	}
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn set_taskbar_progress(_window: &winit::Window, _progress: TaskbarProgress) {
+}
+
+/// A cursor made from an image, shown over the window until it's dropped.
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+pub(crate) struct NativeCursor {
+	xconn: std::sync::Arc<winit::os::unix::x11::XConnection>,
+	cursor: std::os::raw::c_ulong,
+}
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+impl Drop for NativeCursor {
+	fn drop(&mut self) {
+		// the window keeps showing the cursor until winit defines its own again
+		unsafe { (self.xconn.xlib.XFreeCursor)(self.xconn.display, self.cursor) };
+	}
+}
+
+/// Makes an Xcursor from the image and defines it on the window, or `None` on Wayland.
+#[cfg(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+))]
+pub(crate) fn create_cursor(
+	window: &winit::Window,
+	rgba: &[u8],
+	dimensions: [u32; 2],
+	hotspot: [u32; 2],
+) -> Option<NativeCursor> {
+	use std::slice;
+	use winit::os::unix::WindowExt;
+
+	let xconn = window.get_xlib_xconnection()?;
+	let xwindow = window.get_xlib_window()?;
+
+	unsafe {
+		let image = (xconn.xcursor.XcursorImageCreate)(dimensions[0] as i32, dimensions[1] as i32);
+		if image.is_null() {
+			return None;
+		}
+		(*image).xhot = hotspot[0];
+		(*image).yhot = hotspot[1];
+
+		// Xcursor takes premultiplied ARGB
+		let pixels = slice::from_raw_parts_mut((*image).pixels, (dimensions[0] * dimensions[1]) as usize);
+		for (pixel, rgba) in pixels.iter_mut().zip(rgba.chunks(4)) {
+			let alpha = rgba[3] as u32;
+			let premultiply = |channel: u8| (channel as u32 * alpha + 127) / 255;
+			*pixel = alpha << 24 | premultiply(rgba[0]) << 16 | premultiply(rgba[1]) << 8 | premultiply(rgba[2]);
+		}
+
+		let cursor = (xconn.xcursor.XcursorImageLoadCursor)(xconn.display, image);
+		(xconn.xcursor.XcursorImageDestroy)(image);
+		if cursor == 0 {
+			return None;
+		}
+		(xconn.xlib.XDefineCursor)(xconn.display, xwindow, cursor);
+		(xconn.xlib.XFlush)(xconn.display);
+
+		Some(NativeCursor { xconn: xconn, cursor: cursor })
+	}
+}
+
+/// A cursor made from an image, shown over the window until it's dropped.
+#[cfg(target_os = "windows")]
+pub(crate) struct NativeCursor {
+	hwnd: winapi::shared::windef::HWND,
+	cursor: winapi::shared::windef::HCURSOR,
+}
+#[cfg(target_os = "windows")]
+impl Drop for NativeCursor {
+	fn drop(&mut self) {
+		use winapi::um::winuser::{ DestroyCursor, RemovePropW };
+
+		unsafe {
+			RemovePropW(self.hwnd, win32_cursor::CURSOR.as_ptr());
+			win32_cursor::refresh(self.hwnd);
+			DestroyCursor(self.cursor);
+		}
+	}
+}
+
+/// Makes a cursor from the image and shows it over the window. winit sets its own cursor on every `WM_SETCURSOR`,
+/// so the window procedure is wrapped to set this one instead.
+#[cfg(target_os = "windows")]
+pub(crate) fn create_cursor(
+	window: &winit::Window,
+	rgba: &[u8],
+	dimensions: [u32; 2],
+	hotspot: [u32; 2],
+) -> Option<NativeCursor> {
+	use std::ptr;
+	use winapi::{
+		shared::minwindef::FALSE,
+		um::{
+			wingdi::{ CreateBitmap, DeleteObject },
+			winuser::{
+				CreateIconIndirect,
+				GetPropW,
+				GetWindowLongPtrW,
+				SetPropW,
+				SetWindowLongPtrW,
+				GWLP_WNDPROC,
+				ICONINFO,
+			},
+		},
+	};
+	use winit::os::windows::WindowExt;
+
+	let (width, height) = (dimensions[0] as i32, dimensions[1] as i32);
+	let bgra: Vec<u8> = rgba.chunks(4).flat_map(|rgba| vec![rgba[2], rgba[1], rgba[0], rgba[3]]).collect();
+	let hwnd = window.get_hwnd() as _;
+
+	unsafe {
+		let color = CreateBitmap(width, height, 1, 32, bgra.as_ptr() as _);
+		// the mask is unused for cursors with alpha, but still required
+		let mask = CreateBitmap(width, height, 1, 1, ptr::null());
+		let cursor =
+			if color.is_null() || mask.is_null() {
+				ptr::null_mut()
+			} else {
+				let mut info =
+					ICONINFO {
+						fIcon: FALSE,
+						xHotspot: hotspot[0],
+						yHotspot: hotspot[1],
+						hbmMask: mask,
+						hbmColor: color,
+					};
+				CreateIconIndirect(&mut info)
+			};
+		DeleteObject(color as _);
+		DeleteObject(mask as _);
+		if cursor.is_null() {
+			return None;
+		}
+
+		// the previous procedure is stored before switching, since messages can arrive on winit's thread right away
+		if GetPropW(hwnd, win32_cursor::PREVIOUS_PROC.as_ptr()).is_null() {
+			let previous = GetWindowLongPtrW(hwnd, GWLP_WNDPROC);
+			SetPropW(hwnd, win32_cursor::PREVIOUS_PROC.as_ptr(), previous as _);
+			SetWindowLongPtrW(hwnd, GWLP_WNDPROC, win32_cursor::window_proc as *const () as _);
+		}
+		SetPropW(hwnd, win32_cursor::CURSOR.as_ptr(), cursor as _);
+		win32_cursor::refresh(hwnd);
+
+		Some(NativeCursor { hwnd: hwnd, cursor: cursor })
+	}
+}
+
+/// The window procedure wrapped by `create_cursor`, and the window properties it reads on winit's thread.
+#[cfg(target_os = "windows")]
+mod win32_cursor {
+	use lazy_static::lazy_static;
+	use std::{ ffi::OsStr, mem, os::windows::ffi::OsStrExt };
+	use winapi::{
+		shared::{ minwindef::{ LOWORD, LPARAM, LRESULT, TRUE, UINT, WPARAM }, windef::{ HWND, POINT } },
+		um::winuser::{
+			CallWindowProcW,
+			GetCursorPos,
+			GetPropW,
+			PostMessageW,
+			RemovePropW,
+			SetCursor,
+			SetWindowLongPtrW,
+			WindowFromPoint,
+			GWLP_WNDPROC,
+			HTCLIENT,
+			WM_MOUSEMOVE,
+			WM_NCDESTROY,
+			WM_SETCURSOR,
+			WNDPROC,
+		},
+	};
+
+	lazy_static! {
+		pub(super) static ref CURSOR: Vec<u16> = wide("nice_game_cursor");
+		pub(super) static ref PREVIOUS_PROC: Vec<u16> = wide("nice_game_previous_proc");
+	}
+
+	fn wide(name: &str) -> Vec<u16> {
+		OsStr::new(name).encode_wide().chain(Some(0)).collect()
+	}
+
+	pub(super) unsafe extern "system" fn window_proc(hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+		let previous: WNDPROC = mem::transmute(GetPropW(hwnd, PREVIOUS_PROC.as_ptr()));
+		match msg {
+			WM_SETCURSOR if LOWORD(lparam as u32) as LRESULT == HTCLIENT => {
+				let cursor = GetPropW(hwnd, CURSOR.as_ptr());
+				if !cursor.is_null() {
+					SetCursor(cursor as _);
+					return TRUE as LRESULT;
+				}
+			},
+			WM_NCDESTROY => {
+				RemovePropW(hwnd, CURSOR.as_ptr());
+				RemovePropW(hwnd, PREVIOUS_PROC.as_ptr());
+				SetWindowLongPtrW(hwnd, GWLP_WNDPROC, mem::transmute::<WNDPROC, usize>(previous) as _);
+			},
+			_ => (),
+		}
+		CallWindowProcW(previous, hwnd, msg, wparam, lparam)
+	}
+
+	/// Has winit's thread set the cursor again if it's over the window, instead of waiting for the mouse to move.
+	pub(super) unsafe fn refresh(hwnd: HWND) {
+		let mut position = POINT { x: 0, y: 0 };
+		if GetCursorPos(&mut position) != 0 && WindowFromPoint(position) == hwnd {
+			PostMessageW(hwnd, WM_SETCURSOR, hwnd as WPARAM, (WM_MOUSEMOVE << 16) as LPARAM | HTCLIENT as LPARAM);
+		}
+	}
+}
+
+/// Never made; `create_cursor` always falls back to the software cursor here.
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+	target_os = "windows",
+)))]
+pub(crate) enum NativeCursor {}
+
+/// winit 0.18 on macOS resets the cursor from its own cursor rects, so images go through the software cursor there.
+#[cfg(not(any(
+	target_os = "linux",
+	target_os = "dragonfly",
+	target_os = "freebsd",
+	target_os = "netbsd",
+	target_os = "openbsd",
+	target_os = "windows",
+)))]
+pub(crate) fn create_cursor(
+	_window: &winit::Window,
+	_rgba: &[u8],
+	_dimensions: [u32; 2],
+	_hotspot: [u32; 2],
+) -> Option<NativeCursor> {
+	None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+	use super::x11_mode_refresh_rate;
+	use std::{ mem, os::raw::c_ulong };
+	use winit::os::unix::x11::ffi::{ RR_DoubleScan, RR_Interlace, XRRModeInfo };
+
+	/// A mode with the given timings and no name.
+	fn mode(dot_clock: c_ulong, h_total: u32, v_total: u32, flags: i32) -> XRRModeInfo {
+		let mut mode: XRRModeInfo = unsafe { mem::zeroed() };
+		mode.dotClock = dot_clock;
+		mode.hTotal = h_total;
+		mode.vTotal = v_total;
+		mode.modeFlags = flags as c_ulong;
+		mode
+	}
+
+	#[test]
+	fn progressive_mode() {
+		// CEA-861 1920x1080 at 60 Hz
+		let rate = x11_mode_refresh_rate(&mode(148_500_000, 2200, 1125, 0)).unwrap();
+		assert!((rate - 60.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn interlaced_and_doublescan_modes() {
+		let interlaced = x11_mode_refresh_rate(&mode(74_250_000, 2200, 1125, RR_Interlace)).unwrap();
+		assert!((interlaced - 60.0).abs() < 1e-9);
+		let doublescan = x11_mode_refresh_rate(&mode(25_175_000, 800, 262, RR_DoubleScan)).unwrap();
+		assert!((doublescan - 25_175_000.0 / (800.0 * 524.0)).abs() < 1e-9);
+	}
+
+	#[test]
+	fn empty_timings() {
+		assert_eq!(x11_mode_refresh_rate(&mode(148_500_000, 0, 0, 0)), None);
+	}
+}